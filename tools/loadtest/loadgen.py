@@ -6,9 +6,14 @@
 загрузки забивают серверный mux writer_tx, а параллельный шквал мелких открытий
 требует ConnectAck через переполненный канал -> должен застопорить mux_handler.
 
+Сценарий bulk (multipath, `mux_stripe`): несколько загрузок читаются на полной
+скорости, репортёр печатает MB/s. Прогон с `mux_stripe = false` и `true` на
+роутере при прочих равных показывает выигрыш раскладки по туннелям пула.
+
 Запуск:
-  python3 loadgen.py <aeza_ip> [port] [duration] [slow_workers] [churn_workers]
+  python3 loadgen.py <aeza_ip> [port] [duration] [slow_workers] [churn_workers] [bulk_workers]
   напр.: python3 loadgen.py 85.192.38.29 9443 300 40 400
+  bulk:  python3 loadgen.py 85.192.38.29 9443 60 0 0 1
 """
 import asyncio, ssl, sys, time
 
@@ -17,6 +22,7 @@ PORT = int(sys.argv[2]) if len(sys.argv) > 2 else 9443
 DUR = int(sys.argv[3]) if len(sys.argv) > 3 else 300
 SLOW = int(sys.argv[4]) if len(sys.argv) > 4 else 40
 CHURN = int(sys.argv[5]) if len(sys.argv) > 5 else 400
+BULK = int(sys.argv[6]) if len(sys.argv) > 6 else 0
 CONNECT_TIMEOUT = 12.0
 
 ctx = ssl.create_default_context()
@@ -24,7 +30,8 @@ ctx.check_hostname = False
 ctx.verify_mode = ssl.CERT_NONE
 
 stats = {"churn_ok": 0, "churn_to": 0, "churn_err": 0,
-         "slow_ok": 0, "slow_to": 0, "slow_err": 0, "slow_bytes": 0}
+         "slow_ok": 0, "slow_to": 0, "slow_err": 0, "slow_bytes": 0,
+         "bulk_ok": 0, "bulk_err": 0, "bulk_bytes": 0}
 stop_at = time.time() + DUR
 
 
@@ -73,6 +80,22 @@ async def slow_worker():
             stats["slow_err"] += 1
 
 
+async def bulk_worker():
+    # Загрузка на полной скорости: упирается в окно туннеля, а не в читателя.
+    while time.time() < stop_at:
+        try:
+            reader, writer = await req("/download?size=1073741824")  # 1 ГБ
+            while time.time() < stop_at:
+                chunk = await asyncio.wait_for(reader.read(262144), timeout=CONNECT_TIMEOUT)
+                if not chunk:
+                    break
+                stats["bulk_bytes"] += len(chunk)
+            writer.close()
+            stats["bulk_ok"] += 1
+        except Exception:
+            stats["bulk_err"] += 1
+
+
 async def reporter():
     last = dict(stats)
     while time.time() < stop_at:
@@ -81,17 +104,21 @@ async def reporter():
         last = dict(stats)
         el = int(time.time() - (stop_at - DUR))
         print(f"[{el}s] churn ok={d['churn_ok']} TIMEOUT={d['churn_to']} err={d['churn_err']} | "
-              f"slow ok={d['slow_ok']} TIMEOUT={d['slow_to']} MB={d['slow_bytes']//1048576}", flush=True)
+              f"slow ok={d['slow_ok']} TIMEOUT={d['slow_to']} MB={d['slow_bytes']//1048576} | "
+              f"bulk err={d['bulk_err']} MB/s={d['bulk_bytes']/1048576/5:.1f}", flush=True)
 
 
 async def main():
-    print(f"loadgen -> {IP}:{PORT} slow={SLOW} churn={CHURN} dur={DUR}s", flush=True)
+    print(f"loadgen -> {IP}:{PORT} slow={SLOW} churn={CHURN} bulk={BULK} dur={DUR}s", flush=True)
     tasks = [asyncio.create_task(slow_worker()) for _ in range(SLOW)]
     tasks += [asyncio.create_task(churn_worker()) for _ in range(CHURN)]
+    tasks += [asyncio.create_task(bulk_worker()) for _ in range(BULK)]
     r = asyncio.create_task(reporter())
     await asyncio.gather(*tasks, return_exceptions=True)
     r.cancel()
     print(f"ИТОГО {stats}", flush=True)
+    if BULK:
+        print(f"bulk в среднем {stats['bulk_bytes']/1048576/DUR:.1f} MB/s", flush=True)
 
 
 asyncio.run(main())
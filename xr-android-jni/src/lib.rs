@@ -1331,6 +1331,68 @@ pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeFetchManifest
     jstring_into_raw(&mut env, json)
 }
 
+/// Long-poll изменений шары: висит на агенте до `hold_secs`, пока версия
+/// манифеста равна `since`. Возвращает `{"changed":true,"version":N,
+/// "manifest":{..}}` со свежим манифестом (подпись проверена, как в
+/// `nativeFetchManifest`), `{"changed":false}` по истечении удержания или
+/// `{"error":".."}`. `since = 0` отвечается сразу: так достаётся первая версия.
+/// `index_path` тот же, что у `nativeSyncShare`: рядом с индексом хешей лежит
+/// копия листинга, и изменение приезжает дельтой от неё; пусто = без копии.
+/// Блокирует поток на всё удержание, звать только с фонового.
+#[no_mangle]
+pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeWaitManifest(
+    mut env: JNIEnv,
+    _class: JClass,
+    agent_url: JString,
+    token_json: JString,
+    agent_pubkey: JString,
+    relay_json: JString,
+    index_path: JString,
+    since: jlong,
+    hold_secs: jlong,
+) -> jstring {
+    let agent_url = match read_jstring(&mut env, &agent_url) {
+        Ok(s) => s,
+        Err(e) => return jstring_into_raw(&mut env, json_error(&e)),
+    };
+    let token = match read_jstring(&mut env, &token_json).and_then(|s| parse_token(&s)) {
+        Ok(t) => t,
+        Err(e) => return jstring_into_raw(&mut env, json_error(&e)),
+    };
+    let agent_pubkey = match read_jstring(&mut env, &agent_pubkey) {
+        Ok(s) => s,
+        Err(e) => return jstring_into_raw(&mut env, json_error(&e)),
+    };
+    let relay = read_jstring(&mut env, &relay_json).ok().and_then(|s| parse_relay(&s));
+    let index_path: Option<PathBuf> = match read_jstring(&mut env, &index_path) {
+        Ok(s) if !s.trim().is_empty() => Some(PathBuf::from(s)),
+        _ => None,
+    };
+    let hold = Duration::from_secs(hold_secs.max(0) as u64);
+
+    let json = match with_onboarding_runtime(sync::wait_manifest_relay(
+        &agent_url,
+        &token,
+        &agent_pubkey,
+        relay.as_ref(),
+        index_path.as_deref(),
+        since.max(0) as u64,
+        hold,
+    )) {
+        Ok(Ok(sync::ManifestWait::Changed { manifest, version })) => {
+            serde_json::json!({ "changed": true, "version": version, "manifest": manifest }).to_string()
+        }
+        Ok(Ok(sync::ManifestWait::UpToDate)) => serde_json::json!({ "changed": false }).to_string(),
+        Ok(Err(e)) | Err(e) => {
+            // WARN, как у манифеста: обрыв ожидания при уходе сети штатен,
+            // но без следа в журнале «не обновляется» не разобрать.
+            journal_log("WARN", "files", &format!("ожидание изменений шары {}: {}", token.share_id, e));
+            json_error(&e)
+        }
+    };
+    jstring_into_raw(&mut env, json)
+}
+
 /// Download a single manifest entry to `dest_dir` (one-time download). The
 /// entry is a ShareManifestEntry JSON; the file is SHA-256-verified before being
 /// published. Returns `{"ok":true}` or `{"error":".."}`.
@@ -1744,6 +1806,87 @@ pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeImportCancel(
     jstring_into_raw(&mut env, json)
 }
 
+/// What the share holds and how much more it takes: the agent's
+/// `{"used_bytes":..,"files":..,"quota_bytes":..,"max_files":..,"free_bytes":..,
+/// "writable":..,"extensions":[..],"mime":[..]}` (absent limits are left out),
+/// or `{"error":".."}`.
+#[no_mangle]
+pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeShareUsage(
+    mut env: JNIEnv,
+    _class: JClass,
+    addr: JString,
+    port: jint,
+    token_json: JString,
+    agent_pubkey: JString,
+    relay_json: JString,
+    timeout_ms: jlong,
+) -> jstring {
+    let parts = (|| -> Result<(String, ShareToken, String, Option<RelayGrant>), String> {
+        let addr = read_jstring(&mut env, &addr)?;
+        let token = read_jstring(&mut env, &token_json).and_then(|s| parse_token(&s))?;
+        let pubkey = read_jstring(&mut env, &agent_pubkey)?;
+        let relay = read_jstring(&mut env, &relay_json).ok().and_then(|s| parse_relay(&s));
+        Ok((addr, token, pubkey, relay))
+    })();
+    let (addr, token, pubkey, relay) = match parts {
+        Ok(p) => p,
+        Err(e) => return jstring_into_raw(&mut env, json_error(&e)),
+    };
+    let grant = grant_from_parts(addr, port.max(0) as u16, token, pubkey, relay);
+    let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+
+    let json = match with_onboarding_runtime(sync::fetch_share_usage(&grant, timeout)) {
+        Ok(Ok(usage)) => serde_json::to_string(&usage)
+            .unwrap_or_else(|e| json_error(&format!("serialize: {e}"))),
+        Ok(Err(e)) | Err(e) => json_error(&e),
+    };
+    jstring_into_raw(&mut env, json)
+}
+
+/// Fetch the preview of `path` (an entry the manifest marks with `preview`)
+/// and write the JPEG to `dest_path`, a file in the app's cache. Returns
+/// `{"ok":true}` or `{"error":".."}`: `not_found`, or `no_preview: ...` for a
+/// file the agent cannot preview.
+#[no_mangle]
+pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeFetchThumbnail(
+    mut env: JNIEnv,
+    _class: JClass,
+    addr: JString,
+    port: jint,
+    token_json: JString,
+    agent_pubkey: JString,
+    relay_json: JString,
+    path: JString,
+    dest_path: JString,
+    timeout_ms: jlong,
+) -> jstring {
+    type ThumbArgs = (String, ShareToken, String, Option<RelayGrant>, String, String);
+    let parts = (|| -> Result<ThumbArgs, String> {
+        let addr = read_jstring(&mut env, &addr)?;
+        let token = read_jstring(&mut env, &token_json).and_then(|s| parse_token(&s))?;
+        let pubkey = read_jstring(&mut env, &agent_pubkey)?;
+        let relay = read_jstring(&mut env, &relay_json).ok().and_then(|s| parse_relay(&s));
+        let path = read_jstring(&mut env, &path)?;
+        let dest = read_jstring(&mut env, &dest_path)?;
+        Ok((addr, token, pubkey, relay, path, dest))
+    })();
+    let (addr, token, pubkey, relay, path, dest) = match parts {
+        Ok(p) => p,
+        Err(e) => return jstring_into_raw(&mut env, json_error(&e)),
+    };
+    let grant = grant_from_parts(addr, port.max(0) as u16, token, pubkey, relay);
+    let timeout = Duration::from_millis(timeout_ms.max(0) as u64);
+
+    let json = match with_onboarding_runtime(sync::fetch_thumbnail(&grant, &path, timeout)) {
+        Ok(Ok(jpeg)) => match std::fs::write(&dest, jpeg) {
+            Ok(()) => serde_json::json!({ "ok": true }).to_string(),
+            Err(e) => json_error(&format!("write: {e}")),
+        },
+        Ok(Err(e)) | Err(e) => json_error(&e),
+    };
+    jstring_into_raw(&mut env, json)
+}
+
 /// The file a delete asks for. An empty name is refused before the network: no
 /// agent route matches it, and the `not_found` that would come back reads as
 /// "the file is already gone" and hides the caller's slip.
@@ -1868,8 +2011,10 @@ pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeMigrateShareD
 }
 
 /// Poll the running transfer's progress as JSON (`{active,cancelled,file,
-/// files_done,files_total,bytes_done,bytes_total}`); `active:false` when idle.
-/// The UI polls this for a progress bar and computes speed from the byte delta.
+/// files_done,files_total,bytes_done,bytes_resumed,bytes_total}`);
+/// `active:false` when idle. The UI polls this for a progress bar and computes
+/// speed from the delta of `bytes_done - bytes_resumed` (a resumed partial's
+/// bytes did not move now).
 #[no_mangle]
 pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeTransferProgress(
     mut env: JNIEnv,
@@ -1881,7 +2026,8 @@ pub extern "system" fn Java_com_xrproxy_app_jni_NativeBridge_nativeTransferProgr
 }
 
 /// Request cancellation of the running sync/download. It aborts at the next
-/// chunk and discards any half-written file.
+/// chunk and keeps the half-written file, recorded, for the next attempt to
+/// resume.
 ///
 /// `id` это номер передачи из последнего снимка прогресса, и отмена доходит
 /// только до неё: пока номера не было, решение UI («идёт то, что я хочу
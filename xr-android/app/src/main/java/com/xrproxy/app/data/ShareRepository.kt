@@ -32,6 +32,14 @@ class ShareRepository(private val context: Context) {
         val ok: Boolean get() = error == null
     }
 
+    /** Итог одного ожидания изменений шары ([waitManifest]). */
+    sealed interface WaitOutcome {
+        /** Шара изменилась: свежий список и версия, с которой ждать дальше. */
+        data class Changed(val version: Long, val entries: List<ManifestEntry>) : WaitOutcome
+        /** За удержание ничего не изменилось. */
+        data object UpToDate : WaitOutcome
+    }
+
     /** Outcome of a storage-directory migration (XR-043). */
     data class MigrateOutcome(
         val moved: Int,
@@ -225,6 +233,26 @@ class ShareRepository(private val context: Context) {
         )
     }
 
+    /** Long-poll изменений шары: висит на агенте, пока версия манифеста равна
+     *  [since]. [since] = 0 отвечается сразу и даёт первую версию. Ошибка
+     *  (сеть, старый агент без ожидания) приходит как failure. */
+    fun waitManifest(config: ShareConfig, since: Long): Result<WaitOutcome> {
+        val token = config.tokenJson ?: return Result.failure(IllegalStateException("no token"))
+        val res = NativeBridge.nativeWaitManifest(
+            config.agentBaseUrls, token, config.agentPubkey, config.relayArg, hashIndexPath(config),
+            since, WAIT_HOLD_SECS,
+        )
+        return runCatching {
+            val o = JSONObject(res)
+            o.optString("error").takeIf { it.isNotBlank() && it != "null" }?.let {
+                throw IllegalStateException(it)
+            }
+            if (!o.optBoolean("changed", false)) return@runCatching WaitOutcome.UpToDate
+            val entries = parseManifest(o.getJSONObject("manifest").toString()).getOrThrow()
+            WaitOutcome.Changed(o.getLong("version"), entries)
+        }
+    }
+
     /** One-time download of a single file into the share's app directory.
      *  Returns null on success, otherwise the error code ("busy" when another
      *  transfer is already running). */
@@ -340,7 +368,8 @@ class ShareRepository(private val context: Context) {
      *  is walked by [localPaths]/[localManifest] for the UI, cleaned by the
      *  true-mirror delete, and may sit on user-visible shared storage. Keyed by
      *  shareId with share-relative entries inside, so a storage-directory change
-     *  (XR-043) does not invalidate it. */
+     *  (XR-043) does not invalidate it. The native side keeps the share's last
+     *  listing beside it (`<id>.manifest.json`) to fetch changes as deltas. */
     private fun hashIndexPath(config: ShareConfig): String =
         File(File(context.filesDir, "share-index").apply { mkdirs() }, sanitize(config.shareId) + ".json")
             .absolutePath
@@ -355,6 +384,9 @@ class ShareRepository(private val context: Context) {
         private const val INVITE_TIMEOUT_MS = 15_000L
         /** Listing is cheap on the agent (cached hashes), so a tight bound. */
         private const val MANIFEST_TIMEOUT_MS = 60_000L
+        /** Удержание ожидания изменений, как у пресетов: агент держит не
+         *  дольше минуты, иначе висящий ответ рвут прокси по пути. */
+        private const val WAIT_HOLD_SECS = 55L
         /** Transfers may be multi-GB; the engine uses a 10s connect-timeout, so a
          *  long total just bounds a genuinely stuck transfer. */
         private const val XFER_TIMEOUT_MS = 3_600_000L
@@ -282,6 +282,23 @@ object NativeBridge {
         timeoutMs: Long,
     ): String
 
+    /** Long-poll изменений шары: висит на агенте до [holdSecs], пока версия
+     *  манифеста равна [since]. `{"changed":true,"version":N,"manifest":{..}}`
+     *  со свежим манифестом (подпись проверена, как в [nativeFetchManifest]),
+     *  `{"changed":false}` по истечении удержания, `{"error":".."}` при сбое.
+     *  [since] = 0 отвечается сразу. [indexPath] как у [nativeSyncShare]: по
+     *  копии листинга рядом с ним изменение приезжает дельтой. Блокирует на
+     *  всё удержание. */
+    external fun nativeWaitManifest(
+        agentUrl: String,
+        tokenJson: String,
+        agentPubkey: String,
+        relayJson: String,
+        indexPath: String,
+        since: Long,
+        holdSecs: Long,
+    ): String
+
     /** Pure diff for SAF storage. [manifestJson] is the agent manifest;
      *  [localJson] is `[{"path":..,"sha256":..}...]` the caller enumerated from
      *  the SAF tree. [selectionJson] is a JSON array of chosen manifest paths;
@@ -369,6 +386,33 @@ object NativeBridge {
         timeoutMs: Long,
     ): String
 
+    /** Space taken and left in the share under the owner's quota and disk
+     *  reserve: `{"used_bytes":..,"files":..,"free_bytes":..,"writable":..}` plus
+     *  `quota_bytes`/`max_files`/`extensions`/`mime` when the owner set them, or
+     *  `{"error":".."}`. */
+    external fun nativeShareUsage(
+        addr: String,
+        port: Int,
+        tokenJson: String,
+        agentPubkey: String,
+        relayJson: String,
+        timeoutMs: Long,
+    ): String
+
+    /** Fetch the JPEG preview of [path] (a manifest entry with `preview`) into
+     *  [destPath], a file in the app cache. Returns `{"ok":true}` or
+     *  `{"error":".."}` (`not_found`, `no_preview: ...`). */
+    external fun nativeFetchThumbnail(
+        addr: String,
+        port: Int,
+        tokenJson: String,
+        agentPubkey: String,
+        relayJson: String,
+        path: String,
+        destPath: String,
+        timeoutMs: Long,
+    ): String
+
     /** Delete [path] from the share itself (LLD-28, XR-250): the agent drops the
      *  file, so it leaves every holder of the share, not only this device.
      *  [expectedSha] is the hash of the manifest row the user acted on and goes
@@ -408,9 +452,10 @@ object NativeBridge {
     ): String
 
     /** Poll the running transfer's progress: `{active,id,cancelled,share,file,
-     *  files_done,files_total,bytes_done,bytes_total}` (`active:false` when
-     *  idle; `share` is empty for a storage migration; `id` это номер передачи
-     *  для отмены). */
+     *  files_done,files_total,bytes_done,bytes_resumed,bytes_total}`
+     *  (`active:false` when idle; `share` is empty for a storage migration;
+     *  `id` это номер передачи для отмены; `bytes_resumed` это часть
+     *  `bytes_done`, взятая из недокачанного `.part`). */
     external fun nativeTransferProgress(): String
 
     /** Cancel the sync/download с номером [id] из снимка прогресса (aborts at
@@ -493,6 +493,7 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
 
     fun openShare(config: ShareConfig) {
         offlineRetryJob?.cancel()
+        liveWatchJob?.cancel()
         _ui.update {
             it.copy(
                 openShareId = config.shareId, currentPath = "",
@@ -604,6 +605,7 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
                     withContext(Dispatchers.IO) { repo.saveManifestCache(config, fresh) }
                 }
                 enqueueMissingSelected(config.shareId)
+                startLiveWatch(config)
             } else {
                 maybeStartOfflineRetry(result.exceptionOrNull())
             }
@@ -657,6 +659,7 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
                     withContext(Dispatchers.IO) { repo.saveManifestCache(config, fresh) }
                 }
                 enqueueMissingSelected(config.shareId)
+                startLiveWatch(config)
             } else {
                 maybeStartOfflineRetry(result.exceptionOrNull())
             }
@@ -692,6 +695,7 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
                 val result = fetchManifestHealing(config)
                 if (result.isSuccess) {
                     applyFreshManifest(config, result.getOrDefault(emptyList()))
+                    startLiveWatch(config)
                     return@launch
                 }
                 // Содержательный ответ (404, 4xx, истёкший доступ, подпись не
@@ -706,6 +710,33 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
         }
     }
 
+    /** Живые изменения открытой шары: пока она на экране, на агенте висит
+     *  ожидание, и новый список подменяется молча, как у офлайн-ретрая. Если
+     *  шара ещё и зеркалится, изменение сразу будит фоновую синхронизацию, а
+     *  не ждёт шестичасового круга. Сбой ожидания (сеть, агент старее ручки)
+     *  цикл гасит: список остаётся как был, потерю сети ловит офлайн-ретрай. */
+    private var liveWatchJob: Job? = null
+
+    private fun startLiveWatch(config: ShareConfig) {
+        if (liveWatchJob?.isActive == true) return
+        liveWatchJob = viewModelScope.launch {
+            // 0 отвечается сразу: первая версия, с которой ждать дальше.
+            var since = 0L
+            while (true) {
+                val outcome = withContext(Dispatchers.IO) { repo.waitManifest(config, since) }
+                    .getOrNull() ?: return@launch
+                if (_ui.value.openShareId != config.shareId) return@launch
+                if (outcome !is ShareRepository.WaitOutcome.Changed) continue
+                applyFreshManifest(config, outcome.entries)
+                if (since != 0L) {
+                    val mirrored = store().get(config.shareId)?.syncEnabled == true
+                    if (mirrored) withContext(Dispatchers.IO) { ShareSyncScheduler.syncNow(getApplication()) }
+                }
+                since = outcome.version
+            }
+        }
+    }
+
     /** Свежий манифест пришёл вне явного действия пользователя: молча подменить
      *  список, снять пометку офлайна и доложить недокачанное в очередь. Текущая
      *  папка живёт в currentPath и подмену переживает. */
@@ -784,6 +815,7 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
         // джобы, поэтому возврат в шару застаёт свои строки на месте, а уход в
         // соседнюю их не роняет. Скачивание на агенте от нас и так не зависит.
         offlineRetryJob?.cancel()
+        liveWatchJob?.cancel()
         _ui.update {
             it.copy(
                 openShareId = null, currentPath = "", manifest = emptyList(),
@@ -1277,7 +1309,7 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
     private fun ensureTransferPolling() {
         if (transferPoll?.isActive == true) return
         transferPoll = viewModelScope.launch {
-            var lastBytes = 0L
+            var lastMoved = 0L
             var lastTime = System.currentTimeMillis()
             var lastFile = ""
             var wasActive = false
@@ -1289,6 +1321,9 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
                 runCatching { JSONObject(snap) }.getOrNull()?.let { o ->
                     active = o.optBoolean("active", false)
                     val bytesDone = if (active) o.optLong("bytes_done") else 0L
+                    // A resumed partial lands in bytes_done at once; only bytes
+                    // that actually moved count toward the speed.
+                    val moved = bytesDone - (if (active) o.optLong("bytes_resumed") else 0L)
                     val file = o.optString("file")
                     val now = System.currentTimeMillis()
                     val dt = (now - lastTime).coerceAtLeast(1)
@@ -1296,11 +1331,11 @@ class FilesViewModel(app: Application) : AndroidViewModel(app) {
                     // previous point: its whole byte count over a few ms would
                     // read as an absurd speed, so it shows as 0 for one tick.
                     val speed = if (active && wasActive && file == lastFile) {
-                        ((bytesDone - lastBytes) * 1000 / dt).coerceAtLeast(0)
+                        ((moved - lastMoved) * 1000 / dt).coerceAtLeast(0)
                     } else {
                         0
                     }
-                    lastBytes = bytesDone
+                    lastMoved = moved
                     lastTime = now
                     lastFile = file
                     _ui.update {
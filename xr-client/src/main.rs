@@ -10,7 +10,7 @@ use xr_proto::config::{decode_key, load_client_config, ObfuscationConfig, Server
 use xr_proto::obfuscation::{ModifierStrategy, Obfuscator};
 use xr_proto::protocol::Codec;
 use xr_proto::routing;
-use xr_proto::server_pool::{PoolProfile, PoolServer, ServerPool};
+use xr_proto::server_pool::{PoolProfile, PoolServer, SelectionPolicy, ServerPool};
 
 const CRASH_LOG: &str = "/etc/xr-proxy/crash.log";
 
@@ -84,8 +84,7 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
     let strategy = ModifierStrategy::from_str(&config.obfuscation.modifier)
         .ok_or("unknown modifier strategy")?;
     let obfuscator = Obfuscator::new(key, config.obfuscation.salt as u32, strategy);
-    let udp_obfuscator = obfuscator.clone(); // for UDP relay
-    let codec = Codec::new(
+    let mut codec = Codec::new(
         obfuscator,
         config.obfuscation.padding_min,
         config.obfuscation.padding_max,
@@ -98,12 +97,14 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Build router, optionally merging with hub preset.
     let geoip_path = config.geoip.as_ref().map(|g| g.database.as_str());
     let hub_config = config.hub.as_ref();
+    let mut current_key = None;
     let router = if let Some(hub) = hub_config {
         let cache_dir = std::path::Path::new("/var/lib/xr-proxy/presets");
         let mut cache = xr_core::presets::PresetCache::new(cache_dir, &hub.url, &hub.preset);
         cache.load_from_disk();
         // Forced fetch at startup with short timeout.
         let _ = cache.fetch_if_stale(std::time::Duration::from_secs(2)).await;
+        current_key = cache.current_key(xr_proto::config::unix_now());
         if let Some(preset_rules) = cache.routing_config() {
             tracing::info!("preset '{}' loaded, merging with local overrides", hub.preset);
             routing::Router::from_merged(&config.routing, preset_rules, geoip_path)
@@ -118,32 +119,67 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
         routing::Router::new(&config.routing, geoip_path)
     };
 
+    // Ротация ключа наступила, пока клиент стоял, или прошла раньше: сервер
+    // старый ключ уже отвергает, поэтому общий кодек сразу собирается на новом.
+    if let Some(key) = current_key {
+        match key.codec(config.obfuscation.padding_min, config.obfuscation.padding_max) {
+            Ok(rotated) => codec = rotated,
+            Err(e) => tracing::warn!("ignoring preset key rotation: {}", e),
+        }
+    }
+
     let on_server_down = routing::Action::on_server_down_from_str(&config.client.on_server_down);
 
     // Build the server pool: per-server MuxPool (N parallel mux tunnels each),
     // primary/backup by priority, failover/failback inside the pool (LLD-10).
     let mut pool_servers = Vec::with_capacity(server_entries.len());
+    // Пулы на общем ключе: ротация из пресета меняет только их, у записей со
+    // своим ключом (override) другой провайдер и своя ротация.
+    let mut shared_pools = Vec::new();
     for entry in &server_entries {
         let addr: SocketAddr = format!("{}:{}", entry.address, entry.port)
             .parse()
             .map_err(|e| format!("invalid server address {}: {}", entry.address, e))?;
         let entry_codec = codec_for_entry(entry, &config.obfuscation, &codec)?;
-        let mux_pool = xr_proto::mux_pool::MuxPool::new(
-            Arc::new(move || {
-                Box::pin(async move {
-                    xr_proto::tunnel::connect_to_server(&addr).await
-                })
-            }),
-            entry_codec,
-            config.client.mux_pool_size,
-        );
+        let connect: xr_proto::mux_pool::ConnectFn = Arc::new(move || {
+            Box::pin(async move {
+                xr_proto::tunnel::connect_to_server(&addr).await
+            })
+        });
+        let mux_pool = if config.client.mux_stripe {
+            xr_proto::mux_pool::MuxPool::new_striped(
+                connect,
+                entry_codec,
+                config.client.mux_pool_size,
+            )
+        } else {
+            xr_proto::mux_pool::MuxPool::new(connect, entry_codec, config.client.mux_pool_size)
+        };
+        if !entry.has_key_override() {
+            shared_pools.push(mux_pool.clone());
+        }
         pool_servers.push(PoolServer {
             name: entry.display_name().to_string(),
             addr: addr.to_string(),
             pool: mux_pool,
         });
     }
-    let server_pool = ServerPool::new(pool_servers, PoolProfile::router(), None);
+    let policy = SelectionPolicy::parse(&config.client.server_policy).unwrap_or_else(|| {
+        tracing::warn!(
+            "unknown server_policy {:?}, falling back to priority",
+            config.client.server_policy
+        );
+        SelectionPolicy::Priority
+    });
+    let profile = PoolProfile { policy, ..PoolProfile::router() };
+    let server_pool = ServerPool::new(pool_servers, profile, None);
+
+    // Отдельный relay-порт у primary, и ключ у него тот же, что у mux primary:
+    // на общем ключе он и поворачивается вместе с пулами.
+    let udp_key: udp_relay::RelayKey = Arc::new(std::sync::RwLock::new(Arc::new(
+        codec_for_entry(&server_entries[0], &config.obfuscation, &codec)?.obfuscator().clone(),
+    )));
+    let udp_key_rotates = !server_entries[0].has_key_override();
 
     // Фоновый пробер: держит mux ко всем серверам тёплым и возвращает трафик
     // на primary после восстановления (failback с hold-down).
@@ -205,6 +241,10 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Новые TCP-сессии после swap'а видят обновлённые правила, уже
     // активные продолжают со своим выбранным Action. Это честная семантика
     // "изменение применяется к новым соединениям".
+    //
+    // Тем же пресетом хаб раздаёт следующий ключ обфускации: в `switch_at`
+    // пулы на общем ключе переходят на него, и роутеры флота переезжают без
+    // правки client.toml.
     if let Some(hub) = config.hub.as_ref() {
         let hub_url = hub.url.clone();
         let preset_name = hub.preset.clone();
@@ -212,7 +252,10 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
         let local_overrides = config.routing.clone();
         let geoip_path_owned = config.geoip.as_ref().map(|g| g.database.clone());
         let state = state.clone();
+        let padding = (config.obfuscation.padding_min, config.obfuscation.padding_max);
+        let udp_key = udp_key.clone();
         tokio::spawn(async move {
+            let mut key_switch = xr_core::presets::KeySwitch::default();
             let cache_dir = std::path::Path::new("/var/lib/xr-proxy/presets");
             let mut cache = xr_core::presets::PresetCache::new(cache_dir, &hub_url, &preset_name);
             cache.load_from_disk();
@@ -220,10 +263,32 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
                 cache,
                 std::time::Duration::from_secs(interval_secs),
                 std::future::pending(),
-                |preset_rules| {
+                |preset| {
+                    let pools = shared_pools.clone();
+                    let key_preset = preset_name.clone();
+                    let udp_key = udp_key.clone();
+                    key_switch.arm(preset.next_key.as_ref(), move |key| {
+                        match key.codec(padding.0, padding.1) {
+                            Ok(codec) => {
+                                for pool in &pools {
+                                    pool.set_codec(codec.clone());
+                                }
+                                if udp_key_rotates {
+                                    *udp_key.write().unwrap() = Arc::new(codec.obfuscator().clone());
+                                }
+                                if let Err(e) =
+                                    xr_core::presets::save_applied_key(cache_dir, &key_preset, &key)
+                                {
+                                    tracing::warn!("failed to save applied preset key: {}", e);
+                                }
+                                tracing::info!("obfuscation key rotated on hub schedule");
+                            }
+                            Err(e) => tracing::warn!("preset key rotation failed: {}", e),
+                        }
+                    });
                     let new_router = routing::Router::from_merged(
                         &local_overrides,
-                        preset_rules,
+                        &preset.rules,
                         geoip_path_owned.as_deref(),
                     );
                     match state.router.write() {
@@ -244,14 +309,19 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
-    // Run UDP relay if configured. Relay ходит только через primary: его
-    // failover не входит в LLD-10 (у relay свой канал и своя семантика).
+    // Run UDP relay if configured. Датаграммы идут через mux активного сервера
+    // (failover пула достаётся даром); отдельный relay-порт primary остаётся
+    // запасным путём для серверов без датаграмм в mux.
     let server_address = server_entries[0].address.clone();
+    let udp_pool = state.server_pool.clone();
     let udp_handle = if let Some(udp_config) = config.udp_relay {
         if udp_config.enabled {
             tracing::info!("Starting UDP relay (port {})", udp_config.listen_port);
             Some(tokio::spawn(async move {
-                if let Err(e) = udp_relay::run_udp_relay(&udp_config, udp_obfuscator, &server_address).await {
+                if let Err(e) =
+                    udp_relay::run_udp_relay(&udp_config, udp_key, &server_address, udp_pool)
+                        .await
+                {
                     tracing::error!("UDP relay failed: {}", e);
                 }
             }))
@@ -308,7 +378,7 @@ fn codec_for_entry(
     obfuscation: &ObfuscationConfig,
     shared: &Codec,
 ) -> Result<Codec, Box<dyn std::error::Error>> {
-    if entry.key.is_none() && entry.salt.is_none() && entry.modifier.is_none() {
+    if !entry.has_key_override() {
         return Ok(shared.clone());
     }
     let key = decode_key(entry.key.as_deref().unwrap_or(&obfuscation.key))?;
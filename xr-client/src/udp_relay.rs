@@ -2,18 +2,26 @@
 ///
 /// Uses TPROXY (nftables + policy routing) to intercept UDP packets
 /// while preserving original destination address.
+///
+/// Основной путь это датаграммы в mux (`ServerPool::open_udp`): одна
+/// ассоциация на поток устройства, тот же туннель и тот же failover, что у
+/// TCP. Сервер без датаграмм в mux (старая сборка) обслуживается прежним
+/// отдельным relay-портом с NAT по туннельному порту.
 
 use std::collections::HashMap;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::os::unix::io::{AsRawFd, FromRawFd};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 use tokio::net::UdpSocket;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio::time::{interval, Duration};
 use xr_proto::config::UdpRelayClientConfig;
+use xr_proto::mux::MuxDatagram;
 use xr_proto::obfuscation::Obfuscator;
+use xr_proto::protocol::TargetAddr;
+use xr_proto::server_pool::ServerPool;
 use xr_proto::udp_relay::{self, RelayPacket, RelayType};
 
 // Linux socket constants that libc does not always export on musl/cross targets
@@ -37,6 +45,11 @@ const SOCK_NONBLOCK: libc::c_int = 0o4000;
 /// на устройство, и ответ по нему находит ровно одно устройство.
 const TUNNEL_PORT_POOL: std::ops::RangeInclusive<u16> = 40000..=65000;
 
+/// Очередь пакетов одного mux-потока. Держит пакеты, пока ассоциация
+/// открывается (один RTT до сервера); переполнение значит, что туннель не
+/// успевает, и пакет теряется, как потерялся бы в сети.
+const MUX_FLOW_QUEUE: usize = 64;
+
 struct UdpFlow {
     src_addr: SocketAddr,
     last_activity: Instant,
@@ -155,11 +168,19 @@ impl FlowTable {
         expired.len()
     }
 
+    /// Устройство уже ходит через отдельный relay (его флоу заведён).
+    fn has(&self, src_addr: SocketAddr) -> bool {
+        self.ports.contains_key(&src_addr)
+    }
+
     fn len(&self) -> usize {
         self.flows.len()
     }
 }
 
+/// Пакет устройства в очереди его mux-потока: (оригинальный адресат, тело).
+type MuxFlowPacket = (SocketAddr, Vec<u8>);
+
 /// Кэш спуфящих сокетов: адрес отправителя ответа -> сокет, забинденный на него
 /// с `IP_TRANSPARENT`. Адресатов в таблице флоу нет (номер выдаётся на
 /// устройство), поэтому живость сокета считается по нему самому: сокет живёт,
@@ -200,22 +221,38 @@ impl SpoofCache {
     }
 }
 
+/// Ключ отдельного relay. Ротация из пресета подменяет его на месте, как и
+/// кодек пулов: следующий пакет уже идёт на новом.
+pub type RelayKey = Arc<RwLock<Arc<Obfuscator>>>;
+
 struct RelayState {
     flows: Mutex<FlowTable>,
     spoof_sockets: Mutex<SpoofCache>,
-    obfuscator: Obfuscator,
+    obfuscator: RelayKey,
     vps_addr: SocketAddr,
     flow_timeout: Duration,
     source_ips: Vec<Ipv4Addr>,
     exclude_ports: Vec<u16>,
+    /// Потоки, идущие датаграммами в mux: адрес устройства -> очередь его таска.
+    mux_flows: Mutex<HashMap<SocketAddr, mpsc::Sender<MuxFlowPacket>>>,
+    server_pool: Arc<ServerPool>,
+    tunnel: Arc<UdpSocket>,
+}
+
+impl RelayState {
+    /// Текущий ключ relay-порта.
+    fn key(&self) -> Arc<Obfuscator> {
+        self.obfuscator.read().unwrap().clone()
+    }
 }
 
 // -- Main entry ---
 
 pub async fn run_udp_relay(
     config: &UdpRelayClientConfig,
-    obfuscator: Obfuscator,
+    obfuscator: RelayKey,
     server_address: &str,
+    server_pool: Arc<ServerPool>,
 ) -> io::Result<()> {
     let vps_host = config.vps_host.as_deref().unwrap_or(server_address);
     let vps_addr: SocketAddr = format!("{}:{}", vps_host, config.vps_port)
@@ -234,6 +271,12 @@ pub async fn run_udp_relay(
         tracing::info!("UDP relay: relaying only {:?}", source_ips);
     }
 
+    // Tunnel socket to VPS (normal tokio socket, no recvmsg needed)
+    let tunnel_socket = UdpSocket::bind("0.0.0.0:0").await?;
+    tracing::info!("UDP relay tunnel to {} (fallback for servers without mux datagrams)", vps_addr);
+
+    let tunnel = Arc::new(tunnel_socket);
+
     let state = Arc::new(RelayState {
         flows: Mutex::new(FlowTable::new(TUNNEL_PORT_POOL)),
         spoof_sockets: Mutex::new(SpoofCache::new()),
@@ -242,6 +285,9 @@ pub async fn run_udp_relay(
         flow_timeout: Duration::from_secs(config.flow_timeout_sec),
         source_ips,
         exclude_ports: config.exclude_dst_ports.clone(),
+        mux_flows: Mutex::new(HashMap::new()),
+        server_pool,
+        tunnel: tunnel.clone(),
     });
 
     // Bind local TPROXY listener with AsyncFd directly (not tokio UdpSocket)
@@ -256,14 +302,8 @@ pub async fn run_udp_relay(
         if config.use_tproxy { "TPROXY" } else { "REDIRECT" }
     );
 
-    // Tunnel socket to VPS (normal tokio socket, no recvmsg needed)
-    let tunnel_socket = UdpSocket::bind("0.0.0.0:0").await?;
-    tracing::info!("UDP relay tunnel to {}", vps_addr);
-
-    let tunnel = Arc::new(tunnel_socket);
-
     // Keepalive sender
-    let ka_obfs = state.obfuscator.clone();
+    let ka_state = state.clone();
     let ka_tunnel = tunnel.clone();
     let ka_vps = vps_addr;
     let ka_secs = config.keepalive_interval_sec;
@@ -271,7 +311,7 @@ pub async fn run_udp_relay(
         let mut timer = interval(Duration::from_secs(ka_secs));
         loop {
             timer.tick().await;
-            let wire = udp_relay::encode_keepalive(&ka_obfs);
+            let wire = udp_relay::encode_keepalive(&ka_state.key());
             let _ = ka_tunnel.send_to(&wire, ka_vps).await;
         }
     });
@@ -307,7 +347,6 @@ pub async fn run_udp_relay(
     // Upstream: LAN -> VPS
     let up_state = state.clone();
     let up_local = local_async.clone();
-    let up_tunnel = tunnel.clone();
     let use_tproxy = config.use_tproxy;
     let upstream = async move {
         let mut buf = vec![0u8; 65536];
@@ -334,19 +373,14 @@ pub async fn run_udp_relay(
                 continue;
             }
 
-            // Флоу заводится вместе со своим туннельным портом, он и уходит в
-            // туннель вместо настоящего порта устройства.
-            let packet = {
-                let mut flows = up_state.flows.lock().await;
-                match flows.upstream_packet(src_addr, orig_dst, buf[..n].to_vec(), Instant::now()) {
-                    Some(p) => p,
-                    None => continue,
-                }
-            };
-            let wire = udp_relay::encode_relay_packet(&up_state.obfuscator, &packet);
-            if let Err(e) = up_tunnel.send_to(&wire, up_state.vps_addr).await {
-                tracing::warn!("UDP relay: send to VPS failed: {}", e);
+            let payload = buf[..n].to_vec();
+            if up_state.flows.lock().await.has(src_addr) {
+                // Поток уже живёт на отдельном relay: сервер без датаграмм в
+                // mux, переезжать посреди потока незачем.
+                send_standalone(&up_state, src_addr, orig_dst, payload).await;
+                continue;
             }
+            route_to_mux(&up_state, src_addr, orig_dst, payload).await;
         }
         #[allow(unreachable_code)]
         Ok::<(), io::Error>(())
@@ -363,7 +397,7 @@ pub async fn run_udp_relay(
                 continue;
             }
 
-            let packet = match udp_relay::decode_relay_packet(&down_state.obfuscator, &buf[..n]) {
+            let packet = match udp_relay::decode_relay_packet(&down_state.key(), &buf[..n]) {
                 Some(p) => p,
                 None => {
                     tracing::debug!("UDP relay: invalid packet from VPS");
@@ -417,6 +451,117 @@ pub async fn run_udp_relay(
     }
 }
 
+/// Пакет в отдельный relay: флоу заводится вместе со своим туннельным портом,
+/// он и уходит в туннель вместо настоящего порта устройства.
+async fn send_standalone(state: &RelayState, src_addr: SocketAddr, orig_dst: SocketAddr, payload: Vec<u8>) {
+    let packet = {
+        let mut flows = state.flows.lock().await;
+        match flows.upstream_packet(src_addr, orig_dst, payload, Instant::now()) {
+            Some(p) => p,
+            None => return,
+        }
+    };
+    let wire = udp_relay::encode_relay_packet(&state.key(), &packet);
+    if let Err(e) = state.tunnel.send_to(&wire, state.vps_addr).await {
+        tracing::warn!("UDP relay: send to VPS failed: {}", e);
+    }
+}
+
+/// Пакет в mux-поток устройства, заводя поток на первом пакете. Приём не
+/// ждёт открытия ассоциации: пакет ложится в очередь таска потока.
+async fn route_to_mux(state: &Arc<RelayState>, src_addr: SocketAddr, orig_dst: SocketAddr, payload: Vec<u8>) {
+    let mut mux_flows = state.mux_flows.lock().await;
+    let payload = match mux_flows.get(&src_addr) {
+        Some(tx) => match tx.try_send((orig_dst, payload)) {
+            Ok(()) => return,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!("UDP relay: mux flow {} queue full, dropping", src_addr);
+                return;
+            }
+            // Таск потока ушёл (простой, смерть mux): заводим поток заново.
+            Err(mpsc::error::TrySendError::Closed((_, payload))) => payload,
+        },
+        None => payload,
+    };
+    let (tx, rx) = mpsc::channel(MUX_FLOW_QUEUE);
+    let _ = tx.try_send((orig_dst, payload));
+    mux_flows.insert(src_addr, tx);
+    drop(mux_flows);
+    tokio::spawn(run_mux_flow(state.clone(), src_addr, rx));
+}
+
+/// Таск одного потока устройства поверх mux. Открывает ассоциацию; сервер без
+/// датаграмм (`Unsupported`) или исчерпанный пул уводят поток на отдельный
+/// relay вместе со всем, что успело накопиться в очереди.
+async fn run_mux_flow(state: Arc<RelayState>, src_addr: SocketAddr, mut rx: mpsc::Receiver<MuxFlowPacket>) {
+    match state.server_pool.open_udp().await {
+        Ok(assoc) => {
+            tracing::debug!("UDP relay: {} via mux (sid={})", src_addr, assoc.stream_id());
+            pump_mux_flow(&state, src_addr, assoc, &mut rx).await;
+            retire_mux_flow(&state, src_addr, &mut rx).await;
+        }
+        Err(e) => {
+            tracing::debug!("UDP relay: {} falls back to relay port ({})", src_addr, e);
+            retire_mux_flow(&state, src_addr, &mut rx).await;
+            while let Ok((dst, payload)) = rx.try_recv() {
+                send_standalone(&state, src_addr, dst, payload).await;
+            }
+        }
+    }
+}
+
+/// Снять поток из таблицы, закрыв его очередь: новый пакет устройства
+/// заведёт поток заново, а не ляжет в очередь, которую никто не читает.
+async fn retire_mux_flow(state: &RelayState, src_addr: SocketAddr, rx: &mut mpsc::Receiver<MuxFlowPacket>) {
+    rx.close();
+    let mut mux_flows = state.mux_flows.lock().await;
+    // Запись могла уже смениться новым потоком того же устройства.
+    if mux_flows.get(&src_addr).is_some_and(|tx| tx.is_closed()) {
+        mux_flows.remove(&src_addr);
+    }
+}
+
+async fn pump_mux_flow(
+    state: &RelayState,
+    src_addr: SocketAddr,
+    mut assoc: MuxDatagram,
+    rx: &mut mpsc::Receiver<MuxFlowPacket>,
+) {
+    loop {
+        let step = tokio::time::timeout(state.flow_timeout, async {
+            tokio::select! {
+                p = rx.recv() => {
+                    let Some((dst, payload)) = p else { return false };
+                    if let Err(e) = assoc.send_to(&TargetAddr::Ip(dst), &payload) {
+                        tracing::debug!("UDP relay: mux send for {} failed: {}", src_addr, e);
+                        return e.kind() == io::ErrorKind::InvalidInput;
+                    }
+                    true
+                }
+                d = assoc.recv_from() => {
+                    let Some((from, payload)) = d else { return false };
+                    // Ответ отдаём устройству с адреса отправителя (спуф), как
+                    // и в отдельном relay: у входящего P2P это не тот, кому писали.
+                    let TargetAddr::Ip(from) = from else { return true };
+                    match get_or_create_spoof_socket(state, from).await {
+                        Ok(sock) => {
+                            if let Err(e) = do_sendto(sock.as_raw_fd(), &payload, src_addr) {
+                                tracing::warn!("UDP relay: spoof send to {} failed: {}", src_addr, e);
+                            }
+                        }
+                        Err(e) => tracing::warn!("UDP relay: spoof socket for {} failed: {}", from, e),
+                    }
+                    true
+                }
+            }
+        })
+        .await;
+        if step != Ok(true) {
+            return;
+        }
+    }
+}
+
 // -- Socket setup ---
 
 /// Get or create a UDP socket bound to `spoof_addr` with IP_TRANSPARENT.
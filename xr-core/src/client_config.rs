@@ -342,6 +342,7 @@ pub fn parse_config(json: &str) -> Result<VpnConfig, String> {
     let hub_cache_dir = get_str("hub_cache_dir").ok();
     let hub_refresh_interval_secs = get_num("hub_refresh_interval_secs").ok();
     let mux_pool_size = get_num("mux_pool_size").map(|v| v as usize).unwrap_or(0);
+    let server_policy = get_str("server_policy").unwrap_or_else(|_| "priority".into());
 
     let dns_resolvers = parse_dns_resolvers(json);
     let servers = parse_servers(json);
@@ -367,6 +368,7 @@ pub fn parse_config(json: &str) -> Result<VpnConfig, String> {
         // платформы, а не значение конфига.
         system_resolver: None,
         mux_pool_size,
+        server_policy,
     })
 }
 
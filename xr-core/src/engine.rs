@@ -17,15 +17,15 @@ use smoltcp::socket::tcp::State as TcpState;
 use tokio::sync::mpsc;
 use tokio::time::Duration;
 
-use xr_proto::config::{decode_key, RoutingConfig, ServerEntry};
+use xr_proto::config::{decode_key, unix_now, RoutingConfig, ServerEntry};
 use xr_proto::obfuscation::{ModifierStrategy, Obfuscator};
 use xr_proto::protocol::Codec;
 use xr_proto::routing::{Action, Router};
-use xr_proto::server_pool::{PoolProfile, PoolServer, ServerPool};
+use xr_proto::server_pool::{PoolProfile, PoolServer, SelectionPolicy, ServerPool};
 
 use crate::dns::FakeDns;
 use crate::ip_stack::{IpStack, PacketQueue};
-use crate::session::{relay_session_with_domain, ProtectSocketFn, SessionContext, SystemResolverFn, TcpSessionKey};
+use crate::session::{relay_session_with_domain, relay_udp_flow, ProtectSocketFn, SessionContext, SystemResolverFn, TcpSessionKey};
 use crate::state::{StateHandle, VpnState};
 use crate::stats::Stats;
 
@@ -98,6 +98,10 @@ pub struct VpnConfig {
     pub system_resolver: Option<SystemResolverFn>,
     /// Number of parallel mux tunnels (0 → pool default).
     pub mux_pool_size: usize,
+    /// Server selection policy of the pool (`priority`, `lowest_latency`,
+    /// `latency_with_hysteresis`). Latency policies probe every server each
+    /// tick, which costs radio wake-ups on a phone.
+    pub server_policy: String,
 }
 
 pub struct VpnEngine {
@@ -211,9 +215,10 @@ impl VpnEngine {
         let strategy = ModifierStrategy::from_str(&self.config.modifier)
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unknown modifier"))?;
         let obfuscator = Obfuscator::new(key, self.config.salt, strategy);
-        let codec = Codec::new(obfuscator, self.config.padding_min, self.config.padding_max);
+        let mut codec = Codec::new(obfuscator, self.config.padding_min, self.config.padding_max);
 
         // Build router, optionally merging with hub preset.
+        let mut current_key = None;
         let router = if let (Some(hub_url), Some(preset_name), Some(cache_dir)) = (
             &self.config.hub_url,
             &self.config.hub_preset,
@@ -230,6 +235,7 @@ impl VpnEngine {
             if let Ok(handle) = rt {
                 let _ = handle.block_on(cache.fetch_if_stale(Duration::from_secs(2)));
             }
+            current_key = cache.current_key(unix_now());
             if cache.routing_config().is_some() {
                 tracing::info!("merging preset '{}' with local overrides", preset_name);
             } else {
@@ -246,6 +252,14 @@ impl VpnEngine {
         } else {
             build_router(&self.config.routing, None, self.config.geoip_path.as_deref())
         };
+        // Ротация наступила, пока движок стоял, или прошла раньше: старый ключ
+        // сервер уже отвергает, поэтому пул сразу собирается на новом.
+        if let Some(key) = current_key {
+            match key.codec(self.config.padding_min, self.config.padding_max) {
+                Ok(rotated) => codec = rotated,
+                Err(e) => tracing::warn!("ignoring preset key rotation: {}", e),
+            }
+        }
         let on_server_down = Action::on_server_down_from_str(&self.config.on_server_down);
         let fake_dns = Arc::new(FakeDns::with_stats(self.stats.clone()));
 
@@ -267,6 +281,7 @@ impl VpnEngine {
 
         // Build per-server mux pools with the protected socket factory.
         let mut pool_servers = Vec::with_capacity(entries.len());
+        let mut mux_pools = Vec::with_capacity(entries.len());
         for entry in &entries {
             let addr: SocketAddr = format!("{}:{}", entry.address, entry.port)
                 .parse()
@@ -288,6 +303,7 @@ impl VpnEngine {
                 codec.clone(),
                 self.config.mux_pool_size,
             );
+            mux_pools.push(mux_pool.clone());
             pool_servers.push(PoolServer {
                 name: entry.display_name().to_string(),
                 addr: addr.to_string(),
@@ -297,9 +313,16 @@ impl VpnEngine {
         // Failover/failback дублируем в пользовательский журнал движка,
         // на эти записи опирается индикация LLD-10 §2.6.
         let stats_events = self.stats.clone();
+        let policy = SelectionPolicy::parse(&self.config.server_policy).unwrap_or_else(|| {
+            tracing::warn!(
+                "unknown server_policy {:?}, falling back to priority",
+                self.config.server_policy
+            );
+            SelectionPolicy::Priority
+        });
         let server_pool = ServerPool::new(
             pool_servers,
-            PoolProfile::mobile(),
+            PoolProfile { policy, ..PoolProfile::mobile() },
             Some(Arc::new(move |msg: &str| stats_events.add_log(msg))),
         );
 
@@ -370,6 +393,8 @@ impl VpnEngine {
         // продолжают со своим Action, новые подключения видят обновления.
         // Цикл общий с xr-client (LLD-37): пока хаб отвечает, правило
         // доезжает за секунды висящим запросом, иначе прежний опрос.
+        // Тем же пресетом едет следующий ключ обфускации: в момент
+        // переключения новые туннели всех серверов пула идут уже с ним.
         if let (Some(hub_url), Some(preset_name), Some(cache_dir)) = (
             self.config.hub_url.clone(),
             self.config.hub_preset.clone(),
@@ -380,7 +405,10 @@ impl VpnEngine {
             let geoip_path_owned = self.config.geoip_path.clone();
             let ctx_bg = ctx.clone();
             let mut shutdown_rx_bg = shutdown_rx.clone();
+            let padding = (self.config.padding_min, self.config.padding_max);
+            let stats_key = self.stats.clone();
             tokio::spawn(async move {
+                let mut key_switch = crate::presets::KeySwitch::default();
                 let mut cache = crate::presets::PresetCache::new(
                     std::path::Path::new(&cache_dir),
                     &hub_url,
@@ -393,15 +421,33 @@ impl VpnEngine {
                     async move {
                         let _ = shutdown_rx_bg.changed().await;
                     },
-                    |preset_rules| {
+                    |preset| {
                         swap_router(
                             &ctx_bg,
                             &local_overrides,
                             None,
-                            Some(preset_rules),
+                            Some(&preset.rules),
                             geoip_path_owned.as_deref(),
                             &format!("пресет '{}' обновился", preset_name),
                         );
+                        let pools = mux_pools.clone();
+                        let stats = stats_key.clone();
+                        let key_dir = std::path::PathBuf::from(&cache_dir);
+                        let key_preset = preset_name.clone();
+                        key_switch.arm(preset.next_key.as_ref(), move |key| {
+                            match key.codec(padding.0, padding.1) {
+                                Ok(codec) => {
+                                    for pool in &pools {
+                                        pool.set_codec(codec.clone());
+                                    }
+                                    if let Err(e) = crate::presets::save_applied_key(&key_dir, &key_preset, &key) {
+                                        tracing::warn!("failed to save applied preset key: {}", e);
+                                    }
+                                    stats.add_log("ключ обфускации сменён по расписанию хаба");
+                                }
+                                Err(e) => tracing::warn!("preset key rotation failed: {}", e),
+                            }
+                        });
                     },
                 )
                 .await;
@@ -609,6 +655,10 @@ async fn run_event_loop(
 
     let mut stale_keys: Vec<TcpSessionKey> = Vec::new();
 
+    // Проксируемые UDP-потоки: (src, dst) датаграмма → очередь задачи
+    // `relay_udp_flow`. Ключ тот же, что у TCP, но smoltcp здесь не участвует.
+    let mut udp_flows: HashMap<TcpSessionKey, mpsc::Sender<Vec<u8>>> = HashMap::new();
+
     loop {
         if *shutdown_rx.borrow() { return Ok(()); }
 
@@ -642,6 +692,9 @@ async fn run_event_loop(
                     }
                     port_to_key.clear();
                 }
+                // Закрытая очередь гасит задачу потока, ассоциация уходит
+                // вместе с mux старой сети.
+                udp_flows.clear();
             }
         }
 
@@ -651,6 +704,8 @@ async fn run_event_loop(
             if let Some(dns_response) = try_handle_dns(&packet, &fake_dns) {
                 ctx.stats.add_dns_query();
                 queue.push_outbound_public(dns_response);
+            } else if forward_udp_flow(&packet, &mut udp_flows, &ctx, &fake_dns, &queue) {
+                // Ушёл в туннель датаграммой.
             } else {
                 tcp_packets.push(packet);
             }
@@ -876,6 +931,7 @@ async fn run_event_loop(
         }
 
         // ── 5. Cleanup ──────────────────────────────────────────────
+        udp_flows.retain(|_, tx| !tx.is_closed());
         for key in &stale_keys {
             if let Some(session) = sessions.remove(key) {
                 port_to_key.remove(&session.eph_port);
@@ -1038,6 +1094,61 @@ fn drain_download_to_tx(
     }
 }
 
+// ── Proxied UDP ─────────────────────────────────────────────────────
+
+/// Глубина очереди одного UDP-потока. Переполнение это потеря датаграмма,
+/// штатная для UDP, а не подпор всего цикла событий.
+const UDP_FLOW_QUEUE: usize = 64;
+
+/// Отправить не-DNS UDP-пакет в mux-ассоциацию, если маршрут для его цели это
+/// `Action::Proxy`. `false` значит «не наш»: пакет идёт старым путём в
+/// smoltcp, у которого UDP-сокетов нет, то есть отбрасывается как раньше.
+fn forward_udp_flow(
+    packet: &[u8],
+    flows: &mut HashMap<TcpSessionKey, mpsc::Sender<Vec<u8>>>,
+    ctx: &Arc<SessionContext>,
+    fake_dns: &FakeDns,
+    queue: &PacketQueue,
+) -> bool {
+    let Some((src_ip, dst_ip, 17, ihl)) = parse_ipv4_header(packet) else { return false };
+    let udp = &packet[ihl..];
+    let Some((src_port, dst_port, data_offset)) = parse_udp_header(udp) else { return false };
+    let key = TcpSessionKey {
+        src_addr: SocketAddr::new(IpAddr::V4(src_ip), src_port),
+        dst_addr: SocketAddr::new(IpAddr::V4(dst_ip), dst_port),
+    };
+    let payload = udp[data_offset..].to_vec();
+
+    let payload = match flows.get(&key) {
+        Some(tx) => match tx.try_send(payload) {
+            Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => return true,
+            Err(mpsc::error::TrySendError::Closed(p)) => {
+                flows.remove(&key);
+                p
+            }
+        },
+        None => payload,
+    };
+
+    let domain = fake_dns.lookup(dst_ip);
+    let action = ctx.router.read().unwrap().resolve(domain.as_deref(), key.dst_addr.ip());
+    if action != Action::Proxy {
+        return false;
+    }
+
+    let (tx, rx) = mpsc::channel(UDP_FLOW_QUEUE);
+    let _ = tx.try_send(payload);
+    flows.insert(key, tx);
+    let ctx = ctx.clone();
+    let queue = queue.clone();
+    tokio::spawn(async move {
+        if let Err(e) = relay_udp_flow(ctx, key, domain, rx, queue).await {
+            tracing::debug!("udp flow {} -> {} ended: {}", key.src_addr, key.dst_addr, e);
+        }
+    });
+    true
+}
+
 // ── Packet helpers ──────────────────────────────────────────────────
 
 fn try_handle_dns(packet: &[u8], fake_dns: &FakeDns) -> Option<Vec<u8>> {
@@ -1173,6 +1284,7 @@ mod tests {
             hub_refresh_interval_secs: None,
             system_resolver: None,
             mux_pool_size: 1,
+            server_policy: "priority".into(),
         }
     }
 
@@ -1233,6 +1345,7 @@ mod tests {
                 updated_at: "2026-08-11T00:00:00Z".into(),
                 description: String::new(),
                 rules: routing_with(vec![("proxy", "example.com"), ("proxy", "youtube.com")]),
+                next_key: None,
                 signature: None,
             },
         )
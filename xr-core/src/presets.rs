@@ -3,8 +3,8 @@
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use xr_proto::config::RoutingConfig;
-use xr_proto::preset::{Preset, PresetSummary};
+use xr_proto::config::{unix_now, RoutingConfig};
+use xr_proto::preset::{NextKey, Preset, PresetSummary};
 use xr_proto::user_rule::UserRule;
 
 /// Исход [`PresetCache::refresh`]: обновились до новой версии либо локальная
@@ -211,6 +211,33 @@ impl PresetCache {
         self.cached.as_ref().map(|p| &p.rules)
     }
 
+    /// Весь закэшированный пресет: кроме правил в нём едет следующий ключ
+    /// обфускации.
+    pub fn preset(&self) -> Option<&Preset> {
+        self.cached.as_ref()
+    }
+
+    /// Ключ, на котором стартовать в момент `now`: уже наступивший `next_key`
+    /// пресета или ключ, применённый раньше ([`save_applied_key`]), из двух
+    /// более поздний. `None` значит ключ из конфига. Наступивший `next_key`
+    /// тут же сохраняется: следующий пресет его уже не несёт.
+    pub fn current_key(&self, now: u64) -> Option<NextKey> {
+        let saved = read_applied_key(&self.cache_dir, &self.preset_name);
+        let due = self
+            .preset()
+            .and_then(|p| p.next_key.clone())
+            .filter(|k| k.is_due(now));
+        match (due, saved) {
+            (Some(due), saved) if saved.as_ref().is_none_or(|s| s.switch_at < due.switch_at) => {
+                if let Err(e) = save_applied_key(&self.cache_dir, &self.preset_name, &due) {
+                    tracing::warn!("failed to save applied preset key: {}", e);
+                }
+                Some(due)
+            }
+            (_, saved) => saved,
+        }
+    }
+
     fn cache_path(&self) -> PathBuf {
         self.cache_dir.join(format!("{}.json", self.preset_name))
     }
@@ -236,8 +263,9 @@ impl PresetCache {
 
 /// Фоновая доставка правил с хаба, одна на всех потребителей (LLD-37):
 /// её крутят и `xr-client`, и `VpnEngine`, раньше у каждого был свой почти
-/// дословно совпадающий цикл. `on_rules` зовётся на каждой новой версии,
-/// вызывающий собирает merged-роутер и подменяет свой `RwLock`.
+/// дословно совпадающий цикл. `on_preset` зовётся на каждой новой версии,
+/// вызывающий собирает merged-роутер, подменяет свой `RwLock` и взводит
+/// [`KeySwitch`] по `next_key`.
 ///
 /// Пока хаб отвечает, правило доезжает за секунды. Отказ уводит в
 /// деградированный режим: пауза с backoff от [`DEGRADED_BACKOFF_START`] до
@@ -249,7 +277,7 @@ pub async fn watch_loop(
     mut cache: PresetCache,
     fallback_interval: Duration,
     shutdown: impl std::future::Future<Output = ()>,
-    mut on_rules: impl FnMut(&RoutingConfig),
+    mut on_preset: impl FnMut(&Preset),
 ) {
     let work = async {
         let mut backoff = Duration::ZERO;
@@ -257,16 +285,16 @@ pub async fn watch_loop(
             if !backoff.is_zero() {
                 tokio::time::sleep(backoff).await;
                 if cache.fetch_if_stale(Duration::from_secs(5)).await {
-                    if let Some(rules) = cache.routing_config() {
-                        on_rules(rules);
+                    if let Some(preset) = cache.preset() {
+                        on_preset(preset);
                     }
                 }
             }
             match cache.wait_for_update(WAIT_HOLD).await {
                 Ok(RefreshOutcome::Updated(version)) => {
                     backoff = Duration::ZERO;
-                    if let Some(rules) = cache.routing_config() {
-                        on_rules(rules);
+                    if let Some(preset) = cache.preset() {
+                        on_preset(preset);
                     }
                     tracing::info!("preset updated to v{} without restart", version);
                 }
@@ -290,6 +318,74 @@ pub async fn watch_loop(
     }
 }
 
+/// Плановая смена ключа обфускации по `next_key` пресета. Взводится на
+/// каждой версии пресета: тот же ключ оставляет таймер как есть, другой (или
+/// его снятие) перевзводит. В `switch_at` вызывается `apply`, уже
+/// наступивший момент срабатывает сразу. Таймер снимается с владельцем.
+#[derive(Default)]
+pub struct KeySwitch {
+    armed: Option<(NextKey, tokio::task::AbortHandle)>,
+}
+
+impl KeySwitch {
+    pub fn arm(&mut self, next: Option<&NextKey>, apply: impl FnOnce(NextKey) + Send + 'static) {
+        if self.armed.as_ref().map(|(key, _)| key) == next {
+            return;
+        }
+        if let Some((_, timer)) = self.armed.take() {
+            timer.abort();
+        }
+        let Some(next) = next.cloned() else {
+            return;
+        };
+        let delay = Duration::from_secs(next.switch_at.saturating_sub(unix_now()));
+        let key = next.clone();
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            apply(key);
+        });
+        self.armed = Some((next, timer.abort_handle()));
+    }
+}
+
+impl Drop for KeySwitch {
+    fn drop(&mut self) {
+        if let Some((_, timer)) = self.armed.take() {
+            timer.abort();
+        }
+    }
+}
+
+/// Запомнить ключ, на который клиент перешёл по расписанию хаба:
+/// `<cache_dir>/<name>.key.json` рядом с кэшем пресета. Без этого рестарт
+/// после второй ротации или после того, как хаб снял `next_key`, вернул бы
+/// клиента на ключ из конфига, а его сервер уже отвергает.
+pub fn save_applied_key(cache_dir: &Path, preset_name: &str, key: &NextKey) -> std::io::Result<()> {
+    std::fs::create_dir_all(cache_dir)?;
+    let data = serde_json::to_string_pretty(key).map_err(std::io::Error::other)?;
+    let path = applied_key_path(cache_dir, preset_name);
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, &path)
+}
+
+/// Сохранённый [`save_applied_key`] ключ; `None`, когда его нет или он битый.
+pub fn read_applied_key(cache_dir: &Path, preset_name: &str) -> Option<NextKey> {
+    let path = applied_key_path(cache_dir, preset_name);
+    let data = std::fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&data) {
+        Ok(key) => Some(key),
+        Err(e) => {
+            tracing::warn!("unreadable applied key {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn applied_key_path(cache_dir: &Path, preset_name: &str) -> PathBuf {
+    cache_dir.join(format!("{}.key.json", preset_name))
+}
+
 fn next_backoff(current: Duration, cap: Duration) -> Duration {
     if current.is_zero() {
         DEGRADED_BACKOFF_START.min(cap)
@@ -666,8 +762,8 @@ mod tests {
                 async {
                     let _ = stop_rx.await;
                 },
-                move |rules| {
-                    let _ = rules_tx.send(rules.rules[0].domains.clone());
+                move |preset| {
+                    let _ = rules_tx.send(preset.rules.rules[0].domains.clone());
                 },
             )
             .await;
@@ -717,8 +813,8 @@ mod tests {
                 async {
                     let _ = stop_rx.await;
                 },
-                move |rules| {
-                    let _ = rules_tx.send(rules.rules[0].domains.clone());
+                move |preset| {
+                    let _ = rules_tx.send(preset.rules.rules[0].domains.clone());
                 },
             )
             .await;
@@ -749,6 +845,75 @@ mod tests {
         );
     }
 
+    fn next_key(salt: u64, switch_at: u64) -> NextKey {
+        NextKey {
+            obfuscation_key: "a2V5".into(),
+            modifier: "positional_xor_rotate".into(),
+            salt,
+            switch_at,
+        }
+    }
+
+    // Ключ из очередной версии пресета взводит таймер один раз: повтор того
+    // же ключа (пресет переиздан ради правил) второй смены не планирует, а
+    // уже наступивший момент срабатывает сразу.
+    #[tokio::test(start_paused = true)]
+    async fn key_switch_fires_once_per_key() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let send = |tx: &tokio::sync::mpsc::UnboundedSender<u64>| {
+            let tx = tx.clone();
+            move |key: NextKey| {
+                let _ = tx.send(key.salt);
+            }
+        };
+        let mut switch = KeySwitch::default();
+
+        let soon = next_key(1, unix_now() + 60);
+        switch.arm(Some(&soon), send(&tx));
+        switch.arm(Some(&soon.clone()), send(&tx));
+        assert_eq!(rx.recv().await, Some(1));
+
+        switch.arm(Some(&next_key(2, 0)), send(&tx));
+        assert_eq!(rx.recv().await, Some(2));
+
+        // Снятый из пресета ключ отменяет взведённую смену.
+        switch.arm(Some(&next_key(3, unix_now() + 60)), send(&tx));
+        switch.arm(None, send(&tx));
+        drop(switch);
+        drop(tx);
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(rx.recv().await, None);
+    }
+
+    /// Наступивший ключ переживает рестарт: после того как хаб снял
+    /// `next_key`, старт берёт сохранённый, а не ключ конфига. Более поздний
+    /// из двух побеждает, будущий в расчёт не идёт.
+    #[test]
+    fn current_key_survives_rotation_and_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut preset = sample_preset();
+        preset.next_key = Some(next_key(1, 100));
+        PresetCache::write_to_disk(dir.path(), &preset).unwrap();
+        let mut cache = PresetCache::new(dir.path(), "http://hub.invalid", "russia");
+        cache.load_from_disk();
+        assert_eq!(cache.current_key(50), None, "момент ещё не наступил");
+        assert_eq!(cache.current_key(100).map(|k| k.salt), Some(1));
+
+        preset.next_key = None;
+        PresetCache::write_to_disk(dir.path(), &preset).unwrap();
+        cache.load_from_disk();
+        assert_eq!(cache.current_key(200).map(|k| k.salt), Some(1));
+
+        // Вторая ротация вытесняет первую, а с ранним switch_at нет.
+        preset.next_key = Some(next_key(2, 300));
+        PresetCache::write_to_disk(dir.path(), &preset).unwrap();
+        cache.load_from_disk();
+        assert_eq!(cache.current_key(300).map(|k| k.salt), Some(2));
+        save_applied_key(dir.path(), "russia", &next_key(3, 400)).unwrap();
+        assert_eq!(cache.current_key(500).map(|k| k.salt), Some(3));
+        assert_eq!(read_applied_key(dir.path(), "russia").map(|k| k.salt), Some(3));
+    }
+
     fn sample_preset() -> Preset {
         serde_json::from_str(
             r#"{"name":"russia","version":7,"updated_at":"2026-01-01T00:00:00+00:00",
@@ -5,6 +5,8 @@
 //! 2. Apply routing rules → Proxy or Direct
 //! 3. Establish PROTECTED outbound connection (bypasses VPN)
 //! 4. Relay data between smoltcp socket and outbound connection
+//!
+//! Proxied UDP flows bypass smoltcp entirely: see `relay_udp_flow`.
 
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -436,6 +438,77 @@ async fn relay_direct(
     relay_via_direct_stream(tr, tw, initial_data, data_rx, data_tx, waker).await
 }
 
+/// Простой UDP-потока, после которого ассоциация в туннеле закрывается. Короче
+/// TCP-шного: у UDP нет FIN, и конец потока виден только по тишине.
+const UDP_FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// UDP-поток приложения `key.src_addr → key.dst_addr` через mux-ассоциацию
+/// (`Command::UdpAssociate`). Движок зовёт его только для потоков с
+/// `Action::Proxy`; ответы целиком собираются в IPv4/UDP-пакеты от имени
+/// `key.dst_addr` и уходят прямо в TUN, мимо smoltcp.
+///
+/// Сервер без UDP-бита в `MuxCaps` отвечает `Unsupported`. Тогда поток не
+/// пересоздаётся на каждом датаграмме: задача молча вычерпывает `data_rx` до
+/// простоя, как и раньше, когда такие пакеты отбрасывал smoltcp.
+pub(crate) async fn relay_udp_flow(
+    ctx: Arc<SessionContext>,
+    key: TcpSessionKey,
+    domain: Option<String>,
+    mut data_rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    queue: crate::ip_stack::PacketQueue,
+) -> io::Result<()> {
+    let (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) = (key.src_addr.ip(), key.dst_addr.ip()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "udp flow is IPv4-only"));
+    };
+    let target = match domain {
+        Some(ref d) => TargetAddr::Domain(d.clone(), key.dst_addr.port()),
+        None => TargetAddr::Ip(key.dst_addr),
+    };
+
+    let mut assoc = match ctx.server_pool.open_udp().await {
+        Ok(assoc) => assoc,
+        Err(e) => {
+            tracing::debug!("udp flow {:?}: mux open fail: {}", target, e);
+            while let Ok(Some(_)) = tokio::time::timeout(UDP_FLOW_IDLE_TIMEOUT, data_rx.recv()).await {}
+            return Err(e);
+        }
+    };
+    let sender = assoc.sender();
+    tracing::debug!("udp flow {:?} via mux stream {}", target, assoc.stream_id());
+
+    let deadline = tokio::time::Instant::now() + MAX_LIFETIME;
+    loop {
+        tokio::select! {
+            pkt = data_rx.recv() => match pkt {
+                Some(data) => {
+                    ctx.stats.add_bytes_up(data.len() as u64);
+                    // Датаграмма больше кадра теряется одна, как потерялась бы
+                    // в сети; поток рвёт только мёртвый mux.
+                    if let Err(e) = sender.send_to(&target, &data) {
+                        if e.kind() != io::ErrorKind::InvalidInput {
+                            return Err(e);
+                        }
+                        tracing::debug!("udp flow {:?}: dropping datagram of {} bytes: {}", target, data.len(), e);
+                    }
+                }
+                None => return Ok(()),
+            },
+            dgram = assoc.recv_from() => match dgram {
+                Some((_from, data)) => {
+                    ctx.stats.add_bytes_down(data.len() as u64);
+                    queue.push_outbound_public(crate::engine::build_udp_response(
+                        dst_ip, src_ip, key.dst_addr.port(), key.src_addr.port(), &data,
+                    ));
+                    queue.notifier().notify_one();
+                }
+                None => return Ok(()),
+            },
+            _ = tokio::time::sleep(UDP_FLOW_IDLE_TIMEOUT) => return Ok(()),
+            _ = tokio::time::sleep_until(deadline) => return Ok(()),
+        }
+    }
+}
+
 /// Relay data between smoltcp channels and a directly connected socket.
 ///
 /// Молчащая сессия не должна висеть до потолка жизни, держа задачу, каналы и
@@ -25,7 +25,7 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use xr_proto::share::{
     scope_contains, RelayGrant, ShareGrant, ShareInfo, ShareManifest, ShareManifestEntry,
-    ShareToken, SCOPE_IMPORT, SCOPE_WRITE,
+    ShareToken, ShareUsage, SCOPE_IMPORT, SCOPE_WRITE,
 };
 
 // ── Transfer control (progress + cancel) ─────────────────────────────
@@ -52,6 +52,8 @@ struct TransferControl {
     /// Номер передачи, которую попросили остановить.
     cancel: AtomicU64,
     bytes_done: AtomicU64,
+    /// Part of `bytes_done` a resumed partial already had on disk.
+    bytes_resumed: AtomicU64,
     bytes_total: AtomicU64,
     files_done: AtomicU64,
     files_total: AtomicU64,
@@ -70,6 +72,7 @@ impl TransferControl {
             id: AtomicU64::new(0),
             cancel: AtomicU64::new(0),
             bytes_done: AtomicU64::new(0),
+            bytes_resumed: AtomicU64::new(0),
             bytes_total: AtomicU64::new(0),
             files_done: AtomicU64::new(0),
             files_total: AtomicU64::new(0),
@@ -110,6 +113,7 @@ impl TransferGuard {
         TRANSFER.id.store(id, Ordering::Relaxed);
         TRANSFER.cancel.store(0, Ordering::Relaxed);
         TRANSFER.bytes_done.store(0, Ordering::Relaxed);
+        TRANSFER.bytes_resumed.store(0, Ordering::Relaxed);
         TRANSFER.bytes_total.store(bytes_total, Ordering::Relaxed);
         TRANSFER.files_done.store(0, Ordering::Relaxed);
         TRANSFER.files_total.store(files_total as u64, Ordering::Relaxed);
@@ -137,6 +141,7 @@ impl Drop for TransferGuard {
         TRANSFER.id.store(0, Ordering::Relaxed);
         TRANSFER.cancel.store(0, Ordering::Relaxed);
         TRANSFER.bytes_done.store(0, Ordering::Relaxed);
+        TRANSFER.bytes_resumed.store(0, Ordering::Relaxed);
         TRANSFER.bytes_total.store(0, Ordering::Relaxed);
         TRANSFER.files_done.store(0, Ordering::Relaxed);
         TRANSFER.files_total.store(0, Ordering::Relaxed);
@@ -176,6 +181,29 @@ fn transfer_add_bytes(n: u64) {
     TRANSFER.bytes_done.fetch_add(n, Ordering::Relaxed);
 }
 
+fn transfer_bytes_done() -> u64 {
+    TRANSFER.bytes_done.load(Ordering::Relaxed)
+}
+
+fn transfer_bytes_resumed() -> u64 {
+    TRANSFER.bytes_resumed.load(Ordering::Relaxed)
+}
+
+/// Roll progress back to `done` after an attempt whose bytes will be counted
+/// again (a block transfer that fell back to the whole download, a direct
+/// leg that broke and resumes over the relay).
+fn transfer_rewind_bytes(done: u64) {
+    TRANSFER.bytes_done.store(done, Ordering::Relaxed);
+}
+
+/// Count `n` bytes a resumed partial already held: they go into `bytes_done`
+/// (the bar starts where the partial ends) and into `bytes_resumed`, set from
+/// `resumed` (the value before this file) so a retried file is counted once.
+fn transfer_add_resumed(resumed: u64, n: u64) {
+    TRANSFER.bytes_done.fetch_add(n, Ordering::Relaxed);
+    TRANSFER.bytes_resumed.store(resumed + n, Ordering::Relaxed);
+}
+
 /// A poll-able snapshot of transfer progress for the UI.
 #[derive(Debug, Clone, Serialize)]
 pub struct TransferSnapshot {
@@ -194,6 +222,10 @@ pub struct TransferSnapshot {
     pub files_done: u64,
     pub files_total: u64,
     pub bytes_done: u64,
+    /// Bytes of `bytes_done` that a partial from an earlier attempt (a killed
+    /// app, a dropped network) already had on disk. They did not move now, so
+    /// a speed readout leaves them out.
+    pub bytes_resumed: u64,
     pub bytes_total: u64,
 }
 
@@ -207,6 +239,7 @@ pub fn transfer_snapshot() -> TransferSnapshot {
         files_done: TRANSFER.files_done.load(Ordering::Relaxed),
         files_total: TRANSFER.files_total.load(Ordering::Relaxed),
         bytes_done: TRANSFER.bytes_done.load(Ordering::Relaxed),
+        bytes_resumed: transfer_bytes_resumed(),
         bytes_total: TRANSFER.bytes_total.load(Ordering::Relaxed),
     }
 }
@@ -528,6 +561,57 @@ fn tmp_sibling(path: &Path, seq: u64) -> PathBuf {
     path.with_file_name(name)
 }
 
+// ── Local manifest copy (delta manifests) ───────────────────────────
+//
+// A share of half a million files lists in megabytes; without a copy the
+// consumer downloads and re-plans all of it on every sync. The last verified
+// listing is kept next to the hash index, and the next fetch hands its version
+// to the agent as `?base=`, which answers with a signed delta when it still
+// remembers the changes since then and with the full listing otherwise.
+
+const MANIFEST_COPY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ManifestCopy {
+    version: u32,
+    manifest: ShareManifest,
+}
+
+/// Where the manifest copy of a share lives: beside its [`HashIndex`] file
+/// (`<share>.json` -> `<share>.manifest.json`), so one app-private directory
+/// holds both and callers pass a single path.
+pub fn manifest_copy_path(index_path: &Path) -> PathBuf {
+    index_path.with_extension("manifest.json")
+}
+
+/// The stored listing, if any. As with [`HashIndex::load`], a missing, corrupt
+/// or foreign file is no copy at all; so is an unversioned listing, which no
+/// delta can ever apply to.
+fn load_manifest_copy(path: &Path) -> Option<ShareManifest> {
+    let bytes = std::fs::read(path).ok()?;
+    let copy = serde_json::from_slice::<ManifestCopy>(&bytes).ok()?;
+    (copy.version == MANIFEST_COPY_VERSION && copy.manifest.version != 0).then_some(copy.manifest)
+}
+
+/// Persist the listing atomically, like [`HashIndex::save`]. A listing the
+/// copy already holds is not rewritten; failure only costs a full fetch next
+/// time.
+fn save_manifest_copy(path: &Path, manifest: &ShareManifest, previous: Option<&ShareManifest>) {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    if manifest.version == 0 || previous.is_some_and(|p| p.version == manifest.version) {
+        return;
+    }
+    let copy = ManifestCopy { version: MANIFEST_COPY_VERSION, manifest: manifest.clone() };
+    let Ok(bytes) = serde_json::to_vec(&copy) else { return };
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let tmp = tmp_sibling(path, SEQ.fetch_add(1, Ordering::Relaxed));
+    if std::fs::write(&tmp, bytes).is_err() || std::fs::rename(&tmp, path).is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+}
+
 /// Like [`scan_local_dir`], but hashing goes through a persistent [`HashIndex`]:
 /// a file whose `(size, mtime)` matches the index reuses the stored hash, so a
 /// warm scan is a stat-walk with no content reads. The index is rebuilt from
@@ -568,7 +652,7 @@ fn scan_dir(
             // attempts, so the index never matches), and once listed it lands
             // in plan.delete, where a cancelled retry wipes the resume
             // progress (XR-107).
-            if entry.file_name().to_string_lossy().ends_with(PART_SUFFIX) {
+            if is_partial_name(&entry.file_name().to_string_lossy()) {
                 continue;
             }
             let rel = rel_slash(root, &path);
@@ -600,6 +684,15 @@ fn scan_dir(
 /// manifest.
 const PART_SUFFIX: &str = ".xrsync-part";
 
+/// Suffix of the [`PartRecord`] beside a partial. Skipped and swept together
+/// with the partial itself.
+const PART_RECORD_SUFFIX: &str = ".xrsync-part.json";
+
+/// A download leftover or its record: never local state.
+fn is_partial_name(name: &str) -> bool {
+    name.ends_with(PART_SUFFIX) || name.ends_with(PART_RECORD_SUFFIX)
+}
+
 /// Outcome of [`migrate_dir`]. `moved`/`bytes` count relocated files; `conflicts`
 /// are paths left in place because the destination already held a **different**
 /// file there (the source copy is kept, the user decides); `failed` are per-file
@@ -650,7 +743,7 @@ fn collect_dir(root: &Path, dir: &Path, out: &mut Vec<MigFile>) -> std::io::Resu
         if ft.is_dir() {
             collect_dir(root, &path, out)?;
         } else if ft.is_file() {
-            if entry.file_name().to_string_lossy().ends_with(PART_SUFFIX) {
+            if is_partial_name(&entry.file_name().to_string_lossy()) {
                 continue;
             }
             let rel = rel_slash(root, &path);
@@ -777,7 +870,7 @@ fn remove_part_leftovers(root: &Path) {
         let path = entry.path();
         if path.is_dir() {
             remove_part_leftovers(&path);
-        } else if entry.file_name().to_string_lossy().ends_with(PART_SUFFIX) {
+        } else if is_partial_name(&entry.file_name().to_string_lossy()) {
             let _ = std::fs::remove_file(&path);
         }
     }
@@ -1006,6 +1099,12 @@ pub async fn sync_share_grant(
 /// `share:write` scope: the invite is read-only for this share, so we refuse
 /// before touching the network (LLD-28 п. 2.4). Worded for the user.
 pub const ERR_NO_WRITE_SCOPE: &str = "no_write_scope: нет права записи на эту шару";
+/// The agent answered `415`: the share's policy does not take this file type
+/// (its accepted types are in [`fetch_share_usage`]).
+pub const ERR_TYPE_NOT_ALLOWED: &str = "type_not_allowed: шара не принимает файлы такого типа";
+/// The agent answered `507`: the share's quota, its file limit or the disk
+/// reserve would be exceeded, or the disk is simply full.
+pub const ERR_NO_SPACE: &str = "no_space: в шаре не осталось места";
 
 /// Upload `local_path` to `rel` inside the grant's share (LLD-28). Uses the same
 /// transport as sync: direct plain HTTP first, the relay's pinned TLS as a
@@ -1105,10 +1204,41 @@ async fn upload_with(
     let resp = req.send().await.map_err(|e| format!("network: {e}"))?;
     match resp.status().as_u16() {
         201 | 204 => Ok(()),
+        415 => Err(ERR_TYPE_NOT_ALLOWED.to_string()),
+        507 => Err(ERR_NO_SPACE.to_string()),
         code => Err(format!("http_{code}")),
     }
 }
 
+/// What the grant's share holds and how much more it takes under the owner's
+/// quota and disk reserve, with the file types it accepts, so the UI shows the
+/// space left before an upload. Read scope is enough.
+pub async fn fetch_share_usage(grant: &ShareGrant, timeout: Duration) -> Result<ShareUsage, String> {
+    let token = decode_share_token(&grant.token)?;
+    let candidates = grant_direct_bases(grant);
+    let token = &token;
+    direct_then_relay(
+        &candidates,
+        &grant.agent_pubkey,
+        grant.relay.as_ref(),
+        &token.share_id,
+        timeout,
+        |client, base| async move {
+            let resp = client
+                .get(format!("{}/usage", base.trim_end_matches('/')))
+                .bearer_auth(token_blob(token))
+                .send()
+                .await
+                .map_err(|e| format!("network: {e}"))?;
+            match resp.status().as_u16() {
+                200 => resp.json().await.map_err(|e| format!("parse: {e}")),
+                code => Err(format!("http_{code}")),
+            }
+        },
+    )
+    .await
+}
+
 /// DELETE `rel` over an already-chosen transport. `204` is success, `404` maps to
 /// a named error, otherwise `http_<code>`; a connect failure is `network:`.
 async fn delete_with(
@@ -1303,6 +1433,45 @@ pub async fn import_cancel(grant: &ShareGrant, job_id: &str, timeout: Duration)
     .await
 }
 
+// -- previews ---------------------------------------------------------
+
+/// The agent answered `415`: this file has no preview (not an image, too large
+/// for the agent's limit, or it failed to decode). Not worth asking again until
+/// the file changes.
+pub const ERR_NO_PREVIEW: &str = "no_preview: у файла нет превью";
+
+/// Fetch the JPEG preview of `rel` for the file browser: a thumbnail of an image
+/// or a poster frame of a video, for entries the manifest marks with `preview`.
+/// Needs only the read scope. A `404` (no such file, or previews off at the
+/// agent) maps to `not_found`, a `415` to [`ERR_NO_PREVIEW`].
+pub async fn fetch_thumbnail(grant: &ShareGrant, rel: &str, timeout: Duration) -> Result<Vec<u8>, String> {
+    let token = decode_share_token(&grant.token)?;
+    let candidates = grant_direct_bases(grant);
+    let token = &token;
+    direct_then_relay(
+        &candidates,
+        &grant.agent_pubkey,
+        grant.relay.as_ref(),
+        &token.share_id,
+        timeout,
+        |client, base| async move {
+            let resp = client
+                .get(format!("{}/thumb/{}", base.trim_end_matches('/'), encode_path(rel)))
+                .bearer_auth(token_blob(token))
+                .send()
+                .await
+                .map_err(|e| format!("network: {e}"))?;
+            match resp.status().as_u16() {
+                200 => resp.bytes().await.map(|b| b.to_vec()).map_err(|e| format!("network: {e}")),
+                404 => Err("not_found".into()),
+                415 => Err(ERR_NO_PREVIEW.to_string()),
+                code => Err(format!("http_{code}")),
+            }
+        },
+    )
+    .await
+}
+
 /// A connect/timeout failure (as opposed to an authoritative HTTP/signature
 /// answer): only this warrants falling back to the relay.
 fn is_unreachable(err: &str) -> bool {
@@ -1508,7 +1677,16 @@ async fn run_sync(
     index_path: Option<&Path>,
     dry_run: bool,
 ) -> Result<SyncResult, String> {
-    let manifest = fetch_manifest_with(client, base_url, token, agent_pubkey).await?;
+    let copy_path = index_path.map(manifest_copy_path);
+    let copy = copy_path.as_deref().and_then(load_manifest_copy);
+    let url = format!("{}/manifest", base_url.trim_end_matches('/'));
+    let manifest = match fetch_manifest_at(client, &url, token, agent_pubkey, copy.as_ref()).await? {
+        Some((manifest, _)) => manifest,
+        None => return Err("http_304".into()),
+    };
+    if let Some(p) = &copy_path {
+        save_manifest_copy(p, &manifest, copy.as_ref());
+    }
     let mut index = index_path.map(HashIndex::load).unwrap_or_default();
     let local = scan_local_dir_indexed(dest_root, &mut index).map_err(|e| format!("scan: {e}"))?;
     // Persist right after the scan: the expensive hashing just happened, and a
@@ -1609,18 +1787,114 @@ async fn fetch_manifest_with(
     token: &ShareToken,
     agent_pubkey: &str,
 ) -> Result<ShareManifest, String> {
-    use xr_proto::share::{MANIFEST_SIGNED_AT_HEADER, MANIFEST_SIG_HEADER};
-
     let url = format!("{}/manifest", agent_url.trim_end_matches('/'));
+    match fetch_manifest_at(client, &url, token, agent_pubkey, None).await? {
+        Some((manifest, _)) => Ok(manifest),
+        None => Err("http_304".into()),
+    }
+}
+
+/// Итог ожидания изменений шары ([`wait_manifest_relay`]).
+#[derive(Debug)]
+pub enum ManifestWait {
+    /// Шара изменилась или версия клиента не совпала с агентской: свежий
+    /// манифест и версия, с которой ждать дальше.
+    Changed { manifest: ShareManifest, version: u64 },
+    /// За удержание ничего не изменилось, ждать дальше с той же версией.
+    UpToDate,
+}
+
+/// Запас клиентского таймаута над удержанием ожидания: клиент не должен
+/// рвать собственный висящий запрос раньше агента (как у пресетов).
+const MANIFEST_WAIT_MARGIN: Duration = Duration::from_secs(15);
+
+/// Long-poll изменений шары: запрос висит на `manifest/wait` агента, пока
+/// версия манифеста равна `since`, и возвращается либо свежим манифестом с
+/// новой версией, либо [`ManifestWait::UpToDate`] по истечении `hold`.
+/// `since = 0` отвечается сразу: так достаётся первая версия. Подпись
+/// манифеста проверяется так же, как в [`fetch_manifest`]; агент без
+/// ожидания изменений отвечает `http_404`, без версии в ответе это
+/// `manifest_unversioned`, и вызывающий уходит на плановый опрос.
+/// `index_path`, как у синка, указывает на копию листинга рядом с индексом
+/// хешей: с ней изменение приезжает дельтой, а не всем листингом.
+#[allow(clippy::too_many_arguments)]
+pub async fn wait_manifest_relay(
+    agent_url: &str,
+    token: &ShareToken,
+    agent_pubkey: &str,
+    relay: Option<&RelayGrant>,
+    index_path: Option<&Path>,
+    since: u64,
+    hold: Duration,
+) -> Result<ManifestWait, String> {
+    let candidates = split_candidates(agent_url);
+    let timeout = hold + MANIFEST_WAIT_MARGIN;
+    let copy_path = index_path.map(manifest_copy_path);
+    let copy = copy_path.as_deref().and_then(load_manifest_copy);
+    let copy = copy.as_ref();
+    let out = direct_then_relay(&candidates, agent_pubkey, relay, &token.share_id, timeout, |client, base| async move {
+        let url = format!(
+            "{}/manifest/wait?since={since}&timeout_secs={}",
+            base.trim_end_matches('/'),
+            hold.as_secs()
+        );
+        match fetch_manifest_at(&client, &url, token, agent_pubkey, copy).await? {
+            Some((manifest, Some(version))) => Ok(ManifestWait::Changed { manifest, version }),
+            Some((_, None)) => Err("manifest_unversioned".into()),
+            None => Ok(ManifestWait::UpToDate),
+        }
+    })
+    .await;
+    if let (Some(p), Ok(ManifestWait::Changed { manifest, .. })) = (&copy_path, &out) {
+        save_manifest_copy(p, manifest, copy);
+    }
+    out
+}
+
+/// GET a manifest URL (the plain one or its wait route) and verify it as
+/// [`fetch_manifest`] describes. `None` is a `304` from a wait that timed out;
+/// the version is absent from an agent that predates live changes.
+///
+/// With a `copy` of an earlier listing the agent is asked for a delta from its
+/// version; a delta is verified over its own signature domain and applied
+/// onto the copy, so the caller always gets the full listing back. A delta for
+/// some other base is refused rather than grafted onto the wrong listing.
+async fn fetch_manifest_at(
+    client: &reqwest::Client,
+    url: &str,
+    token: &ShareToken,
+    agent_pubkey: &str,
+    copy: Option<&ShareManifest>,
+) -> Result<Option<(ShareManifest, Option<u64>)>, String> {
+    use xr_proto::share::{
+        ShareManifestDelta, MANIFEST_BASE_PARAM, MANIFEST_DELTA_HEADER, MANIFEST_SIGNED_AT_HEADER,
+        MANIFEST_SIG_HEADER, MANIFEST_VERSION_HEADER,
+    };
+
+    let url = match copy {
+        Some(c) => {
+            let sep = if url.contains('?') { '&' } else { '?' };
+            format!("{url}{sep}{MANIFEST_BASE_PARAM}={}", c.version)
+        }
+        None => url.to_string(),
+    };
     let resp = client
         .get(&url)
         .bearer_auth(token_blob(token))
         .send()
         .await
         .map_err(|e| format!("network: {e}"))?;
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
     if !resp.status().is_success() {
         return Err(format!("http_{}", resp.status().as_u16()));
     }
+    let version = resp
+        .headers()
+        .get(MANIFEST_VERSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
     let sig = resp
         .headers()
         .get(MANIFEST_SIG_HEADER)
@@ -1631,6 +1905,7 @@ async fn fetch_manifest_with(
         .get(MANIFEST_SIGNED_AT_HEADER)
         .and_then(|v| v.to_str().ok())
         .and_then(|s| s.parse::<u64>().ok());
+    let is_delta = resp.headers().contains_key(MANIFEST_DELTA_HEADER);
     let body = resp.bytes().await.map_err(|e| format!("read: {e}"))?;
 
     if !agent_pubkey.is_empty() {
@@ -1641,11 +1916,110 @@ async fn fetch_manifest_with(
             // indistinguishable here, so both are refused.
             return Err("manifest_unsigned".into());
         };
-        xr_proto::share::verify_share_manifest(&sig, &key, &token.share_id, signed_at, &body)
-            .map_err(|e| format!("manifest_signature: {e}"))?;
+        let verify = if is_delta {
+            xr_proto::share::verify_manifest_delta
+        } else {
+            xr_proto::share::verify_share_manifest
+        };
+        verify(&sig, &key, &token.share_id, signed_at, &body).map_err(|e| format!("manifest_signature: {e}"))?;
+    }
+
+    if is_delta {
+        let delta = serde_json::from_slice::<ShareManifestDelta>(&body).map_err(|e| format!("parse: {e}"))?;
+        let Some(mut manifest) = copy.filter(|c| c.version == delta.base).cloned() else {
+            return Err("manifest_delta_base".into());
+        };
+        delta.apply(&mut manifest);
+        return Ok(Some((manifest, Some(delta.version))));
     }
+    let manifest = serde_json::from_slice::<ShareManifest>(&body).map_err(|e| format!("parse: {e}"))?;
+    // The signed version in the body wins over the bare header when both exist.
+    let version = Some(manifest.version).filter(|v| *v != 0).or(version);
+    Ok(Some((manifest, version)))
+}
+
+// ── Resumable partials ──────────────────────────────────────────────
+//
+// A partial survives a killed app and a dropped network: beside
+// `<name>.xrsync-part` sits a record naming the version of the file it
+// assembles and how many of its bytes are known to be on disk. The next
+// download of that version cuts the partial to the recorded offset and asks
+// for the rest with a Range request, over whichever leg answers (direct or
+// relay). Bytes past the offset are not trusted: after a crash the tail of a
+// file can hold whatever never left the page cache.
+
+/// The partial is synced to disk (and its record moved forward) every this
+/// many bytes, so a kill costs at most this much of a download.
+const PART_SYNC_EVERY: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PartRecord {
+    sha256: String,
+    size: u64,
+    mtime: i64,
+    /// Bytes of the partial synced to disk.
+    offset: u64,
+}
+
+impl PartRecord {
+    fn new(entry: &ShareManifestEntry, offset: u64) -> Self {
+        Self { sha256: entry.sha256.clone(), size: entry.size, mtime: entry.mtime, offset }
+    }
+
+    /// Whether the partial assembles this version of the file. The hash names
+    /// the version; an entry the agent has not hashed yet falls back to its
+    /// mtime.
+    fn matches(&self, entry: &ShareManifestEntry) -> bool {
+        self.size == entry.size
+            && self.sha256.eq_ignore_ascii_case(&entry.sha256)
+            && (!entry.sha256.is_empty() || self.mtime == entry.mtime)
+    }
+}
+
+fn part_record_path(part: &Path) -> PathBuf {
+    let mut name = part.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+/// The record beside `part`. A missing or torn one is no record: the
+/// partial it would describe is not resumed.
+fn load_part_record(part: &Path) -> Option<PartRecord> {
+    let bytes = std::fs::read(part_record_path(part)).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+async fn remove_partial(part: &Path) {
+    let _ = tokio::fs::remove_file(part).await;
+    let _ = tokio::fs::remove_file(part_record_path(part)).await;
+}
 
-    serde_json::from_slice::<ShareManifest>(&body).map_err(|e| format!("parse: {e}"))
+/// Push `written` bytes of `file` to disk, then record them as resumable.
+async fn sync_partial(
+    file: &mut tokio::fs::File,
+    part: &Path,
+    entry: &ShareManifestEntry,
+    written: u64,
+) -> Result<(), String> {
+    use tokio::io::AsyncWriteExt;
+    file.flush().await.map_err(|e| format!("flush: {e}"))?;
+    file.sync_data().await.map_err(|e| format!("flush: {e}"))?;
+    let record = serde_json::to_vec(&PartRecord::new(entry, written)).map_err(|e| format!("write: {e}"))?;
+    tokio::fs::write(part_record_path(part), record)
+        .await
+        .map_err(|e| format!("write: {e}"))
+}
+
+/// Record a partial written by someone else (the block transfer) as resumable
+/// up to its current length.
+async fn record_partial(part: &Path, entry: &ShareManifestEntry) -> Result<(), String> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(part)
+        .await
+        .map_err(|e| format!("open part: {e}"))?;
+    let len = file.metadata().await.map_err(|e| format!("open part: {e}"))?.len();
+    sync_partial(&mut file, part, entry, len).await
 }
 
 /// Download one entry to `dest_root`, streaming + verifying SHA-256, and only
@@ -1705,17 +2079,50 @@ async fn download_entry_with(
 
     use tokio::io::AsyncWriteExt;
 
-    // Resume: if a shorter-than-expected partial is on disk, ask for the rest
-    // with a Range request rather than re-downloading from byte zero (a 20 GB
-    // file should not restart after a blip). A complete/oversized stale partial
-    // is discarded. Integrity is still the final SHA-256 over the whole file.
+    // Progress as it stood before this file: an attempt whose bytes get
+    // counted again (a block transfer falling back, a broken direct leg
+    // retried over the relay) rolls back to it.
+    let start = (transfer_bytes_done(), transfer_bytes_resumed());
+
+    // A changed big file with a local copy and nothing to resume goes by
+    // blocks: only the chunks the copy lacks travel. The partial it leaves on
+    // failure is a correct prefix of the new file (pieces are written in
+    // order), so it is recorded and the whole download below (or the next
+    // sync, after a cancel) resumes from it instead of byte 0.
+    if entry.size >= xr_proto::chunks::CHUNKED_MIN_FILE
+        && !entry.sha256.is_empty()
+        && dest.is_file()
+        && !part.exists()
+    {
+        match download_blocks(client, agent_url, token, entry, &dest, &part).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if part.exists() {
+                    let _ = record_partial(&part, entry).await;
+                }
+                if e == "cancelled" {
+                    return Err(e);
+                }
+                transfer_rewind_bytes(start.0);
+            }
+        }
+    }
+
+    // Resume: a partial of this very version continues from its recorded
+    // offset with a Range request rather than from byte zero (a 20 GB file
+    // should not restart after a blip or an app kill). A partial of another
+    // version, one without a record (its tail is not known to be on disk) or
+    // a complete one is discarded. Integrity is still the final SHA-256 over
+    // the whole file.
     let mut resume_from: u64 = 0;
     if let Ok(meta) = tokio::fs::metadata(&part).await {
-        let n = meta.len();
-        if n > 0 && n < entry.size {
-            resume_from = n;
+        let offset = load_part_record(&part)
+            .filter(|r| r.matches(entry))
+            .map_or(0, |r| r.offset.min(meta.len()));
+        if offset > 0 && offset < entry.size {
+            resume_from = offset;
         } else {
-            let _ = tokio::fs::remove_file(&part).await;
+            remove_partial(&part).await;
         }
     }
 
@@ -1733,29 +2140,56 @@ async fn download_entry_with(
     // body is the whole file, so we must restart to avoid corrupting the partial.
     let appending = resume_from > 0 && resp.status().as_u16() == 206;
 
+    transfer_rewind_bytes(start.0);
     let mut hasher = Sha256::new();
     let mut file = if appending {
-        // Re-hash the bytes already on disk so the final check covers them.
+        // Cut the unrecorded tail, then re-hash the bytes kept so the final
+        // check covers them.
+        let f = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&part)
+            .await
+            .map_err(|e| format!("open part: {e}"))?;
+        f.set_len(resume_from).await.map_err(|e| format!("open part: {e}"))?;
+        drop(f);
         read_into_hasher(&part, &mut hasher).await.map_err(|e| format!("read part: {e}"))?;
-        transfer_add_bytes(resume_from);
+        transfer_add_resumed(start.1, resume_from);
         tokio::fs::OpenOptions::new()
             .append(true)
             .open(&part)
             .await
             .map_err(|e| format!("open part: {e}"))?
     } else {
+        remove_partial(&part).await;
         tokio::fs::File::create(&part).await.map_err(|e| format!("create: {e}"))?
     };
 
-    while let Some(chunk) = resp.chunk().await.map_err(|e| format!("read: {e}"))? {
+    let mut written = if appending { resume_from } else { 0 };
+    let mut synced = written;
+    loop {
+        let chunk = match resp.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                // Keep what arrived: the relay leg or the next sync resumes
+                // from here.
+                let _ = sync_partial(&mut file, &part, entry, written).await;
+                return Err(format!("read: {e}"));
+            }
+        };
         if transfer_cancelled() {
             // Keep the partial so the next attempt resumes from here.
-            let _ = file.flush().await;
+            let _ = sync_partial(&mut file, &part, entry, written).await;
             return Err("cancelled".into());
         }
         hasher.update(&chunk);
         file.write_all(&chunk).await.map_err(|e| format!("write: {e}"))?;
         transfer_add_bytes(chunk.len() as u64);
+        written += chunk.len() as u64;
+        if written - synced >= PART_SYNC_EVERY {
+            sync_partial(&mut file, &part, entry, written).await?;
+            synced = written;
+        }
     }
     file.flush().await.map_err(|e| format!("flush: {e}"))?;
     drop(file);
@@ -1766,12 +2200,121 @@ async fn download_entry_with(
     // nothing to compare against. A non-empty mismatch is still a hard error.
     if !entry.sha256.is_empty() && !got.eq_ignore_ascii_case(&entry.sha256) {
         // A corrupt result is discarded so the next attempt starts clean.
-        let _ = tokio::fs::remove_file(&part).await;
+        remove_partial(&part).await;
         return Err(format!("sha256 mismatch (want {}, got {got})", entry.sha256));
     }
     tokio::fs::rename(&part, &dest)
         .await
-        .map_err(|e| format!("rename: {e}"))
+        .map_err(|e| format!("rename: {e}"))?;
+    let _ = tokio::fs::remove_file(part_record_path(&part)).await;
+    Ok(())
+}
+
+/// Block-level transfer of a changed file over its stale local copy at `dest`:
+/// fetch the agent's chunk list, cut the copy the same way
+/// ([`xr_proto::chunks`]), then write the new file into `part` piece by piece,
+/// copying known chunks from the copy and range-requesting the gaps. Verified
+/// against the manifest SHA-256 before it replaces `dest`. An error means
+/// "fall back to the whole download"; a hash mismatch also drops the partial,
+/// since its prefix came from a list that lied.
+async fn download_blocks(
+    client: &reqwest::Client,
+    agent_url: &str,
+    token: &ShareToken,
+    entry: &ShareManifestEntry,
+    dest: &Path,
+    part: &Path,
+) -> Result<(), String> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    use xr_proto::chunks::{chunk_reader, plan_pieces, remote_bytes, Piece};
+    use xr_proto::share::FileChunks;
+
+    let base = agent_url.trim_end_matches('/');
+    let rel = encode_path(&entry.path);
+    let resp = client
+        .get(format!("{base}/chunks/{rel}"))
+        .bearer_auth(token_blob(token))
+        .send()
+        .await
+        .map_err(|e| format!("network: {e}"))?;
+    if !resp.status().is_success() {
+        return Err(format!("http_{}", resp.status().as_u16()));
+    }
+    let remote: FileChunks = resp.json().await.map_err(|e| format!("parse: {e}"))?;
+    if remote.size != entry.size || !remote.sha256.eq_ignore_ascii_case(&entry.sha256) {
+        // The agent cut another version of the file than the manifest lists.
+        return Err("chunks_stale".into());
+    }
+    let src = dest.to_path_buf();
+    let local = tokio::task::spawn_blocking(move || {
+        std::fs::File::open(&src).and_then(|f| chunk_reader(std::io::BufReader::new(f)))
+    })
+    .await
+    .map_err(|e| format!("chunk task: {e}"))?
+    .map_err(|e| format!("chunk local: {e}"))?;
+    let pieces = plan_pieces(&remote, &local.chunks);
+    if remote_bytes(&pieces) >= entry.size {
+        return Err("chunks_no_gain".into());
+    }
+
+    let mut old = tokio::fs::File::open(dest).await.map_err(|e| format!("open: {e}"))?;
+    let mut out = tokio::fs::File::create(part).await.map_err(|e| format!("create: {e}"))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 256 * 1024];
+    for piece in pieces {
+        if transfer_cancelled() {
+            let _ = out.flush().await;
+            return Err("cancelled".into());
+        }
+        match piece {
+            Piece::Local { from, len } => {
+                old.seek(std::io::SeekFrom::Start(from)).await.map_err(|e| format!("seek: {e}"))?;
+                let mut left = len;
+                while left > 0 {
+                    let n = (left as usize).min(buf.len());
+                    old.read_exact(&mut buf[..n]).await.map_err(|e| format!("read local: {e}"))?;
+                    hasher.update(&buf[..n]);
+                    out.write_all(&buf[..n]).await.map_err(|e| format!("write: {e}"))?;
+                    transfer_add_bytes(n as u64);
+                    left -= n as u64;
+                }
+            }
+            Piece::Remote { offset, len } => {
+                let mut resp = client
+                    .get(format!("{base}/file/{rel}"))
+                    .bearer_auth(token_blob(token))
+                    .header("Range", format!("bytes={offset}-{}", offset + len - 1))
+                    .send()
+                    .await
+                    .map_err(|e| format!("network: {e}"))?;
+                if resp.status().as_u16() != 206 {
+                    return Err(format!("http_{}", resp.status().as_u16()));
+                }
+                let mut got = 0u64;
+                while let Some(chunk) = resp.chunk().await.map_err(|e| format!("read: {e}"))? {
+                    got += chunk.len() as u64;
+                    if got > len {
+                        return Err("range overrun".into());
+                    }
+                    hasher.update(&chunk);
+                    out.write_all(&chunk).await.map_err(|e| format!("write: {e}"))?;
+                    transfer_add_bytes(chunk.len() as u64);
+                }
+                if got != len {
+                    return Err("range short".into());
+                }
+            }
+        }
+    }
+    out.flush().await.map_err(|e| format!("flush: {e}"))?;
+    drop(out);
+
+    let got = hex_lower(&hasher.finalize());
+    if !got.eq_ignore_ascii_case(&entry.sha256) {
+        let _ = tokio::fs::remove_file(part).await;
+        return Err(format!("sha256 mismatch (want {}, got {got})", entry.sha256));
+    }
+    tokio::fs::rename(part, dest).await.map_err(|e| format!("rename: {e}"))
 }
 
 /// Stream a file's existing bytes through a hasher (for resuming a partial).
@@ -1856,18 +2399,21 @@ async fn apply_plan_with(
 /// Sweep `.xrsync-part` leftovers whose target the manifest (within the
 /// selection) no longer covers. The scan does not list partials, so an orphan
 /// would otherwise sit on disk forever; one whose target is still covered
-/// stays, it is the Range-resume progress of the next fetch. A share file
-/// that itself ends in the suffix is covered by its own path and untouched.
+/// stays, it is the Range-resume progress of the next fetch. A partial whose
+/// record names another version than the manifest lists is stale (the file
+/// changed on the agent, the bytes can never be resumed) and goes too, as does
+/// a record left without its partial. A share file that itself ends in the
+/// suffix is covered by its own path and untouched.
 fn remove_orphan_partials(
     root: &Path,
     manifest: &ShareManifest,
     selection: Option<&HashSet<String>>,
 ) {
-    let covered: HashSet<&str> = manifest
+    let covered: HashMap<&str, &ShareManifestEntry> = manifest
         .entries
         .iter()
         .filter(|e| path_selected(&e.path, selection))
-        .map(|e| e.path.as_str())
+        .map(|e| (e.path.as_str(), e))
         .collect();
     let mut orphans = Vec::new();
     collect_orphan_partials(root, root, &covered, &mut orphans);
@@ -1881,7 +2427,7 @@ fn remove_orphan_partials(
 fn collect_orphan_partials(
     root: &Path,
     dir: &Path,
-    covered: &HashSet<&str>,
+    covered: &HashMap<&str, &ShareManifestEntry>,
     out: &mut Vec<PathBuf>,
 ) {
     let Ok(rd) = std::fs::read_dir(dir) else { return };
@@ -1895,9 +2441,22 @@ fn collect_orphan_partials(
             collect_orphan_partials(root, &path, covered, out);
         } else if ft.is_file() {
             let rel = rel_slash(root, &path);
-            let Some(target) = rel.strip_suffix(PART_SUFFIX) else { continue };
-            if !covered.contains(rel.as_str()) && !covered.contains(target) {
-                out.push(path);
+            if covered.contains_key(rel.as_str()) {
+                continue;
+            }
+            if let Some(target) = rel.strip_suffix(PART_SUFFIX) {
+                let stale = match covered.get(target) {
+                    None => true,
+                    Some(e) => load_part_record(&path).is_some_and(|r| !r.matches(e)),
+                };
+                if stale {
+                    out.push(part_record_path(&path));
+                    out.push(path);
+                }
+            } else if let Some(part) = rel.strip_suffix(".json") {
+                if part.ends_with(PART_SUFFIX) && !root.join(part).is_file() {
+                    out.push(path);
+                }
             }
         }
     }
@@ -2449,6 +3008,7 @@ mod tests {
             mtime: 1,
             sha256: sha.into(),
             meta: None,
+            preview: None,
         }
     }
     fn local(path: &str, sha: &str) -> LocalFile {
@@ -2458,7 +3018,7 @@ mod tests {
         LocalFile { path: path.into(), sha256: sha.into(), size }
     }
     fn manifest(entries: Vec<ShareManifestEntry>) -> ShareManifest {
-        ShareManifest { entries }
+        ShareManifest { entries, version: 0 }
     }
 
     #[test]
@@ -2777,6 +3337,28 @@ mod tests {
         assert!(dir.path().join("named.xrsync-part").exists());
     }
 
+    #[test]
+    fn partials_of_another_version_and_lone_records_are_swept() {
+        let dir = tempfile::tempdir().unwrap();
+        let m = manifest(vec![entry("new.bin", "n2"), entry("same.bin", "s")]);
+        let part = |name: &str| dir.path().join(format!("{name}.xrsync-part"));
+        let record = |name: &str, sha: &str| {
+            std::fs::write(part(name), b"half").unwrap();
+            let r = PartRecord::new(&entry(name, sha), 4);
+            std::fs::write(part_record_path(&part(name)), serde_json::to_vec(&r).unwrap()).unwrap();
+        };
+        // The file changed on the agent since this partial began.
+        record("new.bin", "n1");
+        record("same.bin", "s");
+        // A record whose partial is already gone.
+        std::fs::write(dir.path().join("lost.bin.xrsync-part.json"), b"{}").unwrap();
+
+        remove_orphan_partials(dir.path(), &m, None);
+        assert!(!part("new.bin").exists() && !part_record_path(&part("new.bin")).exists());
+        assert!(part("same.bin").exists() && part_record_path(&part("same.bin")).exists());
+        assert!(!dir.path().join("lost.bin.xrsync-part.json").exists());
+    }
+
     // ── HashIndex (XR-098) ────────────────────────────────────────────
 
     /// Rewrite `path` with same-length `content`, restoring the original mtime.
@@ -3120,6 +3702,112 @@ mod tests {
         assert_eq!(m.entries.len(), 1);
     }
 
+    #[tokio::test]
+    async fn wait_manifest_carries_version_and_maps_304() {
+        // Изменение приезжает подписанным манифестом с версией для следующего
+        // ожидания; истёкшее удержание это 304 и UpToDate, а не ошибка.
+        let (key, pub_b64) = agent_key();
+        let headers = format!(
+            "{}{}: 1700000000042\r\n",
+            signed_headers(&key, "s1", 1234, MANIFEST_BODY),
+            xr_proto::share::MANIFEST_VERSION_HEADER
+        );
+        let url = serve_once(http_response(MANIFEST_BODY, &headers)).await;
+        let out = wait_manifest_relay(&url, &test_token("s1"), &pub_b64, None, None, 0, Duration::from_secs(1))
+            .await
+            .unwrap();
+        let ManifestWait::Changed { manifest, version } = out else {
+            panic!("ждали свежий манифест: {out:?}");
+        };
+        assert_eq!(version, 1_700_000_000_042);
+        assert_eq!(manifest.entries[0].path, "a.txt");
+
+        let url = serve_once(
+            "HTTP/1.1 304 Not Modified\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+        )
+        .await;
+        let out = wait_manifest_relay(&url, &test_token("s1"), &pub_b64, None, None, 7, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(matches!(out, ManifestWait::UpToDate), "{out:?}");
+
+        // Старый агент версию не шлёт: ждать нечем, вызывающий уходит на опрос.
+        let url = serve_once(http_response(
+            MANIFEST_BODY,
+            &signed_headers(&key, "s1", 1234, MANIFEST_BODY),
+        ))
+        .await;
+        let err = wait_manifest_relay(&url, &test_token("s1"), &pub_b64, None, None, 0, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err, "manifest_unversioned");
+    }
+
+    /// One-shot server that answers `response` and hands back the request text.
+    async fn serve_once_capture(response: String) -> (String, tokio::sync::oneshot::Receiver<String>) {
+        use tokio::io::AsyncWriteExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let req = read_http_request(&mut sock).await;
+            let _ = sock.write_all(response.as_bytes()).await;
+            let _ = sock.shutdown().await;
+            let _ = tx.send(req);
+        });
+        (format!("http://{addr}"), rx)
+    }
+
+    #[tokio::test]
+    async fn manifest_copy_asks_for_a_delta_and_applies_it() {
+        use xr_proto::share::{
+            sign_manifest_delta, MANIFEST_DELTA_HEADER, MANIFEST_SIGNED_AT_HEADER, MANIFEST_SIG_HEADER,
+        };
+        let (key, pub_b64) = agent_key();
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("s1.json");
+        let row = |path: &str| ShareManifestEntry {
+            path: path.into(),
+            size: 1,
+            mtime: 1,
+            sha256: "aa".into(),
+            meta: None,
+            preview: None,
+        };
+        let copy = ShareManifest { entries: vec![row("a.txt"), row("b.txt")], version: 5 };
+        save_manifest_copy(&manifest_copy_path(&index), &copy, None);
+
+        let delta_response = |body: &str| {
+            let sig = sign_manifest_delta(&key, "s1", 1234, body.as_bytes());
+            http_response(
+                body,
+                &format!("{MANIFEST_SIG_HEADER}: {sig}\r\n{MANIFEST_SIGNED_AT_HEADER}: 1234\r\n{MANIFEST_DELTA_HEADER}: 5\r\n"),
+            )
+        };
+        let body = r#"{"base":5,"version":6,"changed":[{"path":"c.txt","size":1,"mtime":1,"sha256":"aa"}],"removed":["a.txt"]}"#;
+        let (url, req) = serve_once_capture(delta_response(body)).await;
+        let out = wait_manifest_relay(&url, &test_token("s1"), &pub_b64, None, Some(&index), 0, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(req.await.unwrap().contains("base=5"), "копия листинга даёт базу дельты");
+        let ManifestWait::Changed { manifest, version } = out else {
+            panic!("ждали листинг: {out:?}");
+        };
+        assert_eq!(version, 6);
+        assert_eq!(manifest.entries, vec![row("b.txt"), row("c.txt")]);
+        let stored = load_manifest_copy(&manifest_copy_path(&index)).unwrap();
+        assert_eq!(stored, manifest, "копия переехала на новую версию");
+
+        // Дельта от чужой базы на копию не ложится.
+        let body = r#"{"base":5,"version":7,"removed":["b.txt"]}"#;
+        let url = serve_once(delta_response(body)).await;
+        let err = wait_manifest_relay(&url, &test_token("s1"), &pub_b64, None, Some(&index), 0, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err, "manifest_delta_base");
+    }
+
     // -- candidate walk: LAN then public (XR-050) -----------------------
 
     /// A manifest server that answers *every* connection with the same response,
@@ -3301,6 +3989,8 @@ mod tests {
                 exp: 9_999_999_999,
                 signature: "s".into(),
             },
+            backups: Vec::new(),
+            punch: false,
         }
     }
 
@@ -3530,12 +4220,161 @@ mod tests {
         format!("http://{addr}")
     }
 
+    /// An agent stand-in for block transfer: `/chunks/...` answers `chunks`,
+    /// `/file/...` serves `file` whole or by `Range` (206, open-ended too). Counts the file
+    /// bytes it sent, which is what block transfer is meant to cut.
+    async fn serve_blocks(
+        file: std::sync::Arc<Vec<u8>>,
+        chunks: String,
+    ) -> (String, std::sync::Arc<AtomicU64>) {
+        use tokio::io::AsyncWriteExt;
+        let sent = std::sync::Arc::new(AtomicU64::new(0));
+        let counter = sent.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let req = read_http_request(&mut sock).await;
+                if req.starts_with("GET /chunks/") {
+                    let _ = sock.write_all(http_response(&chunks, "").as_bytes()).await;
+                } else {
+                    let range = req.lines().find_map(|l| {
+                        let (k, v) = l.split_once(':')?;
+                        if !k.eq_ignore_ascii_case("range") {
+                            return None;
+                        }
+                        let (a, b) = v.trim().strip_prefix("bytes=")?.split_once('-')?;
+                        let b = if b.is_empty() { file.len() - 1 } else { b.parse::<usize>().ok()? };
+                        Some((a.parse::<usize>().ok()?, b))
+                    });
+                    let (status, body) = match range {
+                        Some((a, b)) => ("206 Partial Content", &file[a..=b]),
+                        None => ("200 OK", &file[..]),
+                    };
+                    counter.fetch_add(body.len() as u64, Ordering::Relaxed);
+                    let head = format!(
+                        "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = sock.write_all(head.as_bytes()).await;
+                    let _ = sock.write_all(body).await;
+                }
+                let _ = sock.shutdown().await;
+            }
+        });
+        (format!("http://{addr}"), sent)
+    }
+
+    fn noise(seed: u64, n: usize) -> Vec<u8> {
+        let mut x = seed | 1;
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn changed_big_file_travels_by_blocks_and_falls_back_whole() {
+        let _lock = transfer_lock_async().await;
+        let old = noise(3, 10 * 1024 * 1024);
+        let mut new = old.clone();
+        new[5_000_000..5_000_100].copy_from_slice(&noise(4, 100));
+        let new = std::sync::Arc::new(new);
+        let entry = ShareManifestEntry {
+            path: "disk.img".into(),
+            size: new.len() as u64,
+            mtime: 1,
+            sha256: hex_lower(&Sha256::digest(&new[..])),
+            meta: None,
+            preview: None,
+        };
+        let chunks = xr_proto::chunks::chunk_reader(&new[..]).unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        std::fs::write(dest.path().join("disk.img"), &old).unwrap();
+        let (url, sent) = serve_blocks(new.clone(), serde_json::to_string(&chunks).unwrap()).await;
+        download_entry(&url, &test_token("s1"), &entry, dest.path(), Duration::from_secs(20))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dest.path().join("disk.img")).unwrap(), *new);
+        let fetched = sent.load(Ordering::Relaxed);
+        assert!(fetched < old.len() as u64 / 4, "по сети ушли только чанки правки: {fetched}");
+
+        // Список чанков не той версии файла: честная докачка целиком.
+        std::fs::write(dest.path().join("disk.img"), &old).unwrap();
+        let stale = xr_proto::share::FileChunks { sha256: "00".into(), ..chunks };
+        let (url, sent) = serve_blocks(new.clone(), serde_json::to_string(&stale).unwrap()).await;
+        download_entry(&url, &test_token("s1"), &entry, dest.path(), Duration::from_secs(20))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dest.path().join("disk.img")).unwrap(), *new);
+        assert_eq!(sent.load(Ordering::Relaxed), new.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn a_recorded_partial_resumes_after_a_restart() {
+        let _lock = transfer_lock_async().await;
+        let file = std::sync::Arc::new(noise(5, 3 * 1024 * 1024));
+        let entry = ShareManifestEntry {
+            path: "video.mp4".into(),
+            size: file.len() as u64,
+            mtime: 1,
+            sha256: hex_lower(&Sha256::digest(&file[..])),
+            meta: None,
+            preview: None,
+        };
+        let dest = tempfile::tempdir().unwrap();
+        let part = dest.path().join("video.mp4.xrsync-part");
+        let keep = 1024 * 1024;
+        // A killed app: the recorded prefix plus a tail that never reached
+        // the disk for real.
+        let mut left = file[..keep].to_vec();
+        left.extend_from_slice(&[0u8; 100]);
+        std::fs::write(&part, &left).unwrap();
+        std::fs::write(
+            part_record_path(&part),
+            serde_json::to_vec(&PartRecord::new(&entry, keep as u64)).unwrap(),
+        )
+        .unwrap();
+
+        let guard = TransferGuard::acquire("s1", 1, entry.size).unwrap();
+        let (url, sent) = serve_blocks(file.clone(), String::new()).await;
+        download_entry(&url, &test_token("s1"), &entry, dest.path(), Duration::from_secs(20))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dest.path().join("video.mp4")).unwrap(), *file);
+        assert_eq!(sent.load(Ordering::Relaxed), (file.len() - keep) as u64, "only the rest travelled");
+        let snap = transfer_snapshot();
+        assert_eq!((snap.bytes_done, snap.bytes_resumed), (entry.size, keep as u64));
+        assert!(!part.exists() && !part_record_path(&part).exists());
+        drop(guard);
+
+        // A partial of another version, or one without a record, starts over.
+        for record in [Some(PartRecord { sha256: "00".into(), ..PartRecord::new(&entry, keep as u64) }), None] {
+            std::fs::remove_file(dest.path().join("video.mp4")).unwrap();
+            std::fs::write(&part, &file[..keep]).unwrap();
+            if let Some(r) = &record {
+                std::fs::write(part_record_path(&part), serde_json::to_vec(r).unwrap()).unwrap();
+            }
+            let (url, sent) = serve_blocks(file.clone(), String::new()).await;
+            download_entry(&url, &test_token("s1"), &entry, dest.path(), Duration::from_secs(20))
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read(dest.path().join("video.mp4")).unwrap(), *file);
+            assert_eq!(sent.load(Ordering::Relaxed), file.len() as u64);
+        }
+    }
+
     const HELLO_SHA: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
 
     #[tokio::test]
     async fn sync_with_index_records_download_and_skips_rehash() {
         // Синк берёт тот же единственный гвард передачи, что и тесты отмены.
-        let _lock = transfer_lock();
+        let _lock = transfer_lock_async().await;
         let dest = tempfile::tempdir().unwrap();
         let aux = tempfile::tempdir().unwrap();
         let ix_path = aux.path().join("share.json");
@@ -3574,11 +4413,16 @@ mod tests {
     // -- адресная отмена передачи (XR-217) ----------------------------
 
     /// Контроллер передачи один на процесс, поэтому тесты, которые берут гвард,
-    /// идут по очереди: иначе чужой гвард отдаёт `busy` соседнему тесту.
-    static TRANSFER_TESTS: Mutex<()> = Mutex::new(());
+    /// идут по очереди: иначе чужой гвард отдаёт `busy` соседнему тесту. Замок
+    /// из tokio: async-тесты держат его через `.await` ([`transfer_lock_async`]).
+    static TRANSFER_TESTS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn transfer_lock() -> tokio::sync::MutexGuard<'static, ()> {
+        TRANSFER_TESTS.blocking_lock()
+    }
 
-    fn transfer_lock() -> std::sync::MutexGuard<'static, ()> {
-        TRANSFER_TESTS.lock().unwrap_or_else(|e| e.into_inner())
+    async fn transfer_lock_async() -> tokio::sync::MutexGuard<'static, ()> {
+        TRANSFER_TESTS.lock().await
     }
 
     /// Регрессия XR-217: отмена шары A, долетевшая уже после старта передачи
@@ -144,6 +144,7 @@ fn config(server_port: u16) -> VpnConfig {
         system_resolver: None,
         // Столько же тоннелей, сколько поднимает приложение по умолчанию.
         mux_pool_size: 4,
+        server_policy: "priority".into(),
     }
 }
 
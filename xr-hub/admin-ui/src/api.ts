@@ -104,6 +104,35 @@ export const api = {
   listExposes: () => request<ExposeRecord[]>('/admin/exposes'),
   deleteExpose: (name: string) =>
     request<void>(`/admin/exposes/${name}`, { method: 'DELETE' }),
+  // Сессии браузера на публикации: их докладывает фронт xr-web, «выйти везде»
+  // он забирает следующим докладом.
+  exposeSessions: (name: string) =>
+    request<PublicationSessions>(`/admin/exposes/${name}/sessions`),
+  logoutExpose: (name: string) =>
+    request<{ logout_before: number }>(`/admin/exposes/${name}/logout`, { method: 'POST' }),
+  // Политика браузерного входа и гостевые ссылки публикации.
+  setExposeAccess: (name: string, req: SetAccessRequest) =>
+    request<ExposeRecord>(`/admin/exposes/${name}/access`, {
+      method: 'PUT',
+      body: JSON.stringify(req),
+    }),
+  // Чужие машины, которым хаб выдаёт туннель к TCP-публикации.
+  setExposeAgents: (name: string, agents: string[]) =>
+    request<ExposeRecord>(`/admin/exposes/${name}/agents`, {
+      method: 'PUT',
+      body: JSON.stringify({ agents }),
+    }),
+  listGuests: (name: string) => request<GuestLink[]>(`/admin/exposes/${name}/guests`),
+  createGuest: (name: string, label: string, ttlSeconds: number) =>
+    request<CreatedGuest>(`/admin/exposes/${name}/guests`, {
+      method: 'POST',
+      body: JSON.stringify({ label, ttl_seconds: ttlSeconds }),
+    }),
+  revokeGuest: (name: string, id: string) =>
+    request<void>(`/admin/exposes/${name}/guests/${id}`, { method: 'DELETE' }),
+
+  // Relay: хаб опрашивает stats-ручку каждого relay и сводит ответы.
+  relaysOverview: () => request<RelaysOverview>('/admin/relays'),
 }
 
 // Types
@@ -206,10 +235,88 @@ export interface ShareRecord {
   comment: string
 }
 
+export type WebAccessKind = 'owner' | 'public' | 'password' | 'invites' | 'users'
+
 export interface ExposeRecord {
   name: string
   agent_pubkey: string
   created: string
+  /** Нет поля значит HTTP-публикация. */
+  kind?: 'tcp'
+  access: WebAccessKind
+  users?: string[]
+  invites?: string[]
+  connect_agents?: string[]
+}
+
+export interface SetAccessRequest {
+  kind: WebAccessKind
+  password?: string
+  users?: string[]
+  invites?: string[]
+}
+
+export interface GuestLink {
+  id: string
+  label: string
+  created: number
+  expires: number
+  revoked: boolean
+}
+
+export interface CreatedGuest {
+  id: string
+  token: string
+  url?: string
+  expires: number
+}
+
+export interface WebSessionInfo {
+  id: string
+  publication: string
+  username: string
+  created: number
+  last_seen: number
+  last_ip: string
+  user_agent: string
+  grant?: string
+}
+
+export interface PublicationSessions {
+  reported_at: number | null
+  sessions: WebSessionInfo[]
+}
+
+export interface RelayAgent {
+  fingerprint: string
+  registered_at: number
+  generation: number
+  peer_ip: string
+  live_splices: number
+  names: string[]
+}
+
+export interface RelayStatus {
+  relay: string
+  admin: boolean
+  error?: string
+  started_at?: number
+  live_splices: number
+  agents: RelayAgent[]
+}
+
+export interface ShareTransit {
+  share_id: string
+  name?: string
+  bytes: number
+  live_splices: number
+}
+
+export interface RelaysOverview {
+  relays: RelayStatus[]
+  shares: ShareTransit[]
+  live_splices: number
+  agents: number
 }
 
 export interface CreateShareRequest {
@@ -43,6 +43,11 @@ const router = createRouter({
       name: 'ExposesList',
       component: () => import('./pages/ExposesList.vue'),
     },
+    {
+      path: '/relays',
+      name: 'RelaysList',
+      component: () => import('./pages/RelaysList.vue'),
+    },
   ],
 })
 
@@ -20,5 +20,9 @@ export const useExposesStore = defineStore('exposes', () => {
     await fetchList()
   }
 
-  return { exposes, loading, fetchList, remove }
+  async function logoutEverywhere(name: string) {
+    await api.logoutExpose(name)
+  }
+
+  return { exposes, loading, fetchList, remove, logoutEverywhere }
 })
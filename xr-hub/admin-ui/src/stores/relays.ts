@@ -0,0 +1,19 @@
+import { defineStore } from 'pinia'
+import { ref } from 'vue'
+import { api, type RelaysOverview } from '../api'
+
+export const useRelaysStore = defineStore('relays', () => {
+  const overview = ref<RelaysOverview | null>(null)
+  const loading = ref(false)
+
+  async function fetchOverview() {
+    loading.value = true
+    try {
+      overview.value = await api.relaysOverview()
+    } finally {
+      loading.value = false
+    }
+  }
+
+  return { overview, loading, fetchOverview }
+})
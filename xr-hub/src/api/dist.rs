@@ -105,6 +105,7 @@ mod tests {
             signing: None,
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
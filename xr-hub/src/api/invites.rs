@@ -367,7 +367,7 @@ fn claim_id_header(headers: &axum::http::HeaderMap) -> Option<String> {
 
 /// Ключ сверяем в постоянное время: подбирать его на потреблённом инвайте
 /// пришлось бы вслепую, и по времени ответа подсказки быть не должно.
-fn constant_time_eq(a: &str, b: &str) -> bool {
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
     use subtle::ConstantTimeEq;
     a.as_bytes().ct_eq(b.as_bytes()).into()
 }
@@ -522,12 +522,20 @@ pub(crate) async fn build_invite(
             .first()
             .map(|s| (s.address.clone(), s.port))
             .unwrap_or_else(|| (defaults.server_address.clone(), defaults.server_port));
+        // После переключения ротации новые инвайты несут уже новый ключ:
+        // старый сервер к этому моменту отвергает.
+        let (obfuscation_key, modifier, salt) = match &defaults.next_key {
+            Some(next) if next.switch_at <= chrono::Utc::now() => {
+                (next.obfuscation_key.clone(), next.modifier.clone(), next.salt)
+            }
+            _ => (defaults.obfuscation_key.clone(), defaults.modifier.clone(), defaults.salt),
+        };
         InvitePayload {
             server_address,
             server_port,
-            obfuscation_key: defaults.obfuscation_key.clone(),
-            modifier: defaults.modifier.clone(),
-            salt: defaults.salt,
+            obfuscation_key,
+            modifier,
+            salt,
             preset: preset_name,
             hub_url: defaults.hub_url.clone(),
             servers,
@@ -646,6 +654,7 @@ mod tests {
             signing: None,
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
@@ -4,9 +4,11 @@ pub mod dist;
 pub mod invites;
 pub mod presets;
 pub mod register;
+pub mod relays;
 pub mod share_v2;
 pub mod shares;
 pub mod web;
+pub mod web_access;
 
 use std::sync::Arc;
 
@@ -68,6 +70,7 @@ pub fn router(state: Arc<AppState>) -> Router {
         .route("/share/register", post(register::register))
         // v2 self-service multishare (agent-authenticated by reg-token/credential).
         .route("/relay", get(share_v2::get_relay))
+        .route("/relays", get(share_v2::get_relays))
         .route("/share/exchange", post(share_v2::exchange))
         .route("/share/add", post(share_v2::add))
         .route("/share/mint", post(share_v2::mint))
@@ -81,11 +84,16 @@ pub fn router(state: Arc<AppState>) -> Router {
         .route("/expose", get(web::list))
         .route("/expose/{name}", delete(web::remove))
         .route("/expose/{name}/mandate", post(web::mandate))
+        .route("/expose/{name}/connect", post(web::connect))
         // LLD-38 п. 3.5: служебные ручки браузерного фронта под общим секретом
         // [web]. Прав админки у фронта нет, ключа подписи он не видит.
         .route("/web/route", post(web::route))
+        .route("/web/share-route", post(web::share_route))
         .route("/web/verify-password", post(web::verify_password))
-        .route("/web/status", get(web::status));
+        .route("/web/verify-access", post(web_access::verify_access))
+        .route("/web/guest", post(web_access::redeem_guest))
+        .route("/web/status", get(web::status))
+        .route("/web/sessions", post(web::report_sessions));
 
     // Auth (no session required).
     let auth_routes = Router::new()
@@ -106,6 +114,16 @@ pub fn router(state: Arc<AppState>) -> Router {
         .route("/shares/{id}/token", post(shares::mint_token))
         .route("/exposes", get(web::admin_list))
         .route("/exposes/{name}", delete(web::admin_remove))
+        .route("/exposes/{name}/sessions", get(web::admin_sessions))
+        .route("/exposes/{name}/logout", post(web::admin_logout))
+        .route("/exposes/{name}/access", put(web_access::admin_set_access))
+        .route("/exposes/{name}/agents", put(web_access::admin_set_agents))
+        .route(
+            "/exposes/{name}/guests",
+            get(web_access::admin_list_guests).post(web_access::admin_create_guest),
+        )
+        .route("/exposes/{name}/guests/{id}", delete(web_access::admin_revoke_guest))
+        .route("/relays", get(relays::admin_relays))
         .route("/shares/reg-token", post(register::create_reg_token))
         .route("/shares/setup-token", post(register::create_setup_token))
         .layer(middleware::from_fn_with_state(
@@ -204,6 +222,7 @@ mod tests {
             signing: None,
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
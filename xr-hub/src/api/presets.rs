@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
@@ -8,8 +9,9 @@ use axum::response::IntoResponse;
 use axum::Json;
 use serde::Deserialize;
 use xr_proto::config::RoutingConfig;
-use xr_proto::preset::{Preset, PresetSummary};
+use xr_proto::preset::{NextKey, Preset, PresetSummary};
 
+use crate::signing::SigningContext;
 use crate::state::AppState;
 use crate::storage;
 
@@ -156,6 +158,7 @@ pub async fn create_preset(
         updated_at: now,
         description: req.description,
         rules: req.rules,
+        next_key: state.config.invites.defaults.next_key.as_ref().map(|k| k.to_wire()),
         signature: None,
     };
 
@@ -191,6 +194,7 @@ pub async fn update_preset(
         updated_at: now,
         description: req.description,
         rules: req.rules,
+        next_key: state.config.invites.defaults.next_key.as_ref().map(|k| k.to_wire()),
         signature: None,
     };
 
@@ -224,6 +228,32 @@ pub async fn delete_preset(
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Довести следующий ключ из конфига до сохранённых пресетов. Ключ задаётся
+/// в конфиге хаба, а пресеты лежат на диске со старой подписью, поэтому при
+/// старте каждый расходящийся пресет получает новую версию и подпись: её
+/// клиенты и заберут своим long-poll, перевыпускать инвайты не нужно.
+/// Возвращает число переизданных пресетов.
+pub(crate) fn sync_next_key(
+    presets: &mut HashMap<String, Preset>,
+    next_key: Option<&NextKey>,
+    signing: Option<&SigningContext>,
+    data_dir: &Path,
+) -> anyhow::Result<usize> {
+    let mut republished = 0;
+    for preset in presets.values_mut() {
+        if preset.next_key.as_ref() == next_key {
+            continue;
+        }
+        preset.next_key = next_key.cloned();
+        preset.version += 1;
+        preset.updated_at = chrono::Utc::now().to_rfc3339();
+        preset.signature = signing.map(|ctx| ctx.sign_preset(preset));
+        storage::save_preset(data_dir, preset)?;
+        republished += 1;
+    }
+    Ok(republished)
+}
+
 /// Разбудить всех, кто висит на ручке ожидания. Зовётся после записи на диск
 /// и вставки в мапу, чтобы проснувшийся увидел уже новую версию.
 fn bump_generation(state: &AppState) {
@@ -269,6 +299,7 @@ mod tests {
                 default_action: "direct".into(),
                 rules: Vec::new(),
             },
+            next_key: None,
             signature: None,
         }
     }
@@ -288,6 +319,7 @@ mod tests {
             signing: None,
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
@@ -445,6 +477,35 @@ mod tests {
         assert_eq!(waiter.await.unwrap().status(), StatusCode::NOT_FOUND);
     }
 
+    // Ключ из конфига доезжает до пресетов, лежащих на диске: расходящийся
+    // пресет переиздаётся новой версией (её и заберёт long-poll), а уже
+    // несущий тот же ключ не трогается, и рестарт хаба версий не множит.
+    #[test]
+    fn sync_next_key_republishes_only_stale_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let next = NextKey {
+            obfuscation_key: "a2V5".into(),
+            modifier: "positional_xor_rotate".into(),
+            salt: 7,
+            switch_at: 1_800_000_000,
+        };
+        let mut presets = HashMap::new();
+        presets.insert("russia".to_string(), preset(1));
+
+        assert_eq!(sync_next_key(&mut presets, Some(&next), None, dir.path()).unwrap(), 1);
+        assert_eq!(presets["russia"].version, 2);
+        assert_eq!(presets["russia"].next_key.as_ref(), Some(&next));
+        let stored = crate::storage::load_all_presets(dir.path()).unwrap();
+        assert_eq!(stored["russia"].version, 2);
+
+        assert_eq!(sync_next_key(&mut presets, Some(&next), None, dir.path()).unwrap(), 0);
+        assert_eq!(presets["russia"].version, 2);
+
+        // Снятие ключа из конфига это тоже правка пресета.
+        assert_eq!(sync_next_key(&mut presets, None, None, dir.path()).unwrap(), 1);
+        assert!(presets["russia"].next_key.is_none());
+    }
+
     // Клиент вправе попросить больше потолка, но висеть дольше минуты нельзя:
     // такой ответ рвут промежуточные прокси, и клиент видит обрыв вместо 304.
     #[test]
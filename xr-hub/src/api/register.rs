@@ -327,6 +327,7 @@ mod tests {
             }),
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
@@ -388,6 +389,7 @@ mod tests {
             }),
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
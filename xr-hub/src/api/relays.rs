@@ -0,0 +1,351 @@
+//! Relay overview for the admin (`GET /api/v1/admin/relays`).
+//!
+//! Each relay reports its own registry, live splices and per-share totals on a
+//! loopback stats endpoint (`[relay.admin]`, JSON [`RelayStats`]). The hub polls
+//! every configured relay that has an `admin` address, puts names on what the
+//! relay only knows by id (share ids, `web:<publication>`, agent fingerprints)
+//! and sums the shares across relays. A relay that doesn't answer is listed
+//! with its error instead of failing the whole view.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::Json;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use xr_proto::share::{agent_fingerprint, RelayAgentStats, RelayShareStats, RelayStats};
+
+use crate::config::HubRelayConfig;
+use crate::state::AppState;
+
+/// How long one relay gets to answer before the view shows it as unreachable.
+const STATS_TIMEOUT: Duration = Duration::from_secs(3);
+/// Ceiling on a stats body: generous for thousands of agents, small enough
+/// that a misdirected `admin` address can't feed the hub a stream.
+const MAX_STATS_BODY: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct RelaysOverview {
+    pub relays: Vec<RelayStatus>,
+    /// Every share seen on any relay, bytes and live splices summed.
+    pub shares: Vec<ShareTransit>,
+    pub live_splices: u64,
+    pub agents: usize,
+}
+
+/// One configured relay and what it reported.
+#[derive(Debug, Serialize)]
+pub struct RelayStatus {
+    /// `addr:port` of the transit port, as agents and consumers dial it.
+    pub relay: String,
+    /// Whether the relay has a stats endpoint configured on the hub side.
+    pub admin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    pub live_splices: u64,
+    pub agents: Vec<AgentOnRelay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgentOnRelay {
+    #[serde(flatten)]
+    pub stats: RelayAgentStats,
+    /// Names of the shares and publications of this agent the hub knows.
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareTransit {
+    pub share_id: String,
+    /// Share name or `web:<publication>`; `None` for ids the hub doesn't know
+    /// (a share removed while its transit is still counted).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub bytes: u64,
+    pub live_splices: u64,
+}
+
+/// `GET /api/v1/admin/relays` - every relay with its agents, plus per-share
+/// transit summed across relays.
+pub async fn admin_relays(State(state): State<Arc<AppState>>) -> Json<RelaysOverview> {
+    let polls: Vec<_> = state
+        .config
+        .relay_list()
+        .into_iter()
+        .map(|r| {
+            let r = r.clone();
+            tokio::spawn(async move {
+                let stats = match &r.admin {
+                    Some(admin) => Some(fetch_stats(admin, r.admin_token.as_deref()).await),
+                    None => None,
+                };
+                (r, stats)
+            })
+        })
+        .collect();
+    let mut reports = Vec::with_capacity(polls.len());
+    for poll in polls {
+        if let Ok(report) = poll.await {
+            reports.push(report);
+        }
+    }
+    Json(overview(&state, reports).await)
+}
+
+/// Put names on the raw reports and sum the shares.
+async fn overview(
+    state: &AppState,
+    reports: Vec<(HubRelayConfig, Option<Result<RelayStats, String>>)>,
+) -> RelaysOverview {
+    let (share_names, agent_names) = names(state).await;
+    let mut shares: HashMap<String, ShareTransit> = HashMap::new();
+    let mut relays = Vec::with_capacity(reports.len());
+    for (relay, stats) in reports {
+        let mut status = RelayStatus {
+            relay: format!("{}:{}", relay.addr, relay.port),
+            admin: stats.is_some(),
+            error: None,
+            started_at: None,
+            live_splices: 0,
+            agents: Vec::new(),
+        };
+        match stats {
+            Some(Ok(stats)) => {
+                status.started_at = Some(stats.started_at);
+                status.live_splices = stats.live_splices;
+                status.agents = stats
+                    .agents
+                    .into_iter()
+                    .map(|a| AgentOnRelay {
+                        names: agent_names.get(&a.fingerprint).cloned().unwrap_or_default(),
+                        stats: a,
+                    })
+                    .collect();
+                for RelayShareStats { share_id, bytes, live_splices } in stats.shares {
+                    let entry = shares.entry(share_id.clone()).or_insert_with(|| ShareTransit {
+                        name: share_names.get(&share_id).cloned(),
+                        share_id,
+                        bytes: 0,
+                        live_splices: 0,
+                    });
+                    entry.bytes += bytes;
+                    entry.live_splices += live_splices;
+                }
+            }
+            Some(Err(e)) => status.error = Some(e),
+            None => {}
+        }
+        relays.push(status);
+    }
+    let mut shares: Vec<ShareTransit> = shares.into_values().collect();
+    shares.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.share_id.cmp(&b.share_id)));
+    RelaysOverview {
+        live_splices: relays.iter().map(|r| r.live_splices).sum(),
+        agents: relays.iter().map(|r| r.agents.len()).sum(),
+        relays,
+        shares,
+    }
+}
+
+/// Share id -> name (shares by their record, publications as `web:<name>`), and
+/// agent fingerprint -> the names it serves.
+async fn names(state: &AppState) -> (HashMap<String, String>, HashMap<String, Vec<String>>) {
+    let mut share_names = HashMap::new();
+    let mut agent_names: HashMap<String, Vec<String>> = HashMap::new();
+    for rec in state.shares.read().await.values() {
+        share_names.insert(rec.share_id.clone(), rec.name.clone());
+        agent_names
+            .entry(agent_fingerprint(&rec.agent_pubkey))
+            .or_default()
+            .push(rec.name.clone());
+    }
+    for rec in state.exposes.read().await.values() {
+        let id = xr_proto::share::web_share_id(&rec.name);
+        share_names.insert(id.clone(), id.clone());
+        agent_names.entry(agent_fingerprint(&rec.agent_pubkey)).or_default().push(id);
+    }
+    for list in agent_names.values_mut() {
+        list.sort();
+    }
+    (share_names, agent_names)
+}
+
+/// One `GET /stats` against a relay's admin endpoint. Plain HTTP/1.1 with
+/// `Connection: close` over a raw socket: the endpoint is loopback or tunnelled
+/// and answers one small JSON body, which doesn't justify an HTTP client.
+pub async fn fetch_stats(admin: &str, token: Option<&str>) -> Result<RelayStats, String> {
+    tokio::time::timeout(STATS_TIMEOUT, fetch_stats_inner(admin, token))
+        .await
+        .map_err(|_| "timed out".to_string())?
+}
+
+async fn fetch_stats_inner(admin: &str, token: Option<&str>) -> Result<RelayStats, String> {
+    let mut tcp = tokio::net::TcpStream::connect(admin)
+        .await
+        .map_err(|e| format!("connect {admin}: {e}"))?;
+    let auth = token
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("Authorization: Bearer {t}\r\n"))
+        .unwrap_or_default();
+    let req = format!("GET /stats HTTP/1.1\r\nHost: {admin}\r\n{auth}Connection: close\r\n\r\n");
+    tcp.write_all(req.as_bytes()).await.map_err(|e| e.to_string())?;
+    let mut raw = Vec::new();
+    tcp.take(MAX_STATS_BODY)
+        .read_to_end(&mut raw)
+        .await
+        .map_err(|e| e.to_string())?;
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed response")?;
+    let head = String::from_utf8_lossy(&raw[..split]);
+    let status = head.lines().next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(format!("relay answered {status}"));
+    }
+    serde_json::from_slice(&raw[split + 4..]).map_err(|e| format!("malformed stats: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::RwLock;
+    use xr_proto::share::{ExposeRecord, ShareRecord};
+
+    use super::*;
+    use crate::config::HubConfig;
+
+    const AGENT: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+
+    /// Answer one request with `status` and `body`, return the request head.
+    async fn fake_relay(
+        status: &'static str,
+        body: String,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let task = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = tcp.read(&mut buf).await.unwrap();
+            let resp = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            tcp.write_all(resp.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+        (addr, task)
+    }
+
+    fn stats(share_id: &str, bytes: u64, live: u64) -> RelayStats {
+        RelayStats {
+            started_at: 1_700_000_000,
+            live_splices: live,
+            agents: vec![RelayAgentStats {
+                fingerprint: agent_fingerprint(AGENT),
+                registered_at: 1_700_000_100,
+                generation: 3,
+                peer_ip: "198.51.100.7".into(),
+                live_splices: live,
+            }],
+            shares: vec![RelayShareStats { share_id: share_id.into(), bytes, live_splices: live }],
+        }
+    }
+
+    fn relay(addr: &str) -> HubRelayConfig {
+        let cfg: HubConfig = toml::from_str(&format!(
+            "[server]\n[admin]\nusers = []\n[relay]\naddr = \"{addr}\"\nport = 8444\n[relay.obfuscation]\nkey = \"QQ==\"\n"
+        ))
+        .unwrap();
+        cfg.relay.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fetch_stats_sends_token_and_checks_status() {
+        let body = serde_json::to_string(&stats("s1", 10, 1)).unwrap();
+        let (addr, req) = fake_relay("200 OK", body).await;
+        let got = fetch_stats(&addr, Some("s3cret")).await.unwrap();
+        assert_eq!(got, stats("s1", 10, 1));
+        let head = req.await.unwrap();
+        assert!(head.starts_with("GET /stats HTTP/1.1\r\n"), "{head}");
+        assert!(head.contains("Authorization: Bearer s3cret\r\n"), "{head}");
+
+        let (addr, _) = fake_relay("401 Unauthorized", "{}".into()).await;
+        let err = fetch_stats(&addr, None).await.unwrap_err();
+        assert!(err.contains("401"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn overview_names_agents_and_sums_shares_across_relays() {
+        let config: HubConfig = toml::from_str("[server]\n[admin]\nusers = []\n").unwrap();
+        let mut shares = HashMap::new();
+        shares.insert(
+            "s1".to_string(),
+            ShareRecord {
+                share_id: "s1".into(),
+                name: "photos".into(),
+                owner: String::new(),
+                addr: "203.0.113.9".into(),
+                addrs: Vec::new(),
+                port: 8443,
+                agent_pubkey: AGENT.into(),
+                created_at: String::new(),
+                comment: String::new(),
+                via_relay: true,
+                writable: false,
+            },
+        );
+        let mut exposes = HashMap::new();
+        exposes.insert(
+            "dash".to_string(),
+            ExposeRecord {
+                name: "dash".into(),
+                agent_pubkey: AGENT.into(),
+                created: String::new(),
+                logout_before: 0,
+                access: Default::default(),
+                guests: Vec::new(),
+                kind: Default::default(),
+                connect_agents: Vec::new(),
+            },
+        );
+        let state = AppState {
+            presets: RwLock::new(HashMap::new()),
+            invites: RwLock::new(HashMap::new()),
+            shares: RwLock::new(shares),
+            exposes: RwLock::new(exposes),
+            sessions: RwLock::new(HashMap::new()),
+            config,
+            signing: None,
+            preset_gen: tokio::sync::watch::Sender::new(0),
+            web_attempts: Default::default(),
+            web_sessions: Default::default(),
+        };
+
+        let view = overview(
+            &state,
+            vec![
+                (relay("relay1.example.com"), Some(Ok(stats("s1", 100, 1)))),
+                (relay("relay2.example.com"), Some(Ok(stats("s1", 50, 2)))),
+                (relay("relay3.example.com"), Some(Err("timed out".into()))),
+                (relay("relay4.example.com"), None),
+            ],
+        )
+        .await;
+        assert_eq!(view.relays.len(), 4);
+        assert_eq!((view.live_splices, view.agents), (3, 2));
+        assert_eq!(view.relays[0].agents[0].names, vec!["photos", "web:dash"]);
+        assert_eq!(view.relays[2].error.as_deref(), Some("timed out"));
+        assert!(!view.relays[3].admin);
+        assert_eq!(view.shares.len(), 1);
+        assert_eq!(view.shares[0].name.as_deref(), Some("photos"));
+        assert_eq!((view.shares[0].bytes, view.shares[0].live_splices), (150, 3));
+    }
+}
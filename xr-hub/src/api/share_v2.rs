@@ -20,14 +20,14 @@
 use std::path::Path;
 use std::sync::Arc;
 
-use axum::extract::{Path as AxPath, State};
+use axum::extract::{Path as AxPath, Query, State};
 use axum::http::{HeaderMap, StatusCode};
 use axum::Json;
 use base64::Engine;
 use serde::{Deserialize, Serialize};
 use xr_proto::share::{
     sign_agent_credential, sign_relay_token, sign_share_token, verify_agent_credential,
-    AgentCredential, RelayDescriptor, RelayGrant, ShareGrant, ShareRecord, SCOPE_IMPORT, SCOPE_READ,
+    AgentCredential, RelayDescriptor, ShareGrant, ShareRecord, SCOPE_IMPORT, SCOPE_READ,
     SCOPE_WRITE,
 };
 
@@ -113,6 +113,10 @@ pub struct ExchangeResp {
     /// reverse tunnel at install. `None` if the hub has no relay configured.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relay: Option<RelayDescriptor>,
+    /// Every relay the agent is placed on, `relay` first: a current agent
+    /// registers with all of them, an older one reads only `relay`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<RelayDescriptor>,
 }
 
 /// `POST /api/v1/share/exchange` — one-time trade of a reg-token for a long-lived
@@ -127,8 +131,23 @@ pub async fn exchange(
 
     let exp = now_unix().saturating_add(AGENT_CREDENTIAL_TTL);
     let cred = sign_agent_credential(&signing.signing_key, req.agent_pubkey.trim(), exp);
-    let relay = state.config.relay.as_ref().map(|r| r.descriptor());
-    Ok(Json(ExchangeResp { credential: encode_blob(&cred), exp, relay }))
+    let relays = placed_relays(&state, &req.agent_pubkey);
+    Ok(Json(ExchangeResp {
+        credential: encode_blob(&cred),
+        exp,
+        relay: relays.first().cloned(),
+        relays,
+    }))
+}
+
+/// Descriptors of the relays an agent is placed on, best first.
+fn placed_relays(state: &AppState, agent_pubkey: &str) -> Vec<RelayDescriptor> {
+    state
+        .config
+        .relays_for(agent_pubkey)
+        .into_iter()
+        .map(|r| r.descriptor())
+        .collect()
 }
 
 /// `GET /api/v1/relay`: the hub's relay descriptor (LLD-23), or `null`.
@@ -136,8 +155,33 @@ pub async fn exchange(
 /// already get it in every grant. The agent fetches it at startup to bring up
 /// its reverse tunnel without re-exchanging a token or hand-editing the config
 /// (XR-123), so a plain binary update is enough to switch an agent onto relay.
+///
+/// The agent isn't named here, so with several relays this is only the first
+/// one: an agent that knows [`get_relays`] asks for its own placement instead.
 pub async fn get_relay(State(state): State<Arc<AppState>>) -> Json<Option<RelayDescriptor>> {
-    Json(state.config.relay.as_ref().map(|r| r.descriptor()))
+    Json(state.config.relay_list().first().map(|r| r.descriptor()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RelaysQuery {
+    /// The agent's pubkey; without it the answer is every configured relay.
+    #[serde(default)]
+    pub agent: Option<String>,
+}
+
+/// `GET /api/v1/relays?agent=<pubkey>`: the relays the agent registers with,
+/// best first (its placement, see [`HubConfig::relays_for`]). Public for the
+/// same reason as [`get_relay`]. Empty when the hub has no relay.
+///
+/// [`HubConfig::relays_for`]: crate::config::HubConfig::relays_for
+pub async fn get_relays(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<RelaysQuery>,
+) -> Json<Vec<RelayDescriptor>> {
+    match q.agent.as_deref() {
+        Some(agent) => Json(placed_relays(&state, agent)),
+        None => Json(state.config.relay_list().iter().map(|r| r.descriptor()).collect()),
+    }
 }
 
 // ── add: credential → new share + access token ──────────────────────
@@ -183,6 +227,14 @@ pub struct AddShareResp {
     /// a relay, so the agent knows where to open its reverse tunnel.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relay: Option<RelayDescriptor>,
+    /// All relays the agent is placed on, `relay` first (see [`ExchangeResp`]).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<RelayDescriptor>,
+    /// The share's browser page on `s.<web domain>` with the token in it
+    /// (LLD-38 §2.6), when the hub has a web domain and the share is relay
+    /// reachable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_url: Option<String>,
 }
 
 /// `POST /api/v1/share/add` — register a share under the credential's pubkey and
@@ -236,26 +288,30 @@ pub async fn add(
     };
     storage::save_share(Path::new(&state.config.server.data_dir), &share)
         .map_err(|e| crate::api::persist_failed("запись шары", e))?;
-    state.shares.write().await.insert(share_id.clone(), share);
 
     let exp = now.saturating_add(ttl);
     // The hand-out token is read-only: write scope is minted only through a
     // write-binding on an invite (LLD-28 п. 2.2), never on the share link.
-    let token = sign_share_token(&signing.signing_key, &share_id, SCOPE_READ, exp);
+    let token = encode_blob(&sign_share_token(&signing.signing_key, &share_id, SCOPE_READ, exp));
+    let web_url = crate::api::web::share_page_url(&state, &share, &token);
+    state.shares.write().await.insert(share_id.clone(), share);
     // Give the agent the relay descriptor for a relay-reachable share, so it can
     // bring up the reverse tunnel it just promised the consumer will use.
-    let relay = req
-        .via_relay
-        .then(|| state.config.relay.as_ref().map(|r| r.descriptor()))
-        .flatten();
+    let relays = if req.via_relay {
+        placed_relays(&state, &cred.agent_pubkey)
+    } else {
+        Vec::new()
+    };
     Ok(Json(AddShareResp {
         share_id,
         addr,
         port: req.port,
-        token: encode_blob(&token),
+        token,
         exp,
         addrs,
-        relay,
+        relay: relays.first().cloned(),
+        relays,
+        web_url,
     }))
 }
 
@@ -288,6 +344,9 @@ pub struct MintReq {
 pub struct MintResp {
     pub token: String,
     pub exp: u64,
+    /// See [`AddShareResp::web_url`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_url: Option<String>,
 }
 
 /// `POST /api/v1/share/mint` — issue another access token for a share the
@@ -302,7 +361,7 @@ pub async fn mint(
     let cred = verify_credential_blob(signing, &req.credential, now)?;
     let ttl = clamp_token_ttl(req.ttl_seconds)?;
 
-    {
+    let rec = {
         let shares = state.shares.read().await;
         let rec = shares
             .get(&req.share_id)
@@ -310,13 +369,15 @@ pub async fn mint(
         if rec.agent_pubkey != cred.agent_pubkey {
             return Err((StatusCode::FORBIDDEN, "share belongs to another agent".into()));
         }
-    }
+        rec.clone()
+    };
 
     let exp = now.saturating_add(ttl);
     // Read-only, like `add`: the owner needs no write link to their own machine,
     // and a second write-scope channel would be extra surface (LLD-28 п. 2.2).
-    let token = sign_share_token(&signing.signing_key, &req.share_id, SCOPE_READ, exp);
-    Ok(Json(MintResp { token: encode_blob(&token), exp }))
+    let token = encode_blob(&sign_share_token(&signing.signing_key, &req.share_id, SCOPE_READ, exp));
+    let web_url = crate::api::web::share_page_url(&state, &rec, &token);
+    Ok(Json(MintResp { token, exp, web_url }))
 }
 
 /// Drop a share the presenting agent owns (`xr-share unshare`). Same ownership
@@ -495,17 +556,15 @@ pub async fn invite_shares(
             let token = sign_share_token(&signing.signing_key, sid, &scope, exp);
             // A relay-reachable share gets a relay leg next to the direct address
             // (LLD-23 §2.4): its own transit token, bound to this agent+share, and
-            // the relay descriptor. The consumer tries direct first, relay last.
-            let relay = rec.via_relay.then(|| state.config.relay.as_ref()).flatten().map(|r| {
+            // the agent's relays, primary plus backup. The consumer tries direct
+            // first, relay last.
+            let relay = if rec.via_relay {
                 let relay_token =
                     sign_relay_token(&signing.signing_key, sid, &rec.agent_pubkey, exp);
-                RelayGrant {
-                    addr: r.addr.clone(),
-                    port: r.port,
-                    obf: r.obf.clone(),
-                    relay_token,
-                }
-            });
+                state.config.relay_grant(&rec.agent_pubkey, relay_token)
+            } else {
+                None
+            };
             out.push(ShareGrant {
                 share_id: rec.share_id.clone(),
                 name: rec.name.clone(),
@@ -610,6 +669,7 @@ mod tests {
             signing: Some(SigningContext { signing_key: hub }),
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
@@ -754,6 +814,46 @@ mod tests {
         assert!(d.is_none(), "no relay configured => null");
     }
 
+    /// `[relay]` плюс два `[[relays]]`: каждый агент сидит ровно на двух,
+    /// раскладка стабильна, грант несёт основной и запасной под одним токеном.
+    #[tokio::test]
+    async fn agents_are_placed_on_two_relays() {
+        let key = base64::engine::general_purpose::STANDARD.encode(b"relay-obf-key-32-bytes-long!!!!!");
+        let mut config = config_with_relay();
+        for host in ["relay2.example.com", "relay3.example.com"] {
+            config.push_str(&format!(
+                "[[relays]]\naddr = \"{host}\"\nport = 8444\n[relays.obfuscation]\nkey = \"{key}\"\n"
+            ));
+        }
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let agent_pk = base64::engine::general_purpose::STANDARD
+            .encode(SigningKey::from_bytes(&[7u8; 32]).verifying_key().as_bytes());
+        let state = state_with(&config, hub, vec![share("relayed", &agent_pk, true)]);
+        assert_eq!(state.config.relay_list().len(), 3);
+
+        let Json(placed) = get_relays(
+            State(state.clone()),
+            Query(RelaysQuery { agent: Some(agent_pk.clone()) }),
+        )
+        .await;
+        assert_eq!(placed.len(), 2);
+        assert_ne!(placed[0], placed[1]);
+        let Json(again) = get_relays(
+            State(state.clone()),
+            Query(RelaysQuery { agent: Some(agent_pk.clone()) }),
+        )
+        .await;
+        assert_eq!(again, placed, "раскладка не должна плавать между запросами");
+        let Json(all) = get_relays(State(state.clone()), Query(RelaysQuery { agent: None })).await;
+        assert_eq!(all.len(), 3);
+
+        let Json(grants) = invite_shares(State(state), AxPath(TOKEN.to_string()))
+            .await
+            .unwrap();
+        let relay = grants[0].relay.as_ref().expect("via_relay share gets a relay leg");
+        assert_eq!(relay.descriptors(), placed);
+    }
+
     #[test]
     fn relay_obf_config_parses() {
         // The [relay] block round-trips into the descriptor the hub hands out.
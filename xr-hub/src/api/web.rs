@@ -22,8 +22,10 @@ use axum::Json;
 use base64::Engine;
 use serde::{Deserialize, Serialize};
 use xr_proto::share::{
-    sign_expose_token, sign_relay_token, valid_publication_name, verify_agent_credential,
-    web_share_id, AgentCredential, ExposeRecord, ExposeToken, WebRoute,
+    decode_share_token, sign_expose_token, sign_relay_token, tcp_share_id, valid_publication_name,
+    verify_agent_credential, verify_share_token, web_share_id, AgentCredential, ExposeAccess,
+    ExposeKind, ExposeRecord, ExposeToken, ShareRecord, TcpRoute, WebAccessKind, WebRoute,
+    WebSessionInfo, WebSessionsReport, WebSessionsVerdict, WebShareRoute, SCOPE_READ,
 };
 
 use crate::api::register::now_unix;
@@ -83,7 +85,7 @@ fn credential_from_header(
 
 /// Проверить имя публикации до всего остального: имя едет в `Host`, и всё, что
 /// не DNS-метка, до агента просто не доберётся.
-fn checked_name(raw: &str) -> Result<String, (StatusCode, String)> {
+pub(crate) fn checked_name(raw: &str) -> Result<String, (StatusCode, String)> {
     let name = raw.trim().to_string();
     if !valid_publication_name(&name) {
         return Err((
@@ -114,6 +116,9 @@ pub struct AddExposeReq {
     pub credential: String,
     /// Имя публикации, оно же поддомен.
     pub name: String,
+    /// HTTP (по умолчанию) или сырой TCP.
+    #[serde(default)]
+    pub kind: ExposeKind,
 }
 
 #[derive(Debug, Serialize)]
@@ -121,21 +126,44 @@ pub struct ExposeResp {
     pub name: String,
     pub agent_pubkey: String,
     pub created: String,
+    #[serde(skip_serializing_if = "ExposeKind::is_http")]
+    pub kind: ExposeKind,
+    /// Политика браузерного входа без её секретов: хэш пароля публикации
+    /// наружу не уходит.
+    pub access: WebAccessKind,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub invites: Vec<String>,
+    /// Чужие машины, которым выдаётся туннель к TCP-публикации.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub connect_agents: Vec<String>,
 }
 
 impl From<&ExposeRecord> for ExposeResp {
     fn from(r: &ExposeRecord) -> Self {
+        let (users, invites) = match &r.access {
+            ExposeAccess::Users { users } => (users.clone(), Vec::new()),
+            ExposeAccess::Invites { invites } => (Vec::new(), invites.clone()),
+            _ => (Vec::new(), Vec::new()),
+        };
         Self {
             name: r.name.clone(),
             agent_pubkey: r.agent_pubkey.clone(),
             created: r.created.clone(),
+            kind: r.kind,
+            access: r.access.kind(),
+            users,
+            invites,
+            connect_agents: r.connect_agents.clone(),
         }
     }
 }
 
 /// `POST /api/v1/expose/add` - завести публикацию под ключом предъявленного
-/// мандата. Повтор своей же публикации проходит молча (идемпотентно), занятое
-/// чужим агентом имя отвергается: поддомен адресует ровно одну машину.
+/// мандата. Повтор своей же публикации проходит молча (идемпотентно) и может
+/// сменить её вид, занятое чужим агентом имя отвергается: поддомен адресует
+/// ровно одну машину.
 pub async fn add(
     State(state): State<Arc<AppState>>,
     Json(req): Json<AddExposeReq>,
@@ -146,19 +174,30 @@ pub async fn add(
     let name = checked_name(&req.name)?;
 
     let mut exposes = state.exposes.write().await;
-    if let Some(rec) = exposes.get(&name) {
+    if let Some(rec) = exposes.get_mut(&name) {
         if rec.agent_pubkey != cred.agent_pubkey {
             return Err((
                 StatusCode::CONFLICT,
                 format!("имя {name} уже занято другим агентом"),
             ));
         }
-        return Ok(Json(ExposeResp::from(rec)));
+        if rec.kind != req.kind {
+            let updated = ExposeRecord { kind: req.kind, ..rec.clone() };
+            crate::storage::save_expose(Path::new(&state.config.server.data_dir), &updated)
+                .map_err(|e| crate::api::persist_failed("запись публикации", e))?;
+            *rec = updated;
+        }
+        return Ok(Json(ExposeResp::from(&*rec)));
     }
     let rec = ExposeRecord {
         name: name.clone(),
         agent_pubkey: cred.agent_pubkey.clone(),
         created: chrono::Utc::now().to_rfc3339(),
+        logout_before: 0,
+        access: ExposeAccess::Owner,
+        guests: Vec::new(),
+        kind: req.kind,
+        connect_agents: Vec::new(),
     };
     crate::storage::save_expose(Path::new(&state.config.server.data_dir), &rec)
         .map_err(|e| crate::api::persist_failed("запись публикации", e))?;
@@ -285,6 +324,95 @@ pub async fn mandate(
     Ok(Json(MandateResp { mandate: encode_mandate(&token), exp }))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ConnectReq {
+    /// Мандат агента той машины, с которой открывают туннель.
+    pub credential: String,
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+    /// Что требует политика публикации, как на браузерной форме: учётка,
+    /// пароль публикации или код инвайта.
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub invite: String,
+}
+
+/// `POST /api/v1/expose/{name}/connect` - маршрут TCP-публикации для
+/// `xr-share connect`: relay-грант и мандат публикации. Своя машина
+/// публикации получает его всегда. Чужая, даже заведённая на этом хабе (мандат
+/// агента выдаётся и гостям по setup-токену), только из списка машин
+/// публикации ([`ExposeRecord::connect_agents`]), и поверх списка её
+/// проверяет политика входа, как браузер: пароль публикации, учётка, инвайт.
+/// HTTP-публикации маршрут не выдаётся: их открывает браузерный вход.
+pub async fn connect(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+    Json(req): Json<ConnectReq>,
+) -> Result<Json<TcpRoute>, (StatusCode, String)> {
+    let signing = signing_or_503(&state)?;
+    let now = now_unix();
+    let cred = verify_credential_blob(signing, &req.credential, now)?;
+    let ttl = clamp_mandate_ttl(req.ttl_seconds)?;
+    let name = checked_name(&name)?;
+
+    let (agent_pubkey, access) = {
+        let exposes = state.exposes.read().await;
+        let rec = exposes
+            .get(&name)
+            .ok_or((StatusCode::NOT_FOUND, "публикация не найдена".into()))?;
+        if rec.kind != ExposeKind::Tcp {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("публикация {name} это HTTP-сервис: её открывает браузерный вход"),
+            ));
+        }
+        let own = rec.agent_pubkey == cred.agent_pubkey;
+        if !own && !rec.connect_agents.contains(&cred.agent_pubkey) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("машина не допущена к публикации {name}: владелец добавляет её в список машин публикации"),
+            ));
+        }
+        // Своей машине политика не нужна: туннель к себе самой ей ничего не даёт.
+        (rec.agent_pubkey.clone(), (!own).then(|| rec.access.clone()))
+    };
+    if let Some(access) = access.filter(|a| !a.is_owner()) {
+        let creds = crate::api::web_access::VerifyAccessReq {
+            publication: name.clone(),
+            username: req.username.clone(),
+            password: req.password.clone(),
+            invite: req.invite.clone(),
+        };
+        if !crate::api::web_access::admit(&state, &name, &access, &creds, now_ms()).await?.ok {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("политика входа публикации {name} не пускает: проверьте пароль, учётку или инвайт"),
+            ));
+        }
+    }
+    let exp = now.saturating_add(ttl);
+    let relay_token = sign_relay_token(&signing.signing_key, &tcp_share_id(&name), &agent_pubkey, exp);
+    let relay = state.config.relay_grant(&agent_pubkey, relay_token).ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "у хаба нет relay: туннелю не через что идти".to_string(),
+    ))?;
+    tracing::info!(
+        "туннель к {name}: маршрут агенту {}",
+        cred.agent_pubkey.chars().take(12).collect::<String>()
+    );
+    let token = sign_expose_token(&signing.signing_key, &name, &agent_pubkey, exp);
+    Ok(Json(TcpRoute {
+        publication: name,
+        agent_pubkey,
+        relay,
+        mandate: encode_mandate(&token),
+        exp,
+    }))
+}
+
 /// base64url-no-pad JSON мандата: та же форма блоба, что у токенов шар, и
 /// агент разбирает её тем же способом. Сама форма живёт рядом с типом в
 /// `xr-proto`, чтобы минт и предъявление фронтом не разъехались.
@@ -315,7 +443,7 @@ const PASSWORD_MAX_DELAY_MS: u64 = 5 * 60 * 1000;
 /// Сравнение постоянного времени, отказ без подробностей о том, что именно не
 /// сошлось. Блок `[web]` не задан значит браузерный вход выключен, и ручка
 /// говорит это прямо, а не притворяется отказом авторизации.
-fn require_web_secret(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+pub(crate) fn require_web_secret(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
     use subtle::ConstantTimeEq;
     let web = state.config.web.as_ref().ok_or((
         StatusCode::SERVICE_UNAVAILABLE,
@@ -354,26 +482,38 @@ pub async fn route(
 ) -> Result<Json<WebRoute>, (StatusCode, String)> {
     require_web_secret(&state, &headers)?;
     let signing = signing_or_503(&state)?;
-    let relay = state.config.relay.as_ref().ok_or((
-        StatusCode::SERVICE_UNAVAILABLE,
-        "у хаба нет relay: браузерному входу не через что идти".to_string(),
-    ))?;
+    if state.config.relay_list().is_empty() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "у хаба нет relay: браузерному входу не через что идти".to_string(),
+        ));
+    }
     let name = checked_name(&req.publication)?;
 
-    let agent_pubkey = {
+    let (agent_pubkey, access) = {
         let exposes = state.exposes.read().await;
-        exposes
+        let rec = exposes
             .get(&name)
-            .ok_or((StatusCode::NOT_FOUND, "публикация не найдена".into()))?
-            .agent_pubkey
-            .clone()
+            .ok_or((StatusCode::NOT_FOUND, "публикация не найдена".into()))?;
+        // Сырой TCP браузеру не по зубам: маршрут на него фронту не нужен.
+        if rec.kind == ExposeKind::Tcp {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("публикация {name} это TCP-сервис: её открывает `xr-share connect`"),
+            ));
+        }
+        (rec.agent_pubkey.clone(), rec.access.kind())
     };
 
+    // Relay'и те же, на которых агент держит туннели: основной и запасной.
+    let placed = state.config.relays_for(&agent_pubkey);
+    let relay = placed[0];
     let exp = now_unix().saturating_add(WEB_ROUTE_TTL);
     Ok(Json(WebRoute {
         publication: name.clone(),
         agent_pubkey: agent_pubkey.clone(),
         relay: relay.descriptor(),
+        backup_relays: placed[1..].iter().map(|r| r.descriptor()).collect(),
         relay_token: sign_relay_token(
             &signing.signing_key,
             &web_share_id(&name),
@@ -383,9 +523,79 @@ pub async fn route(
         expose_token: sign_expose_token(&signing.signing_key, &name, &agent_pubkey, exp),
         exp,
         splice_lifetime_secs: relay.splice_lifetime_secs,
+        access,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareRouteReq {
+    pub share_id: String,
+    /// Блоб токена шары из ссылки, как его принёс браузер.
+    pub token: String,
+}
+
+/// `POST /api/v1/web/share-route` - маршрут шары для её страницы на
+/// `s.<web-домен>` (LLD-38 п. 2.6). Фронт проверил токен сам, но хаб
+/// проверяет его ещё раз: маршрут выдаётся держателю ссылки, а не всякому,
+/// кто знает общий секрет, и `share_id` из чужого реестра фронт не перечислит.
+pub async fn share_route(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<ShareRouteReq>,
+) -> Result<Json<WebShareRoute>, (StatusCode, String)> {
+    require_web_secret(&state, &headers)?;
+    let signing = signing_or_503(&state)?;
+    if state.config.relay_list().is_empty() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "у хаба нет relay: странице шары не через что идти".to_string(),
+        ));
+    }
+    let now = now_unix();
+    let token = decode_share_token(&req.token)
+        .ok_or((StatusCode::BAD_REQUEST, "токен шары не разобрался".to_string()))?;
+    verify_share_token(&token, &signing.verifying_key(), &req.share_id, SCOPE_READ, now)
+        .map_err(|e| (StatusCode::FORBIDDEN, format!("токен шары отвергнут: {e}")))?;
+
+    let rec = state
+        .shares
+        .read()
+        .await
+        .get(&req.share_id)
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, "шара не найдена".to_string()))?;
+    // Транзит через relay владелец включает на шару сам (`share --relay`): шара
+    // только с прямым адресом на relay не выведена, и её расход туда не идёт.
+    if !rec.via_relay {
+        return Err((
+            StatusCode::CONFLICT,
+            "шара заведена без relay: страница в браузере ей недоступна".to_string(),
+        ));
+    }
+    let placed = state.config.relays_for(&rec.agent_pubkey);
+    let relay = placed[0];
+    let exp = now.saturating_add(WEB_ROUTE_TTL);
+    Ok(Json(WebShareRoute {
+        share_id: rec.share_id.clone(),
+        name: rec.name.clone(),
+        agent_pubkey: rec.agent_pubkey.clone(),
+        relay: relay.descriptor(),
+        backup_relays: placed[1..].iter().map(|r| r.descriptor()).collect(),
+        relay_token: sign_relay_token(&signing.signing_key, &rec.share_id, &rec.agent_pubkey, exp),
+        exp,
+        splice_lifetime_secs: relay.splice_lifetime_secs,
     }))
 }
 
+/// Адрес страницы шары для браузера: `https://s.<web-домен>/<id>/?token=...`.
+/// `None`, пока у хаба нет web-домена или шара заведена без relay: тогда
+/// ссылка остаётся только `xrshare://` для приложения и `pull`.
+pub fn share_page_url(state: &AppState, rec: &ShareRecord, token_blob: &str) -> Option<String> {
+    let domain = state.config.web.as_ref().map(|w| w.domain.trim())?;
+    (rec.via_relay && !domain.is_empty())
+        .then(|| format!("https://s.{domain}/{}/?token={token_blob}", rec.share_id))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct VerifyPasswordReq {
     pub username: String,
@@ -401,7 +611,8 @@ pub struct VerifyPasswordResp {
 
 /// `POST /api/v1/web/verify-password` - вердикт по паролю владельца для входа
 /// на публикацию (LLD-38 п. 3.2). Второго пароля владельцу не заводим, хэши
-/// живут там же, где жили, а фронт получает `true`/`false`.
+/// живут там же, где жили, а фронт получает `true`/`false`. Фронт с политиками
+/// входа ходит в `web/verify-access`; эта ручка остаётся для фронтов старше.
 pub async fn verify_password(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -440,6 +651,94 @@ pub fn verify_password_at(
     }
 }
 
+/// `POST /api/v1/web/sessions` - доклад фронта о живых сессиях браузера. Хаб
+/// кладёт его на доску для админки и отвечает моментами «выйти везде» по
+/// публикациям: сессии старше них фронт снимает сам. Своих сессий браузера хаб
+/// не держит и токенов их не видит, только отпечатки.
+pub async fn report_sessions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(report): Json<WebSessionsReport>,
+) -> Result<Json<WebSessionsVerdict>, (StatusCode, String)> {
+    require_web_secret(&state, &headers)?;
+    let verdict = {
+        let exposes = state.exposes.read().await;
+        let invites = state.invites.read().await;
+        let now_rfc = chrono::Utc::now().to_rfc3339();
+        let mut revoked = crate::api::web_access::revoked_guests(&exposes, now_unix());
+        revoked.extend(crate::api::web_access::revoked_invites(&exposes, &invites, &report.sessions, &now_rfc));
+        WebSessionsVerdict {
+            logout_before: exposes
+                .values()
+                .filter(|r| r.logout_before > 0)
+                .map(|r| (r.name.clone(), r.logout_before))
+                .collect(),
+            revoked_guests: revoked,
+        }
+    };
+    // Сессии, которые фронт ещё не успел снять, на доску уже не попадают:
+    // админ, нажавший «выйти везде», не должен видеть их живыми.
+    let live = report
+        .sessions
+        .into_iter()
+        .filter(|s| verdict.logout_before.get(&s.publication).is_none_or(|&cut| s.created >= cut))
+        .filter(|s| s.grant.is_empty() || !verdict.revoked_guests.contains(&s.grant))
+        .collect();
+    state.web_sessions.replace(live, now_unix());
+    Ok(Json(verdict))
+}
+
+/// Сессии одной публикации для админки.
+#[derive(Debug, Serialize)]
+pub struct PublicationSessions {
+    /// Когда фронт докладывал последний раз, unix-секунды; `null` не докладывал
+    /// с рестарта хаба, и пустой список тогда значит «не знаю», а не «никого».
+    pub reported_at: Option<u64>,
+    pub sessions: Vec<WebSessionInfo>,
+}
+
+/// `GET /api/v1/admin/exposes/{name}/sessions` - кто сейчас вошёл на
+/// публикацию: владелец, адрес и браузер последнего запроса. Свежесть списка
+/// равна периоду доклада фронта.
+pub async fn admin_sessions(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+) -> Result<Json<PublicationSessions>, (StatusCode, String)> {
+    if !state.exposes.read().await.contains_key(&name) {
+        return Err((StatusCode::NOT_FOUND, "публикация не найдена".into()));
+    }
+    Ok(Json(state.web_sessions.of(&name)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogoutResp {
+    pub logout_before: u64,
+}
+
+/// `POST /api/v1/admin/exposes/{name}/logout` - «выйти везде»: все сессии
+/// браузера на публикации, открытые до этого момента, мертвы. Момент пишется в
+/// запись публикации и переживает рестарты хаба и фронта; фронт узнаёт его
+/// следующим докладом и снимает сессии сам.
+pub async fn admin_logout(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+) -> Result<Json<LogoutResp>, (StatusCode, String)> {
+    let now = now_unix();
+    let mut exposes = state.exposes.write().await;
+    let rec = exposes
+        .get_mut(&name)
+        .ok_or((StatusCode::NOT_FOUND, "публикация не найдена".into()))?;
+    let mut updated = rec.clone();
+    updated.logout_before = now;
+    crate::storage::save_expose(Path::new(&state.config.server.data_dir), &updated)
+        .map_err(|e| crate::api::persist_failed("запись публикации", e))?;
+    *rec = updated;
+    drop(exposes);
+    state.web_sessions.drop_publication(&name);
+    tracing::info!("публикация {name}: выход везде, сессии до {now} сняты");
+    Ok(Json(LogoutResp { logout_before: now }))
+}
+
 /// Состояние одной публикации: кто её держит и на связи ли он сейчас.
 #[derive(Debug, Serialize)]
 pub struct PublicationStatus {
@@ -512,19 +811,16 @@ const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
 /// relay не трогает (LLD-38 п. 2.5).
 async fn probe_via_relay(state: &AppState, name: &str, agent_pubkey: &str) -> Result<bool, String> {
     let signing = state.signing.as_ref().ok_or("у хаба нет ключа подписи")?;
-    let relay = state.config.relay.as_ref().ok_or("у хаба не настроен relay")?;
     let token = sign_relay_token(
         &signing.signing_key,
         &web_share_id(name),
         agent_pubkey,
         now_unix() + 60,
     );
-    let grant = xr_proto::share::RelayGrant {
-        addr: relay.addr.clone(),
-        port: relay.port,
-        obf: relay.obf.clone(),
-        relay_token: token,
-    };
+    let grant = state
+        .config
+        .relay_grant(agent_pubkey, token)
+        .ok_or("у хаба не настроен relay")?;
     let endpoint = xr_proto::relay_client::RelayEndpoint::from_grant(&grant)?;
     match tokio::time::timeout(PROBE_TIMEOUT, xr_proto::relay_client::probe_agent_online(&endpoint))
         .await
@@ -535,13 +831,45 @@ async fn probe_via_relay(state: &AppState, name: &str, agent_pubkey: &str) -> Re
     }
 }
 
-fn now_ms() -> u64 {
+pub(crate) fn now_ms() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .map(|d| d.as_millis() as u64)
         .unwrap_or(0)
 }
 
+/// Последний доклад фронта о сессиях браузера (см. [`report_sessions`]).
+/// Только в памяти: после рестарта хаба доска пуста до следующего доклада, а
+/// сами сессии живут на фронте.
+#[derive(Default)]
+pub struct SessionBoard {
+    inner: std::sync::Mutex<Option<(u64, Vec<WebSessionInfo>)>>,
+}
+
+impl SessionBoard {
+    pub fn replace(&self, sessions: Vec<WebSessionInfo>, now: u64) {
+        *self.inner.lock().expect("session board lock") = Some((now, sessions));
+    }
+
+    /// Сессии публикации, свежие сверху.
+    pub fn of(&self, publication: &str) -> PublicationSessions {
+        let guard = self.inner.lock().expect("session board lock");
+        let Some((reported_at, all)) = guard.as_ref() else {
+            return PublicationSessions { reported_at: None, sessions: Vec::new() };
+        };
+        let mut sessions: Vec<WebSessionInfo> =
+            all.iter().filter(|s| s.publication == publication).cloned().collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+        PublicationSessions { reported_at: Some(*reported_at), sessions }
+    }
+
+    pub fn drop_publication(&self, publication: &str) {
+        if let Some((_, all)) = self.inner.lock().expect("session board lock").as_mut() {
+            all.retain(|s| s.publication != publication);
+        }
+    }
+}
+
 /// Счётчик неверных паролей на имя владельца с растущей задержкой (LLD-38
 /// п. 3.2). Живёт в памяти хаба: рестарт сбрасывает счётчики, и это осознанно,
 /// перебор через рестарт чужого процесса не ускоряется.
@@ -645,6 +973,7 @@ mod tests {
             signing: Some(SigningContext { signing_key: hub }),
             preset_gen: tokio::sync::watch::Sender::new(0),
             web_attempts: Default::default(),
+            web_sessions: Default::default(),
         })
     }
 
@@ -666,7 +995,11 @@ mod tests {
         let first = cred_blob(&hub, &agent_pk(7));
         let Json(resp) = add(
             State(state.clone()),
-            Json(AddExposeReq { credential: first.clone(), name: "dash".into() }),
+            Json(AddExposeReq {
+                credential: first.clone(),
+                name: "dash".into(),
+                kind: ExposeKind::Http,
+            }),
         )
         .await
         .expect("своё имя заводится");
@@ -676,7 +1009,7 @@ mod tests {
         // Тот же агент повторно: идемпотентно, без конфликта.
         assert!(add(
             State(state.clone()),
-            Json(AddExposeReq { credential: first, name: "dash".into() })
+            Json(AddExposeReq { credential: first, name: "dash".into(), kind: ExposeKind::Http })
         )
         .await
         .is_ok());
@@ -684,7 +1017,11 @@ mod tests {
         // Чужой агент на то же имя.
         let err = add(
             State(state.clone()),
-            Json(AddExposeReq { credential: cred_blob(&hub, &agent_pk(9)), name: "dash".into() }),
+            Json(AddExposeReq {
+                credential: cred_blob(&hub, &agent_pk(9)),
+                name: "dash".into(),
+                kind: ExposeKind::Http,
+            }),
         )
         .await
         .expect_err("чужое имя занимать нельзя");
@@ -706,14 +1043,22 @@ mod tests {
         for name in ["dash", "notes"] {
             let _ = add(
                 State(state.clone()),
-                Json(AddExposeReq { credential: mine.clone(), name: name.into() }),
+                Json(AddExposeReq {
+                    credential: mine.clone(),
+                    name: name.into(),
+                    kind: ExposeKind::Http,
+                }),
             )
             .await
             .unwrap();
         }
         let _ = add(
             State(state.clone()),
-            Json(AddExposeReq { credential: alien.clone(), name: "other".into() }),
+            Json(AddExposeReq {
+                credential: alien.clone(),
+                name: "other".into(),
+                kind: ExposeKind::Http,
+            }),
         )
         .await
         .unwrap();
@@ -752,7 +1097,11 @@ mod tests {
         let alien = cred_blob(&hub, &agent_pk(9));
         let _ = add(
             State(state.clone()),
-            Json(AddExposeReq { credential: mine.clone(), name: "dash".into() }),
+            Json(AddExposeReq {
+                credential: mine.clone(),
+                name: "dash".into(),
+                kind: ExposeKind::Http,
+            }),
         )
         .await
         .unwrap();
@@ -807,7 +1156,11 @@ mod tests {
         for name in ["", "Dash", "da.sh", "../etc", "dash-"] {
             let err = add(
                 State(state.clone()),
-                Json(AddExposeReq { credential: cred.clone(), name: name.into() }),
+                Json(AddExposeReq {
+                    credential: cred.clone(),
+                    name: name.into(),
+                    kind: ExposeKind::Http,
+                }),
             )
             .await
             .expect_err("имя обязано быть меткой");
@@ -827,12 +1180,70 @@ mod tests {
         let cred = cred_blob(hub, &agent_pk(7));
         let _ = add(
             State(state.clone()),
-            Json(AddExposeReq { credential: cred, name: "dash".into() }),
+            Json(AddExposeReq { credential: cred, name: "dash".into(), kind: ExposeKind::Http }),
         )
         .await
         .unwrap();
     }
 
+    fn web_session(id: &str, publication: &str, created: u64) -> WebSessionInfo {
+        WebSessionInfo {
+            id: id.into(),
+            publication: publication.into(),
+            username: "owner".into(),
+            created,
+            last_seen: created,
+            last_ip: "198.51.100.4".into(),
+            user_agent: "Firefox".into(),
+            grant: String::new(),
+        }
+    }
+
+    /// Доклад фронта виден админке по публикации, а «выйти везде» переживает
+    /// рестарт хаба записью публикации, едет фронту вердиктом и сразу убирает
+    /// старые сессии с доски, не дожидаясь, пока фронт их снимет.
+    #[tokio::test]
+    async fn logout_everywhere_reaches_the_front_and_the_board() {
+        let dir = tempfile::tempdir().unwrap();
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let state = state_web(dir.path(), hub.clone());
+        with_dash(&state, &hub).await;
+
+        let Json(before) = admin_sessions(State(state.clone()), AxPath("dash".into())).await.unwrap();
+        assert_eq!(before.reported_at, None, "до доклада доска не знает ничего");
+
+        let report = || WebSessionsReport {
+            sessions: vec![web_session("a", "dash", 100), web_session("b", "notes", 100)],
+        };
+        let err = report_sessions(State(state.clone()), secret("wrong"), Json(report()))
+            .await
+            .expect_err("доклад только под общим секретом");
+        assert_eq!(err.0, StatusCode::UNAUTHORIZED);
+
+        let Json(verdict) = report_sessions(State(state.clone()), secret("s3cret"), Json(report()))
+            .await
+            .unwrap();
+        assert!(verdict.logout_before.is_empty());
+        let Json(listed) = admin_sessions(State(state.clone()), AxPath("dash".into())).await.unwrap();
+        assert_eq!(listed.sessions, vec![web_session("a", "dash", 100)], "чужая публикация не видна");
+
+        let Json(out) = admin_logout(State(state.clone()), AxPath("dash".into())).await.unwrap();
+        let Json(listed) = admin_sessions(State(state.clone()), AxPath("dash".into())).await.unwrap();
+        assert!(listed.sessions.is_empty());
+        let saved = crate::storage::load_all_exposes(dir.path()).unwrap();
+        assert_eq!(saved["dash"].logout_before, out.logout_before, "момент пишется на диск");
+
+        let Json(verdict) = report_sessions(State(state.clone()), secret("s3cret"), Json(report()))
+            .await
+            .unwrap();
+        assert_eq!(verdict.logout_before.get("dash"), Some(&out.logout_before));
+        let Json(listed) = admin_sessions(State(state.clone()), AxPath("dash".into())).await.unwrap();
+        assert!(listed.sessions.is_empty(), "несобранная фронтом старая сессия на доску не попадает");
+
+        let err = admin_logout(State(state.clone()), AxPath("ghost".into())).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+    }
+
     /// Маршрут это дверь фронта в хаб, и открывает её только общий секрет:
     /// без заголовка и с чужим секретом ручка не отдаёт ничего, а с верным
     /// собирает транзит на `web:<имя>` и мандат ровно на эту публикацию
@@ -957,7 +1368,11 @@ mod tests {
         with_dash(&state, &hub).await;
         let _ = add(
             State(state.clone()),
-            Json(AddExposeReq { credential: cred_blob(&hub, &agent_pk(9)), name: "notes".into() }),
+            Json(AddExposeReq {
+                credential: cred_blob(&hub, &agent_pk(9)),
+                name: "notes".into(),
+                kind: ExposeKind::Http,
+            }),
         )
         .await
         .unwrap();
@@ -1009,4 +1424,182 @@ mod tests {
             .expect_err("снятой публикации нет");
         assert_eq!(err.0, StatusCode::NOT_FOUND);
     }
+
+    fn connect_req(credential: &str, ttl_seconds: Option<u64>) -> ConnectReq {
+        ConnectReq {
+            credential: credential.into(),
+            ttl_seconds,
+            username: String::new(),
+            password: String::new(),
+            invite: String::new(),
+        }
+    }
+
+    /// TCP-публикацию открывает своя машина и машины из списка публикации, а
+    /// чужую, даже заведённую на этом хабе, хаб не пускает. Поверх списка
+    /// действует политика входа. Маршрут несёт relay-токен на `tcp:<имя>` и
+    /// мандат. Для HTTP-публикации `connect` не работает, а браузерный вход не
+    /// выдаёт маршрута к TCP.
+    #[tokio::test]
+    async fn tcp_publication_is_reached_only_by_connect() {
+        use xr_proto::share::verify_relay_token;
+        let dir = tempfile::tempdir().unwrap();
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let state = state_web(dir.path(), hub.clone());
+        with_dash(&state, &hub).await;
+        let Json(resp) = add(
+            State(state.clone()),
+            Json(AddExposeReq {
+                credential: cred_blob(&hub, &agent_pk(7)),
+                name: "ssh".into(),
+                kind: ExposeKind::Tcp,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.kind, ExposeKind::Tcp);
+        let tunnel = |req: ConnectReq| connect(State(state.clone()), AxPath("ssh".into()), Json(req));
+
+        let own = cred_blob(&hub, &agent_pk(7));
+        let _ = tunnel(connect_req(&own, None)).await.expect("своя машина");
+        let other_machine = cred_blob(&hub, &agent_pk(9));
+        let err = tunnel(connect_req(&other_machine, None)).await.expect_err("чужая машина хаба");
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+
+        let _ = crate::api::web_access::admin_set_agents(
+            State(state.clone()),
+            AxPath("ssh".into()),
+            Json(crate::api::web_access::SetAgentsReq { agents: vec![agent_pk(9)] }),
+        )
+        .await
+        .unwrap();
+        let Json(r) = tunnel(connect_req(&other_machine, Some(600))).await.expect("машина из списка");
+        let now = now_unix();
+        assert_eq!(r.agent_pubkey, agent_pk(7));
+        assert_eq!(r.relay.addr, "relay.example.com");
+        assert!(r.exp <= now + 600);
+        verify_relay_token(&r.relay.relay_token, &hub.verifying_key(), "tcp:ssh", &agent_pk(7), now)
+            .expect("relay-токен на tcp:ssh");
+        let json = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(&r.mandate).unwrap();
+        let token: ExposeToken = serde_json::from_slice(&json).unwrap();
+        assert!(verify_expose_token(&token, &hub.verifying_key(), "ssh", &agent_pk(7), now).is_ok());
+
+        // Политика по паролю: машине из списка нужен и пароль публикации.
+        let _ = crate::api::web_access::admin_set_access(
+            State(state.clone()),
+            AxPath("ssh".into()),
+            Json(crate::api::web_access::SetAccessReq {
+                kind: WebAccessKind::Password,
+                password: Some("для друга".into()),
+                users: Vec::new(),
+                invites: Vec::new(),
+            }),
+        )
+        .await
+        .unwrap();
+        let err = tunnel(connect_req(&other_machine, None)).await.expect_err("без пароля");
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+        let with_password = ConnectReq { password: "для друга".into(), ..connect_req(&other_machine, None) };
+        let _ = tunnel(with_password).await.expect("с паролем публикации");
+        let _ = tunnel(connect_req(&own, None)).await.expect("своей машине пароль не нужен");
+
+        let err = connect(State(state.clone()), AxPath("dash".into()), Json(connect_req(&other_machine, None)))
+            .await
+            .expect_err("HTTP-публикация");
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        let foreign_hub = cred_blob(&SigningKey::from_bytes(&[1u8; 32]), &agent_pk(9));
+        let err = tunnel(connect_req(&foreign_hub, None)).await.expect_err("мандат агента чужого хаба");
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+
+        let err = route(
+            State(state.clone()),
+            secret("s3cret"),
+            Json(RouteReq { publication: "ssh".into() }),
+        )
+        .await
+        .expect_err("браузер к TCP не ходит");
+        assert_eq!(err.0, StatusCode::CONFLICT);
+
+        // Без relay туннелю не через что идти.
+        let plain = state_with(dir.path(), hub.clone());
+        *plain.exposes.write().await = state.exposes.read().await.clone();
+        let err = connect(State(plain), AxPath("ssh".into()), Json(connect_req(&own, None)))
+            .await
+            .expect_err("нет relay");
+        assert_eq!(err.0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    /// Маршрут страницы шары выдаётся только по живому токену этой самой шары
+    /// и только шаре с relay: прямой адрес фронту не поможет.
+    #[tokio::test]
+    async fn share_route_needs_the_share_token_and_relay() {
+        use xr_proto::share::{sign_share_token, verify_relay_token, ShareRecord};
+
+        let dir = tempfile::tempdir().unwrap();
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let state = state_web(dir.path(), hub.clone());
+        for (id, via_relay) in [("relayed", true), ("direct", false)] {
+            state.shares.write().await.insert(
+                id.to_string(),
+                ShareRecord {
+                    share_id: id.into(),
+                    name: format!("шара {id}"),
+                    owner: String::new(),
+                    addr: "203.0.113.9".into(),
+                    addrs: Vec::new(),
+                    port: 8443,
+                    agent_pubkey: agent_pk(7),
+                    created_at: String::new(),
+                    comment: String::new(),
+                    via_relay,
+                    writable: false,
+                },
+            );
+        }
+        let blob = |id: &str, exp: u64| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&sign_share_token(&hub, id, SCOPE_READ, exp)).unwrap())
+        };
+        let ask = |id: &str, token: String| {
+            share_route(
+                State(state.clone()),
+                secret("s3cret"),
+                Json(ShareRouteReq { share_id: id.into(), token }),
+            )
+        };
+
+        let Json(route) = ask("relayed", blob("relayed", now_unix() + 600)).await.expect("маршрут");
+        assert_eq!(route.name, "шара relayed");
+        assert_eq!(route.relay.addr, "relay.example.com");
+        assert_eq!(route.splice_lifetime_secs, 900);
+        // Транзит идёт на `share_id` самой шары, а не в неймспейс публикаций.
+        verify_relay_token(&route.relay_token, &hub.verifying_key(), "relayed", &agent_pk(7), now_unix())
+            .expect("relay-токен на эту шару");
+
+        let err = ask("relayed", blob("direct", now_unix() + 600)).await.expect_err("чужой токен");
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+        let err = ask("relayed", blob("relayed", now_unix() - 1)).await.expect_err("истёкший токен");
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+        let err = ask("direct", blob("direct", now_unix() + 600)).await.expect_err("шара без relay");
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        let err = ask("gone", blob("gone", now_unix() + 600)).await.expect_err("нет шары");
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+
+        let err = share_route(
+            State(state.clone()),
+            HeaderMap::new(),
+            Json(ShareRouteReq { share_id: "relayed".into(), token: blob("relayed", now_unix() + 600) }),
+        )
+        .await
+        .expect_err("нужен секрет");
+        assert_eq!(err.0, StatusCode::UNAUTHORIZED);
+
+        let rec = state.shares.read().await.get("relayed").cloned().unwrap();
+        assert_eq!(
+            share_page_url(&state, &rec, "T").as_deref(),
+            Some("https://s.web.example.com/relayed/?token=T")
+        );
+        let rec = state.shares.read().await.get("direct").cloned().unwrap();
+        assert_eq!(share_page_url(&state, &rec, "T"), None);
+    }
 }
@@ -0,0 +1,812 @@
+//! Политики браузерного входа на публикации и гостевые ссылки.
+//!
+//! Политика живёт в записи публикации ([`ExposeAccess`]) и проверяется здесь
+//! же, на хабе: пароли публикаций, учётки и инвайты до фронта не доезжают.
+//! Фронт получает с маршрутом только вид политики и по нему рисует форму
+//! входа, а то, что браузер на ней ввёл, приносит в [`verify_access`] за
+//! вердиктом, как раньше приносил пароль владельца.
+//!
+//! Гостевая ссылка это случайный токен в пути `/.xr-web/guest/<токен>` с
+//! потолком срока. Хаб помнит только SHA-256 токена; отозванная ссылка
+//! остаётся в записи до своего срока, и фронт по докладу сессий снимает
+//! открытые по ней сессии (см. [`revoked_guests`]). Так же живут сессии по
+//! инвайту: они не переживают срок инвайта, а его отзыв или снятие из
+//! политики снимает их с ближайшим докладом (см. [`revoked_invites`]).
+
+use std::path::Path;
+use std::sync::Arc;
+
+use argon2::Argon2;
+use axum::extract::{Path as AxPath, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use base64::Engine;
+use password_hash::{PasswordHash, PasswordVerifier};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xr_proto::preset::Invite;
+use xr_proto::share::{ExposeAccess, ExposeRecord, GuestLink, WebAccessKind, WebSessionInfo};
+
+use crate::api::register::now_unix;
+use crate::api::web::{checked_name, now_ms, require_web_secret, ExposeResp};
+use crate::state::AppState;
+
+/// Срок гостевой ссылки по умолчанию: сутки.
+const DEFAULT_GUEST_TTL: u64 = 24 * 3600;
+/// Потолок срока гостевой ссылки: месяц. Дольше это уже не гость, а учётка, и
+/// для неё есть политика по паролю или по инвайтам.
+const MAX_GUEST_TTL: u64 = 30 * 24 * 3600;
+
+fn not_found() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, "публикация не найдена".into())
+}
+
+/// Записать изменённую запись публикации: сперва диск, потом память, как и
+/// остальные ручки реестра.
+async fn update_expose<F>(state: &AppState, name: &str, change: F) -> Result<ExposeRecord, (StatusCode, String)>
+where
+    F: FnOnce(&mut ExposeRecord) -> Result<(), (StatusCode, String)>,
+{
+    let mut exposes = state.exposes.write().await;
+    let rec = exposes.get_mut(name).ok_or_else(not_found)?;
+    let mut updated = rec.clone();
+    change(&mut updated)?;
+    crate::storage::save_expose(Path::new(&state.config.server.data_dir), &updated)
+        .map_err(|e| crate::api::persist_failed("запись публикации", e))?;
+    *rec = updated.clone();
+    Ok(updated)
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Чем сессия по инвайту помечена у фронта: отпечаток кода, а не сам код,
+/// иначе он лёг бы в файл сессий фронта и в админку.
+fn invite_grant(code: &str) -> String {
+    format!("invite:{}", &token_hash(code)[..16])
+}
+
+/// Пускает ли ещё инвайт: тот же рубеж, что у шар по инвайту, отозванный и
+/// истёкший доступа не дают.
+fn invite_live(inv: &Invite, now_rfc: &str) -> bool {
+    inv.expires_at.as_str() > now_rfc && inv.consumed_at.is_none()
+}
+
+/// Сходится ли пароль с argon2-хэшем пароля публикации.
+fn hash_matches(hash: &str, password: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        tracing::error!("плохой хэш пароля публикации");
+        return false;
+    };
+    Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok()
+}
+
+// Вердикт для фронта.
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyAccessReq {
+    pub publication: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// Код инвайта, для политики по инвайтам.
+    #[serde(default)]
+    pub invite: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyAccessResp {
+    pub ok: bool,
+    /// Под каким именем фронт откроет сессию: его видно в списке сессий
+    /// админки. Пусто при отказе.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub who: String,
+    /// Чем пометить сессию, чтобы её можно было снять отзывом (инвайт).
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub grant: String,
+    /// Потолок жизни сессии, unix-секунды: срок инвайта. `0` потолка нет.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub until: u64,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+impl VerifyAccessResp {
+    fn refused() -> Self {
+        Self { ok: false, who: String::new(), grant: String::new(), until: 0 }
+    }
+
+    fn admitted(who: String) -> Self {
+        Self { ok: true, who, grant: String::new(), until: 0 }
+    }
+}
+
+/// `POST /api/v1/web/verify-access` - вердикт по тому, что браузер ввёл на
+/// форме входа публикации, по её политике.
+pub async fn verify_access(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<VerifyAccessReq>,
+) -> Result<Json<VerifyAccessResp>, (StatusCode, String)> {
+    verify_access_at(&state, &headers, &req, now_ms()).await
+}
+
+/// Та же проверка с явным «сейчас» в миллисекундах, ради задержки попыток.
+pub async fn verify_access_at(
+    state: &AppState,
+    headers: &HeaderMap,
+    req: &VerifyAccessReq,
+    now_ms: u64,
+) -> Result<Json<VerifyAccessResp>, (StatusCode, String)> {
+    require_web_secret(state, headers)?;
+    let name = checked_name(&req.publication)?;
+    let access = state
+        .exposes
+        .read()
+        .await
+        .get(&name)
+        .ok_or_else(not_found)?
+        .access
+        .clone();
+    admit(state, &name, &access, req, now_ms).await.map(Json)
+}
+
+/// Вердикт политики `access` публикации `name` по тому, что предъявлено:
+/// браузерной формой или туннелем `connect`. Задержка перебора у них общая.
+pub(crate) async fn admit(
+    state: &AppState,
+    name: &str,
+    access: &ExposeAccess,
+    req: &VerifyAccessReq,
+    now_ms: u64,
+) -> Result<VerifyAccessResp, (StatusCode, String)> {
+    // Лимит попыток на то, что перебирают: имя учётки у входа по учёткам (тот
+    // же счётчик, что у пароля владельца), сама публикация у общего пароля и
+    // у инвайтов.
+    let attempts_key = match access {
+        ExposeAccess::Owner | ExposeAccess::Users { .. } => req.username.clone(),
+        ExposeAccess::Password { .. } => format!("{name}|password"),
+        ExposeAccess::Invites { .. } => format!("{name}|invite"),
+        ExposeAccess::Public => String::new(),
+    };
+    if let Some(wait_ms) = state.web_attempts.blocked_for(&attempts_key, now_ms) {
+        let secs = wait_ms.div_ceil(1000);
+        tracing::warn!("вход на {name}: попытки {attempts_key} упёрлись в задержку, ещё {secs} с");
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("слишком много попыток, повторить через {secs} с"),
+        ));
+    }
+
+    let admitted = match access {
+        ExposeAccess::Public => Some(VerifyAccessResp::admitted("публичный вход".to_string())),
+        ExposeAccess::Owner => {
+            crate::api::auth::password_matches(&state.config, &req.username, &req.password)
+                .then(|| VerifyAccessResp::admitted(req.username.clone()))
+        }
+        ExposeAccess::Users { users } => {
+            // Проверка пароля идёт и для имени не из списка: отказ по списку не
+            // должен отличаться по времени от отказа по паролю.
+            let ok = crate::api::auth::password_matches(&state.config, &req.username, &req.password);
+            (ok && users.contains(&req.username)).then(|| VerifyAccessResp::admitted(req.username.clone()))
+        }
+        ExposeAccess::Password { hash } => hash_matches(hash, &req.password)
+            .then(|| VerifyAccessResp::admitted("пароль публикации".to_string())),
+        ExposeAccess::Invites { invites } => {
+            let code = req.invite.trim();
+            let now_rfc = chrono::Utc::now().to_rfc3339();
+            let all = state.invites.read().await;
+            invites
+                .iter()
+                .find(|t| crate::api::invites::constant_time_eq(t, code))
+                .and_then(|t| all.get(t))
+                .filter(|inv| invite_live(inv, &now_rfc))
+                .map(|inv| {
+                    let grant = invite_grant(&inv.token);
+                    VerifyAccessResp {
+                        ok: true,
+                        // Без комментария инвайт называется отпечатком, а не
+                        // началом кода: `who` пишется в файл сессий.
+                        who: match inv.comment.trim() {
+                            "" => format!("инвайт {}", &grant["invite:".len()..]),
+                            c => format!("инвайт: {c}"),
+                        },
+                        grant,
+                        // Продление сессии не уводит её за срок инвайта.
+                        until: chrono::DateTime::parse_from_rfc3339(&inv.expires_at)
+                            .map(|t| t.timestamp().max(0) as u64)
+                            .unwrap_or(0),
+                    }
+                })
+        }
+    };
+
+    match admitted {
+        Some(resp) => {
+            state.web_attempts.succeeded(&attempts_key);
+            Ok(resp)
+        }
+        None => {
+            state.web_attempts.failed(&attempts_key, now_ms);
+            tracing::warn!("вход на {name}: отказ по политике {:?}", access.kind());
+            Ok(VerifyAccessResp::refused())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GuestReq {
+    pub publication: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GuestResp {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub label: String,
+    /// Потолок жизни гостевой сессии, unix-секунды.
+    pub expires: u64,
+}
+
+/// `POST /api/v1/web/guest` - вердикт по гостевой ссылке. Токен это 32
+/// случайных байта, перебирать его бессмысленно, поэтому лимита попыток у
+/// ручки нет.
+pub async fn redeem_guest(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<GuestReq>,
+) -> Result<Json<GuestResp>, (StatusCode, String)> {
+    require_web_secret(&state, &headers)?;
+    let name = checked_name(&req.publication)?;
+    let now = now_unix();
+    let hash = token_hash(req.token.trim());
+    let exposes = state.exposes.read().await;
+    let rec = exposes.get(&name).ok_or_else(not_found)?;
+    let found = rec
+        .guests
+        .iter()
+        .find(|g| crate::api::invites::constant_time_eq(&g.token_hash, &hash))
+        .filter(|g| !g.revoked && g.expires > now);
+    Ok(Json(match found {
+        Some(g) => GuestResp {
+            ok: true,
+            id: g.id.clone(),
+            label: g.label.clone(),
+            expires: g.expires,
+        },
+        None => {
+            tracing::warn!("гостевой вход на {name}: ссылка не найдена, отозвана или истекла");
+            GuestResp { ok: false, id: String::new(), label: String::new(), expires: 0 }
+        }
+    }))
+}
+
+/// Отозванные и ещё не истёкшие гостевые ссылки всех публикаций: их фронт
+/// получает в ответ на доклад сессий.
+pub fn revoked_guests(exposes: &std::collections::HashMap<String, ExposeRecord>, now: u64) -> Vec<String> {
+    exposes
+        .values()
+        .flat_map(|r| r.guests.iter())
+        .filter(|g| g.revoked && g.expires > now)
+        .map(|g| g.id.clone())
+        .collect()
+}
+
+/// Сессии по инвайтам из доклада фронта, которые пора снять: инвайт отозван
+/// или истёк, снят из политики публикации или политика уже не по инвайтам.
+/// Срок фронт держит и сам, а отзыв узнаёт только отсюда.
+pub fn revoked_invites(
+    exposes: &std::collections::HashMap<String, ExposeRecord>,
+    invites: &std::collections::HashMap<String, Invite>,
+    sessions: &[WebSessionInfo],
+    now_rfc: &str,
+) -> Vec<String> {
+    let mut revoked: Vec<String> = sessions
+        .iter()
+        .filter(|s| s.grant.starts_with("invite:"))
+        .filter(|s| {
+            let allowed = match exposes.get(&s.publication).map(|r| &r.access) {
+                Some(ExposeAccess::Invites { invites }) => invites.as_slice(),
+                _ => &[],
+            };
+            !allowed.iter().any(|code| {
+                invite_grant(code) == s.grant && invites.get(code).is_some_and(|inv| invite_live(inv, now_rfc))
+            })
+        })
+        .map(|s| s.grant.clone())
+        .collect();
+    revoked.sort();
+    revoked.dedup();
+    revoked
+}
+
+// Админка.
+
+#[derive(Debug, Deserialize)]
+pub struct SetAccessReq {
+    pub kind: WebAccessKind,
+    /// Новый пароль публикации. Для политики по паролю его можно не слать,
+    /// если пароль уже задан и меняется только что-то другое.
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub invites: Vec<String>,
+}
+
+/// `PUT /api/v1/admin/exposes/{name}/access` - сменить политику входа. Смена
+/// это заодно «выйти везде»: сессии, открытые по прежней политике, по новой
+/// могли бы и не открыться.
+pub async fn admin_set_access(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+    Json(req): Json<SetAccessReq>,
+) -> Result<Json<ExposeResp>, (StatusCode, String)> {
+    let bad = |msg: &str| (StatusCode::BAD_REQUEST, msg.to_string());
+    let access = match req.kind {
+        WebAccessKind::Owner => ExposeAccess::Owner,
+        WebAccessKind::Public => ExposeAccess::Public,
+        WebAccessKind::Password => match req.password.as_deref().map(str::trim) {
+            Some(p) if !p.is_empty() => ExposeAccess::Password {
+                hash: crate::api::auth::hash_password(p)
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?,
+            },
+            _ => match state.exposes.read().await.get(&name).map(|r| r.access.clone()) {
+                Some(kept @ ExposeAccess::Password { .. }) => kept,
+                Some(_) => return Err(bad("для входа по паролю нужен пароль")),
+                None => return Err(not_found()),
+            },
+        },
+        WebAccessKind::Users => {
+            if req.users.is_empty() {
+                return Err(bad("для входа по учёткам нужна хотя бы одна учётка"));
+            }
+            if let Some(unknown) = req
+                .users
+                .iter()
+                .find(|u| !state.config.admin.users.iter().any(|a| &a.username == *u))
+            {
+                return Err(bad(&format!("учётки {unknown} нет в [admin.users]")));
+            }
+            ExposeAccess::Users { users: req.users }
+        }
+        WebAccessKind::Invites => {
+            if req.invites.is_empty() {
+                return Err(bad("для входа по инвайтам нужен хотя бы один инвайт"));
+            }
+            let all = state.invites.read().await;
+            if let Some(unknown) = req.invites.iter().find(|t| !all.contains_key(*t)) {
+                return Err((StatusCode::NOT_FOUND, format!("инвайт {unknown} не найден")));
+            }
+            ExposeAccess::Invites { invites: req.invites }
+        }
+    };
+
+    let now = now_unix();
+    let kind = access.kind();
+    let rec = update_expose(&state, &name, |rec| {
+        rec.access = access;
+        rec.logout_before = now;
+        Ok(())
+    })
+    .await?;
+    state.web_sessions.drop_publication(&name);
+    tracing::info!("публикация {name}: политика входа {kind:?}, прежние сессии сняты");
+    Ok(Json(ExposeResp::from(&rec)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAgentsReq {
+    pub agents: Vec<String>,
+}
+
+/// `PUT /api/v1/admin/exposes/{name}/agents` - машины, которым хаб выдаёт
+/// туннель к TCP-публикации (см. [`crate::api::web::connect`]). Политика входа
+/// при этом не меняется и сессии не снимаются.
+pub async fn admin_set_agents(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+    Json(req): Json<SetAgentsReq>,
+) -> Result<Json<ExposeResp>, (StatusCode, String)> {
+    let mut agents = Vec::new();
+    for agent in &req.agents {
+        crate::api::register::validate_ed25519_pubkey(agent)?;
+        let agent = agent.trim().to_string();
+        if !agents.contains(&agent) {
+            agents.push(agent);
+        }
+    }
+    let count = agents.len();
+    let rec = update_expose(&state, &name, |rec| {
+        rec.connect_agents = agents;
+        Ok(())
+    })
+    .await?;
+    tracing::info!("публикация {name}: туннель открывают {count} машин(ы) сверх своей");
+    Ok(Json(ExposeResp::from(&rec)))
+}
+
+/// Гостевая ссылка глазами админки: без хэша токена.
+#[derive(Debug, Serialize)]
+pub struct GuestView {
+    pub id: String,
+    pub label: String,
+    pub created: u64,
+    pub expires: u64,
+    pub revoked: bool,
+}
+
+impl From<&GuestLink> for GuestView {
+    fn from(g: &GuestLink) -> Self {
+        Self {
+            id: g.id.clone(),
+            label: g.label.clone(),
+            created: g.created,
+            expires: g.expires,
+            revoked: g.revoked,
+        }
+    }
+}
+
+/// `GET /api/v1/admin/exposes/{name}/guests` - гостевые ссылки публикации.
+pub async fn admin_list_guests(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+) -> Result<Json<Vec<GuestView>>, (StatusCode, String)> {
+    let exposes = state.exposes.read().await;
+    let rec = exposes.get(&name).ok_or_else(not_found)?;
+    Ok(Json(rec.guests.iter().map(GuestView::from).collect()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGuestReq {
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateGuestResp {
+    pub id: String,
+    /// Сам токен: показывается один раз, хаб его не хранит.
+    pub token: String,
+    /// Готовая ссылка, если web-домен задан.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    pub expires: u64,
+}
+
+/// `POST /api/v1/admin/exposes/{name}/guests` - выпустить гостевую ссылку.
+pub async fn admin_create_guest(
+    State(state): State<Arc<AppState>>,
+    AxPath(name): AxPath<String>,
+    Json(req): Json<CreateGuestReq>,
+) -> Result<Json<CreateGuestResp>, (StatusCode, String)> {
+    let ttl = req.ttl_seconds.unwrap_or(DEFAULT_GUEST_TTL);
+    if ttl == 0 || ttl > MAX_GUEST_TTL {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("ttl_seconds must be 1..={MAX_GUEST_TTL}"),
+        ));
+    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let hash = token_hash(&token);
+    let now = now_unix();
+    let link = GuestLink {
+        id: hash[..12].to_string(),
+        token_hash: hash,
+        label: req.label.trim().to_string(),
+        created: now,
+        expires: now.saturating_add(ttl),
+        revoked: false,
+    };
+    let id = link.id.clone();
+    let expires = link.expires;
+    update_expose(&state, &name, |rec| {
+        // Истёкшие ссылки больше ничего не держат: ни входа, ни сессий.
+        rec.guests.retain(|g| g.expires > now);
+        rec.guests.push(link);
+        Ok(())
+    })
+    .await?;
+
+    let url = match state.config.web.as_ref().map(|w| w.domain.trim()) {
+        Some(domain) if !domain.is_empty() => {
+            format!("https://{name}.{domain}/.xr-web/guest/{token}")
+        }
+        _ => String::new(),
+    };
+    tracing::info!("публикация {name}: гостевая ссылка {id} до {expires}");
+    Ok(Json(CreateGuestResp { id, token, url, expires }))
+}
+
+/// `DELETE /api/v1/admin/exposes/{name}/guests/{id}` - отозвать гостевую
+/// ссылку. Открытые по ней сессии фронт снимает следующим докладом.
+pub async fn admin_revoke_guest(
+    State(state): State<Arc<AppState>>,
+    AxPath((name, id)): AxPath<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let now = now_unix();
+    update_expose(&state, &name, |rec| {
+        rec.guests.retain(|g| g.expires > now);
+        let link = rec
+            .guests
+            .iter_mut()
+            .find(|g| g.id == id)
+            .ok_or((StatusCode::NOT_FOUND, "гостевая ссылка не найдена".to_string()))?;
+        link.revoked = true;
+        Ok(())
+    })
+    .await?;
+    tracing::info!("публикация {name}: гостевая ссылка {id} отозвана");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::sync::RwLock;
+    use xr_proto::preset::{Invite, InvitePayload};
+
+    use crate::config::HubConfig;
+
+    const INVITE: &str = "abcdefghij0123456789AB";
+
+    fn invite(consumed: bool) -> Invite {
+        Invite {
+            token: INVITE.into(),
+            created_at: "2026-01-01T00:00:00+00:00".into(),
+            expires_at: "2099-01-01T00:00:00+00:00".into(),
+            consumed_at: consumed.then(|| "2026-01-02T00:00:00+00:00".into()),
+            claimed_by_ip: None,
+            claim_id: None,
+            one_time: false,
+            comment: "друг".into(),
+            payload: InvitePayload {
+                server_address: "203.0.113.10".into(),
+                server_port: 8443,
+                obfuscation_key: String::new(),
+                modifier: "positional_xor_rotate".into(),
+                salt: 0,
+                preset: "russia".into(),
+                hub_url: String::new(),
+                servers: Vec::new(),
+            },
+            share_ids: Vec::new(),
+            write_share_ids: Vec::new(),
+        }
+    }
+
+    /// Хаб с браузерным входом, двумя учётками (`owner` и `friend`, пароль у
+    /// обеих `secret`), инвайтом и публикацией `dash`.
+    fn state(dir: &Path) -> Arc<AppState> {
+        let hash = crate::api::auth::hash_password("secret").unwrap();
+        let text = format!(
+            concat!(
+                "[server]\ndata_dir = {dir:?}\n",
+                "[admin]\n[[admin.users]]\nusername = \"owner\"\npassword_hash = {hash:?}\n",
+                "[[admin.users]]\nusername = \"friend\"\npassword_hash = {hash:?}\n",
+                "[web]\ndomain = \"web.example.com\"\nshared_secret = \"s3cret\"\n",
+            ),
+            dir = dir.display().to_string(),
+            hash = hash,
+        );
+        let config: HubConfig = toml::from_str(&text).unwrap();
+        let rec = ExposeRecord {
+            name: "dash".into(),
+            agent_pubkey: "agent".into(),
+            created: String::new(),
+            logout_before: 0,
+            access: ExposeAccess::Owner,
+            guests: Vec::new(),
+            kind: Default::default(),
+            connect_agents: Vec::new(),
+        };
+        Arc::new(AppState {
+            presets: RwLock::new(HashMap::new()),
+            invites: RwLock::new(HashMap::from([(INVITE.to_string(), invite(false))])),
+            shares: RwLock::new(HashMap::new()),
+            exposes: RwLock::new(HashMap::from([("dash".to_string(), rec)])),
+            sessions: RwLock::new(HashMap::new()),
+            config,
+            signing: None,
+            preset_gen: tokio::sync::watch::Sender::new(0),
+            web_attempts: Default::default(),
+            web_sessions: Default::default(),
+        })
+    }
+
+    fn secret() -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert("authorization", "Bearer s3cret".parse().unwrap());
+        h
+    }
+
+    async fn set(state: &Arc<AppState>, req: SetAccessReq) -> Result<ExposeResp, (StatusCode, String)> {
+        admin_set_access(State(state.clone()), AxPath("dash".into()), Json(req))
+            .await
+            .map(|Json(r)| r)
+    }
+
+    fn policy(kind: WebAccessKind) -> SetAccessReq {
+        SetAccessReq { kind, password: None, users: Vec::new(), invites: Vec::new() }
+    }
+
+    async fn verdict(state: &AppState, username: &str, password: &str, invite: &str) -> Option<String> {
+        let req = VerifyAccessReq {
+            publication: "dash".into(),
+            username: username.into(),
+            password: password.into(),
+            invite: invite.into(),
+        };
+        let Json(resp) = verify_access_at(state, &secret(), &req, 0).await.unwrap();
+        resp.ok.then_some(resp.who)
+    }
+
+    #[tokio::test]
+    async fn policy_decides_who_gets_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        // По умолчанию любая учётка админки, как до политик.
+        assert_eq!(verdict(&state, "friend", "secret", "").await.as_deref(), Some("friend"));
+
+        // Только перечисленные учётки.
+        let resp = set(&state, SetAccessReq { users: vec!["owner".into()], ..policy(WebAccessKind::Users) })
+            .await
+            .unwrap();
+        assert_eq!(resp.access, WebAccessKind::Users);
+        assert_eq!(verdict(&state, "owner", "secret", "").await.as_deref(), Some("owner"));
+        assert_eq!(verdict(&state, "friend", "secret", "").await, None, "учётка не из списка");
+        let err = set(&state, SetAccessReq { users: vec!["ghost".into()], ..policy(WebAccessKind::Users) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+
+        // Пароль публикации: учётка не нужна, хэш наружу не уходит.
+        let resp = set(
+            &state,
+            SetAccessReq { password: Some("для друга".into()), ..policy(WebAccessKind::Password) },
+        )
+        .await
+        .unwrap();
+        assert!(!serde_json::to_string(&resp).unwrap().contains("argon2"));
+        assert!(verdict(&state, "", "для друга", "").await.is_some());
+        assert_eq!(verdict(&state, "owner", "secret", "").await, None, "пароль админки тут не ключ");
+        // Смена другого без нового пароля оставляет прежний.
+        set(&state, policy(WebAccessKind::Password)).await.unwrap();
+        assert!(verdict(&state, "", "для друга", "").await.is_some());
+
+        // Инвайт: живой пускает, отозванный нет.
+        set(&state, SetAccessReq { invites: vec![INVITE.into()], ..policy(WebAccessKind::Invites) })
+            .await
+            .unwrap();
+        assert_eq!(verdict(&state, "", "", INVITE).await.as_deref(), Some("инвайт: друг"));
+        assert_eq!(verdict(&state, "", "", "чужой").await, None);
+        // Без комментария инвайт зовётся отпечатком: кода в файле сессий нет.
+        state.invites.write().await.insert(INVITE.into(), Invite { comment: String::new(), ..invite(false) });
+        let who = verdict(&state, "", "", INVITE).await.unwrap();
+        assert_eq!(who, format!("инвайт {}", &invite_grant(INVITE)[7..]));
+        assert!(!who.contains(&INVITE[..6]), "{who}");
+        state.invites.write().await.insert(INVITE.into(), invite(true));
+        assert_eq!(verdict(&state, "", "", INVITE).await, None, "отозванный инвайт не пускает");
+
+        // Политика легла на диск, и каждая смена это «выйти везде».
+        let stored = crate::storage::load_all_exposes(dir.path()).unwrap();
+        assert!(matches!(stored["dash"].access, ExposeAccess::Invites { .. }));
+        assert!(stored["dash"].logout_before > 0);
+    }
+
+    /// Сессия по инвайту помечена им и не переживает ни срок инвайта, ни его
+    /// отзыв, ни смену политики: доклад сессий возвращает её метку.
+    #[tokio::test]
+    async fn invite_session_ends_with_its_invite() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        set(&state, SetAccessReq { invites: vec![INVITE.into()], ..policy(WebAccessKind::Invites) })
+            .await
+            .unwrap();
+        let req = VerifyAccessReq {
+            publication: "dash".into(),
+            username: String::new(),
+            password: String::new(),
+            invite: INVITE.into(),
+        };
+        let Json(pass) = verify_access_at(&state, &secret(), &req, 0).await.unwrap();
+        assert!(pass.ok && pass.grant.starts_with("invite:"), "{pass:?}");
+        assert!(!pass.grant.contains(INVITE), "кода инвайта в метке нет");
+        assert_eq!(pass.until, 4_070_908_800, "потолок это срок инвайта");
+
+        let report = |grant: &str| xr_proto::share::WebSessionsReport {
+            sessions: vec![WebSessionInfo {
+                id: "s1".into(),
+                publication: "dash".into(),
+                username: pass.who.clone(),
+                created: now_unix(),
+                last_seen: now_unix(),
+                last_ip: String::new(),
+                user_agent: String::new(),
+                grant: grant.into(),
+            }],
+        };
+        let revoked = |grant: String| {
+            let state = state.clone();
+            let report = report(&grant);
+            async move {
+                let Json(verdict) = crate::api::web::report_sessions(State(state), secret(), Json(report))
+                    .await
+                    .unwrap();
+                verdict.revoked_guests
+            }
+        };
+        assert!(revoked(pass.grant.clone()).await.is_empty(), "живой инвайт сессию держит");
+
+        state.invites.write().await.insert(INVITE.into(), invite(true));
+        assert_eq!(revoked(pass.grant.clone()).await, vec![pass.grant.clone()], "отзыв снимает сессию");
+        assert!(state.web_sessions.of("dash").sessions.is_empty(), "снятая на доску не попадает");
+
+        state.invites.write().await.insert(INVITE.into(), invite(false));
+        set(&state, policy(WebAccessKind::Owner)).await.unwrap();
+        assert_eq!(revoked(pass.grant.clone()).await, vec![pass.grant], "смена политики тоже");
+    }
+
+    #[tokio::test]
+    async fn guest_link_is_minted_redeemed_and_revoked() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let Json(link) = admin_create_guest(
+            State(state.clone()),
+            AxPath("dash".into()),
+            Json(CreateGuestReq { label: "Вася".into(), ttl_seconds: Some(600) }),
+        )
+        .await
+        .unwrap();
+        assert!(link.url.starts_with("https://dash.web.example.com/.xr-web/guest/"), "{}", link.url);
+        let stored = crate::storage::load_all_exposes(dir.path()).unwrap();
+        assert!(
+            !serde_json::to_string(&stored["dash"]).unwrap().contains(&link.token),
+            "токена на диске нет"
+        );
+
+        let redeem = |token: String| {
+            let state = state.clone();
+            async move {
+                let Json(resp) = redeem_guest(
+                    State(state),
+                    secret(),
+                    Json(GuestReq { publication: "dash".into(), token }),
+                )
+                .await
+                .unwrap();
+                resp
+            }
+        };
+        let pass = redeem(link.token.clone()).await;
+        assert!(pass.ok);
+        assert_eq!((pass.id.as_str(), pass.label.as_str()), (link.id.as_str(), "Вася"));
+        assert!(!redeem("чужой".into()).await.ok);
+
+        admin_revoke_guest(State(state.clone()), AxPath(("dash".into(), link.id.clone())))
+            .await
+            .unwrap();
+        assert!(!redeem(link.token).await.ok, "отозванная ссылка не пускает");
+        let revoked = revoked_guests(&*state.exposes.read().await, now_unix());
+        assert_eq!(revoked, vec![link.id], "отзыв доезжает до фронта с докладом сессий");
+    }
+}
@@ -10,11 +10,17 @@ pub struct HubConfig {
     pub signing: Option<SigningConfig>,
     #[serde(default)]
     pub invites: InvitesConfig,
-    /// The relay this hub advertises for NAT'd shares (LLD-23 §2.4). One relay per
-    /// hub in MVP. Absent means the hub has no relay: `via_relay` shares still work
-    /// on their direct address, they just get no relay fallback in the grant.
+    /// The relay this hub advertises for NAT'd shares (LLD-23 §2.4). Legacy
+    /// single-relay form, kept working as the first entry of [`relays`](Self::relays).
+    /// No relay at all means `via_relay` shares still work on their direct
+    /// address, they just get no relay fallback in the grant.
     #[serde(default)]
     pub relay: Option<HubRelayConfig>,
+    /// More relays (`[[relays]]`). Each agent is placed on
+    /// [`RELAYS_PER_AGENT`] of them, so one relay outage doesn't take every
+    /// relayed share and web publication down.
+    #[serde(default)]
+    pub relays: Vec<HubRelayConfig>,
     /// Браузерный вход (LLD-38 п. 3.5): общий секрет служебных ручек и домен,
     /// на поддоменах которого живут публикации. Блока нет это выключенный
     /// браузерный вход: служебные ручки отвечают `503`, реестр публикаций при
@@ -52,6 +58,72 @@ pub struct HubRelayConfig {
     /// соединения штатно до обрыва (LLD-38 п. 2.4).
     #[serde(default = "default_splice_lifetime")]
     pub splice_lifetime_secs: u64,
+    /// The relay's stats endpoint (`[relay.admin] listen` on the relay), as
+    /// `host:port` reachable from the hub: the relay's loopback when both run
+    /// on one VPS, or an SSH/WireGuard tunnel to it. Absent means the admin
+    /// view lists the relay without stats.
+    #[serde(default)]
+    pub admin: Option<String>,
+    /// `[relay.admin] token` of the relay, if it has one.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// The relay runs with `punch = true`: grants tell consumers they may try
+    /// a direct path through it before the splice.
+    #[serde(default)]
+    pub punch: bool,
+}
+
+/// How many relays one agent registers with: the primary plus one backup. Two
+/// survive a single relay outage; more would only multiply idle tunnels.
+pub const RELAYS_PER_AGENT: usize = 2;
+
+impl HubConfig {
+    /// Every configured relay: the legacy `[relay]` first, then `[[relays]]`.
+    pub fn relay_list(&self) -> Vec<&HubRelayConfig> {
+        self.relay.iter().chain(self.relays.iter()).collect()
+    }
+
+    /// The relays an agent registers with and consumers reach it through, best
+    /// first. Rendezvous hashing on the agent key: the placement is stable
+    /// across hub restarts without storing it, agents spread evenly, and adding
+    /// a relay moves only the agents that land on it.
+    pub fn relays_for(&self, agent_pubkey: &str) -> Vec<&HubRelayConfig> {
+        use sha2::{Digest, Sha256};
+        let mut ranked: Vec<(u64, &HubRelayConfig)> = self
+            .relay_list()
+            .into_iter()
+            .map(|r| {
+                let mut h = Sha256::new();
+                h.update(agent_pubkey.trim().as_bytes());
+                h.update([0u8]);
+                h.update(format!("{}:{}", r.addr, r.port).as_bytes());
+                let digest = h.finalize();
+                let score = u64::from_be_bytes(digest[..8].try_into().expect("8 bytes"));
+                (score, r)
+            })
+            .collect();
+        ranked.sort_by_key(|r| std::cmp::Reverse(r.0));
+        ranked.into_iter().take(RELAYS_PER_AGENT).map(|(_, r)| r).collect()
+    }
+
+    /// The consumer's relay leg to an agent: its placed relays, primary plus
+    /// backups, all behind the same transit token. `None` without relays.
+    pub fn relay_grant(
+        &self,
+        agent_pubkey: &str,
+        relay_token: xr_proto::share::RelayToken,
+    ) -> Option<xr_proto::share::RelayGrant> {
+        let placed = self.relays_for(agent_pubkey);
+        let (primary, backups) = placed.split_first()?;
+        Some(xr_proto::share::RelayGrant {
+            addr: primary.addr.clone(),
+            port: primary.port,
+            obf: primary.obf.clone(),
+            relay_token,
+            backups: backups.iter().map(|r| r.descriptor()).collect(),
+            punch: primary.punch,
+        })
+    }
 }
 
 impl HubRelayConfig {
@@ -61,6 +133,7 @@ impl HubRelayConfig {
             addr: self.addr.clone(),
             port: self.port,
             obf: self.obf.clone(),
+            punch: self.punch,
         }
     }
 }
@@ -93,6 +166,10 @@ impl ServerConfig {
 pub struct TlsConfig {
     pub cert: String,
     pub key: String,
+    /// Встроенный ACME: хаб сам заказывает пару в `cert`/`key` и продлевает
+    /// её без рестарта. Без блока файлы кладёт кто-то снаружи.
+    #[serde(default)]
+    pub acme: Option<xr_proto::acme::AcmeConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -133,6 +210,34 @@ pub struct InviteDefaults {
     /// `[[servers]]` роутера, в TOML это `[[invites.defaults.servers]]`.
     #[serde(default)]
     pub servers: Vec<xr_proto::preset::PayloadServer>,
+    /// Следующий ключ профиля для ротации, `[invites.defaults.next_key]`.
+    /// Хаб подписывает его в каждый пресет, а после `switch_at` выдаёт его
+    /// же в новых инвайтах вместо `obfuscation_key`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_key: Option<NextKeyConfig>,
+}
+
+/// Ключ ротации в конфиге хаба: момент переключения задаётся в RFC 3339,
+/// на провод уходит unix-секундами.
+#[derive(Debug, Clone, serde::Serialize, Deserialize)]
+pub struct NextKeyConfig {
+    pub obfuscation_key: String,
+    #[serde(default = "default_modifier")]
+    pub modifier: String,
+    #[serde(default)]
+    pub salt: u64,
+    pub switch_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl NextKeyConfig {
+    pub fn to_wire(&self) -> xr_proto::preset::NextKey {
+        xr_proto::preset::NextKey {
+            obfuscation_key: self.obfuscation_key.clone(),
+            modifier: self.modifier.clone(),
+            salt: self.salt,
+            switch_at: self.switch_at.timestamp().max(0) as u64,
+        }
+    }
 }
 
 impl InviteDefaults {
@@ -156,6 +261,7 @@ impl Default for InviteDefaults {
             salt: 0,
             hub_url: String::new(),
             servers: Vec::new(),
+            next_key: None,
         }
     }
 }
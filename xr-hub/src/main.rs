@@ -7,6 +7,7 @@ mod release;
 mod signing;
 mod state;
 mod storage;
+mod tls;
 // Логику build.rs держим отдельным файлом и подключаем её тестами (XR-238).
 #[cfg(test)]
 mod ui_dist;
@@ -460,17 +461,7 @@ async fn main() -> Result<()> {
     match tls_config {
         Some(tls) => {
             tracing::info!("starting xr-hub on {} (TLS)", bind_addr);
-            let rustls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(
-                &tls.cert,
-                &tls.key,
-            )
-            .await
-            .context("loading TLS certificates")?;
-
-            axum_server::bind_rustls(bind_addr, rustls_config)
-                .serve(app.into_make_service())
-                .await
-                .context("running TLS server")?;
+            tls::serve(bind_addr, tls, app).await?;
         }
         None => {
             tracing::warn!("TLS not configured — starting in plain HTTP mode (dev only!)");
@@ -3,7 +3,7 @@ use base64::Engine;
 use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
 use serde::Serialize;
 use xr_proto::config::RoutingConfig;
-use xr_proto::preset::Preset;
+use xr_proto::preset::{NextKey, Preset};
 
 pub struct SigningContext {
     pub signing_key: SigningKey,
@@ -14,6 +14,8 @@ pub struct SigningContext {
 struct CanonicalPreset<'a> {
     description: &'a str,
     name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_key: Option<&'a NextKey>,
     rules: &'a RoutingConfig,
     updated_at: &'a str,
     version: u64,
@@ -71,6 +73,7 @@ fn canonical_json(preset: &Preset) -> Vec<u8> {
     let canonical = CanonicalPreset {
         description: &preset.description,
         name: &preset.name,
+        next_key: preset.next_key.as_ref(),
         rules: &preset.rules,
         updated_at: &preset.updated_at,
         version: preset.version,
@@ -101,6 +104,7 @@ mod tests {
                     geoip: vec![],
                 }],
             },
+            next_key: None,
             signature: None,
         }
     }
@@ -154,6 +158,27 @@ mod tests {
         assert!(!verify_preset(&preset, &ctx.verifying_key()).unwrap());
     }
 
+    /// Следующий ключ подписывается вместе с правилами: подменённый в пути
+    /// ключ увёл бы клиентов на чужой сервер, поэтому подпись обязана его
+    /// покрывать.
+    #[test]
+    fn verify_fails_on_swapped_next_key() {
+        let key = SigningKey::generate(&mut rand::thread_rng());
+        let ctx = SigningContext { signing_key: key };
+        let mut preset = test_preset();
+        preset.next_key = Some(NextKey {
+            obfuscation_key: "a2V5".into(),
+            modifier: "positional_xor_rotate".into(),
+            salt: 7,
+            switch_at: 1_800_000_000,
+        });
+        preset.signature = Some(ctx.sign_preset(&preset));
+        assert!(verify_preset(&preset, &ctx.verifying_key()).unwrap());
+
+        preset.next_key.as_mut().unwrap().obfuscation_key = "b3RoZXI=".into();
+        assert!(!verify_preset(&preset, &ctx.verifying_key()).unwrap());
+    }
+
     #[test]
     fn verify_fails_on_tampered_data() {
         let key = SigningKey::generate(&mut rand::thread_rng());
@@ -29,6 +29,8 @@ pub struct AppState {
     /// Счётчик неверных паролей на ручке браузерного входа (LLD-38 п. 3.2):
     /// перебор гасится задержкой на стороне хаба, а не только на фронте.
     pub web_attempts: crate::api::web::PasswordAttempts,
+    /// Последний доклад браузерного фронта о его сессиях, для админки.
+    pub web_sessions: crate::api::web::SessionBoard,
 }
 
 /// Load state from disk and build AppState.
@@ -41,7 +43,7 @@ pub fn hydrate(config: HubConfig) -> Result<Arc<AppState>> {
     std::fs::create_dir_all(data_dir.join("shares"))?;
     std::fs::create_dir_all(data_dir.join("expose"))?;
 
-    let presets = storage::load_all_presets(data_dir)?;
+    let mut presets = storage::load_all_presets(data_dir)?;
     let invites = storage::load_all_invites(data_dir)?;
     let shares = storage::load_all_shares(data_dir)?;
     let exposes = storage::load_all_exposes(data_dir)?;
@@ -61,6 +63,22 @@ pub fn hydrate(config: HubConfig) -> Result<Arc<AppState>> {
         .map(|s| SigningContext::from_file(&s.private_key))
         .transpose()?;
 
+    // Ротация ключа (см. `NextKeyConfig`): битый ключ в конфиге роняет старт,
+    // а не всплывает у клиентов в день переключения.
+    let next_key = config.invites.defaults.next_key.as_ref().map(|k| k.to_wire());
+    if let Some(key) = &next_key {
+        key.codec(16, 128).map_err(anyhow::Error::msg)?;
+    }
+    let republished = crate::api::presets::sync_next_key(
+        &mut presets,
+        next_key.as_ref(),
+        signing.as_ref(),
+        data_dir,
+    )?;
+    if republished > 0 {
+        tracing::info!("next obfuscation key stamped into {} preset(s)", republished);
+    }
+
     Ok(Arc::new(AppState {
         presets: RwLock::new(presets),
         invites: RwLock::new(invites),
@@ -71,5 +89,6 @@ pub fn hydrate(config: HubConfig) -> Result<Arc<AppState>> {
         signing,
         preset_gen: watch::Sender::new(0),
         web_attempts: Default::default(),
+        web_sessions: Default::default(),
     }))
 }
@@ -0,0 +1,129 @@
+//! TLS хаба: пара из файлов `[tls]`, при `[tls.acme]` заказанная и
+//! продлеваемая самим хабом (HTTP-01 на своём листенере), горячая подмена
+//! после продления и метрика срока на `GET /metrics`.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::extract::{Path as AxPath, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use xr_proto::acme::{AcmeManager, CertStatus, Challenge, Http01Tokens};
+
+use crate::config::TlsConfig;
+
+/// Поднять HTTPS-листенер хаба. С ACME первый заказ идёт до листенера, если
+/// пары ещё нет: без сертификата слушать нечем.
+pub async fn serve(bind_addr: SocketAddr, tls: TlsConfig, app: Router) -> Result<()> {
+    let status = Arc::new(CertStatus::default());
+    let acme = match tls.acme.clone() {
+        Some(cfg) => Some(
+            AcmeManager::new(cfg, &tls.cert, &tls.key, status.clone())
+                .map_err(anyhow::Error::msg)?,
+        ),
+        None => None,
+    };
+    if let Some(m) = &acme {
+        if m.config().challenge == Challenge::Http01 {
+            serve_http01(&m.config().http01_bind, m.http01()).await?;
+        }
+        if !Path::new(&tls.cert).exists() {
+            tracing::info!("ACME: сертификата {} ещё нет, заказ до старта листенера", tls.cert);
+            m.issue().await.map_err(|e| anyhow::anyhow!("первый заказ ACME: {e}"))?;
+        }
+    }
+    match status.observe(Path::new(&tls.cert)) {
+        Ok(not_after) => tracing::info!("сертификат {} годен до {not_after}", tls.cert),
+        Err(e) => tracing::warn!("срок сертификата не прочитан: {e}"),
+    }
+
+    let rustls_config = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+        .await
+        .context("loading TLS certificates")?;
+    if let Some(m) = acme {
+        let rc = rustls_config.clone();
+        tokio::spawn(m.run(move || {
+            let (rc, cert, key) = (rc.clone(), tls.cert.clone(), tls.key.clone());
+            async move {
+                match rc.reload_from_pem_file(&cert, &key).await {
+                    Ok(()) => tracing::info!("TLS: новый сертификат подхвачен без рестарта"),
+                    Err(e) => tracing::error!("TLS: новый сертификат не подхвачен: {e}"),
+                }
+            }
+        }));
+    }
+
+    let app = app.merge(metrics_router(status));
+    axum_server::bind_rustls(bind_addr, rustls_config)
+        .serve(app.into_make_service())
+        .await
+        .context("running TLS server")
+}
+
+/// Листенер HTTP-01: отдаёт только токены, которые сейчас ждёт CA, всё
+/// прочее на нём `404`.
+async fn serve_http01(bind: &str, tokens: Http01Tokens) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("листенер HTTP-01 на {bind}"))?;
+    tracing::info!("ACME: HTTP-01 на {bind}");
+    let app = Router::new()
+        .route(&format!("{}{{token}}", Http01Tokens::PATH_PREFIX), get(http01_token))
+        .with_state(tokens);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("листенер HTTP-01 умер: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn http01_token(State(tokens): State<Http01Tokens>, AxPath(token): AxPath<String>) -> Response {
+    match tokens.get(&token) {
+        Some(key_auth) => key_auth.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// `GET /metrics` - срок сертификата и продления в формате Prometheus. Срок
+/// сертификата и так виден любому, кто откроет хаб, поэтому без авторизации.
+fn metrics_router(status: Arc<CertStatus>) -> Router {
+    Router::new().route(
+        "/metrics",
+        get(move || async move {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                status.render("xr-hub"),
+            )
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn metrics_and_http01_answer() {
+        let status = Arc::new(CertStatus::default());
+        let resp = metrics_router(status)
+            .oneshot(axum::http::Request::get("/metrics").body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), 1 << 16).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("xr_acme_failures_total{service=\"xr-hub\"} 0"));
+
+        let tokens = Http01Tokens::default();
+        assert_eq!(
+            http01_token(State(tokens), AxPath("nope".into())).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
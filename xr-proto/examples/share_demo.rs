@@ -69,6 +69,7 @@ fn main() {
                 mtime: 1_750_000_000,
                 sha256: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".into(),
                 meta: None,
+                preview: None,
             },
             ShareManifestEntry {
                 path: "notes/readme.txt".into(),
@@ -84,8 +85,10 @@ fn main() {
                     published: "2026-07-31".into(),
                     title: "Настоящий заголовок".into(),
                 }),
+                preview: None,
             },
         ],
+        version: 0,
     };
     println!("=== ShareManifest (agent-served — path/size/mtime/sha256) ===");
     println!("{}\n", pretty(&manifest));
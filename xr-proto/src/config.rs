@@ -59,6 +59,12 @@ impl ServerEntry {
     pub fn display_name(&self) -> &str {
         if self.name.is_empty() { &self.address } else { &self.name }
     }
+
+    /// Есть ли у записи свой ключ (`key`/`salt`/`modifier`) поверх общего
+    /// `[obfuscation]`.
+    pub fn has_key_override(&self) -> bool {
+        self.key.is_some() || self.salt.is_some() || self.modifier.is_some()
+    }
 }
 
 impl ClientConfig {
@@ -87,7 +93,7 @@ impl ClientConfig {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct ObfuscationConfig {
     pub key: String,
     #[serde(default = "default_modifier")]
@@ -98,6 +104,50 @@ pub struct ObfuscationConfig {
     pub padding_min: u8,
     #[serde(default = "default_padding_max")]
     pub padding_max: u8,
+    /// Только сервер: после этого момента (unix-секунды) `key` больше не
+    /// принимается. Дата переключения ротации: к ней клиенты уже получили
+    /// новый ключ пресетом, а отстающие упрутся в fallback, как чужие.
+    #[serde(default)]
+    pub valid_until: Option<u64>,
+    /// Только сервер: ключи, которые принимаются наряду с `key` на время
+    /// ротации (клиенты переезжают на новый ключ не разом). Каждый коннект
+    /// живёт на том ключе, которым прислан его первый кадр.
+    #[serde(default)]
+    pub rotation: Vec<RotationKey>,
+}
+
+/// Ключ ротации со своим окном действия. Границы в unix-секундах, любая
+/// может отсутствовать: без `valid_from` ключ принимается сразу, без
+/// `valid_until` бессрочно.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RotationKey {
+    pub key: String,
+    #[serde(default = "default_modifier")]
+    pub modifier: String,
+    #[serde(default = "default_salt")]
+    pub salt: u64,
+    #[serde(default)]
+    pub valid_from: Option<u64>,
+    #[serde(default)]
+    pub valid_until: Option<u64>,
+}
+
+impl RotationKey {
+    /// Принимается ли ключ в момент `now`: `valid_from` включительно,
+    /// `valid_until` уже нет.
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.valid_from.is_none_or(|from| now >= from)
+            && self.valid_until.is_none_or(|until| now < until)
+    }
+}
+
+/// Текущее время в unix-секундах: шкала окон ключей ротации и `switch_at`
+/// пресета, общая для сервера и клиентов.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -153,6 +203,17 @@ pub struct ClientSettings {
     /// head-of-line blocking when one TCP enters slow-start or recovery.
     #[serde(default = "default_mux_pool_size")]
     pub mux_pool_size: usize,
+    /// Multipath: bulk of every stream is striped across all pool tunnels
+    /// (sequence numbers, reordering on the far side), so one large download
+    /// is not bounded by a single TCP's congestion window. Needs a server that
+    /// negotiates the stripe capability; older ones silently get plain mux.
+    #[serde(default)]
+    pub mux_stripe: bool,
+    /// How the server pool picks the active server: `priority` (config order,
+    /// backups only while the primary is down), `lowest_latency` or
+    /// `latency_with_hysteresis` (by measured RTT).
+    #[serde(default = "default_server_policy")]
+    pub server_policy: String,
     /// Drop QUIC (UDP/443) from LAN so browsers fall back to TCP/443,
     /// which the TPROXY redirect can intercept. Without this, any site
     /// advertising h3 bypasses the proxy entirely over UDP.
@@ -170,6 +231,8 @@ impl Default for ClientSettings {
             bypass_ips: vec![],
             bypass_rules: vec![],
             mux_pool_size: default_mux_pool_size(),
+            mux_stripe: false,
+            server_policy: default_server_policy(),
             block_quic: true,
         }
     }
@@ -282,7 +345,7 @@ pub struct UdpRelayClientConfig {
     pub keepalive_interval_sec: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct UdpRelayServerConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -297,14 +360,14 @@ pub struct UdpRelayServerConfig {
     pub flow_timeout_sec: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct ServerListenConfig {
     #[serde(default = "default_listen_addr")]
     pub listen: String,
     pub port: u16,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct LimitsConfig {
     #[serde(default = "default_max_connections")]
     pub max_connections: u32,
@@ -333,7 +396,7 @@ impl Default for LimitsConfig {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct FallbackConfig {
     #[serde(default)]
     pub enabled: bool,
@@ -350,7 +413,7 @@ impl Default for FallbackConfig {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct LoggingConfig {
     #[serde(default = "default_log_level")]
     pub level: String,
@@ -441,6 +504,9 @@ fn default_refresh_interval() -> u64 {
 fn default_mux_pool_size() -> usize {
     4
 }
+fn default_server_policy() -> String {
+    "priority".into()
+}
 
 // ── Loaders ──────────────────────────────────────────────────────────
 
@@ -1,5 +1,13 @@
 pub mod accept;
+/// Built-in ACME for the server-side services (xr-hub, xr-web): order, renewal
+/// and expiry metrics. Gated with `acme`; clients and the agent never pull it.
+#[cfg(feature = "acme")]
+pub mod acme;
 pub mod app_update;
+/// Content-defined chunking for block-level transfer of changed share files.
+/// Goes with `share` (agent and consumer must cut alike), and in tests.
+#[cfg(any(feature = "share", test))]
+pub mod chunks;
 pub mod config;
 pub mod invite_url;
 pub mod mux;
@@ -7,6 +15,11 @@ pub mod mux_pool;
 pub mod obfuscation;
 pub mod preset;
 pub mod protocol;
+/// Direct consumer <-> agent path by UDP hole punching, the relay as rendezvous.
+/// The reflector/wire part goes with `share` (the relay needs it); the QUIC
+/// link itself sits behind `punch`.
+#[cfg(any(feature = "share", test))]
+pub mod punch;
 /// Consumer-side relay client (LLD-23). Gated with the `share` feature (and in
 /// tests): only file-sharing consumers/agents pull it, never the OpenWRT client.
 #[cfg(any(feature = "share", test))]
@@ -16,13 +16,19 @@
 //! сливает их одним biased-select'ом с приоритетом контрольного, поэтому
 //! ConnectAck нового стрима не залипает за мегабайтами Data чужих загрузок
 //! (head-of-line, корень XR-086).
+//!
+//! Multipath (striping): несколько туннелей одного пула можно связать в группу
+//! полос ([`StripeGroup`]). У членов группы общие карта стримов и балк-очередь,
+//! Data и Close любого стрима уходят тем туннелем, чей writer свободен первым,
+//! с номером в стриме, а приёмник склеивает их по номерам. Один большой
+//! download так не упирается в окно перегрузки одного TCP.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
 use std::io;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU8, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Weak};
 use std::task::{Context, Poll};
 
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
@@ -30,7 +36,8 @@ use tokio::sync::{mpsc, Mutex, Notify};
 use tokio::time::Duration;
 
 use crate::protocol::{
-    decode_mux_payload, encode_mux_payload, Codec, Command, Frame, TargetAddr,
+    decode_datagram, decode_mux_payload, encode_datagram, encode_mux_payload, Codec, Command,
+    Frame, TargetAddr,
     CLOSE_REASON_CONNECT_FAIL, CLOSE_REASON_RESOLVE_FAIL, HEADER_LEN, MAX_PADDING_LEN,
     MAX_PAYLOAD_LEN, NONCE_LEN,
 };
@@ -83,6 +90,22 @@ const MUX_PROTOCOL_VERSION: u8 = 1;
 /// Бит capability в байте флагов MuxInit/MuxInitAck: пир умеет оконный flow
 /// control стримов (WindowUpdate, LLD-27).
 const MUX_FLAG_WINDOW: u8 = 0x01;
+/// Бит capability: пир релеит датаграммы (UdpAssociate/UdpData), UDP идёт тем
+/// же туннелем, а не отдельным relay-портом.
+const MUX_FLAG_UDP: u8 = 0x02;
+/// Бит capability: пир склеивает стримы из полос (StripeData/StripeClose)
+/// разных туннелей одной группы. Клиент ставит его только вместе с id группы
+/// в MuxInit.
+const MUX_FLAG_STRIPE: u8 = 0x04;
+/// Длина id группы полос: идёт в MuxInit сразу за байтом флагов.
+pub const STRIPE_GROUP_ID_LEN: usize = 16;
+/// Номер в StripeClose стрима, по которому отправитель не поставил ни
+/// ConnectAck, ни Data (отказ `send_stream_close`, пустой стрим): ждать
+/// приёмнику нечего, стрим снимается сразу.
+const STRIPE_SEQ_REFUSED: u32 = u32::MAX;
+/// Потолок полос, ждущих пропущенный номер. Больше в полёте не бывает при
+/// честном пире: окно стрима (LLD-27) держит их в пределах мегабайта.
+const STRIPE_REORDER_MAX: usize = STREAM_CHANNEL_SIZE;
 /// Начальное окно приёма стрима (LLD-27): столько байт Data пир шлёт без
 /// возврата кредита. Покрывает BDP наших линков (~640 КБ при 50 Мбит/с и RTT
 /// 100мс) и режет память на медленный стрим до 1 МиБ вместо полного канала
@@ -101,23 +124,43 @@ const WINDOW_UPDATE_THRESHOLD: u32 = STREAM_RECV_WINDOW / 2;
 pub struct MuxCaps {
     /// Оконный flow control стримов: слать WindowUpdate и уважать окно пира.
     pub window: bool,
+    /// Датаграммы поверх mux (UdpAssociate/UdpData). Без бита клиент уходит
+    /// на отдельный UDP relay.
+    pub udp: bool,
+    /// Полосы multipath (StripeData/StripeClose): туннель входит в группу,
+    /// стримы склеиваются по номерам со всех её туннелей.
+    pub stripe: bool,
 }
 
 impl MuxCaps {
     /// Что умеет эта сборка; уходит в хендшейк, пересекается с флагами пира.
-    pub const LOCAL: MuxCaps = MuxCaps { window: true };
+    pub const LOCAL: MuxCaps = MuxCaps { window: true, udp: true, stripe: true };
+
+    /// Что подтверждает акцептор по умолчанию (`mux_handshake_server`): только
+    /// стримы. Датаграммы релеит не всякий акцептор (xr-relay, агент шары их не
+    /// обслуживают), поэтому xr-server включает их явно через
+    /// `mux_handshake_server_with`. Полосы тоже только у xr-server.
+    pub const STREAMS: MuxCaps = MuxCaps { window: true, udp: false, stripe: false };
 
     fn to_flags(self) -> u8 {
+        let mut flags = 0;
         if self.window {
-            MUX_FLAG_WINDOW
-        } else {
-            0
+            flags |= MUX_FLAG_WINDOW;
+        }
+        if self.udp {
+            flags |= MUX_FLAG_UDP;
         }
+        if self.stripe {
+            flags |= MUX_FLAG_STRIPE;
+        }
+        flags
     }
 
     fn from_flags(flags: u8) -> Self {
         MuxCaps {
             window: flags & MUX_FLAG_WINDOW != 0,
+            udp: flags & MUX_FLAG_UDP != 0,
+            stripe: flags & MUX_FLAG_STRIPE != 0,
         }
     }
 }
@@ -179,8 +222,15 @@ impl RelayWindow {
 /// Resolve-сбои сравниваются только с доменными успехами: при мёртвом DNS
 /// IP-таргеты (например, CIDR-роутинг Telegram) продолжают работать и не
 /// должны маскировать полностью лежащие домены.
+///
+/// Здесь же копятся замеры линка для выбора сервера по задержке: сглаженный
+/// RTT (эхо keepalive-Ping и свежие пробы `MuxPool::probe_fresh`) и счётчик
+/// принятых туннелем байт, из которого `ServerPool` считает пропускную.
 pub struct RelayHealth {
     inner: std::sync::Mutex<RelayWindow>,
+    /// Сглаженный RTT в микросекундах, 0 значит замеров ещё не было.
+    srtt_us: AtomicU64,
+    rx_bytes: AtomicU64,
 }
 
 impl RelayHealth {
@@ -191,9 +241,44 @@ impl RelayHealth {
                 cur: RelayBucket::default(),
                 prev: RelayBucket::default(),
             }),
+            srtt_us: AtomicU64::new(0),
+            rx_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Замер RTT. Сглаживание как у SRTT в TCP (вес нового замера 1/8):
+    /// одиночный выброс под нагрузкой не перекидывает выбор сервера.
+    pub fn record_rtt(&self, rtt: Duration) {
+        let sample = (rtt.as_micros() as u64).max(1);
+        let _ = self
+            .srtt_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |cur| {
+                Some(if cur == 0 { sample } else { cur - cur / 8 + sample / 8 })
+            });
+    }
+
+    /// Сглаженный RTT; `None`, пока не было ни одного замера.
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.srtt_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
         }
     }
 
+    /// Забыть RTT: замеры на прежней сети после её смены ничего не значат.
+    pub fn reset_rtt(&self) {
+        self.srtt_us.store(0, Ordering::Relaxed);
+    }
+
+    pub fn record_rx(&self, n: usize) {
+        self.rx_bytes.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Всего байт, принятых туннелями сервера (монотонный счётчик).
+    pub fn rx_bytes(&self) -> u64 {
+        self.rx_bytes.load(Ordering::Relaxed)
+    }
+
     pub fn record_success(&self, domain: bool) {
         let mut w = self.inner.lock().unwrap();
         w.rotate();
@@ -912,13 +997,129 @@ impl Drop for MuxWriteHalf {
     }
 }
 
+// ── MuxDatagram: UDP over mux ───────────────────────────────────────
+
+/// Потолок тела UdpData: лимит payload кодека минус префикс stream_id.
+/// Адрес едет внутри тела, поэтому датаграмма чуть меньше этого числа.
+const DATAGRAM_BODY_MAX: usize = MAX_PAYLOAD_LEN - 4;
+
+/// Датаграммная ассоциация внутри mux (UdpAssociate). Каждый UdpData несёт
+/// адрес: на отправке это получатель, на приёме отправитель ответа, так что
+/// одна ассоциация обслуживает любой набор адресатов, как обычный UDP-сокет.
+///
+/// Семантика UDP, не TCP: окна нет, кадр при полной очереди writer'а
+/// выбрасывается, а не ждёт места, и на приёме переполнение канала роняет
+/// датаграмму, а не убивает ассоциацию. Drop шлёт Close.
+#[derive(Debug)]
+pub struct MuxDatagram {
+    rx: mpsc::Receiver<Vec<u8>>,
+    sender: MuxDatagramSender,
+}
+
+impl MuxDatagram {
+    /// Следующая датаграмма: (адрес отправителя, тело). None = ассоциацию
+    /// закрыл пир или mux умер. Битые кадры пропускаются.
+    pub async fn recv_from(&mut self) -> Option<(TargetAddr, Vec<u8>)> {
+        loop {
+            let body = self.rx.recv().await?;
+            match decode_datagram(&body) {
+                Ok((addr, data)) => return Some((addr, data.to_vec())),
+                Err(e) => tracing::debug!(
+                    "mux datagram {}: bad UdpData: {}",
+                    self.sender.stream_id,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Отправить датаграмму адресату, см. [`MuxDatagramSender::send_to`].
+    pub fn send_to(&self, dst: &TargetAddr, data: &[u8]) -> io::Result<()> {
+        self.sender.send_to(dst, data)
+    }
+
+    /// Отправляющая половина для отдельного таска (приём живёт в recv_from).
+    /// Close уходит только с дропом самой ассоциации.
+    pub fn sender(&self) -> MuxDatagramSender {
+        self.sender.clone()
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.sender.stream_id
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.sender.alive.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MuxDatagram {
+    fn drop(&mut self) {
+        if self.sender.alive.load(Ordering::Relaxed) {
+            close_on_drop(&self.sender.writer_tx, self.sender.stream_id);
+        }
+    }
+}
+
+/// Клонируемая отправка датаграмм одной ассоциации.
+#[derive(Debug, Clone)]
+pub struct MuxDatagramSender {
+    stream_id: u32,
+    writer_tx: mpsc::Sender<OutFrame>,
+    alive: Arc<AtomicBool>,
+}
+
+impl MuxDatagramSender {
+    /// Поставить датаграмму в балк-план. Не ждёт: полная очередь writer'а
+    /// значит затор туннеля, и датаграмма теряется, как потерялась бы в сети
+    /// (Ok). Err: mux мёртв (BrokenPipe) или датаграмма не влезает в кадр
+    /// (InvalidInput).
+    pub fn send_to(&self, dst: &TargetAddr, data: &[u8]) -> io::Result<()> {
+        if !self.alive.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "mux connection dead"));
+        }
+        let payload = encode_datagram(dst, data)?;
+        if payload.len() > DATAGRAM_BODY_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram too large for mux frame",
+            ));
+        }
+        match self.writer_tx.try_send(OutFrame {
+            stream_id: self.stream_id,
+            command: Command::UdpData,
+            payload,
+        }) {
+            Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "mux writer closed",
+            )),
+        }
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+}
+
 // ── Multiplexer ─────────────────────────────────────────────────────
 
-/// Notification about a new incoming stream (Connect from remote).
+/// Что открывает входящий стрим.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    /// Connect: байтовый стрим до TCP-таргета из payload.
+    Connect,
+    /// UdpAssociate: датаграммная ассоциация, payload пуст.
+    Datagram,
+}
+
+/// Notification about a new incoming stream (Connect or UdpAssociate from remote).
 #[derive(Debug)]
 pub struct NewStream {
     pub stream_id: u32,
     pub payload: Vec<u8>,
+    pub kind: StreamKind,
 }
 
 /// Приёмная сторона зарегистрированного стрима плюс состояние для учёта
@@ -934,6 +1135,28 @@ struct StreamEntry {
     window: Arc<SendWindow>,
     /// Причина Close пира, разделяется с MuxStream (см. одноимённое поле там).
     close_reason: Arc<AtomicU8>,
+    /// Склейка полос: есть только у стримов туннеля из группы полос.
+    reorder: Option<Reorder>,
+}
+
+/// Склейка стрима из полос (multipath). Полосы едут разными туннелями группы
+/// и приходят вперемешку, потребитель получает их строго по номерам. Close
+/// тоже пронумерован и снимает стрим, только когда до него дошла очередь,
+/// иначе он обогнал бы хвост Data с медленного туннеля (тот же XR-241, что
+/// держит Close в балк-плане).
+#[derive(Debug, Default)]
+struct Reorder {
+    /// Номер полосы, которую потребитель ждёт следующей.
+    next: u32,
+    /// Полосы, пришедшие раньше своей очереди.
+    pending: BTreeMap<u32, Vec<u8>>,
+    /// Close пира: номер, на котором стрим снимается, и причина.
+    close_at: Option<(u32, Vec<u8>)>,
+    /// ConnectAck уже отдан потребителю. ConnectAck идёт контрольным планом
+    /// своего туннеля, а первые полосы могут обогнать его по соседнему: до
+    /// подтверждения они ждут в `pending`, иначе `open_registered` принял бы
+    /// полосу за ConnectAck.
+    open: bool,
 }
 
 impl Drop for StreamEntry {
@@ -951,7 +1174,9 @@ pub struct Multiplexer {
     /// Контрольный план (приоритет в writer'е): Connect/ConnectAck/Ping/Pong.
     ctrl_tx: mpsc::Sender<OutFrame>,
     streams: Arc<Mutex<HashMap<u32, StreamEntry>>>,
-    next_stream_id: AtomicU32,
+    /// Счётчик id стримов; у членов группы полос он общий, чтобы id не
+    /// совпадали в общей карте стримов.
+    next_stream_id: Arc<AtomicU32>,
     alive: Arc<AtomicBool>,
     _close_notify: Arc<Notify>,
     /// Channel for incoming Connect frames for unregistered stream_ids.
@@ -966,6 +1191,8 @@ pub struct Multiplexer {
     shutdown_notify: Arc<Notify>,
     /// Возможности, согласованные хендшейком (LLD-27).
     caps: MuxCaps,
+    /// Группа полос, в которую входит туннель (multipath); None = одиночный.
+    stripe: Option<Arc<StripeGroup>>,
 }
 
 impl Multiplexer {
@@ -976,7 +1203,7 @@ impl Multiplexer {
     where
         S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
     {
-        Self::new_inner(stream, codec, 1, None, caps) // client uses odd stream IDs
+        Self::new_inner(stream, codec, 1, None, caps, None) // client uses odd stream IDs
     }
 
     /// Клиентский мультиплексор с учётом исходов relay в общем здоровье пула
@@ -991,7 +1218,23 @@ impl Multiplexer {
     where
         S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
     {
-        Self::new_inner(stream, codec, 1, Some(health), caps)
+        Self::new_inner(stream, codec, 1, Some(health), caps, None)
+    }
+
+    /// Клиентский туннель в группе полос (multipath). Группа берётся, только
+    /// если хендшейк согласовал `stripe`; иначе это обычный
+    /// [`Multiplexer::new_client_tracked`].
+    pub fn new_client_striped<S>(
+        stream: S,
+        codec: Codec,
+        health: Arc<RelayHealth>,
+        caps: MuxCaps,
+        group: Arc<StripeGroup>,
+    ) -> Arc<Self>
+    where
+        S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
+    {
+        Self::new_inner(stream, codec, 1, Some(health), caps, Some(group))
     }
 
     /// Create a server-side multiplexer over an established TCP connection.
@@ -999,7 +1242,20 @@ impl Multiplexer {
     where
         S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
     {
-        Self::new_inner(stream, codec, 2, None, caps) // server uses even stream IDs
+        Self::new_inner(stream, codec, 2, None, caps, None) // server uses even stream IDs
+    }
+
+    /// Серверный туннель в группе полос клиента (см. [`StripeRegistry`]).
+    pub fn new_server_striped<S>(
+        stream: S,
+        codec: Codec,
+        caps: MuxCaps,
+        group: Arc<StripeGroup>,
+    ) -> Arc<Self>
+    where
+        S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
+    {
+        Self::new_inner(stream, codec, 2, None, caps, Some(group))
     }
 
     fn new_inner<S>(
@@ -1008,14 +1264,34 @@ impl Multiplexer {
         first_stream_id: u32,
         relay_health: Option<Arc<RelayHealth>>,
         caps: MuxCaps,
+        group: Option<Arc<StripeGroup>>,
     ) -> Arc<Self>
     where
         S: AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
     {
-        let (writer_tx, writer_rx) = mpsc::channel::<OutFrame>(WRITER_CHANNEL_SIZE);
         let (ctrl_tx, ctrl_rx) = mpsc::channel::<OutFrame>(CTRL_CHANNEL_SIZE);
-        let streams: Arc<Mutex<HashMap<u32, StreamEntry>>> =
-            Arc::new(Mutex::new(HashMap::new()));
+        // Член группы полос берёт у неё карту стримов, счётчик id и балк-очередь:
+        // кадр любого стрима группы найдёт свою запись, каким бы туннелем ни
+        // пришёл, а Data уйдут тем writer'ом, что свободен первым.
+        let group = group.filter(|_| caps.stripe);
+        let caps = MuxCaps { stripe: group.is_some(), ..caps };
+        let (writer_tx, bulk, streams, next_stream_id) = match &group {
+            Some(g) => (
+                g.bulk_tx.clone(),
+                BulkPlane::Striped(g.clone()),
+                g.streams.clone(),
+                g.next_stream_id.clone(),
+            ),
+            None => {
+                let (writer_tx, writer_rx) = mpsc::channel::<OutFrame>(WRITER_CHANNEL_SIZE);
+                (
+                    writer_tx,
+                    BulkPlane::Own(writer_rx),
+                    Arc::new(Mutex::new(HashMap::new())),
+                    Arc::new(AtomicU32::new(first_stream_id)),
+                )
+            }
+        };
         let alive = Arc::new(AtomicBool::new(true));
         let close_notify = Arc::new(Notify::new());
         let shutdown_notify = Arc::new(Notify::new());
@@ -1031,13 +1307,25 @@ impl Multiplexer {
             let close_notify = close_notify.clone();
             let codec = codec.clone();
             let reader_ctrl = ctrl_tx.clone();
+            let group = group.clone();
+            let shutdown_notify = shutdown_notify.clone();
             tokio::spawn(async move {
-                if let Err(e) = reader_task(read_half, codec, streams.clone(), reader_ctrl, new_stream_tx, relay_health).await {
+                if let Err(e) = reader_task(read_half, codec, streams.clone(), reader_ctrl, new_stream_tx, relay_health, caps).await {
                     tracing::debug!("mux reader ended: {}", e);
                 }
                 alive.store(false, Ordering::Relaxed);
-                // Close all stream channels.
-                streams.lock().await.clear();
+                // Close all stream channels. В группе полос карта общая, и
+                // снимаются стримы всей группы: часть их полос ушла этим
+                // туннелем и уже не придёт, склейка встала бы на дыре навсегда.
+                match group {
+                    Some(group) => {
+                        group.fail_streams().await;
+                        // Writer мёртвого туннеля не должен больше забирать кадры из
+                        // общей очереди: они ушли бы в сокет, который никто не читает.
+                        shutdown_notify.notify_one();
+                    }
+                    None => streams.lock().await.clear(),
+                }
                 close_notify.notify_waiters();
             });
         }
@@ -1050,11 +1338,18 @@ impl Multiplexer {
             let alive = alive.clone();
             let codec = codec.clone();
             let shutdown_notify = shutdown_notify.clone();
+            let group = group.clone();
             tokio::spawn(async move {
-                if let Err(e) = writer_task(write_half, codec, ctrl_rx, writer_rx, shutdown_notify).await {
+                let res = writer_task(write_half, codec, ctrl_rx, bulk, shutdown_notify).await;
+                alive.store(false, Ordering::Relaxed);
+                if let Err(e) = res {
                     tracing::debug!("mux writer ended: {}", e);
+                    // Упавший writer унёс кадры, уже взятые из общей очереди
+                    // группы: у пира на их номерах встала бы склейка.
+                    if let Some(group) = group {
+                        group.fail_streams().await;
+                    }
                 }
-                alive.store(false, Ordering::Relaxed);
             });
         }
 
@@ -1062,15 +1357,21 @@ impl Multiplexer {
             writer_tx,
             ctrl_tx,
             streams,
-            next_stream_id: AtomicU32::new(first_stream_id),
+            next_stream_id,
             alive,
             _close_notify: close_notify,
             new_stream_rx: Mutex::new(Some(new_stream_rx)),
             shutdown_notify,
             caps,
+            stripe: group,
         })
     }
 
+    /// Склейка для новой записи стрима: только у члена группы полос.
+    fn new_reorder(&self, open: bool) -> Option<Reorder> {
+        self.stripe.as_ref().map(|_| Reorder { open, ..Default::default() })
+    }
+
     /// Окно отправки нового стрима: при выключенном flow control кредит
     /// бесконечен, путь отправки не ветвится (LLD-27).
     fn new_send_window(&self) -> Arc<SendWindow> {
@@ -1109,6 +1410,7 @@ impl Multiplexer {
                 domain: false,
                 window: window.clone(),
                 close_reason: close_reason.clone(),
+                reorder: self.new_reorder(true),
             },
         );
 
@@ -1125,6 +1427,35 @@ impl Multiplexer {
         }
     }
 
+    /// Register a datagram association opened by the remote side (UdpAssociate,
+    /// server-side use). ConnectAck шлёт вызывающий, как и для стрима.
+    pub async fn register_datagram(self: &Arc<Self>, stream_id: u32) -> MuxDatagram {
+        let (data_tx, data_rx) = mpsc::channel(STREAM_CHANNEL_SIZE);
+        self.streams.lock().await.insert(
+            stream_id,
+            StreamEntry {
+                tx: data_tx,
+                got_data: false,
+                domain: false,
+                window: SendWindow::new(i64::MAX),
+                close_reason: Arc::new(AtomicU8::new(0)),
+                reorder: self.new_reorder(true),
+            },
+        );
+        self.datagram_handle(stream_id, data_rx)
+    }
+
+    fn datagram_handle(&self, stream_id: u32, rx: mpsc::Receiver<Vec<u8>>) -> MuxDatagram {
+        MuxDatagram {
+            rx,
+            sender: MuxDatagramSender {
+                stream_id,
+                writer_tx: self.writer_tx.clone(),
+                alive: self.alive.clone(),
+            },
+        }
+    }
+
     /// Send a raw frame (used for ConnectAck, Ping, Pong).
     ///
     /// Кадр уходит по приоритетному контрольному каналу и НЕ встаёт в хвост за
@@ -1141,6 +1472,11 @@ impl Multiplexer {
     /// writer-таск (TCP send-буфер полон, сокет не принимает даже контроль),
     /// логируем WARN с командой. Поведение то же (дожидаемся), только лог.
     pub async fn send_frame(&self, stream_id: u32, command: Command, payload: Vec<u8>) -> io::Result<()> {
+        if command == Command::ConnectAck {
+            if let Some(group) = &self.stripe {
+                group.note_open(stream_id);
+            }
+        }
         let fut = self
             .ctrl_tx
             .send(OutFrame { stream_id, command, payload });
@@ -1182,6 +1518,11 @@ impl Multiplexer {
         self.alive.load(Ordering::Relaxed)
     }
 
+    /// Возможности, согласованные хендшейком.
+    pub fn caps(&self) -> MuxCaps {
+        self.caps
+    }
+
     /// Force-shutdown this Multiplexer. Marks it dead, wakes the writer
     /// task, which does an explicit writer.shutdown() (FIN) -> remote gets EOF and
     /// reconnects. Use this when the pool decides a slot is zombie (server-state
@@ -1197,6 +1538,165 @@ impl Multiplexer {
     }
 }
 
+// ── Stripe group (multipath) ────────────────────────────────────────
+
+/// Группа полос: туннели одного клиента к одному серверу, по которым стримы
+/// раскладываются кадрами. Члены делят карту стримов, счётчик id и балк-очередь;
+/// writer каждого туннеля забирает из общей очереди следующий кадр, как только
+/// освободился, поэтому быстрый путь сам берёт на себя больше, а деградировавший
+/// меньше. Номер кадру выдаётся в момент забора, под локом очереди, так что
+/// нумерация повторяет порядок отправки стрима.
+///
+/// Склеивать может только одна точка выхода: группы живут внутри одного
+/// сервера, полосы между разными VPS не раскладываются.
+pub struct StripeGroup {
+    id: [u8; STRIPE_GROUP_ID_LEN],
+    streams: Arc<Mutex<HashMap<u32, StreamEntry>>>,
+    next_stream_id: Arc<AtomicU32>,
+    bulk_tx: mpsc::Sender<OutFrame>,
+    bulk_rx: Mutex<mpsc::Receiver<OutFrame>>,
+    /// Следующий номер полосы по стриму. Запись появляется с первой полосой
+    /// или с ConnectAck (`note_open`) и уходит с Close.
+    seqs: std::sync::Mutex<HashMap<u32, u32>>,
+}
+
+impl StripeGroup {
+    fn with_id(id: [u8; STRIPE_GROUP_ID_LEN], first_stream_id: u32) -> Arc<Self> {
+        let (bulk_tx, bulk_rx) = mpsc::channel(WRITER_CHANNEL_SIZE);
+        Arc::new(Self {
+            id,
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            next_stream_id: Arc::new(AtomicU32::new(first_stream_id)),
+            bulk_tx,
+            bulk_rx: Mutex::new(bulk_rx),
+            seqs: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Новая клиентская группа со случайным id.
+    pub fn new_client() -> Arc<Self> {
+        Self::with_id(rand::random(), 1)
+    }
+
+    pub fn id(&self) -> &[u8; STRIPE_GROUP_ID_LEN] {
+        &self.id
+    }
+
+    /// Стрим подтверждён (ConnectAck поставлен в очередь): его будущий Close
+    /// нумеруется, а не уходит отказом [`STRIPE_SEQ_REFUSED`]. Вызывается при
+    /// постановке ConnectAck, раньше любого Close того же стрима.
+    fn note_open(&self, stream_id: u32) {
+        self.seqs.lock().unwrap().entry(stream_id).or_insert(0);
+    }
+
+    /// Снять все стримы группы у себя и у пира: туннель группы умер, и полосы,
+    /// которые он забрал из общей очереди или не дочитал, уже не придут.
+    /// StripeClose с отказным номером едет уцелевшими туннелями и снимает
+    /// стрим у пира сразу, не дожидаясь пропавших номеров. Полная очередь
+    /// теряет его, как и [`close_on_drop`].
+    async fn fail_streams(&self) {
+        let ids: Vec<u32> = self.streams.lock().await.drain().map(|(id, _)| id).collect();
+        self.seqs.lock().unwrap().clear();
+        for stream_id in ids {
+            let _ = self.bulk_tx.try_send(OutFrame {
+                stream_id,
+                command: Command::StripeClose,
+                payload: STRIPE_SEQ_REFUSED.to_be_bytes().to_vec(),
+            });
+        }
+    }
+
+    /// Следующий балк-кадр группы для writer'а одного из туннелей. Отмена
+    /// безопасна: `recv` отменяется без потери, нумерация идёт уже после него
+    /// без точек ожидания.
+    async fn next_frame(&self) -> Option<OutFrame> {
+        let mut rx = self.bulk_rx.lock().await;
+        let mut frame = rx.recv().await?;
+        self.sequence(&mut frame);
+        Some(frame)
+    }
+
+    /// Data и Close становятся полосами с номером; остальное (UdpData, кредит
+    /// окна запасным путём) порядка внутри стрима не требует и едет как есть.
+    fn sequence(&self, frame: &mut OutFrame) {
+        let mut seqs = self.seqs.lock().unwrap();
+        let (command, seq) = match frame.command {
+            Command::Data => {
+                let next = seqs.entry(frame.stream_id).or_insert(0);
+                let seq = *next;
+                *next += 1;
+                (Command::StripeData, seq)
+            }
+            Command::Close => (
+                Command::StripeClose,
+                seqs.remove(&frame.stream_id).unwrap_or(STRIPE_SEQ_REFUSED),
+            ),
+            _ => return,
+        };
+        let mut payload = Vec::with_capacity(4 + frame.payload.len());
+        payload.extend_from_slice(&seq.to_be_bytes());
+        payload.extend_from_slice(&frame.payload);
+        frame.payload = payload;
+        frame.command = command;
+    }
+}
+
+/// Серверный реестр групп полос: туннели с одним id группы из MuxInit
+/// собираются в одну [`StripeGroup`]. Держит слабые ссылки, группа умирает
+/// вместе с последним своим туннелем.
+#[derive(Default)]
+pub struct StripeRegistry {
+    groups: std::sync::Mutex<HashMap<[u8; STRIPE_GROUP_ID_LEN], Weak<StripeGroup>>>,
+}
+
+impl StripeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Группа для туннеля с этим id: живая или новая.
+    pub fn join(&self, id: [u8; STRIPE_GROUP_ID_LEN]) -> Arc<StripeGroup> {
+        let mut groups = self.groups.lock().unwrap();
+        groups.retain(|_, g| g.strong_count() > 0);
+        if let Some(group) = groups.get(&id).and_then(Weak::upgrade) {
+            return group;
+        }
+        let group = StripeGroup::with_id(id, 2);
+        groups.insert(id, Arc::downgrade(&group));
+        group
+    }
+
+    /// Число живых групп (наблюдаемость, тесты).
+    pub fn len(&self) -> usize {
+        self.groups
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|g| g.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Откуда writer берёт балк-кадры: своя очередь туннеля или общая очередь
+/// группы полос.
+enum BulkPlane {
+    Own(mpsc::Receiver<OutFrame>),
+    Striped(Arc<StripeGroup>),
+}
+
+impl BulkPlane {
+    async fn recv(&mut self) -> Option<OutFrame> {
+        match self {
+            BulkPlane::Own(rx) => rx.recv().await,
+            BulkPlane::Striped(group) => group.next_frame().await,
+        }
+    }
+}
+
 // ── Reader task ─────────────────────────────────────────────────────
 
 async fn reader_task<R: AsyncReadExt + Unpin>(
@@ -1206,6 +1706,7 @@ async fn reader_task<R: AsyncReadExt + Unpin>(
     ctrl_tx: mpsc::Sender<OutFrame>,
     new_stream_tx: mpsc::Sender<NewStream>,
     relay_health: Option<Arc<RelayHealth>>,
+    caps: MuxCaps,
 ) -> io::Result<()> {
     // Буфер обязан вмещать максимальный легальный кадр целиком, иначе на нём
     // decode вечно возвращает None, а следующий read идёт в пустой хвост
@@ -1237,12 +1738,15 @@ async fn reader_task<R: AsyncReadExt + Unpin>(
                 if n == 0 { return Ok(()); }
                 last_recv = tokio::time::Instant::now();
                 filled += n;
+                if let Some(h) = &relay_health {
+                    h.record_rx(n);
+                }
 
                 // Decode all complete frames.
                 loop {
                     match codec.decode_frame(&buf[..filled])? {
                         Some((frame, consumed)) => {
-                            dispatch_frame(&frame, &streams, &ctrl_tx, &new_stream_tx, &relay_health).await;
+                            dispatch_frame(&frame, &streams, &ctrl_tx, &new_stream_tx, &relay_health, caps).await;
                             buf.copy_within(consumed..filled, 0);
                             filled -= consumed;
                         }
@@ -1288,12 +1792,26 @@ async fn reader_task<R: AsyncReadExt + Unpin>(
     }
 }
 
+/// RTT по Pong: payload это эхо метки Ping (unix millis). Метка в будущем
+/// или старше минуты значит чужой формат или прыжок часов, такой замер
+/// отбрасывается.
+fn pong_rtt(payload: &[u8]) -> Option<Duration> {
+    let sent = u64::from_be_bytes(payload.get(..8)?.try_into().ok()?);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_millis() as u64;
+    let rtt = Duration::from_millis(now.checked_sub(sent)?);
+    (rtt < Duration::from_secs(60)).then_some(rtt)
+}
+
 async fn dispatch_frame(
     frame: &Frame,
     streams: &Arc<Mutex<HashMap<u32, StreamEntry>>>,
     ctrl_tx: &mpsc::Sender<OutFrame>,
     new_stream_tx: &mpsc::Sender<NewStream>,
     relay_health: &Option<Arc<RelayHealth>>,
+    caps: MuxCaps,
 ) {
     match frame.command {
         Command::Ping => {
@@ -1307,7 +1825,13 @@ async fn dispatch_frame(
                 payload: frame.payload.clone(),
             });
         }
-        Command::Pong => {}
+        Command::Pong => {
+            // Эхо нашего keepalive-Ping: в payload метка отправки, разница с
+            // текущим временем это RTT линка (выбор сервера по задержке).
+            if let (Some(h), Some(rtt)) = (relay_health, pong_rtt(&frame.payload)) {
+                h.record_rtt(rtt);
+            }
+        }
         Command::WindowUpdate => {
             // Пир вернул кредит окна (LLD-27): пополнить окно отправки стрима
             // и разбудить заснувших. Не блокируется (атомик + notify), для
@@ -1323,64 +1847,104 @@ async fn dispatch_frame(
         }
         Command::Data | Command::ConnectAck => {
             if let Ok((stream_id, data)) = decode_mux_payload(&frame.payload) {
-                let mut remove = false;
-                {
-                    let mut streams_guard = streams.lock().await;
-                    if let Some(entry) = streams_guard.get_mut(&stream_id) {
-                        // Первый Data-кадр стрима это доказательство, что relay
-                        // на сервере реально отработал (resolve + connect до
-                        // апстрима), засчитываем успех в здоровье (XR-094).
-                        // ConnectAck не считается: сервер шлёт его ДО resolve.
-                        if frame.command == Command::Data && !entry.got_data {
-                            entry.got_data = true;
-                            if let Some(h) = relay_health {
-                                h.record_success(entry.domain);
+                let mut streams_guard = streams.lock().await;
+                let outcome = match streams_guard.get_mut(&stream_id) {
+                    // ConnectAck стрима из группы полос: отдать его и выпустить
+                    // полосы, обогнавшие подтверждение соседним туннелем.
+                    Some(entry)
+                        if frame.command == Command::ConnectAck
+                            && entry.reorder.as_ref().is_some_and(|r| !r.open) =>
+                    {
+                        if deliver(stream_id, entry, data.to_vec(), false, relay_health) {
+                            Reassembled::Kill
+                        } else {
+                            if let Some(reorder) = entry.reorder.as_mut() {
+                                reorder.open = true;
                             }
+                            stripe_flush(stream_id, entry, relay_health)
                         }
-                        // NEVER use send().await here — it blocks the reader task
-                        // and deadlocks ALL other streams. Use try_send; if the
-                        // channel is full, the stream consumer is stuck — kill it.
-                        match entry.tx.try_send(data.to_vec()) {
-                            Ok(()) => {}
-                            Err(mpsc::error::TrySendError::Full(_)) => {
-                                tracing::warn!("mux stream {} channel full, closing", stream_id);
-                                remove = true;
-                            }
-                            Err(mpsc::error::TrySendError::Closed(_)) => {
-                                remove = true;
-                            }
+                    }
+                    Some(entry) => {
+                        let is_data = frame.command == Command::Data;
+                        if deliver(stream_id, entry, data.to_vec(), is_data, relay_health) {
+                            Reassembled::Kill
+                        } else {
+                            Reassembled::Keep
+                        }
+                    }
+                    None => Reassembled::Keep,
+                };
+                finish_reassembly(outcome, &mut streams_guard, stream_id, relay_health);
+            }
+        }
+        Command::StripeData if caps.stripe => {
+            if let Some((stream_id, seq, data)) = decode_stripe(&frame.payload) {
+                let mut streams_guard = streams.lock().await;
+                let outcome = match streams_guard.get_mut(&stream_id) {
+                    Some(entry) => stripe_data(stream_id, entry, seq, data.to_vec(), relay_health),
+                    None => Reassembled::Keep,
+                };
+                finish_reassembly(outcome, &mut streams_guard, stream_id, relay_health);
+            }
+        }
+        Command::StripeClose if caps.stripe => {
+            if let Some((stream_id, seq, reason)) = decode_stripe(&frame.payload) {
+                let mut streams_guard = streams.lock().await;
+                let outcome = match streams_guard.get_mut(&stream_id) {
+                    Some(entry) => match entry.reorder.as_mut() {
+                        Some(reorder) if seq != STRIPE_SEQ_REFUSED => {
+                            reorder.close_at = Some((seq, reason.to_vec()));
+                            stripe_flush(stream_id, entry, relay_health)
+                        }
+                        _ => Reassembled::Closed(reason.to_vec()),
+                    },
+                    None => Reassembled::Keep,
+                };
+                finish_reassembly(outcome, &mut streams_guard, stream_id, relay_health);
+            }
+        }
+        Command::UdpData if caps.udp => {
+            if let Ok((stream_id, body)) = decode_mux_payload(&frame.payload) {
+                let mut streams_guard = streams.lock().await;
+                if let Some(entry) = streams_guard.get(&stream_id) {
+                    // Как и для Data, только try_send. Но полный канал здесь
+                    // значит потерю одной датаграммы, а не смерть ассоциации:
+                    // UDP-потребитель переживает дропы сам.
+                    match entry.tx.try_send(body.to_vec()) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            tracing::debug!("mux datagram {} channel full, dropping", stream_id);
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => {
+                            // Локальная сторона бросила ассоциацию, а её Close
+                            // мог не уйти: без ответа поток пира висел бы до idle.
+                            streams_guard.remove(&stream_id);
+                            close_on_drop(ctrl_tx, stream_id);
                         }
                     }
-                }
-                if remove {
-                    streams.lock().await.remove(&stream_id);
                 }
             }
         }
         Command::Close => {
             if let Ok((stream_id, reason)) = decode_mux_payload(&frame.payload) {
                 let removed = streams.lock().await.remove(&stream_id);
-                // Ненулевая причина в Close = установка relay на VPS упала
-                // (см. CLOSE_REASON_*). Считаем сбой в здоровье сервера, но
-                // только для ещё зарегистрированного стрима, чтобы дубль
-                // Close не удвоил счёт (XR-094).
+                // Только для ещё зарегистрированного стрима, чтобы дубль Close
+                // не удвоил счёт (XR-094).
                 if let Some(entry) = &removed {
-                    if let Some(&code) = reason.first() {
-                        // Причина публикуется до дропа entry (и его tx): когда
-                        // recv() стрима вернёт None, close_reason уже на месте.
-                        entry.close_reason.store(code, Ordering::Relaxed);
-                        if let Some(h) = relay_health {
-                            match code {
-                                CLOSE_REASON_RESOLVE_FAIL => h.record_resolve_fail(),
-                                CLOSE_REASON_CONNECT_FAIL => h.record_connect_fail(),
-                                _ => {}
-                            }
-                        }
-                    }
+                    record_close(entry, reason, relay_health);
                 }
             }
         }
-        Command::Connect => {
+        // UdpAssociate без согласованного бита не принимаем: пир, не знающий
+        // датаграмм, не должен получить их под видом Connect.
+        Command::Connect | Command::UdpAssociate
+            if frame.command == Command::Connect || caps.udp =>
+        {
+            let kind = if frame.command == Command::Connect {
+                StreamKind::Connect
+            } else {
+                StreamKind::Datagram
+            };
             if let Ok((stream_id, data)) = decode_mux_payload(&frame.payload) {
                 let streams_guard = streams.lock().await;
                 if let Some(entry) = streams_guard.get(&stream_id) {
@@ -1394,6 +1958,7 @@ async fn dispatch_frame(
                     match new_stream_tx.try_send(NewStream {
                         stream_id,
                         payload: data.to_vec(),
+                        kind,
                     }) {
                         Ok(()) => {}
                         Err(mpsc::error::TrySendError::Full(_)) => {
@@ -1411,13 +1976,152 @@ async fn dispatch_frame(
     }
 }
 
+/// Отдать кадр потребителю стрима. true = стрим надо снять: потребитель ушёл
+/// или не успевает (полный канал).
+fn deliver(
+    stream_id: u32,
+    entry: &mut StreamEntry,
+    data: Vec<u8>,
+    is_data: bool,
+    relay_health: &Option<Arc<RelayHealth>>,
+) -> bool {
+    // Первый Data-кадр стрима это доказательство, что relay на сервере реально
+    // отработал (resolve + connect до апстрима), засчитываем успех в здоровье
+    // (XR-094). ConnectAck не считается: сервер шлёт его ДО resolve.
+    if is_data && !entry.got_data {
+        entry.got_data = true;
+        if let Some(h) = relay_health {
+            h.record_success(entry.domain);
+        }
+    }
+    // NEVER use send().await here — it blocks the reader task and deadlocks
+    // ALL other streams. Use try_send; if the channel is full, the stream
+    // consumer is stuck — kill it.
+    match entry.tx.try_send(data) {
+        Ok(()) => false,
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            tracing::warn!("mux stream {} channel full, closing", stream_id);
+            true
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => true,
+    }
+}
+
+/// Учесть Close пира у снимаемой записи: причина для потребителя и здоровье.
+fn record_close(entry: &StreamEntry, reason: &[u8], relay_health: &Option<Arc<RelayHealth>>) {
+    // Ненулевая причина в Close = установка relay на VPS упала (см.
+    // CLOSE_REASON_*). Считаем сбой в здоровье сервера (XR-094).
+    if let Some(&code) = reason.first() {
+        // Причина публикуется до дропа entry (и его tx): когда recv() стрима
+        // вернёт None, close_reason уже на месте.
+        entry.close_reason.store(code, Ordering::Relaxed);
+        if let Some(h) = relay_health {
+            match code {
+                CLOSE_REASON_RESOLVE_FAIL => h.record_resolve_fail(),
+                CLOSE_REASON_CONNECT_FAIL => h.record_connect_fail(),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Итог приёма кадра стримом.
+enum Reassembled {
+    Keep,
+    /// Снять без причины: канал полон, потребитель ушёл, склейка переполнена.
+    Kill,
+    /// Очередь дошла до Close пира: снять с его причиной.
+    Closed(Vec<u8>),
+}
+
+fn finish_reassembly(
+    outcome: Reassembled,
+    streams: &mut HashMap<u32, StreamEntry>,
+    stream_id: u32,
+    relay_health: &Option<Arc<RelayHealth>>,
+) {
+    match outcome {
+        Reassembled::Keep => {}
+        Reassembled::Kill => {
+            streams.remove(&stream_id);
+        }
+        Reassembled::Closed(reason) => {
+            if let Some(entry) = streams.remove(&stream_id) {
+                record_close(&entry, &reason, relay_health);
+            }
+        }
+    }
+}
+
+/// Разобрать StripeData/StripeClose: stream_id, номер полосы, остаток.
+fn decode_stripe(payload: &[u8]) -> Option<(u32, u32, &[u8])> {
+    let (stream_id, body) = decode_mux_payload(payload).ok()?;
+    let seq = u32::from_be_bytes(body.get(..4)?.try_into().ok()?);
+    Some((stream_id, seq, &body[4..]))
+}
+
+/// Полоса стрима: встать в склейку и выпустить всё, что стало подряд.
+fn stripe_data(
+    stream_id: u32,
+    entry: &mut StreamEntry,
+    seq: u32,
+    data: Vec<u8>,
+    relay_health: &Option<Arc<RelayHealth>>,
+) -> Reassembled {
+    let Some(reorder) = entry.reorder.as_mut() else {
+        // Датаграммной ассоциации и одиночному туннелю полосы не положены.
+        return Reassembled::Keep;
+    };
+    if seq < reorder.next {
+        return Reassembled::Keep; // дубль уже отданной полосы
+    }
+    reorder.pending.insert(seq, data);
+    if reorder.pending.len() > STRIPE_REORDER_MAX {
+        tracing::warn!(
+            "mux stream {} stripe reorder overflow (gap at {}), closing",
+            stream_id,
+            reorder.next
+        );
+        return Reassembled::Kill;
+    }
+    stripe_flush(stream_id, entry, relay_health)
+}
+
+/// Отдать потребителю полосы, идущие подряд от `next`, и снять стрим, если
+/// очередь дошла до Close пира.
+fn stripe_flush(
+    stream_id: u32,
+    entry: &mut StreamEntry,
+    relay_health: &Option<Arc<RelayHealth>>,
+) -> Reassembled {
+    loop {
+        let Some(reorder) = entry.reorder.as_mut() else {
+            return Reassembled::Keep;
+        };
+        if !reorder.open {
+            return Reassembled::Keep;
+        }
+        if reorder.close_at.as_ref().is_some_and(|(at, _)| *at == reorder.next) {
+            let (_, reason) = reorder.close_at.take().unwrap_or_default();
+            return Reassembled::Closed(reason);
+        }
+        let Some(data) = reorder.pending.remove(&reorder.next) else {
+            return Reassembled::Keep;
+        };
+        reorder.next += 1;
+        if deliver(stream_id, entry, data, true, relay_health) {
+            return Reassembled::Kill;
+        }
+    }
+}
+
 // ── Writer task ─────────────────────────────────────────────────────
 
 async fn writer_task<W: AsyncWriteExt + Unpin>(
     mut writer: W,
     codec: Codec,
     mut ctrl_rx: mpsc::Receiver<OutFrame>,
-    mut data_rx: mpsc::Receiver<OutFrame>,
+    mut data_rx: BulkPlane,
     shutdown: Arc<Notify>,
 ) -> io::Result<()> {
     // ПРИОРИТЕТ контрольного плана: `biased` select проверяет ctrl_rx раньше
@@ -1452,7 +2156,7 @@ async fn writer_task<W: AsyncWriteExt + Unpin>(
                 frame.payload
             }
             _ => {
-                // Data/Connect/ConnectAck/Close: prefix with stream_id.
+                // Data/Connect/ConnectAck/Close/Udp*/Stripe*: prefix with stream_id.
                 encode_mux_payload(frame.stream_id, &frame.payload)
             }
         };
@@ -1483,9 +2187,23 @@ pub async fn mux_handshake_client<S: AsyncReadExt + AsyncWriteExt + Unpin>(
     stream: &mut S,
     codec: &Codec,
 ) -> io::Result<Option<MuxCaps>> {
-    // Send MuxInit: версия + байт флагов (LLD-27). Старый сервер читает только
-    // первый байт и лишний игнорирует.
-    let init_payload = vec![MUX_PROTOCOL_VERSION, MuxCaps::LOCAL.to_flags()];
+    mux_handshake_client_striped(stream, codec, None).await
+}
+
+/// Как [`mux_handshake_client`], но с заявкой в группу полос: бит `stripe` и
+/// id группы уходят в MuxInit. Без группы бит не ставится.
+pub async fn mux_handshake_client_striped<S: AsyncReadExt + AsyncWriteExt + Unpin>(
+    stream: &mut S,
+    codec: &Codec,
+    group: Option<&StripeGroup>,
+) -> io::Result<Option<MuxCaps>> {
+    let offer = MuxCaps { stripe: group.is_some(), ..MuxCaps::LOCAL };
+    // Send MuxInit: версия + байт флагов (LLD-27), за ними id группы полос.
+    // Старый сервер читает только первый байт и лишний игнорирует.
+    let mut init_payload = vec![MUX_PROTOCOL_VERSION, offer.to_flags()];
+    if let Some(group) = group {
+        init_payload.extend_from_slice(group.id());
+    }
     let wire = codec.encode_frame(Command::MuxInit, &init_payload)?;
     stream.write_all(&wire).await?;
 
@@ -1515,7 +2233,7 @@ pub async fn mux_handshake_client<S: AsyncReadExt + AsyncWriteExt + Unpin>(
                     // Третий байт это флаги сервера; старый сервер его не шлёт,
                     // отсутствие читается как пустые флаги (окно выключено).
                     let flags = frame.payload.get(2).copied().unwrap_or(0);
-                    return Ok(Some(MuxCaps::from_flags(flags & MuxCaps::LOCAL.to_flags())));
+                    return Ok(Some(MuxCaps::from_flags(flags & offer.to_flags())));
                 }
                 return Ok(None); // rejected
             }
@@ -1525,11 +2243,23 @@ pub async fn mux_handshake_client<S: AsyncReadExt + AsyncWriteExt + Unpin>(
 }
 
 /// Server: check if frame is MuxInit, send MuxInitAck. Возвращает
-/// согласованные возможности, None = не mux / версия не наша.
+/// согласованные возможности, None = не mux / версия не наша. Подтверждает
+/// только стримы ([`MuxCaps::STREAMS`]).
 pub async fn mux_handshake_server<S: AsyncWriteExt + Unpin>(
     stream: &mut S,
     codec: &Codec,
     init_frame: &Frame,
+) -> io::Result<Option<MuxCaps>> {
+    mux_handshake_server_with(stream, codec, init_frame, MuxCaps::STREAMS).await
+}
+
+/// Как [`mux_handshake_server`], но с явным набором возможностей акцептора:
+/// xr-server передаёт [`MuxCaps::LOCAL`], потому что релеит датаграммы.
+pub async fn mux_handshake_server_with<S: AsyncWriteExt + Unpin>(
+    stream: &mut S,
+    codec: &Codec,
+    init_frame: &Frame,
+    local: MuxCaps,
 ) -> io::Result<Option<MuxCaps>> {
     if init_frame.command != Command::MuxInit {
         return Ok(None);
@@ -1547,7 +2277,9 @@ pub async fn mux_handshake_server<S: AsyncWriteExt + Unpin>(
     // байт, отсутствие = пустые флаги. В ack уходит пересечение с нашими,
     // старый клиент лишний третий байт игнорирует.
     let peer_flags = init_frame.payload.get(1).copied().unwrap_or(0);
-    let caps = MuxCaps::from_flags(peer_flags & MuxCaps::LOCAL.to_flags());
+    let mut caps = MuxCaps::from_flags(peer_flags & local.to_flags());
+    // Полосы без id группы склеивать не с чем.
+    caps.stripe &= mux_stripe_group_id(init_frame).is_some();
 
     // Accept.
     let ack =
@@ -1556,6 +2288,16 @@ pub async fn mux_handshake_server<S: AsyncWriteExt + Unpin>(
     Ok(Some(caps))
 }
 
+/// Id группы полос из MuxInit клиента (см. [`mux_handshake_client_striped`]);
+/// None, если клиент в группу не просился.
+pub fn mux_stripe_group_id(init_frame: &Frame) -> Option<[u8; STRIPE_GROUP_ID_LEN]> {
+    let flags = init_frame.payload.get(1).copied().unwrap_or(0);
+    if flags & MUX_FLAG_STRIPE == 0 {
+        return None;
+    }
+    init_frame.payload.get(2..2 + STRIPE_GROUP_ID_LEN)?.try_into().ok()
+}
+
 // ── Client open_stream (standalone function) ────────────────────────
 
 /// Open a stream on a client multiplexer: send Connect, wait for ConnectAck.
@@ -1570,11 +2312,68 @@ pub async fn mux_open_stream(
     // Адрес кодируем до всего остального: некодируемый target это ошибка
     // вызывающего, и на ней незачем ни жечь stream_id, ни брать лок streams.
     let connect_payload = target.encode()?;
+    let window = mux.new_send_window();
+    let close_reason = Arc::new(AtomicU8::new(0));
+    let (stream_id, data_rx) = open_registered(
+        mux,
+        Command::Connect,
+        connect_payload,
+        matches!(target, TargetAddr::Domain(..)),
+        window.clone(),
+        close_reason.clone(),
+    )
+    .await?;
+    Ok(MuxStream {
+        stream_id,
+        rx: Some(data_rx),
+        writer_tx: mux.writer_tx.clone(),
+        alive: mux.alive.clone(),
+        closed: false,
+        detached: false,
+        window,
+        recv_credit: mux.new_recv_credit(stream_id),
+        close_reason,
+    })
+}
 
+/// Open a datagram association on a client multiplexer: send UdpAssociate,
+/// wait for ConnectAck. `Unsupported`, если пир не согласовал датаграммы:
+/// вызывающий уходит на отдельный UDP relay.
+pub async fn mux_open_udp(mux: &Arc<Multiplexer>) -> io::Result<MuxDatagram> {
+    if !mux.is_alive() {
+        return Err(io::Error::new(io::ErrorKind::BrokenPipe, "mux connection dead"));
+    }
+    if !mux.caps.udp {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "peer does not relay datagrams over mux",
+        ));
+    }
+    let (stream_id, data_rx) = open_registered(
+        mux,
+        Command::UdpAssociate,
+        Vec::new(),
+        false,
+        SendWindow::new(i64::MAX),
+        Arc::new(AtomicU8::new(0)),
+    )
+    .await?;
+    Ok(mux.datagram_handle(stream_id, data_rx))
+}
+
+/// Общая часть открытия: зарегистрировать запись, отправить открывающий кадр
+/// (`command`) контрольным планом и дождаться ConnectAck. Возвращает stream_id
+/// и приёмный канал; на любом неуспехе запись снимается.
+async fn open_registered(
+    mux: &Arc<Multiplexer>,
+    command: Command,
+    payload: Vec<u8>,
+    domain: bool,
+    window: Arc<SendWindow>,
+    close_reason: Arc<AtomicU8>,
+) -> io::Result<(u32, mpsc::Receiver<Vec<u8>>)> {
     let stream_id = mux.next_stream_id.fetch_add(2, Ordering::Relaxed);
     let (data_tx, mut data_rx) = mpsc::channel(STREAM_CHANNEL_SIZE);
-    let window = mux.new_send_window();
-    let close_reason = Arc::new(AtomicU8::new(0));
 
     // Register before sending Connect so we don't miss ConnectAck.
     // Таймаут+WARN на взятие async-Mutex `streams`: если открытие вешается ЗДЕСЬ
@@ -1587,14 +2386,15 @@ pub async fn mux_open_stream(
                 StreamEntry {
                     tx: data_tx,
                     got_data: false,
-                    domain: matches!(target, TargetAddr::Domain(..)),
-                    window: window.clone(),
-                    close_reason: close_reason.clone(),
+                    domain,
+                    window,
+                    close_reason,
+                    reorder: mux.new_reorder(false),
                 },
             );
         }
         Err(_) => {
-            tracing::warn!("mux_open_stream wedged >4s taking streams lock (deadlock?)");
+            tracing::warn!("mux open ({:?}) wedged >4s taking streams lock (deadlock?)", command);
             return Err(io::Error::new(
                 io::ErrorKind::WouldBlock,
                 "mux streams lock wedged",
@@ -1602,7 +2402,7 @@ pub async fn mux_open_stream(
         }
     }
 
-    // Guard снимает регистрацию, если мы НЕ вернём MuxStream: по ошибке или по
+    // Guard снимает регистрацию, если мы НЕ вернём стрим: по ошибке или по
     // ОТМЕНЕ future (наш bounded-таймаут в ServerPool рвёт ожидание ConnectAck на
     // полпути). Без этого на неотвечающем/blackhole сервере (ConnectAck не
     // приходит, а поздний try_send не срабатывает, ведь receiver жив пока future
@@ -1617,16 +2417,14 @@ pub async fn mux_open_stream(
     // если открытие вешается здесь, значит встал сам writer-таск (TCP send-буфер
     // полон, сокет не принимает даже контроль). Именно так выглядел живой хэнг DE
     // (0 пакетов на сервер, open timed out).
-    match tokio::time::timeout(
-        OPEN_STEP_TIMEOUT,
-        mux.send_frame(stream_id, Command::Connect, connect_payload),
-    )
-    .await
+    match tokio::time::timeout(OPEN_STEP_TIMEOUT, mux.send_frame(stream_id, command, payload))
+        .await
     {
         Ok(r) => r?,
         Err(_) => {
             tracing::warn!(
-                "mux_open_stream wedged >4s sending Connect (ctrl channel / writer task stuck?)"
+                "mux open wedged >4s sending {:?} (ctrl channel / writer task stuck?)",
+                command
             );
             return Err(io::Error::new(
                 io::ErrorKind::WouldBlock,
@@ -1638,18 +2436,11 @@ pub async fn mux_open_stream(
     // Wait for ConnectAck — delivered as first message on the channel.
     // The reader task dispatches ConnectAck payload (after stream_id prefix)
     // to this stream's channel.
-    let result = match tokio::time::timeout(Duration::from_secs(10), data_rx.recv()).await {
-        Ok(Some(_ack_payload)) => Ok(MuxStream {
-            stream_id,
-            rx: Some(data_rx),
-            writer_tx: mux.writer_tx.clone(),
-            alive: mux.alive.clone(),
-            closed: false,
-            detached: false,
-            window,
-            recv_credit: mux.new_recv_credit(stream_id),
-            close_reason,
-        }),
+    match tokio::time::timeout(Duration::from_secs(10), data_rx.recv()).await {
+        Ok(Some(_ack_payload)) => {
+            guard.disarm();
+            Ok((stream_id, data_rx))
+        }
         Ok(None) => Err(io::Error::new(
             io::ErrorKind::BrokenPipe,
             "mux connection died during open",
@@ -1658,17 +2449,14 @@ pub async fn mux_open_stream(
             io::ErrorKind::TimedOut,
             "mux connect ack timeout",
         )),
-    };
-    if result.is_ok() {
-        guard.disarm();
     }
-    result
 }
 
-/// Снимает регистрацию стрима из `mux.streams`, если `mux_open_stream` не дошёл
-/// до успешного возврата `MuxStream`. Ловит и обычный ранний выход, и ОТМЕНУ
-/// future (bounded-таймаут в `ServerPool::open_stream`). Очистка идёт в
-/// отдельном таске: `streams` за async-Mutex, синхронный Drop его не залочит.
+/// Снимает регистрацию стрима из `mux.streams`, если открытие (`mux_open_stream`,
+/// `mux_open_udp`) не дошло до успешного возврата хэндла. Ловит и обычный
+/// ранний выход, и ОТМЕНУ future (bounded-таймаут в `ServerPool::open_stream`).
+/// Очистка идёт в отдельном таске: `streams` за async-Mutex, синхронный Drop
+/// его не залочит.
 struct StreamRegGuard {
     streams: Option<Arc<Mutex<HashMap<u32, StreamEntry>>>>,
     stream_id: u32,
@@ -1734,8 +2522,8 @@ mod tests {
 
         let (client_result, server_result) = tokio::join!(client_task, server_task);
         // Обе стороны новые: хендшейк проходит и согласовывает окно (LLD-27).
-        assert_eq!(client_result.unwrap().unwrap(), Some(MuxCaps { window: true }));
-        assert_eq!(server_result.unwrap().unwrap(), Some(MuxCaps { window: true }));
+        assert_eq!(client_result.unwrap().unwrap(), Some(MuxCaps::STREAMS));
+        assert_eq!(server_result.unwrap().unwrap(), Some(MuxCaps::STREAMS));
     }
 
     #[tokio::test]
@@ -2231,7 +3019,7 @@ mod tests {
         let caps = mux_handshake_client(&mut client_io, &codec).await.unwrap();
         assert_eq!(
             caps,
-            Some(MuxCaps { window: false }),
+            Some(MuxCaps { window: false, udp: false, stripe: false }),
             "старый сервер без байта флагов = mux есть, окна нет"
         );
         old_server.await.unwrap();
@@ -2247,7 +3035,7 @@ mod tests {
 
         let init = Frame { command: Command::MuxInit, payload: vec![MUX_PROTOCOL_VERSION] };
         let caps = mux_handshake_server(&mut server_io, &codec, &init).await.unwrap();
-        assert_eq!(caps, Some(MuxCaps { window: false }));
+        assert_eq!(caps, Some(MuxCaps { window: false, udp: false, stripe: false }));
 
         // Ack глазами старого клиента: он смотрит только payload[0..2].
         let mut buf = vec![0u8; 256];
@@ -2294,7 +3082,7 @@ mod tests {
     async fn test_window_slow_consumer_gets_all_bytes() {
         let (client_io, server_io) = duplex(65536);
         let codec = test_codec();
-        let caps = MuxCaps { window: true };
+        let caps = MuxCaps { window: true, udp: false, stripe: false };
         let client_mux = Multiplexer::new_client(client_io, codec.clone(), caps);
         let server_mux = Multiplexer::new_server(server_io, codec.clone(), caps);
 
@@ -2334,7 +3122,7 @@ mod tests {
     async fn test_no_window_slow_consumer_stream_killed() {
         let (client_io, server_io) = duplex(65536);
         let codec = test_codec();
-        let caps = MuxCaps { window: false };
+        let caps = MuxCaps { window: false, udp: false, stripe: false };
         let client_mux = Multiplexer::new_client(client_io, codec.clone(), caps);
         let server_mux = Multiplexer::new_server(server_io, codec.clone(), caps);
 
@@ -2407,7 +3195,7 @@ mod tests {
         let key = b"test-key-32-bytes-long-enough!!!".to_vec();
         let obfs = Obfuscator::new(key, 0xDEADBEEF, ModifierStrategy::PositionalXorRotate);
         let codec = Codec::new(obfs, 255, 255); // padding ровно 255, максимум
-        let caps = MuxCaps { window: true };
+        let caps = MuxCaps { window: true, udp: false, stripe: false };
         let (client_io, server_io) = duplex(1 << 20);
         let client_mux = Multiplexer::new_client(client_io, codec.clone(), caps);
         let server_mux = Multiplexer::new_server(server_io, codec.clone(), caps);
@@ -2443,7 +3231,7 @@ mod tests {
     async fn test_close_wakes_sender_blocked_on_window() {
         let (client_io, server_io) = duplex(65536);
         let codec = test_codec();
-        let caps = MuxCaps { window: true };
+        let caps = MuxCaps { window: true, udp: false, stripe: false };
         let client_mux = Multiplexer::new_client(client_io, codec.clone(), caps);
         let server_mux = Multiplexer::new_server(server_io, codec.clone(), caps);
 
@@ -2479,7 +3267,7 @@ mod tests {
     async fn test_mux_stream_io_bulk_over_window() {
         let (client_io, server_io) = duplex(65536);
         let codec = test_codec();
-        let caps = MuxCaps { window: true };
+        let caps = MuxCaps { window: true, udp: false, stripe: false };
         let client_mux = Multiplexer::new_client(client_io, codec.clone(), caps);
         let server_mux = Multiplexer::new_server(server_io, codec.clone(), caps);
 
@@ -2559,7 +3347,7 @@ mod tests {
         let codec_w = codec.clone();
         let shutdown = Arc::new(Notify::new());
         let writer = tokio::spawn(async move {
-            writer_task(w, codec_w, ctrl_rx, data_rx, shutdown).await.unwrap();
+            writer_task(w, codec_w, ctrl_rx, BulkPlane::Own(data_rx), shutdown).await.unwrap();
         });
 
         let mut buf = Vec::new();
@@ -2590,4 +3378,387 @@ mod tests {
             "ConnectAck обязан уйти первым, не в хвосте за балком Data"
         );
     }
+
+    /// UDP поверх mux: ассоциация открывается, датаграммы ходят в обе стороны
+    /// с адресом в каждом кадре, входящая ассоциация приходит с видом Datagram.
+    #[tokio::test]
+    async fn test_datagram_roundtrip() {
+        let (client_io, server_io) = duplex(65536);
+        let codec = test_codec();
+        let client_mux = Multiplexer::new_client(client_io, codec.clone(), MuxCaps::LOCAL);
+        let server_mux = Multiplexer::new_server(server_io, codec.clone(), MuxCaps::LOCAL);
+
+        let s = server_mux.clone();
+        tokio::spawn(async move {
+            let mut rx = s.take_new_stream_rx().await.unwrap();
+            let ns = rx.recv().await.unwrap();
+            assert_eq!(ns.kind, StreamKind::Datagram);
+            let mut assoc = s.register_datagram(ns.stream_id).await;
+            s.send_frame(ns.stream_id, Command::ConnectAck, vec![0]).await.unwrap();
+            // Эхо с подменой адреса: ответ «пришёл» от того, кому слали.
+            while let Some((dst, data)) = assoc.recv_from().await {
+                assoc.send_to(&dst, &data).unwrap();
+            }
+        });
+
+        let mut assoc = mux_open_udp(&client_mux).await.unwrap();
+        let dst = TargetAddr::Ip("198.51.100.7:3478".parse().unwrap());
+        assoc.send_to(&dst, b"stun").unwrap();
+        let (from, data) = assoc.recv_from().await.unwrap();
+        assert_eq!(data, b"stun");
+        match from {
+            TargetAddr::Ip(a) => assert_eq!(a, "198.51.100.7:3478".parse().unwrap()),
+            _ => panic!("wrong type"),
+        }
+
+        // Датаграмма, не влезающая в кадр, это ошибка вызывающего, а не обрыв.
+        let err = assoc.send_to(&dst, &vec![0u8; MAX_PAYLOAD_LEN]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(assoc.is_alive());
+    }
+
+    /// Датаграмма в ассоциацию, чей приёмник уже закрыт, а Close не ушёл:
+    /// принимающая сторона отвечает Close, и поток пира кончается сразу, а не
+    /// по простою.
+    #[tokio::test]
+    async fn test_datagram_to_closed_association_replies_close() {
+        let (client_io, server_io) = duplex(65536);
+        let codec = test_codec();
+        let client_mux = Multiplexer::new_client(client_io, codec.clone(), MuxCaps::LOCAL);
+        let server_mux = Multiplexer::new_server(server_io, codec.clone(), MuxCaps::LOCAL);
+
+        let s = server_mux.clone();
+        tokio::spawn(async move {
+            let mut rx = s.take_new_stream_rx().await.unwrap();
+            let ns = rx.recv().await.unwrap();
+            let (tx, data_rx) = mpsc::channel(1);
+            drop(data_rx);
+            s.streams.lock().await.insert(
+                ns.stream_id,
+                StreamEntry {
+                    tx,
+                    got_data: false,
+                    domain: false,
+                    window: SendWindow::new(i64::MAX),
+                    close_reason: Arc::new(AtomicU8::new(0)),
+                    reorder: None,
+                },
+            );
+            s.send_frame(ns.stream_id, Command::ConnectAck, vec![0]).await.unwrap();
+        });
+
+        let mut assoc = mux_open_udp(&client_mux).await.unwrap();
+        let dst = TargetAddr::Ip("198.51.100.7:53".parse().unwrap());
+        assoc.send_to(&dst, b"q").unwrap();
+        let got = tokio::time::timeout(Duration::from_secs(5), assoc.recv_from()).await;
+        assert!(matches!(got, Ok(None)), "ассоциация должна закрыться по Close сервера");
+        assert!(server_mux.streams.lock().await.is_empty());
+    }
+
+    /// Пир без бита udp: открытие сразу отдаёт Unsupported (вызывающий уходит
+    /// на отдельный relay), ничего не шлёт и stream_id не регистрирует.
+    #[tokio::test]
+    async fn test_open_udp_without_cap_is_unsupported() {
+        let (client_io, server_io) = duplex(65536);
+        let codec = test_codec();
+        let client_mux = Multiplexer::new_client(client_io, codec.clone(), MuxCaps::STREAMS);
+        let _server_mux = Multiplexer::new_server(server_io, codec.clone(), MuxCaps::STREAMS);
+
+        let err = mux_open_udp(&client_mux).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(client_mux.streams.lock().await.is_empty());
+    }
+
+    /// Хендшейк с акцептором, релеющим датаграммы, согласует бит udp; обычный
+    /// `mux_handshake_server` его не подтверждает (xr-relay, агенты шар).
+    #[tokio::test]
+    async fn test_handshake_server_with_negotiates_udp() {
+        for (local, expect_udp) in [(MuxCaps::LOCAL, true), (MuxCaps::STREAMS, false)] {
+            let (mut client_io, mut server_io) = duplex(1024);
+            let codec = test_codec();
+            let server_codec = codec.clone();
+            let server = tokio::spawn(async move {
+                let mut buf = vec![0u8; 256];
+                let mut filled = 0;
+                let init = loop {
+                    let n = server_io.read(&mut buf[filled..]).await.unwrap();
+                    filled += n;
+                    if let Some((f, _)) = server_codec.decode_frame(&buf[..filled]).unwrap() {
+                        break f;
+                    }
+                };
+                mux_handshake_server_with(&mut server_io, &server_codec, &init, local)
+                    .await
+                    .unwrap()
+            });
+            let caps = mux_handshake_client(&mut client_io, &codec).await.unwrap().unwrap();
+            assert_eq!(caps.udp, expect_udp);
+            assert_eq!(server.await.unwrap().unwrap().udp, expect_udp);
+        }
+    }
+
+    fn stripe_frame(command: Command, stream_id: u32, seq: u32, body: &[u8]) -> Frame {
+        let mut inner = seq.to_be_bytes().to_vec();
+        inner.extend_from_slice(body);
+        Frame { command, payload: encode_mux_payload(stream_id, &inner) }
+    }
+
+    /// Склейка полос: кадры, пришедшие вперемешку и раньше ConnectAck, отдаются
+    /// потребителю строго по номерам, а Close снимает стрим только после хвоста.
+    #[tokio::test]
+    async fn test_stripe_reorder_delivers_in_sequence() {
+        let streams = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, mut rx) = mpsc::channel(16);
+        let close_reason = Arc::new(AtomicU8::new(0));
+        streams.lock().await.insert(
+            1,
+            StreamEntry {
+                tx,
+                got_data: false,
+                domain: false,
+                window: SendWindow::new(i64::MAX),
+                close_reason: close_reason.clone(),
+                reorder: Some(Reorder::default()),
+            },
+        );
+        let (ctrl_tx, _ctrl_rx) = mpsc::channel(4);
+        let (new_tx, _new_rx) = mpsc::channel(4);
+        let dispatch = |frame: Frame| {
+            let streams = streams.clone();
+            let ctrl_tx = ctrl_tx.clone();
+            let new_tx = new_tx.clone();
+            async move {
+                dispatch_frame(&frame, &streams, &ctrl_tx, &new_tx, &None, MuxCaps::LOCAL).await;
+            }
+        };
+
+        dispatch(stripe_frame(
+            Command::StripeClose,
+            1,
+            3,
+            &[crate::protocol::CLOSE_REASON_CONNECT_FAIL],
+        ))
+        .await;
+        dispatch(stripe_frame(Command::StripeData, 1, 2, b"c")).await;
+        dispatch(stripe_frame(Command::StripeData, 1, 0, b"a")).await;
+        assert!(rx.try_recv().is_err(), "до ConnectAck полосы ждут в склейке");
+
+        dispatch(Frame { command: Command::ConnectAck, payload: encode_mux_payload(1, &[0]) })
+            .await;
+        assert_eq!(rx.try_recv().unwrap(), vec![0]);
+        assert_eq!(rx.try_recv().unwrap(), b"a");
+        assert!(rx.try_recv().is_err(), "дыра на номере 1");
+
+        dispatch(stripe_frame(Command::StripeData, 1, 1, b"b")).await;
+        assert_eq!(rx.try_recv().unwrap(), b"b");
+        assert_eq!(rx.try_recv().unwrap(), b"c");
+        assert!(rx.recv().await.is_none(), "Close снимает стрим после хвоста");
+        assert_eq!(close_reason.load(Ordering::Relaxed), crate::protocol::CLOSE_REASON_CONNECT_FAIL);
+        assert!(streams.lock().await.is_empty());
+    }
+
+    /// Номера выдаются при заборе из общей очереди: Data по порядку, Close
+    /// следующим номером; Close без отправленных Data и ConnectAck это отказ.
+    #[tokio::test]
+    async fn test_stripe_group_sequences_bulk_frames() {
+        let group = StripeGroup::new_client();
+        let frame = |stream_id, command| OutFrame { stream_id, command, payload: vec![7] };
+        for (stream_id, command) in [
+            (1, Command::Data),
+            (1, Command::Data),
+            (1, Command::Close),
+            (3, Command::Close),
+            (5, Command::Close),
+            (7, Command::UdpData),
+        ] {
+            if stream_id == 5 {
+                group.note_open(5);
+            }
+            group.bulk_tx.send(frame(stream_id, command)).await.unwrap();
+        }
+        let mut got = Vec::new();
+        for _ in 0..6 {
+            let f = group.next_frame().await.unwrap();
+            got.push((f.stream_id, f.command, f.payload));
+        }
+        let seq = |n: u32, tail: u8| {
+            let mut p = n.to_be_bytes().to_vec();
+            p.push(tail);
+            p
+        };
+        assert_eq!(
+            got,
+            vec![
+                (1, Command::StripeData, seq(0, 7)),
+                (1, Command::StripeData, seq(1, 7)),
+                (1, Command::StripeClose, seq(2, 7)),
+                (3, Command::StripeClose, seq(STRIPE_SEQ_REFUSED, 7)),
+                (5, Command::StripeClose, seq(0, 7)),
+                (7, Command::UdpData, vec![7]),
+            ]
+        );
+    }
+
+    /// Смерть туннеля группы снимает её стримы у себя и шлёт пиру отказной
+    /// StripeClose, а пир по нему снимает стрим сразу, несмотря на дыру.
+    #[tokio::test]
+    async fn test_stripe_group_fail_streams_closes_peer() {
+        let group = StripeGroup::new_client();
+        let (tx, mut rx) = mpsc::channel(4);
+        group.streams.lock().await.insert(
+            3,
+            StreamEntry {
+                tx,
+                got_data: false,
+                domain: false,
+                window: SendWindow::new(i64::MAX),
+                close_reason: Arc::new(AtomicU8::new(0)),
+                reorder: Some(Reorder { open: true, ..Default::default() }),
+            },
+        );
+        group.note_open(3);
+        group.fail_streams().await;
+        assert!(rx.recv().await.is_none(), "локальный стрим закрыт");
+        assert!(group.seqs.lock().unwrap().is_empty());
+
+        let f = group.next_frame().await.unwrap();
+        assert_eq!((f.stream_id, f.command), (3, Command::StripeClose));
+        assert_eq!(f.payload, STRIPE_SEQ_REFUSED.to_be_bytes());
+
+        // Пир со склейкой, застрявшей на пропавшей полосе 0.
+        let peer = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, mut peer_rx) = mpsc::channel(4);
+        let mut reorder = Reorder { open: true, ..Default::default() };
+        reorder.pending.insert(1, b"late".to_vec());
+        peer.lock().await.insert(
+            3,
+            StreamEntry {
+                tx,
+                got_data: true,
+                domain: false,
+                window: SendWindow::new(i64::MAX),
+                close_reason: Arc::new(AtomicU8::new(0)),
+                reorder: Some(reorder),
+            },
+        );
+        let (ctrl_tx, _ctrl_rx) = mpsc::channel(4);
+        let (new_tx, _new_rx) = mpsc::channel(4);
+        let frame = Frame { command: f.command, payload: encode_mux_payload(3, &f.payload) };
+        dispatch_frame(&frame, &peer, &ctrl_tx, &new_tx, &None, MuxCaps::LOCAL).await;
+        assert!(peer_rx.recv().await.is_none());
+        assert!(peer.lock().await.is_empty());
+    }
+
+    /// Multipath целиком: два туннеля одной группы, стрим открыт на первом,
+    /// выгрузка сервера раскладывается по обоим и собирается без потерь и
+    /// перестановок; Close доходит после хвоста.
+    #[tokio::test]
+    async fn test_striped_stream_roundtrip_over_two_tunnels() {
+        let codec = test_codec();
+        let caps = MuxCaps::LOCAL;
+        let client_group = StripeGroup::new_client();
+        let registry = StripeRegistry::new();
+        let server_group = registry.join(*client_group.id());
+        let health = Arc::new(RelayHealth::new());
+
+        let mut client_muxes = Vec::new();
+        let mut server_muxes = Vec::new();
+        for _ in 0..2 {
+            let (client_io, server_io) = duplex(64 * 1024);
+            client_muxes.push(Multiplexer::new_client_striped(
+                client_io,
+                codec.clone(),
+                health.clone(),
+                caps,
+                client_group.clone(),
+            ));
+            server_muxes.push(Multiplexer::new_server_striped(
+                server_io,
+                codec.clone(),
+                caps,
+                server_group.clone(),
+            ));
+        }
+        assert!(client_muxes[0].caps().stripe);
+        assert_eq!(registry.len(), 1);
+
+        const TOTAL: usize = 2 * 1024 * 1024;
+        let server = server_muxes[0].clone();
+        let server_task = tokio::spawn(async move {
+            let mut rx = server.take_new_stream_rx().await.unwrap();
+            let ns = rx.recv().await.unwrap();
+            let mut stream = server.register_stream(ns.stream_id).await;
+            server.send_frame(ns.stream_id, Command::ConnectAck, vec![0]).await.unwrap();
+            assert_eq!(stream.recv().await.unwrap(), b"GET");
+            send_pattern(&stream, TOTAL).await.unwrap();
+            stream.close().await.unwrap();
+        });
+
+        let target = TargetAddr::Domain("example.com".to_string(), 443);
+        let mut stream = mux_open_stream(&client_muxes[0], &target).await.unwrap();
+        stream.send(b"GET").await.unwrap();
+        let mut received = Vec::with_capacity(TOTAL);
+        while let Some(chunk) = stream.recv().await {
+            received.extend_from_slice(&chunk);
+        }
+        assert_eq!(received.len(), TOTAL);
+        assert!(received.iter().enumerate().all(|(i, &b)| b == (i % 199) as u8));
+        server_task.await.unwrap();
+    }
+
+    /// Бит stripe согласуется только с id группы в MuxInit и только акцептором,
+    /// который полосы склеивает.
+    #[tokio::test]
+    async fn test_handshake_negotiates_stripe_with_group_id() {
+        let group = StripeGroup::new_client();
+        for (local, expect) in [(MuxCaps::LOCAL, true), (MuxCaps::STREAMS, false)] {
+            let (mut client_io, mut server_io) = duplex(1024);
+            let codec = test_codec();
+            let server_codec = codec.clone();
+            let server = tokio::spawn(async move {
+                let mut buf = vec![0u8; 256];
+                let mut filled = 0;
+                let init = loop {
+                    let n = server_io.read(&mut buf[filled..]).await.unwrap();
+                    filled += n;
+                    if let Some((f, _)) = server_codec.decode_frame(&buf[..filled]).unwrap() {
+                        break f;
+                    }
+                };
+                let caps = mux_handshake_server_with(&mut server_io, &server_codec, &init, local)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                (caps, mux_stripe_group_id(&init))
+            });
+            let caps = mux_handshake_client_striped(&mut client_io, &codec, Some(&group))
+                .await
+                .unwrap()
+                .unwrap();
+            let (server_caps, id) = server.await.unwrap();
+            assert_eq!(caps.stripe, expect);
+            assert_eq!(server_caps.stripe, expect);
+            assert_eq!(id, Some(*group.id()));
+        }
+    }
+
+    /// RTT по эху Ping: метка из прошлого даёт разницу, из будущего или
+    /// короткий payload замером не считаются.
+    #[test]
+    fn test_pong_rtt_from_ping_timestamp() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let rtt = pong_rtt(&(now - 40).to_be_bytes()).unwrap();
+        assert!(rtt >= Duration::from_millis(40) && rtt < Duration::from_secs(5));
+        assert_eq!(pong_rtt(&(now + 60_000).to_be_bytes()), None);
+        assert_eq!(pong_rtt(&[0, 1, 2]), None);
+
+        let health = RelayHealth::new();
+        assert_eq!(health.rtt(), None);
+        health.record_rtt(Duration::from_millis(80));
+        health.record_rtt(Duration::from_millis(160));
+        assert_eq!(health.rtt(), Some(Duration::from_millis(90)));
+    }
 }
@@ -12,6 +12,12 @@
 //! On per-slot failure (BrokenPipe / TimedOut) `open_stream` walks to
 //! the next slot and tries there; failed slots are reconnected lazily on
 //! the next call that lands on them.
+//!
+//! Striped mode (`MuxPool::new_striped`): all slots join one
+//! `StripeGroup`, so a single heavy stream spreads its bulk frames across
+//! every tunnel instead of riding the one it was opened on. Only for one
+//! server — the server reassembles the stripes, so members of a group must
+//! all terminate on the same `xr-server`.
 
 use std::future::Future;
 use std::io;
@@ -24,7 +30,10 @@ use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
-use crate::mux::{mux_handshake_client, mux_open_stream, Multiplexer, MuxStream, RelayHealth};
+use crate::mux::{
+    mux_handshake_client, mux_handshake_client_striped, mux_open_stream, mux_open_udp,
+    Multiplexer, MuxDatagram, MuxStream, RelayHealth, StripeGroup,
+};
 use crate::protocol::{Codec, TargetAddr};
 
 /// Factory for creating TCP connections to the server.
@@ -76,7 +85,9 @@ const PROBE_LEASE: Duration = Duration::from_secs(15);
 /// Client-side connection pool over multiple parallel multiplexed tunnels.
 pub struct MuxPool {
     connect_fn: ConnectFn,
-    codec: Codec,
+    /// Кодек новых туннелей. Ротация ключа подменяет его на месте
+    /// (`set_codec`), живые туннели доживают на своём.
+    codec: std::sync::RwLock<Codec>,
     slots: Vec<Mutex<Option<Arc<Multiplexer>>>>,
     /// Per-slot consecutive ConnectAck-timeout counter. Reset on any successful
     /// open_stream or on invalidation. Reaching `MAX_CONSECUTIVE_TIMEOUTS`
@@ -96,6 +107,11 @@ pub struct MuxPool {
     /// `ServerPool` ловит сервер с мёртвым DNS/egress, у которого туннель и
     /// keepalive живы, а сама работа падает.
     relay_health: Arc<RelayHealth>,
+    /// Группа multipath, в которую вступают все слоты. `None` —
+    /// обычный пул: стрим целиком живёт на своём туннеле. Группа меняется
+    /// на свежую в `recycle`, чтобы новые туннели не вступали в группу,
+    /// чьи члены на сервере ещё доживают на старой сети.
+    stripe: Option<std::sync::Mutex<Arc<StripeGroup>>>,
 }
 
 impl MuxPool {
@@ -103,6 +119,23 @@ impl MuxPool {
     /// `DEFAULT_POOL_SIZE` so callers can pass through config defaults
     /// without panicking on a misconfigured zero.
     pub fn new(connect_fn: ConnectFn, codec: Codec, size: usize) -> Arc<Self> {
+        Self::build(connect_fn, codec, size, None)
+    }
+
+    /// Пул, слоты которого раскладывают балк каждого стрима по всем
+    /// туннелям. Сервер без поддержки полос согласует обычный mux,
+    /// и пул работает как `new`.
+    pub fn new_striped(connect_fn: ConnectFn, codec: Codec, size: usize) -> Arc<Self> {
+        let group = std::sync::Mutex::new(StripeGroup::new_client());
+        Self::build(connect_fn, codec, size, Some(group))
+    }
+
+    fn build(
+        connect_fn: ConnectFn,
+        codec: Codec,
+        size: usize,
+        stripe: Option<std::sync::Mutex<Arc<StripeGroup>>>,
+    ) -> Arc<Self> {
         let size = if size == 0 { DEFAULT_POOL_SIZE } else { size };
         let mut slots = Vec::with_capacity(size);
         let mut timeout_counters = Vec::with_capacity(size);
@@ -112,16 +145,32 @@ impl MuxPool {
         }
         Arc::new(Self {
             connect_fn,
-            codec,
+            codec: std::sync::RwLock::new(codec),
             slots,
             timeout_counters,
             next: AtomicUsize::new(0),
             down_until_ms: AtomicU64::new(0),
             created: Instant::now(),
             relay_health: Arc::new(RelayHealth::new()),
+            stripe,
         })
     }
 
+    /// Сменить ключ для следующих подключений. Сервер сверяет ключ только по
+    /// первому кадру, поэтому поднятые туннели не переподнимаются: они
+    /// доживают на старом, а новые слоты и пробы идут уже с новым.
+    pub fn set_codec(&self, codec: Codec) {
+        *self.codec.write().unwrap() = codec;
+    }
+
+    fn codec(&self) -> Codec {
+        self.codec.read().unwrap().clone()
+    }
+
+    fn stripe_group(&self) -> Option<Arc<StripeGroup>> {
+        self.stripe.as_ref().map(|g| g.lock().unwrap().clone())
+    }
+
     /// Relay активного сервера деградировал по live-трафику: Connect'ы
     /// формально успешны (ConnectAck приходит), но сама работа (resolve или
     /// connect до апстрима на VPS) массово падает. Порог и окно см.
@@ -130,6 +179,11 @@ impl MuxPool {
         self.relay_health.degraded()
     }
 
+    /// Сглаженный RTT до сервера (Pong keepalive и свежие пробы).
+    pub fn rtt(&self) -> Option<Duration> {
+        self.relay_health.rtt()
+    }
+
     /// Доступ к счётчикам исходов relay (сброс окна из `ServerPool`, тесты).
     pub(crate) fn relay_health(&self) -> &Arc<RelayHealth> {
         &self.relay_health
@@ -224,6 +278,25 @@ impl MuxPool {
     /// the next slot is tried. After walking all slots, the last error
     /// is returned.
     pub async fn open_stream(&self, target: &TargetAddr) -> io::Result<MuxStream> {
+        self.open_with(|mux| async move { mux_open_stream(&mux, target).await })
+            .await
+    }
+
+    /// Open a datagram association (UDP over mux) through one of the pool's
+    /// tunnels. Same slot walk and breaker as `open_stream`; `Unsupported`
+    /// (server without the udp capability) returns at once, the caller falls
+    /// back to the standalone UDP relay.
+    pub async fn open_udp(&self) -> io::Result<MuxDatagram> {
+        self.open_with(|mux| async move { mux_open_udp(&mux).await }).await
+    }
+
+    /// Slot walk shared by `open_stream`/`open_udp`: `open` runs on the chosen
+    /// slot's mux, its BrokenPipe/TimedOut move on to the next slot.
+    async fn open_with<T, F, Fut>(&self, open: F) -> io::Result<T>
+    where
+        F: Fn(Arc<Multiplexer>) -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
         // Circuit-breaker gate. When the server is presumed down we avoid
         // re-walking every dead slot (which costs ~8s on a silent server) and
         // instead fail fast so the caller falls open to Direct immediately.
@@ -289,7 +362,7 @@ impl MuxPool {
                 }
             };
 
-            match mux_open_stream(&mux, target).await {
+            match open(mux).await {
                 Ok(stream) => {
                     // Successful open — reset the timeout counter so a future
                     // burst doesn't accumulate on top of past transient failures.
@@ -411,14 +484,25 @@ impl MuxPool {
         }
 
         let mut stream = (self.connect_fn)().await?;
-        match mux_handshake_client(&mut stream, &self.codec).await {
+        let group = self.stripe_group();
+        let codec = self.codec();
+        match mux_handshake_client_striped(&mut stream, &codec, group.as_deref()).await {
             Ok(Some(caps)) => {
-                let mux = Multiplexer::new_client_tracked(
-                    stream,
-                    self.codec.clone(),
-                    self.relay_health.clone(),
-                    caps,
-                );
+                let mux = match group {
+                    Some(group) if caps.stripe => Multiplexer::new_client_striped(
+                        stream,
+                        codec,
+                        self.relay_health.clone(),
+                        caps,
+                        group,
+                    ),
+                    _ => Multiplexer::new_client_tracked(
+                        stream,
+                        codec,
+                        self.relay_health.clone(),
+                        caps,
+                    ),
+                };
                 *guard = Some(mux.clone());
                 tracing::info!("mux slot {} connection established", idx);
                 Ok(mux)
@@ -444,10 +528,15 @@ impl MuxPool {
     /// breaker is cleared so the next `open_stream` doesn't short-circuit on a
     /// stale cooldown. The probe connection is dropped immediately (its FIN
     /// closes the server-side mux cleanly).
+    ///
+    /// The handshake is a single round-trip on an established TCP, so its
+    /// duration doubles as an RTT sample for latency-aware server selection.
     pub async fn probe_fresh(&self) -> io::Result<()> {
         let mut stream = (self.connect_fn)().await?;
-        match mux_handshake_client(&mut stream, &self.codec).await {
+        let started = Instant::now();
+        match mux_handshake_client(&mut stream, &self.codec()).await {
             Ok(Some(_)) => {
+                self.relay_health.record_rtt(started.elapsed());
                 self.clear_breaker();
                 Ok(())
             }
@@ -477,8 +566,12 @@ impl MuxPool {
             self.invalidate_slot(idx).await;
         }
         self.clear_breaker();
-        // Исходы relay набраны на прежней сети и устарели вместе с ней.
+        // Исходы relay и RTT набраны на прежней сети и устарели вместе с ней.
         self.relay_health.reset();
+        self.relay_health.reset_rtt();
+        if let Some(ref group) = self.stripe {
+            *group.lock().unwrap() = StripeGroup::new_client();
+        }
     }
 
     async fn invalidate_slot(&self, idx: usize) {
@@ -515,6 +608,52 @@ mod tests {
         })
     }
 
+    /// Ротация ключа: после `set_codec` новые подключения пула идут с новым
+    /// ключом. Сервер в тесте знает только новый ключ и рвёт всё остальное,
+    /// как xr-server после даты переключения.
+    #[tokio::test]
+    async fn test_set_codec_rekeys_new_connections() {
+        use crate::mux::mux_handshake_server;
+        use crate::protocol::Command;
+        use tokio::io::AsyncReadExt;
+
+        let rotated = Codec::new(
+            Obfuscator::new(
+                b"rotated-key-32-bytes-long-enough".to_vec(),
+                7,
+                ModifierStrategy::PositionalXorRotate,
+            ),
+            0,
+            0,
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_codec = rotated.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                let codec = server_codec.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 512];
+                    let n = tcp.read(&mut buf).await.unwrap_or(0);
+                    if let Ok(Some((init, _))) = codec.decode_frame(&buf[..n]) {
+                        if init.command == Command::MuxInit {
+                            let _ = mux_handshake_server(&mut tcp, &codec, &init).await;
+                            let _ = tcp.read(&mut buf).await;
+                        }
+                    }
+                });
+            }
+        });
+        let connect_fn: ConnectFn =
+            Arc::new(move || Box::pin(async move { TcpStream::connect(addr).await }));
+
+        let pool = MuxPool::new(connect_fn, test_codec(), 1);
+        assert!(pool.probe_fresh().await.is_err(), "старый ключ сервер не принимает");
+        pool.set_codec(rotated);
+        pool.probe_fresh().await.expect("новый ключ обязан пройти рукопожатие");
+    }
+
     #[tokio::test]
     async fn test_pool_size_zero_uses_default() {
         let pool = MuxPool::new(always_failing_connect(), test_codec(), 0);
@@ -1,7 +1,9 @@
 /// Shared data types for xr-hub control-plane: presets and invites.
 use serde::{Deserialize, Serialize};
 
-use crate::config::RoutingConfig;
+use crate::config::{decode_key, RoutingConfig};
+use crate::obfuscation::{ModifierStrategy, Obfuscator};
+use crate::protocol::Codec;
 
 /// Full preset with routing rules, versioning, and optional signature.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,10 +14,45 @@ pub struct Preset {
     #[serde(default)]
     pub description: String,
     pub rules: RoutingConfig,
+    /// Следующий ключ обфускации профиля и момент переключения на него.
+    /// Хаб кладёт его в каждый пресет и подписывает вместе с правилами, так
+    /// что до клиентов он доезжает тем же long-poll, без перевыпуска
+    /// инвайтов. Без значения поле не сериализуется, и подписи пресетов,
+    /// выданные до него, остаются действительными.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_key: Option<NextKey>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub signature: Option<String>,
 }
 
+/// Ключ, на который клиенты переходят в `switch_at` (unix-секунды). До этого
+/// момента работает ключ из конфига или инвайта: сервер принимает оба, пока
+/// открыты окна ротации, а после `switch_at` старый отвергает.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NextKey {
+    pub obfuscation_key: String,
+    pub modifier: String,
+    pub salt: u64,
+    pub switch_at: u64,
+}
+
+impl NextKey {
+    /// Наступил ли момент переключения.
+    pub fn is_due(&self, now: u64) -> bool {
+        now >= self.switch_at
+    }
+
+    /// Кодек нового ключа. Паддинг свой у каждого клиента и ротацией не
+    /// меняется, поэтому приходит от вызывающего.
+    pub fn codec(&self, padding_min: u8, padding_max: u8) -> Result<Codec, String> {
+        let key = decode_key(&self.obfuscation_key).map_err(|e| format!("next key: {e}"))?;
+        let strategy = ModifierStrategy::from_str(&self.modifier)
+            .ok_or_else(|| format!("next key: unknown modifier {:?}", self.modifier))?;
+        let obfuscator = Obfuscator::new(key, self.salt as u32, strategy);
+        Ok(Codec::new(obfuscator, padding_min, padding_max))
+    }
+}
+
 /// Lightweight summary for listing presets (version check without full rules).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PresetSummary {
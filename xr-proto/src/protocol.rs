@@ -53,6 +53,21 @@ pub enum Command {
     /// Bidirectional: return receive-window credit for a stream (LLD-27).
     /// Payload after the stream_id prefix: u32 BE, bytes of credit returned.
     WindowUpdate = 9,
+    /// Client → Server: open a datagram association on this stream_id (UDP
+    /// over mux). Answered with ConnectAck or Close, like Connect.
+    UdpAssociate = 10,
+    /// Bidirectional: one datagram of an association. Payload after the
+    /// stream_id prefix: encoded `TargetAddr` (destination upstream, source
+    /// downstream) followed by the datagram body.
+    UdpData = 11,
+    /// Bidirectional: Data of a striped stream (multipath, only with the stripe
+    /// capability). Payload after the stream_id prefix: u32 BE sequence number,
+    /// then the data. Any tunnel of the stripe group may carry it.
+    StripeData = 12,
+    /// Bidirectional: Close of a striped stream. Payload after the stream_id
+    /// prefix: u32 BE sequence number (count of StripeData sent before it),
+    /// then the optional close reason.
+    StripeClose = 13,
 }
 
 /// Причина закрытия стрима в payload Close (байт после stream_id), сервер ->
@@ -76,6 +91,10 @@ pub const CLOSE_REASON_RELAY_BUSY: u8 = 4;
 /// этом живёт. В здоровье сервера не считается: сервер исправен, это клиент
 /// вышел за свою долю, и failover на backup от такого отказа не нужен.
 pub const CLOSE_REASON_STREAM_LIMIT: u8 = 5;
+/// Relay -> потребитель: месячный транзитный бюджет шары или её агента на этом
+/// relay выбран. Отдельный код, чтобы исчерпанный лимит не выглядел ни
+/// перегрузом, ни агент-офлайн: до нового месяца повтор бесполезен.
+pub const CLOSE_REASON_BUDGET_EXHAUSTED: u8 = 6;
 
 impl Command {
     fn from_byte(b: u8) -> Option<Self> {
@@ -93,6 +112,10 @@ impl Command {
             7 => Some(Self::Ping),
             8 => Some(Self::Pong),
             9 => Some(Self::WindowUpdate),
+            10 => Some(Self::UdpAssociate),
+            11 => Some(Self::UdpData),
+            12 => Some(Self::StripeData),
+            13 => Some(Self::StripeClose),
             _ => None,
         }
     }
@@ -124,6 +147,19 @@ pub fn decode_mux_payload(payload: &[u8]) -> io::Result<(u32, &[u8])> {
     Ok((stream_id, &payload[4..]))
 }
 
+/// Encode a UdpData body (after the stream_id prefix): [TargetAddr][datagram...].
+pub fn encode_datagram(addr: &TargetAddr, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buf = addr.encode()?;
+    buf.extend_from_slice(data);
+    Ok(buf)
+}
+
+/// Decode a UdpData body: returns (address, datagram_slice).
+pub fn decode_datagram(body: &[u8]) -> io::Result<(TargetAddr, &[u8])> {
+    let (addr, consumed) = TargetAddr::decode(body)?;
+    Ok((addr, &body[consumed..]))
+}
+
 // ── Address types ────────────────────────────────────────────────────
 
 /// Target address for Connect command.
@@ -243,6 +279,12 @@ impl Codec {
         }
     }
 
+    /// The key this codec obfuscates with, for framings outside the mux
+    /// (the standalone UDP relay).
+    pub fn obfuscator(&self) -> &Obfuscator {
+        &self.obfuscator
+    }
+
     /// Encode a frame into wire bytes.
     pub fn encode_frame(&self, command: Command, payload: &[u8]) -> io::Result<Vec<u8>> {
         if payload.len() > MAX_PAYLOAD_LEN {
@@ -449,6 +491,20 @@ mod tests {
         let (frame, _) = codec.decode_frame(&wire).unwrap().unwrap();
         assert_eq!(frame.command, Command::WindowUpdate);
         assert_eq!(frame.payload, payload);
+
+        // UdpAssociate/UdpData: датаграммы поверх mux
+        for command in [
+            Command::UdpAssociate,
+            Command::UdpData,
+            Command::StripeData,
+            Command::StripeClose,
+        ] {
+            let payload = encode_mux_payload(9, b"dgram");
+            let wire = codec.encode_frame(command, &payload).unwrap();
+            let (frame, _) = codec.decode_frame(&wire).unwrap().unwrap();
+            assert_eq!(frame.command, command);
+            assert_eq!(frame.payload, payload);
+        }
     }
 
     #[test]
@@ -468,6 +524,24 @@ mod tests {
         assert!(decoded.is_empty());
     }
 
+    #[test]
+    fn test_datagram_roundtrip() {
+        let addr = TargetAddr::Domain("dns.google".to_string(), 53);
+        let body = encode_datagram(&addr, b"\x12\x34query").unwrap();
+        let (decoded, data) = decode_datagram(&body).unwrap();
+        match decoded {
+            TargetAddr::Domain(d, p) => {
+                assert_eq!(d, "dns.google");
+                assert_eq!(p, 53);
+            }
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(data, b"\x12\x34query");
+
+        // Обрезанный адрес это ошибка, а не пустая датаграмма.
+        assert!(decode_datagram(&body[..3]).is_err());
+    }
+
     #[test]
     fn test_partial_buffer() {
         let codec = test_codec();
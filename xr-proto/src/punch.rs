@@ -0,0 +1,521 @@
+//! Direct consumer <-> agent path through NAT, with the relay as rendezvous.
+//!
+//! Every relayed byte crosses the relay VPS twice. When both NATs map a UDP
+//! socket to one stable public endpoint (the common, non-symmetric kind), the two
+//! sides can reach each other directly once each has sent a datagram towards the
+//! other's public endpoint. The relay helps with exactly two things:
+//!
+//! - **Reflection.** A UDP reflector on the relay's port answers a `whoami`
+//!   datagram with the source endpoint it saw, so each side learns its public
+//!   mapping ([`observe`]).
+//! - **Exchange.** The consumer opens a relay stream on [`RELAY_PUNCH_TARGET`]
+//!   with its usual relay token; the relay verifies it and splices it to a
+//!   [`RELAY_PUNCH_REVERSE_TARGET`] stream on the agent, exactly like transit.
+//!   The consumer sends a [`PunchOffer`] with its endpoint, the agent answers a
+//!   [`PunchAnswer`] with its own.
+//!
+//! Both then spray punch datagrams at each other and the consumer dials QUIC to
+//! the agent's endpoint. The QUIC TLS is pinned to the agent's identity key with
+//! the same [`PinnedAgentVerifier`](crate::relay_tls::PinnedAgentVerifier) as
+//! relay transit, so the direct path trusts exactly what the splice trusts. A
+//! QUIC stream then carries what a relay stream carries (the E2E TLS to the
+//! agent), and callers can't tell which path served them. Any failure leaves the
+//! consumer on the splice.
+//!
+//! Both sides being on one LAN is not handled here: the grant's LAN addresses
+//! already take that case without the relay.
+//!
+//! [`RELAY_PUNCH_TARGET`]: crate::relay_client::RELAY_PUNCH_TARGET
+//! [`RELAY_PUNCH_REVERSE_TARGET`]: crate::relay_client::RELAY_PUNCH_REVERSE_TARGET
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+/// First bytes of every rendezvous datagram.
+pub const PUNCH_MAGIC: [u8; 4] = *b"XRP1";
+const KIND_WHOAMI: u8 = 1;
+const KIND_SEEN: u8 = 2;
+const KIND_PUNCH: u8 = 3;
+const TXID_LEN: usize = 8;
+/// Size of a reflector request. The answer (magic, kind, txid and the address
+/// as text, at most 47 bytes for IPv6) always fits in it, so the reflector
+/// never sends more than it received: a spoofed source gains no amplification.
+pub const WHOAMI_LEN: usize = 64;
+/// Reflector attempts before giving up: UDP may drop any single datagram.
+const OBSERVE_TRIES: usize = 3;
+/// How long one reflector attempt waits for the answer.
+const OBSERVE_WAIT: Duration = Duration::from_millis(400);
+/// Spacing of punch datagrams while the NAT mappings open.
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The consumer's half of the exchange over the rendezvous stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PunchOffer {
+    /// Public UDP endpoint the relay's reflector saw, `ip:port`.
+    pub endpoint: String,
+    /// The relay token the consumer opened the rendezvous with. The agent
+    /// never sees the relay's check, so it verifies the token itself and
+    /// keeps the link no longer than the token's `exp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<crate::share::RelayToken>,
+}
+
+/// The agent's half: its own public endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PunchAnswer {
+    pub endpoint: String,
+}
+
+/// A `whoami` request carrying `txid`, zero-padded to [`WHOAMI_LEN`].
+pub fn whoami_request(txid: [u8; TXID_LEN]) -> [u8; WHOAMI_LEN] {
+    let mut req = [0u8; WHOAMI_LEN];
+    req[..4].copy_from_slice(&PUNCH_MAGIC);
+    req[4] = KIND_WHOAMI;
+    req[5..5 + TXID_LEN].copy_from_slice(&txid);
+    req
+}
+
+/// The reflector's answer to `request` from `from`, or `None` if the datagram
+/// isn't a well-formed `whoami` (anything else is dropped silently).
+pub fn reflect(request: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+    if request.len() != WHOAMI_LEN || request[..4] != PUNCH_MAGIC || request[4] != KIND_WHOAMI {
+        return None;
+    }
+    let mut reply = Vec::with_capacity(WHOAMI_LEN);
+    reply.extend_from_slice(&PUNCH_MAGIC);
+    reply.push(KIND_SEEN);
+    reply.extend_from_slice(&request[5..5 + TXID_LEN]);
+    reply.extend_from_slice(from.to_string().as_bytes());
+    Some(reply)
+}
+
+/// The endpoint in a reflector answer, if it answers `txid`.
+pub fn parse_seen(reply: &[u8], txid: &[u8; TXID_LEN]) -> Option<SocketAddr> {
+    let head = 5 + TXID_LEN;
+    if reply.len() <= head || reply[..4] != PUNCH_MAGIC || reply[4] != KIND_SEEN {
+        return None;
+    }
+    if &reply[5..head] != txid {
+        return None;
+    }
+    std::str::from_utf8(&reply[head..]).ok()?.parse().ok()
+}
+
+/// Ask the reflector which public endpoint `sock` maps to.
+pub async fn observe(sock: &UdpSocket, reflector: SocketAddr) -> io::Result<SocketAddr> {
+    let txid: [u8; TXID_LEN] = rand::random();
+    let req = whoami_request(txid);
+    let mut buf = [0u8; WHOAMI_LEN];
+    for _ in 0..OBSERVE_TRIES {
+        sock.send_to(&req, reflector).await?;
+        let wait = tokio::time::sleep(OBSERVE_WAIT);
+        tokio::pin!(wait);
+        loop {
+            tokio::select! {
+                _ = &mut wait => break,
+                r = sock.recv_from(&mut buf) => {
+                    let (n, from) = r?;
+                    if from != reflector {
+                        continue;
+                    }
+                    if let Some(seen) = parse_seen(&buf[..n], &txid) {
+                        return Ok(seen);
+                    }
+                }
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "punch: reflector did not answer"))
+}
+
+/// Send punch datagrams to `peer` for `duration`. They only exist to open this
+/// side's NAT mapping towards the peer; the peer drops them unread.
+pub async fn spray(sock: &UdpSocket, peer: SocketAddr, duration: Duration) {
+    let datagram = [PUNCH_MAGIC[0], PUNCH_MAGIC[1], PUNCH_MAGIC[2], PUNCH_MAGIC[3], KIND_PUNCH];
+    let deadline = tokio::time::Instant::now() + duration;
+    while tokio::time::Instant::now() < deadline {
+        if sock.send_to(&datagram, peer).await.is_err() {
+            return;
+        }
+        tokio::time::sleep(PUNCH_INTERVAL).await;
+    }
+}
+
+/// Resolve the relay's `host:port` to the reflector address (same port, UDP).
+pub async fn reflector_addr(relay_dial: &str) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host(relay_dial)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("resolve {relay_dial}")))
+}
+
+/// A fresh UDP socket of the reflector's address family. Returned as std so it
+/// can be cloned before QUIC takes it over: the clone keeps sending punch
+/// datagrams from the very port QUIC uses.
+pub fn bind_for(reflector: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let any: SocketAddr = if reflector.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let sock = std::net::UdpSocket::bind(any)?;
+    sock.set_nonblocking(true)?;
+    Ok(sock)
+}
+
+#[cfg(feature = "punch")]
+pub use quic::*;
+
+/// The QUIC half: pinned endpoints, the punched link and the two rendezvous
+/// roles. Behind the `punch` feature, since it pulls quinn.
+#[cfg(feature = "punch")]
+mod quic {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::UdpSocket;
+
+    use super::{bind_for, observe, reflector_addr, spray, PunchAnswer, PunchOffer};
+    use crate::mux::{MuxStream, Multiplexer};
+    use crate::relay_client::{open_relay_target, RELAY_PUNCH_TARGET};
+    use crate::share::RelayToken;
+
+    /// ALPN of the punched QUIC link.
+    pub const PUNCH_ALPN: &[u8] = b"xr-punch/1";
+    /// How long the exchange over the rendezvous stream may take.
+    pub const RENDEZVOUS_TIMEOUT: Duration = Duration::from_secs(5);
+    /// How long the QUIC handshake through fresh NAT mappings may take.
+    pub const PUNCH_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    /// How long each side sprays punch datagrams: the whole connect window.
+    const PUNCH_SPRAY: Duration = Duration::from_secs(5);
+    /// An idle link is closed after this long.
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+    /// Keep-alive on the link, well inside common NAT UDP timeouts (30 s+).
+    const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+    fn quic_err(e: impl std::fmt::Display) -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionRefused, format!("punch: {e}"))
+    }
+
+    fn transport() -> Arc<quinn::TransportConfig> {
+        let mut t = quinn::TransportConfig::default();
+        t.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().expect("idle timeout in range")));
+        t.keep_alive_interval(Some(KEEP_ALIVE));
+        // The agent opens no streams towards the consumer.
+        t.max_concurrent_uni_streams(0u8.into());
+        Arc::new(t)
+    }
+
+    /// A punched QUIC link to the peer. The endpoint is kept alongside the
+    /// connection: it owns the socket and drives the connection.
+    pub struct PunchedLink {
+        endpoint: quinn::Endpoint,
+        conn: quinn::Connection,
+    }
+
+    impl PunchedLink {
+        pub fn is_alive(&self) -> bool {
+            self.conn.close_reason().is_none()
+        }
+
+        pub fn remote(&self) -> SocketAddr {
+            self.conn.remote_address()
+        }
+
+        /// Open one stream to the agent (consumer side).
+        pub async fn open(&self) -> io::Result<PunchedIo> {
+            let (send, recv) = self.conn.open_bi().await.map_err(quic_err)?;
+            Ok(PunchedIo { send, recv })
+        }
+
+        /// The next stream the consumer opened (agent side); `None` once the
+        /// link is gone.
+        pub async fn accept(&self) -> Option<PunchedIo> {
+            let (send, recv) = self.conn.accept_bi().await.ok()?;
+            Some(PunchedIo { send, recv })
+        }
+    }
+
+    impl Drop for PunchedLink {
+        fn drop(&mut self) {
+            self.conn.close(0u8.into(), b"");
+            self.endpoint.close(0u8.into(), b"");
+        }
+    }
+
+    /// One QUIC stream as an `AsyncRead + AsyncWrite` byte channel, the punched
+    /// counterpart of a relay [`MuxStreamIo`](crate::mux::MuxStreamIo).
+    pub struct PunchedIo {
+        send: quinn::SendStream,
+        recv: quinn::RecvStream,
+    }
+
+    impl AsyncRead for PunchedIo {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().recv).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for PunchedIo {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            AsyncWrite::poll_write(Pin::new(&mut self.get_mut().send), cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().send), cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            AsyncWrite::poll_shutdown(Pin::new(&mut self.get_mut().send), cx)
+        }
+    }
+
+    /// Spray towards `peer` from a clone of `sock`, in the background.
+    fn spawn_spray(sock: &std::net::UdpSocket, peer: SocketAddr) -> io::Result<()> {
+        let sprayer = UdpSocket::from_std(sock.try_clone()?)?;
+        tokio::spawn(async move { spray(&sprayer, peer, PUNCH_SPRAY).await });
+        Ok(())
+    }
+
+    fn endpoint(
+        sock: std::net::UdpSocket,
+        server: Option<quinn::ServerConfig>,
+    ) -> io::Result<quinn::Endpoint> {
+        quinn::Endpoint::new(
+            quinn::EndpointConfig::default(),
+            server,
+            sock,
+            Arc::new(quinn::TokioRuntime),
+        )
+    }
+
+    /// Consumer side: dial QUIC from `sock` (already observed by the reflector)
+    /// to the agent's public endpoint, pinned to `agent_pubkey`.
+    pub async fn dial(
+        sock: std::net::UdpSocket,
+        agent: SocketAddr,
+        agent_pubkey: &str,
+    ) -> io::Result<PunchedLink> {
+        let mut tls = crate::relay_tls::pinned_client_config(agent_pubkey)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        tls.alpn_protocols = vec![PUNCH_ALPN.to_vec()];
+        let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(tls).map_err(quic_err)?;
+        let mut cfg = quinn::ClientConfig::new(Arc::new(crypto));
+        cfg.transport_config(transport());
+
+        spawn_spray(&sock, agent)?;
+        let endpoint = endpoint(sock, None)?;
+        let connecting = endpoint.connect_with(cfg, agent, "xr-share-agent").map_err(quic_err)?;
+        let conn = tokio::time::timeout(PUNCH_CONNECT_TIMEOUT, connecting)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "punch: QUIC connect timed out"))?
+            .map_err(quic_err)?;
+        Ok(PunchedLink { endpoint, conn })
+    }
+
+    /// Agent side: serve QUIC on `sock` with the identity certificate and
+    /// accept the one connection coming from the consumer's address. Anything
+    /// from elsewhere is refused.
+    pub async fn listen(
+        sock: std::net::UdpSocket,
+        consumer: SocketAddr,
+        identity: &rustls::ServerConfig,
+    ) -> io::Result<PunchedLink> {
+        let mut tls = identity.clone();
+        tls.alpn_protocols = vec![PUNCH_ALPN.to_vec()];
+        let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls).map_err(quic_err)?;
+        let mut cfg = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        cfg.transport_config(transport());
+
+        spawn_spray(&sock, consumer)?;
+        let endpoint = endpoint(sock, Some(cfg))?;
+        let accept = async {
+            while let Some(incoming) = endpoint.accept().await {
+                if incoming.remote_address().ip() != consumer.ip() {
+                    incoming.refuse();
+                    continue;
+                }
+                return incoming.await.map_err(quic_err);
+            }
+            Err(quic_err("endpoint closed"))
+        };
+        let conn = tokio::time::timeout(PUNCH_CONNECT_TIMEOUT, accept)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "punch: no QUIC from the consumer"))??;
+        Ok(PunchedLink { endpoint, conn })
+    }
+
+    /// The consumer's whole rendezvous through one relay: learn the public
+    /// endpoint, trade it for the agent's over a punch stream, then dial.
+    pub async fn punch_consumer(
+        mux: &Arc<Multiplexer>,
+        token: &RelayToken,
+        relay_dial: &str,
+    ) -> io::Result<PunchedLink> {
+        let reflector = reflector_addr(relay_dial).await?;
+        let sock = bind_for(reflector)?;
+        let mine = observe(&UdpSocket::from_std(sock.try_clone()?)?, reflector).await?;
+
+        let mut stream = open_relay_target(mux, token, RELAY_PUNCH_TARGET).await?;
+        let offer = PunchOffer { endpoint: mine.to_string(), token: Some(token.clone()) };
+        let offer = serde_json::to_vec(&offer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        stream.send(&offer).await?;
+        let answer = tokio::time::timeout(RENDEZVOUS_TIMEOUT, stream.recv())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "punch: agent did not answer"))?
+            .ok_or_else(|| quic_err("agent refused the rendezvous"))?;
+        drop(stream);
+        let answer: PunchAnswer = serde_json::from_slice(&answer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("punch answer: {e}")))?;
+        let agent: SocketAddr = answer
+            .endpoint
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("punch answer: {e}")))?;
+        dial(sock, agent, &token.agent_pubkey).await
+    }
+
+    /// The agent's whole rendezvous on a punch-reverse stream: read the offer,
+    /// learn the own endpoint through the same relay, answer, then listen.
+    /// `admit` judges the offer before any socket is bound; its verdict comes
+    /// back with the link.
+    pub async fn punch_agent<T>(
+        mut stream: MuxStream,
+        relay_dial: &str,
+        identity: &rustls::ServerConfig,
+        admit: impl FnOnce(&PunchOffer) -> io::Result<T>,
+    ) -> io::Result<(PunchedLink, T)> {
+        let offer = tokio::time::timeout(RENDEZVOUS_TIMEOUT, stream.recv())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "punch: no offer"))?
+            .ok_or_else(|| quic_err("consumer left the rendezvous"))?;
+        let offer: PunchOffer = serde_json::from_slice(&offer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("punch offer: {e}")))?;
+        let admitted = admit(&offer)?;
+        let consumer: SocketAddr = offer
+            .endpoint
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("punch offer: {e}")))?;
+
+        let reflector = reflector_addr(relay_dial).await?;
+        let sock = bind_for(reflector)?;
+        let mine = observe(&UdpSocket::from_std(sock.try_clone()?)?, reflector).await?;
+        let answer = serde_json::to_vec(&PunchAnswer { endpoint: mine.to_string() })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        stream.send(&answer).await?;
+        Ok((listen(sock, consumer, identity).await?, admitted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflector_answers_only_whoami_and_never_amplifies() {
+        let txid = [7u8; TXID_LEN];
+        let from: SocketAddr = "[2001:db8:ffff:ffff:ffff:ffff:ffff:ffff]:65535".parse().unwrap();
+        let reply = reflect(&whoami_request(txid), from).expect("a whoami gets an answer");
+        assert!(reply.len() <= WHOAMI_LEN, "answer must not outgrow the request");
+        assert_eq!(parse_seen(&reply, &txid), Some(from));
+        assert_eq!(parse_seen(&reply, &[8u8; TXID_LEN]), None, "foreign txid");
+
+        assert!(reflect(&whoami_request(txid)[..20], from).is_none(), "short request");
+        assert!(reflect(&reply, from).is_none(), "an answer is not a request");
+        assert!(reflect(&[0u8; WHOAMI_LEN], from).is_none());
+    }
+
+    #[tokio::test]
+    async fn observe_learns_the_endpoint_from_a_reflector() {
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflector_at = reflector.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            // Lose the first request: the client must retry.
+            let _ = reflector.recv_from(&mut buf).await.unwrap();
+            loop {
+                let (n, from) = reflector.recv_from(&mut buf).await.unwrap();
+                if let Some(reply) = reflect(&buf[..n], from) {
+                    reflector.send_to(&reply, from).await.unwrap();
+                }
+            }
+        });
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let seen = observe(&sock, reflector_at).await.unwrap();
+        assert_eq!(seen, sock.local_addr().unwrap());
+    }
+
+    /// Прямой QUIC-путь держит тот же пин, что и relay-TLS: агент с ключом из
+    /// токена отвечает по стриму, чужой ключ рвёт хендшейк.
+    #[cfg(feature = "punch")]
+    #[tokio::test]
+    async fn punched_link_pins_agent_key() {
+        use crate::relay_tls::{cert_ed25519_spki, identity_server_config};
+        use base64::Engine as _;
+        use rustls::pki_types::PrivateKeyDer;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let kp = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let params = rcgen::CertificateParams::new(vec!["xr-share-agent".to_string()]).unwrap();
+        let cert_der = params.self_signed(&kp).unwrap().der().to_vec();
+        let spki = cert_ed25519_spki(&cert_der).unwrap();
+        let key_der = PrivateKeyDer::try_from(kp.serialize_der()).unwrap();
+        let server_cfg = identity_server_config(cert_der, key_der).unwrap();
+        let pubkey = base64::engine::general_purpose::STANDARD.encode(spki);
+
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let bind = || {
+            let sock = std::net::UdpSocket::bind(loopback).unwrap();
+            sock.set_nonblocking(true).unwrap();
+            sock
+        };
+        let pair = || (bind(), bind());
+
+        let (agent, consumer) = pair();
+        let (agent_at, consumer_at) = (agent.local_addr().unwrap(), consumer.local_addr().unwrap());
+        let cfg = server_cfg.clone();
+        tokio::spawn(async move {
+            let link = listen(agent, consumer_at, &cfg).await.unwrap();
+            while let Some(mut io) = link.accept().await {
+                let mut got = [0u8; 4];
+                io.read_exact(&mut got).await.unwrap();
+                io.write_all(b"pong").await.unwrap();
+                io.shutdown().await.unwrap();
+            }
+        });
+        let link = dial(consumer, agent_at, &pubkey).await.expect("пин совпал");
+        assert!(link.is_alive());
+        let mut io = link.open().await.unwrap();
+        io.write_all(b"ping").await.unwrap();
+        let mut got = [0u8; 4];
+        io.read_exact(&mut got).await.unwrap();
+        assert_eq!(&got, b"pong");
+
+        let (agent, consumer) = pair();
+        let (agent_at, consumer_at) = (agent.local_addr().unwrap(), consumer.local_addr().unwrap());
+        tokio::spawn(async move {
+            let _ = listen(agent, consumer_at, &server_cfg).await;
+        });
+        let mut wrong = spki;
+        wrong[0] ^= 0xFF;
+        let alien = base64::engine::general_purpose::STANDARD.encode(wrong);
+        assert!(dial(consumer, agent_at, &alien).await.is_err(), "чужой SPKI не должен проходить пин");
+    }
+}
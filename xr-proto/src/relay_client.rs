@@ -18,13 +18,15 @@
 
 use std::io;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 
 use crate::mux::{mux_handshake_client, mux_open_stream, MuxStream, Multiplexer};
-use crate::protocol::{Codec, TargetAddr, CLOSE_REASON_AGENT_OFFLINE};
+use crate::protocol::{Codec, TargetAddr, CLOSE_REASON_AGENT_OFFLINE, CLOSE_REASON_BUDGET_EXHAUSTED};
 use crate::share::{RelayGrant, RelayToken};
 
 /// Pseudo-target the consumer leg opens against the relay (LLD-23 §3.5). The
@@ -35,6 +37,12 @@ pub const RELAY_REGISTER_TARGET: &str = "xr-relay:register";
 /// Pseudo-target the relay opens **towards the agent** for each reverse-stream
 /// (LLD-23 §2.2). The agent serves its HTTP router over such a stream.
 pub const RELAY_REVERSE_TARGET: &str = "xr-relay:reverse";
+/// Pseudo-target of a rendezvous stream: the consumer opens it with its relay
+/// token to trade public UDP endpoints with the agent before a direct punch
+/// (see [`punch`](crate::punch)). Authorized and metered like a connect.
+pub const RELAY_PUNCH_TARGET: &str = "xr-relay:punch";
+/// Pseudo-target the relay opens towards the agent for a rendezvous stream.
+pub const RELAY_PUNCH_REVERSE_TARGET: &str = "xr-relay:punch-reverse";
 /// The relay's hello verdict on success: transit granted, splice begins. A
 /// failed hello is answered with a `Close` instead (agent offline / rejected).
 pub const RELAY_HELLO_OK: u8 = 0x01;
@@ -43,6 +51,11 @@ pub const RELAY_HELLO_OK: u8 = 0x01;
 /// until its agent comes back, which callers surface to the user instead of a
 /// raw transport error (XR-134).
 pub const RELAY_ERR_AGENT_OFFLINE: &str = "relay: agent offline";
+/// Error text for a hello refused because the monthly transit budget of the
+/// share or its agent on this relay is spent (`Close` with
+/// [`CLOSE_REASON_BUDGET_EXHAUSTED`]). Another relay keeps its own budget, so
+/// the endpoint still fails over to it.
+pub const RELAY_ERR_BUDGET_EXHAUSTED: &str = "relay: transit budget exhausted";
 
 /// Dial the relay and complete the mux handshake, yielding a client-side
 /// multiplexer (odd stream ids). The obfuscation `codec` must match the relay's.
@@ -64,7 +77,16 @@ pub async fn open_relay_stream(
     mux: &Arc<Multiplexer>,
     token: &RelayToken,
 ) -> io::Result<MuxStream> {
-    let target = TargetAddr::Domain(RELAY_CONNECT_TARGET.to_string(), 0);
+    open_relay_target(mux, token, RELAY_CONNECT_TARGET).await
+}
+
+/// [`open_relay_stream`] on any authorized pseudo-target (connect or punch).
+pub async fn open_relay_target(
+    mux: &Arc<Multiplexer>,
+    token: &RelayToken,
+    pseudo: &str,
+) -> io::Result<MuxStream> {
+    let target = TargetAddr::Domain(pseudo.to_string(), 0);
     let mut stream = mux_open_stream(mux, &target).await?;
     let hello = serde_json::to_vec(token)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
@@ -81,6 +103,10 @@ pub async fn open_relay_stream(
             io::ErrorKind::ConnectionRefused,
             RELAY_ERR_AGENT_OFFLINE,
         )),
+        _ if stream.close_reason() == Some(CLOSE_REASON_BUDGET_EXHAUSTED) => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            RELAY_ERR_BUDGET_EXHAUSTED,
+        )),
         _ => Err(io::Error::new(
             io::ErrorKind::ConnectionRefused,
             "relay: source unavailable",
@@ -88,32 +114,103 @@ pub async fn open_relay_stream(
     }
 }
 
-/// A relay the consumer can open streams against: dial address, obfuscation
-/// codec and transit token. Keeps one mux alive and redials it on death, so a
+/// A relay the consumer can open streams against: one or more relay legs (the
+/// grant's primary plus its backups), the obfuscation codec of each and the
+/// transit token. Keeps one mux alive per leg and redials it on death, so a
 /// dropped tunnel (relay restart, mux lifetime cap) is transparent to callers.
+///
+/// With several legs a stream open walks them starting from the one that
+/// served last: a dead relay costs at most [`RELAY_DIAL_TIMEOUT`] once, after
+/// which the working leg stays preferred until it fails in turn.
 pub struct RelayEndpoint {
-    dial: String,
-    codec: Codec,
+    legs: Vec<RelayLeg>,
     token: RelayToken,
-    mux: Mutex<Option<Arc<Multiplexer>>>,
+    /// Индекс ноги, через которую прошёл последний успешный стрим.
+    preferred: AtomicUsize,
     /// Пиннинг-конфиг rustls строится один раз на эндпоинт: разбор ключа и
     /// сборка конфига стоят заметно дороже самого хендшейка, а пин у эндпоинта
     /// один на все соединения.
     #[cfg(feature = "relay-tls")]
     tls: std::sync::OnceLock<Arc<rustls::ClientConfig>>,
+    /// Пробитый напрямую путь до агента, если есть (см. [`transit`](Self::transit)).
+    #[cfg(feature = "punch")]
+    direct: Arc<std::sync::Mutex<DirectPath>>,
+}
+
+/// Состояние прямого пути эндпоинта: живая QUIC-связь, идущая попытка или пауза
+/// после неудачи. Пробивка идёт в фоне и не держит ни одного стрима.
+#[cfg(feature = "punch")]
+#[derive(Default)]
+struct DirectPath {
+    link: Option<Arc<crate::punch::PunchedLink>>,
+    busy: bool,
+    retry_at: Option<std::time::Instant>,
+}
+
+/// Pause after a failed punch before the next try: a NAT that refused once
+/// (symmetric mapping, blocked UDP) will refuse again, and each attempt costs a
+/// rendezvous stream on the relay.
+#[cfg(feature = "punch")]
+const PUNCH_RETRY: Duration = Duration::from_secs(10 * 60);
+
+/// Ceiling on dialing one relay and finishing the mux handshake. Without it a
+/// relay whose address silently drops SYNs would hold the failover to the next
+/// leg for the OS connect timeout (minutes).
+pub const RELAY_DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One relay of an endpoint and its cached mux.
+struct RelayLeg {
+    dial: String,
+    codec: Codec,
+    /// The relay reflects UDP and serves rendezvous streams.
+    #[cfg_attr(not(feature = "punch"), allow(dead_code))]
+    punch: bool,
+    mux: Mutex<Option<Arc<Multiplexer>>>,
+}
+
+impl RelayLeg {
+    /// A live mux to the relay, redialing if the previous one died.
+    async fn live_mux(&self) -> io::Result<Arc<Multiplexer>> {
+        let mut guard = self.mux.lock().await;
+        let alive = guard.as_ref().map(|m| m.is_alive()).unwrap_or(false);
+        if !alive {
+            let dial = connect_relay_mux(&self.dial, self.codec.clone());
+            let mux = tokio::time::timeout(RELAY_DIAL_TIMEOUT, dial)
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, format!("relay {}: dial timeout", self.dial))
+                })??;
+            *guard = Some(mux);
+        }
+        Ok(guard.as_ref().expect("mux just set").clone())
+    }
 }
 
 impl RelayEndpoint {
-    /// Build from the relay leg of a grant. Fails only if the obfuscation params
-    /// are malformed (bad key / unknown modifier).
+    /// Build from the relay leg of a grant, backups included. Fails only if the
+    /// obfuscation params of some relay are malformed (bad key / unknown
+    /// modifier).
     pub fn from_grant(grant: &RelayGrant) -> Result<Self, String> {
+        let legs = grant
+            .descriptors()
+            .iter()
+            .map(|d| {
+                Ok(RelayLeg {
+                    dial: d.dial(),
+                    codec: d.obf.codec()?,
+                    punch: grant.punch && d.punch,
+                    mux: Mutex::new(None),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
         Ok(Self {
-            dial: grant.dial(),
-            codec: grant.obf.codec()?,
+            legs,
             token: grant.relay_token.clone(),
-            mux: Mutex::new(None),
+            preferred: AtomicUsize::new(0),
             #[cfg(feature = "relay-tls")]
             tls: std::sync::OnceLock::new(),
+            #[cfg(feature = "punch")]
+            direct: Arc::default(),
         })
     }
 
@@ -124,20 +221,178 @@ impl RelayEndpoint {
         &self.token.agent_pubkey
     }
 
-    /// A live mux to the relay, redialing if the previous one died.
-    async fn live_mux(&self) -> io::Result<Arc<Multiplexer>> {
-        let mut guard = self.mux.lock().await;
-        let alive = guard.as_ref().map(|m| m.is_alive()).unwrap_or(false);
-        if !alive {
-            *guard = Some(connect_relay_mux(&self.dial, self.codec.clone()).await?);
+    /// Open one authorized relay stream to the agent, failing over between the
+    /// legs. A leg that answers "agent offline" is skipped too: the agent may
+    /// have lost only that relay. The agent-offline verdict wins over transport
+    /// errors when no leg served, so a dead share still reads as one (XR-134).
+    pub async fn stream(&self) -> io::Result<MuxStream> {
+        let n = self.legs.len();
+        let start = self.preferred.load(Ordering::Relaxed) % n;
+        let mut offline: Option<io::Error> = None;
+        let mut last_err: Option<io::Error> = None;
+        for i in 0..n {
+            let idx = (start + i) % n;
+            let leg = &self.legs[idx];
+            let opened = match leg.live_mux().await {
+                Ok(mux) => open_relay_stream(&mux, &self.token).await,
+                Err(e) => Err(e),
+            };
+            match opened {
+                Ok(stream) => {
+                    if idx != start {
+                        tracing::info!("relay failover: {} -> {}", self.legs[start].dial, leg.dial);
+                    }
+                    self.preferred.store(idx, Ordering::Relaxed);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    if n > 1 {
+                        tracing::debug!("relay {} failed: {e}", leg.dial);
+                    }
+                    if e.to_string() == RELAY_ERR_AGENT_OFFLINE {
+                        offline = Some(e);
+                    } else {
+                        last_err = Some(e);
+                    }
+                }
+            }
         }
-        Ok(guard.as_ref().expect("mux just set").clone())
+        Err(offline.or(last_err).expect("endpoint has at least one leg"))
     }
 
-    /// Open one authorized relay stream to the agent.
-    pub async fn stream(&self) -> io::Result<MuxStream> {
-        let mux = self.live_mux().await?;
-        open_relay_stream(&mux, &self.token).await
+    /// One byte channel to the agent: over the punched direct link when there
+    /// is one, else a relay [`stream`](Self::stream). A relay stream also kicks a
+    /// background punch if the grant allows it, so the next channels skip the
+    /// relay; this one never waits for the punch. Callers see the same bytes
+    /// either way (the E2E TLS to the agent runs on top).
+    pub async fn transit(&self) -> io::Result<TransitIo> {
+        #[cfg(feature = "punch")]
+        if let Some(link) = self.direct_link() {
+            match link.open().await {
+                Ok(io) => return Ok(TransitIo::Direct(io)),
+                Err(e) => {
+                    tracing::debug!("punched link to {} lost: {e}", link.remote());
+                    self.direct.lock().expect("direct path lock").link = None;
+                }
+            }
+        }
+        let stream = self.stream().await?;
+        #[cfg(feature = "punch")]
+        self.kick_punch().await;
+        Ok(TransitIo::Relay(stream.into_io()))
+    }
+
+    /// The agent's endpoint on the punched direct path, if one is up.
+    #[cfg(feature = "punch")]
+    pub fn direct_path(&self) -> Option<SocketAddr> {
+        self.direct_link().map(|l| l.remote())
+    }
+
+    #[cfg(feature = "punch")]
+    fn direct_link(&self) -> Option<Arc<crate::punch::PunchedLink>> {
+        let mut direct = self.direct.lock().expect("direct path lock");
+        if direct.link.as_ref().is_some_and(|l| !l.is_alive()) {
+            direct.link = None;
+        }
+        direct.link.clone()
+    }
+
+    /// Start a punch through the leg that just served, unless one runs, the
+    /// last one failed recently, or that relay can't rendezvous.
+    #[cfg(feature = "punch")]
+    async fn kick_punch(&self) {
+        let leg = &self.legs[self.preferred.load(Ordering::Relaxed) % self.legs.len()];
+        if !leg.punch {
+            return;
+        }
+        {
+            let mut direct = self.direct.lock().expect("direct path lock");
+            let cooling = direct.retry_at.is_some_and(|t| std::time::Instant::now() < t);
+            if direct.busy || direct.link.is_some() || cooling {
+                return;
+            }
+            direct.busy = true;
+        }
+        let Some(mux) = leg.mux.lock().await.clone() else {
+            self.direct.lock().expect("direct path lock").busy = false;
+            return;
+        };
+        let direct = self.direct.clone();
+        let token = self.token.clone();
+        let dial = leg.dial.clone();
+        tokio::spawn(async move {
+            let punched = crate::punch::punch_consumer(&mux, &token, &dial).await;
+            let mut direct = direct.lock().expect("direct path lock");
+            direct.busy = false;
+            match punched {
+                Ok(link) => {
+                    tracing::info!("direct path to agent via {} (relay {dial} bypassed)", link.remote());
+                    direct.link = Some(Arc::new(link));
+                }
+                Err(e) => {
+                    tracing::debug!("punch via {dial} failed, staying on the relay: {e}");
+                    direct.retry_at = Some(std::time::Instant::now() + PUNCH_RETRY);
+                }
+            }
+        });
+    }
+}
+
+/// A byte channel to the agent from [`RelayEndpoint::transit`]: a relay stream
+/// or a stream of the punched direct link.
+pub enum TransitIo {
+    Relay(crate::mux::MuxStreamIo),
+    #[cfg(feature = "punch")]
+    Direct(crate::punch::PunchedIo),
+}
+
+impl tokio::io::AsyncRead for TransitIo {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            TransitIo::Relay(io) => std::pin::Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "punch")]
+            TransitIo::Direct(io) => std::pin::Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for TransitIo {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        match self.get_mut() {
+            TransitIo::Relay(io) => std::pin::Pin::new(io).poll_write(cx, buf),
+            #[cfg(feature = "punch")]
+            TransitIo::Direct(io) => std::pin::Pin::new(io).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            TransitIo::Relay(io) => std::pin::Pin::new(io).poll_flush(cx),
+            #[cfg(feature = "punch")]
+            TransitIo::Direct(io) => std::pin::Pin::new(io).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match self.get_mut() {
+            TransitIo::Relay(io) => std::pin::Pin::new(io).poll_shutdown(cx),
+            #[cfg(feature = "punch")]
+            TransitIo::Direct(io) => std::pin::Pin::new(io).poll_shutdown(cx),
+        }
     }
 }
 
@@ -145,7 +400,7 @@ impl RelayEndpoint {
 /// mux-стрима (LLD-38 п. 2.3). Реализует `AsyncRead + AsyncWrite`, поэтому
 /// отдаётся hyper как обычный транспорт.
 #[cfg(feature = "relay-tls")]
-pub type RelayTlsStream = tokio_rustls::client::TlsStream<crate::mux::MuxStreamIo>;
+pub type RelayTlsStream = tokio_rustls::client::TlsStream<TransitIo>;
 
 /// Соединение до агента одним вызовом: relay-стрим по токену эндпоинта плюс
 /// pinned-TLS с проверкой `SPKI == agent_pubkey` (LLD-38 п. 2.3). Это тот же
@@ -171,11 +426,11 @@ pub async fn relay_tls_connect(endpoint: &RelayEndpoint) -> io::Result<RelayTlsS
             endpoint.tls.get_or_init(|| built).clone()
         }
     };
-    let stream = endpoint.stream().await?;
+    let io = endpoint.transit().await?;
     let name = ServerName::try_from("xr-share-agent")
         .expect("literal SNI name is valid");
     TlsConnector::from(cfg)
-        .connect(name, stream.into_io())
+        .connect(name, io)
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("pinned TLS: {e}")))
 }
@@ -219,7 +474,7 @@ impl LoopbackForwarder {
     /// Bind the loopback listener and spawn the accept loop. Returns once bound,
     /// so `local_addr()` is immediately usable.
     pub async fn spawn(endpoint: Arc<RelayEndpoint>) -> io::Result<Self> {
-        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::atomic::AtomicBool;
         let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
         let local_addr = listener.local_addr()?;
         let agent_offline = Arc::new(AtomicBool::new(false));
@@ -233,10 +488,10 @@ impl LoopbackForwarder {
                 let ep = endpoint.clone();
                 let flag = flag.clone();
                 tokio::spawn(async move {
-                    match ep.stream().await {
-                        Ok(stream) => {
+                    match ep.transit().await {
+                        Ok(io) => {
                             flag.store(false, Ordering::Relaxed);
-                            if let Err(e) = splice(sock, stream).await {
+                            if let Err(e) = splice(sock, io).await {
                                 tracing::debug!("relay splice ended: {e}");
                             }
                         }
@@ -274,11 +529,10 @@ impl Drop for LoopbackForwarder {
 }
 
 /// Blindly move bytes both ways between the loopback TCP socket and the relay
-/// stream until either end closes. The bytes are TLS ciphertext (E2E to the
-/// agent); this function never inspects them.
-async fn splice(mut tcp: TcpStream, relay: MuxStream) -> io::Result<()> {
-    let mut relay_io = relay.into_io();
-    tokio::io::copy_bidirectional(&mut tcp, &mut relay_io).await?;
+/// stream (or the punched link) until either end closes. The bytes are TLS
+/// ciphertext (E2E to the agent); this function never inspects them.
+async fn splice(mut tcp: TcpStream, mut transit: TransitIo) -> io::Result<()> {
+    tokio::io::copy_bidirectional(&mut tcp, &mut transit).await?;
     Ok(())
 }
 
@@ -393,6 +647,8 @@ mod tests {
                 padding_max: 0,
             },
             relay_token: token,
+            backups: Vec::new(),
+            punch: false,
         }
     }
 
@@ -550,6 +806,58 @@ mod tests {
         assert!(probe_agent_online(&endpoint).await.is_err(), "relay недоступен");
     }
 
+    /// Грант с запасным relay: мёртвый основной не роняет транзит, стрим уходит
+    /// на запасной, и тот остаётся предпочтительным. Отказ agent-offline на
+    /// одном relay тоже ведёт дальше: агент мог потерять только его.
+    #[tokio::test]
+    async fn test_endpoint_fails_over_to_backup_relay() {
+        let codec = test_codec();
+        let backup = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let backup_addr = backup.local_addr().unwrap();
+        let relay_codec = codec.clone();
+        tokio::spawn(async move {
+            let (tcp, _) = backup.accept().await.unwrap();
+            run_test_relay(tcp, relay_codec, false).await;
+        });
+        let dead = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let dead_addr = dead.local_addr().unwrap();
+        drop(dead);
+
+        let mut grant = test_grant(dead_addr, dummy_token());
+        let mut leg = grant.descriptor();
+        leg.port = backup_addr.port();
+        grant.backups.push(leg);
+        let endpoint = Arc::new(RelayEndpoint::from_grant(&grant).unwrap());
+        let fwd = LoopbackForwarder::spawn(endpoint.clone()).await.unwrap();
+        for msg in [b"first".as_slice(), b"again".as_slice()] {
+            let mut client = TcpStream::connect(fwd.local_addr()).await.unwrap();
+            client.write_all(msg).await.unwrap();
+            let mut got = vec![0u8; msg.len()];
+            client.read_exact(&mut got).await.unwrap();
+            assert_eq!(got, msg);
+        }
+        assert_eq!(endpoint.preferred.load(Ordering::Relaxed), 1);
+
+        // Основной жив, но агента на нём нет: уходим на запасной.
+        let primary = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let primary_addr = primary.local_addr().unwrap();
+        let backup = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let backup_addr = backup.local_addr().unwrap();
+        for (listener, reject) in [(primary, true), (backup, false)] {
+            let codec = codec.clone();
+            tokio::spawn(async move {
+                let (tcp, _) = listener.accept().await.unwrap();
+                run_test_relay(tcp, codec, reject).await;
+            });
+        }
+        let mut grant = test_grant(primary_addr, dummy_token());
+        let mut leg = grant.descriptor();
+        leg.port = backup_addr.port();
+        grant.backups.push(leg);
+        let endpoint = RelayEndpoint::from_grant(&grant).unwrap();
+        assert!(probe_agent_online(&endpoint).await.unwrap(), "агент на запасном relay");
+    }
+
     /// Тестовый relay, за сплайсом которого стоит настоящий TLS-агент: hello
     /// подтверждается, дальше стрим отдаётся rustls-серверу с сертификатом
     /// агента, и он эхает расшифрованное.
@@ -9,19 +9,25 @@
 //!
 //! ```text
 //! open_stream(target) -> [ServerPool] -> MuxPool активного -> MuxStream
+//! open_udp()          ->      │                           -> MuxDatagram
 //!                              │
 //!                              └─ health_loop: проба primary + failback
 //! ```
 //!
+//! Кого держать активным, решает `SelectionPolicy` профиля: по умолчанию
+//! статический приоритет, либо замеренный RTT (Pong keepalive и свежие
+//! пробы), чтобы живой, но медленный primary не держал весь трафик.
+//!
 //! Wire-протокол и логика слотов не трогаются, весь failover-механизм
 //! сводится к выбору индекса активного `MuxPool`.
 
+use std::future::Future;
 use std::io;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use crate::mux::MuxStream;
+use crate::mux::{MuxDatagram, MuxStream};
 use crate::mux_pool::MuxPool;
 use crate::protocol::TargetAddr;
 
@@ -58,6 +64,41 @@ const FAILBACK_FLAP_CAP: u32 = 6;
 /// Максимальная задержка перед повторным failback на мигающий слот.
 const FAILBACK_BACKOFF_MAX: Duration = Duration::from_secs(1800);
 
+/// Запас `latency_with_hysteresis` по умолчанию: кандидат должен быть быстрее
+/// активного хотя бы на столько (и не меньше чем на пятую часть его RTT).
+/// Джиттер мобильной сети в десятки миллисекунд так не гоняет трафик.
+pub const LATENCY_HYSTERESIS_MARGIN: Duration = Duration::from_millis(30);
+
+/// Как пул выбирает активный сервер среди живых.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionPolicy {
+    /// Порядок из конфига: primary, пока жив, резерв только на время его
+    /// падения (failback с hold-down).
+    Priority,
+    /// Всегда самый быстрый по замеренному RTT из живых. Ничья решается
+    /// приоритетом.
+    LowestLatency,
+    /// Как `LowestLatency`, но переход только если кандидат быстрее активного
+    /// на `margin` (и на 20%) и остаётся лучшим непрерывно `failback_hold`.
+    LatencyWithHysteresis { margin: Duration },
+}
+
+impl SelectionPolicy {
+    /// Значение из конфига: `priority`, `lowest_latency`,
+    /// `latency_with_hysteresis`. Неизвестное имя это `None`, вызывающий
+    /// решает, ругаться ли.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "priority" => Some(Self::Priority),
+            "lowest_latency" => Some(Self::LowestLatency),
+            "latency_with_hysteresis" => Some(Self::LatencyWithHysteresis {
+                margin: LATENCY_HYSTERESIS_MARGIN,
+            }),
+            _ => None,
+        }
+    }
+}
+
 /// Энергетический профиль пула (LLD-10 §2.7). Роутер может позволить себе
 /// тёплые резервы и частые пробы; телефону каждое лишнее пробуждение радио
 /// стоит батареи, поэтому там пробер живёт только в деградированном состоянии.
@@ -71,6 +112,10 @@ pub struct PoolProfile {
     /// Сколько primary должен быть непрерывно живым, прежде чем активный
     /// трафик вернётся на него. Гасит флаппинг на нестабильной связи.
     pub failback_hold: Duration,
+    /// Политика выбора активного. Политики по задержке пробят все серверы
+    /// каждый тик (RTT нужен у каждого), так что на холодном профиле они
+    /// стоят лишних пробуждений радио.
+    pub policy: SelectionPolicy,
 }
 
 impl PoolProfile {
@@ -80,6 +125,7 @@ impl PoolProfile {
             warm_backups: true,
             probe_interval: Duration::from_secs(15),
             failback_hold: Duration::from_secs(60),
+            policy: SelectionPolicy::Priority,
         }
     }
 
@@ -90,6 +136,7 @@ impl PoolProfile {
             warm_backups: false,
             probe_interval: Duration::from_secs(60),
             failback_hold: Duration::from_secs(60),
+            policy: SelectionPolicy::Priority,
         }
     }
 }
@@ -108,6 +155,16 @@ pub enum HealthState {
     Down { since: Instant, class: DownClass },
 }
 
+/// Снимок сервера для мониторинга: здоровье плюс замеры линка.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerHealth {
+    pub state: HealthState,
+    /// Сглаженный RTT; `None`, пока замеров не было.
+    pub rtt: Option<Duration>,
+    /// Принято туннелями сервера, байт/с, между двумя последними тиками пробера.
+    pub rx_rate: u64,
+}
+
 /// Сервер на входе в пул: лейблы для логов + готовый `MuxPool`.
 /// Кодек (в т.ч. per-server override ключа) собирает вызывающий.
 pub struct PoolServer {
@@ -135,6 +192,10 @@ struct SlotState {
     /// До этого момента failback на слот подавлен (анти-флаппинг). `None`
     /// значит подавления нет.
     failback_suppressed_until: Option<Instant>,
+    /// Счётчик принятых байт и момент, когда он снят: от него пробер
+    /// считает `rx_rate`.
+    rx_mark: Option<(u64, Instant)>,
+    rx_rate: u64,
 }
 
 struct ServerSlot {
@@ -213,6 +274,20 @@ impl ServerSlot {
         st.failback_suppressed_until = None;
     }
 
+    /// Снять пропускную за интервал с прошлого замера.
+    fn sample_rx(&self) {
+        let bytes = self.pool.relay_health().rx_bytes();
+        let now = Instant::now();
+        let mut st = self.state.lock().unwrap();
+        if let Some((prev, at)) = st.rx_mark {
+            let secs = now.duration_since(at).as_secs_f64();
+            if secs > 0.0 {
+                st.rx_rate = (bytes.saturating_sub(prev) as f64 / secs) as u64;
+            }
+        }
+        st.rx_mark = Some((bytes, now));
+    }
+
     fn reset(&self) {
         let mut st = self.state.lock().unwrap();
         st.health = HealthState::Up;
@@ -220,6 +295,8 @@ impl ServerSlot {
         st.became_active_at = None;
         st.flap_count = 0;
         st.failback_suppressed_until = None;
+        st.rx_mark = None;
+        st.rx_rate = 0;
     }
 }
 
@@ -236,6 +313,8 @@ enum SwitchReason {
     Failback,
     /// Первый прогрев: активным становится самый приоритетный из живых.
     Warmup,
+    /// Политика по задержке нашла сервер быстрее активного.
+    Latency,
 }
 
 impl SwitchReason {
@@ -245,6 +324,7 @@ impl SwitchReason {
             Self::RelayDegraded => "failover (relay degraded)",
             Self::Failback => "failback",
             Self::Warmup => "warmup",
+            Self::Latency => "switch (lower latency)",
         }
     }
 
@@ -254,6 +334,7 @@ impl SwitchReason {
             Self::RelayDegraded => "через сервер не идёт трафик, перешёл на резерв",
             Self::Failback => "основной сервер снова живой, вернулся на него",
             Self::Warmup => "выбран сервер",
+            Self::Latency => "нашёлся сервер быстрее, перешёл на него",
         }
     }
 }
@@ -278,6 +359,9 @@ pub struct ServerPool {
     active: AtomicUsize,
     profile: PoolProfile,
     on_event: Option<PoolEventFn>,
+    /// Кандидат `latency_with_hysteresis` и с какого момента он непрерывно
+    /// лучше активного.
+    latency_candidate: Mutex<Option<(usize, Instant)>>,
 }
 
 impl ServerPool {
@@ -304,6 +388,8 @@ impl ServerPool {
                     became_active_at: None,
                     flap_count: 0,
                     failback_suppressed_until: None,
+                    rx_mark: None,
+                    rx_rate: 0,
                 }),
             })
             .collect();
@@ -312,6 +398,7 @@ impl ServerPool {
             active: AtomicUsize::new(0),
             profile,
             on_event,
+            latency_candidate: Mutex::new(None),
         })
     }
 
@@ -328,14 +415,18 @@ impl ServerPool {
         self.slots[self.active_index()].label().to_string()
     }
 
-    /// "name (ip:port)" активного, идёт в дебаг-строки вместо прежнего
-    /// одиночного `server_addr`.
+    /// "name (ip:port, 42ms)" активного, идёт в дебаг-строки вместо прежнего
+    /// одиночного `server_addr`. RTT дописывается, когда он уже замерен.
     pub fn active_label(&self) -> String {
         let slot = &self.slots[self.active_index()];
+        let addr = match slot.pool.rtt() {
+            Some(rtt) => format!("{}, {}ms", slot.addr, rtt.as_millis()),
+            None => slot.addr.clone(),
+        };
         if slot.name.is_empty() {
-            slot.addr.clone()
+            addr
         } else {
-            format!("{} ({})", slot.name, slot.addr)
+            format!("{} ({})", slot.name, addr)
         }
     }
 
@@ -344,9 +435,17 @@ impl ServerPool {
         self.active_index() != 0
     }
 
-    /// Хук для мониторинга/панели здоровья (LLD-11).
-    pub fn server_health(&self, idx: usize) -> Option<HealthState> {
-        self.slots.get(idx).map(|s| s.state.lock().unwrap().health)
+    /// Хук для мониторинга/панели здоровья (LLD-11): состояние сервера и
+    /// замеры его линка.
+    pub fn server_health(&self, idx: usize) -> Option<ServerHealth> {
+        self.slots.get(idx).map(|s| {
+            let st = s.state.lock().unwrap();
+            ServerHealth {
+                state: st.health,
+                rtt: s.pool.rtt(),
+                rx_rate: st.rx_rate,
+            }
+        })
     }
 
     /// Событие пула уходит в два адреса, и текст у них разный: в tracing по-английски,
@@ -427,6 +526,25 @@ impl ServerPool {
     /// `Err` значит, что исчерпан весь пул; вызывающий уводит соединение
     /// в Direct.
     pub async fn open_stream(&self, target: &TargetAddr) -> io::Result<MuxStream> {
+        self.open_with(|pool| async move { pool.open_stream(target).await })
+            .await
+    }
+
+    /// Открыть датаграммную ассоциацию (UDP поверх mux) с тем же failover,
+    /// что и у стримов. `Unsupported` значит, что активный сервер датаграмм
+    /// не релеит (старая сборка): вызывающий уходит на отдельный UDP relay,
+    /// здоровье сервера от этого не страдает.
+    pub async fn open_udp(&self) -> io::Result<MuxDatagram> {
+        self.open_with(|pool| async move { pool.open_udp().await }).await
+    }
+
+    /// Обход серверов, общий для `open_stream`/`open_udp`: `open` выполняется
+    /// над `MuxPool` очередного сервера.
+    async fn open_with<T, F, Fut>(&self, open: F) -> io::Result<T>
+    where
+        F: Fn(Arc<MuxPool>) -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
         let start = self.active_index();
         let mut failures: Vec<(usize, io::Error)> = Vec::new();
 
@@ -436,7 +554,7 @@ impl ServerPool {
             // соединение утекло бы в Direct вместо живого резерва.
             let outcome = tokio::time::timeout(
                 PER_SERVER_OPEN_TIMEOUT,
-                self.slots[idx].pool.open_stream(target),
+                open(self.slots[idx].pool.clone()),
             )
             .await;
             match outcome {
@@ -468,6 +586,11 @@ impl ServerPool {
                     tracing::debug!("open_stream rejected the target ({}), no failover", e);
                     return Err(e);
                 }
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Unsupported => {
+                    // Сервер жив, но не умеет запрошенное (датаграммы у старой
+                    // сборки). Это не сбой транспорта: ни failover, ни mark_down.
+                    return Err(e);
+                }
                 Ok(Err(e)) => {
                     self.slots[idx].mark_down();
                     tracing::debug!(
@@ -592,6 +715,9 @@ impl ServerPool {
     }
 
     async fn health_tick(&self) {
+        for slot in &self.slots {
+            slot.sample_rx();
+        }
         self.relay_degradation_tick();
 
         let active = self.active_index();
@@ -607,6 +733,11 @@ impl ServerPool {
             }
         }
 
+        if self.profile.policy != SelectionPolicy::Priority {
+            self.latency_tick().await;
+            return;
+        }
+
         // Пробим кандидатов на failback (приоритетнее активного) реальным
         // round-trip'ом: свежий handshake ловит blackhole (mux ещё is_alive, но
         // глотает egress) за таймаут коннекта, а не читает мёртвый primary
@@ -640,6 +771,81 @@ impl ServerPool {
         }
     }
 
+    /// Выбор по задержке. Пробятся резервы: у холодного нет keepalive, и без
+    /// пробы его RTT не узнать. Активный пробы не ждёт, его RTT и так свежий
+    /// по Pong keepalive'а mux; пробуется он, только пока замера нет вовсе.
+    /// Down-серверы и мигающие под штрафом (XR-082) в выборе не участвуют.
+    /// Единственный сервер выбирать не из чего, и одна неудачная проба не
+    /// делает его Down.
+    async fn latency_tick(&self) {
+        if self.slots.len() < 2 {
+            return;
+        }
+        let active = self.active_index();
+        let mut probes = Vec::with_capacity(self.slots.len());
+        for (idx, slot) in self.slots.iter().enumerate() {
+            if idx == active && slot.pool.rtt().is_some() {
+                continue;
+            }
+            let pool = slot.pool.clone();
+            probes.push((idx, tokio::spawn(async move {
+                tokio::time::timeout(PROBE_TIMEOUT, pool.probe_fresh()).await
+            })));
+        }
+        for (idx, probe) in probes {
+            match probe.await {
+                Ok(Ok(Ok(()))) => self.slots[idx].mark_up(),
+                _ => self.slots[idx].mark_down(),
+            }
+        }
+
+        let fastest = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.is_down() && !s.failback_suppressed())
+            .filter_map(|(idx, s)| s.pool.rtt().map(|rtt| (idx, rtt)))
+            .min_by_key(|&(_, rtt)| rtt);
+        let Some((best, best_rtt)) = fastest.filter(|&(idx, _)| idx != active) else {
+            *self.latency_candidate.lock().unwrap() = None;
+            return;
+        };
+
+        // С упавшего активного уходят сразу: hold гасит колебания между
+        // живыми, а не держит трафик на мёртвом.
+        if self.slots[active].is_down() {
+            *self.latency_candidate.lock().unwrap() = None;
+            self.switch_active(active, best, SwitchReason::Failover);
+            return;
+        }
+        let margin = match self.profile.policy {
+            SelectionPolicy::LatencyWithHysteresis { margin } => margin,
+            _ => {
+                self.switch_active(active, best, SwitchReason::Latency);
+                return;
+            }
+        };
+        // Активный без замера проигрывает любому живому.
+        let active_rtt = self.slots[active].pool.rtt();
+        let clearly_better =
+            active_rtt.is_none_or(|cur| best_rtt + margin.max(cur / 5) <= cur);
+        let mut candidate = self.latency_candidate.lock().unwrap();
+        if !clearly_better {
+            *candidate = None;
+            return;
+        }
+        match *candidate {
+            Some((idx, since)) if idx == best => {
+                if since.elapsed() >= self.profile.failback_hold {
+                    *candidate = None;
+                    drop(candidate);
+                    self.switch_active(active, best, SwitchReason::Latency);
+                }
+            }
+            _ => *candidate = Some((best, Instant::now())),
+        }
+    }
+
     /// Failover по деградации relay (XR-094): у сервера с мёртвым DNS или
     /// egress туннель и keepalive живы, ConnectAck приходит, поэтому ни
     /// breaker, ни dead-link-детект, ни ограниченный по времени open_stream
@@ -1097,6 +1303,7 @@ mod tests {
             warm_backups: false,
             probe_interval: Duration::from_millis(10),
             failback_hold: Duration::from_millis(300),
+            policy: SelectionPolicy::Priority,
         };
         let pool = ServerPool::new(
             vec![
@@ -1359,7 +1566,7 @@ mod tests {
         pool.health_tick().await;
         assert_eq!(pool.active_index(), 0);
         assert_eq!(
-            pool.server_health(0),
+            pool.server_health(0).map(|h| h.state),
             Some(HealthState::Up),
             "единственный сервер не помечается Down: уходить некуда"
         );
@@ -1422,6 +1629,7 @@ mod tests {
             warm_backups: false,
             probe_interval: Duration::from_millis(10),
             failback_hold: Duration::from_millis(50),
+            policy: SelectionPolicy::Priority,
         };
         let pool = ServerPool::new(
             vec![
@@ -1458,4 +1666,140 @@ mod tests {
             "after the penalty clears, failback resumes"
         );
     }
+
+    fn latency_profile(policy: SelectionPolicy, hold: Duration) -> PoolProfile {
+        PoolProfile {
+            warm_backups: false,
+            probe_interval: Duration::from_millis(10),
+            failback_hold: hold,
+            policy,
+        }
+    }
+
+    /// Живой, но медленный primary больше не держит трафик: `lowest_latency`
+    /// на первом же тике уводит активность на быстрый резерв, а замеры видны
+    /// в `server_health` и `active_label`.
+    #[tokio::test]
+    async fn test_lowest_latency_prefers_faster_backup() {
+        let addr = spawn_test_server().await;
+        let pool = ServerPool::new(
+            vec![
+                slot("primary", connect_to(addr, Arc::new(AtomicU32::new(0)))),
+                slot("backup", connect_to(addr, Arc::new(AtomicU32::new(0)))),
+            ],
+            latency_profile(SelectionPolicy::LowestLatency, Duration::from_secs(60)),
+            None,
+        );
+        // Проба localhost добавит к замеру доли миллисекунды, разрыв останется.
+        pool.slots[0].pool.relay_health().record_rtt(Duration::from_millis(300));
+        pool.slots[1].pool.relay_health().record_rtt(Duration::from_millis(10));
+
+        pool.health_tick().await;
+        assert_eq!(pool.active_index(), 1);
+        let primary = pool.server_health(0).unwrap();
+        let backup = pool.server_health(1).unwrap();
+        assert_eq!(primary.state, HealthState::Up);
+        assert!(primary.rtt.unwrap() > backup.rtt.unwrap());
+        assert!(pool.active_label().ends_with("ms)"), "{}", pool.active_label());
+    }
+
+    /// `latency_with_hysteresis`: разница меньше запаса не переключает, а
+    /// явный выигрыш переключает только отстояв hold.
+    #[tokio::test]
+    async fn test_latency_hysteresis_needs_margin_and_hold() {
+        let addr = spawn_test_server().await;
+        let policy = SelectionPolicy::LatencyWithHysteresis {
+            margin: LATENCY_HYSTERESIS_MARGIN,
+        };
+
+        let pool = ServerPool::new(
+            vec![
+                slot("primary", connect_to(addr, Arc::new(AtomicU32::new(0)))),
+                slot("backup", connect_to(addr, Arc::new(AtomicU32::new(0)))),
+            ],
+            latency_profile(policy, Duration::from_millis(100)),
+            None,
+        );
+        pool.slots[0].pool.relay_health().record_rtt(Duration::from_millis(100));
+        pool.slots[1].pool.relay_health().record_rtt(Duration::from_millis(90));
+        for _ in 0..3 {
+            pool.health_tick().await;
+            tokio::time::sleep(Duration::from_millis(60)).await;
+        }
+        assert_eq!(pool.active_index(), 0, "10ms is within the margin");
+
+        let pool = ServerPool::new(
+            vec![
+                slot("primary", connect_to(addr, Arc::new(AtomicU32::new(0)))),
+                slot("backup", connect_to(addr, Arc::new(AtomicU32::new(0)))),
+            ],
+            latency_profile(policy, Duration::from_millis(100)),
+            None,
+        );
+        pool.slots[0].pool.relay_health().record_rtt(Duration::from_millis(300));
+        pool.slots[1].pool.relay_health().record_rtt(Duration::from_millis(10));
+        pool.health_tick().await;
+        assert_eq!(pool.active_index(), 0, "hold must delay the switch");
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        pool.health_tick().await;
+        assert_eq!(pool.active_index(), 1);
+    }
+
+    /// Активный с RTT от keepalive не пробится, резерв пробится. Упавший
+    /// активный уступает живому сразу, без hold и запаса.
+    #[tokio::test]
+    async fn test_latency_tick_reuses_keepalive_rtt_and_leaves_down_active() {
+        let addr = spawn_test_server().await;
+        let policy = SelectionPolicy::LatencyWithHysteresis {
+            margin: LATENCY_HYSTERESIS_MARGIN,
+        };
+        let (primary_dials, backup_dials) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+        let pool = ServerPool::new(
+            vec![
+                slot("primary", connect_to(addr, primary_dials.clone())),
+                slot("backup", connect_to(addr, backup_dials.clone())),
+            ],
+            latency_profile(policy, Duration::from_secs(60)),
+            None,
+        );
+        pool.slots[0].pool.relay_health().record_rtt(Duration::from_millis(100));
+        pool.slots[1].pool.relay_health().record_rtt(Duration::from_millis(90));
+        pool.health_tick().await;
+        assert_eq!(primary_dials.load(Ordering::Relaxed), 0, "активный меряется keepalive'ом");
+        assert_eq!(backup_dials.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.active_index(), 0);
+
+        pool.slots[0].mark_down();
+        pool.health_tick().await;
+        assert_eq!(pool.active_index(), 1, "с упавшего уходят без hold");
+    }
+
+    /// Единственный сервер проба не роняет: уходить всё равно некуда, а Down
+    /// у него значит лишь, что им некому пользоваться.
+    #[tokio::test]
+    async fn test_latency_tick_never_downs_sole_server() {
+        let pool = ServerPool::new(
+            vec![slot("only", failing_connect(Arc::new(AtomicU32::new(0))))],
+            latency_profile(SelectionPolicy::LowestLatency, Duration::from_secs(60)),
+            None,
+        );
+        pool.health_tick().await;
+        assert_eq!(pool.server_health(0).unwrap().state, HealthState::Up);
+    }
+
+    #[test]
+    fn test_selection_policy_parse() {
+        assert_eq!(SelectionPolicy::parse("priority"), Some(SelectionPolicy::Priority));
+        assert_eq!(
+            SelectionPolicy::parse("lowest_latency"),
+            Some(SelectionPolicy::LowestLatency)
+        );
+        assert_eq!(
+            SelectionPolicy::parse("latency_with_hysteresis"),
+            Some(SelectionPolicy::LatencyWithHysteresis {
+                margin: LATENCY_HYSTERESIS_MARGIN
+            })
+        );
+        assert_eq!(SelectionPolicy::parse("fastest"), None);
+    }
 }
@@ -34,7 +34,7 @@ use serde::{Deserialize, Serialize};
 ///
 /// The vocabulary is deliberately not video-specific: `source` is the channel
 /// for a video, the site or the author elsewhere.
-#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct FileMeta {
     /// The page the file was imported from (the job's own link, or a more
     /// precise one from the plugin).
@@ -71,7 +71,7 @@ impl FileMeta {
 /// One file in a share, as listed by the agent. Carries **metadata only**, and
 /// the bytes are fetched directly from the agent over a range request, never
 /// from the hub.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ShareManifestEntry {
     /// Path relative to the share root, forward-slash separated, no leading
     /// slash and no `..`. The agent guarantees the shape with the
@@ -94,6 +94,21 @@ pub struct ShareManifestEntry {
     /// byte-identical to what it was before.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub meta: Option<FileMeta>,
+    /// The agent renders a preview of this file (`GET /{id}/thumb/{path}`), so
+    /// a browser shows a thumbnail instead of fetching the whole file. Absent
+    /// when it cannot, and on agents that predate previews.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<PreviewKind>,
+}
+
+/// What a file's preview is cut from: the picture itself, or a frame the agent
+/// pulls out of a video with an external tool. Either way the thumbnail is a
+/// small JPEG; the kind only tells the UI to draw a play badge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewKind {
+    Image,
+    Video,
 }
 
 /// The full listing the agent serves for a share. No bytes — `xr-core`'s sync
@@ -101,6 +116,110 @@ pub struct ShareManifestEntry {
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct ShareManifest {
     pub entries: Vec<ShareManifestEntry>,
+    /// The share version this listing was taken at, the same number as
+    /// [`MANIFEST_VERSION_HEADER`]. Inside the signed body because a consumer
+    /// that keeps the listing applies [`ShareManifestDelta`]s on top of it: a
+    /// forged base would graft a delta onto the wrong listing. `0` from an
+    /// agent without versions, and then not serialized at all.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u64,
+}
+
+/// One content-defined chunk of a shared file: where it sits and the SHA-256
+/// of its bytes. Cut by [`crate::chunks`] identically on both ends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChunk {
+    pub offset: u64,
+    pub len: u32,
+    /// Lowercase hex SHA-256 of the chunk bytes.
+    pub sha256: String,
+}
+
+/// The chunk list of one file, served by `GET /{share_id}/chunks/{*path}`
+/// for block-level delta transfer: a consumer holding an older copy fetches
+/// only the chunks it has no bytes for and reassembles the rest locally. Not
+/// signed on purpose: the result is checked against the manifest's signed
+/// `sha256`, so a forged list costs a failed check and a whole download,
+/// never a wrong file.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FileChunks {
+    /// Size of the file the list was cut from.
+    pub size: u64,
+    /// SHA-256 of the whole file, for telling a list of another version of
+    /// the file apart from the one in the manifest.
+    pub sha256: String,
+    /// Chunks in file order, covering it without gaps.
+    pub chunks: Vec<FileChunk>,
+}
+
+/// How much a share holds and how much more it takes, from
+/// `GET /{share_id}/usage`, so a consumer can show the space left and refuse an
+/// upload the agent would refuse anyway. Counts the share's visible files only
+/// (not the agent's own `.xr-` storage). A snapshot: another writer may take
+/// the space between this answer and the upload.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ShareUsage {
+    /// Total size of the share's files.
+    pub used_bytes: u64,
+    /// Number of the share's files.
+    pub files: u64,
+    /// The share's size quota, when the owner set one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    /// The share's file-count limit, when the owner set one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<u64>,
+    /// How much more can be written: the smaller of what the quota leaves and
+    /// the disk space above the owner's reserve. `None` when neither is known
+    /// (no quota on a platform the agent cannot ask for free space).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
+    /// The agent accepts uploads into this share at all.
+    pub writable: bool,
+    /// Accepted file extensions (lowercase, no dot). Together with `mime`, a
+    /// file passes when it matches either list; both empty means any file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// Accepted MIME types by file name, `type/subtype` or `type/*`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mime: Vec<String>,
+}
+
+/// What changed in a share between two versions of its listing: rows added or
+/// changed since `base` in full, and paths that are gone. The agent serves it
+/// instead of the full [`ShareManifest`] to a consumer that already holds the
+/// listing at `base` (see [`MANIFEST_DELTA_HEADER`]), so a share of half a
+/// million files does not travel whole after every edit. Signed over its own
+/// domain ([`manifest_delta_signing_bytes`]) so it never verifies as a listing.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ShareManifestDelta {
+    /// The version the consumer's listing must be at for the delta to apply.
+    pub base: u64,
+    /// The version the listing is at after applying.
+    pub version: u64,
+    /// Added or changed rows, sorted by path.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<ShareManifestEntry>,
+    /// Removed paths, sorted. May name a path the base never listed (added
+    /// and removed again within the span), which applies as a no-op.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl ShareManifestDelta {
+    /// Apply onto `manifest`, which must be at `base` (the caller checks).
+    /// Rows stay sorted by path, as the agent lists them.
+    pub fn apply(&self, manifest: &mut ShareManifest) {
+        let removed: std::collections::HashSet<&str> = self.removed.iter().map(String::as_str).collect();
+        let changed: std::collections::HashSet<&str> =
+            self.changed.iter().map(|e| e.path.as_str()).collect();
+        manifest
+            .entries
+            .retain(|e| !removed.contains(e.path.as_str()) && !changed.contains(e.path.as_str()));
+        manifest.entries.extend(self.changed.iter().cloned());
+        manifest.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        manifest.version = self.version;
+    }
 }
 
 /// A capability the hub mints and the agent checks. Bound to a single
@@ -325,6 +444,11 @@ pub struct RelayDescriptor {
     pub addr: String,
     pub port: u16,
     pub obf: RelayObf,
+    /// The relay also serves hole-punching rendezvous: a UDP reflector on the
+    /// same port and the punch pseudo-targets (see `punch`). Absent on older
+    /// hubs and relays, which leaves consumers on the splice.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub punch: bool,
 }
 
 impl RelayDescriptor {
@@ -337,12 +461,22 @@ impl RelayDescriptor {
 /// The relay leg of a [`ShareGrant`] handed to the **consumer**: the relay
 /// descriptor plus a hub-minted [`RelayToken`] gating transit to the agent
 /// (LLD-23 §2.4, §3.7). Flat on the wire (`{addr, port, obf, relay_token}`).
+///
+/// `backups` are the other relays the agent is registered with, in the hub's
+/// placement order. The token isn't bound to a relay, so the same one opens
+/// transit on any of them; the consumer fails over to the next when a relay
+/// is down. An older consumer ignores the field and uses only the primary.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RelayGrant {
     pub addr: String,
     pub port: u16,
     pub obf: RelayObf,
     pub relay_token: RelayToken,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backups: Vec<RelayDescriptor>,
+    /// See [`RelayDescriptor::punch`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub punch: bool,
 }
 
 impl RelayGrant {
@@ -352,6 +486,7 @@ impl RelayGrant {
             addr: self.addr.clone(),
             port: self.port,
             obf: self.obf.clone(),
+            punch: self.punch,
         }
     }
 
@@ -359,6 +494,13 @@ impl RelayGrant {
     pub fn dial(&self) -> String {
         format!("{}:{}", self.addr, self.port)
     }
+
+    /// Every relay to try, primary first, then the backups in order.
+    pub fn descriptors(&self) -> Vec<RelayDescriptor> {
+        std::iter::once(self.descriptor())
+            .chain(self.backups.iter().cloned())
+            .collect()
+    }
 }
 
 /// A capability the hub mints and the **relay** checks to admit transit to an
@@ -379,6 +521,57 @@ pub struct RelayToken {
     pub signature: String,
 }
 
+/// Short, stable handle of an agent key for ops views: the first 8 bytes of the
+/// key in hex. Enough to tell agents apart and to match them against the hub's
+/// records, without pasting whole keys into dashboards and logs. A key that
+/// isn't base64 falls back to its own leading characters.
+pub fn agent_fingerprint(agent_pubkey: &str) -> String {
+    use base64::Engine as _;
+    match base64::engine::general_purpose::STANDARD.decode(agent_pubkey.trim()) {
+        Ok(raw) if raw.len() >= 8 => raw[..8].iter().map(|b| format!("{b:02x}")).collect(),
+        _ => agent_pubkey.trim().chars().take(16).collect(),
+    }
+}
+
+/// What a relay's loopback admin endpoint reports (`GET /stats`): who is
+/// registered, how many splices are live and how much each share has moved
+/// since the relay started. The hub's admin view collects one per relay.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayStats {
+    /// When the relay process started, unix seconds.
+    pub started_at: u64,
+    /// Splices in flight across the whole relay.
+    pub live_splices: u64,
+    #[serde(default)]
+    pub agents: Vec<RelayAgentStats>,
+    #[serde(default)]
+    pub shares: Vec<RelayShareStats>,
+}
+
+/// One registered agent as the relay sees it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayAgentStats {
+    /// [`agent_fingerprint`] of the agent key.
+    pub fingerprint: String,
+    /// When the current registration was admitted, unix seconds.
+    pub registered_at: u64,
+    /// Registry generation; a jump between two polls means the agent reconnected.
+    pub generation: u64,
+    /// Source IP of the uplink.
+    pub peer_ip: String,
+    /// Splices in flight to this agent.
+    pub live_splices: u64,
+}
+
+/// Transit of one share (or one web publication, see [`web_share_id`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayShareStats {
+    pub share_id: String,
+    /// Ciphertext moved both ways by finished splices.
+    pub bytes: u64,
+    pub live_splices: u64,
+}
+
 /// Публикация локального HTTP-сервиса, как её помнит хаб (LLD-38 п. 2.1):
 /// имя, агент и момент заведения. Апстрим сюда не едет: это внутренний адрес
 /// машины владельца, посреднику он не нужен, а проксирует агент по своему
@@ -393,6 +586,166 @@ pub struct ExposeRecord {
     /// Когда завели, RFC 3339.
     #[serde(default)]
     pub created: String,
+    /// «Выйти везде»: сессии браузера, открытые на публикации раньше этого
+    /// момента, мертвы. Unix-секунды, `0` значит не нажимали ни разу.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub logout_before: u64,
+    /// Кто входит через браузер. Записи до политик её не несут и остаются
+    /// за учётками админки, как и были.
+    #[serde(default, skip_serializing_if = "ExposeAccess::is_owner")]
+    pub access: ExposeAccess,
+    /// Гостевые ссылки: вход без учётки до срока, пока владелец не отозвал.
+    /// Работают поверх любой политики.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guests: Vec<GuestLink>,
+    /// HTTP-сервис или сырой TCP. Записи до TCP-публикаций его не несут и
+    /// остаются HTTP.
+    #[serde(default, skip_serializing_if = "ExposeKind::is_http")]
+    pub kind: ExposeKind,
+    /// Машины (base64 ключи агентов), кроме машины самой публикации, которым
+    /// хаб выдаёт туннель к TCP-публикации. Список ведёт владелец; пустой
+    /// значит туннель открывает только своя машина.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub connect_agents: Vec<String>,
+}
+
+/// Что за сервис стоит за публикацией. HTTP открывается браузером через
+/// `xr-web`, сырой TCP (SSH, игровой сервер, база) только клиентом
+/// `xr-share connect`: браузеру говорить с ним нечем.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExposeKind {
+    #[default]
+    Http,
+    Tcp,
+}
+
+impl ExposeKind {
+    pub fn is_http(&self) -> bool {
+        matches!(self, ExposeKind::Http)
+    }
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+/// Политика браузерного входа на публикацию. Хранит и проверяет её хаб:
+/// пароли и инвайты до посредника не доезжают, он знает только вид политики
+/// ([`WebAccessKind`]) и по нему рисует форму входа.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExposeAccess {
+    /// Учётки админки хаба: вход владельца, как до политик.
+    #[default]
+    Owner,
+    /// Без входа: открыто всем, кто знает адрес.
+    Public,
+    /// Свой пароль публикации (argon2-хэш), без имени: его можно дать другу,
+    /// не давая учётки хаба.
+    Password { hash: String },
+    /// Держатели перечисленных инвайтов хаба: вход по коду инвайта, пока
+    /// инвайт жив и не отозван.
+    Invites { invites: Vec<String> },
+    /// Перечисленные учётки админки хаба, каждая со своим паролем.
+    Users { users: Vec<String> },
+}
+
+impl ExposeAccess {
+    pub fn is_owner(&self) -> bool {
+        matches!(self, ExposeAccess::Owner)
+    }
+
+    pub fn kind(&self) -> WebAccessKind {
+        match self {
+            ExposeAccess::Owner => WebAccessKind::Owner,
+            ExposeAccess::Public => WebAccessKind::Public,
+            ExposeAccess::Password { .. } => WebAccessKind::Password,
+            ExposeAccess::Invites { .. } => WebAccessKind::Invites,
+            ExposeAccess::Users { .. } => WebAccessKind::Users,
+        }
+    }
+}
+
+/// Вид политики без её секретов: всё, что о ней знает посредник.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebAccessKind {
+    /// Логин и пароль учётки хаба.
+    #[default]
+    Owner,
+    /// Формы входа нет вовсе.
+    Public,
+    /// Только пароль.
+    Password,
+    /// Код инвайта.
+    Invites,
+    /// Логин и пароль, как у владельца, но не всякой учётки.
+    Users,
+}
+
+/// Гостевая ссылка на публикацию: `https://<имя>.<домен>/.xr-web/guest/<токен>`.
+/// Хаб помнит только SHA-256 токена, сам токен показывается один раз при
+/// выпуске. Отозванная ссылка остаётся в записи до срока: по ней посредник
+/// снимает уже открытые гостевые сессии.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuestLink {
+    /// Короткий идентификатор для админки и отзыва.
+    pub id: String,
+    /// Hex SHA-256 токена.
+    pub token_hash: String,
+    /// Кому выдана, для глаз владельца.
+    #[serde(default)]
+    pub label: String,
+    /// Unix-секунды.
+    pub created: u64,
+    /// До какого момента ссылка и открытые по ней сессии живут, unix-секунды.
+    pub expires: u64,
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+/// Сессия браузера на публикации глазами посредника (LLD-38 п. 3.2): что он
+/// докладывает хабу для админки. Токена тут нет, только его отпечаток `id`, по
+/// которому сессию не открыть.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebSessionInfo {
+    pub id: String,
+    pub publication: String,
+    pub username: String,
+    /// Когда открыта, unix-секунды.
+    pub created: u64,
+    /// Последний запрос по сессии, unix-секунды.
+    pub last_seen: u64,
+    /// Адрес и `User-Agent` браузера на последнем запросе.
+    #[serde(default)]
+    pub last_ip: String,
+    #[serde(default)]
+    pub user_agent: String,
+    /// Гостевая ссылка или инвайт, по которым открыта сессия: их отзыв её
+    /// снимает. Пусто у входа по паролю.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub grant: String,
+}
+
+/// Доклад посредника хабу: все живые сессии браузера на момент отправки.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebSessionsReport {
+    #[serde(default)]
+    pub sessions: Vec<WebSessionInfo>,
+}
+
+/// Ответ хаба на доклад посредника о сессиях: момент «выйти везде» по каждой
+/// публикации, где его нажимали. Сессии старше него посредник снимает сам.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebSessionsVerdict {
+    #[serde(default)]
+    pub logout_before: std::collections::HashMap<String, u64>,
+    /// Отозванные гостевые ссылки ([`GuestLink::id`]) и инвайты (метки
+    /// [`WebSessionInfo::grant`]): сессии, открытые по ним, посредник снимает
+    /// сам.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revoked_guests: Vec<String>,
 }
 
 /// Мандат публикации (LLD-38 п. 3.4): хаб подписывает, агент проверяет офлайн
@@ -424,6 +777,17 @@ pub fn encode_expose_mandate(token: &ExposeToken) -> String {
         .encode(serde_json::to_vec(token).expect("serialize expose token"))
 }
 
+/// Токен шары из блоба ссылки (base64url-no-pad JSON), обратное к минту хаба.
+/// Подпись не проверяется: это [`verify_share_token`]. `None` на любом мусоре.
+#[cfg(any(feature = "share", test))]
+pub fn decode_share_token(blob: &str) -> Option<ShareToken> {
+    use base64::Engine;
+    let json = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(blob.trim())
+        .ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
 /// Заголовок, которым посредник (`xr-web`, харнесс `expose open`) называет
 /// публикацию на реверс-стриме. Без него запрос обслуживает роутер шары, как и
 /// до LLD-38.
@@ -451,6 +815,10 @@ pub struct WebRoute {
     pub agent_pubkey: String,
     /// Куда и с какой обфускацией идти на relay.
     pub relay: RelayDescriptor,
+    /// Запасные relay, на которых агент тоже зарегистрирован: посредник
+    /// переходит на них, когда основной не отвечает. Токен тот же.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backup_relays: Vec<RelayDescriptor>,
     /// Транзит до агента. `share_id` у него вида `web:<имя>`
     /// (см. [`web_share_id`]), чтобы браузерный расход был отдельной строкой в
     /// агрегатах relay.
@@ -463,6 +831,56 @@ pub struct WebRoute {
     /// Потолок жизни одного сплайса на relay, секунды. Долгие соединения
     /// (WebSocket) фронт закрывает штатно до того, как relay обрубит их сам.
     pub splice_lifetime_secs: u64,
+    /// Вид политики входа: по нему посредник решает, пускать ли без сессии и
+    /// какую форму входа рисовать. `default` держит маршруты старого хаба за
+    /// входом владельца.
+    #[serde(default)]
+    pub access: WebAccessKind,
+}
+
+/// Протокол апгрейда, которым потребитель `xr-share connect` просит у агента
+/// сырой TCP до апстрима публикации. Запрос идёт тем же путём, что и у
+/// браузерного фронта (заголовок публикации, мандат в `Authorization`), и
+/// после `101` на соединении остаются только байты сервиса.
+pub const TCP_UPGRADE: &str = "xr-tcp";
+
+/// Маршрут TCP-публикации для `xr-share connect`: транзит через relay и
+/// мандат публикации. Минтит хаб по мандату агента, апстрим в маршрут не
+/// едет: его, как и у HTTP-публикации, знает только конфиг агента.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TcpRoute {
+    pub publication: String,
+    /// Base64 (standard) ed25519 ключ агента: пин TLS.
+    pub agent_pubkey: String,
+    /// Транзит до агента. `share_id` у токена вида `tcp:<имя>`
+    /// (см. [`tcp_share_id`]).
+    pub relay: RelayGrant,
+    /// Мандат публикации, base64url blob для `Authorization: Bearer`.
+    pub mandate: String,
+    /// Докуда годны мандат и relay-токен, unix-секунды.
+    pub exp: u64,
+}
+
+/// Маршрут шары для её страницы на `s.<web-домен>` (LLD-38 п. 2.6): тот же
+/// транзит до агента, что у [`WebRoute`], но без мандата публикации. Доступ к
+/// шаре даёт [`ShareToken`] из ссылки, его посредник предъявляет агенту как
+/// есть, поэтому конечную авторизацию по-прежнему делает агент.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebShareRoute {
+    pub share_id: String,
+    /// Имя шары из реестра хаба: заголовок страницы.
+    pub name: String,
+    /// Base64 (standard) ed25519 ключ агента: пин TLS и ключ подписи манифеста.
+    pub agent_pubkey: String,
+    pub relay: RelayDescriptor,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backup_relays: Vec<RelayDescriptor>,
+    /// Транзит до агента на `share_id` самой шары: браузерные скачивания
+    /// идут в тот же расход и под тот же бюджет, что и скачивания приложением.
+    pub relay_token: RelayToken,
+    /// Докуда маршрут годен, unix-секунды.
+    pub exp: u64,
+    pub splice_lifetime_secs: u64,
 }
 
 /// `share_id` relay-токена для публикации: `web:<имя>` (LLD-38 п. 2.5).
@@ -472,6 +890,13 @@ pub fn web_share_id(publication: &str) -> String {
     format!("web:{publication}")
 }
 
+/// `share_id` relay-токена для TCP-публикации: `tcp:<имя>`. Свой неймспейс,
+/// чтобы расход туннелей `connect` не смешивался в агрегатах relay ни с
+/// шарами, ни с браузерным входом.
+pub fn tcp_share_id(publication: &str) -> String {
+    format!("tcp:{publication}")
+}
+
 /// Годится ли имя публикации в поддомен: DNS-метка из строчных букв, цифр и
 /// дефисов, 1..=63 символа, дефис не с краю. Проверяют оба конца, хаб и агент:
 /// имя едет в `Host`, и всё, что не метка, до агента просто не доберётся.
@@ -575,6 +1000,25 @@ pub const MANIFEST_SIG_HEADER: &str = "x-xr-manifest-sig";
 /// of the signed bytes, so it cannot be altered in flight.
 pub const MANIFEST_SIGNED_AT_HEADER: &str = "x-xr-manifest-signed-at";
 
+/// Response header with the share's manifest version: a counter the agent
+/// bumps on every change it sees in the share. The consumer passes it back as
+/// `since` to `GET /{share_id}/manifest/wait` and is held until the version
+/// moves. Not signed: a forged value costs at most an extra fetch or a missed
+/// wake-up, never a wrong file, the manifest itself is still verified.
+pub const MANIFEST_VERSION_HEADER: &str = "x-xr-manifest-version";
+
+/// Query parameter of `GET /{share_id}/manifest` and its wait route: the
+/// version of the listing the consumer already holds. When the agent still
+/// remembers the changes since then, the reply is a [`ShareManifestDelta`]
+/// instead of the full listing.
+pub const MANIFEST_BASE_PARAM: &str = "base";
+
+/// Response header marking the body as a [`ShareManifestDelta`] (its value is
+/// the delta's `base`). The signature headers are the same, but the bytes are
+/// signed over [`manifest_delta_signing_bytes`]. Absent on a full listing,
+/// which is also the answer when the base is too old for the agent to diff.
+pub const MANIFEST_DELTA_HEADER: &str = "x-xr-manifest-delta";
+
 /// The exact bytes a manifest signature covers (XR-046). Domain prefix and
 /// newline-delimited fields follow [`token_signing_bytes`]. `share_id` binds
 /// the signature to one share: an agent signs every share it serves with the
@@ -590,6 +1034,15 @@ pub fn manifest_signing_bytes(share_id: &str, signed_at: u64, manifest_json: &[u
     bytes
 }
 
+/// The exact bytes a [`ShareManifestDelta`] signature covers: the
+/// [`manifest_signing_bytes`] layout under its own domain prefix, so a delta
+/// and a listing can never stand in for each other.
+pub fn manifest_delta_signing_bytes(share_id: &str, signed_at: u64, delta_json: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("xr-share-manifest-delta\nv1\n{share_id}\n{signed_at}\n").into_bytes();
+    bytes.extend_from_slice(delta_json);
+    bytes
+}
+
 /// Why a [`verify_share_token`] check failed. Distinct variants so the agent can
 /// log/diagnose without leaking the token itself.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -772,8 +1225,8 @@ impl std::error::Error for RelayRegisterError {}
 #[cfg(any(feature = "share", test))]
 mod crypto {
     use super::{
-        agent_credential_signing_bytes, expose_token_signing_bytes, manifest_signing_bytes,
-        relay_register_signing_bytes, relay_token_signing_bytes, token_signing_bytes,
+        agent_credential_signing_bytes, expose_token_signing_bytes, manifest_delta_signing_bytes,
+        manifest_signing_bytes, relay_register_signing_bytes, relay_token_signing_bytes, token_signing_bytes,
         AgentCredential, AgentCredentialError, ExposeToken, ExposeTokenError, ManifestSigError,
         RelayRegister, RelayRegisterError, RelayToken, RelayTokenError, ShareToken, ShareTokenError,
     };
@@ -1032,6 +1485,18 @@ mod crypto {
         base64::engine::general_purpose::STANDARD.encode(sig.to_bytes())
     }
 
+    /// Sign a [`ShareManifestDelta`](super::ShareManifestDelta) as served, the
+    /// delta twin of [`sign_share_manifest`].
+    pub fn sign_manifest_delta(
+        key: &SigningKey,
+        share_id: &str,
+        signed_at: u64,
+        delta_json: &[u8],
+    ) -> String {
+        let sig = key.sign(&manifest_delta_signing_bytes(share_id, signed_at, delta_json));
+        base64::engine::general_purpose::STANDARD.encode(sig.to_bytes())
+    }
+
     /// Decode a pinned base64 `agent_pubkey` (as carried by a `ShareGrant` /
     /// `ShareInfo`) into a verifying key. Single decode point for consumers.
     pub fn parse_agent_pubkey(b64: &str) -> Result<VerifyingKey, ManifestSigError> {
@@ -1052,6 +1517,26 @@ mod crypto {
         share_id: &str,
         signed_at: u64,
         manifest_json: &[u8],
+    ) -> Result<(), ManifestSigError> {
+        verify_agent_signature(sig_b64, agent_key, &manifest_signing_bytes(share_id, signed_at, manifest_json))
+    }
+
+    /// [`verify_share_manifest`] for a delta body, over
+    /// [`manifest_delta_signing_bytes`].
+    pub fn verify_manifest_delta(
+        sig_b64: &str,
+        agent_key: &VerifyingKey,
+        share_id: &str,
+        signed_at: u64,
+        delta_json: &[u8],
+    ) -> Result<(), ManifestSigError> {
+        verify_agent_signature(sig_b64, agent_key, &manifest_delta_signing_bytes(share_id, signed_at, delta_json))
+    }
+
+    fn verify_agent_signature(
+        sig_b64: &str,
+        agent_key: &VerifyingKey,
+        signed: &[u8],
     ) -> Result<(), ManifestSigError> {
         let sig_bytes = base64::engine::general_purpose::STANDARD
             .decode(sig_b64.trim())
@@ -1060,18 +1545,16 @@ mod crypto {
             .try_into()
             .map_err(|_| ManifestSigError::MalformedSignature)?;
         let signature = ed25519_dalek::Signature::from_bytes(&sig_arr);
-        agent_key
-            .verify(&manifest_signing_bytes(share_id, signed_at, manifest_json), &signature)
-            .map_err(|_| ManifestSigError::BadSignature)
+        agent_key.verify(signed, &signature).map_err(|_| ManifestSigError::BadSignature)
     }
 }
 
 #[cfg(any(feature = "share", test))]
 pub use crypto::{
-    parse_agent_pubkey, sign_agent_credential, sign_expose_token, sign_relay_register,
-    sign_relay_token, sign_share_manifest, sign_share_token, verify_agent_credential,
-    verify_expose_token, verify_relay_register, verify_relay_token, verify_share_manifest,
-    verify_share_token,
+    parse_agent_pubkey, sign_agent_credential, sign_expose_token, sign_manifest_delta,
+    sign_relay_register, sign_relay_token, sign_share_manifest, sign_share_token,
+    verify_agent_credential, verify_expose_token, verify_manifest_delta, verify_relay_register,
+    verify_relay_token, verify_share_manifest, verify_share_token,
 };
 
 #[cfg(test)]
@@ -1330,6 +1813,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn manifest_delta_never_verifies_as_a_listing() {
+        // Same key, share, timestamp and bytes: the domain prefix alone must
+        // keep a delta from passing as a full listing and back.
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let vk = key.verifying_key();
+        let body = br#"{"base":1,"version":2,"removed":["a.txt"]}"#;
+        let sig = sign_manifest_delta(&key, "share-1", 7000, body);
+        assert!(verify_manifest_delta(&sig, &vk, "share-1", 7000, body).is_ok());
+        assert_eq!(
+            verify_share_manifest(&sig, &vk, "share-1", 7000, body),
+            Err(ManifestSigError::BadSignature)
+        );
+        let listing_sig = sign_share_manifest(&key, "share-1", 7000, body);
+        assert_eq!(
+            verify_manifest_delta(&listing_sig, &vk, "share-1", 7000, body),
+            Err(ManifestSigError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn manifest_delta_applies_in_path_order() {
+        let row = |path: &str, sha: &str| ShareManifestEntry {
+            path: path.into(),
+            size: 1,
+            mtime: 0,
+            sha256: sha.into(),
+            meta: None,
+            preview: None,
+        };
+        let mut m = ShareManifest { entries: vec![row("a", "1"), row("b", "1"), row("c", "1")], version: 5 };
+        let delta = ShareManifestDelta {
+            base: 5,
+            version: 9,
+            changed: vec![row("b", "2"), row("bb", "1")],
+            removed: vec!["c".into(), "never-listed".into()],
+        };
+        delta.apply(&mut m);
+        assert_eq!(m.version, 9);
+        assert_eq!(m.entries, vec![row("a", "1"), row("b", "2"), row("bb", "1")]);
+
+        // An unversioned listing serializes as it did before versions existed.
+        let plain = serde_json::to_string(&ShareManifest::default()).unwrap();
+        assert_eq!(plain, r#"{"entries":[]}"#);
+    }
+
     #[test]
     fn manifest_entry_meta_is_optional_on_wire() {
         // XR-255. A listing row without origin must serialize exactly as it did
@@ -1341,6 +1870,7 @@ mod tests {
             mtime: 1,
             sha256: "aa".into(),
             meta: None,
+            preview: None,
         };
         let json = serde_json::to_string(&plain).unwrap();
         assert_eq!(json, r#"{"path":"a.txt","size":5,"mtime":1,"sha256":"aa"}"#);
@@ -1576,6 +2106,23 @@ mod tests {
         assert!(!back.contains("relay"), "no relay leg must not emit the field: {back}");
     }
 
+    #[test]
+    fn relay_grant_backups_are_optional_and_ordered() {
+        // A single-relay grant keeps the old shape; backups follow the primary.
+        let json = r#"{"addr":"r1","port":9443,"obf":{"key":"QQ=="},"relay_token":{"share_id":"s","agent_pubkey":"QQ==","exp":9,"signature":"AA=="}}"#;
+        let mut g: RelayGrant = serde_json::from_str(json).unwrap();
+        assert!(g.backups.is_empty());
+        assert!(!serde_json::to_string(&g).unwrap().contains("backups"));
+
+        let mut backup = g.descriptor();
+        backup.addr = "r2".into();
+        g.backups.push(backup);
+        let dials: Vec<String> = g.descriptors().iter().map(|d| d.dial()).collect();
+        assert_eq!(dials, vec!["r1:9443".to_string(), "r2:9443".to_string()]);
+        let back: RelayGrant = serde_json::from_str(&serde_json::to_string(&g).unwrap()).unwrap();
+        assert_eq!(back, g);
+    }
+
     #[test]
     fn test_share_record_has_no_content() {
         // The hub record is an index entry: address + identity metadata only.
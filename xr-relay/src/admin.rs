@@ -0,0 +1,169 @@
+//! Admin/stats endpoint: what the relay holds right now, for operators and the
+//! hub's admin view (`GET /stats`, JSON [`RelayStats`]).
+//!
+//! Deliberately tiny: one GET route over HTTP/1.1 with `Connection: close`, no
+//! HTTP stack. The relay is blind transit and its attack surface stays that of
+//! the mux port; this listener sits on loopback (or behind a bearer token, see
+//! [`AdminConfig::check`](crate::config::AdminConfig::check)) and reads at most
+//! one small request head per connection. Agents show up by
+//! [`agent_fingerprint`], never by full key.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use xr_proto::accept::accept_loop;
+use xr_proto::share::{agent_fingerprint, RelayAgentStats, RelayShareStats, RelayStats};
+
+use crate::RelayState;
+
+/// Largest request head we read before giving up on the client.
+const MAX_HEAD: usize = 8 * 1024;
+/// How long a client has to send its request head.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Collect the current registry, live splices and per-share totals.
+pub async fn stats(state: &RelayState) -> RelayStats {
+    let mut agents: Vec<RelayAgentStats> = state
+        .registry
+        .snapshot()
+        .await
+        .into_iter()
+        .map(|a| RelayAgentStats {
+            fingerprint: agent_fingerprint(&a.pubkey),
+            registered_at: a.registered_at,
+            generation: a.generation,
+            peer_ip: a.peer.to_string(),
+            live_splices: state.splices.agent(&a.pubkey),
+        })
+        .collect();
+    agents.sort_by(|a, b| a.fingerprint.cmp(&b.fingerprint));
+
+    // A share shows up once it has moved bytes or has a splice in flight.
+    let mut shares: HashMap<String, RelayShareStats> = HashMap::new();
+    for (share_id, bytes) in state.counters.snapshot() {
+        shares.insert(
+            share_id.clone(),
+            RelayShareStats { share_id, bytes, live_splices: 0 },
+        );
+    }
+    for (share_id, live) in state.splices.shares() {
+        shares
+            .entry(share_id.clone())
+            .or_insert(RelayShareStats { share_id, bytes: 0, live_splices: 0 })
+            .live_splices = live;
+    }
+    let mut shares: Vec<RelayShareStats> = shares.into_values().collect();
+    shares.sort_by(|a, b| a.share_id.cmp(&b.share_id));
+
+    RelayStats {
+        started_at: state.started_at,
+        live_splices: state.splices.total(),
+        agents,
+        shares,
+    }
+}
+
+/// Serve the admin endpoint until the listener dies for good.
+pub async fn serve_admin(
+    listener: TcpListener,
+    state: Arc<RelayState>,
+    token: Option<String>,
+) -> io::Result<()> {
+    let listener = &listener;
+    let token: Option<Arc<str>> = token.filter(|t| !t.is_empty()).map(Into::into);
+    accept_loop(
+        "relay admin",
+        move || async move { listener.accept().await.map(Some) },
+        |tcp: TcpStream, peer| {
+            let state = state.clone();
+            let token = token.clone();
+            async move {
+                tokio::spawn(async move {
+                    if let Err(e) = handle(tcp, &state, token.as_deref()).await {
+                        tracing::debug!("relay admin {peer}: {e}");
+                    }
+                });
+            }
+        },
+    )
+    .await
+}
+
+async fn handle(mut tcp: TcpStream, state: &RelayState, token: Option<&str>) -> io::Result<()> {
+    let head = tokio::time::timeout(HEAD_TIMEOUT, read_head(&mut tcp))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request head timeout"))??;
+    let (status, body) = route(&head, state, token).await;
+    let resp = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Connection: close\r\n\
+         \r\n",
+        body.len()
+    );
+    tcp.write_all(resp.as_bytes()).await?;
+    tcp.write_all(&body).await?;
+    tcp.shutdown().await
+}
+
+/// Read up to the blank line that ends the request head.
+async fn read_head(tcp: &mut TcpStream) -> io::Result<String> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = tcp.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "closed mid-head"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            buf.truncate(end);
+            return String::from_utf8(buf)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "non-utf8 head"));
+        }
+        if buf.len() > MAX_HEAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+    }
+}
+
+async fn route(head: &str, state: &RelayState, token: Option<&str>) -> (&'static str, Vec<u8>) {
+    let mut lines = head.split("\r\n");
+    let mut request = lines.next().unwrap_or("").split(' ');
+    let (method, path) = (request.next().unwrap_or(""), request.next().unwrap_or(""));
+
+    if let Some(expected) = token {
+        let presented = lines
+            .filter_map(|l| l.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+            .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+            .map(str::trim);
+        if !presented.is_some_and(|p| same_secret(p, expected)) {
+            return ("401 Unauthorized", error_body("missing or wrong admin token"));
+        }
+    }
+    match (method, path.split('?').next().unwrap_or("")) {
+        ("GET", "/stats") => {
+            let stats = stats(state).await;
+            ("200 OK", serde_json::to_vec(&stats).unwrap_or_default())
+        }
+        (_, "/stats") => ("405 Method Not Allowed", error_body("GET only")),
+        _ => ("404 Not Found", error_body("not found")),
+    }
+}
+
+fn error_body(msg: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({ "error": msg })).unwrap_or_default()
+}
+
+/// Token comparison that doesn't stop at the first differing byte.
+fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
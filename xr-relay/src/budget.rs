@@ -0,0 +1,233 @@
+//! Monthly transit budgets per share and per agent, persisted across restarts.
+//!
+//! The ledger counts ciphertext moved through the relay in the current calendar
+//! month (UTC) and resets when the month turns. A share or agent over its budget
+//! is refused with [`CLOSE_REASON_BUDGET_EXHAUSTED`] at the hello, and a splice
+//! that crosses the line mid-transfer is cut. The totals live in a JSON file that
+//! is flushed periodically and at shutdown, so a restart costs at most one flush
+//! interval of accounting, not the whole month.
+//!
+//! [`CLOSE_REASON_BUDGET_EXHAUSTED`]: xr_proto::protocol::CLOSE_REASON_BUDGET_EXHAUSTED
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex as StdMutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::BudgetConfig;
+
+/// What the ledger file holds: the month the totals belong to and the bytes per
+/// share and per agent.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Ledger {
+    /// `YYYY-MM`, UTC.
+    month: String,
+    #[serde(default)]
+    shares: HashMap<String, u64>,
+    #[serde(default)]
+    agents: HashMap<String, u64>,
+}
+
+/// Whose totals a splice is charged to, built once per splice: the hot path
+/// then only looks the totals up by borrowed key, and allocates just on the
+/// first charge of a month.
+pub struct TransitKey {
+    share_id: String,
+    agent_pubkey: String,
+}
+
+impl TransitKey {
+    pub fn new(share_id: &str, agent_pubkey: &str) -> Self {
+        Self { share_id: share_id.to_string(), agent_pubkey: agent_pubkey.to_string() }
+    }
+}
+
+/// Transit totals of the current month plus the budgets they are held to.
+pub struct TransitLedger {
+    path: PathBuf,
+    share_budget: Option<u64>,
+    agent_budget: Option<u64>,
+    inner: StdMutex<Ledger>,
+    dirty: AtomicBool,
+}
+
+/// Bytes from a config budget in gigabytes; `0` is no budget.
+fn budget_bytes(gb: u64) -> Option<u64> {
+    (gb > 0).then(|| gb.saturating_mul(1_000_000_000))
+}
+
+impl TransitLedger {
+    /// Open the ledger at the configured path. A missing file is an empty ledger
+    /// (first start); an unreadable one is an error rather than a silent reset,
+    /// because a reset hands every share a fresh month.
+    pub fn open(cfg: &BudgetConfig) -> anyhow::Result<Self> {
+        let path = PathBuf::from(&cfg.state_file);
+        let ledger = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| anyhow::anyhow!("parse {}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ledger::default(),
+            Err(e) => return Err(anyhow::anyhow!("read {}: {e}", path.display())),
+        };
+        Ok(Self {
+            path,
+            share_budget: budget_bytes(cfg.share_gb_per_month),
+            agent_budget: budget_bytes(cfg.agent_gb_per_month),
+            inner: StdMutex::new(ledger),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether the share or its agent has spent its budget for the month of
+    /// `now` (unix seconds).
+    pub fn exhausted(&self, share_id: &str, agent_pubkey: &str, now: u64) -> bool {
+        let mut ledger = self.inner.lock().unwrap();
+        roll(&mut ledger, now);
+        over(self.share_budget, ledger.shares.get(share_id))
+            || over(self.agent_budget, ledger.agents.get(agent_pubkey))
+    }
+
+    /// Add `bytes` to the share's and the agent's totals. Returns `false` once
+    /// either is over its budget, so the splice stops.
+    pub fn charge(&self, key: &TransitKey, bytes: u64, now: u64) -> bool {
+        let mut ledger = self.inner.lock().unwrap();
+        roll(&mut ledger, now);
+        let share = add(&mut ledger.shares, &key.share_id, bytes);
+        let agent = add(&mut ledger.agents, &key.agent_pubkey, bytes);
+        self.dirty.store(true, Ordering::Relaxed);
+        !(over(self.share_budget, Some(&share)) || over(self.agent_budget, Some(&agent)))
+    }
+
+    /// Write the totals out if they changed since the last flush. Temp file plus
+    /// rename, so a crash mid-write leaves the previous totals intact.
+    pub fn flush(&self) -> std::io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = {
+            let ledger = self.inner.lock().unwrap();
+            serde_json::to_vec(&*ledger).map_err(std::io::Error::other)?
+        };
+        let res = write_atomic(&self.path, &json);
+        if res.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        res
+    }
+
+    #[cfg(test)]
+    pub fn share_total(&self, share_id: &str) -> u64 {
+        *self.inner.lock().unwrap().shares.get(share_id).unwrap_or(&0)
+    }
+}
+
+/// Add `bytes` to `id`'s total and return the new total. The key is cloned
+/// only when the total is new.
+fn add(totals: &mut HashMap<String, u64>, id: &str, bytes: u64) -> u64 {
+    match totals.get_mut(id) {
+        Some(total) => {
+            *total += bytes;
+            *total
+        }
+        None => {
+            totals.insert(id.to_string(), bytes);
+            bytes
+        }
+    }
+}
+
+fn over(budget: Option<u64>, used: Option<&u64>) -> bool {
+    matches!((budget, used), (Some(b), Some(u)) if *u >= b)
+}
+
+/// Start a new month's totals when `now` is past the ledger's month.
+fn roll(ledger: &mut Ledger, now: u64) {
+    let month = month_of(now);
+    if ledger.month != month {
+        *ledger = Ledger { month, ..Ledger::default() };
+    }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// `YYYY-MM` (UTC) of a unix timestamp. Civil-from-days, so the relay needs no
+/// date crate for one calendar question.
+fn month_of(unix: u64) -> String {
+    let days = (unix / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-18 12:00 UTC and 2026-11-01 00:00 UTC.
+    const OCT: u64 = 1_792_324_800;
+    const NOV: u64 = 1_793_491_200;
+
+    fn cfg(dir: &Path, share_gb: u64, agent_gb: u64) -> BudgetConfig {
+        BudgetConfig {
+            state_file: dir.join("transit.json").display().to_string(),
+            share_gb_per_month: share_gb,
+            agent_gb_per_month: agent_gb,
+            flush_secs: 60,
+        }
+    }
+
+    #[test]
+    fn month_of_follows_the_calendar() {
+        assert_eq!(month_of(0), "1970-01");
+        assert_eq!(month_of(OCT), "2026-10");
+        assert_eq!(month_of(NOV - 1), "2026-10");
+        assert_eq!(month_of(NOV), "2026-11");
+        assert_eq!(month_of(951_782_400), "2000-02"); // leap day
+    }
+
+    #[test]
+    fn budgets_hold_per_share_and_per_agent_and_reset_monthly() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = TransitLedger::open(&cfg(dir.path(), 2, 3)).unwrap();
+        assert!(ledger.charge(&TransitKey::new("s1", "agent"), 1_500_000_000, OCT));
+        assert!(!ledger.exhausted("s1", "agent", OCT));
+        // The share crosses its 2 GB; the agent (3 GB) still has room.
+        assert!(!ledger.charge(&TransitKey::new("s1", "agent"), 600_000_000, OCT));
+        assert!(ledger.exhausted("s1", "agent", OCT));
+        assert!(!ledger.exhausted("s2", "agent", OCT));
+        // Another share of the same agent runs into the agent's budget.
+        assert!(!ledger.charge(&TransitKey::new("s2", "agent"), 1_000_000_000, OCT));
+        assert!(ledger.exhausted("s2", "agent", OCT));
+        // A new month starts from zero.
+        assert!(!ledger.exhausted("s1", "agent", NOV));
+        assert_eq!(ledger.share_total("s1"), 0);
+    }
+
+    #[test]
+    fn totals_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = TransitLedger::open(&cfg(dir.path(), 1, 0)).unwrap();
+        ledger.charge(&TransitKey::new("s1", "agent"), 999_999_999, OCT);
+        ledger.flush().unwrap();
+
+        let reopened = TransitLedger::open(&cfg(dir.path(), 1, 0)).unwrap();
+        assert_eq!(reopened.share_total("s1"), 999_999_999);
+        assert!(!reopened.charge(&TransitKey::new("s1", "agent"), 1, OCT), "the budget carries over");
+
+        std::fs::write(dir.path().join("transit.json"), b"{broken").unwrap();
+        assert!(TransitLedger::open(&cfg(dir.path(), 1, 0)).is_err(), "no silent reset");
+    }
+}
@@ -3,7 +3,8 @@
 //! A standalone `[relay]` block: where to listen, the obfuscation params (shared
 //! with the deployment so the relay's mux looks like the proxy's), the hub's
 //! public key (to verify agent credentials and relay tokens offline), and the
-//! transit limits (§5.2).
+//! transit limits (§5.2): caps, rate shaping (`[relay.shaping]`) and monthly
+//! budgets (`[relay.budget]`). `[relay.admin]` turns on the stats endpoint.
 
 use std::path::Path;
 
@@ -31,6 +32,15 @@ fn default_counter_log_secs() -> u64 {
 fn default_log_level() -> String {
     "info".to_string()
 }
+fn default_budget_state_file() -> String {
+    "/var/lib/xr-relay/transit.json".to_string()
+}
+fn default_budget_flush_secs() -> u64 {
+    60
+}
+fn default_admin_listen() -> String {
+    "127.0.0.1:9444".to_string()
+}
 
 #[derive(Debug, Deserialize)]
 pub struct RelayConfig {
@@ -60,6 +70,79 @@ pub struct RelayConfig {
     pub counter_log_secs: u64,
     #[serde(default = "default_log_level")]
     pub log_level: String,
+    /// Transit rate limits. Absent means unshaped.
+    #[serde(default)]
+    pub shaping: ShapingConfig,
+    /// Monthly transit budgets. Absent means no budgets and no ledger file.
+    #[serde(default)]
+    pub budget: Option<BudgetConfig>,
+    /// Admin/stats endpoint. Absent means none is listening.
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// Rendezvous for direct consumer-agent paths: a UDP reflector on
+    /// `listen:port`. Needs the UDP port open in the firewall.
+    #[serde(default)]
+    pub punch: bool,
+}
+
+/// Token-bucket rate limits on spliced bytes, both directions summed, in
+/// megabits per second. `0` leaves that level unlimited.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ShapingConfig {
+    /// The whole relay: keeps transit from eating the proxy's share of the
+    /// VPS uplink.
+    #[serde(default)]
+    pub global_mbps: u64,
+    /// One agent across all its shares and publications.
+    #[serde(default)]
+    pub per_agent_mbps: u64,
+    /// One share (or one web publication) across all its consumers.
+    #[serde(default)]
+    pub per_share_mbps: u64,
+}
+
+/// Monthly transit budgets (calendar month, UTC), in gigabytes of ciphertext
+/// moved both ways. `0` leaves that level without a budget.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BudgetConfig {
+    /// Where the month's totals are kept between restarts.
+    #[serde(default = "default_budget_state_file")]
+    pub state_file: String,
+    #[serde(default)]
+    pub share_gb_per_month: u64,
+    #[serde(default)]
+    pub agent_gb_per_month: u64,
+    /// How often the totals are written out.
+    #[serde(default = "default_budget_flush_secs")]
+    pub flush_secs: u64,
+}
+
+/// The stats endpoint (`GET /stats`, JSON): registered agents, live splices and
+/// per-share totals for the hub's admin view. Plain HTTP, so it belongs on
+/// loopback; another address is accepted only behind a `token`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminConfig {
+    #[serde(default = "default_admin_listen")]
+    pub listen: String,
+    /// Required as `Authorization: Bearer <token>` when set.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    /// Refuse a non-loopback listen address without a token: the endpoint
+    /// lists every agent's source IP.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let addr: std::net::SocketAddr = self
+            .listen
+            .parse()
+            .map_err(|e| anyhow::anyhow!("admin listen {:?}: {e}", self.listen))?;
+        let has_token = self.token.as_deref().is_some_and(|t| !t.is_empty());
+        if !addr.ip().is_loopback() && !has_token {
+            anyhow::bail!("admin listen {} is not loopback and has no token", self.listen);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -74,6 +157,9 @@ impl RelayConfig {
             .map_err(|e| anyhow::anyhow!("read {}: {e}", path.display()))?;
         let file: RelayFile = toml::from_str(&text)
             .map_err(|e| anyhow::anyhow!("parse {}: {e}", path.display()))?;
+        if let Some(admin) = &file.relay.admin {
+            admin.check()?;
+        }
         Ok(file.relay)
     }
 
@@ -122,5 +208,59 @@ mod tests {
         assert_eq!(cfg.max_registrations_per_ip, 8); // default
         assert!(cfg.codec().is_ok());
         assert!(cfg.hub_key().is_ok());
+        assert_eq!(cfg.shaping.global_mbps, 0); // unshaped by default
+        assert!(cfg.budget.is_none());
+    }
+
+    #[test]
+    fn parses_shaping_and_budget() {
+        let toml_text = r#"
+            [relay]
+            port = 8444
+            hub_pubkey = "QQ=="
+            [relay.obfuscation]
+            key = "QQ=="
+            [relay.shaping]
+            global_mbps = 200
+            per_share_mbps = 20
+            [relay.budget]
+            share_gb_per_month = 50
+        "#;
+        let cfg: RelayFile = toml::from_str(toml_text).unwrap();
+        let cfg = cfg.relay;
+        assert_eq!(cfg.shaping.global_mbps, 200);
+        assert_eq!(cfg.shaping.per_agent_mbps, 0);
+        assert_eq!(cfg.shaping.per_share_mbps, 20);
+        let budget = cfg.budget.expect("budget block");
+        assert_eq!(budget.share_gb_per_month, 50);
+        assert_eq!(budget.agent_gb_per_month, 0);
+        assert_eq!(budget.state_file, "/var/lib/xr-relay/transit.json");
+    }
+
+    #[test]
+    fn admin_must_stay_on_loopback_unless_tokened() {
+        let admin = |listen: &str, token: Option<&str>| AdminConfig {
+            listen: listen.to_string(),
+            token: token.map(str::to_string),
+        };
+        assert!(admin("127.0.0.1:9444", None).check().is_ok());
+        assert!(admin("[::1]:9444", None).check().is_ok());
+        assert!(admin("0.0.0.0:9444", None).check().is_err());
+        assert!(admin("0.0.0.0:9444", Some("")).check().is_err());
+        assert!(admin("10.0.0.5:9444", Some("s3cret")).check().is_ok());
+        assert!(admin("localhost:9444", None).check().is_err(), "an address, not a name");
+
+        let cfg: RelayFile = toml::from_str(
+            r#"
+            [relay]
+            port = 8444
+            hub_pubkey = "QQ=="
+            [relay.obfuscation]
+            key = "QQ=="
+            [relay.admin]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.relay.admin.unwrap().listen, "127.0.0.1:9444");
     }
 }
@@ -11,34 +11,47 @@
 //!
 //! The pseudo-targets never resolve to the network: the relay matches on the
 //! exact string and cannot be steered outward (SSRF-class excluded, §5.2).
-
+//!
+//! With `punch` on, the relay is also a rendezvous for a direct path: a UDP
+//! reflector on the same port tells each side its public endpoint
+//! ([`serve_reflector`]), and a short, token-checked stream on
+//! [`RELAY_PUNCH_TARGET`] lets consumer and agent trade them
+//! (see [`xr_proto::punch`]). Transit then stops crossing the relay.
+
+pub mod admin;
+pub mod budget;
 pub mod config;
 pub mod registry;
+pub mod shaping;
 
+use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::task::{Context, Poll};
+use std::task::{ready, Context, Poll};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::Semaphore;
 
 use xr_proto::accept::accept_loop;
 use xr_proto::mux::{mux_handshake_server, mux_open_stream, Multiplexer};
 use xr_proto::protocol::{
-    Codec, Command, Frame, TargetAddr, CLOSE_REASON_AGENT_OFFLINE, CLOSE_REASON_CONNECT_FAIL,
-    CLOSE_REASON_RELAY_BUSY,
+    Codec, Command, Frame, TargetAddr, CLOSE_REASON_AGENT_OFFLINE, CLOSE_REASON_BUDGET_EXHAUSTED,
+    CLOSE_REASON_CONNECT_FAIL, CLOSE_REASON_RELAY_BUSY,
 };
 use xr_proto::relay_client::{
-    RELAY_CONNECT_TARGET, RELAY_HELLO_OK, RELAY_REGISTER_TARGET, RELAY_REVERSE_TARGET,
+    RELAY_CONNECT_TARGET, RELAY_HELLO_OK, RELAY_PUNCH_REVERSE_TARGET, RELAY_PUNCH_TARGET,
+    RELAY_REGISTER_TARGET, RELAY_REVERSE_TARGET,
 };
 use xr_proto::share::{verify_relay_register, verify_relay_token, RelayRegister, RelayToken};
 
-pub use registry::{AgentRegistry, Counters, IpCaps};
+pub use budget::{TransitKey, TransitLedger};
+pub use registry::{AgentRegistry, Counters, IpCaps, LiveSplices};
+pub use shaping::{Shaper, TokenBucket};
 
 /// Length of the registration challenge nonce (LLD-23 §2.1). 32 random bytes:
 /// unpredictable and single-use, so the answer can't be replayed without a clock.
@@ -49,6 +62,9 @@ const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
 const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
 /// Timeout on reading the first frame (MuxInit) of a fresh connection.
 const HANDSHAKE_READ_TIMEOUT: Duration = Duration::from_secs(15);
+/// Lifetime cap of a rendezvous stream: it carries two small JSON messages,
+/// so anything lingering past this is not a punch.
+const RENDEZVOUS_LIFETIME: Duration = Duration::from_secs(30);
 
 /// Shared relay state: the pinned hub key plus the registry, counters and limits.
 pub struct RelayState {
@@ -58,14 +74,42 @@ pub struct RelayState {
     pub ip_caps: Arc<IpCaps>,
     pub stream_sem: Arc<Semaphore>,
     pub splice_lifetime: Duration,
+    /// Rate limits on spliced bytes (per share, per agent, global).
+    pub shaper: Shaper,
+    /// Monthly transit budgets, `None` when none are configured.
+    pub ledger: Option<TransitLedger>,
+    /// Splices in flight, for the admin endpoint.
+    pub splices: Arc<LiveSplices>,
+    /// Process start, unix seconds.
+    pub started_at: u64,
 }
 
 impl RelayState {
+    /// Unshaped relay without budgets.
     pub fn new(
         hub_key: ed25519_dalek::VerifyingKey,
         max_streams: usize,
         max_reg_per_ip: usize,
         splice_lifetime: Duration,
+    ) -> Arc<Self> {
+        Self::with_limits(
+            hub_key,
+            max_streams,
+            max_reg_per_ip,
+            splice_lifetime,
+            Shaper::default(),
+            None,
+        )
+    }
+
+    /// Relay with rate limits and, optionally, monthly budgets.
+    pub fn with_limits(
+        hub_key: ed25519_dalek::VerifyingKey,
+        max_streams: usize,
+        max_reg_per_ip: usize,
+        splice_lifetime: Duration,
+        shaper: Shaper,
+        ledger: Option<TransitLedger>,
     ) -> Arc<Self> {
         Arc::new(Self {
             hub_key,
@@ -74,6 +118,10 @@ impl RelayState {
             ip_caps: IpCaps::new(max_reg_per_ip),
             stream_sem: Arc::new(Semaphore::new(max_streams)),
             splice_lifetime,
+            shaper,
+            ledger,
+            splices: LiveSplices::new(),
+            started_at: now_unix(),
         })
     }
 }
@@ -117,6 +165,26 @@ pub async fn serve(
     .await
 }
 
+/// UDP reflector for hole punching: answer each well-formed `whoami` with the
+/// source endpoint it came from. The answer is never larger than the request
+/// (see [`xr_proto::punch::WHOAMI_LEN`]) and anything else is dropped, so the
+/// socket can't be turned into an amplifier. Runs until the socket fails.
+pub async fn serve_reflector(sock: UdpSocket) -> io::Result<()> {
+    let mut buf = [0u8; 512];
+    loop {
+        let (n, from) = match sock.recv_from(&mut buf).await {
+            Ok(v) => v,
+            // ICMP port-unreachable of an earlier answer surfaces here on some
+            // platforms; it says nothing about this socket.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(reply) = xr_proto::punch::reflect(&buf[..n], from) {
+            let _ = sock.send_to(&reply, from).await;
+        }
+    }
+}
+
 /// Periodically log the per-share byte totals (LLD-23 §2.6).
 pub fn spawn_counter_logger(state: Arc<RelayState>, interval: Duration) {
     tokio::spawn(async move {
@@ -131,6 +199,24 @@ pub fn spawn_counter_logger(state: Arc<RelayState>, interval: Duration) {
     });
 }
 
+/// Periodically write the budget ledger out, so a restart loses at most one
+/// interval of accounting. No-op without budgets.
+pub fn spawn_ledger_flusher(state: Arc<RelayState>, interval: Duration) {
+    if state.ledger.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        tick.tick().await;
+        loop {
+            tick.tick().await;
+            if let Some(Err(e)) = state.ledger.as_ref().map(TransitLedger::flush) {
+                tracing::warn!("relay transit ledger flush failed: {e}");
+            }
+        }
+    });
+}
+
 /// Read framed bytes until the first complete frame decodes (the MuxInit).
 async fn read_first_frame(tcp: &mut TcpStream, codec: &Codec) -> io::Result<Frame> {
     let mut buf = vec![0u8; 512];
@@ -192,11 +278,16 @@ pub async fn handle_connection(
                 handle_register(stream_id, mux.clone(), peer, &state).await?;
                 break;
             }
-            RELAY_CONNECT_TARGET => {
+            RELAY_CONNECT_TARGET | RELAY_PUNCH_TARGET => {
                 let mux = mux.clone();
                 let state = state.clone();
+                let kind = if target == RELAY_PUNCH_TARGET {
+                    Transit::Rendezvous
+                } else {
+                    Transit::Splice
+                };
                 tokio::spawn(async move {
-                    handle_connect(stream_id, mux, state).await;
+                    handle_connect(stream_id, mux, state, kind).await;
                 });
             }
             _ => {
@@ -257,7 +348,10 @@ async fn handle_register(
         }
     };
 
-    let generation = state.registry.register(pubkey.clone(), mux.clone()).await;
+    let generation = state
+        .registry
+        .register(pubkey.clone(), mux.clone(), peer.ip(), now_unix())
+        .await;
     let _ = stream.send(&[RELAY_HELLO_OK]).await;
     tracing::info!("{peer} agent registered");
 
@@ -271,14 +365,45 @@ async fn handle_register(
     Ok(())
 }
 
-/// Passthrough io that counts the bytes read off the inner end into a shared
-/// total. Wrapping both splice ends counts both transit directions as the bytes
-/// flow, so the per-share total survives any splice outcome (peer reset, the
-/// lifetime cap), unlike `copy_bidirectional`'s return value, which is lost on
-/// error and timeout.
+/// What one splice is charged to: its byte total, the rate buckets that apply
+/// and the monthly ledger. Shared by both splice ends.
+struct Meter {
+    moved: AtomicU64,
+    buckets: Vec<Arc<TokenBucket>>,
+    state: Arc<RelayState>,
+    key: TransitKey,
+    over_budget: AtomicBool,
+}
+
+impl Meter {
+    /// Account `n` bytes just read and return the pause before the next read.
+    fn charge(&self, n: u64) -> Duration {
+        self.moved.fetch_add(n, Ordering::Relaxed);
+        if let Some(ledger) = &self.state.ledger {
+            if !ledger.charge(&self.key, n, now_unix()) {
+                self.over_budget.store(true, Ordering::Relaxed);
+            }
+        }
+        self.buckets.iter().map(|b| b.charge(n)).max().unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Passthrough io that meters the bytes read off the inner end. Wrapping both
+/// splice ends counts both transit directions as the bytes flow, so the
+/// per-share total survives any splice outcome (peer reset, the lifetime cap),
+/// unlike `copy_bidirectional`'s return value, which is lost on error and
+/// timeout. The same hook shapes the splice (the next read waits out the
+/// bucket debt) and cuts it once the monthly budget is spent.
 struct CountedIo<T> {
     inner: T,
-    moved: Arc<AtomicU64>,
+    meter: Arc<Meter>,
+    pause: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<T> CountedIo<T> {
+    fn new(inner: T, meter: Arc<Meter>) -> Self {
+        Self { inner, meter, pause: None }
+    }
 }
 
 impl<T: AsyncRead + Unpin> AsyncRead for CountedIo<T> {
@@ -288,10 +413,23 @@ impl<T: AsyncRead + Unpin> AsyncRead for CountedIo<T> {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let this = self.get_mut();
+        if this.meter.over_budget.load(Ordering::Relaxed) {
+            return Poll::Ready(Err(io::Error::other("transit budget exhausted")));
+        }
+        if let Some(pause) = this.pause.as_mut() {
+            ready!(pause.as_mut().poll(cx));
+            this.pause = None;
+        }
         let before = buf.filled().len();
         let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
         if let Poll::Ready(Ok(())) = &poll {
-            this.moved.fetch_add((buf.filled().len() - before) as u64, Ordering::Relaxed);
+            let n = (buf.filled().len() - before) as u64;
+            if n > 0 {
+                let wait = this.meter.charge(n);
+                if !wait.is_zero() {
+                    this.pause = Some(Box::pin(tokio::time::sleep(wait)));
+                }
+            }
         }
         poll
     }
@@ -315,11 +453,28 @@ impl<T: AsyncWrite + Unpin> AsyncWrite for CountedIo<T> {
     }
 }
 
+/// What a consumer stream is spliced for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transit {
+    /// Transit proper, to the agent's HTTP over [`RELAY_REVERSE_TARGET`].
+    Splice,
+    /// A punch rendezvous to [`RELAY_PUNCH_REVERSE_TARGET`], cut after
+    /// [`RENDEZVOUS_LIFETIME`].
+    Rendezvous,
+}
+
 /// Handle one consumer transit stream (LLD-23 §2.2): read the hello token, verify
 /// it offline, find the agent, open a reverse-stream and splice. Failures answer
 /// with `Close` (agent offline gets [`CLOSE_REASON_AGENT_OFFLINE`], an exhausted
-/// stream cap [`CLOSE_REASON_RELAY_BUSY`]).
-async fn handle_connect(stream_id: u32, mux: Arc<Multiplexer>, state: Arc<RelayState>) {
+/// stream cap [`CLOSE_REASON_RELAY_BUSY`], a spent monthly budget
+/// [`CLOSE_REASON_BUDGET_EXHAUSTED`]). A rendezvous goes through the same
+/// checks and metering; only its reverse target and lifetime differ.
+async fn handle_connect(
+    stream_id: u32,
+    mux: Arc<Multiplexer>,
+    state: Arc<RelayState>,
+    kind: Transit,
+) {
     let permit = match state.stream_sem.clone().try_acquire_owned() {
         Ok(p) => p,
         Err(_) => {
@@ -361,6 +516,16 @@ async fn handle_connect(stream_id: u32, mux: Arc<Multiplexer>, state: Arc<RelayS
         return;
     }
 
+    if let Some(ledger) = &state.ledger {
+        if ledger.exhausted(&token.share_id, &token.agent_pubkey, now_unix()) {
+            tracing::debug!(share = %token.share_id, "relay transit budget exhausted");
+            let _ = mux
+                .send_frame(stream_id, Command::Close, vec![CLOSE_REASON_BUDGET_EXHAUSTED])
+                .await;
+            return;
+        }
+    }
+
     let agent_mux = match state.registry.get(&token.agent_pubkey).await {
         Some(m) if m.is_alive() => m,
         _ => {
@@ -371,9 +536,15 @@ async fn handle_connect(stream_id: u32, mux: Arc<Multiplexer>, state: Arc<RelayS
         }
     };
 
+    let (reverse_target, lifetime) = match kind {
+        Transit::Splice => (RELAY_REVERSE_TARGET, state.splice_lifetime),
+        Transit::Rendezvous => {
+            (RELAY_PUNCH_REVERSE_TARGET, state.splice_lifetime.min(RENDEZVOUS_LIFETIME))
+        }
+    };
     let reverse = match mux_open_stream(
         &agent_mux,
-        &TargetAddr::Domain(RELAY_REVERSE_TARGET.to_string(), 0),
+        &TargetAddr::Domain(reverse_target.to_string(), 0),
     )
     .await
     {
@@ -394,15 +565,27 @@ async fn handle_connect(stream_id: u32, mux: Arc<Multiplexer>, state: Arc<RelayS
     if consumer.send(&[RELAY_HELLO_OK]).await.is_err() {
         return;
     }
-    let moved = Arc::new(AtomicU64::new(0));
-    let mut c_io = CountedIo { inner: consumer.into_io(), moved: moved.clone() };
-    let mut a_io = CountedIo { inner: reverse.into_io(), moved: moved.clone() };
+    let _live = state.splices.enter(&token.share_id, &token.agent_pubkey);
+    let meter = Arc::new(Meter {
+        moved: AtomicU64::new(0),
+        buckets: state.shaper.buckets(&token.share_id, &token.agent_pubkey),
+        state: state.clone(),
+        key: TransitKey::new(&token.share_id, &token.agent_pubkey),
+        over_budget: AtomicBool::new(false),
+    });
+    let mut c_io = CountedIo::new(consumer.into_io(), meter.clone());
+    let mut a_io = CountedIo::new(reverse.into_io(), meter.clone());
     let _ = tokio::time::timeout(
-        state.splice_lifetime,
+        lifetime,
         tokio::io::copy_bidirectional(&mut c_io, &mut a_io),
     )
     .await;
-    state.counters.add(&token.share_id, moved.load(Ordering::Relaxed));
+    drop((c_io, a_io));
+    state.counters.add(&token.share_id, meter.moved.load(Ordering::Relaxed));
+    // The meter holds this splice's buckets: drop it before the shaper checks
+    // whether anyone else still uses them.
+    drop(meter);
+    state.shaper.release(&token.share_id, &token.agent_pubkey);
     drop(permit);
 }
 
@@ -439,9 +622,13 @@ mod tests {
         max_streams: usize,
         splice_lifetime: Duration,
     ) -> (SocketAddr, Arc<RelayState>) {
+        serve_state(RelayState::new(hub.verifying_key(), max_streams, 8, splice_lifetime)).await
+    }
+
+    /// Serve a prepared state on an ephemeral port.
+    async fn serve_state(state: Arc<RelayState>) -> (SocketAddr, Arc<RelayState>) {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
-        let state = RelayState::new(hub.verifying_key(), max_streams, 8, splice_lifetime);
         let s = state.clone();
         let codec = test_codec();
         tokio::spawn(async move { serve(listener, codec, s, 64).await });
@@ -450,7 +637,9 @@ mod tests {
 
     /// Run an agent: register over the real challenge-response, then serve every
     /// reverse-stream by echoing bytes (stands in for the HTTP router + E2E TLS).
-    /// Returns once registered so the consumer can proceed.
+    /// A punch-reverse stream gets its bytes echoed back with a `punch:` prefix,
+    /// so a test can tell which target the relay opened. Returns once registered
+    /// so the consumer can proceed.
     async fn spawn_agent(
         relay_addr: SocketAddr,
         hub: &SigningKey,
@@ -480,16 +669,17 @@ mod tests {
             let mut rx = mux.take_new_stream_rx().await.unwrap();
             while let Some(ns) = rx.recv().await {
                 let (target, _) = TargetAddr::decode(&ns.payload).unwrap();
-                match target {
-                    TargetAddr::Domain(d, _) => assert_eq!(d, RELAY_REVERSE_TARGET),
-                    _ => panic!("reverse stream must target the reverse pseudo-target"),
-                }
+                let prefix: &[u8] = match target {
+                    TargetAddr::Domain(d, _) if d == RELAY_REVERSE_TARGET => b"",
+                    TargetAddr::Domain(d, _) if d == RELAY_PUNCH_REVERSE_TARGET => b"punch:",
+                    _ => panic!("reverse stream must target a reverse pseudo-target"),
+                };
                 let mux = mux.clone();
                 tokio::spawn(async move {
                     let mut stream = mux.register_stream(ns.stream_id).await;
                     mux.send_frame(ns.stream_id, Command::ConnectAck, vec![0]).await.unwrap();
                     while let Some(data) = stream.recv().await {
-                        if stream.send(&data).await.is_err() {
+                        if stream.send(&[prefix, &data].concat()).await.is_err() {
                             break;
                         }
                     }
@@ -640,4 +830,185 @@ mod tests {
         // (not the post-hello ConnectionRefused mapping).
         assert!(open_relay_stream(&mux, &token).await.is_err());
     }
+
+    /// A spent monthly budget refuses the hello with its own Close reason, so
+    /// the consumer sees "budget exhausted" rather than "agent offline"; other
+    /// shares of the same agent still pass.
+    #[tokio::test]
+    async fn test_relay_refuses_over_budget() {
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let agent_pk = b64(identity.verifying_key().as_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = TransitLedger::open(&config::BudgetConfig {
+            state_file: dir.path().join("transit.json").display().to_string(),
+            share_gb_per_month: 1,
+            agent_gb_per_month: 0,
+            flush_secs: 60,
+        })
+        .unwrap();
+        ledger.charge(&TransitKey::new("share-spent", &agent_pk), 1_000_000_000, now_unix());
+        let (relay_addr, _state) = serve_state(RelayState::with_limits(
+            hub.verifying_key(),
+            64,
+            8,
+            Duration::from_secs(30),
+            Shaper::default(),
+            Some(ledger),
+        ))
+        .await;
+        let _agent = spawn_agent(relay_addr, &hub, identity).await;
+
+        let mux = connect_relay_mux(&relay_addr.to_string(), test_codec()).await.unwrap();
+        let token = sign_relay_token(&hub, "share-spent", &agent_pk, now_unix() + 3600);
+        let err = open_relay_stream(&mux, &token).await.unwrap_err();
+        assert_eq!(err.to_string(), xr_proto::relay_client::RELAY_ERR_BUDGET_EXHAUSTED);
+
+        let token = sign_relay_token(&hub, "share-fresh", &agent_pk, now_unix() + 3600);
+        let mut stream = open_relay_stream(&mux, &token).await.unwrap();
+        stream.send(b"ok").await.unwrap();
+        assert_eq!(stream.recv().await.unwrap(), b"ok");
+    }
+
+    /// One raw GET against the admin endpoint: status line and body.
+    async fn admin_get(addr: SocketAddr, path: &str, token: Option<&str>) -> (String, String) {
+        use tokio::io::AsyncWriteExt;
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        let auth = token.map(|t| format!("Authorization: Bearer {t}\r\n")).unwrap_or_default();
+        tcp.write_all(format!("GET {path} HTTP/1.1\r\nHost: relay\r\n{auth}\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut raw = String::new();
+        tcp.read_to_string(&mut raw).await.unwrap();
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    /// The admin endpoint shows the registered agent (by fingerprint, with its
+    /// source IP and generation), the live splice while the stream is open, and
+    /// the share's bytes once it ends. The token gates every route.
+    #[tokio::test]
+    async fn test_admin_stats_show_agents_and_splices() {
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let agent_pk = b64(identity.verifying_key().as_bytes());
+        let (relay_addr, state) = start_relay(&hub).await;
+        let _agent = spawn_agent(relay_addr, &hub, identity).await;
+
+        let admin = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin_addr = admin.local_addr().unwrap();
+        tokio::spawn(admin::serve_admin(admin, state.clone(), Some("s3cret".into())));
+
+        let token = sign_relay_token(&hub, "share-1", &agent_pk, now_unix() + 3600);
+        let mux = connect_relay_mux(&relay_addr.to_string(), test_codec()).await.unwrap();
+        let mut stream = open_relay_stream(&mux, &token).await.unwrap();
+        stream.send(b"ping").await.unwrap();
+        assert_eq!(stream.recv().await.unwrap(), b"ping");
+
+        let (status, _) = admin_get(admin_addr, "/stats", None).await;
+        assert!(status.contains("401"), "{status}");
+        let (status, _) = admin_get(admin_addr, "/nope", Some("s3cret")).await;
+        assert!(status.contains("404"), "{status}");
+
+        let (status, body) = admin_get(admin_addr, "/stats", Some("s3cret")).await;
+        assert!(status.contains("200"), "{status}");
+        let stats: xr_proto::share::RelayStats = serde_json::from_str(&body).unwrap();
+        assert_eq!(stats.live_splices, 1);
+        assert_eq!(stats.agents.len(), 1);
+        let agent = &stats.agents[0];
+        assert_eq!(agent.fingerprint, xr_proto::share::agent_fingerprint(&agent_pk));
+        assert_eq!(agent.peer_ip, "127.0.0.1");
+        assert_eq!(agent.live_splices, 1);
+        assert!(agent.registered_at >= stats.started_at);
+        assert!(!body.contains(&agent_pk), "full keys stay off the endpoint");
+        assert_eq!(stats.shares[0].share_id, "share-1");
+        assert_eq!(stats.shares[0].live_splices, 1);
+
+        drop(stream);
+        for _ in 0..50 {
+            if state.splices.total() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let stats = admin::stats(&state).await;
+        assert_eq!(stats.live_splices, 0);
+        assert_eq!(stats.shares[0].live_splices, 0);
+        assert!(stats.shares[0].bytes > 0, "finished splices leave their bytes behind");
+    }
+
+    /// A per-share limit slows the splice down to its rate: past the bucket's
+    /// one-second burst, every further byte waits for the refill.
+    #[tokio::test]
+    async fn test_relay_shapes_per_share() {
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let agent_pk = b64(identity.verifying_key().as_bytes());
+        // 1 Mbit/s = 125 000 B/s; the bucket is 125 000 B deep.
+        let shaper = Shaper::new(&config::ShapingConfig { per_share_mbps: 1, ..Default::default() });
+        let (relay_addr, _state) = serve_state(RelayState::with_limits(
+            hub.verifying_key(),
+            64,
+            8,
+            Duration::from_secs(30),
+            shaper,
+            None,
+        ))
+        .await;
+        let _agent = spawn_agent(relay_addr, &hub, identity).await;
+
+        let token = sign_relay_token(&hub, "share-slow", &agent_pk, now_unix() + 3600);
+        let mux = connect_relay_mux(&relay_addr.to_string(), test_codec()).await.unwrap();
+        let mut stream = open_relay_stream(&mux, &token).await.unwrap();
+
+        // 100 KB each way through an echo is 200 KB charged: 75 KB over the
+        // burst, so at least ~0.6 s at 125 KB/s.
+        let blob = vec![0x5Au8; 100_000];
+        let started = std::time::Instant::now();
+        stream.send(&blob).await.unwrap();
+        let mut got = 0;
+        while got < blob.len() {
+            got += stream.recv().await.unwrap().len();
+        }
+        assert!(
+            started.elapsed() >= Duration::from_millis(400),
+            "shaped splice finished too fast: {:?}",
+            started.elapsed()
+        );
+    }
+
+    /// Rendezvous: the reflector tells a UDP socket its own endpoint and
+    /// ignores anything that isn't a `whoami`; a token-checked punch stream
+    /// reaches the agent on the punch-reverse target, and a forged token
+    /// doesn't.
+    #[tokio::test]
+    async fn test_relay_punch_rendezvous() {
+        use xr_proto::relay_client::open_relay_target;
+
+        let reflector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflector_at = reflector.local_addr().unwrap();
+        tokio::spawn(serve_reflector(reflector));
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sock.send_to(b"not a whoami", reflector_at).await.unwrap();
+        let seen = xr_proto::punch::observe(&sock, reflector_at).await.unwrap();
+        assert_eq!(seen, sock.local_addr().unwrap());
+
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let identity = SigningKey::from_bytes(&[7u8; 32]);
+        let agent_pk = b64(identity.verifying_key().as_bytes());
+        let (relay_addr, _state) = start_relay(&hub).await;
+        let _agent = spawn_agent(relay_addr, &hub, identity).await;
+
+        let token = sign_relay_token(&hub, "share-1", &agent_pk, now_unix() + 3600);
+        let mux = connect_relay_mux(&relay_addr.to_string(), test_codec()).await.unwrap();
+        let mut stream = open_relay_target(&mux, &token, RELAY_PUNCH_TARGET).await.unwrap();
+        stream.send(b"{\"endpoint\":\"198.51.100.7:4000\"}").await.unwrap();
+        assert_eq!(
+            stream.recv().await.unwrap(),
+            b"punch:{\"endpoint\":\"198.51.100.7:4000\"}".to_vec()
+        );
+
+        let forged = sign_relay_token(&SigningKey::from_bytes(&[1u8; 32]), "share-1", &agent_pk, now_unix() + 3600);
+        assert!(open_relay_target(&mux, &forged, RELAY_PUNCH_TARGET).await.is_err());
+    }
 }
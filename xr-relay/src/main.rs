@@ -4,9 +4,9 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use clap::Parser;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, UdpSocket};
 use xr_relay::config::RelayConfig;
-use xr_relay::{serve, spawn_counter_logger, RelayState};
+use xr_relay::{serve, spawn_counter_logger, spawn_ledger_flusher, RelayState, Shaper, TransitLedger};
 
 #[derive(Parser)]
 #[command(name = "xr-relay", about = "XR share relay: blind transit for NAT'd agents")]
@@ -35,23 +35,51 @@ async fn main() -> anyhow::Result<()> {
 
     let codec = config.codec()?;
     let hub_key = config.hub_key()?;
-    let state = RelayState::new(
+    let ledger = config.budget.as_ref().map(TransitLedger::open).transpose()?;
+    let state = RelayState::with_limits(
         hub_key,
         config.max_streams,
         config.max_registrations_per_ip,
         Duration::from_secs(config.splice_lifetime_secs),
+        Shaper::new(&config.shaping),
+        ledger,
     );
 
     let bind = format!("{}:{}", config.listen, config.port);
     let listener = TcpListener::bind(&bind).await?;
     tracing::info!("xr-relay listening on {bind}");
 
+    if config.punch {
+        let reflector = UdpSocket::bind(&bind).await?;
+        tracing::info!("xr-relay punch reflector on udp {bind}");
+        tokio::spawn(async move {
+            if let Err(e) = xr_relay::serve_reflector(reflector).await {
+                tracing::error!("xr-relay punch reflector is dead: {e}");
+            }
+        });
+    }
+
+    if let Some(admin) = &config.admin {
+        let admin_listener = TcpListener::bind(&admin.listen).await?;
+        tracing::info!("xr-relay admin on {}", admin.listen);
+        let state = state.clone();
+        let token = admin.token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = xr_relay::admin::serve_admin(admin_listener, state, token).await {
+                tracing::error!("xr-relay admin listener is dead: {e}");
+            }
+        });
+    }
+
     spawn_counter_logger(state.clone(), Duration::from_secs(config.counter_log_secs));
+    if let Some(budget) = &config.budget {
+        spawn_ledger_flusher(state.clone(), Duration::from_secs(budget.flush_secs.max(1)));
+    }
 
-    let shutdown = tokio::signal::ctrl_c();
+    let shutdown = shutdown_signal();
     let mut outcome = Ok(());
     tokio::select! {
-        r = serve(listener, codec, state, config.max_connections) => {
+        r = serve(listener, codec, state.clone(), config.max_connections) => {
             if let Err(e) = &r {
                 tracing::error!("xr-relay listener is dead: {e}");
             }
@@ -59,6 +87,22 @@ async fn main() -> anyhow::Result<()> {
         }
         _ = shutdown => tracing::info!("xr-relay shutting down"),
     }
+    if let Some(Err(e)) = state.ledger.as_ref().map(TransitLedger::flush) {
+        tracing::warn!("relay transit ledger flush failed: {e}");
+    }
     outcome?;
     Ok(())
 }
+
+/// Ctrl-C or SIGTERM (`systemctl stop`): either way the ledger gets its final
+/// flush, or the month's counters since the last one are lost.
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to setup SIGTERM handler");
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = sigterm.recv() => {},
+    }
+}
@@ -1,4 +1,5 @@
-//! Agent registry, byte counters and per-IP caps (LLD-23 §2.1, §2.6, §5.2).
+//! Agent registry, byte counters, live splices and per-IP caps (LLD-23 §2.1,
+//! §2.6, §5.2).
 
 use std::collections::HashMap;
 use std::net::IpAddr;
@@ -11,6 +12,18 @@ use xr_proto::mux::Multiplexer;
 struct Registered {
     mux: Arc<Multiplexer>,
     generation: u64,
+    registered_at: u64,
+    peer: IpAddr,
+}
+
+/// One registry entry as the admin endpoint reports it.
+#[derive(Debug, Clone)]
+pub struct RegisteredAgent {
+    pub pubkey: String,
+    pub generation: u64,
+    /// Unix seconds.
+    pub registered_at: u64,
+    pub peer: IpAddr,
 }
 
 /// Maps a proven `agent_pubkey` to its live reverse-tunnel mux (LLD-23 §2.1).
@@ -30,11 +43,24 @@ impl AgentRegistry {
     }
 
     /// Register `mux` under `pubkey`, shutting down and replacing any prior mux.
+    /// `peer` and `now` (unix seconds) are kept for the admin endpoint only.
     /// Returns the generation to hand back to [`deregister`](Self::deregister).
-    pub async fn register(&self, pubkey: String, mux: Arc<Multiplexer>) -> u64 {
+    pub async fn register(
+        &self,
+        pubkey: String,
+        mux: Arc<Multiplexer>,
+        peer: IpAddr,
+        now: u64,
+    ) -> u64 {
         let generation = self.next_gen.fetch_add(1, Ordering::Relaxed);
+        let entry = Registered {
+            mux,
+            generation,
+            registered_at: now,
+            peer,
+        };
         let mut map = self.inner.lock().await;
-        if let Some(old) = map.insert(pubkey, Registered { mux, generation }) {
+        if let Some(old) = map.insert(pubkey, entry) {
             old.mux.shutdown();
         }
         generation
@@ -54,9 +80,23 @@ impl AgentRegistry {
         }
     }
 
-    pub async fn len(&self) -> usize {
+    /// How many agents are registered right now.
+    pub async fn agent_count(&self) -> usize {
         self.inner.lock().await.len()
     }
+
+    /// Every current registration, for the admin endpoint.
+    pub async fn snapshot(&self) -> Vec<RegisteredAgent> {
+        let map = self.inner.lock().await;
+        map.iter()
+            .map(|(pubkey, r)| RegisteredAgent {
+                pubkey: pubkey.clone(),
+                generation: r.generation,
+                registered_at: r.registered_at,
+                peer: r.peer,
+            })
+            .collect()
+    }
 }
 
 /// Per-share transit byte totals (LLD-23 §2.6). No content, no share names beyond
@@ -92,6 +132,86 @@ impl Counters {
     }
 }
 
+/// Splices in flight, per share and per agent. A splice holds a [`SpliceGuard`]
+/// from the granted hello until it ends; dropping it takes the splice off.
+#[derive(Default)]
+pub struct LiveSplices {
+    inner: StdMutex<LiveCounts>,
+}
+
+#[derive(Default)]
+struct LiveCounts {
+    total: u64,
+    shares: HashMap<String, u64>,
+    agents: HashMap<String, u64>,
+}
+
+impl LiveSplices {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Count one splice of `share_id` to `agent_pubkey` until the guard drops.
+    pub fn enter(self: &Arc<Self>, share_id: &str, agent_pubkey: &str) -> SpliceGuard {
+        let mut c = self.inner.lock().unwrap();
+        c.total += 1;
+        *c.shares.entry(share_id.to_string()).or_insert(0) += 1;
+        *c.agents.entry(agent_pubkey.to_string()).or_insert(0) += 1;
+        SpliceGuard {
+            live: self.clone(),
+            share_id: share_id.to_string(),
+            agent_pubkey: agent_pubkey.to_string(),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.inner.lock().unwrap().total
+    }
+
+    pub fn share(&self, share_id: &str) -> u64 {
+        *self.inner.lock().unwrap().shares.get(share_id).unwrap_or(&0)
+    }
+
+    pub fn agent(&self, agent_pubkey: &str) -> u64 {
+        *self.inner.lock().unwrap().agents.get(agent_pubkey).unwrap_or(&0)
+    }
+
+    /// Snapshot of `(share_id, live_splices)`.
+    pub fn shares(&self) -> Vec<(String, u64)> {
+        let c = self.inner.lock().unwrap();
+        c.shares.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+
+    fn leave(&self, share_id: &str, agent_pubkey: &str) {
+        let mut c = self.inner.lock().unwrap();
+        c.total -= 1;
+        decrement(&mut c.shares, share_id);
+        decrement(&mut c.agents, agent_pubkey);
+    }
+}
+
+fn decrement(map: &mut HashMap<String, u64>, key: &str) {
+    if let Some(count) = map.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            map.remove(key);
+        }
+    }
+}
+
+/// RAII splice in a [`LiveSplices`] count; leaves on drop.
+pub struct SpliceGuard {
+    live: Arc<LiveSplices>,
+    share_id: String,
+    agent_pubkey: String,
+}
+
+impl Drop for SpliceGuard {
+    fn drop(&mut self) {
+        self.live.leave(&self.share_id, &self.agent_pubkey);
+    }
+}
+
 /// A live-registration budget per source IP (LLD-23 §5.2). A registrant holds a
 /// [`IpCapGuard`] for the life of its connection; dropping it frees the slot.
 pub struct IpCaps {
@@ -164,27 +284,31 @@ mod tests {
         Multiplexer::new_server(a, codec, xr_proto::mux::MuxCaps::LOCAL)
     }
 
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
     #[tokio::test]
     async fn test_registry_evicts_and_generation_guards() {
         let reg = AgentRegistry::new();
         let first = dead_mux();
-        let g1 = reg.register("agentA".into(), first.clone()).await;
-        assert_eq!(reg.len().await, 1);
+        let g1 = reg.register("agentA".into(), first.clone(), PEER, 100).await;
+        assert_eq!(reg.agent_count().await, 1);
 
         // Re-register the same key: last-writer-wins, old mux is shut down.
         let second = dead_mux();
-        let g2 = reg.register("agentA".into(), second.clone()).await;
+        let g2 = reg.register("agentA".into(), second.clone(), PEER, 200).await;
         assert_ne!(g1, g2);
-        assert_eq!(reg.len().await, 1);
+        assert_eq!(reg.agent_count().await, 1);
+        let snap = reg.snapshot().await;
+        assert_eq!((snap[0].generation, snap[0].registered_at), (g2, 200));
         assert!(!first.is_alive(), "evicted mux must be shut down");
 
         // The stale connection (g1) deregistering must NOT drop the fresh one.
         reg.deregister("agentA", g1).await;
-        assert_eq!(reg.len().await, 1, "generation guard protects the newer registration");
+        assert_eq!(reg.agent_count().await, 1, "generation guard protects the newer registration");
 
         // The current owner (g2) deregisters cleanly.
         reg.deregister("agentA", g2).await;
-        assert_eq!(reg.len().await, 0);
+        assert_eq!(reg.agent_count().await, 0);
     }
 
     #[test]
@@ -200,6 +324,20 @@ mod tests {
         drop(g2);
     }
 
+    #[test]
+    fn test_live_splices_follow_guards() {
+        let live = LiveSplices::new();
+        let a = live.enter("s1", "agent");
+        let b = live.enter("s1", "agent");
+        let c = live.enter("s2", "agent");
+        assert_eq!((live.total(), live.share("s1"), live.agent("agent")), (3, 2, 3));
+        drop((a, c));
+        assert_eq!((live.total(), live.share("s1"), live.share("s2")), (1, 1, 0));
+        drop(b);
+        assert_eq!(live.total(), 0);
+        assert!(live.shares().is_empty(), "finished shares drop out");
+    }
+
     #[test]
     fn test_counters_accumulate_per_share() {
         let c = Counters::new();
@@ -0,0 +1,158 @@
+//! Transit rate limits: token buckets per share, per agent and for the whole
+//! relay. One consumer pulling a huge folder must not saturate the VPS uplink
+//! for everyone else, so every spliced byte is charged to all buckets that
+//! apply, and the splice pauses until the slowest of them has refilled.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::ShapingConfig;
+
+/// Smallest bucket depth. A bucket at least this deep lets a slow limit still
+/// move whole mux frames instead of pausing on every read.
+const MIN_BURST: u64 = 64 * 1024;
+
+/// A token bucket in bytes. Charging may drive it into debt: the caller moves
+/// the bytes it already read and then sleeps for the returned pause, which keeps
+/// the average at `rate` without ever splitting a read.
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: StdMutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// A full bucket refilling at `rate` bytes per second, one second deep.
+    pub fn new(rate: u64) -> Self {
+        let burst = rate.max(MIN_BURST) as f64;
+        Self {
+            rate: rate as f64,
+            burst,
+            state: StdMutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Charge `bytes` and return how long to pause before the next read.
+    pub fn charge(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let (tokens, last) = *state;
+        let refilled = (tokens + now.duration_since(last).as_secs_f64() * self.rate).min(self.burst);
+        let left = refilled - bytes as f64;
+        *state = (left, now);
+        if left >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-left / self.rate)
+        }
+    }
+}
+
+/// Bytes per second from a config rate in megabits per second; `0` is no limit.
+fn rate_bytes(mbps: u64) -> Option<u64> {
+    (mbps > 0).then(|| mbps * 1_000_000 / 8)
+}
+
+/// All rate limits of the relay. Per-share and per-agent buckets are created on
+/// the first splice and shared by every concurrent splice of the same id, so
+/// ten parallel streams of one share split its limit instead of multiplying it.
+#[derive(Default)]
+pub struct Shaper {
+    global: Option<Arc<TokenBucket>>,
+    per_agent: Option<u64>,
+    per_share: Option<u64>,
+    agents: StdMutex<HashMap<String, Arc<TokenBucket>>>,
+    shares: StdMutex<HashMap<String, Arc<TokenBucket>>>,
+}
+
+impl Shaper {
+    pub fn new(cfg: &ShapingConfig) -> Self {
+        Self {
+            global: rate_bytes(cfg.global_mbps).map(|r| Arc::new(TokenBucket::new(r))),
+            per_agent: rate_bytes(cfg.per_agent_mbps),
+            per_share: rate_bytes(cfg.per_share_mbps),
+            ..Self::default()
+        }
+    }
+
+    /// The buckets one splice is charged to. Empty when nothing is limited.
+    pub fn buckets(&self, share_id: &str, agent_pubkey: &str) -> Vec<Arc<TokenBucket>> {
+        let mut out: Vec<Arc<TokenBucket>> = self.global.iter().cloned().collect();
+        if let Some(rate) = self.per_share {
+            out.push(bucket_for(&self.shares, share_id, rate));
+        }
+        if let Some(rate) = self.per_agent {
+            out.push(bucket_for(&self.agents, agent_pubkey, rate));
+        }
+        out
+    }
+
+    /// Forget the buckets of a finished splice once no other splice holds them.
+    /// Called after the splice dropped its own handles.
+    pub fn release(&self, share_id: &str, agent_pubkey: &str) {
+        prune(&self.shares, share_id);
+        prune(&self.agents, agent_pubkey);
+    }
+}
+
+fn bucket_for(
+    map: &StdMutex<HashMap<String, Arc<TokenBucket>>>,
+    key: &str,
+    rate: u64,
+) -> Arc<TokenBucket> {
+    map.lock()
+        .unwrap()
+        .entry(key.to_string())
+        .or_insert_with(|| Arc::new(TokenBucket::new(rate)))
+        .clone()
+}
+
+fn prune(map: &StdMutex<HashMap<String, Arc<TokenBucket>>>, key: &str) {
+    let mut m = map.lock().unwrap();
+    if m.get(key).is_some_and(|b| Arc::strong_count(b) == 1) {
+        m.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_pauses_in_proportion_to_debt() {
+        // 1 MB/s, one second deep: the first megabyte goes through at once, the
+        // next half megabyte costs half a second.
+        let b = TokenBucket::new(1_000_000);
+        assert_eq!(b.charge(1_000_000), Duration::ZERO);
+        assert_eq!(b.charge(500_000), Duration::from_millis(500));
+        // After the pause the debt is paid and the bucket keeps refilling.
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert_eq!(b.charge(1_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn shaper_shares_buckets_per_id_and_prunes() {
+        let shaper = Shaper::new(&ShapingConfig {
+            global_mbps: 100,
+            per_agent_mbps: 20,
+            per_share_mbps: 8,
+        });
+        let a = shaper.buckets("s1", "agent");
+        let b = shaper.buckets("s1", "agent");
+        assert_eq!(a.len(), 3, "global, share and agent");
+        assert!(Arc::ptr_eq(&a[1], &b[1]), "one bucket per share across splices");
+
+        drop(a);
+        shaper.release("s1", "agent");
+        assert_eq!(shaper.shares.lock().unwrap().len(), 1, "a live splice keeps its bucket");
+        drop(b);
+        shaper.release("s1", "agent");
+        assert!(shaper.shares.lock().unwrap().is_empty());
+        assert!(shaper.agents.lock().unwrap().is_empty());
+
+        assert!(Shaper::new(&ShapingConfig::default()).buckets("s", "a").is_empty());
+    }
+}
@@ -21,10 +21,13 @@ fn configure_socket(stream: &TcpStream) {
 }
 
 /// Handle a single client connection end-to-end.
+///
+/// `codecs` это принимаемые ключи, основной первым; коннект дальше живёт на
+/// том, которым разобрался его первый кадр (ротация ключа без рестарта).
 pub async fn handle_client(
     mut client: TcpStream,
     client_addr: SocketAddr,
-    codec: Codec,
+    codecs: &[Codec],
     timeout: Duration,
     fallback_response: Option<Vec<u8>>,
     limits: crate::mux_handler::StreamLimits,
@@ -34,8 +37,8 @@ pub async fn handle_client(
     // Read first frame (Connect command) with timeout
     let mut buf = vec![0u8; 4096];
 
-    let (connect_frame, filled) = match read_first_frame(&mut client, &mut buf, &codec, timeout).await? {
-        FirstFrameOutcome::Ready(frame, leftover) => (frame, leftover),
+    let (connect_frame, filled, codec) = match read_first_frame(&mut client, &mut buf, codecs, timeout).await? {
+        FirstFrameOutcome::Ready(frame, leftover, key) => (frame, leftover, codecs[key].clone()),
         FirstFrameOutcome::NeedFallback(reason) => {
             if reason == FallbackReason::InvalidFrame {
                 tracing::debug!("Invalid frame from {}, sending fallback", client_addr);
@@ -114,11 +117,11 @@ async fn send_fallback_and_close(
 
 /// Итог накопления первого кадра хендшейка: либо кадр собрался, либо
 /// приёмник обязан уйти в fallback (буфер кончился или заголовок не наш).
-#[derive(Debug)]
 enum FirstFrameOutcome {
     /// Кадр разобран; второе поле - сколько байт после него уже лежит в
-    /// начале `buf` (хвост, прочитанный тем же read, что и сам кадр).
-    Ready(Frame, usize),
+    /// начале `buf` (хвост, прочитанный тем же read, что и сам кадр), третье -
+    /// индекс кодека ключа, которым кадр разобрался.
+    Ready(Frame, usize, usize),
     NeedFallback(FallbackReason),
 }
 
@@ -136,13 +139,20 @@ enum FallbackReason {
 /// нужен приёмник, у которого границы чтений заданы явно, а не тем, что
 /// успеет накопиться в сокете к моменту вызова `read` - на живом `TcpStream`
 /// это гонка (см. XR-215).
+///
+/// Ключей может быть несколько (ротация): кадр пробуется каждым, и ключ,
+/// под которым заголовок не разбирается или первый кадр не `Connect` и не
+/// `MuxInit`, выбывает. Чужой ключ изредка даёт правдоподобную команду, но
+/// вместе с длинами, укладывающимися в прочитанное, это практически
+/// исключено, а свой ключ собирает кадр первым.
 async fn read_first_frame<R: AsyncRead + Unpin>(
     reader: &mut R,
     buf: &mut Vec<u8>,
-    codec: &Codec,
+    codecs: &[Codec],
     timeout: Duration,
 ) -> io::Result<FirstFrameOutcome> {
     let mut filled = 0;
+    let mut alive = vec![true; codecs.len()];
 
     loop {
         let n = tokio::time::timeout(timeout, reader.read(&mut buf[filled..]))
@@ -154,29 +164,37 @@ async fn read_first_frame<R: AsyncRead + Unpin>(
         }
         filled += n;
 
-        match codec.decode_frame(&buf[..filled]) {
-            Ok(Some((frame, consumed))) => {
-                buf.copy_within(consumed..filled, 0);
-                return Ok(FirstFrameOutcome::Ready(frame, filled - consumed));
-            }
-            Ok(None) => {
-                if filled >= buf.len() {
-                    // Буфер заполнен целиком, а кадр всё ещё не собран (например,
-                    // header врёт про огромный payload_len, либо это вообще не
-                    // наш протокол). Раньше условие сравнивалось с тем же
-                    // размером буфера и никогда не срабатывало: цикл шёл на
-                    // следующий read в пустой остаток среза, тот немедленно
-                    // возвращал Ok(0), и код принимал это за закрытие клиентом -
-                    // соединение рвалось молча вместо fallback-ответа, и зонд
-                    // видел не то поведение, что у веб-сервера.
-                    return Ok(FirstFrameOutcome::NeedFallback(FallbackReason::Overflow));
-                }
+        let mut pending = false;
+        for (key, (codec, alive)) in codecs.iter().zip(alive.iter_mut()).enumerate() {
+            if !*alive {
                 continue;
             }
-            Err(_) => {
-                return Ok(FirstFrameOutcome::NeedFallback(FallbackReason::InvalidFrame));
+            match codec.decode_frame(&buf[..filled]) {
+                Ok(Some((frame, consumed)))
+                    if matches!(frame.command, Command::Connect | Command::MuxInit) =>
+                {
+                    buf.copy_within(consumed..filled, 0);
+                    return Ok(FirstFrameOutcome::Ready(frame, filled - consumed, key));
+                }
+                Ok(None) => pending = true,
+                Ok(Some(_)) | Err(_) => *alive = false,
             }
         }
+
+        if !pending {
+            return Ok(FirstFrameOutcome::NeedFallback(FallbackReason::InvalidFrame));
+        }
+        if filled >= buf.len() {
+            // Буфер заполнен целиком, а кадр всё ещё не собран (например,
+            // header врёт про огромный payload_len, либо это вообще не
+            // наш протокол). Раньше условие сравнивалось с тем же
+            // размером буфера и никогда не срабатывало: цикл шёл на
+            // следующий read в пустой остаток среза, тот немедленно
+            // возвращал Ok(0), и код принимал это за закрытие клиентом -
+            // соединение рвалось молча вместо fallback-ответа, и зонд
+            // видел не то поведение, что у веб-сервера.
+            return Ok(FirstFrameOutcome::NeedFallback(FallbackReason::Overflow));
+        }
     }
 }
 
@@ -337,7 +355,7 @@ mod tests {
             handle_client(
                 stream,
                 peer,
-                server_codec,
+                &[server_codec],
                 Duration::from_secs(2),
                 Some(server_fallback),
                 crate::mux_handler::StreamLimits::new(1024, 1024),
@@ -394,7 +412,7 @@ mod tests {
             let _ = handle_client(
                 stream,
                 peer,
-                server_codec,
+                &[server_codec],
                 Duration::from_secs(2),
                 None,
                 crate::mux_handler::StreamLimits::new(1024, 1024),
@@ -472,12 +490,12 @@ mod tests {
         let mut reader = ChunkedReader::new(vec![wire[..split].to_vec(), wire[split..].to_vec()]);
         let mut buf = vec![0u8; 4096];
 
-        let outcome = read_first_frame(&mut reader, &mut buf, &codec, Duration::from_secs(2))
+        let outcome = read_first_frame(&mut reader, &mut buf, std::slice::from_ref(&codec), Duration::from_secs(2))
             .await
             .expect("кадр, пришедший двумя read, должен дособраться без ошибки");
 
         match outcome {
-            FirstFrameOutcome::Ready(frame, leftover) => {
+            FirstFrameOutcome::Ready(frame, leftover, _) => {
                 assert_eq!(
                     frame.command,
                     Command::Connect,
@@ -493,4 +511,61 @@ mod tests {
             }
         }
     }
+
+    /// Ротация ключа: первый кадр, зашифрованный дополнительным ключом,
+    /// разбирается им, и коннект получает именно его кодек. Кадр ключом, которого
+    /// сервер не знает, уходит в fallback.
+    #[tokio::test]
+    async fn first_frame_picks_matching_rotation_key() {
+        let primary = make_codec();
+        let rotated = Codec::new(
+            Obfuscator::new(
+                b"rotation-key-0123456789ABCDEFGH".to_vec(),
+                0xDEADBEEF,
+                ModifierStrategy::PositionalXorRotate,
+            ),
+            0,
+            0,
+        );
+        let stranger = Codec::new(
+            Obfuscator::new(
+                b"unknown-key-0123456789ABCDEFGHI".to_vec(),
+                0xDEADBEEF,
+                ModifierStrategy::PositionalXorRotate,
+            ),
+            0,
+            0,
+        );
+        let codecs = [primary, rotated.clone()];
+        let payload = TargetAddr::Ip("127.0.0.1:443".parse().unwrap()).encode().unwrap();
+
+        let wire = rotated.encode_frame(Command::Connect, &payload).unwrap();
+        let mut reader = ChunkedReader::new(vec![wire]);
+        let mut buf = vec![0u8; 4096];
+        match read_first_frame(&mut reader, &mut buf, &codecs, Duration::from_secs(2))
+            .await
+            .unwrap()
+        {
+            FirstFrameOutcome::Ready(frame, _, key) => {
+                assert_eq!(frame.command, Command::Connect);
+                assert_eq!(key, 1, "коннект обязан жить на ключе своего первого кадра");
+            }
+            FirstFrameOutcome::NeedFallback(reason) => panic!("fallback: {:?}", reason),
+        }
+
+        // Незнакомый ключ: на паре сидов заголовок может случайно
+        // разобраться, поэтому берём несколько кадров и требуем, чтобы ни
+        // один не прошёл как свой.
+        for _ in 0..16 {
+            let wire = stranger.encode_frame(Command::Connect, &payload).unwrap();
+            let mut reader = ChunkedReader::new(vec![wire]);
+            let mut buf = vec![0u8; 4096];
+            let outcome =
+                read_first_frame(&mut reader, &mut buf, &codecs, Duration::from_millis(50)).await;
+            assert!(
+                !matches!(outcome, Ok(FirstFrameOutcome::Ready(..))),
+                "кадр чужим ключом не должен приниматься"
+            );
+        }
+    }
 }
@@ -1,18 +1,17 @@
 mod fallback;
 mod handler;
 mod mux_handler;
+mod reload;
 mod udp_relay;
 
 use clap::Parser;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tokio::sync::Semaphore;
-use tokio::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use xr_proto::accept::accept_loop;
-use xr_proto::config::{decode_key, load_server_config};
-use xr_proto::obfuscation::{ModifierStrategy, Obfuscator};
-use xr_proto::protocol::Codec;
+use xr_proto::config::load_server_config;
 
 #[derive(Parser)]
 #[command(name = "xr-server", about = "XR Proxy Server — lightweight obfuscated proxy server")]
@@ -33,76 +32,65 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load config
     let config = load_server_config(&cli.config)?;
 
-    // Setup logging
+    // Setup logging. Фильтр за reload-слоем: перезагрузка конфига меняет
+    // уровень на лету, если он не зафиксирован `--log-level`.
     let log_level = cli.log_level.as_deref().unwrap_or(&config.logging.level);
     let filter = tracing_subscriber::EnvFilter::try_new(log_level)
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn"));
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .compact()
+    let (filter, log_handle) = tracing_subscriber::reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_target(false).compact())
         .init();
 
     tracing::info!("XR Proxy Server starting");
 
-    // Build obfuscator
-    let key = decode_key(&config.obfuscation.key)?;
-    let strategy = ModifierStrategy::from_str(&config.obfuscation.modifier)
-        .ok_or("unknown modifier strategy")?;
-    let obfuscator = Obfuscator::new(key, config.obfuscation.salt as u32, strategy);
-    let udp_obfuscator = obfuscator.clone();
-    // Server doesn't need padding — it uses whatever the client sends
-    let codec = Codec::new(obfuscator, 16, 128);
-
-    // Build fallback response
-    let fallback_response = if config.fallback.enabled {
-        Some(fallback::build_fallback_response(
-            config.fallback.response_file.as_deref(),
-        ))
-    } else {
-        None
-    };
-
-    let timeout = Duration::from_secs(config.limits.connection_timeout_sec);
-    let max_conns = config.limits.max_connections as usize;
-
     // Bind listener
     let bind_addr = format!("{}:{}", config.server.listen, config.server.port);
     let listener = TcpListener::bind(&bind_addr).await?;
     tracing::info!("Server listening on {}", bind_addr);
 
-    // Connection limiter
-    let semaphore = Arc::new(Semaphore::new(max_conns));
-    // Кап стримов внутри mux-сессий (XR-199): семафор коннектов их не видит.
-    let stream_limits = mux_handler::StreamLimits::new(
-        config.limits.max_streams as usize,
-        config.limits.max_streams_per_mux as usize,
-    );
     // Кап называется в логе на старте: иначе о нём узнают только по отказу, а
     // молчание неотличимо от сервера, где лимита нет вовсе.
     tracing::info!(
         "Limits: {} connections, {} streams total, {} streams per mux session",
-        max_conns,
+        config.limits.max_connections,
         config.limits.max_streams,
         config.limits.max_streams_per_mux
     );
+    if !config.obfuscation.rotation.is_empty() || config.obfuscation.valid_until.is_some() {
+        tracing::info!(
+            "Obfuscation key rotation: {} extra key(s), primary valid until {:?}",
+            config.obfuscation.rotation.len(),
+            config.obfuscation.valid_until
+        );
+    }
 
-    // Start UDP relay if configured
-    if let Some(udp_config) = config.udp_relay {
-        if udp_config.enabled {
-            let udp_obfs = udp_obfuscator;
-            tokio::spawn(async move {
-                if let Err(e) = udp_relay::run_udp_relay_server(
-                    udp_config.listen_port,
-                    udp_obfs,
-                    udp_config.flow_timeout_sec,
-                    udp_config.incoming_port_min,
-                    udp_config.incoming_port_max,
-                ).await {
-                    tracing::error!("UDP relay server failed: {}", e);
-                }
-            });
-        }
+    // Горячая перезагрузка: SIGHUP или правка файла. Живые сессии остаются
+    // на своём снимке настроек, новые коннекты берут свежий.
+    let udp_ports = config.udp_relay.as_ref().filter(|u| u.enabled).map(|u| {
+        (u.listen_port, u.flow_timeout_sec, u.incoming_port_min, u.incoming_port_max)
+    });
+    let log_handle = cli.log_level.is_none().then_some(log_handle);
+    let reloader = Arc::new(reload::Reloader::new(cli.config.clone(), config, log_handle)?);
+    tokio::spawn(reloader.clone().run());
+
+    // Start UDP relay if configured. Порт и таймауты берутся на старте, а ключи
+    // из того же снимка, что у mux: ротация и её окна действуют и здесь.
+    if let Some((listen_port, flow_timeout_sec, port_min, port_max)) = udp_ports {
+        let keys = reloader.clone();
+        let udp_keys: udp_relay::KeySource = Box::new(move || keys.settings());
+        tokio::spawn(async move {
+            if let Err(e) = udp_relay::run_udp_relay_server(
+                listen_port,
+                udp_keys,
+                flow_timeout_sec,
+                port_min,
+                port_max,
+            ).await {
+                tracing::error!("UDP relay server failed: {}", e);
+            }
+        });
     }
 
     // Accept loop
@@ -119,10 +107,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         },
         |stream, addr| {
-            let codec = codec.clone();
-            let fallback = fallback_response.clone();
-            let sem = semaphore.clone();
-            let limits = stream_limits.clone();
+            let settings = reloader.settings();
+            let sem = reloader.connections().clone();
 
             tokio::spawn(async move {
                 let _permit = match sem.try_acquire() {
@@ -133,8 +119,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 };
 
-                if let Err(e) =
-                    handler::handle_client(stream, addr, codec, timeout, fallback, limits).await
+                if let Err(e) = handler::handle_client(
+                    stream,
+                    addr,
+                    &settings.codecs,
+                    settings.timeout,
+                    settings.fallback.clone(),
+                    settings.limits.clone(),
+                )
+                .await
                 {
                     tracing::warn!("Client {} error: {}", addr, e);
                 }
@@ -1,18 +1,22 @@
 //! Server-side multiplexed connection handler.
 //!
 //! Accepts MuxInit, then serves multiple concurrent streams over
-//! one TCP connection. Each stream maps to an independent target.
+//! one TCP connection. Each stream maps to an independent target;
+//! datagram associations (UdpAssociate) relay UDP through a socket of their own.
 
 use std::io;
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UdpSocket};
 use tokio::sync::Semaphore;
 use tokio::time::Duration;
 
-use xr_proto::mux::{mux_handshake_server, Multiplexer};
+use xr_proto::mux::{
+    mux_handshake_server_with, mux_stripe_group_id, Multiplexer, MuxCaps, MuxDatagram,
+    StreamKind, StripeRegistry,
+};
 use xr_proto::protocol::{
     Codec, Command, Frame, TargetAddr, CLOSE_REASON_CONNECT_FAIL, CLOSE_REASON_RESOLVE_FAIL,
     CLOSE_REASON_STREAM_LIMIT,
@@ -21,15 +25,22 @@ use xr_proto::protocol::{
 const TARGET_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
 const MAX_LIFETIME: Duration = Duration::from_secs(3600);
+/// Простой датаграммной ассоциации: ни пакета ни в одну сторону. Как
+/// flow_timeout отдельного UDP relay по умолчанию.
+const DATAGRAM_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
 
 /// Кап стримов сервера (XR-199): `max_connections` считает TCP-коннекты, а
 /// стримов в одной mux-сессии сколько угодно, и каждый стоит fd апстрима с
 /// парой тасок. Общий семафор это бюджет VPS, `per_mux` это доля одной сессии,
 /// чтобы жадный клиент не выбрал бюджет целиком.
+///
+/// Здесь же живёт реестр групп multipath: туннели одной группы делят стримы,
+/// и акцептору нужна общая на весь сервер точка их сборки.
 #[derive(Clone)]
 pub struct StreamLimits {
     pub total: Arc<Semaphore>,
     pub per_mux: usize,
+    pub stripes: Arc<StripeRegistry>,
 }
 
 impl StreamLimits {
@@ -37,6 +48,7 @@ impl StreamLimits {
         Self {
             total: Arc::new(Semaphore::new(max_streams)),
             per_mux: max_streams_per_mux,
+            stripes: Arc::new(StripeRegistry::new()),
         }
     }
 }
@@ -67,14 +79,31 @@ async fn handle_mux_client_lt(
     lifetime: Duration,
     limits: StreamLimits,
 ) -> io::Result<()> {
-    let Some(caps) = mux_handshake_server(&mut client, &codec, init_frame).await? else {
+    // Сервер релеит и датаграммы, поэтому подтверждает полный набор.
+    let Some(caps) = mux_handshake_server_with(&mut client, &codec, init_frame, MuxCaps::LOCAL)
+        .await?
+    else {
         tracing::warn!("{} mux handshake rejected", client_addr);
         return Ok(());
     };
 
-    tracing::info!("{} mux session started", client_addr);
-
-    let mux = Multiplexer::new_server(client, codec.clone(), caps);
+    // Туннель multipath вступает в группу клиента: ConnectAck и балк его
+    // стримов могут уйти по любому члену, а собирает их общий реордер.
+    let mux = match mux_stripe_group_id(init_frame).filter(|_| caps.stripe) {
+        Some(id) => {
+            let group = limits.stripes.join(id);
+            tracing::info!(
+                "{} mux session started (stripe group, {} active)",
+                client_addr,
+                limits.stripes.len()
+            );
+            Multiplexer::new_server_striped(client, codec.clone(), caps, group)
+        }
+        None => {
+            tracing::info!("{} mux session started", client_addr);
+            Multiplexer::new_server(client, codec.clone(), caps)
+        }
+    };
 
     let mut new_stream_rx = mux.take_new_stream_rx().await
         .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "new_stream_rx already taken"))?;
@@ -88,15 +117,19 @@ async fn handle_mux_client_lt(
             stream_count += 1;
             let stream_id = new_stream.stream_id;
 
-            // Decode target address from Connect payload.
-            let target_addr = match TargetAddr::decode(&new_stream.payload) {
-                Ok((addr, _)) => addr,
-                Err(e) => {
-                    tracing::debug!("{} sid={} bad Connect payload: {}", client_addr, stream_id, e);
-                    // Send Close for this stream.
-                    let _ = mux.send_stream_close(stream_id, Vec::new()).await;
-                    continue;
-                }
+            // Decode target address from Connect payload. Ассоциации адреса
+            // не несут: он едет в каждом UdpData.
+            let target_addr = match new_stream.kind {
+                StreamKind::Datagram => None,
+                StreamKind::Connect => match TargetAddr::decode(&new_stream.payload) {
+                    Ok((addr, _)) => Some(addr),
+                    Err(e) => {
+                        tracing::debug!("{} sid={} bad Connect payload: {}", client_addr, stream_id, e);
+                        // Send Close for this stream.
+                        let _ = mux.send_stream_close(stream_id, Vec::new()).await;
+                        continue;
+                    }
+                },
             };
 
             // Кап стримов (XR-199): permit берётся до ConnectAck и переезжает в
@@ -131,7 +164,10 @@ async fn handle_mux_client_lt(
                 }
             };
 
-            tracing::info!("{} sid={} -> {}", client_addr, stream_id, addr_display(&target_addr));
+            match &target_addr {
+                Some(addr) => tracing::info!("{} sid={} -> {}", client_addr, stream_id, addr_display(addr)),
+                None => tracing::info!("{} sid={} -> udp", client_addr, stream_id),
+            }
 
             // Send ConnectAck immediately (before connecting to target).
             if let Err(e) = mux.send_frame(stream_id, Command::ConnectAck, vec![0]).await {
@@ -139,6 +175,20 @@ async fn handle_mux_client_lt(
                 break;
             }
 
+            let Some(target_addr) = target_addr else {
+                // Датаграммы занимают те же permit'ы, что и стрим: сокет на VPS
+                // стоит столько же, сколько сокет апстрима (XR-199).
+                let assoc = mux.register_datagram(stream_id).await;
+                tokio::spawn(async move {
+                    if let Err(e) = relay_datagrams(assoc).await {
+                        tracing::debug!("{} sid={} udp relay error: {}", client_addr, stream_id, e);
+                    }
+                    drop(session_permit);
+                    drop(total_permit);
+                });
+                continue;
+            };
+
             // Register the stream on the multiplexer so Data frames are routed to it.
             let mux_stream = mux.register_stream(stream_id).await;
 
@@ -299,6 +349,58 @@ async fn relay_pump(
     }
 }
 
+/// Relay datagrams between a mux association and a UDP socket of its own.
+///
+/// Сокет один на ассоциацию, эфемерный и endpoint-independent: ответ любого
+/// адресата уходит клиенту с адресом отправителя в UdpData, как у full-cone
+/// NAT. Клиент открывает ассоциацию на каждый свой поток, так что сокет на VPS
+/// живёт ровно столько, сколько поток (idle DATAGRAM_IDLE_TIMEOUT).
+async fn relay_datagrams(mut assoc: MuxDatagram) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let sender = assoc.sender();
+    let mut buf = vec![0u8; 65536];
+
+    let pump = async {
+        loop {
+            let step = tokio::time::timeout(DATAGRAM_IDLE_TIMEOUT, async {
+                tokio::select! {
+                    d = assoc.recv_from() => {
+                        // None = клиент закрыл ассоциацию или mux умер.
+                        let Some((dst, data)) = d else { return Ok(false) };
+                        // Ошибка одного адресата (не резолвится, v6 с v4-сокета)
+                        // не рвёт ассоциацию: датаграмма просто теряется.
+                        match resolve_target(&dst).await {
+                            Ok(addr) => {
+                                if let Err(e) = socket.send_to(&data, addr).await {
+                                    tracing::debug!("udp relay send to {}: {}", addr, e);
+                                }
+                            }
+                            Err(e) => tracing::debug!("udp relay resolve {}: {}", addr_display(&dst), e),
+                        }
+                        Ok::<bool, io::Error>(true)
+                    }
+                    r = socket.recv_from(&mut buf) => {
+                        let (n, from) = r?;
+                        sender.send_to(&TargetAddr::Ip(from), &buf[..n])?;
+                        Ok(true)
+                    }
+                }
+            })
+            .await;
+            match step {
+                Ok(Ok(true)) => {}
+                Ok(Ok(false)) | Err(_) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+            }
+        }
+    };
+
+    match tokio::time::timeout(MAX_LIFETIME, pump).await {
+        Ok(r) => r,
+        Err(_) => Ok(()),
+    }
+}
+
 fn addr_display(addr: &TargetAddr) -> String {
     match addr {
         TargetAddr::Domain(d, p) => format!("{}:{}", d, p),
@@ -337,7 +439,7 @@ mod tests {
     use tokio::net::TcpListener;
     use xr_proto::mux::mux_handshake_client;
     use xr_proto::obfuscation::{ModifierStrategy, Obfuscator};
-    use xr_proto::protocol::{decode_mux_payload, encode_mux_payload};
+    use xr_proto::protocol::{decode_datagram, decode_mux_payload, encode_datagram, encode_mux_payload};
 
     fn test_codec() -> Codec {
         let key = b"test-key-32-bytes-long-enough!!!".to_vec();
@@ -714,6 +816,58 @@ mod tests {
         panic!("кончившийся стрим не вернул permit: слот остался занят навсегда");
     }
 
+    /// UDP поверх mux: UdpAssociate получает ConnectAck, датаграмма уходит
+    /// адресату, ответ возвращается UdpData с адресом отправителя. Ассоциация
+    /// занимает permit стрима так же, как Connect.
+    #[tokio::test]
+    async fn datagram_association_relays_both_ways() {
+        let codec = test_codec();
+        let mut client =
+            start_mux_server_limited(codec.clone(), StreamLimits::new(1024, 1)).await;
+
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+            while let Ok((n, from)) = echo.recv_from(&mut buf).await {
+                let mut reply = b"re:".to_vec();
+                reply.extend_from_slice(&buf[..n]);
+                let _ = echo.send_to(&reply, from).await;
+            }
+        });
+
+        let mut reader = FrameReader::new();
+        let assoc = codec
+            .encode_frame(Command::UdpAssociate, &encode_mux_payload(1, &[]))
+            .unwrap();
+        client.write_all(&assoc).await.unwrap();
+        assert_eq!(
+            reader.answer_for(&mut client, &codec, 1).await.command,
+            Command::ConnectAck
+        );
+
+        let body = encode_datagram(&TargetAddr::Ip(echo_addr), b"ping").unwrap();
+        let wire = codec
+            .encode_frame(Command::UdpData, &encode_mux_payload(1, &body))
+            .unwrap();
+        client.write_all(&wire).await.unwrap();
+
+        let frame = reader.answer_for(&mut client, &codec, 1).await;
+        assert_eq!(frame.command, Command::UdpData);
+        let (_, body) = decode_mux_payload(&frame.payload).unwrap();
+        let (from, data) = decode_datagram(body).unwrap();
+        match from {
+            TargetAddr::Ip(a) => assert_eq!(a, echo_addr, "ответ обязан нести адрес отправителя"),
+            _ => panic!("адрес отправителя это IP"),
+        }
+        assert_eq!(data, b"re:ping");
+
+        // Кап сессии в один permit уже занят ассоциацией.
+        client.write_all(&connect_wire(&codec, 3, echo_addr)).await.unwrap();
+        let frame = reader.answer_for(&mut client, &codec, 3).await;
+        assert_eq!(frame.command, Command::Close, "ассоциация держит permit стрима");
+    }
+
     /// XR-094 (инцидент 2026-07-10): resolve-сбой (мёртвый DNS на VPS) уезжает
     /// клиенту причиной 1 в Close. Домен в зарезервированном TLD .invalid не
     /// резолвится нигде; если резолвер недоступен целиком, это тот же класс
@@ -0,0 +1,538 @@
+//! Горячая перезагрузка конфига xr-server: по SIGHUP или по правке файла.
+//!
+//! Живые mux-сессии рестарт убивал по всему флоту, поэтому здесь они не
+//! трогаются вовсе: коннект берёт снимок [`Settings`] при accept и живёт на
+//! нём до конца. Новые коннекты видят свежий снимок сразу, лимиты меняют
+//! ёмкость семафоров на месте, уровень логов переключается reload-слоем
+//! tracing. Чего без рестарта не применить (адрес листенера, порт UDP relay),
+//! называется в логе, чтобы правка не пропала молча.
+//!
+//! Окна ключей ротации тоже живут здесь: на границе окна снимок
+//! пересобирается с тем же конфигом, и новый ключ начинает приниматься, а
+//! истёкший перестаёт, без правки файла.
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use tokio::sync::Semaphore;
+use tokio::time::Duration;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+use xr_proto::config::{
+    decode_key, load_server_config, unix_now, ObfuscationConfig, RotationKey, ServerConfig,
+};
+use xr_proto::obfuscation::{ModifierStrategy, Obfuscator};
+use xr_proto::protocol::Codec;
+
+use crate::fallback;
+use crate::mux_handler::StreamLimits;
+
+/// Как часто сверять mtime конфига. Опрос, а не inotify: правка раз в
+/// недели, а лишняя зависимость и платформенные грабли не окупаются.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Хэндл фильтра логов, через который перезагрузка меняет уровень.
+pub type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Снимок применённого конфига, который берёт каждый новый коннект.
+pub struct Settings {
+    /// Принимаемые сейчас ключи, основной первым.
+    pub codecs: Vec<Codec>,
+    /// Ближайшая граница окон ключей (unix-секунды), на которой набор
+    /// `codecs` поменяется. `None` - границ впереди нет.
+    pub key_change: Option<u64>,
+    pub timeout: Duration,
+    pub fallback: Option<Vec<u8>>,
+    pub limits: StreamLimits,
+}
+
+impl Settings {
+    fn build(config: &ServerConfig, limits: StreamLimits, now: u64) -> Result<Self, Box<dyn Error>> {
+        let (codecs, key_change) = build_codecs(&config.obfuscation, now)?;
+        Ok(Self {
+            codecs,
+            key_change,
+            timeout: Duration::from_secs(config.limits.connection_timeout_sec),
+            fallback: config
+                .fallback
+                .enabled
+                .then(|| fallback::build_fallback_response(config.fallback.response_file.as_deref())),
+            limits,
+        })
+    }
+}
+
+/// Кодеки ключей, принимаемых в момент `now`: `key`, пока не наступил его
+/// `valid_until`, затем ключи `rotation` с открытым окном. Второе значение -
+/// ближайшая будущая граница окон. Ключи вне окна тоже разбираются: опечатка
+/// в будущем ключе должна ронять перезагрузку сейчас, а не всплыть в день
+/// переключения.
+pub fn build_codecs(
+    obfs: &ObfuscationConfig,
+    now: u64,
+) -> Result<(Vec<Codec>, Option<u64>), Box<dyn Error>> {
+    let primary = RotationKey {
+        key: obfs.key.clone(),
+        modifier: obfs.modifier.clone(),
+        salt: obfs.salt,
+        valid_from: None,
+        valid_until: obfs.valid_until,
+    };
+    let mut codecs = Vec::new();
+    let mut key_change: Option<u64> = None;
+    for entry in std::iter::once(&primary).chain(&obfs.rotation) {
+        let strategy =
+            ModifierStrategy::from_str(&entry.modifier).ok_or("unknown modifier strategy")?;
+        let obfuscator = Obfuscator::new(decode_key(&entry.key)?, entry.salt as u32, strategy);
+        if entry.is_valid_at(now) {
+            // Server doesn't need padding — it uses whatever the client sends
+            codecs.push(Codec::new(obfuscator, 16, 128));
+        }
+        for edge in [entry.valid_from, entry.valid_until].into_iter().flatten() {
+            if edge > now {
+                key_change = Some(key_change.map_or(edge, |t| t.min(edge)));
+            }
+        }
+    }
+    Ok((codecs, key_change))
+}
+
+/// Что перезагрузка применила и что ждёт рестарта.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub applied: Vec<&'static str>,
+    pub restart: Vec<&'static str>,
+}
+
+impl ReloadReport {
+    /// `log_reloadable` ложно, когда уровень задан `--log-level`: он
+    /// перекрывает конфиг и перезагрузкой не меняется.
+    fn diff(old: &ServerConfig, new: &ServerConfig, log_reloadable: bool) -> Self {
+        let mut report = Self::default();
+        if old.limits != new.limits {
+            report.applied.push("limits");
+        }
+        if old.fallback != new.fallback {
+            report.applied.push("fallback");
+        }
+        if old.obfuscation != new.obfuscation {
+            report.applied.push("obfuscation");
+            // UDP relay поднят со стартовым ключом и держит его до рестарта.
+            let udp_on = new.udp_relay.as_ref().is_some_and(|u| u.enabled);
+            let same_udp_key = old.obfuscation.key == new.obfuscation.key
+                && old.obfuscation.modifier == new.obfuscation.modifier
+                && old.obfuscation.salt == new.obfuscation.salt;
+            if udp_on && !same_udp_key {
+                report.restart.push("obfuscation (udp_relay)");
+            }
+        }
+        if old.logging.level != new.logging.level {
+            if log_reloadable {
+                report.applied.push("logging.level");
+            } else {
+                report.restart.push("logging.level (overridden by --log-level)");
+            }
+        }
+        if old.logging.file != new.logging.file {
+            report.restart.push("logging.file");
+        }
+        if old.server != new.server {
+            report.restart.push("server");
+        }
+        if old.udp_relay != new.udp_relay {
+            report.restart.push("udp_relay");
+        }
+        report
+    }
+}
+
+/// Держатель живого конфига сервера.
+pub struct Reloader {
+    path: PathBuf,
+    log: Option<LogHandle>,
+    connections: Arc<Semaphore>,
+    /// Недостачи уменьшений `max_connections` и `max_streams`.
+    connections_shrink: Arc<Mutex<Shrink>>,
+    streams_shrink: Arc<Mutex<Shrink>>,
+    applied: Mutex<ServerConfig>,
+    settings: RwLock<Arc<Settings>>,
+}
+
+impl Reloader {
+    pub fn new(
+        path: PathBuf,
+        config: ServerConfig,
+        log: Option<LogHandle>,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Кап стримов внутри mux-сессий (XR-199): семафор коннектов их не видит.
+        let limits = StreamLimits::new(
+            config.limits.max_streams as usize,
+            config.limits.max_streams_per_mux as usize,
+        );
+        let settings = Settings::build(&config, limits, unix_now())?;
+        ensure_keys(&settings)?;
+        Ok(Self {
+            path,
+            log,
+            connections: Arc::new(Semaphore::new(config.limits.max_connections as usize)),
+            connections_shrink: Default::default(),
+            streams_shrink: Default::default(),
+            applied: Mutex::new(config),
+            settings: RwLock::new(Arc::new(settings)),
+        })
+    }
+
+    /// Текущий снимок для нового коннекта.
+    pub fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Лимит одновременных коннектов (`max_connections`).
+    pub fn connections(&self) -> &Arc<Semaphore> {
+        &self.connections
+    }
+
+    /// Перечитать файл и применить. Битый конфиг (в том числе недописанный
+    /// на момент опроса) не применяется ни частично: ошибка, и работает
+    /// прежний.
+    pub fn reload(&self) -> Result<ReloadReport, Box<dyn Error>> {
+        let new = load_server_config(&self.path)?;
+        let filter = EnvFilter::try_new(&new.logging.level)?;
+        let mut applied = self.applied.lock().unwrap();
+        let current = self.settings();
+        let limits = StreamLimits {
+            per_mux: new.limits.max_streams_per_mux as usize,
+            ..current.limits.clone()
+        };
+        let settings = Settings::build(&new, limits, unix_now())?;
+        ensure_keys(&settings)?;
+
+        let report = ReloadReport::diff(&applied, &new, self.log.is_some());
+        resize(
+            &self.connections,
+            &self.connections_shrink,
+            applied.limits.max_connections,
+            new.limits.max_connections,
+        );
+        resize(
+            &settings.limits.total,
+            &self.streams_shrink,
+            applied.limits.max_streams,
+            new.limits.max_streams,
+        );
+        if let Some(log) = &self.log {
+            if applied.logging.level != new.logging.level {
+                log.reload(filter)?;
+            }
+        }
+        *self.settings.write().unwrap() = Arc::new(settings);
+        *applied = new;
+        Ok(report)
+    }
+
+    fn reload_logged(&self, trigger: &str) {
+        match self.reload() {
+            Ok(report) => {
+                if report.applied.is_empty() && report.restart.is_empty() {
+                    tracing::info!("Config reloaded ({}): no changes", trigger);
+                }
+                if !report.applied.is_empty() {
+                    tracing::info!(
+                        "Config reloaded ({}): applied {}",
+                        trigger,
+                        report.applied.join(", ")
+                    );
+                }
+                if !report.restart.is_empty() {
+                    tracing::warn!(
+                        "Config reloaded ({}): restart required for {}",
+                        trigger,
+                        report.restart.join(", ")
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Config reload ({}) failed, keeping previous: {}", trigger, e),
+        }
+    }
+
+    /// Пересобрать снимок на границе окна ключей. Конфиг тот же, меняется
+    /// только набор принимаемых ключей; живые коннекты остаются на своих.
+    fn roll_keys(&self, now: u64) {
+        let applied = self.applied.lock().unwrap();
+        let limits = self.settings().limits.clone();
+        match Settings::build(&applied, limits, now) {
+            Ok(settings) => {
+                if settings.codecs.is_empty() {
+                    tracing::error!(
+                        "Obfuscation key windows closed: no key is accepted, \
+                         every new connection gets the fallback"
+                    );
+                } else {
+                    tracing::info!(
+                        "Obfuscation key window changed: {} key(s) accepted",
+                        settings.codecs.len()
+                    );
+                }
+                *self.settings.write().unwrap() = Arc::new(settings);
+            }
+            // Файл при этом не перечитывался, и то, что собралось на
+            // перезагрузке, обязано собраться снова.
+            Err(e) => tracing::error!("Obfuscation key rollover failed: {}", e),
+        }
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Ждёт SIGHUP и правок файла, пока жив процесс.
+    pub async fn run(self: Arc<Self>) {
+        let mut sighup =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    tracing::warn!("SIGHUP handler unavailable ({}), file watch only", e);
+                    None
+                }
+            };
+        let mut seen = self.mtime();
+        let mut poll = tokio::time::interval(CONFIG_POLL_INTERVAL);
+        poll.tick().await;
+        loop {
+            tokio::select! {
+                Some(()) = async { sighup.as_mut()?.recv().await } => {
+                    seen = self.mtime();
+                    self.reload_logged("SIGHUP");
+                }
+                _ = poll.tick() => {
+                    let mtime = self.mtime();
+                    if mtime.is_some() && mtime != seen {
+                        seen = mtime;
+                        self.reload_logged("file changed");
+                    }
+                    let now = unix_now();
+                    if self.settings().key_change.is_some_and(|t| now >= t) {
+                        self.roll_keys(now);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Конфиг, в котором ни один ключ не принимается прямо сейчас, это
+/// гарантированный отказ всем клиентам; такой не применяется.
+fn ensure_keys(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    if settings.codecs.is_empty() {
+        return Err("no obfuscation key is valid now (check valid_until / valid_from)".into());
+    }
+    Ok(())
+}
+
+/// Недостача уменьшенного семафора: сколько permits ещё забрать, когда их
+/// отпустят живые коннекты. Одна на семафор, чтобы рост мог её погасить.
+#[derive(Default)]
+struct Shrink {
+    owed: usize,
+    /// Фоновый сборщик уже ждёт permits.
+    collecting: bool,
+}
+
+/// Поменять ёмкость семафора на месте. Рост сначала гасит недостачу прошлого
+/// уменьшения и только остаток добавляет новыми permits: иначе их проглотил
+/// бы сборщик недостачи. Уменьшение забирает свободные сразу, а недостачу
+/// (permits держат живые коннекты) выбирает фоновый сборщик по мере их
+/// освобождения: до тех пор новые коннекты получают отказ, живые не рвутся.
+fn resize(sem: &Arc<Semaphore>, shrink: &Arc<Mutex<Shrink>>, old: u32, new: u32) {
+    let mut debt = shrink.lock().unwrap();
+    if new > old {
+        let grow = (new - old) as usize;
+        let cancel = grow.min(debt.owed);
+        debt.owed -= cancel;
+        sem.add_permits(grow - cancel);
+    } else if new < old {
+        let want = (old - new) as usize;
+        debt.owed += want - sem.forget_permits(want);
+        if debt.owed > 0 && !debt.collecting {
+            debt.collecting = true;
+            tokio::spawn(collect(sem.clone(), shrink.clone()));
+        }
+    }
+}
+
+/// Забирать освободившиеся permits по одному, пока недостача не погашена.
+/// Permit, пришедший после того, как рост её списал, возвращается.
+async fn collect(sem: Arc<Semaphore>, shrink: Arc<Mutex<Shrink>>) {
+    while let Ok(permit) = sem.clone().acquire_owned().await {
+        let mut debt = shrink.lock().unwrap();
+        if debt.owed > 0 {
+            debt.owed -= 1;
+            permit.forget();
+        }
+        if debt.owed == 0 {
+            debt.collecting = false;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: &str = "aGFuZGxlci10ZXN0LWtleS0wMTIzNDU2Nzg5QUJDRA==";
+    const KEY_B: &str = "c2Vjb25kLXJvdGF0aW9uLWtleS0wMTIzNDU2Nzg5QUI=";
+
+    fn config_text(port: u16, max_connections: u32, rotation: &str) -> String {
+        format!(
+            r#"
+[server]
+port = {port}
+
+[obfuscation]
+key = "{KEY_A}"
+{rotation}
+
+[limits]
+max_connections = {max_connections}
+max_streams = 8
+"#
+        )
+    }
+
+    fn reloader(dir: &tempfile::TempDir, text: &str) -> Reloader {
+        let path = dir.path().join("server.toml");
+        std::fs::write(&path, text).unwrap();
+        let config = load_server_config(&path).unwrap();
+        Reloader::new(path, config, None).unwrap()
+    }
+
+    /// Ключ и лимиты применяются к новым коннектам сразу, порт называется как
+    /// требующий рестарта, а уровень логов под `--log-level` не трогается.
+    #[tokio::test]
+    async fn reload_applies_limits_and_keys_reports_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let reloader = reloader(&dir, &config_text(8443, 4, ""));
+        assert_eq!(reloader.settings().codecs.len(), 1);
+        assert_eq!(reloader.connections().available_permits(), 4);
+
+        let text = config_text(9443, 6, &format!("[[obfuscation.rotation]]\nkey = \"{KEY_B}\""))
+            .replace("[limits]", "[logging]\nlevel = \"debug\"\n\n[limits]");
+        std::fs::write(dir.path().join("server.toml"), text).unwrap();
+        let report = reloader.reload().unwrap();
+
+        assert_eq!(report.applied, vec!["limits", "obfuscation"]);
+        assert_eq!(
+            report.restart,
+            vec!["logging.level (overridden by --log-level)", "server"]
+        );
+        assert_eq!(reloader.settings().codecs.len(), 2);
+        assert_eq!(reloader.connections().available_permits(), 6);
+    }
+
+    /// Битый файл не применяется ни частично: остаётся прежний снимок.
+    #[tokio::test]
+    async fn broken_config_keeps_previous() {
+        let dir = tempfile::tempdir().unwrap();
+        let reloader = reloader(&dir, &config_text(8443, 4, ""));
+        std::fs::write(
+            dir.path().join("server.toml"),
+            config_text(8443, 16, "[[obfuscation.rotation]]\nkey = \"not base64!\""),
+        )
+        .unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(reloader.connections().available_permits(), 4);
+        assert_eq!(reloader.settings().codecs.len(), 1);
+    }
+
+    /// Окна ключей: новый ключ принимается с `valid_from`, старый перестаёт
+    /// с `valid_until`, и каждая граница впереди называется как точка
+    /// пересборки снимка.
+    #[test]
+    fn key_windows_follow_the_clock() {
+        let mut obfs = ObfuscationConfig {
+            key: KEY_A.into(),
+            modifier: "positional_xor_rotate".into(),
+            salt: 1,
+            padding_min: 16,
+            padding_max: 128,
+            valid_until: Some(200),
+            rotation: vec![RotationKey {
+                key: KEY_B.into(),
+                modifier: "positional_xor_rotate".into(),
+                salt: 7,
+                valid_from: Some(100),
+                valid_until: None,
+            }],
+        };
+
+        let (codecs, change) = build_codecs(&obfs, 50).unwrap();
+        assert_eq!((codecs.len(), change), (1, Some(100)));
+        let (codecs, change) = build_codecs(&obfs, 100).unwrap();
+        assert_eq!((codecs.len(), change), (2, Some(200)));
+        let (codecs, change) = build_codecs(&obfs, 200).unwrap();
+        assert_eq!((codecs.len(), change), (1, None));
+
+        // Битый ключ за пределами окна ломает сборку сразу, а не в день
+        // переключения.
+        obfs.rotation[0].key = "not base64!".into();
+        assert!(build_codecs(&obfs, 50).is_err());
+    }
+
+    /// Конфиг, где все окна закрыты, не применяется: это отказ всему флоту.
+    #[tokio::test]
+    async fn expired_keys_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let reloader = reloader(&dir, &config_text(8443, 4, ""));
+        let text = config_text(8443, 4, "").replace(
+            &format!("key = \"{KEY_A}\""),
+            &format!("key = \"{KEY_A}\"\nvalid_until = 1"),
+        );
+        std::fs::write(dir.path().join("server.toml"), text).unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(reloader.settings().codecs.len(), 1);
+    }
+
+    /// Уменьшение капа не рвёт держателей permits: недостача выбирается по
+    /// мере их освобождения, и итоговая ёмкость равна новому капу.
+    #[tokio::test]
+    async fn shrink_waits_for_held_permits() {
+        let sem = Arc::new(Semaphore::new(4));
+        let shrink = Arc::default();
+        let held = sem.clone().acquire_many_owned(3).await.unwrap();
+        resize(&sem, &shrink, 4, 2);
+        assert_eq!(sem.available_permits(), 0);
+        drop(held);
+        tokio::task::yield_now().await;
+        assert_eq!(sem.available_permits(), 2);
+
+        resize(&sem, &shrink, 2, 5);
+        assert_eq!(sem.available_permits(), 5);
+    }
+
+    /// Рост, пришедший раньше, чем недостача уменьшения выбрана, гасит её, а
+    /// не отдаёт свои permits сборщику: итог равен последнему капу.
+    #[tokio::test]
+    async fn grow_cancels_pending_shrink() {
+        let sem = Arc::new(Semaphore::new(4));
+        let shrink = Arc::default();
+        let held = sem.clone().acquire_many_owned(4).await.unwrap();
+        resize(&sem, &shrink, 4, 2);
+        tokio::task::yield_now().await;
+        resize(&sem, &shrink, 2, 5);
+        tokio::task::yield_now().await;
+        assert_eq!(sem.available_permits(), 1, "новый permit не съеден сборщиком");
+        drop(held);
+        tokio::task::yield_now().await;
+        assert_eq!(sem.available_permits(), 5);
+        assert!(!shrink.lock().unwrap().collecting);
+
+        // Уменьшение после этого снова собирает недостачу.
+        let held = sem.clone().acquire_many_owned(5).await.unwrap();
+        resize(&sem, &shrink, 5, 3);
+        drop(held);
+        tokio::task::yield_now().await;
+        assert_eq!(sem.available_permits(), 3);
+    }
+}
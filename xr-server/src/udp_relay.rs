@@ -12,6 +12,8 @@ use tokio::time::Duration;
 use xr_proto::obfuscation::Obfuscator;
 use xr_proto::udp_relay::{self, RelayPacket, RelayType};
 
+use crate::reload::Settings;
+
 // -- Flow table -------------------------------------------------------
 
 /// Сколько пакетов держит очередь одного потока. Запас нужен на время bind:
@@ -25,6 +27,8 @@ const FLOW_QUEUE: usize = 64;
 struct FlowPacket {
     dst: SocketAddr,
     payload: Vec<u8>,
+    /// Ключ, на котором пакет пришёл: на нём же уходят ответы.
+    obfs: Arc<Obfuscator>,
 }
 
 /// Поток одного src_port со стороны таблицы: только отправной конец очереди.
@@ -40,10 +44,14 @@ struct Flow {
 /// бы поток, а ответ из интернета уходил бы кому попало.
 type FlowKey = (SocketAddr, u16);
 
+/// Откуда relay берёт принимаемые сейчас ключи: тот же снимок, что у
+/// mux-коннектов, с теми же окнами ротации.
+pub type KeySource = Box<dyn Fn() -> Arc<Settings> + Send + Sync>;
+
 struct ServerState {
     /// (пир, src_port) -> очередь его потока
     flows: Mutex<HashMap<FlowKey, Flow>>,
-    obfuscator: Obfuscator,
+    keys: KeySource,
     flow_timeout: Duration,
     #[allow(dead_code)]
     incoming_port_min: u16,
@@ -55,7 +63,7 @@ struct ServerState {
 
 pub async fn run_udp_relay_server(
     listen_port: u16,
-    obfuscator: Obfuscator,
+    keys: KeySource,
     flow_timeout_sec: u64,
     incoming_port_min: u16,
     incoming_port_max: u16,
@@ -66,7 +74,7 @@ pub async fn run_udp_relay_server(
 
     let state = Arc::new(ServerState {
         flows: Mutex::new(HashMap::new()),
-        obfuscator,
+        keys,
         flow_timeout: Duration::from_secs(flow_timeout_sec),
         incoming_port_min,
         incoming_port_max,
@@ -86,7 +94,8 @@ pub async fn run_udp_relay_server(
 
 /// Разобрать датаграмму с relay-порта и раздать её по назначению. Ключ
 /// обфускации на VPS общий, поэтому мусор и чужие пакеты тут дело обычное:
-/// нерасшифровавшееся уходит в лог и дальше не идёт.
+/// нерасшифровавшееся уходит в лог и дальше не идёт. Ключи пробуются по
+/// порядку снимка, основной первым; истёкший в снимок уже не попадает.
 async fn handle_datagram<F, Fut>(
     state: &Arc<ServerState>,
     relay_socket: &Arc<UdpSocket>,
@@ -97,8 +106,13 @@ async fn handle_datagram<F, Fut>(
     F: FnOnce(u16) -> Fut + Send + 'static,
     Fut: Future<Output = io::Result<UdpSocket>> + Send,
 {
-    let packet = match udp_relay::decode_relay_packet(&state.obfuscator, data) {
-        Some(p) => p,
+    let settings = (state.keys)();
+    let decoded = settings.codecs.iter().find_map(|codec| {
+        let obfs = codec.obfuscator();
+        udp_relay::decode_relay_packet(obfs, data).map(|p| (obfs, p))
+    });
+    let (obfs, packet) = match decoded {
+        Some(d) => d,
         None => {
             tracing::debug!("UDP relay server: invalid packet from {}", peer);
             return;
@@ -108,11 +122,11 @@ async fn handle_datagram<F, Fut>(
     match packet.relay_type {
         RelayType::Keepalive => {
             // Keepalive отвечает написавшему: он и держит NAT роутера открытым.
-            let reply = udp_relay::encode_keepalive(&state.obfuscator);
+            let reply = udp_relay::encode_keepalive(obfs);
             let _ = relay_socket.send_to(&reply, peer).await;
         }
         RelayType::Data => {
-            handle_data_packet(state, relay_socket, peer, packet, bind).await;
+            handle_data_packet(state, relay_socket, peer, obfs, packet, bind).await;
         }
         _ => {}
     }
@@ -128,6 +142,7 @@ async fn handle_data_packet<F, Fut>(
     state: &Arc<ServerState>,
     relay_socket: &Arc<UdpSocket>,
     peer: SocketAddr,
+    obfs: &Obfuscator,
     packet: RelayPacket,
     bind: F,
 ) where
@@ -139,6 +154,7 @@ async fn handle_data_packet<F, Fut>(
     let queued = FlowPacket {
         dst: packet.dst,
         payload: packet.payload,
+        obfs: Arc::new(obfs.clone()),
     };
 
     let mut flows = state.flows.lock().await;
@@ -176,9 +192,10 @@ enum FlowEvent {
 
 /// Таск одного потока: поднимает сокет на src_port и дальше сам гоняет обе
 /// стороны, пока поток жив. Наружу отправляет пакеты из очереди по порядку,
-/// обратно заворачивает ответы тому пиру, который поток завёл. Слот в таблице
-/// снимает он же, так что осиротеть сокету не с чего: сокет умирает вместе с
-/// таском.
+/// обратно заворачивает ответы тому пиру, который поток завёл, на ключе его
+/// последнего пакета: роутер, переехавший на ключ ротации, ждёт ответов на
+/// нём. Слот в таблице снимает он же, так что осиротеть сокету не с чего:
+/// сокет умирает вместе с таском.
 async fn run_flow<F, Fut>(
     state: Arc<ServerState>,
     relay_socket: Arc<UdpSocket>,
@@ -203,6 +220,9 @@ async fn run_flow<F, Fut>(
     tracing::info!("UDP relay: bound source port {} for {}", src_port, peer);
 
     let mut buf = vec![0u8; 65536];
+    // Первым событием всегда идёт пакет из очереди: до него сокет потока никому
+    // не известен.
+    let mut obfs: Option<Arc<Obfuscator>> = None;
     loop {
         let tick = tokio::time::timeout(state.flow_timeout, async {
             tokio::select! {
@@ -240,9 +260,11 @@ async fn run_flow<F, Fut>(
                 if let Err(e) = socket.send_to(&queued.payload, queued.dst).await {
                     tracing::warn!("UDP relay: send to {} failed: {}", queued.dst, e);
                 }
+                obfs = Some(queued.obfs);
             }
             FlowEvent::Outbound(None) => return,
             FlowEvent::Inbound(Ok((n, from_addr))) => {
+                let Some(obfs) = &obfs else { continue };
                 if n == 0 {
                     continue;
                 }
@@ -255,7 +277,7 @@ async fn run_flow<F, Fut>(
                     src_port,
                     payload: buf[..n].to_vec(),
                 };
-                let wire = udp_relay::encode_relay_packet(&state.obfuscator, &response);
+                let wire = udp_relay::encode_relay_packet(obfs, &response);
                 if let Err(e) = relay_socket.send_to(&wire, peer).await {
                     tracing::warn!("UDP relay: send response to router failed: {}", e);
                 }
@@ -299,15 +321,38 @@ mod tests {
     use tokio::sync::oneshot;
     use tokio::time::timeout;
     use xr_proto::obfuscation::{ModifierStrategy, Obfuscator};
+    use xr_proto::protocol::Codec;
+
+    use crate::mux_handler::StreamLimits;
 
     /// Запас на ожидание в тестах: успешный путь до него не доходит, а на
     /// сломанном коде тест обязан упасть, а не повиснуть.
     const WAIT: Duration = Duration::from_secs(5);
 
+    fn test_key() -> Obfuscator {
+        Obfuscator::new(b"test-key".to_vec(), 7, ModifierStrategy::PositionalXorRotate)
+    }
+
+    /// Снимок с ключами `keys`, основной первым; остальное relay не смотрит.
+    fn settings_with(keys: &[Obfuscator]) -> Arc<Settings> {
+        Arc::new(Settings {
+            codecs: keys.iter().map(|k| Codec::new(k.clone(), 16, 128)).collect(),
+            key_change: None,
+            timeout: Duration::from_secs(30),
+            fallback: None,
+            limits: StreamLimits::new(16, 16),
+        })
+    }
+
     fn test_state(flow_timeout: Duration) -> Arc<ServerState> {
+        let settings = settings_with(&[test_key()]);
+        test_state_with_keys(flow_timeout, Box::new(move || settings.clone()))
+    }
+
+    fn test_state_with_keys(flow_timeout: Duration, keys: KeySource) -> Arc<ServerState> {
         Arc::new(ServerState {
             flows: Mutex::new(HashMap::new()),
-            obfuscator: Obfuscator::new(b"test-key".to_vec(), 7, ModifierStrategy::PositionalXorRotate),
+            keys,
             flow_timeout,
             incoming_port_min: 0,
             incoming_port_max: 0,
@@ -374,6 +419,7 @@ mod tests {
                 &state,
                 &relay,
                 any_peer(),
+                &test_key(),
                 data_packet(41001, dst, b"first"),
                 slow_bind,
             ),
@@ -393,6 +439,7 @@ mod tests {
                 &state,
                 &relay,
                 any_peer(),
+                &test_key(),
                 data_packet(41002, dst, b"second"),
                 fast_bind,
             ),
@@ -426,6 +473,7 @@ mod tests {
             &state,
             &relay,
             any_peer(),
+            &test_key(),
             data_packet(41003, dst, b"1"),
             slow_bind,
         )
@@ -435,6 +483,7 @@ mod tests {
                 &state,
                 &relay,
                 any_peer(),
+                &test_key(),
                 data_packet(41003, dst, payload),
                 |_port| async { unreachable!("поток уже поднят, второй bind ему не нужен") },
             )
@@ -474,6 +523,7 @@ mod tests {
             &state,
             &relay,
             any_peer(),
+            &test_key(),
             data_packet(src_port, dst, b"first"),
             failing_bind,
         )
@@ -494,6 +544,7 @@ mod tests {
             &state,
             &relay,
             any_peer(),
+            &test_key(),
             data_packet(src_port, dst, b"second"),
             retry_bind,
         )
@@ -522,6 +573,7 @@ mod tests {
             &state,
             &relay,
             router_addr,
+            &test_key(),
             data_packet(src_port, peer_addr, b"ping"),
             |_port| bind_ephemeral(),
         )
@@ -540,7 +592,7 @@ mod tests {
             .await
             .expect("ответ обязан вернуться роутеру")
             .unwrap();
-        let response = udp_relay::decode_relay_packet(&state.obfuscator, &wire[..n]).unwrap();
+        let response = udp_relay::decode_relay_packet(&test_key(), &wire[..n]).unwrap();
         assert_eq!(response.src_port, src_port);
         assert_eq!(response.dst, peer_addr);
         assert_eq!(response.payload, b"pong".to_vec());
@@ -568,6 +620,7 @@ mod tests {
             &state,
             &relay,
             owner_addr,
+            &test_key(),
             data_packet(src_port, peer_addr, b"ping"),
             |_port| bind_ephemeral(),
         )
@@ -585,6 +638,7 @@ mod tests {
             &state,
             &relay,
             hijacker_addr,
+            &test_key(),
             data_packet(src_port, peer_addr, b"hijack"),
             |_port| bind_ephemeral(),
         )
@@ -603,7 +657,7 @@ mod tests {
             .await
             .expect("ответ обязан уйти тому, кто завёл поток")
             .unwrap();
-        let response = udp_relay::decode_relay_packet(&state.obfuscator, &wire[..n]).unwrap();
+        let response = udp_relay::decode_relay_packet(&test_key(), &wire[..n]).unwrap();
         assert_eq!(response.payload, b"pong".to_vec());
         assert!(
             hijacker.try_recv_from(&mut wire).is_err(),
@@ -631,6 +685,7 @@ mod tests {
                 &state,
                 &relay,
                 sender,
+                &test_key(),
                 data_packet(src_port, peer_addr, b"ping"),
                 move |_port| async move {
                     calls.fetch_add(1, Ordering::SeqCst);
@@ -685,6 +740,7 @@ mod tests {
             &state,
             &relay,
             any_peer(),
+            &test_key(),
             data_packet(src_port, dst, b"first"),
             bind,
         )
@@ -699,6 +755,7 @@ mod tests {
         tx.try_send(FlowPacket {
             dst,
             payload: b"second".to_vec(),
+            obfs: Arc::new(test_key()),
         })
         .unwrap();
         drop(flows);
@@ -729,7 +786,7 @@ mod tests {
             &state,
             &relay,
             router.local_addr().unwrap(),
-            &udp_relay::encode_keepalive(&state.obfuscator),
+            &udp_relay::encode_keepalive(&test_key()),
             |_port| async { unreachable!("keepalive не заводит поток") },
         )
         .await;
@@ -739,11 +796,62 @@ mod tests {
             .await
             .expect("на keepalive обязан прийти ответ")
             .unwrap();
-        let reply = udp_relay::decode_relay_packet(&state.obfuscator, &wire[..n]).unwrap();
+        let reply = udp_relay::decode_relay_packet(&test_key(), &wire[..n]).unwrap();
         assert_eq!(reply.relay_type, RelayType::Keepalive);
         assert!(state.flows.lock().await.is_empty());
     }
 
+    /// Relay принимает ключи по окнам снимка, как mux: пока открыты оба, поток
+    /// на ключе ротации работает и ответы получает на нём, а после
+    /// `valid_until` основного пакеты на старом ключе уже не расшифровываются.
+    #[tokio::test]
+    async fn relay_keys_follow_rotation_windows() {
+        let old = test_key();
+        let new = Obfuscator::new(b"next-key".to_vec(), 9, ModifierStrategy::PositionalXorRotate);
+        let current = Arc::new(std::sync::Mutex::new(settings_with(&[old.clone(), new.clone()])));
+        let source = current.clone();
+        let state = test_state_with_keys(
+            Duration::from_secs(3600),
+            Box::new(move || source.lock().unwrap().clone()),
+        );
+        let relay = local_socket().await;
+        let router = local_socket().await;
+        let router_addr = router.local_addr().unwrap();
+        let peer = local_socket().await;
+        let peer_addr = peer.local_addr().unwrap();
+
+        let wire = udp_relay::encode_relay_packet(&new, &data_packet(41020, peer_addr, b"ping"));
+        handle_datagram(&state, &relay, router_addr, &wire, |_port| bind_ephemeral()).await;
+        let mut buf = [0u8; 256];
+        let (n, from) = timeout(WAIT, peer.recv_from(&mut buf))
+            .await
+            .expect("пакет на ключе ротации обязан дойти")
+            .unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        peer.send_to(b"pong", from).await.unwrap();
+        let (n, _) = timeout(WAIT, router.recv_from(&mut buf))
+            .await
+            .expect("ответ обязан вернуться роутеру")
+            .unwrap();
+        let response = udp_relay::decode_relay_packet(&new, &buf[..n]).unwrap();
+        assert_eq!(response.payload, b"pong".to_vec());
+
+        // Окно основного закрылось. Ключи без проверки целостности, поэтому
+        // берём датаграмму, которую новый ключ заведомо не разберёт.
+        *current.lock().unwrap() = settings_with(std::slice::from_ref(&new));
+        let stale = loop {
+            let wire = udp_relay::encode_relay_packet(&old, &data_packet(41021, peer_addr, b"late"));
+            if udp_relay::decode_relay_packet(&new, &wire).is_none() {
+                break wire;
+            }
+        };
+        handle_datagram(&state, &relay, router_addr, &stale, |_port| async {
+            unreachable!("истёкший ключ не заводит поток")
+        })
+        .await;
+        assert!(!state.flows.lock().await.contains_key(&(router_addr, 41021)));
+    }
+
     /// Нерасшифровавшаяся датаграмма не заводит поток и не получает ответа: на
     /// открытый relay-порт пишут и сканеры, а поток это занятый порт на VPS.
     #[tokio::test]
@@ -800,6 +908,7 @@ mod tests {
             &state,
             &relay,
             any_peer(),
+            &test_key(),
             data_packet(src_port, dst, &0u32.to_be_bytes()),
             slow_bind,
         )
@@ -809,6 +918,7 @@ mod tests {
                 &state,
                 &relay,
                 any_peer(),
+                &test_key(),
                 data_packet(src_port, dst, &i.to_be_bytes()),
                 |_port| async { unreachable!("поток уже поднят") },
             )
@@ -927,6 +1037,7 @@ mod tests {
             &state,
             &relay,
             any_peer(),
+            &test_key(),
             data_packet(src_port, dst, b"first"),
             bind,
         )
@@ -0,0 +1,560 @@
+//! Папка шары одним архивом: `GET /{share_id}/archive?path=<папка>&format=zip|tar`.
+//! Браузер и простой клиент забирают каталог одной ссылкой, а не пофайлово.
+//!
+//! Архив собирается на лету, без временных файлов: обход папки тем же
+//! [`walk_share`], что и у манифеста (без ссылок и без `.xr-`), идёт в
+//! блокирующем таске и пишет в канал, из которого течёт тело ответа. Медленный
+//! клиент тормозит обход через ограниченный канал, оборванный останавливает
+//! его. Сбой посреди архива (файл исчез или укоротился) обрывает ответ, а не
+//! отдаёт целым битый архив.
+//!
+//! Всё лежит под одним каталогом с именем папки, а последним в корне архива
+//! идёт `SHA256SUMS` в формате `sha256sum -c`: хеши считаются по тем байтам,
+//! что ушли в архив. ZIP без сжатия (фото и видео не жмутся, а CRC и размеры
+//! считаются попутно в дескрипторах данных), с zip64 там, где нужен.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper::body::{Bytes, Frame};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use crate::manifest::{walk_share, HashCache};
+
+/// Имя файла с хешами в корне архива.
+pub(crate) const SUMS_NAME: &str = "SHA256SUMS";
+
+/// Порция, которой архив уходит в канал.
+const CHUNK: usize = 64 * 1024;
+
+/// Сколько порций может ждать клиента: дальше обход стоит.
+const CHANNEL_DEPTH: usize = 8;
+
+/// Формат архива.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Zip,
+    Tar,
+}
+
+impl Format {
+    /// `format=` из запроса; без него ZIP, который открывает любой браузер и ОС.
+    pub(crate) fn parse(s: Option<&str>) -> Option<Format> {
+        match s.unwrap_or("zip") {
+            "zip" => Some(Format::Zip),
+            "tar" => Some(Format::Tar),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::Tar => "tar",
+        }
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Format::Zip => "application/zip",
+            Format::Tar => "application/x-tar",
+        }
+    }
+}
+
+/// Тело ответа: порции архива из канала от блокирующего таска.
+pub(crate) struct ArchiveBody {
+    rx: mpsc::Receiver<io::Result<Bytes>>,
+}
+
+impl hyper::body::Body for ArchiveBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        self.rx.poll_recv(cx).map(|next| next.map(|r| r.map(Frame::data)))
+    }
+}
+
+/// Начать отдачу папки `dir` архивом под каталогом `top`. Посчитанные по
+/// дороге хеши попутно оседают в кеше хешей.
+pub(crate) fn stream(dir: PathBuf, top: String, format: Format, cache: Arc<HashCache>) -> ArchiveBody {
+    let (tx, rx) = mpsc::channel(CHANNEL_DEPTH);
+    tokio::task::spawn_blocking(move || {
+        let out = ChannelWriter { tx: tx.clone(), buf: Vec::with_capacity(CHUNK) };
+        if let Err(e) = write_archive(&dir, &top, format, &cache, out) {
+            // Клиент ушёл сам: сообщать некому.
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                tracing::warn!("архив {}: {e}", dir.display());
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+    ArchiveBody { rx }
+}
+
+/// Записать архив целиком в `out`. Отдельно от канала, чтобы тесты читали
+/// архив из памяти.
+pub(crate) fn write_archive(dir: &Path, top: &str, format: Format, cache: &HashCache, out: impl Write) -> io::Result<()> {
+    let mut sink: Box<dyn Sink + '_> = match format {
+        Format::Zip => Box::new(ZipSink::new(out)),
+        Format::Tar => Box::new(TarSink::new(out)),
+    };
+    let mut sums = String::new();
+    for entry in walk_share(dir) {
+        let entry = entry.map_err(io::Error::other)?;
+        let rel = entry.path().strip_prefix(dir).map_err(io::Error::other)?;
+        let mut name = top.to_string();
+        for part in rel.components() {
+            name.push('/');
+            name.push_str(&part.as_os_str().to_string_lossy());
+        }
+        let md = entry.metadata().map_err(io::Error::other)?;
+        let mtime = crate::manifest::mtime_secs(&md);
+        if md.is_dir() {
+            sink.dir(&format!("{name}/"), mtime)?;
+        } else if md.is_file() {
+            let file = std::fs::File::open(entry.path())?;
+            let mut reader = Hashing { inner: file.take(md.len()), hasher: Sha256::new(), read: 0 };
+            sink.file(&name, mtime, md.len(), &mut reader)?;
+            if reader.read != md.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{name} укоротился во время отдачи"),
+                ));
+            }
+            let sha = hex(&reader.hasher.finalize());
+            // Файл, тронутый во время чтения, в кеш не идёт: хеш мог смешать версии.
+            let after = std::fs::metadata(entry.path())?;
+            if after.len() == md.len() && crate::manifest::mtime_secs(&after) == mtime {
+                cache.seed(entry.path(), md.len(), mtime, sha.clone());
+            }
+            sums.push_str(&format!("{sha}  {name}\n"));
+        }
+    }
+    let now = crate::server::now_unix() as i64;
+    sink.file(SUMS_NAME, now, sums.len() as u64, &mut sums.as_bytes())?;
+    sink.finish()
+}
+
+/// Один формат архива: каталог, файл известного размера и концовка.
+trait Sink {
+    fn dir(&mut self, name: &str, mtime: i64) -> io::Result<()>;
+    fn file(&mut self, name: &str, mtime: i64, size: u64, data: &mut dyn Read) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+struct TarSink<W: Write> {
+    builder: tar::Builder<W>,
+}
+
+impl<W: Write> TarSink<W> {
+    fn new(out: W) -> Self {
+        let mut builder = tar::Builder::new(out);
+        builder.mode(tar::HeaderMode::Deterministic);
+        Self { builder }
+    }
+
+    fn header(kind: tar::EntryType, mode: u32, size: u64, mtime: i64) -> tar::Header {
+        let mut h = tar::Header::new_gnu();
+        h.set_entry_type(kind);
+        h.set_mode(mode);
+        h.set_size(size);
+        h.set_mtime(mtime.max(0) as u64);
+        h
+    }
+}
+
+impl<W: Write> Sink for TarSink<W> {
+    fn dir(&mut self, name: &str, mtime: i64) -> io::Result<()> {
+        let mut h = Self::header(tar::EntryType::Directory, 0o755, 0, mtime);
+        self.builder.append_data(&mut h, name, io::empty())
+    }
+
+    fn file(&mut self, name: &str, mtime: i64, size: u64, data: &mut dyn Read) -> io::Result<()> {
+        // Длинные имена tar пишет расширением GNU, а паддинг считает по
+        // прочитанному: короткое чтение ловит вызывающий.
+        let mut h = Self::header(tar::EntryType::Regular, 0o644, size, mtime);
+        self.builder.append_data(&mut h, name, data)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.builder.into_inner()?.flush()
+    }
+}
+
+/// ZIP без сжатия. CRC и размеры пишутся в дескрипторе после данных; где
+/// размер или смещение не влезают в 32 бита, поля уходят в zip64.
+struct ZipSink<W: Write> {
+    out: Counting<W>,
+    central: Vec<ZipEntry>,
+}
+
+struct ZipEntry {
+    name: String,
+    offset: u64,
+    crc: u32,
+    size: u64,
+    time: u16,
+    date: u16,
+    dir: bool,
+    zip64: bool,
+}
+
+const ZIP_LOCAL: u32 = 0x0403_4b50;
+const ZIP_DESCRIPTOR: u32 = 0x0807_4b50;
+const ZIP_CENTRAL: u32 = 0x0201_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+/// Флаги: имя в UTF-8 (бит 11) и CRC с размерами в дескрипторе (бит 3).
+const FLAG_UTF8: u16 = 0x0800;
+const FLAG_DESCRIPTOR: u16 = 0x0008;
+/// «Сделано» в unix, версия 4.5: атрибуты читаются как права unix.
+const MADE_BY: u16 = (3 << 8) | 45;
+const MAX32: u64 = u32::MAX as u64;
+
+impl<W: Write> ZipSink<W> {
+    fn new(out: W) -> Self {
+        Self { out: Counting { inner: out, written: 0 }, central: Vec::new() }
+    }
+
+    fn local_header(&mut self, name: &str, flags: u16, time: u16, date: u16, zip64: bool) -> io::Result<()> {
+        let mut h = Vec::with_capacity(30 + name.len() + 20);
+        put32(&mut h, ZIP_LOCAL);
+        put16(&mut h, if zip64 { 45 } else { 20 });
+        put16(&mut h, flags);
+        put16(&mut h, 0); // без сжатия
+        put16(&mut h, time);
+        put16(&mut h, date);
+        put32(&mut h, 0); // CRC в дескрипторе
+        let size = if zip64 { u32::MAX } else { 0 };
+        put32(&mut h, size);
+        put32(&mut h, size);
+        put16(&mut h, name.len() as u16);
+        put16(&mut h, if zip64 { 20 } else { 0 });
+        h.extend_from_slice(name.as_bytes());
+        if zip64 {
+            put16(&mut h, 0x0001);
+            put16(&mut h, 16);
+            put64(&mut h, 0);
+            put64(&mut h, 0);
+        }
+        self.out.write_all(&h)
+    }
+}
+
+impl<W: Write> Sink for ZipSink<W> {
+    fn dir(&mut self, name: &str, mtime: i64) -> io::Result<()> {
+        let (time, date) = dos_datetime(mtime);
+        let offset = self.out.written;
+        self.local_header(name, FLAG_UTF8, time, date, false)?;
+        self.central.push(ZipEntry { name: name.to_string(), offset, crc: 0, size: 0, time, date, dir: true, zip64: false });
+        Ok(())
+    }
+
+    fn file(&mut self, name: &str, mtime: i64, size: u64, data: &mut dyn Read) -> io::Result<()> {
+        let (time, date) = dos_datetime(mtime);
+        let offset = self.out.written;
+        let zip64 = size >= MAX32;
+        self.local_header(name, FLAG_UTF8 | FLAG_DESCRIPTOR, time, date, zip64)?;
+        let mut crc = crc32fast::Hasher::new();
+        let mut buf = vec![0u8; CHUNK];
+        let mut copied = 0u64;
+        loop {
+            let n = data.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+            self.out.write_all(&buf[..n])?;
+            copied += n as u64;
+        }
+        let crc = crc.finalize();
+        let mut d = Vec::with_capacity(24);
+        put32(&mut d, ZIP_DESCRIPTOR);
+        put32(&mut d, crc);
+        if zip64 {
+            put64(&mut d, copied);
+            put64(&mut d, copied);
+        } else {
+            put32(&mut d, copied as u32);
+            put32(&mut d, copied as u32);
+        }
+        self.out.write_all(&d)?;
+        self.central.push(ZipEntry { name: name.to_string(), offset, crc, size: copied, time, date, dir: false, zip64 });
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let ZipSink { out, central } = &mut *self;
+        let cd_start = out.written;
+        for e in central.iter() {
+            let big_size = e.zip64 || e.size >= MAX32;
+            let big_offset = e.offset >= MAX32;
+            let mut extra = Vec::new();
+            if big_size {
+                put64(&mut extra, e.size);
+                put64(&mut extra, e.size);
+            }
+            if big_offset {
+                put64(&mut extra, e.offset);
+            }
+            let mut h = Vec::with_capacity(46 + e.name.len() + 4 + extra.len());
+            put32(&mut h, ZIP_CENTRAL);
+            put16(&mut h, MADE_BY);
+            put16(&mut h, if extra.is_empty() { 20 } else { 45 });
+            put16(&mut h, if e.dir { FLAG_UTF8 } else { FLAG_UTF8 | FLAG_DESCRIPTOR });
+            put16(&mut h, 0);
+            put16(&mut h, e.time);
+            put16(&mut h, e.date);
+            put32(&mut h, e.crc);
+            let size32 = if big_size { u32::MAX } else { e.size as u32 };
+            put32(&mut h, size32);
+            put32(&mut h, size32);
+            put16(&mut h, e.name.len() as u16);
+            put16(&mut h, if extra.is_empty() { 0 } else { 4 + extra.len() as u16 });
+            put16(&mut h, 0); // комментарий
+            put16(&mut h, 0); // диск
+            put16(&mut h, 0); // внутренние атрибуты
+            let mode: u32 = if e.dir { 0o040755 } else { 0o100644 };
+            put32(&mut h, (mode << 16) | if e.dir { 0x10 } else { 0 });
+            put32(&mut h, if big_offset { u32::MAX } else { e.offset as u32 });
+            h.extend_from_slice(e.name.as_bytes());
+            if !extra.is_empty() {
+                put16(&mut h, 0x0001);
+                put16(&mut h, extra.len() as u16);
+                h.extend_from_slice(&extra);
+            }
+            out.write_all(&h)?;
+        }
+        let cd_end = out.written;
+        let count = central.len() as u64;
+        let cd_size = cd_end - cd_start;
+        let mut t = Vec::with_capacity(98);
+        if count >= 0xFFFF || cd_size >= MAX32 || cd_start >= MAX32 {
+            put32(&mut t, ZIP64_END);
+            put64(&mut t, 44);
+            put16(&mut t, MADE_BY);
+            put16(&mut t, 45);
+            put32(&mut t, 0);
+            put32(&mut t, 0);
+            put64(&mut t, count);
+            put64(&mut t, count);
+            put64(&mut t, cd_size);
+            put64(&mut t, cd_start);
+            put32(&mut t, ZIP64_LOCATOR);
+            put32(&mut t, 0);
+            put64(&mut t, cd_end);
+            put32(&mut t, 1);
+        }
+        put32(&mut t, ZIP_END);
+        put16(&mut t, 0);
+        put16(&mut t, 0);
+        put16(&mut t, count.min(0xFFFF) as u16);
+        put16(&mut t, count.min(0xFFFF) as u16);
+        put32(&mut t, cd_size.min(MAX32) as u32);
+        put32(&mut t, cd_start.min(MAX32) as u32);
+        put16(&mut t, 0);
+        out.write_all(&t)?;
+        out.flush()
+    }
+}
+
+/// Время MS-DOS из unix-секунд, по UTC. Раньше 1980 года DOS не умеет, такие
+/// файлы получают 1 января 1980.
+fn dos_datetime(unix: i64) -> (u16, u16) {
+    const DOS_EPOCH: i64 = 315_532_800;
+    let t = unix.max(DOS_EPOCH);
+    let (days, secs) = (t.div_euclid(86_400), t.rem_euclid(86_400));
+    // Гражданская дата из дней от 1970-01-01 (алгоритм Хиннанта).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = (yoe + era * 400 + i64::from(month <= 2)).min(2107);
+    let time = ((secs / 3600) << 11) | (((secs % 3600) / 60) << 5) | ((secs % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+fn put16(v: &mut Vec<u8>, x: u16) {
+    v.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put32(v: &mut Vec<u8>, x: u32) {
+    v.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put64(v: &mut Vec<u8>, x: u64) {
+    v.extend_from_slice(&x.to_le_bytes());
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Читатель файла, попутно считающий SHA-256 и число байт.
+struct Hashing<R> {
+    inner: R,
+    hasher: Sha256,
+    read: u64,
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// Писатель, считающий смещение: ZIP ссылается на него из центрального каталога.
+struct Counting<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Писатель в канал тела ответа. Ушедший клиент это `BrokenPipe`, на нём
+/// обход и останавливается.
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "клиент закрыл соединение"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= CHUNK {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/empty")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"hello").unwrap();
+        std::fs::write(dir.path().join("sub/b.bin"), vec![7u8; 200_000]).unwrap();
+        std::fs::create_dir(dir.path().join(".xr-trash")).unwrap();
+        std::fs::write(dir.path().join(".xr-trash/old"), b"secret").unwrap();
+        dir
+    }
+
+    fn sha(data: &[u8]) -> String {
+        hex(&Sha256::digest(data))
+    }
+
+    #[test]
+    fn tar_holds_the_folder_and_its_sums() {
+        let dir = tree();
+        let cache = HashCache::new();
+        let mut out = Vec::new();
+        write_archive(dir.path(), "photos", Format::Tar, &cache, &mut out).unwrap();
+
+        let mut archive = tar::Archive::new(out.as_slice());
+        let mut files = std::collections::BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            files.insert(path, data);
+        }
+        assert!(files.contains_key("photos/sub/empty/"), "пустой каталог тоже едет: {:?}", files.keys());
+        assert_eq!(files["photos/a.txt"], b"hello");
+        assert_eq!(files["photos/sub/b.bin"].len(), 200_000);
+        assert!(files.keys().all(|p| !p.contains(".xr-")), "служебное не едет: {:?}", files.keys());
+        let sums = String::from_utf8(files[SUMS_NAME].clone()).unwrap();
+        assert!(sums.contains(&format!("{}  photos/a.txt\n", sha(b"hello"))));
+        assert!(sums.contains(&format!("{}  photos/sub/b.bin\n", sha(&[7u8; 200_000]))));
+        // Хеши, посчитанные по дороге, осели в кеше.
+        assert_eq!(cache.known(&dir.path().join("a.txt")), Some(sha(b"hello")));
+    }
+
+    /// Разбор ZIP по центральному каталогу: имена, смещения и CRC сходятся с
+    /// локальными записями и данными.
+    #[test]
+    fn zip_central_directory_matches_the_data() {
+        let dir = tree();
+        let mut out = Vec::new();
+        write_archive(dir.path(), "photos", Format::Zip, &HashCache::new(), &mut out).unwrap();
+
+        let end = out.len() - 22;
+        assert_eq!(u32::from_le_bytes(out[end..end + 4].try_into().unwrap()), ZIP_END);
+        let le16 = |at: usize| u16::from_le_bytes(out[at..at + 2].try_into().unwrap()) as usize;
+        let le32 = |at: usize| u32::from_le_bytes(out[at..at + 4].try_into().unwrap());
+        let count = le16(end + 10);
+        let mut at = le32(end + 16) as usize;
+        let mut names = Vec::new();
+        for _ in 0..count {
+            assert_eq!(le32(at), ZIP_CENTRAL);
+            let (crc, size) = (le32(at + 16), le32(at + 24) as usize);
+            let (name_len, extra_len) = (le16(at + 28), le16(at + 30));
+            let offset = le32(at + 42) as usize;
+            let name = String::from_utf8(out[at + 46..at + 46 + name_len].to_vec()).unwrap();
+            assert_eq!(le32(offset), ZIP_LOCAL);
+            let data = offset + 30 + le16(offset + 26) + le16(offset + 28);
+            assert_eq!(crc32fast::hash(&out[data..data + size]), crc, "{name}");
+            names.push(name);
+            at += 46 + name_len + extra_len;
+        }
+        assert_eq!(
+            names,
+            ["photos/", "photos/a.txt", "photos/sub/", "photos/sub/b.bin", "photos/sub/empty/", SUMS_NAME]
+        );
+    }
+
+    #[test]
+    fn dos_time_is_calendar_correct() {
+        // 2024-02-29 13:45:30 UTC.
+        let (time, date) = dos_datetime(1_709_214_330);
+        assert_eq!((date >> 9) + 1980, 2024);
+        assert_eq!((date >> 5) & 0xF, 2);
+        assert_eq!(date & 0x1F, 29);
+        assert_eq!((time >> 11, (time >> 5) & 0x3F, (time & 0x1F) * 2), (13, 45, 30));
+        assert_eq!(dos_datetime(0), (0, (1 << 5) | 1));
+    }
+}
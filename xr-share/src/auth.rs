@@ -6,6 +6,8 @@
 //! 1. `Authorization: Bearer <blob>`  — primary, used by the app
 //! 2. `X-Share-Token: <blob>`         — explicit header alternative
 //! 3. `?token=<blob>`                 — best-effort for browsers / curl
+//! 4. `Authorization: Basic <user:blob>` — WebDAV clients (a file manager can
+//!    only send a password); the user name is ignored
 //!
 //! base64url-no-pad keeps the blob safe in both headers and query strings. The
 //! actual signature/expiry/share check is [`xr_proto::share::verify_share_token`];
@@ -20,7 +22,8 @@ use xr_proto::share::ShareToken;
 pub fn extract_token(headers: &HeaderMap, uri: &Uri) -> Option<ShareToken> {
     let blob = bearer(headers)
         .or_else(|| header_value(headers, "x-share-token"))
-        .or_else(|| query_token(uri))?;
+        .or_else(|| query_token(uri))
+        .or_else(|| basic_password(headers))?;
     decode_token_blob(&blob)
 }
 
@@ -29,6 +32,15 @@ fn bearer(headers: &HeaderMap) -> Option<String> {
         .and_then(|v| v.strip_prefix("Bearer ").map(str::to_string))
 }
 
+fn basic_password(headers: &HeaderMap) -> Option<String> {
+    let creds = header_value(headers, "authorization")?;
+    let raw = base64::engine::general_purpose::STANDARD
+        .decode(creds.strip_prefix("Basic ")?.trim())
+        .ok()?;
+    let (_user, password) = std::str::from_utf8(&raw).ok()?.split_once(':')?;
+    Some(password.to_string())
+}
+
 fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
     headers
         .get(name)
@@ -87,6 +99,15 @@ mod tests {
         assert_eq!(extract_token(&HeaderMap::new(), &uri), Some(t));
     }
 
+    #[test]
+    fn extracts_from_basic_password() {
+        let t = sample();
+        let creds = base64::engine::general_purpose::STANDARD.encode(format!("me:{}", blob(&t)));
+        let mut h = HeaderMap::new();
+        h.insert("authorization", format!("Basic {creds}").parse().unwrap());
+        assert_eq!(extract_token(&h, &Uri::from_static("/dav/s1/")), Some(t));
+    }
+
     #[test]
     fn none_when_absent_or_garbage() {
         assert_eq!(extract_token(&HeaderMap::new(), &Uri::from_static("/manifest")), None);
@@ -0,0 +1,120 @@
+//! Списки чанков файлов для блочной докачки изменённых файлов.
+//!
+//! Потребитель со старой копией большого файла просит у агента его список
+//! чанков (`GET /{share_id}/chunks/{*path}`), режет свою копию тем же
+//! [`xr_proto::chunks`] и тянет диапазонами только то, чего у него нет.
+//! Нарезка это чтение файла целиком, поэтому списки кешируются по
+//! `(size, mtime)`, как хеши в [`HashCache`], а хеш всего файла из того же
+//! прохода засевает и его.
+//!
+//! Кеш маленький и в памяти: список файла на десять гигабайт весит около
+//! мегабайта, а просят их только для недавно изменённых больших файлов.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use xr_proto::chunks::chunk_reader;
+use xr_proto::share::FileChunks;
+
+use crate::manifest::{mtime_secs, HashCache};
+
+/// Сколько списков держать. Вытесняется давно не спрошенный.
+const CACHED_FILES: usize = 64;
+
+#[derive(Default)]
+pub struct ChunkCache {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    files: HashMap<PathBuf, Cached>,
+    /// Счётчик обращений для вытеснения давно не спрошенного.
+    tick: u64,
+}
+
+struct Cached {
+    size: u64,
+    mtime: i64,
+    chunks: Arc<FileChunks>,
+    used: u64,
+}
+
+impl ChunkCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("chunk cache poisoned")
+    }
+
+    /// Список чанков файла: из кеша, пока `(size, mtime)` те же, иначе
+    /// нарезка с чтением файла целиком (блокирует, звать не с рантайма).
+    /// Файл, изменившийся во время чтения, не кешируется и хеш не засевает:
+    /// такой список описывает неизвестно что, и потребитель его отбросит по
+    /// хешу.
+    pub fn chunks_of(&self, path: &Path, hashes: &HashCache) -> Result<Arc<FileChunks>> {
+        let (size, mtime) = stat(path)?;
+        {
+            let mut inner = self.lock();
+            inner.tick += 1;
+            let tick = inner.tick;
+            if let Some(c) = inner.files.get_mut(path).filter(|c| c.size == size && c.mtime == mtime) {
+                c.used = tick;
+                return Ok(c.chunks.clone());
+            }
+        }
+        let file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let chunks = Arc::new(chunk_reader(std::io::BufReader::new(file))?);
+        if stat(path)? != (size, mtime) || chunks.size != size {
+            return Ok(chunks);
+        }
+        hashes.seed(path, size, mtime, chunks.sha256.clone());
+        let mut inner = self.lock();
+        let used = inner.tick;
+        inner
+            .files
+            .insert(path.to_path_buf(), Cached { size, mtime, chunks: chunks.clone(), used });
+        if inner.files.len() > CACHED_FILES {
+            if let Some(oldest) = inner.files.iter().min_by_key(|(_, c)| c.used).map(|(p, _)| p.clone()) {
+                inner.files.remove(&oldest);
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+fn stat(path: &Path) -> Result<(u64, i64)> {
+    let meta = std::fs::metadata(path).with_context(|| format!("stat {}", path.display()))?;
+    anyhow::ensure!(meta.is_file(), "not a regular file: {}", path.display());
+    Ok((meta.len(), mtime_secs(&meta)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_list_is_cut_once_per_version_and_seeds_the_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("img.bin");
+        std::fs::write(&path, vec![7u8; 300_000]).unwrap();
+        let cache = ChunkCache::new();
+        let hashes = HashCache::new();
+
+        let a = cache.chunks_of(&path, &hashes).unwrap();
+        assert_eq!(a.size, 300_000);
+        assert_eq!(hashes.hash_of(&path).unwrap(), a.sha256, "хеш засеян тем же проходом");
+        let b = cache.chunks_of(&path, &hashes).unwrap();
+        assert!(Arc::ptr_eq(&a, &b), "та же версия файла режется один раз");
+
+        // Другой размер это другая версия файла.
+        std::fs::write(&path, vec![7u8; 10]).unwrap();
+        let c = cache.chunks_of(&path, &hashes).unwrap();
+        assert_eq!(c.size, 10);
+        assert!(cache.chunks_of(&dir.path().join("missing"), &hashes).is_err());
+    }
+}
@@ -204,37 +204,46 @@ pub fn install(config_path: &Path, args: InstallArgs) -> Result<()> {
     let identity = SigningKey::generate(&mut rand::thread_rng());
     let agent_pub = setup::b64(identity.verifying_key().as_bytes());
 
-    let (agent_credential, relay_cfg) = match token.as_deref() {
+    let (agent_credential, relays) = match token.as_deref() {
         Some(token) => {
-            let (cred, relay) = exchange(&hub, token, &agent_pub).context("обмен reg-токена на мандат")?;
+            let (cred, relays) = exchange(&hub, token, &agent_pub).context("обмен reg-токена на мандат")?;
             println!("  ✓ мандат агента получен (можно шарить без админки)");
-            if relay.is_some() {
-                println!("  ✓ relay-дескриптор получен (шары за NAT доступны через relay)");
+            if !relays.is_empty() {
+                println!(
+                    "  ✓ relay-дескрипторов получено: {} (шары за NAT доступны через relay)",
+                    relays.len()
+                );
             }
-            (Some(cred), relay.map(|d| crate::config::RelayAgentConfig::from_descriptor(&d)))
+            (Some(cred), relays)
         }
         None => {
             println!("  ! без --token/--setup мандата нет: запросишь его позже через `install --token <reg-токен>`");
-            (None, None)
+            (None, Vec::new())
         }
     };
 
-    let cfg = AgentConfig {
+    let mut cfg = AgentConfig {
         listen,
         hub_pubkey,
         hub_url: Some(hub.trim_end_matches('/').to_string()),
         agent_credential,
         identity_key: Some(setup::b64(&identity.to_bytes())),
         tls: None,
-        relay: relay_cfg,
+        relay: None,
+        backup_relays: Vec::new(),
         default_invite: setup_invite,
         max_file_mb: None,
+        webdav: false,
         import: None,
+        watch: None,
+        trash: None,
+        thumbs: None,
         shares: Vec::new(),
         exposes: Vec::new(),
         dir: None,
         share_id: None,
     };
+    cfg.set_relays(&relays);
     write_config(config_path, &cfg)?;
     println!("\n✓ Конфиг записан: {} (agent_pubkey {agent_pub})", config_path.display());
 
@@ -335,6 +344,9 @@ pub fn share(config_path: &Path, args: ShareArgs) -> Result<()> {
     let share_id = str_field(&resp, "share_id")?;
     let addr = str_field(&resp, "addr")?;
     let token = str_field(&resp, "token")?;
+    // Страница шары в браузере (LLD-38 п. 2.6): хаб отдаёт её, когда знает
+    // web-домен, а шара выведена на relay.
+    let web_url = resp.get("web_url").and_then(|v| v.as_str()).map(str::to_string);
     // The LAN candidates the hub stored, after its trimming/dedup (XR-050).
     let extra_addrs: Vec<String> = resp
         .get("addrs")
@@ -342,13 +354,9 @@ pub fn share(config_path: &Path, args: ShareArgs) -> Result<()> {
         .map(|a| a.iter().filter_map(|x| x.as_str().map(str::to_string)).collect())
         .unwrap_or_default();
 
-    // A relay-reachable share gets the relay descriptor back; store it so the
-    // running agent brings up its reverse tunnel (LLD-23 §2.4).
-    if let Some(relay) = resp.get("relay").filter(|v| !v.is_null()) {
-        let desc: xr_proto::share::RelayDescriptor =
-            serde_json::from_value(relay.clone()).context("разбор relay-дескриптора")?;
-        cfg.relay = Some(crate::config::RelayAgentConfig::from_descriptor(&desc));
-    }
+    // A relay-reachable share gets the relay descriptors back; store them so the
+    // running agent brings up its reverse tunnels (LLD-23 §2.4).
+    cfg.set_relays(&relay_descriptors(&resp)?);
 
     // Persist the share locally; the running agent hot-reloads it. Тот же путь
     // обновляет свою запись, а не заводит вторую (XR-162).
@@ -362,6 +370,7 @@ pub fn share(config_path: &Path, args: ShareArgs) -> Result<()> {
             writable: args.writable,
             import: args.import,
             attached: false,
+            policy: None,
         },
     );
     write_config(config_path, &cfg)?;
@@ -432,6 +441,10 @@ pub fn share(config_path: &Path, args: ShareArgs) -> Result<()> {
         // No invite: hand out a self-contained link (receiver pulls directly).
         println!("\n  Ссылка для получателя (отправь её в мессенджере):");
         println!("  xrshare://{addr}:{port}/{share_id}?token={token}");
+        if let Some(url) = &web_url {
+            println!("\n  Та же шара в браузере, без приложения:");
+            println!("  {url}");
+        }
     } else {
         println!("\n  Получатели с привязанным инвайтом уже видят шару (xr-share pull / приложение).");
     }
@@ -613,12 +626,10 @@ fn refresh_existing(
         if let (Some(tok), Some(hub)) = (token, existing.hub_url.clone()) {
             if let Some(id) = existing.identity_signing_key(source)? {
                 let agent_pub = setup::b64(id.verifying_key().as_bytes());
-                let (cred, relay) =
+                let (cred, relays) =
                     exchange(&hub, tok, &agent_pub).context("обмен reg-токена на мандат")?;
                 existing.agent_credential = Some(cred);
-                if let Some(d) = relay {
-                    existing.relay = Some(crate::config::RelayAgentConfig::from_descriptor(&d));
-                }
+                existing.set_relays(&relays);
                 changed = true;
                 println!("  мандат получен для существующего конфига");
             }
@@ -879,22 +890,31 @@ fn agent_json(
 // -- hub client ------------------------------------------------------
 
 /// Trade a reg-token for an agent credential blob (`POST /share/exchange`),
-/// plus the hub's relay descriptor if it advertises one (LLD-23 §2.4).
+/// plus the relays the hub placed this agent on, if it has any (LLD-23 §2.4).
 fn exchange(
     hub: &str,
     token: &str,
     agent_pubkey: &str,
-) -> Result<(String, Option<xr_proto::share::RelayDescriptor>)> {
+) -> Result<(String, Vec<xr_proto::share::RelayDescriptor>)> {
     let body = serde_json::json!({ "token": token, "agent_pubkey": agent_pubkey });
     let resp = hub_post(&format!("{}/api/v1/share/exchange", hub.trim_end_matches('/')), &body)?;
     let cred = str_field(&resp, "credential")?;
+    Ok((cred, relay_descriptors(&resp)?))
+}
+
+/// The relay placement from a hub reply, best first: the `relays` list, or
+/// the lone `relay` of a hub that predates several relays. Empty without one.
+fn relay_descriptors(resp: &serde_json::Value) -> Result<Vec<xr_proto::share::RelayDescriptor>> {
+    if let Some(list) = resp.get("relays").filter(|v| !v.is_null()) {
+        return serde_json::from_value(list.clone()).context("разбор relay-дескрипторов");
+    }
     let relay = resp
         .get("relay")
         .filter(|v| !v.is_null())
         .map(|v| serde_json::from_value(v.clone()))
         .transpose()
         .context("разбор relay-дескриптора")?;
-    Ok((cred, relay))
+    Ok(relay.into_iter().collect())
 }
 
 pub(crate) fn hub_post(url: &str, body: &serde_json::Value) -> Result<serde_json::Value> {
@@ -956,9 +976,14 @@ pub(crate) fn write_config(path: &Path, cfg: &AgentConfig) -> Result<()> {
 /// (`safepath`), поэтому хвостовой слеш и симлинк ведут к той же записи.
 /// Путь, который больше не канонизируется (папку унесли), сравнивается как
 /// записан: иначе две пропавшие шары схлопнулись бы в одну.
-fn upsert_share(shares: &mut Vec<ShareEntry>, entry: ShareEntry) -> Option<ShareEntry> {
+fn upsert_share(shares: &mut Vec<ShareEntry>, mut entry: ShareEntry) -> Option<ShareEntry> {
     match shares.iter().position(|s| same_path(&s.path, &entry.path)) {
         Some(idx) => {
+            // Политику пишут руками в конфиг, флагов у `share` для неё нет:
+            // перерегистрация её не сбрасывает.
+            if entry.policy.is_none() {
+                entry.policy = shares[idx].policy.take();
+            }
             let old = std::mem::replace(&mut shares[idx], entry);
             (old.share_id != shares[idx].share_id).then_some(old)
         }
@@ -1007,7 +1032,7 @@ fn same_path(a: &str, b: &str) -> bool {
 fn normalize_legacy(cfg: &mut AgentConfig) {
     if let (Some(dir), Some(id)) = (cfg.dir.take(), cfg.share_id.take()) {
         if !cfg.shares.iter().any(|s| s.share_id == id) {
-            cfg.shares.push(ShareEntry { share_id: id, path: dir, name: None, writable: false, import: false, attached: false });
+            cfg.shares.push(ShareEntry { share_id: id, path: dir, name: None, writable: false, import: false, attached: false, policy: None });
         }
     }
 }
@@ -1063,10 +1088,15 @@ mod tests {
             identity_key: None,
             tls: None,
             relay: None,
+            backup_relays: Vec::new(),
             default_invite: None,
             max_file_mb: None,
+            webdav: false,
             import: None,
-            shares: vec![ShareEntry { share_id: "s1".into(), path: "/srv/x".into(), name: None, writable: false, import: false, attached: false }],
+            watch: None,
+            trash: None,
+            thumbs: None,
+            shares: vec![ShareEntry { share_id: "s1".into(), path: "/srv/x".into(), name: None, writable: false, import: false, attached: false, policy: None }],
             exposes: Vec::new(),
             dir: None,
             share_id: None,
@@ -1309,9 +1339,14 @@ mod share_upsert_tests {
             identity_key: None,
             tls: None,
             relay: None,
+            backup_relays: Vec::new(),
             default_invite: None,
             max_file_mb: None,
+            webdav: false,
             import: None,
+            watch: None,
+            trash: None,
+            thumbs: None,
             shares: Vec::new(),
             exposes: Vec::new(),
             dir: None,
@@ -16,6 +16,7 @@ use anyhow::{Context, Result};
 use base64::Engine;
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use xr_proto::share::ExposeKind;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AgentConfig {
@@ -46,6 +47,11 @@ pub struct AgentConfig {
     /// default build parses it but logs that it's ignored.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub relay: Option<RelayAgentConfig>,
+    /// Further relays the hub placed this agent on (`[[backup_relays]]`). The
+    /// agent keeps a reverse tunnel to each of them too, so consumers fail over
+    /// when the primary relay is down.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backup_relays: Vec<RelayAgentConfig>,
     /// Invite this agent attaches new shares to when `share` gets no explicit
     /// `--invite` (XR-127). Set from a `--setup` token at install, so onboarding
     /// is one command: install once, and every later `share` lands on the invite
@@ -57,10 +63,27 @@ pub struct AgentConfig {
     /// refused with `413` before it is written.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_file_mb: Option<u64>,
+    /// WebDAV поверх шар (`/dav/{share_id}/...`, см. [`crate::dav`]), чтобы
+    /// шару можно было смонтировать в файловом менеджере. Выключено по
+    /// умолчанию; пускают те же токены и скоупы, что и на остальные ручки.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub webdav: bool,
     /// URL-import settings + plugin registry (LLD-29). Absent block means no
     /// import anywhere: the local opt-in on top of the `share:import` scope.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub import: Option<ImportConfig>,
+    /// Слежение за изменениями шар (`[watch]`). Блока нет значит настройки по
+    /// умолчанию: уведомления ОС, опрос только там, где они не заводятся.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watch: Option<WatchConfig>,
+    /// Корзина записываемых шар (`[trash]`, см. [`crate::trash`]). Блока нет
+    /// значит настройки по умолчанию: корзина включена.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash: Option<TrashConfig>,
+    /// Превью файлов шар (`[thumbs]`, см. [`crate::thumbs`]). Блока нет значит
+    /// настройки по умолчанию: миниатюры картинок есть, кадров видео нет.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbs: Option<ThumbsConfig>,
     /// The shares this agent serves. Each `[[share]]` is a `share_id` + path.
     #[serde(default, rename = "share")]
     pub shares: Vec<ShareEntry>,
@@ -109,9 +132,59 @@ pub struct ShareEntry {
     /// поля читается как «не привязывалась».
     #[serde(default)]
     pub attached: bool,
+    /// Ограничения записи в шару (`[share.policy]` под её `[[share]]`). Нет
+    /// блока: пишется всё, пока есть диск и `max_file_mb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<SharePolicy>,
+}
+
+/// Что и сколько можно записать в шару: потолок места и числа файлов, какие
+/// файлы принимать и сколько диска оставить свободным. Проверяется загрузкой
+/// (`PUT`, он же WebDAV) и импортом ([`crate::quota`]); у шары только для
+/// чтения блок лишь сообщает потребителю цифры.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SharePolicy {
+    /// Потолок суммарного размера файлов шары, МиБ. Служебные `.xr-*` не
+    /// считаются: у корзины свой `max_mb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_mb: Option<u64>,
+    /// Потолок числа файлов шары.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<u64>,
+    /// Какие расширения принимать (без точки, регистр не важен). Вместе с
+    /// `mime` файл проходит по любому из списков; оба пусты: любые файлы.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// Какие MIME-типы принимать, по имени файла: `image/jpeg` или `image/*`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mime: Vec<String>,
+    /// Сколько МиБ оставить свободными на диске шары: запись, которая залезла
+    /// бы в этот запас, отказывается, чтобы семейная папка не забила системный
+    /// диск.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reserve_free_mb: Option<u64>,
+}
+
+impl SharePolicy {
+    /// Принимается ли файл с таким именем по спискам типов.
+    pub fn allows(&self, name: &str) -> bool {
+        if self.extensions.is_empty() && self.mime.is_empty() {
+            return true;
+        }
+        let ext = std::path::Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("");
+        if !ext.is_empty() && self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)) {
+            return true;
+        }
+        mime_guess::from_path(name).iter().any(|m| {
+            self.mime.iter().any(|want| match want.strip_suffix("/*") {
+                Some(top) => m.type_().as_str().eq_ignore_ascii_case(top),
+                None => m.essence_str().eq_ignore_ascii_case(want),
+            })
+        })
+    }
 }
 
-/// Одна публикация: имя и локальный адрес сервиса. Апстрим живёт только здесь
+/// Одна публикация: имя, вид и локальный адрес сервиса. Апстрим живёт только здесь
 /// и в хаб не уезжает (LLD-38 п. 2.1), а приходящий запрос адрес не выбирает
 /// никогда: иначе браузерный вход стал бы плечом внутрь домашней сети.
 /// Публикация без записи в этом списке не обслуживается, даже когда запись в
@@ -122,6 +195,155 @@ pub struct ExposeEntry {
     pub name: String,
     /// Куда проксировать внутри машины, например `127.0.0.1:8765`.
     pub upstream: String,
+    /// `http` (по умолчанию) или `tcp`: сырой TCP до апстрима, который
+    /// открывает только `xr-share connect`.
+    #[serde(default, skip_serializing_if = "ExposeKind::is_http")]
+    pub kind: ExposeKind,
+}
+
+/// Как агент узнаёт об изменениях в шарах: уведомления ОС (inotify на Linux)
+/// или опрос обходом дерева. Опрос дороже, но видит и то, о чём ядро молчит:
+/// правки на сетевом диске с другой машины.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WatchConfig {
+    /// Опрашивать все шары, уведомлениям ОС не доверять вовсе. Для шар на
+    /// NFS/SMB, где inotify видит только свои записи.
+    #[serde(default)]
+    pub poll: bool,
+    /// Период опроса, секунды. Он же задержка, с которой изменение доезжает
+    /// до ждущих, когда шара на опросе.
+    #[serde(default = "default_watch_poll_secs")]
+    pub poll_secs: u64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self { poll: false, poll_secs: default_watch_poll_secs() }
+    }
+}
+
+fn default_watch_poll_secs() -> u64 {
+    10
+}
+
+/// Сколько держать в корзине версии, которые агент убрал при `DELETE` и
+/// перезаписи. Срок и объём считаются по каждой шаре отдельно.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TrashConfig {
+    /// Срок хранения версии, дни. `0` выключает корзину: удаление и
+    /// перезапись снова окончательны.
+    #[serde(default = "default_trash_keep_days")]
+    pub keep_days: u64,
+    /// Потолок объёма корзины одной шары, мебибайты. Сверх него первыми уходят
+    /// самые старые версии. `0` значит без потолка, только по сроку.
+    #[serde(default = "default_trash_max_mb")]
+    pub max_mb: u64,
+}
+
+impl TrashConfig {
+    /// Корзина включена: версии откладываются, а не удаляются сразу.
+    pub fn enabled(&self) -> bool {
+        self.keep_days > 0
+    }
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self { keep_days: default_trash_keep_days(), max_mb: default_trash_max_mb() }
+    }
+}
+
+fn default_trash_keep_days() -> u64 {
+    30
+}
+
+fn default_trash_max_mb() -> u64 {
+    2048
+}
+
+fn valid_mime_pattern(m: &str) -> bool {
+    let ok = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b));
+    match m.split_once('/') {
+        Some((top, "*")) => ok(top),
+        Some((top, sub)) => ok(top) && ok(sub),
+        None => false,
+    }
+}
+
+/// Превью для просмотра шары без скачивания: миниатюры картинок и, с внешней
+/// программой, кадры видео. Делаются по первому запросу и кешируются.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThumbsConfig {
+    /// Длинная сторона миниатюры, пиксели. `0` выключает превью вовсе.
+    #[serde(default = "default_thumb_size")]
+    pub size: u32,
+    /// Картинки больше этого не разбираются, мебибайты: декодер держит кадр
+    /// целиком в памяти.
+    #[serde(default = "default_thumb_max_source_mb")]
+    pub max_source_mb: u64,
+    /// Потолок кеша превью одной шары, мебибайты. Сверх него первыми уходят
+    /// давно не запрошенные.
+    #[serde(default = "default_thumb_cache_mb")]
+    pub cache_mb: u64,
+    /// Программа, вынимающая кадр из видео (обычно ffmpeg). Без неё у видео
+    /// превью нет.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster: Option<PosterTool>,
+}
+
+impl ThumbsConfig {
+    /// Превью включены.
+    pub fn enabled(&self) -> bool {
+        self.size > 0
+    }
+}
+
+impl Default for ThumbsConfig {
+    fn default() -> Self {
+        Self {
+            size: default_thumb_size(),
+            max_source_mb: default_thumb_max_source_mb(),
+            cache_mb: default_thumb_cache_mb(),
+            poster: None,
+        }
+    }
+}
+
+/// Внешняя программа для кадра видео, по образцу плагинов импорта: команда и
+/// шаблон аргументов, без шелла.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PosterTool {
+    pub cmd: String,
+    /// Шаблон argv. Элемент, равный `{input}`, заменяется путём к видео, равный
+    /// `{output}` путём к кадру (JPEG или PNG) одним аргументом; `{size}` внутри
+    /// любого элемента заменяется длинной стороной миниатюры.
+    pub args: Vec<String>,
+    /// Расширения файлов, которые считаются видео.
+    #[serde(default = "default_poster_extensions")]
+    pub extensions: Vec<String>,
+    /// Сколько ждать программу, секунды; дольше она убивается.
+    #[serde(default = "default_poster_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_thumb_size() -> u32 {
+    320
+}
+
+fn default_thumb_max_source_mb() -> u64 {
+    64
+}
+
+fn default_thumb_cache_mb() -> u64 {
+    256
+}
+
+fn default_poster_extensions() -> Vec<String> {
+    ["mp4", "m4v", "mov", "mkv", "webm", "avi"].map(String::from).to_vec()
+}
+
+fn default_poster_timeout_secs() -> u64 {
+    30
 }
 
 /// Job limits and the plugin registry for URL import (LLD-29 п. 2.3). The block
@@ -257,6 +479,21 @@ pub struct TlsConfig {
 }
 
 impl AgentConfig {
+    /// Every relay to keep a reverse tunnel to: the primary, then the backups.
+    pub fn relays(&self) -> Vec<RelayAgentConfig> {
+        self.relay.iter().chain(self.backup_relays.iter()).cloned().collect()
+    }
+
+    /// Store the hub's relay placement, best first. An empty list keeps what
+    /// the config had: a hub reply without relays is not a reason to forget them.
+    pub fn set_relays(&mut self, relays: &[xr_proto::share::RelayDescriptor]) {
+        let Some((primary, backups)) = relays.split_first() else {
+            return;
+        };
+        self.relay = Some(RelayAgentConfig::from_descriptor(primary));
+        self.backup_relays = backups.iter().map(RelayAgentConfig::from_descriptor).collect();
+    }
+
     /// Decode and validate the pinned hub public key.
     pub fn hub_verifying_key(&self) -> Result<VerifyingKey> {
         let bytes = base64::engine::general_purpose::STANDARD
@@ -312,6 +549,7 @@ impl AgentConfig {
                     writable: false,
                     import: false,
                     attached: false,
+                    policy: None,
                 });
             }
         }
@@ -357,6 +595,47 @@ impl AgentConfig {
         Ok(())
     }
 
+    /// Политики шар на старте: кривой MIME или расширение с точкой молча не
+    /// пропустили бы ни одного файла, и шара стала бы «только для чтения» без
+    /// объяснений.
+    pub fn validate_policies(&self) -> Result<()> {
+        for e in &self.shares {
+            let Some(p) = &e.policy else { continue };
+            let id = &e.share_id;
+            if let Some(bad) = p.extensions.iter().find(|x| x.is_empty() || x.contains(['.', '/'])) {
+                anyhow::bail!("share {id}: policy extension {bad:?} must be a bare extension like \"jpg\"");
+            }
+            if let Some(bad) = p.mime.iter().find(|m| !valid_mime_pattern(m)) {
+                anyhow::bail!("share {id}: policy mime {bad:?} must look like \"image/jpeg\" or \"image/*\"");
+            }
+            if p.quota_mb == Some(0) || p.max_files == Some(0) {
+                anyhow::bail!("share {id}: a zero quota_mb/max_files blocks every write, unset writable instead");
+            }
+        }
+        Ok(())
+    }
+
+    /// Программа кадров видео на старте: путь к видео и к кадру только целыми
+    /// элементами argv, как `{url}` у импорта. Имя файла шары приходит от
+    /// того, кто в неё пишет, и в середине строки звало бы к играм с кавычками.
+    pub fn validate_thumbs(&self) -> Result<()> {
+        let Some(poster) = self.thumbs.as_ref().and_then(|t| t.poster.as_ref()) else {
+            return Ok(());
+        };
+        for slot in ["{input}", "{output}"] {
+            if !poster.args.iter().any(|a| a == slot) {
+                anyhow::bail!("[thumbs.poster] args must contain \"{slot}\" as its own element");
+            }
+            if poster.args.iter().any(|a| a != slot && a.contains(slot)) {
+                anyhow::bail!("[thumbs.poster] \"{slot}\" must be a whole argv element, not part of one");
+            }
+        }
+        if poster.extensions.is_empty() {
+            anyhow::bail!("[thumbs.poster] extensions must not be empty");
+        }
+        Ok(())
+    }
+
     /// Проверки публикаций на старте (LLD-38 п. 2.1). Кривое имя или апстрим
     /// это отказ запуска, а не 502 на первом же запросе из браузера: до
     /// публикации доходят редко, и молчаливо неработающая она хуже, чем
@@ -494,11 +773,28 @@ mod tests {
             identity_key: Some("priv".into()),
             tls: None,
             relay: None,
+            backup_relays: Vec::new(),
             default_invite: Some("inv123".into()),
             max_file_mb: Some(100),
+            webdav: true,
             import: None,
-            shares: vec![ShareEntry { share_id: "a".into(), path: "/srv/x".into(), name: Some("X".into()), writable: true, import: true, attached: true }],
-            exposes: vec![ExposeEntry { name: "dash".into(), upstream: "127.0.0.1:8765".into() }],
+            watch: Some(WatchConfig { poll: true, poll_secs: 30 }),
+            trash: Some(TrashConfig { keep_days: 7, max_mb: 0 }),
+            thumbs: Some(ThumbsConfig {
+                poster: Some(PosterTool {
+                    cmd: "ffmpeg".into(),
+                    args: vec!["-i".into(), "{input}".into(), "{output}".into()],
+                    extensions: vec!["mp4".into()],
+                    timeout_secs: 10,
+                }),
+                ..ThumbsConfig::default()
+            }),
+            shares: vec![ShareEntry { share_id: "a".into(), path: "/srv/x".into(), name: Some("X".into()), writable: true, import: true, attached: true, policy: Some(SharePolicy { quota_mb: Some(500), mime: vec!["image/*".into()], ..Default::default() }) }],
+            exposes: vec![ExposeEntry {
+                name: "dash".into(),
+                upstream: "127.0.0.1:8765".into(),
+                kind: ExposeKind::Http,
+            }],
             dir: None,
             share_id: None,
         };
@@ -508,11 +804,64 @@ mod tests {
         assert!(back.resolved_shares()[0].writable, "writable flag must survive the roundtrip");
         assert!(back.resolved_shares()[0].import, "import flag must survive the roundtrip");
         assert_eq!(back.max_file_mb, Some(100));
+        assert!(back.webdav, "webdav обязан пережить перезапись конфига");
         assert_eq!(back.hub_url.as_deref(), Some("https://hub"));
         assert_eq!(back.default_invite.as_deref(), Some("inv123"));
         assert!(back.dir.is_none());
         assert_eq!(back.exposes.len(), 1, "публикация обязана пережить перезапись конфига");
         assert_eq!(back.exposes[0].upstream, "127.0.0.1:8765");
+        let watch = back.watch.expect("блок [watch] обязан пережить перезапись конфига");
+        assert!(watch.poll);
+        assert_eq!(watch.poll_secs, 30);
+        let trash = back.trash.expect("блок [trash] обязан пережить перезапись конфига");
+        assert_eq!((trash.keep_days, trash.max_mb), (7, 0));
+        let poster = back.thumbs.and_then(|t| t.poster).expect("программа кадров обязана пережить перезапись конфига");
+        assert_eq!(poster.args[1], "{input}");
+        let policy = back.shares[0].policy.as_ref().expect("политика шары обязана пережить перезапись конфига");
+        assert_eq!(policy.quota_mb, Some(500));
+    }
+
+    #[test]
+    fn share_policy_parses_filters_and_validates() {
+        let text = r#"
+            listen = "0.0.0.0:8443"
+            hub_pubkey = "QQ=="
+            [[share]]
+            share_id = "fam"
+            path = "/srv/family"
+            writable = true
+            [share.policy]
+            quota_mb = 2048
+            extensions = ["pdf"]
+            mime = ["image/*", "video/mp4"]
+            reserve_free_mb = 1024
+        "#;
+        let cfg: AgentConfig = toml::from_str(text).unwrap();
+        cfg.validate_policies().unwrap();
+        let policy = cfg.shares[0].policy.as_ref().unwrap();
+        assert_eq!((policy.quota_mb, policy.max_files), (Some(2048), None));
+        for (name, ok) in [
+            ("a/IMG_1.JPG", true),
+            ("scan.PDF", true),
+            ("clip.mp4", true),
+            ("clip.mkv", false),
+            ("run.exe", false),
+            ("README", false),
+        ] {
+            assert_eq!(policy.allows(name), ok, "{name}");
+        }
+        assert!(SharePolicy::default().allows("run.exe"), "пустые списки пускают всё");
+
+        for bad in [
+            r#"extensions = [".jpg"]"#,
+            r#"mime = ["image"]"#,
+            r#"mime = ["*/*"]"#,
+            "quota_mb = 0",
+        ] {
+            let text = format!("listen = \"0.0.0.0:8443\"\nhub_pubkey = \"QQ==\"\n[[share]]\nshare_id = \"x\"\npath = \"/x\"\n[share.policy]\n{bad}\n");
+            let cfg: AgentConfig = toml::from_str(&text).unwrap();
+            assert!(cfg.validate_policies().is_err(), "{bad}");
+        }
     }
 
     #[test]
@@ -552,6 +901,18 @@ mod tests {
         let mut broken = cfg.clone();
         broken.exposes.push(broken.exposes[0].clone());
         assert!(broken.validate_expose().is_err());
+
+        // Вид публикации: без поля это HTTP, `tcp` открывает сырой туннель.
+        assert_eq!(cfg.exposes[0].kind, ExposeKind::Http);
+        let tcp: AgentConfig = toml::from_str(
+            "listen = \"0.0.0.0:8443\"\nhub_pubkey = \"QQ==\"\n\
+             [[expose]]\nname = \"ssh\"\nupstream = \"127.0.0.1:22\"\nkind = \"tcp\"\n",
+        )
+        .unwrap();
+        tcp.validate_expose().unwrap();
+        assert_eq!(tcp.exposes[0].kind, ExposeKind::Tcp);
+        let written = toml::to_string(&cfg).unwrap();
+        assert!(!written.contains("kind"), "HTTP-публикация пишется как раньше: {written}");
     }
 
     #[test]
@@ -0,0 +1,611 @@
+//! WebDAV поверх шар агента: файловый менеджер монтирует шару как папку, без
+//! `xr-share pull`. Включается флагом `webdav = true` в конфиге агента и живёт
+//! в своём пространстве URL, `/dav/{share_id}/{*path}`.
+//!
+//! Своей модели доступа тут нет: те же корни шар, тот же `safepath`, те же
+//! токены и скоупы (`share:read` на чтение, `share:write` и writable-шара на
+//! запись). `GET`, `PUT` и `DELETE` файла идут ровно по путям обычных ручек
+//! агента, с предусловиями, корзиной и поднятием версии манифеста. Токен
+//! принимается и паролем Basic-авторизации ([`crate::auth`]): другого способа
+//! передать его у файлового менеджера нет. Обычно же шару монтируют через
+//! `xr-share mount` ([`crate::mount`]), который подставляет токен сам и ходит к
+//! агенту напрямую или через relay.
+//!
+//! Класс 1, без блокировок: `LOCK`/`UNLOCK`, `COPY` и `PROPPATCH` не
+//! поддерживаются. davfs2, gvfs/KIO и rclone пишут и без них; Finder без
+//! класса 2 монтирует шару только на чтение.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::extract::{Path as AxPath, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use xr_proto::share::{SCOPE_READ, SCOPE_WRITE};
+
+use crate::manifest::{walk_share, HashCache, RESERVED_PREFIX};
+use crate::safepath::resolve_within;
+use crate::server::{self, AgentState};
+use crate::trash::Reason;
+
+/// Методы ручки: отдаются в `Allow` и на `OPTIONS`.
+const ALLOW: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE, MKCOL, MOVE";
+
+/// Что в `href` остаётся как есть: незарезервированные символы RFC 3986.
+const HREF: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+type DavResult = Result<Response, (StatusCode, &'static str)>;
+
+/// Корень шары, `/dav/{share_id}` и `/dav/{share_id}/`.
+pub(crate) async fn dav_root(
+    State(state): State<Arc<AgentState>>,
+    AxPath(share_id): AxPath<String>,
+    req: Request,
+) -> Response {
+    dispatch(&state, &share_id, "", req).await
+}
+
+/// Всё, что глубже корня шары.
+pub(crate) async fn dav_path(
+    State(state): State<Arc<AgentState>>,
+    AxPath((share_id, rel)): AxPath<(String, String)>,
+    req: Request,
+) -> Response {
+    dispatch(&state, &share_id, &rel, req).await
+}
+
+async fn dispatch(state: &Arc<AgentState>, share_id: &str, rel: &str, req: Request) -> Response {
+    let method = req.method().as_str().to_string();
+    let result = match method.as_str() {
+        "OPTIONS" => Ok(options()),
+        "PROPFIND" => propfind(state, share_id, rel, req.headers(), req.uri()).await,
+        "GET" | "HEAD" => Ok(server::file_response(state, share_id, rel, req).await),
+        "PUT" => server::handle_put(state, share_id, rel, req).await,
+        "DELETE" => delete(state, share_id, rel, req).await,
+        "MKCOL" => mkcol(state, share_id, rel, req.headers(), req.uri()).await,
+        "MOVE" => move_to(state, share_id, rel, req.headers(), req.uri()).await,
+        _ => Err((StatusCode::METHOD_NOT_ALLOWED, "method not allowed")),
+    };
+    let mut resp = result.unwrap_or_else(|e| e.into_response());
+    // Без приглашения к Basic файловый менеджер не спросит пароль вовсе.
+    match resp.status() {
+        StatusCode::UNAUTHORIZED => {
+            resp.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"xr-share\", charset=\"UTF-8\""),
+            );
+        }
+        StatusCode::METHOD_NOT_ALLOWED => {
+            resp.headers_mut().insert(header::ALLOW, HeaderValue::from_static(ALLOW));
+        }
+        _ => {}
+    }
+    tracing::debug!("DAV {method} share={share_id} rel={rel} -> {}", resp.status().as_u16());
+    resp
+}
+
+/// `OPTIONS` без токена: клиенты спрашивают его до авторизации, а ответ не
+/// говорит ничего о шарах.
+fn options() -> Response {
+    (
+        StatusCode::OK,
+        [("dav", "1"), (header::ALLOW.as_str(), ALLOW), ("ms-author-via", "DAV")],
+    )
+        .into_response()
+}
+
+/// Гейты в порядке остальных ручек: шара есть (`404`), для записи writable
+/// (`403`), токен привязан к шаре и несёт скоуп (`401`/`403`). Возвращает
+/// корень шары и то, файловая ли она.
+fn gates(
+    state: &AgentState,
+    share_id: &str,
+    scope: &str,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> Result<(PathBuf, bool), (StatusCode, &'static str)> {
+    let shares = state.snapshot();
+    let share = shares
+        .get(share_id)
+        .ok_or((StatusCode::NOT_FOUND, "no such share"))?;
+    if scope == SCOPE_WRITE && !share.writable {
+        return Err((StatusCode::FORBIDDEN, "share is read-only"));
+    }
+    server::check_token_parts(state, share_id, scope, headers, uri)?;
+    Ok((share.path.clone(), share.is_file))
+}
+
+// ── PROPFIND ───────────────────────────────────────────────────────
+
+/// Свойства одного ресурса в ответе `PROPFIND`.
+struct Node {
+    /// Путь в шаре, пустой у корня.
+    rel: String,
+    dir: bool,
+    size: u64,
+    modified: SystemTime,
+    /// SHA-256 из кеша хешей, если он там уже есть: ради листинга не хешируем.
+    etag: Option<String>,
+}
+
+impl Node {
+    fn of(path: &Path, rel: String, cache: &HashCache) -> Option<Node> {
+        let md = std::fs::metadata(path).ok()?;
+        let dir = md.is_dir();
+        Some(Node {
+            rel,
+            dir,
+            size: if dir { 0 } else { md.len() },
+            modified: md.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            etag: if dir { None } else { cache.known(path) },
+        })
+    }
+}
+
+/// `PROPFIND`: свойства ресурса и, кроме `Depth: 0`, его детей. `infinity`
+/// отвечается как `1`: RFC 4918 разрешает не отдавать дерево целиком, а
+/// клиенты и так обходят его по уровню.
+async fn propfind(
+    state: &Arc<AgentState>,
+    share_id: &str,
+    rel: &str,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> DavResult {
+    let (root, is_file) = gates(state, share_id, SCOPE_READ, headers, uri)?;
+    let deep = headers.get("depth").and_then(|v| v.to_str().ok()).map(str::trim) != Some("0");
+    let (st, share_id, rel) = (state.clone(), share_id.to_string(), rel.trim_matches('/').to_string());
+    let body = tokio::task::spawn_blocking(move || {
+        let nodes = if is_file {
+            file_share_nodes(&root, &rel, deep, &st.hash_cache)?
+        } else {
+            dir_share_nodes(&root, &rel, deep, &st.hash_cache)?
+        };
+        Ok::<_, (StatusCode, &'static str)>(multistatus(&share_id, &nodes))
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "propfind task failed"))??;
+    Ok((
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response())
+}
+
+fn dir_share_nodes(
+    root: &Path,
+    rel: &str,
+    deep: bool,
+    cache: &HashCache,
+) -> Result<Vec<Node>, (StatusCode, &'static str)> {
+    let target = resolve_within(root, rel).map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+    let me = Node::of(&target, rel.to_string(), cache).ok_or((StatusCode::NOT_FOUND, "no such file"))?;
+    let list = me.dir && deep;
+    let mut nodes = vec![me];
+    if !list {
+        return Ok(nodes);
+    }
+    let entries = std::fs::read_dir(&target).map_err(|e| server::io_status(&e))?;
+    let mut children = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Служебное `.xr-` и ссылки не видны, как и в манифесте.
+        if name.starts_with(RESERVED_PREFIX) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(t) if t.is_file() || t.is_dir() => {}
+            _ => continue,
+        }
+        let child = if rel.is_empty() { name } else { format!("{rel}/{name}") };
+        children.extend(Node::of(&entry.path(), child, cache));
+    }
+    children.sort_by(|a, b| a.rel.cmp(&b.rel));
+    nodes.extend(children);
+    Ok(nodes)
+}
+
+/// Файловая шара видна каталогом из одного файла.
+fn file_share_nodes(
+    path: &Path,
+    rel: &str,
+    deep: bool,
+    cache: &HashCache,
+) -> Result<Vec<Node>, (StatusCode, &'static str)> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let file = Node::of(path, name.clone(), cache).ok_or((StatusCode::NOT_FOUND, "no such file"))?;
+    if rel == name {
+        return Ok(vec![file]);
+    }
+    if !rel.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "no such file"));
+    }
+    let root = Node { rel: String::new(), dir: true, size: 0, modified: file.modified, etag: None };
+    Ok(if deep { vec![root, file] } else { vec![root] })
+}
+
+fn multistatus(share_id: &str, nodes: &[Node]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for n in nodes {
+        let _ = write!(
+            out,
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop>",
+            xml_escape(&href(share_id, &n.rel, n.dir))
+        );
+        if let Some(name) = n.rel.rsplit('/').next().filter(|s| !s.is_empty()) {
+            let _ = write!(out, "<D:displayname>{}</D:displayname>", xml_escape(name));
+        }
+        if n.dir {
+            out.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            let mime = mime_guess::from_path(&n.rel).first_or_octet_stream();
+            let _ = write!(
+                out,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+                 <D:getcontenttype>{}</D:getcontenttype>",
+                n.size,
+                xml_escape(mime.as_ref())
+            );
+        }
+        let _ = write!(out, "<D:getlastmodified>{}</D:getlastmodified>", httpdate::fmt_http_date(n.modified));
+        if let Some(sha) = &n.etag {
+            let _ = write!(out, "<D:getetag>\"{sha}\"</D:getetag>");
+        }
+        out.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    out.push_str("</D:multistatus>\n");
+    out
+}
+
+/// `href` ресурса: путь от корня сервера, по сегментам в процентной записи;
+/// у каталога со слэшем на конце.
+fn href(share_id: &str, rel: &str, dir: bool) -> String {
+    let mut h = format!("/dav/{}/", utf8_percent_encode(share_id, HREF));
+    if rel.is_empty() {
+        return h;
+    }
+    let segments: Vec<String> = rel.split('/').map(|s| utf8_percent_encode(s, HREF).to_string()).collect();
+    h.push_str(&segments.join("/"));
+    if dir {
+        h.push('/');
+    }
+    h
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// ── запись ─────────────────────────────────────────────────────────
+
+/// `DELETE`: файл уходит обычным путём записи агента, каталог разбирается
+/// здесь же.
+async fn delete(state: &Arc<AgentState>, share_id: &str, rel: &str, req: Request) -> DavResult {
+    let is_dir = state
+        .snapshot()
+        .get(share_id)
+        .filter(|s| !s.is_file)
+        .and_then(|s| resolve_within(&s.path, rel).ok())
+        .is_some_and(|p| p.is_dir());
+    if is_dir {
+        delete_dir(state, share_id, rel, req.headers(), req.uri()).await
+    } else {
+        server::handle_delete(state, share_id, rel, req).await
+    }
+}
+
+/// Каталог целиком. Файлы по одному уходят в корзину, как при одиночном
+/// `DELETE`; остаток дерева (пустые каталоги, служебное) удаляется разом.
+async fn delete_dir(
+    state: &Arc<AgentState>,
+    share_id: &str,
+    rel: &str,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> DavResult {
+    let (root, _) = gates(state, share_id, SCOPE_WRITE, headers, uri)?;
+    let target = resolve_within(&root, rel).map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+    if target == root {
+        return Err((StatusCode::FORBIDDEN, "share root"));
+    }
+    let walk = target.clone();
+    let files: Vec<PathBuf> = tokio::task::spawn_blocking(move || {
+        walk_share(&walk)
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "walk task failed"))?;
+    for file in &files {
+        server::set_aside(state, &root, file, Reason::Deleted).await?;
+        if let Some(key) = crate::meta::rel_key(&root, file) {
+            crate::meta::forget(&root, &key);
+        }
+    }
+    tokio::fs::remove_dir_all(&target).await.map_err(|e| server::io_status(&e))?;
+    state.versions.bump(share_id);
+    tracing::info!("DAV DELETE share={share_id} dir={rel} files={}", files.len());
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// `MKCOL`: новый каталог. Уже есть (`405`), нет родителя (`409`).
+async fn mkcol(
+    state: &Arc<AgentState>,
+    share_id: &str,
+    rel: &str,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> DavResult {
+    let (root, _) = gates(state, share_id, SCOPE_WRITE, headers, uri)?;
+    let target = resolve_within(&root, rel).map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+    if target.exists() {
+        return Err((StatusCode::METHOD_NOT_ALLOWED, "already exists"));
+    }
+    if !target.parent().is_some_and(Path::is_dir) {
+        return Err((StatusCode::CONFLICT, "parent missing"));
+    }
+    tokio::fs::create_dir(&target).await.map_err(|e| server::io_status(&e))?;
+    Ok(StatusCode::CREATED.into_response())
+}
+
+/// `MOVE` в пределах шары (переименование файлом менеджере). Файл поверх
+/// файла разрешён при `Overwrite: T` (по умолчанию), и заменённый уходит в
+/// корзину; каталог поверх чего-либо и что-либо поверх каталога отказываются
+/// (`409`): рекурсивная замена через корзину не стоит редкого случая.
+async fn move_to(
+    state: &Arc<AgentState>,
+    share_id: &str,
+    rel: &str,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> DavResult {
+    let (root, _) = gates(state, share_id, SCOPE_WRITE, headers, uri)?;
+    let from = resolve_within(&root, rel).map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+    let dest_rel = headers
+        .get("destination")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|d| destination_rel(d, share_id))
+        .ok_or((StatusCode::BAD_REQUEST, "bad destination"))?;
+    let to = resolve_within(&root, &dest_rel).map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+    if from == root || to == root || to == from {
+        return Err((StatusCode::FORBIDDEN, "bad destination"));
+    }
+    if to.starts_with(&from) {
+        return Err((StatusCode::CONFLICT, "destination inside source"));
+    }
+    let src = std::fs::metadata(&from).map_err(|_| (StatusCode::NOT_FOUND, "no such file"))?;
+    // Переименованием не обойти правила шары: `.txt` в `.exe` это тот же ввоз.
+    if src.is_file() && !server::share_policy(state, share_id).allows(&dest_rel) {
+        return Err(crate::quota::Refusal::Type.status());
+    }
+    let existed = to.exists();
+    if existed {
+        let overwrite = headers.get("overwrite").and_then(|v| v.to_str().ok()).map(str::trim);
+        if overwrite.is_some_and(|o| o.eq_ignore_ascii_case("f")) {
+            return Err((StatusCode::PRECONDITION_FAILED, "destination exists"));
+        }
+        if src.is_dir() || to.is_dir() {
+            return Err((StatusCode::CONFLICT, "destination exists"));
+        }
+    }
+    if !to.parent().is_some_and(Path::is_dir) {
+        return Err((StatusCode::CONFLICT, "parent missing"));
+    }
+
+    let sha = if src.is_file() { state.hash_cache.known(&from) } else { None };
+    if existed {
+        server::set_aside(state, &root, &to, Reason::Replaced).await?;
+    }
+    server::rename_replace(&from, &to).await.map_err(|e| server::io_status(&e))?;
+    // Файл переезжает вместе с хешем и происхождением: содержимое то же.
+    if src.is_file() {
+        if let (Some(sha), Ok(md)) = (sha, std::fs::metadata(&to)) {
+            state.hash_cache.seed(&to, md.len(), server::mtime_secs(&md), sha);
+        }
+        if let (Some(old), Some(new)) = (crate::meta::rel_key(&root, &from), crate::meta::rel_key(&root, &to)) {
+            let row = crate::meta::load(&root).remove(&old);
+            crate::meta::forget(&root, &old);
+            crate::meta::forget(&root, &new);
+            if let Some(row) = row {
+                crate::meta::record(&root, &[(new, row)]);
+            }
+        }
+    }
+    state.versions.bump(share_id);
+    tracing::info!("DAV MOVE share={share_id} {rel} -> {dest_rel}");
+    Ok(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED }.into_response())
+}
+
+/// Путь назначения `MOVE` внутри той же шары. `Destination` приходит
+/// абсолютным URL (так шлют почти все клиенты) или абсолютным путём; хост не
+/// сверяется: за `xr-share mount` он локальный, и значим только путь.
+fn destination_rel(dest: &str, share_id: &str) -> Option<String> {
+    let path = match dest.find("://") {
+        Some(i) => {
+            let rest = &dest[i + 3..];
+            &rest[rest.find('/')?..]
+        }
+        None => dest,
+    };
+    let path = path.split(['?', '#']).next()?;
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let rest = decoded.strip_prefix("/dav/")?.strip_prefix(share_id)?.strip_prefix('/')?;
+    Some(rest.trim_end_matches('/').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request as HttpRequest;
+    use base64::Engine;
+    use ed25519_dalek::SigningKey;
+    use http_body_util::BodyExt;
+    use std::sync::RwLock;
+    use tower::ServiceExt;
+    use xr_proto::share::{sign_share_token, ShareToken};
+
+    use crate::server::{router, ShareRoot, SharesMap};
+
+    fn app(key: &SigningKey, dir: &Path, writable: bool) -> axum::Router {
+        let mut shares = SharesMap::new();
+        let root = ShareRoot { path: dir.canonicalize().unwrap(), is_file: false, writable, import: false, policy: Default::default() };
+        shares.insert("S".into(), root);
+        let cache = Arc::new(HashCache::new());
+        router(Arc::new(AgentState {
+            shares: RwLock::new(Arc::new(shares)),
+            hub_key: key.verifying_key(),
+            hash_cache: cache.clone(),
+            identity: None,
+            max_file_mb: None,
+            import: crate::import::ImportManager::new(None, cache),
+            expose: RwLock::new(Arc::new(Vec::new())),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: Some(crate::config::TrashConfig::default()),
+            webdav: true,
+            thumbs: None,
+            usage: Default::default(),
+        }))
+    }
+
+    fn blob(t: &ShareToken) -> String {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(t).unwrap())
+    }
+
+    /// Запрос с токеном паролем Basic, как его шлёт файловый менеджер.
+    fn dav(method: &str, uri: &str, tok: Option<&ShareToken>, headers: &[(&str, &str)], body: &[u8]) -> HttpRequest<Body> {
+        let mut b = HttpRequest::builder().method(method).uri(uri);
+        if let Some(t) = tok {
+            let creds = base64::engine::general_purpose::STANDARD.encode(format!("me:{}", blob(t)));
+            b = b.header("authorization", format!("Basic {creds}"));
+        }
+        for (k, v) in headers {
+            b = b.header(*k, *v);
+        }
+        b.body(Body::from(body.to_vec())).unwrap()
+    }
+
+    async fn text(r: Response) -> String {
+        String::from_utf8(r.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap()
+    }
+
+    fn scope_rw() -> String {
+        format!("{SCOPE_READ} {SCOPE_WRITE}")
+    }
+
+    #[tokio::test]
+    async fn propfind_lists_the_share_without_service_names() {
+        let key = SigningKey::from_bytes(&[61u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a b.txt"), b"hello").unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/c.txt"), b"x").unwrap();
+        std::fs::write(dir.path().join(".xr-meta.json"), b"{}").unwrap();
+        let app = app(&key, dir.path(), false);
+        let rtok = sign_share_token(&key, "S", SCOPE_READ, server::now_unix() + 1000);
+
+        // Без токена приглашение к Basic, иначе клиент не спросит пароль.
+        let r = app.clone().oneshot(dav("PROPFIND", "/dav/S/", None, &[], b"")).await.unwrap();
+        assert_eq!(r.status(), StatusCode::UNAUTHORIZED);
+        assert!(r.headers().get(header::WWW_AUTHENTICATE).is_some());
+
+        let r = app
+            .clone()
+            .oneshot(dav("PROPFIND", "/dav/S/", Some(&rtok), &[("depth", "1")], b""))
+            .await
+            .unwrap();
+        assert_eq!(r.status(), StatusCode::MULTI_STATUS);
+        let body = text(r).await;
+        for href in ["<D:href>/dav/S/</D:href>", "<D:href>/dav/S/a%20b.txt</D:href>", "<D:href>/dav/S/sub/</D:href>"] {
+            assert!(body.contains(href), "{href} в {body}");
+        }
+        assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+        assert!(!body.contains(".xr-"), "служебное не видно: {body}");
+        assert!(!body.contains("c.txt"), "глубина 1 не заходит в подкаталоги");
+
+        let r = app
+            .clone()
+            .oneshot(dav("PROPFIND", "/dav/S/sub", Some(&rtok), &[("depth", "0")], b""))
+            .await
+            .unwrap();
+        let body = text(r).await;
+        assert_eq!(body.matches("<D:response>").count(), 1);
+
+        // Читающий токен не пишет, а служебное не достать и по имени.
+        let r = app.clone().oneshot(dav("PUT", "/dav/S/new.txt", Some(&rtok), &[], b"x")).await.unwrap();
+        assert_eq!(r.status(), StatusCode::FORBIDDEN);
+        let r = app.oneshot(dav("GET", "/dav/S/.xr-meta.json", Some(&rtok), &[], b"")).await.unwrap();
+        assert_eq!(r.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn writes_map_onto_the_share_and_its_trash() {
+        let key = SigningKey::from_bytes(&[62u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&key, dir.path(), true);
+        let wtok = sign_share_token(&key, "S", &scope_rw(), server::now_unix() + 1000);
+        let send = |method: &str, uri: &str, headers: &[(&str, &str)], body: &[u8]| {
+            app.clone().oneshot(dav(method, uri, Some(&wtok), headers, body))
+        };
+
+        let r = send("OPTIONS", "/dav/S/", &[], b"").await.unwrap();
+        assert_eq!(r.headers().get("dav").unwrap(), "1");
+
+        assert_eq!(send("MKCOL", "/dav/S/docs", &[], b"").await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(send("MKCOL", "/dav/S/docs", &[], b"").await.unwrap().status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(send("MKCOL", "/dav/S/no/such", &[], b"").await.unwrap().status(), StatusCode::CONFLICT);
+        assert_eq!(send("PUT", "/dav/S/docs/a.txt", &[], b"one").await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(send("PUT", "/dav/S/docs/b.txt", &[], b"two").await.unwrap().status(), StatusCode::CREATED);
+        let r = send("GET", "/dav/S/docs/a.txt", &[], b"").await.unwrap();
+        assert_eq!(text(r).await, "one");
+
+        // Переименование с абсолютным URL в Destination, как шлют клиенты.
+        let dest = [("destination", "http://127.0.0.1:9/dav/S/docs/new%20name.txt")];
+        assert_eq!(send("MOVE", "/dav/S/docs/a.txt", &dest, b"").await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(std::fs::read(dir.path().join("docs/new name.txt")).unwrap(), b"one");
+        assert!(!dir.path().join("docs/a.txt").exists());
+
+        // Поверх файла: с Overwrite: F отказ, по умолчанию замена через корзину.
+        let dest = [("destination", "/dav/S/docs/new%20name.txt"), ("overwrite", "F")];
+        assert_eq!(send("MOVE", "/dav/S/docs/b.txt", &dest, b"").await.unwrap().status(), StatusCode::PRECONDITION_FAILED);
+        let dest = [("destination", "/dav/S/docs/new%20name.txt")];
+        assert_eq!(send("MOVE", "/dav/S/docs/b.txt", &dest, b"").await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read(dir.path().join("docs/new name.txt")).unwrap(), b"two");
+        let dest = [("destination", "/dav/S/../escape.txt")];
+        assert_eq!(send("MOVE", "/dav/S/docs/new%20name.txt", &dest, b"").await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        // Каталог целиком: файлы в корзине, дерева нет.
+        assert_eq!(send("DELETE", "/dav/S/docs", &[], b"").await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert!(!dir.path().join("docs").exists());
+        let kept: Vec<String> = crate::trash::list(dir.path()).unwrap().into_iter().map(|i| i.path).collect();
+        assert_eq!(kept.len(), 2, "заменённая при MOVE и удалённая с каталогом: {kept:?}");
+        assert!(kept.iter().all(|p| p == "docs/new name.txt"));
+    }
+
+    #[test]
+    fn destination_is_taken_from_url_or_path() {
+        assert_eq!(destination_rel("http://h:1/dav/S/a/b%20c/", "S").as_deref(), Some("a/b c"));
+        assert_eq!(destination_rel("/dav/S/x.txt?q=1", "S").as_deref(), Some("x.txt"));
+        assert_eq!(destination_rel("/dav/T/x.txt", "S"), None);
+        assert_eq!(destination_rel("/other/S/x.txt", "S"), None);
+    }
+}
@@ -0,0 +1,304 @@
+//! Дельты манифеста для больших шар.
+//!
+//! Полный листинг шары на полмиллиона фотографий весит мегабайты, и без
+//! дельт телефон скачивает и сравнивает его целиком после каждой правки.
+//! Агент помнит последний отданный листинг каждой шары и цепочку шагов
+//! между версиями ([`ManifestHistory`]), а клиент, у которого листинг уже
+//! есть, передаёт его версию (`?base=`) и получает подписанную
+//! [`ShareManifestDelta`]: добавленные и изменённые строки целиком плюс
+//! пропавшие пути.
+//!
+//! Версия здесь называет ровно один листинг. Если обход нашёл изменение
+//! раньше, чем его заметило слежение за корнем, у листинга ещё старая
+//! версия, и история сама поднимает [`ShareVersions`]: иначе два разных
+//! листинга ушли бы под одним номером, и дельта от него легла бы не на
+//! тот. Когда базы в истории нет (агент перезапускался, шаги вытеснены) или
+//! дельта вышла не меньше половины листинга, отдаётся полный листинг.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use xr_proto::share::{ShareManifest, ShareManifestDelta, ShareManifestEntry};
+
+use crate::server::SharesMap;
+use crate::watch::ShareVersions;
+
+/// Сколько строк шагов хранить на шару. Старые шаги вытесняются первыми:
+/// клиент с такой давней базой получит полный листинг, а память агента не
+/// растёт от шары, где файлы меняются весь день.
+const HISTORY_MAX_ROWS: usize = 50_000;
+
+/// Последние листинги и шаги между их версиями по `share_id`.
+#[derive(Default)]
+pub struct ManifestHistory {
+    inner: Mutex<HashMap<String, History>>,
+}
+
+struct History {
+    /// Версия последнего отданного листинга.
+    version: u64,
+    /// Отпечаток каждой его строки по пути. Для сверки хватает хеша строки, а
+    /// держать сами строки полумиллиона файлов агенту дорого.
+    rows: HashMap<String, u64>,
+    steps: VecDeque<Step>,
+    /// Строк во всех шагах вместе, для вытеснения.
+    step_rows: usize,
+}
+
+/// Переход листинга от версии `base` к `version`.
+struct Step {
+    base: u64,
+    version: u64,
+    changed: Vec<ShareManifestEntry>,
+    removed: Vec<String>,
+}
+
+impl ManifestHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, History>> {
+        self.inner.lock().expect("history lock poisoned")
+    }
+
+    /// Сверить только что построенный листинг с прошлым отданным. Проставляет
+    /// листингу версию, под которой его отдавать (`version` прочитана до
+    /// обхода и поднимается, если обход нашёл то, чего она не знает), и,
+    /// если клиент прислал `base`, возвращает дельту от неё, когда она есть.
+    pub fn observe(
+        &self,
+        share_id: &str,
+        listing: &mut ShareManifest,
+        version: u64,
+        versions: &ShareVersions,
+        base: Option<u64>,
+    ) -> Option<ShareManifestDelta> {
+        let mut map = self.lock();
+        let Some(h) = map.get_mut(share_id) else {
+            let rows = listing.entries.iter().map(|e| (e.path.clone(), row_print(e))).collect();
+            map.insert(
+                share_id.to_string(),
+                History { version, rows, steps: VecDeque::new(), step_rows: 0 },
+            );
+            listing.version = version;
+            return None;
+        };
+
+        let mut changed = Vec::new();
+        let mut kept = 0usize;
+        for e in &listing.entries {
+            match h.rows.get(&e.path) {
+                Some(&fp) if fp == row_print(e) => kept += 1,
+                Some(_) => {
+                    kept += 1;
+                    changed.push(e.clone());
+                }
+                None => changed.push(e.clone()),
+            }
+        }
+        // Пропавшие пути ищутся только когда они есть: на спокойной шаре
+        // каждая строка прошлого листинга нашлась в новом.
+        let removed: Vec<String> = if kept < h.rows.len() {
+            let now: HashSet<&str> = listing.entries.iter().map(|e| e.path.as_str()).collect();
+            let mut gone: Vec<String> = h.rows.keys().filter(|p| !now.contains(p.as_str())).cloned().collect();
+            gone.sort();
+            gone
+        } else {
+            Vec::new()
+        };
+
+        if changed.is_empty() && removed.is_empty() {
+            if version > h.version {
+                // Версию подняло изменение, которого в листинге не видно
+                // (файл записали и тут же вернули): пустой шаг, чтобы клиент
+                // с прошлой версией получил пустую дельту, а не весь листинг.
+                h.push(Step { base: h.version, version, changed, removed });
+            }
+        } else if version < h.version {
+            // Опоздавший обход начался до уже записанного шага и видел
+            // старое содержимое: в историю он не идёт, иначе откатил бы её
+            // строки и поднял версию на устаревшем листинге. Отдаётся под
+            // своей версией, ожидание с неё ответит сразу свежим.
+            listing.version = version;
+            return None;
+        } else {
+            let next = if version > h.version { version } else { versions.bump(share_id) };
+            for path in &removed {
+                h.rows.remove(path);
+            }
+            for e in &changed {
+                h.rows.insert(e.path.clone(), row_print(e));
+            }
+            h.push(Step { base: h.version, version: next, changed, removed });
+        }
+        listing.version = h.version;
+        base.and_then(|b| h.delta(b, listing.entries.len()))
+    }
+
+    /// Забыть историю снятых шар.
+    pub fn retain(&self, live: &SharesMap) {
+        self.lock().retain(|id, _| live.contains_key(id));
+    }
+}
+
+impl History {
+    fn push(&mut self, step: Step) {
+        self.version = step.version;
+        self.step_rows += step.changed.len() + step.removed.len();
+        self.steps.push_back(step);
+        while self.step_rows > HISTORY_MAX_ROWS {
+            let Some(old) = self.steps.pop_front() else { break };
+            self.step_rows -= old.changed.len() + old.removed.len();
+        }
+    }
+
+    /// Свернуть шаги от `base` до текущей версии в одну дельту. `None`, если
+    /// такой базы в истории нет или дельта не дешевле листинга из `full_rows`
+    /// строк.
+    fn delta(&self, base: u64, full_rows: usize) -> Option<ShareManifestDelta> {
+        let start = if base == self.version {
+            self.steps.len()
+        } else {
+            self.steps.iter().position(|s| s.base == base)?
+        };
+        // Поздний шаг перекрывает ранний: путь, изменённый и потом удалённый,
+        // уходит только удалённым.
+        let mut folded: BTreeMap<&str, Option<&ShareManifestEntry>> = BTreeMap::new();
+        for step in self.steps.range(start..) {
+            for e in &step.changed {
+                folded.insert(&e.path, Some(e));
+            }
+            for path in &step.removed {
+                folded.insert(path, None);
+            }
+        }
+        if folded.len() * 2 > full_rows && !folded.is_empty() {
+            return None;
+        }
+        let mut delta = ShareManifestDelta { base, version: self.version, ..Default::default() };
+        for (path, row) in folded {
+            match row {
+                Some(e) => delta.changed.push(e.clone()),
+                None => delta.removed.push(path.to_string()),
+            }
+        }
+        Some(delta)
+    }
+}
+
+fn row_print(e: &ShareManifestEntry) -> u64 {
+    let mut h = DefaultHasher::new();
+    e.hash(&mut h);
+    h.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(path: &str, sha: &str) -> ShareManifestEntry {
+        ShareManifestEntry { path: path.into(), size: 1, mtime: 0, sha256: sha.into(), meta: None, preview: None }
+    }
+
+    fn listing(rows: &[(&str, &str)]) -> ShareManifest {
+        ShareManifest { entries: rows.iter().map(|(p, s)| row(p, s)).collect(), version: 0 }
+    }
+
+    fn many(n: usize) -> Vec<(String, String)> {
+        (0..n).map(|i| (format!("f{i:03}"), "a".to_string())).collect()
+    }
+
+    fn listing_of(rows: &[(String, String)]) -> ShareManifest {
+        ShareManifest { entries: rows.iter().map(|(p, s)| row(p, s)).collect(), version: 0 }
+    }
+
+    #[test]
+    fn folds_steps_into_one_delta_from_any_remembered_base() {
+        let history = ManifestHistory::new();
+        let versions = ShareVersions::new();
+        let mut rows = many(10);
+
+        let mut m = listing_of(&rows);
+        assert!(history.observe("s", &mut m, 100, &versions, Some(1)).is_none());
+        assert_eq!(m.version, 100);
+
+        rows[1].1 = "b".into();
+        rows.push(("new".into(), "a".into()));
+        let mut m = listing_of(&rows);
+        history.observe("s", &mut m, 101, &versions, None);
+        assert_eq!(m.version, 101);
+
+        rows.retain(|(p, _)| p != "new" && p != "f002");
+        let mut m = listing_of(&rows);
+        let d = history.observe("s", &mut m, 102, &versions, Some(100)).unwrap();
+        assert_eq!((d.base, d.version), (100, 102));
+        assert_eq!(d.changed, vec![row("f001", "b")]);
+        // `new` появился и пропал внутри отрезка: клиенту он не мешает.
+        assert_eq!(d.removed, vec!["f002".to_string(), "new".to_string()]);
+
+        // От последнего шага и от текущей версии.
+        let mut m = listing_of(&rows);
+        let d = history.observe("s", &mut m, 102, &versions, Some(101)).unwrap();
+        assert_eq!(d.removed, vec!["f002".to_string(), "new".to_string()]);
+        assert!(d.changed.is_empty());
+        let mut m = listing_of(&rows);
+        let d = history.observe("s", &mut m, 102, &versions, Some(102)).unwrap();
+        assert!(d.changed.is_empty() && d.removed.is_empty());
+
+        // Незнакомая база (прошлая жизнь агента) это полный листинг.
+        let mut m = listing_of(&rows);
+        assert!(history.observe("s", &mut m, 102, &versions, Some(7)).is_none());
+    }
+
+    #[test]
+    fn a_change_the_version_missed_gets_a_version_of_its_own() {
+        // Обход увидел файл раньше слежения: тот же номер для другого
+        // листинга недопустим, версия поднимается, ждущие просыпаются.
+        let history = ManifestHistory::new();
+        let versions = ShareVersions::new();
+        let v0 = versions.current("s");
+        let mut m = listing(&[("a", "1")]);
+        history.observe("s", &mut m, v0, &versions, None);
+
+        let mut m = listing(&[("a", "1"), ("b", "1")]);
+        history.observe("s", &mut m, v0, &versions, None);
+        assert_eq!(m.version, v0 + 1);
+        assert_eq!(versions.current("s"), v0 + 1);
+
+        // Опоздавший обход с уже устаревшей версией, но тем же содержимым,
+        // отдаётся под последней версией и ничего не поднимает.
+        let mut m = listing(&[("a", "1"), ("b", "1")]);
+        history.observe("s", &mut m, v0, &versions, None);
+        assert_eq!(m.version, v0 + 1);
+        assert_eq!(versions.current("s"), v0 + 1);
+
+        // А с другим, старым содержимым он истории не трогает.
+        let mut m = listing(&[("a", "1")]);
+        assert!(history.observe("s", &mut m, v0, &versions, Some(v0)).is_none());
+        assert_eq!(m.version, v0);
+        assert_eq!(versions.current("s"), v0 + 1);
+        let mut m = listing(&[("a", "1"), ("b", "1")]);
+        let d = history.observe("s", &mut m, v0 + 1, &versions, Some(v0)).unwrap();
+        assert_eq!(m.version, v0 + 1);
+        assert_eq!(d.changed, vec![row("b", "1")]);
+    }
+
+    #[test]
+    fn a_delta_as_big_as_the_listing_and_evicted_bases_fall_back_to_full() {
+        let history = ManifestHistory::new();
+        let versions = ShareVersions::new();
+        let mut m = listing(&[("a", "1"), ("b", "1"), ("c", "1")]);
+        history.observe("s", &mut m, 1, &versions, None);
+        let mut m = listing(&[("a", "2"), ("b", "2"), ("c", "1")]);
+        assert!(history.observe("s", &mut m, 2, &versions, Some(1)).is_none());
+
+        // Один огромный шаг вытесняет историю целиком.
+        let mut big = listing_of(&many(HISTORY_MAX_ROWS + 10));
+        history.observe("s", &mut big, 3, &versions, None);
+        let mut big = listing_of(&many(HISTORY_MAX_ROWS + 10));
+        assert!(history.observe("s", &mut big, 3, &versions, Some(2)).is_none());
+    }
+}
@@ -1,4 +1,5 @@
-//! Экспорт локального HTTP-сервиса наружу (LLD-38, фаза 1).
+//! Экспорт локального сервиса наружу (LLD-38, фаза 1): HTTP для браузерного
+//! входа и сырой TCP для `xr-share connect`.
 //!
 //! Публикация это «имя, агент, локальный адрес». Имя и агент живут в хабе, имя
 //! же служит поддоменом браузерного входа, а локальный адрес не уезжает с
@@ -17,6 +18,12 @@
 //! в тот самый обработчик, что и реверс-стрим, подставляя мандат вместо
 //! будущего `xr-web`. Он позволяет проверить весь путь до сервиса без VPS,
 //! обычным curl, и он же показывает отказ гейта, если мандат не подставлять.
+//!
+//! TCP-публикация (SSH, игровой сервер, база) идёт через тот же гейт: клиент
+//! `connect` просит у агента апгрейд [`TCP_UPGRADE`] с заголовком публикации
+//! и мандатом, агент сверяет мандат и сам выбирает апстрим по имени из
+//! конфига, а после `101` гоняет байты. Поэтому и здесь удалённая сторона
+//! апстрим не выбирает.
 
 use std::path::Path;
 use std::sync::Arc;
@@ -30,7 +37,8 @@ use axum::response::{IntoResponse, Response};
 use base64::Engine;
 use ed25519_dalek::VerifyingKey;
 use xr_proto::share::{
-    valid_publication_name, verify_expose_token, ExposeToken, EXPOSE_HEADER, FORWARDED_AUTH_HEADER,
+    valid_publication_name, verify_expose_token, ExposeKind, ExposeToken, EXPOSE_HEADER,
+    FORWARDED_AUTH_HEADER, TCP_UPGRADE,
 };
 
 use crate::config::{AgentConfig, ExposeEntry};
@@ -89,7 +97,8 @@ pub fn requested_publication(headers: &HeaderMap) -> Option<String> {
     valid_publication_name(raw).then(|| raw.to_string())
 }
 
-/// Обслужить запрос к публикации: мандат, поиск апстрима, проксирование.
+/// Обслужить запрос к публикации: мандат, поиск апстрима, проксирование или
+/// туннель.
 pub async fn serve(gate: &ExposeGate, name: &str, req: Request) -> Response {
     if let Err(resp) = check_mandate(gate, name, req.headers()) {
         return resp;
@@ -105,7 +114,63 @@ pub async fn serve(gate: &ExposeGate, name: &str, req: Request) -> Response {
             .into_response();
     };
     let upstream = entry.upstream.clone();
-    proxy(&upstream, req).await
+    match entry.kind {
+        ExposeKind::Http => proxy(&upstream, req).await,
+        ExposeKind::Tcp => tunnel(name, &upstream, req).await,
+    }
+}
+
+/// Туннель TCP-публикации: апгрейд [`TCP_UPGRADE`] и дальше байты в обе
+/// стороны до апстрима. Соединение с апстримом поднимается до ответа `101`,
+/// поэтому мёртвый сервис даёт внятный `502`, а не туннель, закрытый сразу
+/// после открытия. Обычный HTTP-запрос (браузерный вход) сюда не годится:
+/// сервис за публикацией говорит не по HTTP.
+async fn tunnel(name: &str, upstream: &str, req: Request) -> Response {
+    let (mut parts, _body) = req.into_parts();
+    let consumer = match upgrade_proto(&parts.headers) {
+        Some(proto) if proto.eq_ignore_ascii_case(TCP_UPGRADE) => {
+            parts.extensions.remove::<hyper::upgrade::OnUpgrade>()
+        }
+        _ => None,
+    };
+    let Some(consumer) = consumer else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("публикация {name} это TCP-сервис: её открывает `xr-share connect {name}`"),
+        )
+            .into_response();
+    };
+    let mut stream = match tokio::time::timeout(
+        UPSTREAM_CONNECT_TIMEOUT,
+        tokio::net::TcpStream::connect(upstream),
+    )
+    .await
+    {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => return upstream_down(upstream, &e.to_string()),
+        Err(_) => return upstream_down(upstream, "таймаут соединения"),
+    };
+    tracing::info!("публикация {name}: туннель к {upstream}");
+    let addr = upstream.to_string();
+    tokio::spawn(async move {
+        match consumer.await {
+            Ok(io) => {
+                let mut io = hyper_util::rt::TokioIo::new(io);
+                if let Err(e) = tokio::io::copy_bidirectional(&mut io, &mut stream).await {
+                    tracing::debug!("туннель к {addr} закрылся: {e}");
+                }
+            }
+            Err(e) => tracing::warn!("туннель к {addr} не переключился: {e}"),
+        }
+    });
+    (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::UPGRADE, HeaderValue::from_static(TCP_UPGRADE)),
+            (header::CONNECTION, HeaderValue::from_static("upgrade")),
+        ],
+    )
+        .into_response()
 }
 
 /// Проверить мандат публикации офлайн. Мандата нет, он чужой, протух или
@@ -387,6 +452,10 @@ pub enum ExposeCommand {
         /// указывать, если запись в конфиге уже есть.
         #[arg(long)]
         upstream: Option<String>,
+        /// Сырой TCP вместо HTTP (SSH, игровой сервер, база): в браузере
+        /// такая публикация не открывается, до неё ходят `xr-share connect`.
+        #[arg(long)]
+        tcp: bool,
     },
     /// Снять публикацию с хаба и из конфига.
     Rm {
@@ -413,7 +482,10 @@ pub enum ExposeCommand {
 /// Точка входа команды `expose`.
 pub fn run(config_path: &Path, cmd: ExposeCommand) -> Result<()> {
     match cmd {
-        ExposeCommand::Add { name, upstream } => add(config_path, &name, upstream.as_deref()),
+        ExposeCommand::Add { name, upstream, tcp } => {
+            let kind = if tcp { ExposeKind::Tcp } else { ExposeKind::Http };
+            add(config_path, &name, upstream.as_deref(), kind)
+        }
         ExposeCommand::Rm { name } => rm(config_path, &name),
         ExposeCommand::Ls => ls(config_path),
         ExposeCommand::Open { name, port, without_mandate } => {
@@ -435,7 +507,7 @@ fn hub_and_credential(cfg: &AgentConfig) -> Result<(String, String)> {
     Ok((hub.trim_end_matches('/').to_string(), cred))
 }
 
-fn add(config_path: &Path, name: &str, upstream: Option<&str>) -> Result<()> {
+fn add(config_path: &Path, name: &str, upstream: Option<&str>, kind: ExposeKind) -> Result<()> {
     let mut cfg = crate::cli::read_config(config_path)?;
     let (hub, cred) = hub_and_credential(&cfg)?;
     if !valid_publication_name(name) {
@@ -450,20 +522,26 @@ fn add(config_path: &Path, name: &str, upstream: Option<&str>) -> Result<()> {
         (None, None) => bail!("для новой публикации нужен --upstream (например 127.0.0.1:8765)"),
     };
 
-    let body = serde_json::json!({ "credential": cred, "name": name });
+    let body = serde_json::json!({ "credential": cred, "name": name, "kind": kind });
     let resp = crate::cli::hub_post(&format!("{hub}/api/v1/expose/add"), &body)
         .context("регистрация публикации в хабе")?;
     let agent = crate::cli::str_field(&resp, "agent_pubkey")?;
 
     match cfg.exposes.iter_mut().find(|e| e.name == name) {
-        Some(e) => e.upstream = upstream.clone(),
-        None => cfg.exposes.push(ExposeEntry { name: name.to_string(), upstream: upstream.clone() }),
+        Some(e) => {
+            e.upstream = upstream.clone();
+            e.kind = kind;
+        }
+        None => cfg.exposes.push(ExposeEntry { name: name.to_string(), upstream: upstream.clone(), kind }),
     }
     cfg.validate_expose()?;
     crate::cli::write_config(config_path, &cfg)?;
 
     println!("Публикация {name} заведена: {upstream} на агенте {}", short(&agent));
-    println!("Проверить без VPS: xr-share expose open --name {name}");
+    match kind {
+        ExposeKind::Http => println!("Проверить без VPS: xr-share expose open --name {name}"),
+        ExposeKind::Tcp => println!("Открыть с другой машины этого хаба: xr-share connect {name}"),
+    }
     Ok(())
 }
 
@@ -493,6 +571,9 @@ fn ls(config_path: &Path) -> Result<()> {
     for row in &rows {
         let name = row.get("name").and_then(|v| v.as_str()).unwrap_or("?");
         match cfg.exposes.iter().find(|e| e.name == name) {
+            Some(e) if e.kind == ExposeKind::Tcp => {
+                println!("{name:<20} {:<24} обслуживается, TCP", e.upstream)
+            }
             Some(e) => println!("{name:<20} {:<24} обслуживается", e.upstream),
             // Запись в хабе без записи в конфиге не обслуживается вовсе, и это
             // видно тут, а не по 404 из браузера.
@@ -516,6 +597,9 @@ fn open(config_path: &Path, name: &str, port: u16, with_mandate: bool) -> Result
         .find(|e| e.name == name)
         .with_context(|| format!("в конфиге нет публикации {name}: заведи её `expose add`"))?
         .clone();
+    if entry.kind == ExposeKind::Tcp {
+        bail!("публикация {name} это TCP: харнесс говорит по HTTP, открой её `xr-share connect {name}`");
+    }
     let gate = gate_from_config(&cfg, config_path)?;
     if gate.agent_pubkey.is_none() {
         bail!("у агента нет identity-ключа, мандат сверять нечем - переустанови `xr-share install`");
@@ -550,6 +634,198 @@ fn open(config_path: &Path, name: &str, port: u16, with_mandate: bool) -> Result
     })
 }
 
+// Команда `xr-share connect`.
+
+/// Аргументы `connect`: к какой TCP-публикации, на каком локальном порту и
+/// с чем идти к её политике входа, если машина не своя.
+#[cfg(feature = "relay")]
+#[derive(clap::Args)]
+pub struct ConnectArgs {
+    /// Имя TCP-публикации.
+    pub publication: String,
+    /// Локальный порт на 127.0.0.1; 0 (по умолчанию) это любой свободный.
+    #[arg(long, default_value_t = 0)]
+    pub port: u16,
+    /// Учётка хаба, если политика публикации пускает по учёткам. Пароль
+    /// берётся из переменной окружения XR_SHARE_CONNECT_PASSWORD, как и
+    /// пароль публикации: в командной строке его видно всем в списке процессов.
+    #[arg(long, default_value = "")]
+    pub user: String,
+    /// Код инвайта, если политика публикации пускает по инвайтам.
+    #[arg(long, default_value = "")]
+    pub invite: String,
+}
+
+/// Откуда `connect` берёт пароль для политики публикации.
+#[cfg(feature = "relay")]
+const CONNECT_PASSWORD_ENV: &str = "XR_SHARE_CONNECT_PASSWORD";
+
+/// Запас до срока маршрута, с которым `connect` берёт у хаба свежий: туннель,
+/// открытый на последней минуте мандата, агент ещё пустит, но следующий уже нет.
+#[cfg(feature = "relay")]
+const ROUTE_REFRESH_MARGIN_SECS: u64 = 60;
+
+/// Откуда `connect` берёт маршрут: у хаба по мандату агента, а в тестах прямо
+/// из рук.
+#[cfg(feature = "relay")]
+pub type RouteSource = Arc<dyn Fn() -> Result<xr_proto::share::TcpRoute> + Send + Sync>;
+
+/// Живой маршрут `connect`: loopback-форвардер до агента через relay и
+/// pinned-TLS поверх него. Перестраивается целиком, когда маршрут подходит к
+/// сроку: relay-токен форвардера и мандат живут одинаково.
+#[cfg(feature = "relay")]
+struct TcpLink {
+    route: xr_proto::share::TcpRoute,
+    forwarder: xr_proto::relay_client::LoopbackForwarder,
+    tls: Arc<tokio_rustls::rustls::ClientConfig>,
+}
+
+#[cfg(feature = "relay")]
+impl TcpLink {
+    async fn build(route: xr_proto::share::TcpRoute) -> Result<Self> {
+        let endpoint = xr_proto::relay_client::RelayEndpoint::from_grant(&route.relay)
+            .map_err(|e| anyhow::anyhow!("relay в маршруте: {e}"))?;
+        let forwarder = xr_proto::relay_client::LoopbackForwarder::spawn(Arc::new(endpoint))
+            .await
+            .context("loopback-форвардер до relay")?;
+        let tls = xr_proto::relay_tls::pinned_client_config(&route.agent_pubkey)
+            .map_err(|e| anyhow::anyhow!("ключ агента в маршруте: {e}"))?;
+        Ok(Self { route, forwarder, tls: Arc::new(tls) })
+    }
+
+    /// Один туннель: TCP до форвардера, pinned-TLS до агента и апгрейд
+    /// [`TCP_UPGRADE`] с мандатом. Отказ агента приходит его же текстом.
+    async fn open(&self) -> Result<hyper::upgrade::Upgraded> {
+        let name = &self.route.publication;
+        let tcp = tokio::net::TcpStream::connect(self.forwarder.local_addr())
+            .await
+            .context("loopback-форвардер")?;
+        let sni = tokio_rustls::rustls::pki_types::ServerName::try_from("xr-share-agent")
+            .expect("литеральное имя SNI годится");
+        let tls = match tokio_rustls::TlsConnector::from(self.tls.clone()).connect(sni, tcp).await {
+            Ok(tls) => tls,
+            Err(_) if self.forwarder.agent_offline() => {
+                bail!("машина публикации {name} сейчас не на связи с relay")
+            }
+            Err(e) => bail!("pinned TLS до агента: {e}"),
+        };
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(tls))
+                .await
+                .context("HTTP до агента")?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.with_upgrades().await {
+                tracing::debug!("соединение с агентом закрылось: {e}");
+            }
+        });
+        let req = axum::http::Request::builder()
+            .uri("/")
+            .header(header::HOST, name.as_str())
+            .header(EXPOSE_HEADER, name.as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.route.mandate))
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, TCP_UPGRADE)
+            .body(http_body_util::Empty::<hyper::body::Bytes>::new())
+            .context("запрос туннеля")?;
+        let mut resp = sender.send_request(req).await.context("запрос туннеля до агента")?;
+        if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+            use http_body_util::BodyExt;
+            let status = resp.status();
+            let text = match resp.into_body().collect().await {
+                Ok(b) => String::from_utf8_lossy(&b.to_bytes()).into_owned(),
+                Err(_) => String::new(),
+            };
+            bail!("агент не открыл туннель (HTTP {status}): {}", text.trim());
+        }
+        hyper::upgrade::on(&mut resp).await.context("переключение туннеля")
+    }
+}
+
+/// Слушать `listener` и каждое принятое соединение вести туннелем к
+/// публикации. Маршрут берётся у `source` при старте и заново, когда он
+/// подходит к сроку; отказ одного туннеля закрывает только его соединение.
+#[cfg(feature = "relay")]
+pub async fn serve_connect(listener: tokio::net::TcpListener, source: RouteSource) -> Result<()> {
+    let fetch = |source: RouteSource| async move {
+        tokio::task::spawn_blocking(move || source())
+            .await
+            .context("запрос маршрута")?
+    };
+    let first = TcpLink::build(fetch(source.clone()).await?).await?;
+    let link = Arc::new(tokio::sync::Mutex::new(Arc::new(first)));
+    loop {
+        let (mut sock, peer) = listener.accept().await.context("приём соединения")?;
+        let link = link.clone();
+        let source = source.clone();
+        tokio::spawn(async move {
+            let current = {
+                let mut guard = link.lock().await;
+                if guard.route.exp.saturating_sub(ROUTE_REFRESH_MARGIN_SECS) <= now_unix() {
+                    match fetch(source).await {
+                        Ok(route) => match TcpLink::build(route).await {
+                            Ok(fresh) => *guard = Arc::new(fresh),
+                            Err(e) => tracing::warn!("маршрут не перестроился: {e:#}"),
+                        },
+                        Err(e) => tracing::warn!("свежий маршрут не получен: {e:#}"),
+                    }
+                }
+                guard.clone()
+            };
+            match current.open().await {
+                Ok(tunnel) => {
+                    tracing::info!("{peer}: туннель к {} открыт", current.route.publication);
+                    let mut tunnel = hyper_util::rt::TokioIo::new(tunnel);
+                    if let Err(e) = tokio::io::copy_bidirectional(&mut sock, &mut tunnel).await {
+                        tracing::debug!("{peer}: туннель закрылся: {e}");
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("{peer}: {e:#}");
+                    eprintln!("Туннель не открылся: {e:#}");
+                }
+            }
+        });
+    }
+}
+
+/// `xr-share connect <публикация>`: маршрут у хаба по мандату этой машины и
+/// локальный порт, за которым стоит сервис публикации.
+#[cfg(feature = "relay")]
+pub fn connect(config_path: &Path, args: ConnectArgs) -> Result<()> {
+    let cfg = crate::cli::read_config(config_path)?;
+    let (hub, cred) = hub_and_credential(&cfg)?;
+    let name = args.publication.clone();
+    if !valid_publication_name(&name) {
+        bail!("имя публикации это DNS-метка: строчные буквы, цифры и дефис");
+    }
+    let url = format!("{hub}/api/v1/expose/{name}/connect");
+    let body = serde_json::json!({
+        "credential": cred,
+        "username": args.user,
+        "password": std::env::var(CONNECT_PASSWORD_ENV).unwrap_or_default(),
+        "invite": args.invite,
+    });
+    let source: RouteSource = Arc::new(move || {
+        let resp = crate::cli::hub_post(&url, &body).context("маршрут публикации у хаба")?;
+        serde_json::from_value(resp).context("разбор маршрута публикации")
+    });
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("рантайм для туннеля")?;
+    rt.block_on(async move {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", args.port))
+            .await
+            .with_context(|| format!("не занять порт {} на 127.0.0.1", args.port))?;
+        let local = listener.local_addr()?;
+        println!("{local}");
+        println!("Публикация {name}: соединения на этот адрес уходят туннелем к её сервису");
+        println!("Остановить: Ctrl-C");
+        serve_connect(listener, source).await
+    })
+}
+
 fn hub_get(url: &str, credential: &str) -> Result<serde_json::Value> {
     hub_call(ureq::get(url), credential)
 }
@@ -614,6 +890,7 @@ mod tests {
             publications: Arc::new(vec![ExposeEntry {
                 name: "dash".into(),
                 upstream: upstream.into(),
+                kind: ExposeKind::Http,
             }]),
         }
     }
@@ -797,6 +1074,45 @@ mod tests {
         assert!(body_text(resp).await.contains("127.0.0.1:1"));
     }
 
+    fn tcp_gate(upstream: &str) -> ExposeGate {
+        ExposeGate {
+            publications: Arc::new(vec![ExposeEntry {
+                name: "dash".into(),
+                upstream: upstream.into(),
+                kind: ExposeKind::Tcp,
+            }]),
+            ..gate(upstream)
+        }
+    }
+
+    fn tcp_request(auth: Option<&str>) -> Request {
+        let mut req = request(auth);
+        let h = req.headers_mut();
+        h.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        h.insert(header::UPGRADE, HeaderValue::from_static(TCP_UPGRADE));
+        req
+    }
+
+    #[tokio::test]
+    async fn tcp_publication_is_not_a_web_page() {
+        // Обычный запрос с валидным мандатом к TCP-публикации: 400 с
+        // подсказкой про connect, а не попытка говорить с SSH по HTTP.
+        let g = tcp_gate("127.0.0.1:1");
+        let resp = serve(&g, "dash", request(Some(&mandate("dash")))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(body_text(resp).await.contains("xr-share connect dash"));
+    }
+
+    #[tokio::test]
+    async fn tcp_tunnel_requires_mandate() {
+        let g = tcp_gate("127.0.0.1:1");
+        let resp = serve(&g, "dash", tcp_request(None)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let relay = blob(&sign_relay_token(&hub(), "tcp:dash", &agent_pk(), now_unix() + 3600));
+        let resp = serve(&g, "dash", tcp_request(Some(&relay))).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
     #[test]
     fn requested_publication_takes_only_labels() {
         let mut h = HeaderMap::new();
@@ -101,6 +101,9 @@ pub struct JobSpec {
     pub max_file_bytes: Option<u64>,
     /// `auto` | `none` from the config, resolved to a wrapper in [`sandbox_wrap`].
     pub sandbox: String,
+    /// The share's write policy (see [`crate::quota`]): the disk reserve is
+    /// watched while the plugin runs, types and quota at publication.
+    pub policy: crate::config::SharePolicy,
 }
 
 struct Job {
@@ -384,7 +387,8 @@ impl ImportManager {
 
         // Wait with a watchdog: kill on the lifetime cap, on the job dir
         // outgrowing max_total_mb (checked every few seconds, LLD-29 п. 2.7),
-        // or on cancellation. Cancellation is detected by the job's absence
+        // on the download eating into the share's disk reserve, or on
+        // cancellation. Cancellation is detected by the job's absence
         // from the table: `cancel` kills the process group itself, but only
         // this task holds the child handle, so the tick is what makes a cancel
         // land in the spawn window (pid not registered yet) and on Windows
@@ -416,6 +420,15 @@ impl ImportManager {
                                 ));
                             }
                         }
+                        if let (None, Some(reserve)) = (&kill_reason, spec.policy.reserve_free_mb) {
+                            let dir = job_dir.to_path_buf();
+                            let free = tokio::task::spawn_blocking(move || crate::quota::free_space(&dir))
+                                .await
+                                .unwrap_or(None);
+                            if free.is_some_and(|f| f < reserve.saturating_mul(1024 * 1024)) {
+                                kill_reason = Some(crate::quota::Refusal::Disk.describe().to_string());
+                            }
+                        }
                     }
                     if cancelled || kill_reason.is_some() {
                         if let Some(pid) = pid {
@@ -454,6 +467,7 @@ impl ImportManager {
             max_file_bytes: spec.max_file_bytes,
             share_root: spec.share_root.clone(),
             url: spec.url.clone(),
+            policy: spec.policy.clone(),
         };
         let cache = self.cache.clone();
         tokio::task::spawn_blocking(move || publish_files(&publish, &cache))
@@ -614,12 +628,15 @@ struct PublishSpec {
     /// The link the job was started with. Every imported file gets at least
     /// this, whatever the plugin knows.
     url: String,
+    policy: crate::config::SharePolicy,
 }
 
 /// Publish the job dir's output into the share: top-level regular files only,
 /// hidden names skipped (that also covers `.xr-*`), each checked against
-/// `max_file_mb`, hashed, fsync'd and renamed over the target, hash seeded
-/// (LLD-29 п. 2.7). A mid-way failure reports what did get published.
+/// `max_file_mb` and the share's policy, hashed, fsync'd and renamed over the
+/// target, hash seeded (LLD-29 п. 2.7). A mid-way failure reports what did get
+/// published. The disk reserve is not checked here: the bytes already sit in
+/// the job dir on the same disk, and the watchdog guarded it during the run.
 ///
 /// The origin of each file (XR-255) is written after its rename, so a job that
 /// dies half-way never leaves a row without a file. What the plugin left in its
@@ -648,11 +665,17 @@ fn publish_files(spec: &PublishSpec, cache: &HashCache) -> Result<Vec<String>, S
         .map(|raw| crate::meta::parse_plugin_output(&String::from_utf8_lossy(&raw)))
         .unwrap_or_default();
 
+    // Measured once; each published file then adds to it.
+    let mut usage = if crate::quota::counts_usage(&spec.policy) {
+        crate::quota::measure(&spec.share_root)
+    } else {
+        crate::quota::Usage::default()
+    };
     let mut published: Vec<String> = Vec::new();
     let mut origins: Vec<(String, xr_proto::share::FileMeta)> = Vec::new();
     for name in names {
         let src = spec.job_dir.join(&name);
-        let result = publish_one(&src, spec, &name, cache);
+        let result = admit(&src, spec, &name, &mut usage).and_then(|()| publish_one(&src, spec, &name, cache));
         if let Err(e) = result {
             crate::meta::record(&spec.share_root, &origins);
             let suffix = if published.is_empty() {
@@ -674,6 +697,27 @@ fn publish_files(spec: &PublishSpec, cache: &HashCache) -> Result<Vec<String>, S
     Ok(published)
 }
 
+/// The share's policy for one output file: its type, then the quota and file
+/// limit with the file it would replace discounted. Counts the file in `usage`
+/// when it passes.
+fn admit(src: &Path, spec: &PublishSpec, name: &str, usage: &mut crate::quota::Usage) -> Result<(), String> {
+    use crate::quota::Refusal;
+    if !spec.policy.allows(name) {
+        return Err(Refusal::Type.describe().into());
+    }
+    if !crate::quota::counts_usage(&spec.policy) {
+        return Ok(());
+    }
+    let len = std::fs::metadata(src).map_err(|e| format!("stat: {e}"))?.len();
+    let replaced = std::fs::metadata(spec.dest_dir.join(name)).ok().filter(|m| m.is_file()).map(|m| m.len());
+    crate::quota::room(&spec.policy, *usage, None, replaced)
+        .and_then(|room| room.check(len))
+        .map_err(|r| r.describe().to_string())?;
+    usage.bytes = usage.bytes.saturating_sub(replaced.unwrap_or(0)) + len;
+    usage.files += u64::from(replaced.is_none());
+    Ok(())
+}
+
 fn publish_one(src: &Path, spec: &PublishSpec, name: &str, cache: &HashCache) -> Result<(), String> {
     let meta = std::fs::metadata(src).map_err(|e| format!("stat: {e}"))?;
     if let Some(cap) = spec.max_file_bytes {
@@ -1025,6 +1069,7 @@ mod tests {
             max_total_bytes: None,
             max_file_bytes: None,
             sandbox: "none".into(),
+            policy: Default::default(),
         };
         let manager = |cfg: Option<ImportConfig>| ImportManager::new(cfg, Arc::new(HashCache::new()));
         let with_depth = |depth: usize| ImportConfig { queue_depth: depth, ..ImportConfig::reference() };
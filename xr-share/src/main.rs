@@ -3,20 +3,30 @@
 //! tokens offline. The hub only indexes the address; bytes flow straight from
 //! here to the consumer.
 
+mod archive;
 mod auth;
+mod chunks;
 mod cli;
 mod config;
+mod dav;
+mod delta;
 mod expose;
 mod import;
 mod manifest;
 mod meta;
+mod mount;
 mod pull;
 mod push;
+mod quota;
 #[cfg(feature = "relay")]
 mod relay;
 mod safepath;
 mod server;
 mod setup;
+mod sync;
+mod thumbs;
+mod trash;
+mod watch;
 
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
@@ -72,15 +82,32 @@ enum Commands {
     Push(push::PushArgs),
     /// Remove a file from a writable share on an invite (desktop).
     Rm(push::RmArgs),
+    /// List or restore what deletes and overwrites moved to a writable share's
+    /// trash (desktop).
+    Trash {
+        #[command(subcommand)]
+        command: push::TrashCommand,
+    },
+    /// Keep a local folder and a writable share on an invite in sync both ways
+    /// (desktop); edits made on both sides are kept as conflict copies.
+    Sync(sync::SyncArgs),
+    /// Mount a share on an invite as a local WebDAV folder (desktop): a proxy on
+    /// 127.0.0.1 that adds the grant's token and reaches the agent directly or
+    /// through its relay.
+    Mount(mount::MountArgs),
     /// Start a URL-import job on a writable share and poll it to completion
     /// (LLD-29): the agent downloads the page's content with its plugin.
     Import(cli::ImportArgs),
-    /// Открыть наружу локальный HTTP-сервис этой машины (LLD-38): завести
+    /// Открыть наружу локальный HTTP- или TCP-сервис этой машины (LLD-38): завести
     /// публикацию, снять её, посмотреть список или проверить путь локально.
     Expose {
         #[command(subcommand)]
         command: expose::ExposeCommand,
     },
+    /// Открыть локальный порт к TCP-публикации (SSH, игровой сервер, база)
+    /// через relay: соединения на него уходят туннелем к сервису публикации.
+    #[cfg(feature = "relay")]
+    Connect(expose::ConnectArgs),
     /// Manage OS autostart (systemd on Linux, Scheduled Task on Windows).
     Service {
         #[command(subcommand)]
@@ -120,8 +147,13 @@ fn main() -> Result<()> {
         Some(Commands::Pull(args)) => return pull::pull(args),
         Some(Commands::Push(args)) => return push::push(args),
         Some(Commands::Rm(args)) => return push::rm(args),
+        Some(Commands::Trash { command }) => return push::trash(command),
+        Some(Commands::Sync(args)) => return sync::sync(args),
+        Some(Commands::Mount(args)) => return mount::mount(args),
         Some(Commands::Import(args)) => return cli::import(args),
         Some(Commands::Expose { command }) => return expose::run(&config_path, command),
+        #[cfg(feature = "relay")]
+        Some(Commands::Connect(args)) => return expose::connect(&config_path, args),
         Some(Commands::Service { action }) => {
             return match action {
                 ServiceAction::Install => setup::service_install(&config_path),
@@ -187,6 +219,8 @@ async fn run(path: &Path) -> Result<()> {
     // То же для публикаций (LLD-38): кривое имя или апстрим валят старт, а не
     // первый запрос из браузера.
     cfg.validate_expose()?;
+    cfg.validate_thumbs()?;
+    cfg.validate_policies()?;
 
     // Resolve the configured shares. An empty set is allowed (the agent runs and
     // waits for `xr-share share <path>` to add one, picked up by hot-reload).
@@ -219,6 +253,16 @@ async fn run(path: &Path) -> Result<()> {
         max_file_mb: cfg.max_file_mb,
         import: import_mgr,
         expose: RwLock::new(Arc::new(cfg.exposes.clone())),
+        versions: watch::ShareVersions::new(),
+        history: delta::ManifestHistory::new(),
+        chunk_cache: chunks::ChunkCache::new(),
+        // Корзина включена и без блока [trash]; `keep_days = 0` её выключает.
+        trash: Some(cfg.trash.clone().unwrap_or_default()).filter(|t| t.enabled()),
+        webdav: cfg.webdav,
+        thumbs: Some(cfg.thumbs.clone().unwrap_or_default())
+            .filter(|t| t.enabled())
+            .map(|t| thumbs::Thumbs::new(t, path.parent().unwrap_or(Path::new(".")))),
+        usage: Arc::new(quota::UsageCache::new()),
     });
     // Публикации видно в логе на старте: молчание тут неотличимо от «наружу не
     // открыто ничего», а это разные вещи.
@@ -230,6 +274,11 @@ async fn run(path: &Path) -> Result<()> {
     spawn_config_watcher(state.clone(), path.to_path_buf());
     // Keep manifests cheap to serve even for large shares.
     spawn_manifest_warmer(state.clone());
+    // Версии в корзинах стареют и без записей в шары.
+    spawn_trash_sweeper(state.clone());
+    // Live change notifications: bump a share's manifest version the moment its
+    // tree changes, so `/{share_id}/manifest/wait` answers within seconds.
+    watch::spawn(state.clone(), cfg.watch.clone().unwrap_or_default());
     // Reverse tunnel to the relay for shares behind NAT (LLD-23), only in a build
     // with the `relay` feature and a configured relay + credential + identity.
     spawn_relay_uplink(&cfg, path, state.clone());
@@ -266,43 +315,51 @@ async fn run(path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Bring up the relay reverse tunnel (LLD-23) when the build supports it and the
-/// config has a relay, a credential and an identity. Missing pieces are logged,
-/// not fatal: the agent still serves its direct listener.
+/// Bring up the relay reverse tunnels (LLD-23) when the build supports it and the
+/// config has a relay, a credential and an identity. One tunnel per relay the
+/// hub placed this agent on (primary plus backup), so consumers have somewhere
+/// to fail over to. Missing pieces are logged, not fatal: the agent still
+/// serves its direct listener.
 #[cfg(feature = "relay")]
 fn spawn_relay_uplink(cfg: &AgentConfig, path: &Path, state: Arc<AgentState>) {
-    // Prefer the hub's live relay descriptor (XR-123), fall back to the config
-    // `[relay]`. Lets an updated agent pick up relay without a re-exchange or any
-    // hand-editing of the config.
-    let relay = cfg
-        .hub_url
-        .as_deref()
-        .and_then(relay::fetch_relay_descriptor)
-        .map(|d| config::RelayAgentConfig::from_descriptor(&d))
-        .or_else(|| cfg.relay.clone());
-    let Some(relay) = relay else { return };
-    if !relay::relay_obf_ok(&relay.obf) {
-        tracing::warn!("relay configured but obfuscation params are invalid; reverse tunnel disabled");
-        return;
-    }
     let (Some(cred), Ok(Some(identity))) = (cfg.agent_credential.clone(), cfg.identity_signing_key(path))
     else {
-        tracing::warn!(
-            "relay configured but no agent_credential/identity; reverse tunnel disabled \
-             (re-run `xr-share install --token`)"
-        );
+        if cfg.relay.is_some() {
+            tracing::warn!(
+                "relay configured but no agent_credential/identity; reverse tunnel disabled \
+                 (re-run `xr-share install --token`)"
+            );
+        }
         return;
     };
-    match relay::spawn(state, relay, cred, identity) {
-        Ok(()) => tracing::info!("relay reverse tunnel enabled"),
-        Err(e) => tracing::warn!("relay reverse tunnel disabled: {e:#}"),
+    // Prefer the hub's live placement (XR-123), fall back to the config relays.
+    // Lets an updated agent pick up relay without a re-exchange or any
+    // hand-editing of the config.
+    let agent_pubkey = setup::b64(identity.verifying_key().as_bytes());
+    let relays = cfg
+        .hub_url
+        .as_deref()
+        .map(|hub| relay::fetch_relay_descriptors(hub, &agent_pubkey))
+        .filter(|d| !d.is_empty())
+        .map(|d| d.iter().map(config::RelayAgentConfig::from_descriptor).collect())
+        .unwrap_or_else(|| cfg.relays());
+    for relay in relays {
+        let dial = relay.dial();
+        if !relay::relay_obf_ok(&relay.obf) {
+            tracing::warn!("relay {dial}: obfuscation params are invalid; reverse tunnel disabled");
+            continue;
+        }
+        match relay::spawn(state.clone(), relay, cred.clone(), identity.clone()) {
+            Ok(()) => tracing::info!("relay reverse tunnel enabled via {dial}"),
+            Err(e) => tracing::warn!("relay {dial}: reverse tunnel disabled: {e:#}"),
+        }
     }
 }
 
 /// Direct-only build: a `[relay]` in the config can't be honoured, say so once.
 #[cfg(not(feature = "relay"))]
 fn spawn_relay_uplink(cfg: &AgentConfig, _path: &Path, _state: Arc<AgentState>) {
-    if cfg.relay.is_some() {
+    if !cfg.relays().is_empty() {
         tracing::warn!(
             "config has a [relay] block, but this build has no relay support \
              (rebuild with `--features relay` to reach shares behind NAT)"
@@ -357,6 +414,22 @@ fn spawn_manifest_warmer(state: Arc<AgentState>) {
     });
 }
 
+/// Раз в час применять срок и потолок корзины ко всем шарам; первый проход
+/// сразу на старте, чтобы простоявший агент не держал просроченное до первой
+/// записи.
+fn spawn_trash_sweeper(state: Arc<AgentState>) {
+    if state.trash.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            let st = state.clone();
+            let _ = tokio::task::spawn_blocking(move || st.sweep_trash()).await;
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        }
+    });
+}
+
 fn mtime_of(path: &Path) -> Option<SystemTime> {
     std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
@@ -369,6 +442,8 @@ fn reload_config(path: &Path) -> Result<AgentConfig> {
     // previous config instead of half-applying.
     cfg.validate_import()?;
     cfg.validate_expose()?;
+    cfg.validate_thumbs()?;
+    cfg.validate_policies()?;
     Ok(cfg)
 }
 
@@ -96,6 +96,14 @@ impl HashCache {
         self.hashed(path, meta.len(), mtime_secs(&meta))
     }
 
+    /// The hash of `path` if the cache already holds it for the file's current
+    /// `(size, mtime)`, **never hashing**. The trash records it next to a removed
+    /// version when it is free, and does without it when it is not.
+    pub fn known(&self, path: &Path) -> Option<String> {
+        let meta = std::fs::metadata(path).ok()?;
+        self.cached(path, meta.len(), mtime_secs(&meta))
+    }
+
     /// Record a known hash for `path` at the given `(size, mtime)`, so a manifest
     /// build right after an upload returns the file already hashed (no lazy warm,
     /// LLD-28 п. 2.3). The caller passes the hash it computed while streaming and
@@ -122,10 +130,12 @@ pub(crate) fn mtime_secs(meta: &std::fs::Metadata) -> i64 {
 /// reserved `.xr-` component pruned. Pruning (not per-entry filtering) matters
 /// for directories: an import job dir's contents must never surface, however
 /// the files inside are named (LLD-29 п. 3.8). The root itself is exempt so an
-/// oddly-named share still serves.
+/// oddly-named share still serves. Siblings come in name order, so a streamed
+/// archive of the same tree is the same archive.
 pub(crate) fn walk_share(root: &Path) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> {
     WalkDir::new(root)
         .follow_links(false)
+        .sort_by_file_name()
         .into_iter()
         .filter_entry(|e| e.depth() == 0 || !is_reserved(e.file_name()))
 }
@@ -161,11 +171,12 @@ pub fn build_manifest(root: &Path, cache: &HashCache) -> Result<ShareManifest> {
             sha256: cache.hashed(path, meta.len(), mtime)?,
             meta: origin.remove(&rel),
             path: rel,
+            preview: None,
         });
     }
 
     entries.sort_by(|a, b| a.path.cmp(&b.path));
-    Ok(ShareManifest { entries })
+    Ok(ShareManifest { entries, version: 0 })
 }
 
 /// Like [`build_manifest`] but **never hashes**: each entry carries the cached
@@ -202,11 +213,12 @@ pub fn build_listing(root: &Path, cache: &HashCache) -> Result<ShareManifest> {
             sha256: cache.cached(path, meta.len(), mtime).unwrap_or_default(),
             meta: origin.remove(&rel),
             path: rel,
+            preview: None,
         });
     }
 
     entries.sort_by(|a, b| a.path.cmp(&b.path));
-    Ok(ShareManifest { entries })
+    Ok(ShareManifest { entries, version: 0 })
 }
 
 /// Listing for a single-file share, without hashing (see [`build_listing`]).
@@ -229,7 +241,9 @@ pub fn build_listing_for_file(path: &Path, cache: &HashCache) -> Result<ShareMan
             // A single-file share is the file itself: there is no directory to
             // keep an origin index in, and nothing imports into it either.
             meta: None,
+            preview: None,
         }],
+        version: 0,
     })
 }
 
@@ -253,7 +267,9 @@ pub fn build_manifest_for_file(path: &Path, cache: &HashCache) -> Result<ShareMa
             mtime,
             sha256: cache.hashed(path, meta.len(), mtime)?,
             meta: None,
+            preview: None,
         }],
+        version: 0,
     })
 }
 
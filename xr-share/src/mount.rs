@@ -0,0 +1,449 @@
+//! `xr-share mount`: локальный WebDAV-адрес шары с инвайта. Файловый менеджер
+//! (davfs2, gvfs/KIO, rclone, Finder) монтирует `http://127.0.0.1:<порт>/dav/<шара>/`,
+//! а прокси подставляет токен гранта и ведёт запросы к WebDAV агента
+//! ([`crate::dav`]).
+//!
+//! К агенту прокси ходит напрямую, если тот отвечает по одному из адресов
+//! гранта (LAN первым), а за NAT через relay-плечо гранта: loopback-форвардер
+//! и pinned-TLS до ключа агента, как `connect` у TCP-публикаций. Путь
+//! выбирается при старте и заново, когда токен подходит к сроку или прямое
+//! соединение перестало открываться. Токен в монтировании не нужен и не
+//! хранится: любой `Authorization` клиента заменяется токеном гранта.
+//!
+//! Раз пароля нет, прокси принимает только запросы с `Host` своего
+//! loopback-адреса (`127.0.0.1:<порт>` или `localhost:<порт>`): страница в
+//! браузере, чей домен перепривязан на 127.0.0.1 (DNS rebinding), приходит со
+//! своим именем в `Host` и получает `403`, а не файлы шары.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use hyper::client::conn::http1::SendRequest;
+
+use crate::pull::{InviteShareDto, HUB_DEFAULT};
+
+/// Аргументы `mount`: какая шара с какого инвайта и на каком локальном порту.
+#[derive(clap::Args)]
+pub struct MountArgs {
+    /// Инвайт с доступом к шаре.
+    #[arg(long)]
+    pub invite: String,
+    /// Шара по share_id или имени; можно не указывать, если на инвайте одна.
+    #[arg(long)]
+    pub share: Option<String>,
+    /// Хаб (по умолчанию https://xr-hub.zoobr.top).
+    #[arg(long)]
+    pub hub: Option<String>,
+    /// Локальный порт на 127.0.0.1; 0 (по умолчанию) это любой свободный.
+    #[arg(long, default_value_t = 0)]
+    pub port: u16,
+}
+
+/// Запас до срока токена, с которым грант берётся у хаба заново.
+const GRANT_REFRESH_MARGIN_SECS: u64 = 60;
+
+/// Сколько ждать TCP до агента напрямую.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Сколько простаивающих соединений с агентом держать: файловый менеджер
+/// шлёт `PROPFIND` пачками, и новый TLS через relay на каждый дорог.
+const IDLE_MAX: usize = 8;
+
+/// Откуда `mount` берёт грант: у хаба по инвайту, а в тестах прямо из рук.
+pub type GrantSource = Arc<dyn Fn() -> Result<InviteShareDto> + Send + Sync>;
+
+/// Чем прокси доходит до агента.
+enum Upstream {
+    /// Напрямую, `host:port` из адресов гранта.
+    Direct(String),
+    /// Через relay: форвардер на loopback и pinned-TLS поверх него.
+    #[cfg(feature = "relay")]
+    Relay {
+        forwarder: xr_proto::relay_client::LoopbackForwarder,
+        tls: Arc<tokio_rustls::rustls::ClientConfig>,
+    },
+}
+
+/// Живой путь до агента под одним грантом.
+struct Link {
+    token: String,
+    exp: u64,
+    upstream: Upstream,
+    idle: Mutex<Vec<SendRequest<Body>>>,
+    /// Прямое соединение не открылось: следующий запрос ищет путь заново.
+    broken: AtomicBool,
+}
+
+impl Link {
+    async fn build(source: &GrantSource) -> Result<Self> {
+        let source = source.clone();
+        let (share, direct) = tokio::task::spawn_blocking(move || {
+            let share = source()?;
+            let direct = crate::pull::resolve_base("http", &share).map(|(base, _)| base);
+            Ok::<_, anyhow::Error>((share, direct))
+        })
+        .await
+        .context("запрос гранта")??;
+        let exp = crate::auth::decode_token_blob(&share.token)
+            .context("токен гранта не декодируется")?
+            .exp;
+        let upstream = match direct {
+            Ok(base) => Upstream::Direct(authority(&base).to_string()),
+            Err(e) => relay_upstream(&share, e).await?,
+        };
+        Ok(Self { token: share.token, exp, upstream, idle: Mutex::new(Vec::new()), broken: AtomicBool::new(false) })
+    }
+
+    fn stale(&self) -> bool {
+        self.broken.load(Ordering::Relaxed)
+            || self.exp.saturating_sub(GRANT_REFRESH_MARGIN_SECS) <= crate::server::now_unix()
+    }
+
+    /// Простаивающее соединение, если есть готовое, иначе новое.
+    async fn sender(&self) -> Result<SendRequest<Body>> {
+        {
+            let mut idle = self.idle.lock().unwrap();
+            idle.retain(|s| !s.is_closed());
+            if let Some(i) = idle.iter().position(|s| s.is_ready()) {
+                return Ok(idle.swap_remove(i));
+            }
+        }
+        self.connect().await
+    }
+
+    /// Вернуть соединение после ответа. Пока по нему течёт тело, оно не готово
+    /// и [`Link::sender`] его пропускает.
+    fn put_back(&self, sender: SendRequest<Body>) {
+        let mut idle = self.idle.lock().unwrap();
+        if !sender.is_closed() && idle.len() < IDLE_MAX {
+            idle.push(sender);
+        }
+    }
+
+    async fn connect(&self) -> Result<SendRequest<Body>> {
+        match &self.upstream {
+            Upstream::Direct(addr) => {
+                let tcp = match tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect(addr)).await {
+                    Ok(Ok(tcp)) => tcp,
+                    Ok(Err(e)) => {
+                        self.broken.store(true, Ordering::Relaxed);
+                        bail!("агент {addr} не отвечает: {e}");
+                    }
+                    Err(_) => {
+                        self.broken.store(true, Ordering::Relaxed);
+                        bail!("агент {addr} не отвечает: таймаут соединения");
+                    }
+                };
+                handshake(tcp).await
+            }
+            #[cfg(feature = "relay")]
+            Upstream::Relay { forwarder, tls } => {
+                let tcp = tokio::net::TcpStream::connect(forwarder.local_addr())
+                    .await
+                    .context("loopback-форвардер")?;
+                let sni = tokio_rustls::rustls::pki_types::ServerName::try_from("xr-share-agent")
+                    .expect("литеральное имя SNI годится");
+                match tokio_rustls::TlsConnector::from(tls.clone()).connect(sni, tcp).await {
+                    Ok(tls) => handshake(tls).await,
+                    Err(_) if forwarder.agent_offline() => bail!("машина шары сейчас не на связи с relay"),
+                    Err(e) => bail!("pinned TLS до агента: {e}"),
+                }
+            }
+        }
+    }
+
+    fn host(&self) -> &str {
+        match &self.upstream {
+            Upstream::Direct(addr) => addr,
+            #[cfg(feature = "relay")]
+            Upstream::Relay { .. } => "xr-share-agent",
+        }
+    }
+}
+
+async fn handshake<IO>(io: IO) -> Result<SendRequest<Body>>
+where
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, conn) = hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(io))
+        .await
+        .context("HTTP до агента")?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            tracing::debug!("соединение с агентом закрылось: {e}");
+        }
+    });
+    Ok(sender)
+}
+
+/// Прямой путь не нашёлся: relay-плечо гранта, если оно есть и собрано.
+#[cfg(feature = "relay")]
+async fn relay_upstream(share: &InviteShareDto, direct: anyhow::Error) -> Result<Upstream> {
+    let Some(grant) = &share.relay else {
+        return Err(direct.context("агент недоступен напрямую, а relay у шары нет"));
+    };
+    let endpoint = xr_proto::relay_client::RelayEndpoint::from_grant(grant)
+        .map_err(|e| anyhow::anyhow!("relay в гранте: {e}"))?;
+    let forwarder = xr_proto::relay_client::LoopbackForwarder::spawn(Arc::new(endpoint))
+        .await
+        .context("loopback-форвардер до relay")?;
+    let tls = xr_proto::relay_tls::pinned_client_config(&share.agent_pubkey)
+        .map_err(|e| anyhow::anyhow!("ключ агента в гранте: {e}"))?;
+    tracing::info!("агент {} недоступен напрямую ({direct:#}), идём через relay", share.name);
+    Ok(Upstream::Relay { forwarder, tls: Arc::new(tls) })
+}
+
+#[cfg(not(feature = "relay"))]
+async fn relay_upstream(share: &InviteShareDto, direct: anyhow::Error) -> Result<Upstream> {
+    if share.relay.is_some() {
+        return Err(direct.context("агент недоступен напрямую, а relay в этой сборке выключен (фича relay)"));
+    }
+    Err(direct.context("агент недоступен напрямую"))
+}
+
+/// `host:port` из базы вида `http://host:port/share_id`.
+fn authority(base: &str) -> &str {
+    let rest = base.split_once("://").map_or(base, |(_, r)| r);
+    rest.split('/').next().unwrap_or(rest)
+}
+
+/// Состояние прокси: шара и текущий путь до агента.
+struct Mount {
+    share_id: String,
+    /// Порт, который слушает прокси, для сверки `Host`.
+    port: u16,
+    source: GrantSource,
+    link: tokio::sync::Mutex<Arc<Link>>,
+}
+
+impl Mount {
+    /// Текущий путь, перестроенный, если он устарел. Не перестроился —
+    /// работаем старым: агент сам откажет, если токен истёк.
+    async fn link(&self) -> Arc<Link> {
+        let mut guard = self.link.lock().await;
+        if guard.stale() {
+            match Link::build(&self.source).await {
+                Ok(fresh) => *guard = Arc::new(fresh),
+                Err(e) => tracing::warn!("путь до агента не перестроился: {e:#}"),
+            }
+        }
+        guard.clone()
+    }
+}
+
+/// Слушать `listener` и вести запросы под `/dav/{share_id}` к агенту шары.
+/// Грант берётся у `source` при старте, чтобы ошибка доступа была видна сразу.
+pub async fn serve_mount(listener: tokio::net::TcpListener, share_id: String, source: GrantSource) -> Result<()> {
+    let port = listener.local_addr().context("адрес прокси mount")?.port();
+    let first = Link::build(&source).await?;
+    let mount = Arc::new(Mount { share_id, port, source, link: tokio::sync::Mutex::new(Arc::new(first)) });
+    let app = axum::Router::new().fallback(proxy).with_state(mount);
+    axum::serve(listener, app).await.context("прокси mount")
+}
+
+/// `Host` запроса это адрес самого прокси, а не чужое имя.
+fn local_host(host: Option<&HeaderValue>, port: u16) -> bool {
+    let Some(host) = host.and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    let Some((name, p)) = host.rsplit_once(':') else {
+        return false;
+    };
+    p.parse() == Ok(port) && (name == "127.0.0.1" || name.eq_ignore_ascii_case("localhost"))
+}
+
+async fn proxy(State(mount): State<Arc<Mount>>, req: Request) -> Response {
+    if !local_host(req.headers().get(header::HOST), mount.port) {
+        return (StatusCode::FORBIDDEN, "чужой Host: прокси отвечает только на свой loopback-адрес").into_response();
+    }
+    let prefix = format!("/dav/{}", mount.share_id);
+    let path = req.uri().path();
+    if path != prefix && !path.starts_with(&format!("{prefix}/")) {
+        return (StatusCode::NOT_FOUND, "не эта шара").into_response();
+    }
+    let link = mount.link().await;
+    let (mut parts, body) = req.into_parts();
+    parts.uri = parts
+        .uri
+        .path_and_query()
+        .map_or_else(|| prefix.clone(), |pq| pq.as_str().to_string())
+        .parse()
+        .unwrap_or_default();
+    let (Ok(auth), Ok(host)) = (
+        HeaderValue::from_str(&format!("Bearer {}", link.token)),
+        HeaderValue::from_str(link.host()),
+    ) else {
+        return (StatusCode::BAD_GATEWAY, "токен гранта не годится в заголовок").into_response();
+    };
+    parts.headers.insert(header::AUTHORIZATION, auth);
+    parts.headers.insert(header::HOST, host);
+
+    let mut sender = match link.sender().await {
+        Ok(s) => s,
+        Err(e) => return upstream_down(&e),
+    };
+    let result = sender.send_request(Request::from_parts(parts, body)).await;
+    link.put_back(sender);
+    match result {
+        Ok(resp) => {
+            let (parts, body) = resp.into_parts();
+            Response::from_parts(parts, Body::new(body))
+        }
+        Err(e) => upstream_down(&anyhow::Error::new(e)),
+    }
+}
+
+fn upstream_down(e: &anyhow::Error) -> Response {
+    tracing::warn!("mount: {e:#}");
+    (StatusCode::BAD_GATEWAY, format!("агент шары недоступен: {e:#}")).into_response()
+}
+
+/// `xr-share mount --invite <инвайт>`: локальный WebDAV-адрес шары.
+pub fn mount(args: MountArgs) -> Result<()> {
+    let hub = args.hub.clone().unwrap_or_else(|| HUB_DEFAULT.to_string());
+    let first = match &args.share {
+        Some(want) => crate::push::select_share(&hub, &args.invite, want)?,
+        None => crate::sync::only_share(&hub, &args.invite)?,
+    };
+    let (share_id, name) = (first.share_id.clone(), first.name.clone());
+    let invite = args.invite.clone();
+    let want = share_id.clone();
+    let source: GrantSource = Arc::new(move || crate::push::select_share(&hub, &invite, &want));
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("рантайм для mount")?;
+    rt.block_on(async move {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", args.port))
+            .await
+            .with_context(|| format!("не занять порт {} на 127.0.0.1", args.port))?;
+        let local = listener.local_addr()?;
+        println!("http://{local}/dav/{share_id}/");
+        println!("Шара «{name}»: смонтируй этот адрес как WebDAV (логин и пароль любые)");
+        println!("Остановить: Ctrl-C");
+        serve_mount(listener, share_id, source).await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use std::sync::RwLock;
+    use xr_proto::share::{sign_share_token, SCOPE_READ};
+
+    use crate::manifest::HashCache;
+    use crate::server::{router, AgentState, ShareRoot, SharesMap};
+
+    #[test]
+    fn only_the_proxys_own_host_passes() {
+        let ok = |h: &str| local_host(Some(&HeaderValue::from_str(h).unwrap()), 8091);
+        assert!(ok("127.0.0.1:8091"));
+        assert!(ok("LocalHost:8091"));
+        assert!(!ok("127.0.0.1:8092"));
+        assert!(!ok("127.0.0.1"));
+        assert!(!ok("evil.example:8091"));
+        assert!(!ok("localhost.evil.example:8091"));
+        assert!(!local_host(None, 8091));
+    }
+
+    #[test]
+    fn authority_is_cut_from_base() {
+        assert_eq!(authority("http://10.0.0.5:8090/S"), "10.0.0.5:8090");
+        assert_eq!(authority("http://[::1]:8090/S"), "[::1]:8090");
+    }
+
+    /// Прямое плечо: клиент без токена получает листинг, а чужие пути
+    /// остаются за прокси.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn proxies_dav_with_the_grant_token() {
+        let key = SigningKey::from_bytes(&[63u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"hello").unwrap();
+        let mut shares = SharesMap::new();
+        let root = ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false, policy: Default::default() };
+        shares.insert("S".into(), root);
+        let cache = Arc::new(HashCache::new());
+        let app = router(Arc::new(AgentState {
+            shares: RwLock::new(Arc::new(shares)),
+            hub_key: key.verifying_key(),
+            hash_cache: cache.clone(),
+            identity: None,
+            max_file_mb: None,
+            import: crate::import::ImportManager::new(None, cache),
+            expose: RwLock::new(Arc::new(Vec::new())),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: None,
+            webdav: true,
+            thumbs: None,
+            usage: Default::default(),
+        }));
+        let agent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = agent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let _ = axum::serve(agent, app).await;
+        });
+
+        let tok = sign_share_token(&key, "S", SCOPE_READ, crate::server::now_unix() + 1000);
+        let blob = {
+            use base64::Engine;
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&tok).unwrap())
+        };
+        let source: GrantSource = Arc::new(move || {
+            Ok(InviteShareDto {
+                share_id: "S".into(),
+                name: "docs".into(),
+                addr: "127.0.0.1".into(),
+                addrs: Vec::new(),
+                port,
+                agent_pubkey: String::new(),
+                token: blob.clone(),
+                relay: None,
+            })
+        });
+        let local = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", local.local_addr().unwrap());
+        tokio::spawn(serve_mount(local, "S".into(), source));
+
+        let (listing, outside, rebound) = tokio::task::spawn_blocking(move || {
+            let listing = ureq::request("PROPFIND", &format!("{base}/dav/S/"))
+                .set("Depth", "1")
+                .set("Authorization", "Basic Zm9vOmJhcg==")
+                .call()
+                .unwrap();
+            let status = listing.status();
+            let body = listing.into_string().unwrap();
+            let outside = match ureq::get(&format!("{base}/S/manifest")).call() {
+                Err(ureq::Error::Status(code, _)) => code,
+                other => panic!("ожидался отказ: {:?}", other.map(|r| r.status())),
+            };
+            // Страница с перепривязанного домена: тот же сокет, чужой Host.
+            let rebound = {
+                use std::io::{Read, Write};
+                let mut sock = std::net::TcpStream::connect(base.trim_start_matches("http://")).unwrap();
+                sock.write_all(b"GET /dav/S/a.txt HTTP/1.1\r\nHost: rebound.example\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                let mut head = String::new();
+                sock.read_to_string(&mut head).unwrap();
+                head
+            };
+            ((status, body), outside, rebound)
+        })
+        .await
+        .unwrap();
+        assert_eq!(listing.0, 207);
+        assert!(listing.1.contains("/dav/S/a.txt"), "{}", listing.1);
+        assert_eq!(outside, 404);
+        assert!(rebound.starts_with("HTTP/1.1 403"), "{rebound}");
+        assert!(!rebound.contains("hello"));
+    }
+}
@@ -2,12 +2,16 @@
 //!
 //! Authenticates by an invite, lists the shares attached to it, lets the operator
 //! pick a subset of files (a whole folder or individual files), and downloads
-//! them with SHA-256 verification. Self-contained on `ureq` so the agent binary
+//! them with SHA-256 verification. With `--watch` it stays on after the first
+//! pass, long-polling each share's `manifest/wait` and fetching what changed.
+//! With `--archive` a folder comes as one tar stream from the agent's
+//! `/archive` route instead, each file still checked against the manifest.
+//! Self-contained on `ureq` so the agent binary
 //! still cross-compiles to Windows (depending on `xr-core` would pull
 //! reqwest/aws-lc, which does not build for `windows-gnu`). The Android receiver
 //! uses `xr-core` over JNI; the pure diff there is shared, the transport is not.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -17,9 +21,10 @@ use clap::Args;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
+use xr_proto::chunks::{chunk_reader, plan_pieces, remote_bytes, Piece, CHUNKED_MIN_FILE};
 use xr_proto::share::{
-    parse_agent_pubkey, verify_share_manifest, ShareManifest, MANIFEST_SIGNED_AT_HEADER,
-    MANIFEST_SIG_HEADER,
+    parse_agent_pubkey, verify_share_manifest, FileChunks, ShareManifest, ShareManifestEntry,
+    MANIFEST_SIGNED_AT_HEADER, MANIFEST_SIG_HEADER, MANIFEST_VERSION_HEADER,
 };
 
 pub(crate) const HUB_DEFAULT: &str = "https://xr-hub.zoobr.top";
@@ -49,6 +54,16 @@ pub struct PullArgs {
     /// Limit to one share by its share_id or name.
     #[arg(long)]
     pub share: Option<String>,
+    /// Keep running after the first pass: wait for changes on the agent and
+    /// fetch new and updated files as they appear. With `--all` new files are
+    /// followed too; otherwise the picked files are kept up to date.
+    #[arg(long)]
+    pub watch: bool,
+    /// Fetch a folder of each share as one tar stream instead of file by file:
+    /// `--archive` alone takes the whole share, `--archive photos/2024` one
+    /// folder. Each file is still checked against the signed manifest.
+    #[arg(long, value_name = "FOLDER", num_args = 0..=1, default_missing_value = "")]
+    pub archive: Option<String>,
 }
 
 /// One share on an invite, as the hub returns it (`GET /invite/{t}/shares`).
@@ -69,6 +84,10 @@ pub(crate) struct InviteShareDto {
     #[serde(default)]
     pub(crate) agent_pubkey: String,
     pub(crate) token: String,
+    /// Relay leg for a share behind NAT (LLD-23 п. 2.4). Only `mount` walks it;
+    /// the blocking harnesses stay direct-only.
+    #[serde(default)]
+    pub(crate) relay: Option<xr_proto::share::RelayGrant>,
 }
 
 impl InviteShareDto {
@@ -118,9 +137,13 @@ pub fn pull(args: PullArgs) -> Result<()> {
         return Ok(());
     }
 
+    if args.archive.is_some() && (args.watch || args.all || !args.select.is_empty()) {
+        bail!("--archive сам выбирает папку: без --watch, --all и --select");
+    }
     let scheme = if args.https { "https" } else { "http" };
     let dest_root = PathBuf::from(args.dest.clone().unwrap_or_else(|| "xr-share-pull".into()));
     let mut total = 0usize;
+    let mut followed: Vec<Followed> = Vec::new();
 
     for s in &shares {
         if let Some(f) = &args.share {
@@ -132,36 +155,188 @@ pub fn pull(args: PullArgs) -> Result<()> {
         // agent's base is used for the manifest and every download of this share.
         let (base, manifest) = resolve_base(scheme, s)
             .with_context(|| format!("шара «{}» недоступна", s.name))?;
+        if let Some(folder) = &args.archive {
+            total += pull_archive(&base, s, &manifest, folder, &dest_root.join(sanitize(&s.name)))
+                .with_context(|| format!("архив шары «{}»", s.name))?;
+            continue;
+        }
+        // With `--watch --all` an empty share is still followed: the first file
+        // to land in it gets fetched.
+        let follow_all = args.watch && args.all;
         if manifest.entries.is_empty() {
             println!("[{}] пусто", s.name);
-            continue;
+            if !follow_all {
+                continue;
+            }
         }
 
         let chosen = choose(&manifest, &args, &s.name)?;
-        if chosen.is_empty() {
+        if chosen.is_empty() && !follow_all {
             println!("[{}] ничего не выбрано", s.name);
             continue;
         }
 
-        let share_dir = dest_root.join(sanitize(&s.name));
-        for entry in manifest.entries.iter().filter(|e| chosen.contains(&e.path)) {
-            let dest = safe_join(&share_dir, &entry.path)
-                .with_context(|| format!("небезопасный путь в манифесте: {}", entry.path))?;
-            download_verify(
-                &format!("{base}/file/{}", encode_path(&entry.path)),
-                &s.token,
-                &dest,
-                &entry.sha256,
-            )
-            .with_context(|| format!("скачивание {}", entry.path))?;
-            println!("  ✓ {}", entry.path);
-            total += 1;
-        }
+        let mut f = Followed {
+            share: s,
+            base,
+            dir: dest_root.join(sanitize(&s.name)),
+            chosen: (!args.all).then_some(chosen),
+            fetched: HashMap::new(),
+        };
+        total += f.fetch_changed(&manifest)?;
+        followed.push(f);
     }
     println!("Готово: {total} файл(ов) в {}", dest_root.display());
+    if args.watch && !followed.is_empty() {
+        println!("Жду изменений (Ctrl+C чтобы выйти)…");
+        std::thread::scope(|scope| {
+            for f in followed {
+                scope.spawn(move || watch_share(scheme, f));
+            }
+        });
+    }
     Ok(())
 }
 
+/// How long one wait is held on the agent. The agent caps it at a minute, and
+/// the read timeout below leaves room on top so the client never tears down
+/// its own pending request.
+const WAIT_HOLD_SECS: u64 = 55;
+
+/// Back-off bounds between failed waits: an agent that restarts comes back
+/// within seconds, one behind a sleeping laptop is polled no more than once a
+/// minute.
+const WAIT_BACKOFF_START: Duration = Duration::from_secs(2);
+const WAIT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// One share being pulled: where it is reached, what to take, and the version
+/// of each file already on disk, so a change round fetches only what moved.
+struct Followed<'a> {
+    share: &'a InviteShareDto,
+    base: String,
+    dir: PathBuf,
+    /// The picked paths; `None` takes everything, new files included (`--all`).
+    chosen: Option<HashSet<String>>,
+    /// Path → (size, mtime, sha256) of the copy fetched by this run.
+    fetched: HashMap<String, (u64, i64, String)>,
+}
+
+impl Followed<'_> {
+    fn wanted(&self, entry: &ShareManifestEntry) -> bool {
+        self.chosen.as_ref().is_none_or(|c| c.contains(&entry.path))
+    }
+
+    fn is_current(&self, entry: &ShareManifestEntry) -> bool {
+        self.fetched
+            .get(&entry.path)
+            .is_some_and(|(size, mtime, sha)| {
+                *size == entry.size && *mtime == entry.mtime && *sha == entry.sha256
+            })
+    }
+
+    /// Download every wanted entry that differs from what this run already
+    /// fetched. The first pass fetches everything picked.
+    fn fetch_changed(&mut self, manifest: &ShareManifest) -> Result<usize> {
+        let mut n = 0usize;
+        let todo: Vec<&ShareManifestEntry> = manifest
+            .entries
+            .iter()
+            .filter(|e| self.wanted(e) && !self.is_current(e))
+            .collect();
+        for entry in todo {
+            let dest = safe_join(&self.dir, &entry.path)
+                .with_context(|| format!("небезопасный путь в манифесте: {}", entry.path))?;
+            let url = format!("{}/file/{}", self.base, encode_path(&entry.path));
+            let by_blocks = dest.is_file()
+                && entry.size >= CHUNKED_MIN_FILE
+                && !entry.sha256.is_empty()
+                && download_blocks(&self.base, &self.share.token, entry, &dest).is_ok();
+            if !by_blocks {
+                download_verify(&url, &self.share.token, &dest, &entry.sha256)
+                    .with_context(|| format!("скачивание {}", entry.path))?;
+            }
+            println!("  ✓ {}{}", entry.path, if by_blocks { " (блоками)" } else { "" });
+            self.fetched
+                .insert(entry.path.clone(), (entry.size, entry.mtime, entry.sha256.clone()));
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// A wanted file the agent lists but has not hashed yet (it was changed
+    /// behind the agent's back and the warmer has not reached it). It cannot be
+    /// verified, so it waits for the next manifest instead.
+    fn has_unhashed(&self, manifest: &ShareManifest) -> bool {
+        manifest.entries.iter().any(|e| self.wanted(e) && e.sha256.is_empty())
+    }
+}
+
+/// Follow one share until the process is stopped: hold a wait on the agent,
+/// fetch what changed when it answers, back off and re-locate the agent when it
+/// does not. The first wait goes without a version and is answered at once,
+/// that is where the version to wait on comes from.
+fn watch_share(scheme: &str, mut f: Followed<'_>) {
+    let name = f.share.name.clone();
+    let mut since = 0u64;
+    let mut backoff = Duration::ZERO;
+    loop {
+        if !backoff.is_zero() {
+            std::thread::sleep(backoff);
+            // The agent may be back on another address (LAN vs public).
+            if let Ok((base, _)) = resolve_base(scheme, f.share) {
+                f.base = base;
+            }
+        }
+        let url = format!(
+            "{}/manifest/wait?since={since}&timeout_secs={WAIT_HOLD_SECS}",
+            f.base
+        );
+        let read_timeout = Duration::from_secs(WAIT_HOLD_SECS + 15);
+        let (manifest, version) = match fetch_manifest_at(&url, f.share, read_timeout) {
+            Ok(Fetched::NotModified) => {
+                backoff = Duration::ZERO;
+                continue;
+            }
+            Ok(Fetched::Manifest(m, Some(v))) => (m, v),
+            Ok(Fetched::Manifest(_, None)) => {
+                eprintln!("[{name}] агент не сообщает версию манифеста: обнови xr-share на стороне агента");
+                return;
+            }
+            Ok(Fetched::Gone) => {
+                eprintln!(
+                    "[{name}] агент не знает ожидания изменений или шару сняли: слежение остановлено"
+                );
+                return;
+            }
+            Err(e) => {
+                backoff = (backoff * 2).clamp(WAIT_BACKOFF_START, WAIT_BACKOFF_MAX);
+                eprintln!("[{name}] ожидание изменений: {e:#}, повтор через {}s", backoff.as_secs());
+                continue;
+            }
+        };
+        match f.fetch_changed(&manifest) {
+            Ok(0) => {}
+            Ok(n) => println!("[{name}] обновлено файлов: {n}"),
+            Err(e) => {
+                // The version is not taken: the next wait on the old one is
+                // answered at once and the failed files are fetched again.
+                backoff = (backoff * 2).clamp(WAIT_BACKOFF_START, WAIT_BACKOFF_MAX);
+                eprintln!("[{name}] {e:#}, повтор через {}s", backoff.as_secs());
+                continue;
+            }
+        }
+        backoff = Duration::ZERO;
+        if f.has_unhashed(&manifest) {
+            // Ask again shortly with no version, which answers at once: the
+            // hashes show up without any further change to wake us.
+            std::thread::sleep(Duration::from_secs(10));
+            since = 0;
+        } else {
+            since = version;
+        }
+    }
+}
+
 /// Which manifest paths to download: `--all`, `--select`, or interactive.
 fn choose(manifest: &ShareManifest, args: &PullArgs, share_name: &str) -> Result<HashSet<String>> {
     if args.all {
@@ -231,26 +406,47 @@ fn select_paths(values: &[String], manifest: &ShareManifest) -> HashSet<String>
 /// a key is pinned: a missing signature (old agent or stripped headers) is a
 /// refusal with a pointer at updating the agent.
 pub(crate) fn fetch_manifest_verified(url: &str, share: &InviteShareDto) -> Result<ShareManifest> {
+    match fetch_manifest_at(url, share, Duration::from_secs(30))? {
+        Fetched::Manifest(m, _) => Ok(m),
+        Fetched::NotModified => bail!("HTTP 304 на запрос манифеста"),
+        Fetched::Gone => bail!("HTTP 404: шары нет на агенте"),
+    }
+}
+
+/// What a manifest request got: the verified manifest with its version (absent
+/// from an agent that predates live changes), `304` from a wait that timed
+/// out, or `404`.
+enum Fetched {
+    Manifest(ShareManifest, Option<u64>),
+    NotModified,
+    Gone,
+}
+
+/// [`fetch_manifest_verified`] for both the plain manifest and its wait route,
+/// with the read timeout chosen by the caller (a wait is held up to a minute).
+fn fetch_manifest_at(url: &str, share: &InviteShareDto, read_timeout: Duration) -> Result<Fetched> {
     // A short connect deadline caps the cost of a dead candidate (XR-050): with
     // several addresses tried LAN-first, an unroutable LAN-IP for a receiver
-    // outside that network must fail in seconds, not stall the whole 30s read
-    // budget before the public address is tried. The overall timeout stays 30s
-    // for a slow but reachable agent.
+    // outside that network must fail in seconds, not stall the whole read
+    // budget before the public address is tried.
     let agent = ureq::builder()
         .timeout_connect(Duration::from_secs(6))
         .build();
     let resp = match agent
         .get(url)
         .set("Authorization", &format!("Bearer {}", share.token))
-        .timeout(Duration::from_secs(30))
+        .timeout(read_timeout)
         .call()
     {
+        Ok(r) if r.status() == 304 => return Ok(Fetched::NotModified),
         Ok(r) => r,
+        Err(ureq::Error::Status(404, _)) => return Ok(Fetched::Gone),
         Err(ureq::Error::Status(code, r)) => {
             bail!("HTTP {code}: {}", r.into_string().unwrap_or_default())
         }
         Err(e) => bail!("сеть: {e}"),
     };
+    let version = resp.header(MANIFEST_VERSION_HEADER).and_then(|v| v.parse::<u64>().ok());
     let sig = resp.header(MANIFEST_SIG_HEADER).map(str::to_string);
     let signed_at = resp
         .header(MANIFEST_SIGNED_AT_HEADER)
@@ -266,7 +462,8 @@ pub(crate) fn fetch_manifest_verified(url: &str, share: &InviteShareDto) -> Resu
         verify_share_manifest(&sig, &key, &share.share_id, signed_at, body.as_bytes())
             .map_err(|e| anyhow::anyhow!("подпись манифеста не сошлась ({e}): возможна подмена по пути"))?;
     }
-    serde_json::from_str(&body).context("разбор JSON манифеста")
+    let manifest = serde_json::from_str(&body).context("разбор JSON манифеста")?;
+    Ok(Fetched::Manifest(manifest, version))
 }
 
 /// GET a JSON body, optionally with a bearer token. Maps a 4xx/5xx to a clear
@@ -288,7 +485,7 @@ pub(crate) fn get_json<T: DeserializeOwned>(url: &str, token: Option<&str>) -> R
 
 /// Stream a file to `dest`, verifying SHA-256, publishing atomically only on a
 /// match (a truncated transfer never appears as a real file).
-fn download_verify(url: &str, token: &str, dest: &Path, want_sha: &str) -> Result<()> {
+pub(crate) fn download_verify(url: &str, token: &str, dest: &Path, want_sha: &str) -> Result<()> {
     if let Some(parent) = dest.parent() {
         std::fs::create_dir_all(parent).with_context(|| format!("создание {}", parent.display()))?;
     }
@@ -302,7 +499,7 @@ fn download_verify(url: &str, token: &str, dest: &Path, want_sha: &str) -> Resul
         Err(e) => bail!("сеть: {e}"),
     };
 
-    let part = dest.with_extension("xrpull-part");
+    let part = part_path(dest);
     let mut reader = resp.into_reader();
     let mut file = std::fs::File::create(&part).with_context(|| format!("создание {}", part.display()))?;
     let mut hasher = Sha256::new();
@@ -326,9 +523,198 @@ fn download_verify(url: &str, token: &str, dest: &Path, want_sha: &str) -> Resul
     Ok(())
 }
 
+/// Rebuild a changed file from its stale copy at `dest`: take the agent's chunk
+/// list, reuse every chunk the local copy already has and range-request the
+/// rest. Any failure (an older agent without `/chunks`, a list that no longer
+/// matches the manifest, nothing in common) leaves `dest` untouched and the
+/// caller downloads the file whole.
+fn download_blocks(base: &str, token: &str, entry: &ShareManifestEntry, dest: &Path) -> Result<()> {
+    let rel = encode_path(&entry.path);
+    let remote: FileChunks = get_json(&format!("{base}/chunks/{rel}"), Some(token))?;
+    if remote.size != entry.size || !remote.sha256.eq_ignore_ascii_case(&entry.sha256) {
+        bail!("список чанков не от этой версии файла");
+    }
+    let mut local = std::fs::File::open(dest).with_context(|| format!("открытие {}", dest.display()))?;
+    let have = chunk_reader(std::io::BufReader::new(&local))?;
+    let pieces = plan_pieces(&remote, &have.chunks);
+    if remote_bytes(&pieces) == remote.size {
+        bail!("с локальной копией нет общих блоков");
+    }
+
+    let part = part_path(dest);
+    let mut file = std::fs::File::create(&part).with_context(|| format!("создание {}", part.display()))?;
+    let mut hasher = Sha256::new();
+    let written = (|| -> Result<()> {
+        let mut buf = vec![0u8; 64 * 1024];
+        for piece in pieces {
+            let mut left = piece.len();
+            let mut reader: Box<dyn Read> = match piece {
+                Piece::Local { from, len } => {
+                    std::io::Seek::seek(&mut local, std::io::SeekFrom::Start(from))?;
+                    Box::new((&local).take(len))
+                }
+                Piece::Remote { offset, len } => {
+                    let resp = match ureq::get(&format!("{base}/file/{rel}"))
+                        .set("Authorization", &format!("Bearer {token}"))
+                        .set("Range", &format!("bytes={offset}-{}", offset + len - 1))
+                        .timeout(Duration::from_secs(300))
+                        .call()
+                    {
+                        Ok(r) if r.status() == 206 => r,
+                        Ok(r) => bail!("HTTP {} вместо диапазона", r.status()),
+                        Err(ureq::Error::Status(code, _)) => bail!("HTTP {code}"),
+                        Err(e) => bail!("сеть: {e}"),
+                    };
+                    Box::new(resp.into_reader().take(len))
+                }
+            };
+            while left > 0 {
+                let want = left.min(buf.len() as u64) as usize;
+                let n = reader.read(&mut buf[..want])?;
+                if n == 0 {
+                    bail!("блок оборвался на {} байтах до конца", left);
+                }
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+                left -= n as u64;
+            }
+        }
+        Ok(())
+    })();
+    drop(file);
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&part);
+        return Err(e);
+    }
+
+    let got = hex_lower(&hasher.finalize());
+    if !got.eq_ignore_ascii_case(&entry.sha256) {
+        let _ = std::fs::remove_file(&part);
+        bail!("sha256 не совпал (ждали {}, получили {got})", entry.sha256);
+    }
+    std::fs::rename(&part, dest).with_context(|| format!("переименование в {}", dest.display()))?;
+    Ok(())
+}
+
+/// Download `folder` of a share as one tar stream (`GET /{id}/archive`) and
+/// unpack it under `dir`, laid out as a file-by-file pull would be.
+fn pull_archive(base: &str, share: &InviteShareDto, manifest: &ShareManifest, folder: &str, dir: &Path) -> Result<usize> {
+    let folder = folder.trim_matches('/');
+    let url = format!("{base}/archive?format=tar&path={}", encode_path(folder));
+    // No overall deadline: a large folder streams for as long as it takes, only
+    // a stalled one is cut off.
+    let agent = ureq::builder()
+        .timeout_connect(Duration::from_secs(6))
+        .timeout_read(Duration::from_secs(60))
+        .build();
+    let resp = match agent.get(&url).set("Authorization", &format!("Bearer {}", share.token)).call() {
+        Ok(r) => r,
+        Err(ureq::Error::Status(404, _)) if folder.is_empty() => {
+            bail!("агент не отдаёт архивы: обнови xr-share на стороне агента")
+        }
+        Err(ureq::Error::Status(404, _)) => bail!("в шаре нет папки «{folder}»"),
+        Err(ureq::Error::Status(409, _)) => bail!("«{folder}» это файл, а не папка"),
+        Err(ureq::Error::Status(code, r)) => {
+            bail!("HTTP {code}: {}", r.into_string().unwrap_or_default())
+        }
+        Err(e) => bail!("сеть: {e}"),
+    };
+    let n = unpack_archive(resp.into_reader(), manifest, folder, dir)?;
+    println!("[{}] из архива: {n} файл(ов)", share.name);
+    Ok(n)
+}
+
+/// Unpack a share archive (see `xr-share::archive`): every entry sits under one
+/// top folder standing for `folder`. A file is published only once its SHA-256
+/// matches the signed manifest; a mismatch fails the pull. A file the manifest
+/// holds no hash for (not hashed yet, or added after the manifest was taken) is
+/// skipped and reported: the archive's own `SHA256SUMS` comes from the same
+/// unsigned stream and vouches for nothing.
+fn unpack_archive(reader: impl Read, manifest: &ShareManifest, folder: &str, dir: &Path) -> Result<usize> {
+    let signed: HashMap<&str, &str> = manifest
+        .entries
+        .iter()
+        .filter(|e| !e.sha256.is_empty())
+        .map(|e| (e.path.as_str(), e.sha256.as_str()))
+        .collect();
+    let mut archive = tar::Archive::new(reader);
+    let mut done = 0usize;
+    let mut failed = 0usize;
+    let mut skipped = 0usize;
+    for entry in archive.entries().context("чтение архива")? {
+        let mut entry = entry.context("чтение архива")?;
+        let name = entry.path().context("имя в архиве")?.to_string_lossy().into_owned();
+        if name == crate::archive::SUMS_NAME {
+            continue;
+        }
+        // The top folder is the archive's own name for `folder`.
+        let inner = name.split_once('/').map_or("", |(_, rest)| rest).trim_end_matches('/');
+        if inner.is_empty() {
+            continue;
+        }
+        let rel = if folder.is_empty() { inner.to_string() } else { format!("{folder}/{inner}") };
+        let dest = safe_join(dir, &rel).with_context(|| format!("небезопасный путь в архиве: {name}"))?;
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => {
+                std::fs::create_dir_all(&dest).with_context(|| format!("создание {}", dest.display()))?;
+                continue;
+            }
+            tar::EntryType::Regular => {}
+            _ => continue,
+        }
+        let Some(want) = signed.get(rel.as_str()) else {
+            println!("  – {rel}: пропущен, в подписанном манифесте нет его хеша");
+            skipped += 1;
+            continue;
+        };
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("создание {}", parent.display()))?;
+        }
+        let part = part_path(&dest);
+        let got = {
+            let mut file = std::fs::File::create(&part).with_context(|| format!("создание {}", part.display()))?;
+            let mut hasher = Sha256::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = entry.read(&mut buf).with_context(|| format!("чтение {rel} из архива"))?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+            }
+            hex_lower(&hasher.finalize())
+        };
+        if want.eq_ignore_ascii_case(&got) {
+            std::fs::rename(&part, &dest).with_context(|| format!("переименование в {}", dest.display()))?;
+            println!("  ✓ {rel}");
+            done += 1;
+        } else {
+            let _ = std::fs::remove_file(&part);
+            eprintln!("  ✗ {rel}: sha256 не совпал с манифестом (ждали {want}, получили {got})");
+            failed += 1;
+        }
+    }
+    if skipped > 0 {
+        println!("Пропущено без хеша в манифесте: {skipped}, они приедут следующим pull");
+    }
+    if failed > 0 {
+        bail!("{failed} файл(ов) из архива не прошли проверку, повтори pull");
+    }
+    Ok(done)
+}
+
+/// The partial a download of `dest` is written to before the rename: the whole
+/// file name plus a suffix, so `a.txt` and `a.json` never share one.
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".xrpull-part");
+    dest.with_file_name(name)
+}
+
 /// Join a manifest-relative path under `root`, refusing traversal. The manifest
 /// is not trusted to dictate where we write (mirrors `xr-core::safe_dest`).
-fn safe_join(root: &Path, rel: &str) -> Result<PathBuf> {
+pub(crate) fn safe_join(root: &Path, rel: &str) -> Result<PathBuf> {
     if rel.starts_with('/') || rel.contains('\\') || rel.contains('\0') {
         bail!("traversing path");
     }
@@ -400,8 +786,10 @@ mod tests {
                     mtime: 0,
                     sha256: String::new(),
                     meta: None,
+                    preview: None,
                 })
                 .collect(),
+            version: 0,
         }
     }
 
@@ -445,6 +833,7 @@ mod tests {
             port: 8443,
             agent_pubkey: String::new(),
             token: "t".into(),
+            relay: None,
         }
     }
 
@@ -469,6 +858,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn watch_round_takes_only_moved_or_new_files() {
+        let share = dto("203.0.113.7", &[]);
+        let mut f = Followed {
+            share: &share,
+            base: String::new(),
+            dir: PathBuf::from("/tmp/dl"),
+            chosen: Some(["a.txt".to_string()].into_iter().collect()),
+            fetched: HashMap::new(),
+        };
+        let mut m = manifest_of(&["a.txt", "b.txt"]);
+        m.entries[0].sha256 = "aa".into();
+        assert!(f.wanted(&m.entries[0]) && !f.wanted(&m.entries[1]), "--select держит выбор");
+        assert!(!f.is_current(&m.entries[0]), "не скачанный файл едет");
+
+        f.fetched.insert("a.txt".into(), (1, 0, "aa".into()));
+        assert!(f.is_current(&m.entries[0]), "та же версия повторно не едет");
+        m.entries[0].sha256 = "bb".into();
+        assert!(!f.is_current(&m.entries[0]), "изменённый файл едет снова");
+
+        // Без хеша файл не проверить: ждём следующий манифест.
+        m.entries[0].sha256.clear();
+        assert!(f.has_unhashed(&m));
+        // --all берёт и новые файлы.
+        f.chosen = None;
+        assert!(f.wanted(&m.entries[1]));
+    }
+
     #[test]
     fn safe_join_blocks_traversal() {
         let root = Path::new("/tmp/dl");
@@ -485,4 +902,59 @@ mod tests {
         assert_eq!(sanitize("a/b:c"), "a_b_c");
         assert_eq!(sanitize("  ..  "), "share");
     }
+
+    #[test]
+    fn archive_unpacks_only_verified_files() {
+        let share = tempfile::tempdir().unwrap();
+        let photos = share.path().join("photos");
+        std::fs::create_dir_all(photos.join("2024")).unwrap();
+        std::fs::write(photos.join("a.txt"), b"a").unwrap();
+        std::fs::write(photos.join("2024/b.txt"), b"b").unwrap();
+        std::fs::write(photos.join("late.txt"), b"late").unwrap();
+        let mut tar = Vec::new();
+        let cache = crate::manifest::HashCache::new();
+        crate::archive::write_archive(&photos, "photos", crate::archive::Format::Tar, &cache, &mut tar).unwrap();
+
+        // a.txt signed right, b.txt signed as another version, late.txt not yet
+        // hashed by the agent: skipped, the archive's own sums do not count.
+        let mut manifest = manifest_of(&["photos/a.txt", "photos/2024/b.txt", "photos/late.txt"]);
+        manifest.entries[0].sha256 = hex_lower(&Sha256::digest(b"a"));
+        manifest.entries[1].sha256 = hex_lower(&Sha256::digest(b"old b"));
+        let dest = tempfile::tempdir().unwrap();
+        let err = unpack_archive(tar.as_slice(), &manifest, "photos", dest.path()).unwrap_err();
+        assert!(err.to_string().contains("1 файл"), "{err}");
+        assert_eq!(std::fs::read(dest.path().join("photos/a.txt")).unwrap(), b"a");
+        assert!(!dest.path().join("photos/late.txt").exists());
+        assert!(!dest.path().join("photos/2024/b.txt").exists());
+        assert!(!dest.path().join("photos/2024/b.txt.xrpull-part").exists());
+
+        manifest.entries[1].sha256 = hex_lower(&Sha256::digest(b"b"));
+        let dest = tempfile::tempdir().unwrap();
+        assert_eq!(unpack_archive(tar.as_slice(), &manifest, "photos", dest.path()).unwrap(), 2);
+        assert_eq!(std::fs::read(dest.path().join("photos/2024/b.txt")).unwrap(), b"b");
+        assert!(!dest.path().join("photos/late.txt").exists());
+    }
+
+    #[test]
+    fn archive_files_of_one_stem_do_not_share_a_partial() {
+        let share = tempfile::tempdir().unwrap();
+        let photos = share.path().join("photos");
+        std::fs::create_dir_all(&photos).unwrap();
+        std::fs::write(photos.join("a.txt"), b"text").unwrap();
+        std::fs::write(photos.join("a.json"), b"{}").unwrap();
+        let mut tar = Vec::new();
+        let cache = crate::manifest::HashCache::new();
+        crate::archive::write_archive(&photos, "photos", crate::archive::Format::Tar, &cache, &mut tar).unwrap();
+
+        let mut manifest = manifest_of(&["photos/a.json", "photos/a.txt"]);
+        manifest.entries[0].sha256 = hex_lower(&Sha256::digest(b"{}"));
+        manifest.entries[1].sha256 = hex_lower(&Sha256::digest(b"text"));
+        let dest = tempfile::tempdir().unwrap();
+        assert_eq!(unpack_archive(tar.as_slice(), &manifest, "photos", dest.path()).unwrap(), 2);
+        assert_eq!(std::fs::read(dest.path().join("photos/a.txt")).unwrap(), b"text");
+        assert_eq!(std::fs::read(dest.path().join("photos/a.json")).unwrap(), b"{}");
+        assert_eq!(part_path(&dest.path().join("a.txt")), dest.path().join("a.txt.xrpull-part"));
+        let left: Vec<_> = std::fs::read_dir(dest.path().join("photos")).unwrap().collect();
+        assert_eq!(left.len(), 2, "частичных файлов не осталось");
+    }
 }
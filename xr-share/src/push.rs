@@ -1,4 +1,4 @@
-//! Desktop sender: `xr-share push` / `xr-share rm` (LLD-28 п. 2.4).
+//! Desktop sender: `xr-share push` / `xr-share rm` / `xr-share trash` (LLD-28 п. 2.4).
 //!
 //! The mirror image of [`crate::pull`]: authenticate by an invite, pick a share
 //! it grants, and write to it (`PUT`) or remove from it (`DELETE`) over the same
@@ -13,6 +13,10 @@
 //! the hub, and the agent's own config must allow writes. On overwrite `push`
 //! sends `If-Match` with the hash from the just-fetched manifest, so it cannot
 //! silently clobber a newer version; `--force` drops that guard.
+//!
+//! What `push` overwrites and `rm` removes is not gone: the agent keeps it in
+//! the share's trash ([`crate::trash`]), and `trash list` / `trash restore` get
+//! it back with the same write grant.
 
 use std::fs::File;
 use std::io::Read;
@@ -20,11 +24,12 @@ use std::path::Path;
 use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use clap::Args;
+use clap::{Args, Subcommand};
 use sha2::{Digest, Sha256};
 use xr_proto::share::{scope_contains, SCOPE_WRITE};
 
 use crate::pull::{encode_path, get_json, InviteShareDto, HUB_DEFAULT};
+use crate::trash::{Reason, TrashItem};
 
 #[derive(Args)]
 pub struct PushArgs {
@@ -70,6 +75,55 @@ pub struct RmArgs {
     pub https: bool,
 }
 
+#[derive(Subcommand)]
+pub enum TrashCommand {
+    /// List the versions in a share's trash, newest first.
+    List(TrashListArgs),
+    /// Put a version from the trash back into the share.
+    Restore(TrashRestoreArgs),
+}
+
+#[derive(Args)]
+pub struct TrashListArgs {
+    /// Invite token granting access (the access anchor, LLD-19 п. 9.5).
+    #[arg(long)]
+    pub invite: String,
+    /// Hub base URL (default https://xr-hub.zoobr.top).
+    #[arg(long)]
+    pub hub: Option<String>,
+    /// Which share's trash to list, by its share_id or name.
+    #[arg(long)]
+    pub share: String,
+    /// Only the versions of this path inside the share.
+    #[arg(long)]
+    pub path: Option<String>,
+    /// Reach the agent over https (default http).
+    #[arg(long)]
+    pub https: bool,
+}
+
+#[derive(Args)]
+pub struct TrashRestoreArgs {
+    /// Invite token granting access (the access anchor, LLD-19 п. 9.5).
+    #[arg(long)]
+    pub invite: String,
+    /// Hub base URL (default https://xr-hub.zoobr.top).
+    #[arg(long)]
+    pub hub: Option<String>,
+    /// Which share to restore into, by its share_id or name.
+    #[arg(long)]
+    pub share: String,
+    /// Version id, as `trash list` prints it.
+    pub id: String,
+    /// Restore to this path inside the share instead of the original one. A
+    /// file already there is moved to the trash, not lost.
+    #[arg(long)]
+    pub to: Option<String>,
+    /// Reach the agent over https (default http).
+    #[arg(long)]
+    pub https: bool,
+}
+
 /// Upload a local file into a writable share (the `push` subcommand).
 pub fn push(args: PushArgs) -> Result<()> {
     let hub = args.hub.clone().unwrap_or_else(|| HUB_DEFAULT.to_string());
@@ -117,7 +171,8 @@ pub fn push(args: PushArgs) -> Result<()> {
     };
 
     let sha = sha256_file(local).with_context(|| format!("хеш файла {}", args.file))?;
-    let status = put_file(&base, &share, &rel, local, if_match.as_deref(), &sha)?;
+    let guard = if_match.as_deref().map_or(Guard::None, Guard::Match);
+    let status = put_file(&base, &share, &rel, local, guard, &sha)?;
     match status {
         201 => println!("готово, создан файл: {} -> {}/{rel}", args.file, share.name),
         204 => println!("готово, файл перезаписан: {} -> {}/{rel}", args.file, share.name),
@@ -137,7 +192,7 @@ pub fn rm(args: RmArgs) -> Result<()> {
     // doubles as the reachability check for the address we then delete against.
     let (base, _manifest) = crate::pull::resolve_base(scheme, &share)
         .with_context(|| format!("шара «{}» недоступна", share.name))?;
-    let status = delete_file(&base, &share, &args.rel)?;
+    let status = delete_file(&base, &share, &args.rel, None)?;
     match status {
         204 => println!("готово, файл удалён: {}/{}", share.name, args.rel),
         other => println!("готово, агент ответил {other}"),
@@ -145,6 +200,89 @@ pub fn rm(args: RmArgs) -> Result<()> {
     Ok(())
 }
 
+/// The `trash` subcommands: list a share's trash or restore a version from it.
+pub fn trash(command: TrashCommand) -> Result<()> {
+    match command {
+        TrashCommand::List(args) => trash_list(args),
+        TrashCommand::Restore(args) => trash_restore(args),
+    }
+}
+
+fn trash_list(args: TrashListArgs) -> Result<()> {
+    let hub = args.hub.clone().unwrap_or_else(|| HUB_DEFAULT.to_string());
+    let share = select_share(&hub, &args.invite, &args.share)?;
+    ensure_writable_grant(&share)?;
+    let scheme = if args.https { "https" } else { "http" };
+    let (base, _manifest) = crate::pull::resolve_base(scheme, &share)
+        .with_context(|| format!("шара «{}» недоступна", share.name))?;
+
+    let mut url = format!("{base}/trash");
+    if let Some(p) = &args.path {
+        url = format!("{url}?path={}", encode_path(p));
+    }
+    let items: Vec<TrashItem> = get_json(&url, Some(&share.token)).context("список корзины")?;
+    if items.is_empty() {
+        println!("[{}] корзина пуста", share.name);
+        return Ok(());
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    for i in &items {
+        let what = match i.reason {
+            Reason::Deleted => "удалён",
+            Reason::Replaced => "заменён",
+        };
+        println!(
+            "  {}  {} ({} б), {what} {} назад",
+            i.id,
+            i.path,
+            i.size,
+            ago(now.saturating_sub(i.trashed_at))
+        );
+    }
+    println!("Вернуть: xr-share trash restore --invite … --share {} <id> [--to <путь>]", args.share);
+    Ok(())
+}
+
+fn trash_restore(args: TrashRestoreArgs) -> Result<()> {
+    let hub = args.hub.clone().unwrap_or_else(|| HUB_DEFAULT.to_string());
+    let share = select_share(&hub, &args.invite, &args.share)?;
+    ensure_writable_grant(&share)?;
+    let scheme = if args.https { "https" } else { "http" };
+    let (base, _manifest) = crate::pull::resolve_base(scheme, &share)
+        .with_context(|| format!("шара «{}» недоступна", share.name))?;
+
+    let mut url = format!("{base}/trash/{}/restore", encode_path(&args.id));
+    if let Some(to) = &args.to {
+        url = format!("{url}?to={}", encode_path(to));
+    }
+    let resp = ureq::post(&url)
+        .set("Authorization", &format!("Bearer {}", share.token))
+        .timeout(Duration::from_secs(60))
+        .call();
+    match resp {
+        Ok(r) if r.status() == 204 => {
+            println!("готово, версия {} возвращена, прежний файл ушёл в корзину", args.id)
+        }
+        Ok(_) => println!("готово, версия {} возвращена", args.id),
+        Err(ureq::Error::Status(404, _)) => bail!("версии {} нет в корзине", args.id),
+        Err(ureq::Error::Status(code, _)) => bail!("{}", put_error(code)),
+        Err(e) => bail!("сеть при восстановлении: {e}"),
+    }
+    Ok(())
+}
+
+/// "5 мин", "3 ч", "2 дн": how long ago a version went to the trash.
+fn ago(secs: u64) -> String {
+    match secs {
+        s if s < 3600 => format!("{} мин", s / 60),
+        s if s < 48 * 3600 => format!("{} ч", s / 3600),
+        s => format!("{} дн", s / 86400),
+    }
+}
+
 /// Fetch the invite's shares and pick the one named by `--share` (id or name).
 /// Shared with the `import` harness (`crate::cli`), which selects the same way.
 pub(crate) fn select_share(hub: &str, invite: &str, want: &str) -> Result<InviteShareDto> {
@@ -158,7 +296,7 @@ pub(crate) fn select_share(hub: &str, invite: &str, want: &str) -> Result<Invite
 
 /// Refuse before touching the network if the grant's token has no write scope:
 /// this invite is read-only for the share (no write binding on the hub).
-fn ensure_writable_grant(share: &InviteShareDto) -> Result<()> {
+pub(crate) fn ensure_writable_grant(share: &InviteShareDto) -> Result<()> {
     let token = crate::auth::decode_token_blob(&share.token)
         .context("токен гранта не декодируется")?;
     if !scope_contains(&token.scope, SCOPE_WRITE) {
@@ -189,14 +327,24 @@ fn target_state_of(manifest: &xr_proto::share::ShareManifest, rel: &str) -> Targ
     }
 }
 
+/// Precondition a write travels with (LLD-28 п. 2.3).
+pub(crate) enum Guard<'a> {
+    /// No precondition: last write wins.
+    None,
+    /// `If-Match`: replace only this version.
+    Match(&'a str),
+    /// `If-None-Match: *`: create only, never over an existing file.
+    Absent,
+}
+
 /// PUT the local file, streaming it (constant memory), returning the HTTP status
 /// or a human-readable error for the write-path codes (LLD-28 п. 2.3).
-fn put_file(
+pub(crate) fn put_file(
     base: &str,
     share: &InviteShareDto,
     rel: &str,
     local: &Path,
-    if_match: Option<&str>,
+    guard: Guard<'_>,
     sha: &str,
 ) -> Result<u16> {
     let url = format!("{base}/file/{}", encode_path(rel));
@@ -205,8 +353,10 @@ fn put_file(
         .set("Authorization", &format!("Bearer {}", share.token))
         .set("X-Xr-Sha256", sha)
         .timeout(Duration::from_secs(300));
-    if let Some(m) = if_match {
-        req = req.set("If-Match", m);
+    match guard {
+        Guard::None => {}
+        Guard::Match(m) => req = req.set("If-Match", m),
+        Guard::Absent => req = req.set("If-None-Match", "*"),
     }
     match req.send(file) {
         Ok(r) => Ok(r.status()),
@@ -215,14 +365,17 @@ fn put_file(
     }
 }
 
-/// DELETE `rel` from the share.
-fn delete_file(base: &str, share: &InviteShareDto, rel: &str) -> Result<u16> {
+/// DELETE `rel` from the share, with `If-Match` when `if_match` names the
+/// version to remove.
+pub(crate) fn delete_file(base: &str, share: &InviteShareDto, rel: &str, if_match: Option<&str>) -> Result<u16> {
     let url = format!("{base}/file/{}", encode_path(rel));
-    match ureq::delete(&url)
+    let mut req = ureq::delete(&url)
         .set("Authorization", &format!("Bearer {}", share.token))
-        .timeout(Duration::from_secs(60))
-        .call()
-    {
+        .timeout(Duration::from_secs(60));
+    if let Some(m) = if_match {
+        req = req.set("If-Match", m);
+    }
+    match req.call() {
         Ok(r) => Ok(r.status()),
         Err(ureq::Error::Status(404, _)) => bail!("файла нет на агенте: {rel}"),
         Err(ureq::Error::Status(code, _)) => bail!("{}", put_error(code)),
@@ -238,8 +391,9 @@ fn put_error(code: u16) -> String {
         409 => "цель это каталог".into(),
         412 => "конфликт версий: файл на агенте изменился, перезалей с --force".into(),
         413 => "файл больше лимита агента (max_file_mb)".into(),
+        415 => "шара не принимает файлы такого типа".into(),
         422 => "sha256 не сошёлся при заливке".into(),
-        507 => "на агенте кончилось место".into(),
+        507 => "на агенте кончилось место: квота шары, предел числа файлов или запас диска".into(),
         other => format!("агент ответил HTTP {other}"),
     }
 }
@@ -250,14 +404,14 @@ mod tests {
     use xr_proto::share::{ShareManifest, ShareManifestEntry};
 
     fn entry(path: &str, sha: &str) -> ShareManifestEntry {
-        ShareManifestEntry { path: path.into(), size: 1, mtime: 0, sha256: sha.into(), meta: None }
+        ShareManifestEntry { path: path.into(), size: 1, mtime: 0, sha256: sha.into(), meta: None, preview: None }
     }
 
     #[test]
     fn target_state_classifies_manifest_entry() {
         // The If-Match guard reads the target's state off the manifest: absent,
         // hashed (guard on the hash), or present-but-unhashed (cold cache).
-        let m = ShareManifest { entries: vec![entry("a.txt", "aa"), entry("b.txt", "")] };
+        let m = ShareManifest { entries: vec![entry("a.txt", "aa"), entry("b.txt", "")], version: 0 };
         assert!(matches!(target_state_of(&m, "missing.txt"), TargetState::Absent));
         assert!(matches!(target_state_of(&m, "a.txt"), TargetState::Hashed(h) if h == "aa"));
         assert!(matches!(target_state_of(&m, "b.txt"), TargetState::Unhashed));
@@ -265,7 +419,7 @@ mod tests {
 }
 
 /// Streaming SHA-256 of a file, lowercase hex (constant memory, 64 KiB chunks).
-fn sha256_file(path: &Path) -> Result<String> {
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
     let mut file = File::open(path)?;
     let mut hasher = Sha256::new();
     let mut buf = [0u8; 64 * 1024];
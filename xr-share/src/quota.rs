@@ -0,0 +1,381 @@
+//! Квоты и правила записи шар: сколько места и файлов шаре можно занять, какие
+//! файлы она принимает и сколько диска оставить свободным ([`SharePolicy`]).
+//!
+//! Проверяют их загрузка (`PUT`, он же WebDAV) и импорт: не тот тип это `415`,
+//! нет места (квота, число файлов, запас диска) это `507`, отдельно от `413`
+//! за `max_file_mb`. Загрузки, начатые одновременно, меряют одно и то же
+//! занятое место, поэтому каждая принятая бронирует своё ([`Reservation`]):
+//! объявленную длину, а без `Content-Length` бронь растёт по мере записи, и
+//! следующая видит место за вычетом броней. Бронь снимается, когда запись
+//! кончилась, удачно или нет. Замок держится только на сверку с бронью, не на
+//! всю запись.
+//!
+//! Занятое место это обход шары (видимые файлы, без `.xr-*`), поэтому он
+//! кешируется по версии шары ([`crate::watch`]): пока шара не менялась,
+//! загрузка за загрузкой не обходят её заново. Потребителю те же цифры отдаёт
+//! `GET /{share_id}/usage`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use axum::http::StatusCode;
+use xr_proto::share::ShareUsage;
+
+use crate::config::SharePolicy;
+use crate::server::AgentState;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Что лежит в шаре.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+/// Занятое место шар по их версиям и брони идущих записей.
+#[derive(Default)]
+pub struct UsageCache {
+    inner: Mutex<HashMap<String, (u64, Usage)>>,
+    reserved: Mutex<HashMap<String, Usage>>,
+}
+
+/// Место, обещанное одной идущей записи. Возвращается шаре при drop.
+#[must_use = "бронь снимается, как только её отпустили"]
+pub struct Reservation {
+    cache: Arc<UsageCache>,
+    share_id: String,
+    taken: Usage,
+    /// Место под эту запись без учёта броней: `None`, предела нет.
+    capacity: Option<u64>,
+    limit: Refusal,
+}
+
+impl Reservation {
+    /// Дотянуть бронь до `written` байт, если они ещё влезают рядом с чужими
+    /// бронями. Для записи без объявленной длины, кусок за куском.
+    pub fn cover(&mut self, written: u64) -> Result<(), Refusal> {
+        if written <= self.taken.bytes {
+            return Ok(());
+        }
+        let grow = written - self.taken.bytes;
+        let mut reserved = self.cache.reserved.lock().expect("usage cache poisoned");
+        let pending = reserved.get(&self.share_id).map_or(0, |r| r.bytes);
+        if self.capacity.is_some_and(|cap| cap.saturating_sub(pending) < grow) {
+            return Err(self.limit);
+        }
+        let r = reserved.entry(self.share_id.clone()).or_default();
+        r.bytes = r.bytes.saturating_add(grow);
+        self.taken.bytes = written;
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut reserved = self.cache.reserved.lock().expect("usage cache poisoned");
+        if let Some(r) = reserved.get_mut(&self.share_id) {
+            r.bytes = r.bytes.saturating_sub(self.taken.bytes);
+            r.files = r.files.saturating_sub(self.taken.files);
+            if *r == Usage::default() {
+                reserved.remove(&self.share_id);
+            }
+        }
+    }
+}
+
+impl UsageCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Занятое место шары на версии `version`, обходом, если её ещё не мерили.
+    /// Версию читать до вызова: правка посреди обхода тогда поднимет её, и
+    /// следующий вызов померит заново. Блокирующий.
+    pub fn usage(&self, share_id: &str, root: &Path, version: u64) -> Usage {
+        if let Some((v, u)) = self.lock().get(share_id) {
+            if *v == version {
+                return *u;
+            }
+        }
+        let u = measure(root);
+        self.lock().insert(share_id.to_string(), (version, u));
+        u
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (u64, Usage)>> {
+        self.inner.lock().expect("usage cache poisoned")
+    }
+
+    /// Сверить запись с местом за вычетом чужих броней и, если проходит,
+    /// забронировать своё. `decide` получает уже забронированное и возвращает
+    /// место и бронь; всё под одним замком, так что две записи не займут
+    /// одно и то же. `capacity` это место под запись без броней, до него
+    /// бронь потом дотягивает [`Reservation::cover`].
+    fn reserve(
+        self: &Arc<Self>,
+        share_id: &str,
+        capacity: Option<u64>,
+        decide: impl FnOnce(Usage) -> Result<(Room, Usage), Refusal>,
+    ) -> Result<(Room, Reservation), Refusal> {
+        let mut reserved = self.reserved.lock().expect("usage cache poisoned");
+        let pending = reserved.get(share_id).copied().unwrap_or_default();
+        let (room, taken) = decide(pending)?;
+        let r = reserved.entry(share_id.to_string()).or_default();
+        r.bytes = r.bytes.saturating_add(taken.bytes);
+        r.files = r.files.saturating_add(taken.files);
+        let limit = room.limit;
+        Ok((room, Reservation { cache: self.clone(), share_id: share_id.to_string(), taken, capacity, limit }))
+    }
+}
+
+/// Обойти шару и сложить её видимые файлы. Нечитаемое пропускается: лучше
+/// чуть недосчитать, чем отказать всем из-за одной папки без прав.
+pub fn measure(root: &Path) -> Usage {
+    let mut u = Usage::default();
+    for e in crate::manifest::walk_share(root).flatten() {
+        if !e.file_type().is_file() {
+            continue;
+        }
+        if let Ok(md) = e.metadata() {
+            u.bytes += md.len();
+            u.files += 1;
+        }
+    }
+    u
+}
+
+/// Свободное место на диске под `path`, доступное агенту.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let c = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs заполняет обнулённую структуру по валидному C-пути; при
+    // успехе читаются только два скалярных поля.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Почему запись не принята.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Тип файла не из списков политики.
+    Type,
+    /// Не влезает в `quota_mb`.
+    Quota,
+    /// Уже `max_files` файлов.
+    Files,
+    /// Залезла бы в `reserve_free_mb`.
+    Disk,
+}
+
+impl Refusal {
+    pub fn status(self) -> (StatusCode, &'static str) {
+        match self {
+            Refusal::Type => (StatusCode::UNSUPPORTED_MEDIA_TYPE, "file type not allowed in this share"),
+            Refusal::Quota => (StatusCode::INSUFFICIENT_STORAGE, "share quota exceeded"),
+            Refusal::Files => (StatusCode::INSUFFICIENT_STORAGE, "share file limit reached"),
+            Refusal::Disk => (StatusCode::INSUFFICIENT_STORAGE, "disk reserve reached"),
+        }
+    }
+
+    /// Для ошибки задания импорта.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Refusal::Type => "тип файла не принимается политикой шары",
+            Refusal::Quota => "не влезает в квоту шары (quota_mb)",
+            Refusal::Files => "в шаре уже предельное число файлов (max_files)",
+            Refusal::Disk => "на диске шары остался только запас (reserve_free_mb)",
+        }
+    }
+}
+
+/// Сколько ещё можно записать одним файлом и какой предел упрётся первым.
+#[derive(Debug, Clone, Copy)]
+pub struct Room {
+    /// `None`: места не считали, предела нет.
+    pub bytes: Option<u64>,
+    limit: Refusal,
+}
+
+impl Room {
+    /// Влезает ли файл в `len` байт.
+    pub fn check(&self, len: u64) -> Result<(), Refusal> {
+        match self.bytes {
+            Some(room) if len > room => Err(self.limit),
+            _ => Ok(()),
+        }
+    }
+
+    /// Кто упрётся первым: ради ответа на переполнение посреди загрузки.
+    pub fn limit(&self) -> Refusal {
+        self.limit
+    }
+}
+
+/// Нужен ли политике обход шары (квота или число файлов).
+pub fn counts_usage(policy: &SharePolicy) -> bool {
+    policy.quota_mb.is_some() || policy.max_files.is_some()
+}
+
+/// Место под один файл. `replaced` это размер файла, который запись заменит
+/// (`None` для нового): его байты освобождаются, а число файлов не растёт.
+/// Запас диска считается без такой скидки: новая версия пишется рядом со
+/// старой и только потом встаёт на её место.
+pub fn room(policy: &SharePolicy, usage: Usage, free: Option<u64>, replaced: Option<u64>) -> Result<Room, Refusal> {
+    if replaced.is_none() && policy.max_files.is_some_and(|max| usage.files >= max) {
+        return Err(Refusal::Files);
+    }
+    let mut room = Room { bytes: None, limit: Refusal::Quota };
+    if let Some(quota) = policy.quota_mb {
+        let taken = usage.bytes.saturating_sub(replaced.unwrap_or(0));
+        room.bytes = Some(quota.saturating_mul(MIB).saturating_sub(taken));
+    }
+    if let (Some(reserve), Some(free)) = (policy.reserve_free_mb, free) {
+        let left = free.saturating_sub(reserve.saturating_mul(MIB));
+        if room.bytes.is_none_or(|b| left < b) {
+            room = Room { bytes: Some(left), limit: Refusal::Disk };
+        }
+    }
+    Ok(room)
+}
+
+/// [`room`] для записи `len` байт (`None`: длина не объявлена) в шару
+/// `share_id` с корнем `root`: занятое место из кеша, свободное с диска, обход
+/// вне async-рантайма. Идущие записи уже забронировали своё, оно считается
+/// занятым. Прошедшая запись бронирует `len`, а без длины ничего, и её бронь
+/// растёт через [`Reservation::cover`]; держится она, пока жива [`Reservation`].
+pub async fn room_for(
+    state: &AgentState,
+    share_id: &str,
+    root: &Path,
+    policy: &SharePolicy,
+    replaced: Option<u64>,
+    len: Option<u64>,
+) -> Result<(Room, Reservation), (StatusCode, &'static str)> {
+    let usage = current_usage(state, share_id, root, policy).await?;
+    let dir = root.to_path_buf();
+    let free = tokio::task::spawn_blocking(move || free_space(&dir)).await.unwrap_or(None);
+    // Без броней: место под запись за вычетом броней это оно минус брони.
+    let capacity = room(policy, usage, free, replaced).ok().and_then(|r| r.bytes);
+    state
+        .usage
+        .reserve(share_id, capacity, |pending| {
+            let usage = Usage { bytes: usage.bytes + pending.bytes, files: usage.files + pending.files };
+            let free = free.map(|f| f.saturating_sub(pending.bytes));
+            let room = room(policy, usage, free, replaced)?;
+            if let Some(len) = len {
+                room.check(len)?;
+            }
+            Ok((room, Usage { bytes: len.unwrap_or(0), files: u64::from(replaced.is_none()) }))
+        })
+        .map_err(Refusal::status)
+}
+
+/// Занятое место шары, если политике оно нужно; иначе нули без обхода.
+async fn current_usage(
+    state: &AgentState,
+    share_id: &str,
+    root: &Path,
+    policy: &SharePolicy,
+) -> Result<Usage, (StatusCode, &'static str)> {
+    if !counts_usage(policy) {
+        return Ok(Usage::default());
+    }
+    let version = state.versions.current(share_id);
+    let (sid, dir) = (share_id.to_string(), root.to_path_buf());
+    let cache = state.usage.clone();
+    tokio::task::spawn_blocking(move || cache.usage(&sid, &dir, version))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "usage task failed"))
+}
+
+/// Цифры шары для потребителя. Свободное место считается и без квоты: это то,
+/// что осталось на диске сверх запаса.
+pub fn report(policy: &SharePolicy, usage: Usage, free: Option<u64>, writable: bool) -> ShareUsage {
+    let quota_bytes = policy.quota_mb.map(|q| q.saturating_mul(MIB));
+    let quota_left = quota_bytes.map(|q| q.saturating_sub(usage.bytes));
+    let disk_left = free.map(|f| f.saturating_sub(policy.reserve_free_mb.unwrap_or(0).saturating_mul(MIB)));
+    let free_bytes = match (quota_left, disk_left) {
+        (Some(q), Some(d)) => Some(q.min(d)),
+        (q, d) => q.or(d),
+    };
+    ShareUsage {
+        used_bytes: usage.bytes,
+        files: usage.files,
+        quota_bytes,
+        max_files: policy.max_files,
+        free_bytes,
+        writable,
+        extensions: policy.extensions.iter().map(|e| e.to_ascii_lowercase()).collect(),
+        mime: policy.mime.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(quota_mb: Option<u64>, max_files: Option<u64>, reserve_free_mb: Option<u64>) -> SharePolicy {
+        SharePolicy { quota_mb, max_files, reserve_free_mb, ..Default::default() }
+    }
+
+    #[test]
+    fn room_takes_the_tightest_limit() {
+        let used = Usage { bytes: 9 * MIB, files: 3 };
+        let p = policy(Some(10), Some(3), Some(1));
+
+        // Новый файл при исчерпанном числе файлов не проходит, замена проходит.
+        assert_eq!(room(&p, used, None, None).unwrap_err(), Refusal::Files);
+        let r = room(&p, used, None, Some(2 * MIB)).unwrap();
+        assert_eq!(r.bytes, Some(3 * MIB), "заменяемые байты освобождаются");
+        assert_eq!(r.check(3 * MIB + 1), Err(Refusal::Quota));
+
+        // Диск теснее квоты: упрётся запас.
+        let r = room(&p, used, Some(MIB + 10), Some(0)).unwrap();
+        assert_eq!((r.bytes, r.limit()), (Some(10), Refusal::Disk));
+        assert!(r.check(10).is_ok());
+
+        // Без политики места не считаем вовсе.
+        let r = room(&SharePolicy::default(), used, Some(0), None).unwrap();
+        assert!(r.bytes.is_none() && r.check(u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn usage_is_cached_per_version() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/.xr-trash")).unwrap();
+        std::fs::write(dir.path().join("a/x"), [0u8; 5]).unwrap();
+        std::fs::write(dir.path().join("a/.xr-trash/old"), [0u8; 100]).unwrap();
+        std::fs::write(dir.path().join("y"), [0u8; 7]).unwrap();
+        let cache = UsageCache::new();
+        assert_eq!(cache.usage("s", dir.path(), 1), Usage { bytes: 12, files: 2 });
+
+        std::fs::write(dir.path().join("z"), [0u8; 1]).unwrap();
+        assert_eq!(cache.usage("s", dir.path(), 1).files, 2, "та же версия, тот же ответ");
+        assert_eq!(cache.usage("s", dir.path(), 2), Usage { bytes: 13, files: 3 });
+    }
+
+    #[test]
+    fn report_counts_free_space_without_a_quota() {
+        let used = Usage { bytes: 3 * MIB, files: 2 };
+        let p = SharePolicy { extensions: vec!["JPG".into()], ..policy(None, None, Some(1)) };
+        let r = report(&p, used, Some(5 * MIB), true);
+        assert_eq!((r.quota_bytes, r.free_bytes), (None, Some(4 * MIB)));
+        assert_eq!(r.extensions, ["jpg"]);
+
+        let r = report(&policy(Some(4), None, None), used, Some(50 * MIB), false);
+        assert_eq!((r.quota_bytes, r.free_bytes), (Some(4 * MIB), Some(MIB)));
+        assert_eq!(report(&policy(Some(2), None, None), used, None, true).free_bytes, Some(0));
+    }
+}
@@ -8,6 +8,11 @@
 //! credential), then serves every reverse-stream the relay opens by terminating
 //! **identity-TLS** on it and handing the plaintext to the same axum router the
 //! direct listener uses. The relay only ever moves ciphertext (§3.3).
+//!
+//! A rendezvous stream from the relay ([`RELAY_PUNCH_REVERSE_TARGET`]) asks for
+//! a direct path instead: the agent trades UDP endpoints with the consumer and
+//! listens for its QUIC (see [`xr_proto::punch`]). Every stream of that link is
+//! served exactly like a reverse-stream, identity-TLS included.
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -20,11 +25,13 @@ use tokio_rustls::rustls::pki_types::PrivateKeyDer;
 use tokio_rustls::TlsAcceptor;
 use xr_proto::mux::{mux_handshake_client, mux_open_stream, Multiplexer};
 use xr_proto::protocol::{Command, TargetAddr};
-use xr_proto::relay_client::{RELAY_HELLO_OK, RELAY_REGISTER_TARGET, RELAY_REVERSE_TARGET};
-use xr_proto::share::{sign_relay_register, AgentCredential, RelayObf};
+use xr_proto::relay_client::{
+    RELAY_HELLO_OK, RELAY_PUNCH_REVERSE_TARGET, RELAY_REGISTER_TARGET, RELAY_REVERSE_TARGET,
+};
+use xr_proto::share::{sign_relay_register, verify_relay_token, AgentCredential, RelayObf};
 
 use crate::config::RelayAgentConfig;
-use crate::server::AgentState;
+use crate::server::{now_unix, AgentState};
 
 /// Backoff bounds for the reconnect loop.
 const BACKOFF_MIN: Duration = Duration::from_secs(1);
@@ -38,6 +45,25 @@ const REGISTER_TIMEOUT: Duration = Duration::from_secs(15);
 /// handshake is bounded; a completed request may stream a large file for as long
 /// as the relay's own splice-lifetime cap allows.
 const REVERSE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Punched links held at once. Each costs a UDP socket and a QUIC connection,
+/// and a rendezvous needs only a relay token, so a runaway consumer must not
+/// make the agent open sockets without bound; over the cap the rendezvous is
+/// refused and that consumer stays on the relay.
+const MAX_PUNCHED_LINKS: usize = 32;
+/// A punched link with no stream open for this long is closed. QUIC's own idle
+/// timeout never fires on it: the keep-alive holds the NAT mapping open.
+const PUNCHED_IDLE: Duration = Duration::from_secs(60);
+
+/// What the agent serves its router with: the identity-TLS acceptor and, for
+/// punched QUIC, the rustls config it was built from.
+#[derive(Clone)]
+struct Identity {
+    acceptor: TlsAcceptor,
+    tls: Arc<tokio_rustls::rustls::ServerConfig>,
+    punched: Arc<tokio::sync::Semaphore>,
+    /// Base64 identity key, what relay tokens for this agent are bound to.
+    pubkey: String,
+}
 
 /// Build the agent's self-signed identity certificate: an Ed25519 cert whose
 /// public key **is** the agent's identity key, so the consumer's pinning
@@ -74,17 +100,24 @@ pub fn spawn(
     credential_blob: String,
     identity: SigningKey,
 ) -> Result<()> {
+    use base64::Engine as _;
     let cred = decode_credential(&credential_blob)
         .context("relay: agent credential unusable (re-run `xr-share install --token`)")?;
     let (cert_der, key_der) = identity_cert(&identity)?;
     let server_cfg = xr_proto::relay_tls::identity_server_config(cert_der, key_der)
         .map_err(|e| anyhow!("identity TLS server config: {e}"))?;
-    let acceptor = TlsAcceptor::from(Arc::new(server_cfg));
+    let tls = Arc::new(server_cfg);
+    let served = Identity {
+        acceptor: TlsAcceptor::from(tls.clone()),
+        tls,
+        punched: Arc::new(tokio::sync::Semaphore::new(MAX_PUNCHED_LINKS)),
+        pubkey: base64::engine::general_purpose::STANDARD.encode(identity.verifying_key().as_bytes()),
+    };
 
     tokio::spawn(async move {
         let mut backoff = BACKOFF_MIN;
         loop {
-            match connect_and_serve(&state, &relay, &cred, &identity, &acceptor).await {
+            match connect_and_serve(&state, &relay, &cred, &identity, &served).await {
                 Ok(()) => {
                     // Even a clean end gets the floor delay, so a relay that
                     // accepts then instantly drops the mux can't spin a hot
@@ -112,7 +145,7 @@ async fn connect_and_serve(
     relay: &RelayAgentConfig,
     cred: &AgentCredential,
     identity: &SigningKey,
-    acceptor: &TlsAcceptor,
+    served: &Identity,
 ) -> Result<()> {
     let codec = relay.obf.codec().map_err(|e| anyhow!("relay obfuscation: {e}"))?;
     let mut tcp = TcpStream::connect(relay.dial())
@@ -145,22 +178,39 @@ async fn connect_and_serve(
             ns = new_stream_rx.recv() => {
                 let Some(ns) = ns else { break };
                 let stream_id = ns.stream_id;
-                let is_reverse = matches!(
-                    TargetAddr::decode(&ns.payload),
-                    Ok((TargetAddr::Domain(d, _), _)) if d == RELAY_REVERSE_TARGET
-                );
-                if !is_reverse {
-                    let _ = mux.send_frame(stream_id, Command::Close, Vec::new()).await;
-                    continue;
-                }
+                let target = match TargetAddr::decode(&ns.payload) {
+                    Ok((TargetAddr::Domain(d, _), _)) => d,
+                    _ => String::new(),
+                };
                 let mux = mux.clone();
-                let acceptor = acceptor.clone();
+                let served = served.clone();
                 let state = state.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = serve_reverse(mux, stream_id, acceptor, state).await {
-                        tracing::debug!("reverse stream {stream_id} ended: {e}");
+                match target.as_str() {
+                    RELAY_REVERSE_TARGET => {
+                        tokio::spawn(async move {
+                            if let Err(e) = serve_reverse(mux, stream_id, served.acceptor, state).await {
+                                tracing::debug!("reverse stream {stream_id} ended: {e}");
+                            }
+                        });
                     }
-                });
+                    RELAY_PUNCH_REVERSE_TARGET => {
+                        let Ok(permit) = served.punched.clone().try_acquire_owned() else {
+                            tracing::debug!("punched link cap reached, rendezvous refused");
+                            let _ = mux.send_frame(stream_id, Command::Close, Vec::new()).await;
+                            continue;
+                        };
+                        let dial = relay.dial();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve_punched(mux, stream_id, &dial, served, state).await {
+                                tracing::debug!("punch via {dial} failed: {e:#}");
+                            }
+                            drop(permit);
+                        });
+                    }
+                    _ => {
+                        let _ = mux.send_frame(stream_id, Command::Close, Vec::new()).await;
+                    }
+                }
             }
             // Register-stream traffic: `None` means the relay dropped our
             // registration, so end the connection and re-register. Stray bytes
@@ -218,7 +268,81 @@ async fn serve_reverse(
     mux.send_frame(stream_id, Command::ConnectAck, vec![0])
         .await
         .context("ack reverse stream")?;
-    let io = stream.into_io();
+    serve_tls(stream.into_io(), &acceptor, state).await
+}
+
+/// Rendezvous with a consumer over a punch-reverse stream, then serve the
+/// streams of the punched link until it dies. Each stream gets the same
+/// identity-TLS and router as a reverse-stream, so the consumer's pin holds on
+/// the direct path too.
+///
+/// The link bypasses the relay's budgets and shaping, so it lives no longer
+/// than the relay token the consumer offered (verified here, the relay's check
+/// doesn't reach the agent) and is closed after [`PUNCHED_IDLE`] without
+/// streams. Either way the permit goes back with the return; the consumer then
+/// rendezvouses anew through the relay, budgets included.
+async fn serve_punched(
+    mux: Arc<Multiplexer>,
+    stream_id: u32,
+    relay_dial: &str,
+    served: Identity,
+    state: Arc<AgentState>,
+) -> Result<()> {
+    let stream = mux.register_stream(stream_id).await;
+    mux.send_frame(stream_id, Command::ConnectAck, vec![0])
+        .await
+        .context("ack punch stream")?;
+    let admit = |offer: &xr_proto::punch::PunchOffer| {
+        let denied = |why: String| std::io::Error::new(std::io::ErrorKind::PermissionDenied, why);
+        let token = offer.token.as_ref().ok_or_else(|| denied("offer without a relay token".into()))?;
+        verify_relay_token(token, &state.hub_key, &token.share_id, &served.pubkey, now_unix())
+            .map_err(|e| denied(format!("relay token: {e:?}")))?;
+        Ok(token.exp)
+    };
+    let (link, exp) = xr_proto::punch::punch_agent(stream, relay_dial, &served.tls, admit)
+        .await
+        .context("rendezvous")?;
+    tracing::info!("direct path from consumer {}", link.remote());
+    let expires = tokio::time::Instant::now() + Duration::from_secs(exp.saturating_sub(now_unix()));
+    // Every served stream holds a clone: one strong count left means idle.
+    let open = Arc::new(());
+    loop {
+        let next = tokio::select! {
+            _ = tokio::time::sleep_until(expires) => {
+                tracing::debug!("punched link from {} closed: relay token expired", link.remote());
+                break;
+            }
+            next = tokio::time::timeout(PUNCHED_IDLE, link.accept()) => next,
+        };
+        match next {
+            Ok(Some(io)) => {
+                let acceptor = served.acceptor.clone();
+                let state = state.clone();
+                let open = open.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_tls(io, &acceptor, state).await {
+                        tracing::debug!("punched stream ended: {e}");
+                    }
+                    drop(open);
+                });
+            }
+            Ok(None) => break,
+            Err(_) if Arc::strong_count(&open) == 1 => {
+                tracing::debug!("punched link from {} closed: idle", link.remote());
+                break;
+            }
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Identity-TLS plus the agent's HTTP router over one byte channel, a relay
+/// reverse-stream or a punched QUIC stream alike.
+async fn serve_tls<IO>(io: IO, acceptor: &TlsAcceptor, state: Arc<AgentState>) -> Result<()>
+where
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
     let tls = tokio::time::timeout(REVERSE_HANDSHAKE_TIMEOUT, acceptor.accept(io))
         .await
         .map_err(|_| anyhow!("identity TLS handshake timed out"))?
@@ -255,16 +379,29 @@ pub fn relay_obf_ok(obf: &RelayObf) -> bool {
     obf.codec().is_ok()
 }
 
-/// Fetch the hub's current relay descriptor (XR-123). A plain binary update then
-/// switches an agent onto relay without re-exchanging a token or hand-editing the
-/// config. Returns `None` if the hub advertises no relay or is unreachable, so the
-/// caller falls back to the config `[relay]`. The descriptor is not secret (every
-/// consumer grant carries it), so the fetch is unauthenticated.
-pub fn fetch_relay_descriptor(hub_url: &str) -> Option<xr_proto::share::RelayDescriptor> {
-    let url = format!("{}/api/v1/relay", hub_url.trim_end_matches('/'));
-    let resp = ureq::get(&url).timeout(std::time::Duration::from_secs(10)).call().ok()?;
-    let body = resp.into_string().ok()?;
-    serde_json::from_str::<Option<xr_proto::share::RelayDescriptor>>(&body).ok().flatten()
+/// Fetch the relays the hub placed this agent on, best first (XR-123). A plain
+/// binary update then switches an agent onto relay without re-exchanging a token
+/// or hand-editing the config. A hub that predates several relays only answers
+/// `GET /relay`, which is asked next. Empty if the hub advertises no relay or is
+/// unreachable, so the caller falls back to the config relays. Descriptors are
+/// not secret (every consumer grant carries them), so the fetch is
+/// unauthenticated.
+pub fn fetch_relay_descriptors(hub_url: &str, agent_pubkey: &str) -> Vec<xr_proto::share::RelayDescriptor> {
+    use xr_proto::share::RelayDescriptor;
+    let base = hub_url.trim_end_matches('/');
+    let fetch = |req: ureq::Request| -> Option<String> {
+        req.timeout(std::time::Duration::from_secs(10)).call().ok()?.into_string().ok()
+    };
+    let placed = fetch(ureq::get(&format!("{base}/api/v1/relays")).query("agent", agent_pubkey))
+        .and_then(|body| serde_json::from_str::<Vec<RelayDescriptor>>(&body).ok());
+    if let Some(placed) = placed {
+        return placed;
+    }
+    fetch(ureq::get(&format!("{base}/api/v1/relay")))
+        .and_then(|body| serde_json::from_str::<Option<RelayDescriptor>>(&body).ok())
+        .flatten()
+        .into_iter()
+        .collect()
 }
 
 #[cfg(test)]
@@ -365,7 +502,7 @@ mod tests {
         let mut shares = SharesMap::new();
         shares.insert(
             "S".into(),
-            ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false },
+            ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false, policy: Default::default() },
         );
         let cache = Arc::new(HashCache::new());
         let state = Arc::new(AgentState {
@@ -375,6 +512,13 @@ mod tests {
             identity: Some(identity.clone()),
             max_file_mb: None,
             import: crate::import::ImportManager::new(None, cache),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(Vec::new())),
         });
         let cred = sign_agent_credential(&hub, &agent_pk, now() + 3600);
@@ -405,6 +549,8 @@ mod tests {
             port: relay_addr.port(),
             obf: test_obf(),
             relay_token,
+            backups: Vec::new(),
+            punch: false,
         };
         let endpoint = Arc::new(RelayEndpoint::from_grant(&grant).unwrap());
         let fwd = LoopbackForwarder::spawn(endpoint).await.unwrap();
@@ -454,6 +600,163 @@ mod tests {
         assert!(res.is_err(), "wrong pin must break the E2E handshake");
     }
 
+    /// A relay with its reflector and a registered punch-capable agent serving
+    /// share `S` (`hello.txt`), plus a consumer endpoint whose relay token
+    /// lives `ttl` seconds.
+    struct Punched {
+        relay_state: Arc<xr_relay::RelayState>,
+        endpoint: Arc<RelayEndpoint>,
+        _fwd: LoopbackForwarder,
+        _dir: tempfile::TempDir,
+        base: String,
+        client: reqwest::Client,
+        share_token: String,
+    }
+
+    impl Punched {
+        async fn start(ttl: u64) -> Self {
+            let hub = SigningKey::from_bytes(&[42u8; 32]);
+            let identity = SigningKey::from_bytes(&[9u8; 32]);
+            let agent_pk = b64(identity.verifying_key().as_bytes());
+
+            // Relay with its reflector on the same port.
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let relay_addr = listener.local_addr().unwrap();
+            let reflector = tokio::net::UdpSocket::bind(relay_addr).await.unwrap();
+            tokio::spawn(xr_relay::serve_reflector(reflector));
+            let relay_state =
+                xr_relay::RelayState::new(hub.verifying_key(), 64, 8, Duration::from_secs(30));
+            {
+                let s = relay_state.clone();
+                tokio::spawn(async move { xr_relay::serve(listener, test_codec(), s, 64).await });
+            }
+
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("hello.txt"), b"punched straight through").unwrap();
+            let mut shares = SharesMap::new();
+            shares.insert(
+                "S".into(),
+                ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false, policy: Default::default() },
+            );
+            let cache = Arc::new(HashCache::new());
+            let state = Arc::new(AgentState {
+                shares: RwLock::new(Arc::new(shares)),
+                hub_key: hub.verifying_key(),
+                hash_cache: cache.clone(),
+                identity: Some(identity.clone()),
+                max_file_mb: None,
+                import: crate::import::ImportManager::new(None, cache),
+                versions: crate::watch::ShareVersions::new(),
+                history: crate::delta::ManifestHistory::new(),
+                chunk_cache: crate::chunks::ChunkCache::new(),
+                trash: None,
+                webdav: false,
+                thumbs: None,
+                usage: Default::default(),
+                expose: RwLock::new(Arc::new(Vec::new())),
+            });
+            let cred = sign_agent_credential(&hub, &agent_pk, now() + 3600);
+            let cred_blob =
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cred).unwrap());
+            let relay_cfg = RelayAgentConfig {
+                addr: relay_addr.ip().to_string(),
+                port: relay_addr.port(),
+                obf: test_obf(),
+            };
+            spawn(state, relay_cfg, cred_blob, identity.clone()).unwrap();
+            for _ in 0..100 {
+                if relay_state.registry.get(&agent_pk).await.is_some() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+
+            let grant = RelayGrant {
+                addr: relay_addr.ip().to_string(),
+                port: relay_addr.port(),
+                obf: test_obf(),
+                relay_token: sign_relay_token(&hub, "S", &agent_pk, now() + ttl),
+                backups: Vec::new(),
+                punch: true,
+            };
+            let endpoint = Arc::new(RelayEndpoint::from_grant(&grant).unwrap());
+            let fwd = LoopbackForwarder::spawn(endpoint.clone()).await.unwrap();
+            let base = format!("https://{}", fwd.local_addr());
+            // No pooled connections: each request opens a fresh transit.
+            let client = reqwest::Client::builder()
+                .use_preconfigured_tls(pinned_client_config(&agent_pk).unwrap())
+                .pool_max_idle_per_host(0)
+                .build()
+                .unwrap();
+            let share_token = token_blob(&sign_share_token(&hub, "S", "share:read", now() + 3600));
+            Self { relay_state, endpoint, _fwd: fwd, _dir: dir, base, client, share_token }
+        }
+
+        async fn fetch(&self) -> Vec<u8> {
+            let resp = self
+                .client
+                .get(format!("{}/S/file/hello.txt", self.base))
+                .bearer_auth(&self.share_token)
+                .send()
+                .await
+                .expect("file request");
+            assert!(resp.status().is_success());
+            resp.bytes().await.unwrap().to_vec()
+        }
+
+        /// Wait up to 5 s for the direct path to be up (`true`) or gone.
+        async fn direct_becomes(&self, up: bool) -> bool {
+            for _ in 0..100 {
+                if self.endpoint.direct_path().is_some() == up {
+                    return true;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            false
+        }
+    }
+
+    /// With `punch` on both the relay and the grant, the first request rides the
+    /// splice and kicks a rendezvous; once the direct QUIC path is up, the next
+    /// request reaches the agent (same pinned TLS, same router) without the
+    /// relay moving a byte.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn punched_path_bypasses_the_relay() {
+        let p = Punched::start(3600).await;
+        let file_bytes = b"punched straight through".to_vec();
+        let relay_state = &p.relay_state;
+        let fetch = || p.fetch();
+
+        assert_eq!(&fetch().await[..], &file_bytes[..], "first request rides the relay");
+        assert!(p.direct_becomes(true).await, "the punch must come up on loopback");
+        // The rendezvous and the first request are both off the relay by now.
+        for _ in 0..100 {
+            if relay_state.splices.total() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let moved = || relay_state.counters.snapshot().into_iter().find(|(s, _)| s == "S").map(|(_, n)| n);
+        let relayed = moved();
+
+        assert_eq!(&fetch().await[..], &file_bytes[..], "bytes intact on the direct path");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(moved(), relayed, "the relay must not carry the direct request");
+        assert_eq!(relay_state.splices.total(), 0);
+    }
+
+    /// The agent drops a punched link when the relay token it came with
+    /// expires, keep-alive or not: past `exp` the consumer is back on the
+    /// relay and its budgets.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn punched_link_ends_at_token_expiry() {
+        let p = Punched::start(3).await;
+        p.fetch().await;
+        assert!(p.direct_becomes(true).await, "the punch must come up on loopback");
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(p.direct_becomes(false).await, "the link must not outlive the token");
+    }
+
     /// LLD-38 фаза 1 целиком через живой relay: локальный сервис, публикация в
     /// конфиге агента, реверс-стрим. Мандат публикации пускает до сервиса, а
     /// держатель одного лишь relay-токена на шару того же агента, подставивший
@@ -500,7 +803,7 @@ mod tests {
         let mut shares = SharesMap::new();
         shares.insert(
             "S".into(),
-            ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false },
+            ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false, policy: Default::default() },
         );
         let cache = Arc::new(HashCache::new());
         let state = Arc::new(AgentState {
@@ -510,9 +813,17 @@ mod tests {
             identity: Some(identity.clone()),
             max_file_mb: None,
             import: crate::import::ImportManager::new(None, cache),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(vec![crate::config::ExposeEntry {
                 name: "dash".into(),
                 upstream: upstream_addr.to_string(),
+                kind: Default::default(),
             }])),
         });
         let cred = sign_agent_credential(&hub, &agent_pk, now() + 3600);
@@ -542,6 +853,8 @@ mod tests {
             port: relay_addr.port(),
             obf: test_obf(),
             relay_token,
+            backups: Vec::new(),
+            punch: false,
         };
         let endpoint = Arc::new(RelayEndpoint::from_grant(&grant).unwrap());
         let fwd = LoopbackForwarder::spawn(endpoint).await.unwrap();
@@ -663,7 +976,7 @@ mod tests {
         let mut shares = SharesMap::new();
         shares.insert(
             "S".into(),
-            ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false },
+            ShareRoot { path: dir.path().canonicalize().unwrap(), is_file: false, writable: false, import: false, policy: Default::default() },
         );
         let cache = Arc::new(HashCache::new());
         let state = Arc::new(AgentState {
@@ -673,9 +986,17 @@ mod tests {
             identity: Some(identity.clone()),
             max_file_mb: None,
             import: crate::import::ImportManager::new(None, cache),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(vec![crate::config::ExposeEntry {
                 name: "live".into(),
                 upstream: upstream_addr.to_string(),
+                kind: Default::default(),
             }])),
         });
         let cred = sign_agent_credential(&hub, &agent_pk, now() + 3600);
@@ -710,6 +1031,8 @@ mod tests {
             port: relay_addr.port(),
             obf: test_obf(),
             relay_token,
+            backups: Vec::new(),
+            punch: false,
         };
         let endpoint = Arc::new(RelayEndpoint::from_grant(&grant).unwrap());
         let fwd = LoopbackForwarder::spawn(endpoint).await.unwrap();
@@ -755,4 +1078,157 @@ mod tests {
             .unwrap();
         assert_eq!(&back, b"\x81\x83\x01\x02\x03\x04hey");
     }
+
+    /// TCP-публикация через живой relay: `serve_connect` открывает локальный
+    /// порт, байты с него доходят до сервиса владельца и обратно. Маршрут без
+    /// годного мандата туннель не открывает, а до сервиса не доходит ничего.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn connect_tunnels_a_tcp_publication() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use xr_proto::share::{sign_expose_token, tcp_share_id, ExposeKind, TcpRoute};
+
+        let hub = SigningKey::from_bytes(&[42u8; 32]);
+        let identity = SigningKey::from_bytes(&[17u8; 32]);
+        let agent_pk = b64(identity.verifying_key().as_bytes());
+
+        // Сервис владельца говорит не по HTTP: здоровается первым, как SSH, и
+        // дальше отвечает эхом.
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let upstream_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        {
+            let hits = hits.clone();
+            tokio::spawn(async move {
+                while let Ok((mut sock, _)) = upstream_listener.accept().await {
+                    hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    tokio::spawn(async move {
+                        let _ = sock.write_all(b"SSH-2.0-test\r\n").await;
+                        let mut buf = [0u8; 4096];
+                        loop {
+                            match sock.read(&mut buf).await {
+                                Ok(0) | Err(_) => break,
+                                Ok(n) => {
+                                    if sock.write_all(&buf[..n]).await.is_err() {
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = listener.local_addr().unwrap();
+        let relay_state = xr_relay::RelayState::new(hub.verifying_key(), 64, 8, Duration::from_secs(30));
+        {
+            let s = relay_state.clone();
+            tokio::spawn(async move { xr_relay::serve(listener, test_codec(), s, 64).await });
+        }
+
+        let cache = Arc::new(HashCache::new());
+        let state = Arc::new(AgentState {
+            shares: RwLock::new(Arc::new(SharesMap::new())),
+            hub_key: hub.verifying_key(),
+            hash_cache: cache.clone(),
+            identity: Some(identity.clone()),
+            max_file_mb: None,
+            import: crate::import::ImportManager::new(None, cache),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
+            expose: RwLock::new(Arc::new(vec![crate::config::ExposeEntry {
+                name: "ssh".into(),
+                upstream: upstream_addr.to_string(),
+                kind: ExposeKind::Tcp,
+            }])),
+        });
+        let cred = sign_agent_credential(&hub, &agent_pk, now() + 3600);
+        let cred_blob =
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cred).unwrap());
+        spawn(
+            state,
+            RelayAgentConfig {
+                addr: relay_addr.ip().to_string(),
+                port: relay_addr.port(),
+                obf: test_obf(),
+            },
+            cred_blob,
+            identity.clone(),
+        )
+        .unwrap();
+        let mut registered = false;
+        for _ in 0..100 {
+            if relay_state.registry.get(&agent_pk).await.is_some() {
+                registered = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(registered, "агент обязан зарегистрироваться на relay");
+
+        // Маршрут, каким его выдаёт хаб: relay-токен на `tcp:ssh` и мандат.
+        let route = |mandate_name: &str| {
+            let exp = now() + 3600;
+            let mandate = sign_expose_token(&hub, mandate_name, &agent_pk, exp);
+            TcpRoute {
+                publication: "ssh".into(),
+                agent_pubkey: agent_pk.clone(),
+                relay: RelayGrant {
+                    addr: relay_addr.ip().to_string(),
+                    port: relay_addr.port(),
+                    obf: test_obf(),
+                    relay_token: sign_relay_token(&hub, &tcp_share_id("ssh"), &agent_pk, exp),
+                    backups: Vec::new(),
+                    punch: false,
+                },
+                mandate: base64::engine::general_purpose::URL_SAFE_NO_PAD
+                    .encode(serde_json::to_vec(&mandate).unwrap()),
+                exp,
+            }
+        };
+
+        // Мандат чужой публикации: агент отказывает, соединение закрывается
+        // без единого байта сервиса.
+        let wrong = route("other");
+        let local = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let wrong_addr = local.local_addr().unwrap();
+        let denied = tokio::spawn(crate::expose::serve_connect(local, Arc::new(move || Ok(wrong.clone()))));
+        let mut sock = tokio::net::TcpStream::connect(wrong_addr).await.unwrap();
+        let mut buf = Vec::new();
+        let n = tokio::time::timeout(Duration::from_secs(10), sock.read_to_end(&mut buf))
+            .await
+            .expect("отказ закрывает соединение")
+            .unwrap();
+        assert_eq!(n, 0, "{}", String::from_utf8_lossy(&buf));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+        denied.abort();
+
+        // Годный маршрут: приветствие сервиса и эхо идут сквозь туннель.
+        let good = route("ssh");
+        let local = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good_addr = local.local_addr().unwrap();
+        let served = tokio::spawn(crate::expose::serve_connect(local, Arc::new(move || Ok(good.clone()))));
+        let mut sock = tokio::net::TcpStream::connect(good_addr).await.unwrap();
+        let mut hello = [0u8; 14];
+        tokio::time::timeout(Duration::from_secs(10), sock.read_exact(&mut hello))
+            .await
+            .expect("приветствие сервиса")
+            .unwrap();
+        assert_eq!(&hello, b"SSH-2.0-test\r\n");
+        sock.write_all(b"ping").await.unwrap();
+        let mut back = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(10), sock.read_exact(&mut back))
+            .await
+            .expect("эхо сервиса")
+            .unwrap();
+        assert_eq!(&back, b"ping");
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+        served.abort();
+    }
 }
@@ -2,8 +2,27 @@
 //!
 //! v2 serves **many** shares, routed by `share_id`:
 //!
-//! - `GET /{share_id}/manifest`        — the listing for that share
+//! - `GET /{share_id}/manifest`        — the listing for that share; with
+//!   `?base=` a signed delta from that version when the agent still has it
+//!   (see [`crate::delta`])
+//! - `GET /{share_id}/manifest/wait`   — the same listing, held until the share
+//!   changes past `?since=` (long-poll, see [`crate::watch`])
 //! - `GET /{share_id}/file/{*path}`    — its bytes (range-capable)
+//! - `GET /{share_id}/chunks/{*path}`  — the file's content-defined chunk list,
+//!   so a consumer with an older copy fetches only changed ranges
+//! - `GET /{share_id}/archive`       — a folder (`?path=`, the whole share by
+//!   default) streamed as one zip or tar (`?format=`), see [`crate::archive`]
+//! - `GET /{share_id}/usage`           — space taken and left under the share's
+//!   quota and disk reserve, and the file types it accepts (see [`crate::quota`])
+//! - `GET /{share_id}/thumb/{*path}`   — a JPEG preview of an image or a video
+//!   poster frame, when the config enables them (see [`crate::thumbs`])
+//! - `GET /{share_id}/trash`           — versions a `DELETE` or an overwriting
+//!   `PUT` set aside (write scope, see [`crate::trash`]); `?path=` narrows it
+//!   to one file
+//! - `POST /{share_id}/trash/{id}/restore` — put a version back, at its old
+//!   path or at `?to=`
+//! - `/dav/{share_id}/{*path}`          — WebDAV over the same roots and gates,
+//!   when the config opts in (see [`crate::dav`])
 //! - `GET /manifest` / `GET /file/...` — legacy single-share aliases; the share
 //!   is selected by the **token's** `share_id`, so the v1 consumer keeps working
 //! - `GET /healthz`                    — unauthenticated liveness
@@ -18,10 +37,10 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use axum::body::Body;
-use axum::extract::{Path as AxPath, Request, State};
+use axum::extract::{Path as AxPath, Query, Request, State};
 use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
@@ -34,17 +53,24 @@ use tokio::io::AsyncWriteExt;
 use tower::ServiceExt;
 use tower_http::services::ServeFile;
 use xr_proto::share::{
-    sign_share_manifest, verify_share_token, ShareManifest, MANIFEST_SIGNED_AT_HEADER,
-    MANIFEST_SIG_HEADER, SCOPE_IMPORT, SCOPE_READ, SCOPE_WRITE,
+    sign_manifest_delta, sign_share_manifest, verify_share_token, ShareManifest,
+    ShareManifestDelta, MANIFEST_DELTA_HEADER, MANIFEST_SIGNED_AT_HEADER, MANIFEST_SIG_HEADER,
+    MANIFEST_VERSION_HEADER, SCOPE_IMPORT, SCOPE_READ, SCOPE_WRITE,
 };
 
+use crate::archive;
 use crate::auth::extract_token;
+use crate::chunks::ChunkCache;
+use crate::delta::ManifestHistory;
 use crate::import::{self, ImportManager, JobSpec};
 use crate::manifest::{
     build_listing, build_listing_for_file, build_manifest, build_manifest_for_file, HashCache,
     UPLOAD_TEMP_PREFIX,
 };
+use crate::quota::{self, Refusal};
 use crate::safepath::resolve_within;
+use crate::trash::{self, Reason};
+use crate::watch::ShareVersions;
 
 /// One served share: a canonical path that is either a directory tree or a
 /// single file. A directory share may be `writable` (LLD-28): only then does the
@@ -56,6 +82,9 @@ pub struct ShareRoot {
     /// URL-import jobs are accepted into this share (LLD-29): the local opt-in
     /// on top of `writable`, valid only for a writable directory.
     pub import: bool,
+    /// Quota and upload rules (see [`crate::quota`]); the default limits
+    /// nothing.
+    pub policy: crate::config::SharePolicy,
 }
 
 impl ShareRoot {
@@ -81,7 +110,7 @@ impl ShareRoot {
 
     /// Resolve a manifest-relative request path to a real file inside this share.
     /// For a file share the only valid request is the file's own name.
-    fn resolve(&self, rel: &str) -> Option<PathBuf> {
+    pub(crate) fn resolve(&self, rel: &str) -> Option<PathBuf> {
         if self.is_file {
             let name = self.path.file_name()?.to_string_lossy().into_owned();
             // Tolerate a leading slash / "./", reject anything else.
@@ -121,7 +150,7 @@ pub fn build_shares(entries: &[crate::config::ShareEntry]) -> SharesMap {
                 if e.import && !writable {
                     tracing::warn!("share {}: import ignored, share is not writable", e.share_id);
                 }
-                map.insert(e.share_id.clone(), ShareRoot { path: canon, is_file, writable, import });
+                map.insert(e.share_id.clone(), ShareRoot { path: canon, is_file, writable, import, policy: e.policy.clone().unwrap_or_default() });
             }
             Err(err) => {
                 tracing::warn!("share {}: path unreadable ({err}), skipping: {}", e.share_id, e.path)
@@ -151,11 +180,27 @@ pub struct AgentState {
     /// перечитыванием конфига, что и шары: `expose add` не должен требовать
     /// перезапуска агента.
     pub expose: RwLock<Arc<Vec<crate::config::ExposeEntry>>>,
+    /// Версии манифестов шар для long-poll ожидания изменений. Поднимает их
+    /// слежение за корнями ([`crate::watch`]) и запись через ручки агента.
+    pub versions: ShareVersions,
+    /// Прошлые листинги шар для дельт манифеста ([`crate::delta`]).
+    pub history: ManifestHistory,
+    /// Списки чанков больших файлов для блочной докачки ([`crate::chunks`]).
+    pub chunk_cache: ChunkCache,
+    /// Корзина записываемых шар ([`crate::trash`]), `None` когда она выключена:
+    /// тогда `DELETE` и перезапись окончательны, как раньше.
+    pub trash: Option<crate::config::TrashConfig>,
+    /// Ручки WebDAV ([`crate::dav`]) включены конфигом.
+    pub webdav: bool,
+    /// Превью файлов ([`crate::thumbs`]), `None` когда они выключены.
+    pub thumbs: Option<crate::thumbs::Thumbs>,
+    /// Занятое место шар для их квот ([`crate::quota`]).
+    pub usage: Arc<crate::quota::UsageCache>,
 }
 
 impl AgentState {
     /// Cheap snapshot of the current share table (clones the `Arc`, not the map).
-    fn snapshot(&self) -> Arc<SharesMap> {
+    pub(crate) fn snapshot(&self) -> Arc<SharesMap> {
         self.shares.read().expect("shares lock poisoned").clone()
     }
 
@@ -183,18 +228,55 @@ impl AgentState {
             let _ = root.manifest(&self.hash_cache);
         }
     }
+
+    /// Применить срок и потолок корзины ко всем шарам-каталогам. Срок истекает
+    /// и без записей в шару, поэтому `main` зовёт это по таймеру. Блокирующая,
+    /// как и прогрев.
+    pub fn sweep_trash(&self) {
+        let Some(cfg) = &self.trash else { return };
+        for (share_id, root) in self.snapshot().iter() {
+            if root.is_file {
+                continue;
+            }
+            match trash::prune(&root.path, cfg, now_unix()) {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("корзина шары {share_id}: убрано версий {n}"),
+                Err(e) => tracing::warn!("корзина шары {share_id} не чистится: {e}"),
+            }
+        }
+    }
 }
 
 pub fn router(state: Arc<AgentState>) -> Router {
-    Router::new()
+    // WebDAV (opt-in): its own URL space, every method on one route.
+    let dav = if state.webdav {
+        Router::new()
+            .route("/dav/{share_id}", axum::routing::any(crate::dav::dav_root))
+            .route("/dav/{share_id}/", axum::routing::any(crate::dav::dav_root))
+            .route("/dav/{share_id}/{*path}", axum::routing::any(crate::dav::dav_path))
+    } else {
+        Router::new()
+    };
+    dav
         .route("/healthz", get(healthz))
         // v2: share selected by the URL. The file route also accepts writes
         // (LLD-28); PUT/DELETE are v2-only, no legacy alias.
         .route("/{share_id}/manifest", get(get_manifest))
+        .route("/{share_id}/manifest/wait", get(wait_manifest))
         .route(
             "/{share_id}/file/{*path}",
             get(serve_file).put(put_file).delete(delete_file),
         )
+        .route("/{share_id}/chunks/{*path}", get(serve_chunks))
+        .route("/{share_id}/archive", get(get_archive))
+        .route("/{share_id}/usage", get(get_usage))
+        .route("/{share_id}/thumb/{*path}", get(get_thumb))
+        // Корзина записываемой шары: список версий и возврат, право записи.
+        .route("/{share_id}/trash", get(list_trash))
+        .route(
+            "/{share_id}/trash/{id}/restore",
+            axum::routing::post(restore_trash),
+        )
         // URL-import jobs (LLD-29), v2-only: start, poll, cancel.
         .route("/{share_id}/import", axum::routing::post(start_import))
         .route(
@@ -226,7 +308,7 @@ fn check_token(
 
 /// [`check_token`] for a handler that consumed the request body (the import
 /// routes take a JSON extractor, so only parts remain).
-fn check_token_parts(
+pub(crate) fn check_token_parts(
     state: &AgentState,
     share_id: &str,
     required_scope: &str,
@@ -249,12 +331,77 @@ fn token_share_id(req: &Request) -> Result<String, (StatusCode, &'static str)> {
 
 // ── v2: share id from the URL ───────────────────────────────────────
 
+#[derive(Deserialize)]
+struct ManifestQuery {
+    /// Версия листинга, который уже есть у клиента: ответ дельтой от неё,
+    /// если агент её ещё помнит.
+    base: Option<u64>,
+}
+
 async fn get_manifest(
     State(state): State<Arc<AgentState>>,
     AxPath(share_id): AxPath<String>,
+    Query(query): Query<ManifestQuery>,
+    req: Request,
+) -> Result<Response, (StatusCode, &'static str)> {
+    manifest_response(state, share_id, query.base, req).await
+}
+
+/// Максимум удержания ожидания манифеста и значение по умолчанию: те же, что
+/// у ожидания пресета на хабе. Дольше минуты висящий ответ рвут прокси по
+/// пути, в том числе фронт перед relay.
+const WAIT_MAX_SECS: u64 = 60;
+const WAIT_DEFAULT_SECS: u64 = 55;
+
+#[derive(Deserialize)]
+struct WaitQuery {
+    /// Версия манифеста, которая уже есть у клиента (заголовок
+    /// `X-Xr-Manifest-Version` прошлого ответа). Без неё ответ сразу.
+    #[serde(default)]
+    since: u64,
+    timeout_secs: Option<u64>,
+    /// Как у [`ManifestQuery`]: дельтой от этой версии вместо листинга.
+    base: Option<u64>,
+}
+
+fn wait_hold(requested_secs: Option<u64>) -> Duration {
+    Duration::from_secs(requested_secs.unwrap_or(WAIT_DEFAULT_SECS).min(WAIT_MAX_SECS))
+}
+
+/// Ожидание изменения шары: пока версия совпадает с `since`, запрос висит,
+/// изменение будит его подписанным листингом с новой версией, истечение
+/// удержания отвечает `304`. Сравнение на неравенство, как у пресетов:
+/// перезапуск агента начинает версии заново, и клиента со старой версией
+/// надо ответить сразу, а не ждать роста.
+async fn wait_manifest(
+    State(state): State<Arc<AgentState>>,
+    AxPath(share_id): AxPath<String>,
+    Query(query): Query<WaitQuery>,
     req: Request,
 ) -> Result<Response, (StatusCode, &'static str)> {
-    manifest_response(state, share_id, req).await
+    if !state.snapshot().contains_key(&share_id) {
+        return Err((StatusCode::NOT_FOUND, "no such share"));
+    }
+    check_token(&state, &share_id, SCOPE_READ, &req)?;
+    let deadline = tokio::time::Instant::now() + wait_hold(query.timeout_secs);
+    // Подписка до первой сверки: изменение в этот зазор иначе прошло бы мимо.
+    let mut rx = state.versions.subscribe(&share_id);
+    loop {
+        if *rx.borrow_and_update() != query.since {
+            return manifest_response(state, share_id, query.base, req).await;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {
+                return Ok(StatusCode::NOT_MODIFIED.into_response());
+            }
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    // Шару сняли, пока ждали.
+                    return Err((StatusCode::NOT_FOUND, "no such share"));
+                }
+            }
+        }
+    }
 }
 
 async fn serve_file(
@@ -265,6 +412,39 @@ async fn serve_file(
     file_response(&state, &share_id, &rel, req).await
 }
 
+/// The chunk list of one file for block-level transfer. Same gates as the
+/// file route. Cutting reads the whole file, so a cold list of a huge file can
+/// outlast the consumer's patience: the work is not cancelled with the request
+/// and lands in the cache, and the consumer meanwhile downloads whole.
+async fn serve_chunks(
+    State(state): State<Arc<AgentState>>,
+    AxPath((share_id, rel)): AxPath<(String, String)>,
+    req: Request,
+) -> Response {
+    let safe = {
+        let shares = state.snapshot();
+        let Some(share) = shares.get(&share_id) else {
+            return (StatusCode::NOT_FOUND, "no such share").into_response();
+        };
+        if let Err(e) = check_token(&state, &share_id, SCOPE_READ, &req) {
+            return e.into_response();
+        }
+        match share.resolve(&rel) {
+            Some(p) => p,
+            None => return (StatusCode::FORBIDDEN, "path rejected").into_response(),
+        }
+    };
+    let st = state.clone();
+    match tokio::task::spawn_blocking(move || st.chunk_cache.chunks_of(&safe, &st.hash_cache)).await {
+        Ok(Ok(chunks)) => Json(chunks.as_ref()).into_response(),
+        Ok(Err(e)) => {
+            tracing::debug!("chunks of {share_id}/{rel}: {e:#}");
+            (StatusCode::NOT_FOUND, "no such file").into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "chunks task failed").into_response(),
+    }
+}
+
 async fn put_file(
     State(state): State<Arc<AgentState>>,
     AxPath((share_id, rel)): AxPath<(String, String)>,
@@ -294,7 +474,7 @@ async fn get_manifest_legacy(
     req: Request,
 ) -> Result<Response, (StatusCode, &'static str)> {
     let share_id = token_share_id(&req)?;
-    manifest_response(state, share_id, req).await
+    manifest_response(state, share_id, None, req).await
 }
 
 async fn serve_file_legacy(
@@ -314,12 +494,17 @@ async fn serve_file_legacy(
 async fn manifest_response(
     state: Arc<AgentState>,
     share_id: String,
+    base: Option<u64>,
     req: Request,
 ) -> Result<Response, (StatusCode, &'static str)> {
     if !state.snapshot().contains_key(&share_id) {
         return Err((StatusCode::NOT_FOUND, "no such share"));
     }
     check_token(&state, &share_id, SCOPE_READ, &req)?;
+    // The version is read before the walk: a change landing mid-build then
+    // leaves the client one version behind and wakes its next wait at once,
+    // instead of being folded into a version that never listed it.
+    let version = state.versions.current(&share_id);
     // Listing never hashes (XR-039): it returns metadata plus any hash already in
     // the cache, so it is instant even on a cold cache of a huge share. The
     // warmer fills hashes in the background. Still off the async runtime because
@@ -327,16 +512,25 @@ async fn manifest_response(
     // stall other requests).
     let st = state.clone();
     let sid = share_id.clone();
-    let built = tokio::task::spawn_blocking(move || -> anyhow::Result<ShareManifest> {
+    // The diff against the last served listing also runs here: on a share of
+    // half a million files it is real CPU work.
+    let base = base.filter(|b| *b != 0);
+    let built = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
         let shares = st.snapshot();
         let share = shares
             .get(&sid)
             .ok_or_else(|| anyhow::anyhow!("share removed during build"))?;
-        share.listing(&st.hash_cache)
+        let mut manifest = share.listing(&st.hash_cache)?;
+        if let Some(thumbs) = &st.thumbs {
+            thumbs.advertise(&mut manifest);
+        }
+        let delta = st.history.observe(&sid, &mut manifest, version, &st.versions, base);
+        Ok((manifest, delta))
     })
     .await;
     match built {
-        Ok(Ok(manifest)) => Ok(signed_manifest_response(&state, &share_id, &manifest)),
+        Ok(Ok((_, Some(delta)))) => Ok(signed_delta_response(&state, &share_id, &delta)),
+        Ok(Ok((manifest, None))) => Ok(signed_manifest_response(&state, &share_id, &manifest)),
         Ok(Err(e)) => {
             tracing::error!("manifest build failed: {e:#}");
             Err((StatusCode::INTERNAL_SERVER_ERROR, "manifest error"))
@@ -349,25 +543,51 @@ async fn manifest_response(
 /// signature and its timestamp travel as response headers, the body stays the
 /// plain manifest JSON, so a pre-signing consumer keeps working while a pinning
 /// one verifies the bytes it actually received. Re-serializing on the consumer
-/// is never needed, hence no canonicalization to drift.
+/// is never needed, hence no canonicalization to drift. The version is signed
+/// inside the body and repeated in an unsigned header for the long-poll.
 fn signed_manifest_response(state: &AgentState, share_id: &str, manifest: &ShareManifest) -> Response {
     let body = match serde_json::to_vec(manifest) {
         Ok(b) => b,
         Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "manifest encode").into_response(),
     };
-    let mut resp = Response::builder().header(header::CONTENT_TYPE, "application/json");
+    let resp = Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(MANIFEST_VERSION_HEADER, manifest.version.to_string());
+    sign_body(state, resp, body, |key, signed_at, body| sign_share_manifest(key, share_id, signed_at, body))
+}
+
+/// A [`ShareManifestDelta`] instead of the listing, marked by
+/// [`MANIFEST_DELTA_HEADER`] and signed over its own domain, so the consumer
+/// knows which verifier the bytes belong to and a swapped header fails it.
+fn signed_delta_response(state: &AgentState, share_id: &str, delta: &ShareManifestDelta) -> Response {
+    let body = match serde_json::to_vec(delta) {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "manifest encode").into_response(),
+    };
+    let resp = Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(MANIFEST_VERSION_HEADER, delta.version.to_string())
+        .header(MANIFEST_DELTA_HEADER, delta.base.to_string());
+    sign_body(state, resp, body, |key, signed_at, body| sign_manifest_delta(key, share_id, signed_at, body))
+}
+
+fn sign_body(
+    state: &AgentState,
+    mut resp: axum::http::response::Builder,
+    body: Vec<u8>,
+    sign: impl FnOnce(&SigningKey, u64, &[u8]) -> String,
+) -> Response {
     if let Some(key) = &state.identity {
         let signed_at = now_unix();
-        let sig = sign_share_manifest(key, share_id, signed_at, &body);
         resp = resp
-            .header(MANIFEST_SIG_HEADER, sig)
+            .header(MANIFEST_SIG_HEADER, sign(key, signed_at, &body))
             .header(MANIFEST_SIGNED_AT_HEADER, signed_at.to_string());
     }
     resp.body(Body::from(body))
         .unwrap_or_else(|_| (StatusCode::INTERNAL_SERVER_ERROR, "manifest response").into_response())
 }
 
-async fn file_response(state: &AgentState, share_id: &str, rel: &str, req: Request) -> Response {
+pub(crate) async fn file_response(state: &AgentState, share_id: &str, rel: &str, req: Request) -> Response {
     let shares = state.snapshot();
     let Some(share) = shares.get(share_id) else {
         return (StatusCode::NOT_FOUND, "no such share").into_response();
@@ -394,14 +614,16 @@ async fn file_response(state: &AgentState, share_id: &str, rel: &str, req: Reque
 /// preconditions. The body streams into a reserved `.xr-part-<rand>` temp next
 /// to the target, is hashed on the fly, fsync'd and atomically renamed over the
 /// target on success; the temp is removed on any failure. `201` for a new file,
-/// `204` for an overwrite.
-async fn handle_put(
+/// `204` for an overwrite. The share's policy (see [`crate::quota`]) refuses a
+/// file type it does not take with `415` and a write past its quota, file
+/// limit or disk reserve with `507`, the body being cut off at the room left.
+pub(crate) async fn handle_put(
     state: &Arc<AgentState>,
     share_id: &str,
     rel: &str,
     req: Request,
 ) -> Result<Response, (StatusCode, &'static str)> {
-    let (target, root) = {
+    let (target, root, policy) = {
         let shares = state.snapshot();
         let share = shares
             .get(share_id)
@@ -412,13 +634,16 @@ async fn handle_put(
         check_token(state, share_id, SCOPE_WRITE, &req)?;
         let target = resolve_within(&share.path, rel)
             .map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
-        (target, share.path.clone())
+        (target, share.path.clone(), share.policy.clone())
     };
 
     if target.is_dir() {
         return Err((StatusCode::CONFLICT, "target is a directory"));
     }
     let existed = target.is_file();
+    if !policy.allows(rel) {
+        return Err(Refusal::Type.status());
+    }
 
     // Cheapest gate first: a declared over-cap body is refused before we hash the
     // current target for a precondition (no point reading a large cold file for a
@@ -428,6 +653,10 @@ async fn handle_put(
             return Err((StatusCode::PAYLOAD_TOO_LARGE, "file too large"));
         }
     }
+    let replaced = if existed { std::fs::metadata(&target).ok().map(|m| m.len()) } else { None };
+    // The reservation lives until the handler returns, whichever way.
+    let (_, mut reserved) =
+        quota::room_for(state, share_id, &root, &policy, replaced, content_length(req.headers())).await?;
     // Optimistic-concurrency preconditions (LLD-28 п. 3.7). All header-based, so
     // done before the body is consumed; current-target hashing runs off the async
     // worker like the read path, so a large cold file does not stall the runtime.
@@ -442,8 +671,10 @@ async fn handle_put(
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "mkdir failed"))?;
 
     let tmp = parent.join(format!("{UPLOAD_TEMP_PREFIX}{:016x}", rand::random::<u64>()));
+    // A chunked body declares no length, so its reservation grows as it streams
+    // and running out of room reads as the share being full, not the file too big.
     let cap_bytes = state.max_file_mb.map(|m| m.saturating_mul(1024 * 1024));
-    let (sha, size) = stream_to_temp(req.into_body(), &tmp, cap_bytes).await?;
+    let (sha, size) = stream_to_temp(req.into_body(), &tmp, cap_bytes, &mut reserved).await?;
 
     // Optional integrity check before the file is published.
     if let Some(want) = &expected_sha {
@@ -453,6 +684,12 @@ async fn handle_put(
         }
     }
 
+    // The version being replaced goes to the trash first (a hard link, so the
+    // swap below stays atomic). No trash for it means no overwrite.
+    if let Err(e) = set_aside(state, &root, &target, Reason::Replaced).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
     if rename_replace(&tmp, &target).await.is_err() {
         let _ = tokio::fs::remove_file(&tmp).await;
         return Err((StatusCode::INTERNAL_SERVER_ERROR, "rename failed"));
@@ -467,6 +704,8 @@ async fn handle_put(
     if let Some(key) = crate::meta::rel_key(&root, &target) {
         crate::meta::forget(&root, &key);
     }
+    // Ждущие узнают о записи сразу, не дожидаясь события ОС или опроса.
+    state.versions.bump(share_id);
 
     let status = if existed {
         StatusCode::NO_CONTENT
@@ -480,7 +719,7 @@ async fn handle_put(
 /// Delete a file from a writable directory share. Same gate order as
 /// [`handle_put`], then `409` for a directory, `404` for a missing file, and an
 /// optional `If-Match` precondition (`412`) before the removal (LLD-28 п. 2.3).
-async fn handle_delete(
+pub(crate) async fn handle_delete(
     state: &Arc<AgentState>,
     share_id: &str,
     rel: &str,
@@ -515,18 +754,343 @@ async fn handle_delete(
         }
     }
 
-    tokio::fs::remove_file(&target)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "delete failed"))?;
+    // With the trash on, the file moves there instead of being removed.
+    if !set_aside(state, &root, &target, Reason::Deleted).await? {
+        tokio::fs::remove_file(&target)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "delete failed"))?;
+    }
     // The origin goes with the file: a later upload under the same name is a
     // different file and must not inherit somebody else's page (XR-255).
     if let Some(key) = crate::meta::rel_key(&root, &target) {
         crate::meta::forget(&root, &key);
     }
+    state.versions.bump(share_id);
     tracing::info!("DELETE share={share_id} rel={rel}");
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
+/// Put the current version of `target` into the share's trash before the write
+/// path removes or replaces it, then apply the retention limits. `Ok(false)`
+/// when the trash is off or the file is empty, so the caller falls back to a
+/// final delete; a target that vanished meanwhile has nothing to keep and is
+/// fine too.
+pub(crate) async fn set_aside(
+    state: &Arc<AgentState>,
+    root: &Path,
+    target: &Path,
+    reason: Reason,
+) -> Result<bool, (StatusCode, &'static str)> {
+    let Some(cfg) = state.trash.clone() else {
+        return Ok(false);
+    };
+    let (root, target) = (root.to_path_buf(), target.to_path_buf());
+    let Some(rel) = crate::meta::rel_key(&root, &target) else {
+        return Err((StatusCode::FORBIDDEN, "path rejected"));
+    };
+    // An empty file has nothing to recover, and WebDAV clients create every
+    // file empty before the real upload: such versions would only crowd out
+    // the ones worth keeping.
+    if std::fs::metadata(&target).is_ok_and(|m| m.len() == 0) {
+        return Ok(false);
+    }
+    let sha = state.hash_cache.known(&target);
+    tokio::task::spawn_blocking(move || {
+        match trash::keep(&root, &target, &rel, reason, sha) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !target.exists() => return Ok(true),
+            Err(e) => {
+                tracing::warn!("trash of {} refused: {e}", root.display());
+                return Err(io_status(&e));
+            }
+        }
+        if let Err(e) = trash::prune(&root, &cfg, now_unix()) {
+            tracing::warn!("trash of {} not pruned: {e}", root.display());
+        }
+        Ok(true)
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "trash task failed"))?
+}
+
+// -- folder archive ---------------------------------------------------
+
+#[derive(Deserialize)]
+struct ArchiveQuery {
+    /// The folder to pack; empty (the default) is the whole share.
+    #[serde(default)]
+    path: String,
+    /// `zip` (the default) or `tar`.
+    format: Option<String>,
+}
+
+/// `GET /{share_id}/archive`: a folder of a directory share streamed as one zip
+/// or tar, built on the fly (see [`crate::archive`]). After the read gates:
+/// `403` for a rejected path, `404` for a missing folder, `409` for a file or a
+/// file share, `400` for an unknown format. There is no `Content-Length`: the
+/// size is known only once the walk is done.
+async fn get_archive(
+    State(state): State<Arc<AgentState>>,
+    AxPath(share_id): AxPath<String>,
+    Query(q): Query<ArchiveQuery>,
+    req: Request,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let (root, dir) = {
+        let shares = state.snapshot();
+        let share = shares
+            .get(&share_id)
+            .ok_or((StatusCode::NOT_FOUND, "no such share"))?;
+        check_token(&state, &share_id, SCOPE_READ, &req)?;
+        if share.is_file {
+            return Err((StatusCode::CONFLICT, "not a directory"));
+        }
+        let dir = resolve_within(&share.path, &q.path)
+            .map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+        (share.path.clone(), dir)
+    };
+    let format = archive::Format::parse(q.format.as_deref())
+        .ok_or((StatusCode::BAD_REQUEST, "unknown archive format"))?;
+    match std::fs::metadata(&dir) {
+        Ok(md) if md.is_dir() => {}
+        Ok(_) => return Err((StatusCode::CONFLICT, "not a directory")),
+        Err(_) => return Err((StatusCode::NOT_FOUND, "no such folder")),
+    }
+    // The archive's top folder: the folder's own name, the share's for the root.
+    let top = dir
+        .file_name()
+        .or_else(|| root.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| share_id.clone());
+    let filename = format!("{top}.{}", format.extension());
+    tracing::info!("archive share={share_id} path={:?} format={}", q.path, format.extension());
+    let body = archive::stream(dir, top, format, state.hash_cache.clone());
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(&filename)),
+        ],
+        Body::new(body),
+    )
+        .into_response())
+}
+
+/// `attachment` with an ASCII fallback name and the exact UTF-8 one (RFC 6266).
+fn content_disposition(filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' { c } else { '_' })
+        .collect();
+    let exact = percent_encoding::utf8_percent_encode(filename, percent_encoding::NON_ALPHANUMERIC);
+    format!("attachment; filename=\"{ascii}\"; filename*=UTF-8''{exact}")
+}
+
+// -- usage ----------------------------------------------------------
+
+/// `GET /{share_id}/usage`: what the share holds and what its policy still lets
+/// in (see [`crate::quota`]), so a consumer shows the space left before an
+/// upload. Read scope: whoever browses the share sees the numbers.
+async fn get_usage(
+    State(state): State<Arc<AgentState>>,
+    AxPath(share_id): AxPath<String>,
+    req: Request,
+) -> Result<Json<xr_proto::share::ShareUsage>, (StatusCode, &'static str)> {
+    let (root, policy, writable) = {
+        let shares = state.snapshot();
+        let share = shares
+            .get(&share_id)
+            .ok_or((StatusCode::NOT_FOUND, "no such share"))?;
+        check_token(&state, &share_id, SCOPE_READ, &req)?;
+        (share.path.clone(), share.policy.clone(), share.writable)
+    };
+    let version = state.versions.current(&share_id);
+    let st = state.clone();
+    let (usage, free) = tokio::task::spawn_blocking(move || {
+        (st.usage.usage(&share_id, &root, version), quota::free_space(&root))
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "usage task failed"))?;
+    Ok(Json(quota::report(&policy, usage, free, writable)))
+}
+
+/// The write policy of a share, the default (no limits) for an unknown one.
+pub(crate) fn share_policy(state: &AgentState, share_id: &str) -> crate::config::SharePolicy {
+    state.snapshot().get(share_id).map(|s| s.policy.clone()).unwrap_or_default()
+}
+
+// -- previews --------------------------------------------------------
+
+/// `GET /{share_id}/thumb/{*path}`: the JPEG preview of one file, made on first
+/// request and cached (see [`crate::thumbs`]). Same read gates as the file
+/// route, then `404` when previews are off or the file is missing, and `415`
+/// when the file has no preview (wrong type, too large, failed to decode). The
+/// `ETag` changes with the file, so a consumer revalidates for a `304`.
+async fn get_thumb(
+    State(state): State<Arc<AgentState>>,
+    AxPath((share_id, rel)): AxPath<(String, String)>,
+    req: Request,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let (source, root) = {
+        let shares = state.snapshot();
+        let share = shares
+            .get(&share_id)
+            .ok_or((StatusCode::NOT_FOUND, "no such share"))?;
+        check_token(&state, &share_id, SCOPE_READ, &req)?;
+        let source = share.resolve(&rel).ok_or((StatusCode::FORBIDDEN, "path rejected"))?;
+        (source, (!share.is_file).then(|| share.path.clone()))
+    };
+    let thumbs = state.thumbs.as_ref().ok_or((StatusCode::NOT_FOUND, "previews are off"))?;
+    // A file share has no root of its own to keep the cache under.
+    let cache_dir = match root {
+        Some(root) => root.join(crate::thumbs::THUMBS_DIR),
+        None => thumbs.file_share_dir(&share_id),
+    };
+    let md = match tokio::fs::metadata(&source).await {
+        Ok(md) if md.is_file() => md,
+        _ => return Err((StatusCode::NOT_FOUND, "no such file")),
+    };
+    let etag = format!("\"{}\"", thumbs.key(&rel, &md));
+    let cached = [
+        (header::CONTENT_TYPE, "image/jpeg".to_string()),
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "private, max-age=86400".to_string()),
+    ];
+    if req.headers().get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) == Some(etag.as_str()) {
+        return Ok((StatusCode::NOT_MODIFIED, cached).into_response());
+    }
+    let jpeg = thumbs
+        .get(&cache_dir, &source, &rel)
+        .await
+        .map_err(|_| (StatusCode::UNSUPPORTED_MEDIA_TYPE, "no preview"))?;
+    Ok((cached, jpeg).into_response())
+}
+
+// -- trash (version restore) ----------------------------------------
+
+#[derive(Deserialize)]
+struct TrashQuery {
+    /// Only the versions of this share path.
+    #[serde(default)]
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RestoreQuery {
+    /// Restore to this share path instead of the version's own.
+    #[serde(default)]
+    to: Option<String>,
+}
+
+/// Gates of the trash routes: the share exists (`404`), is writable (`403`) and
+/// the token carries `share:write` (`401`/`403`). Reading the trash is a write
+/// privilege: it exposes what somebody deleted. Returns the share root.
+fn trash_gates(
+    state: &AgentState,
+    share_id: &str,
+    headers: &HeaderMap,
+    uri: &axum::http::Uri,
+) -> Result<PathBuf, (StatusCode, &'static str)> {
+    let shares = state.snapshot();
+    let share = shares
+        .get(share_id)
+        .ok_or((StatusCode::NOT_FOUND, "no such share"))?;
+    if !share.writable {
+        return Err((StatusCode::FORBIDDEN, "share is read-only"));
+    }
+    check_token_parts(state, share_id, SCOPE_WRITE, headers, uri)?;
+    Ok(share.path.clone())
+}
+
+/// `GET /{share_id}/trash`: the versions in the share's trash, newest first.
+async fn list_trash(
+    State(state): State<Arc<AgentState>>,
+    AxPath(share_id): AxPath<String>,
+    Query(q): Query<TrashQuery>,
+    headers: HeaderMap,
+    uri: axum::http::Uri,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let root = trash_gates(&state, &share_id, &headers, &uri)?;
+    let mut items = tokio::task::spawn_blocking(move || trash::list(&root))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "trash task failed"))?
+        .map_err(|e| {
+            tracing::warn!("trash of {share_id} unreadable: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "trash unreadable")
+        })?;
+    if let Some(path) = &q.path {
+        items.retain(|i| &i.path == path);
+    }
+    Ok(Json(items).into_response())
+}
+
+/// `POST /{share_id}/trash/{id}/restore`: put a version back. After the gates:
+/// `404` for an unknown version, the destination safepathed (`403`), `409` if it
+/// is a directory. The share's policy judges the restore like an upload of the
+/// version: `415` for a type it does not take, `507` past its quota, file limit
+/// or disk reserve. A file already at the destination goes to the trash itself,
+/// so a restore is undoable too. `201` on a fresh path, `204` over a file.
+async fn restore_trash(
+    State(state): State<Arc<AgentState>>,
+    AxPath((share_id, id)): AxPath<(String, String)>,
+    Query(q): Query<RestoreQuery>,
+    headers: HeaderMap,
+    uri: axum::http::Uri,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let root = trash_gates(&state, &share_id, &headers, &uri)?;
+    let unreadable = |e: std::io::Error| {
+        tracing::warn!("trash of {share_id} unreadable: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "trash unreadable")
+    };
+    let r = root.clone();
+    let lookup = id.clone();
+    let item = tokio::task::spawn_blocking(move || trash::find(&r, &lookup))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "trash task failed"))?
+        .map_err(unreadable)?
+        .ok_or((StatusCode::NOT_FOUND, "no such version"))?;
+
+    let dest_rel = q.to.unwrap_or(item.path);
+    let dest = resolve_within(&root, &dest_rel).map_err(|_| (StatusCode::FORBIDDEN, "path rejected"))?;
+    if dest.is_dir() {
+        return Err((StatusCode::CONFLICT, "target is a directory"));
+    }
+    let policy = share_policy(&state, &share_id);
+    if !policy.allows(&dest_rel) {
+        return Err(Refusal::Type.status());
+    }
+    // The file it replaces moves to the trash, which the quota does not count.
+    let replaced = std::fs::metadata(&dest).ok().filter(|m| m.is_file()).map(|m| m.len());
+    let (_, _reserved) = quota::room_for(&state, &share_id, &root, &policy, replaced, Some(item.size)).await?;
+    let key = crate::meta::rel_key(&root, &dest).ok_or((StatusCode::FORBIDDEN, "path rejected"))?;
+    let current_sha = state.hash_cache.known(&dest);
+    let (r, d, k) = (root.clone(), dest.clone(), key.clone());
+    let restored = tokio::task::spawn_blocking(move || trash::restore(&r, &id, &d, &k, current_sha))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "trash task failed"))?
+        .map_err(|e| {
+            tracing::warn!("restore in {share_id} failed: {e}");
+            io_status(&e)
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "no such version"))?;
+
+    // The version comes back with its own hash and origin, if it had them.
+    if let (Some(sha), Ok(meta)) = (&restored.item.sha256, std::fs::metadata(&dest)) {
+        state.hash_cache.seed(&dest, meta.len(), mtime_secs(&meta), sha.clone());
+    }
+    crate::meta::forget(&root, &key);
+    if let Some(m) = restored.item.meta.clone() {
+        crate::meta::record(&root, &[(key, m)]);
+    }
+    state.versions.bump(&share_id);
+
+    let status = if restored.replaced {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    };
+    tracing::info!("RESTORE share={share_id} version={} -> {dest_rel}", restored.item.id);
+    Ok(status.into_response())
+}
+
 // -- import path (LLD-29) -------------------------------------------
 
 #[derive(Deserialize)]
@@ -565,7 +1129,9 @@ fn import_gates(
 
 /// `POST /{share_id}/import`: start a job (LLD-29 п. 2.5). After the shared
 /// gates: safepath the destination (`403`), the URL gate (`400`), plugin
-/// routing (`422`) and the height clamp (`400`), then enqueue (`429` full).
+/// routing (`422`) and the height clamp (`400`), a share already full under its
+/// policy (`507`), then enqueue (`429` full). The policy's types and quota are
+/// checked again per file at publication, when the sizes are known.
 async fn start_import(
     State(state): State<Arc<AgentState>>,
     AxPath(share_id): AxPath<String>,
@@ -601,6 +1167,13 @@ async fn start_import(
         Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
     };
 
+    let policy = share_policy(&state, &share_id);
+    match quota::room_for(&state, &share_id, &root, &policy, None, None).await {
+        Ok((room, _)) if room.bytes == Some(0) => return room.limit().status().into_response(),
+        Ok(_) => {}
+        Err(e) => return e.into_response(),
+    }
+
     let spec = JobSpec {
         share_id: share_id.clone(),
         share_root: root,
@@ -612,6 +1185,7 @@ async fn start_import(
         max_total_bytes: cfg.max_total_mb.map(|m| m.saturating_mul(1024 * 1024)),
         max_file_bytes: state.max_file_mb.map(|m| m.saturating_mul(1024 * 1024)),
         sandbox: cfg.sandbox.clone(),
+        policy,
     };
     match state.import.enqueue(spec) {
         Some(job_id) => {
@@ -716,14 +1290,16 @@ fn content_length(headers: &HeaderMap) -> Option<u64> {
     header_str(headers, "content-length")?.parse().ok()
 }
 
-/// Stream a request body into `tmp`, hashing on the fly and enforcing `cap_bytes`
-/// (if any). Fsync before returning `(sha256_hex, size)`. On any error the temp
-/// is removed and a status is returned: `413` over cap, `507` on a full disk,
+/// Stream a request body into `tmp`, hashing on the fly, enforcing `cap_bytes`
+/// (if any) and growing `reserved` to cover what is written. Fsync before
+/// returning `(sha256_hex, size)`. On any error the temp is removed and a status
+/// is returned: `413` over cap, `507` past the share's room or on a full disk,
 /// `400` on a broken body, `500` otherwise.
 async fn stream_to_temp(
     body: Body,
     tmp: &Path,
     cap_bytes: Option<u64>,
+    reserved: &mut quota::Reservation,
 ) -> Result<(String, u64), (StatusCode, &'static str)> {
     let mut file = match tokio::fs::File::create(tmp).await {
         Ok(f) => f,
@@ -742,6 +1318,9 @@ async fn stream_to_temp(
                         break Err((StatusCode::PAYLOAD_TOO_LARGE, "file too large"));
                     }
                 }
+                if let Err(refusal) = reserved.cover(total) {
+                    break Err(refusal.status());
+                }
                 hasher.update(&data);
                 if let Err(e) = file.write_all(&data).await {
                     break Err(io_status(&e));
@@ -771,7 +1350,7 @@ async fn stream_to_temp(
 
 /// Map an IO error to a status: a full disk (`ENOSPC` on unix, `ERROR_DISK_FULL`
 /// on Windows) is `507`, everything else `500`.
-fn io_status(e: &std::io::Error) -> (StatusCode, &'static str) {
+pub(crate) fn io_status(e: &std::io::Error) -> (StatusCode, &'static str) {
     match e.raw_os_error() {
         Some(28) | Some(112) => (StatusCode::INSUFFICIENT_STORAGE, "no space left"),
         _ => (StatusCode::INTERNAL_SERVER_ERROR, "io error"),
@@ -781,7 +1360,7 @@ fn io_status(e: &std::io::Error) -> (StatusCode, &'static str) {
 /// Rename `from` over `to`. Atomic on Unix; Windows cannot rename over an
 /// existing file, so there we remove the target first (a tiny non-atomic window,
 /// accepted for the Windows agent, LLD-28 risk 2).
-async fn rename_replace(from: &Path, to: &Path) -> std::io::Result<()> {
+pub(crate) async fn rename_replace(from: &Path, to: &Path) -> std::io::Result<()> {
     match tokio::fs::rename(from, to).await {
         Ok(()) => Ok(()),
         Err(e) => {
@@ -798,7 +1377,7 @@ async fn rename_replace(from: &Path, to: &Path) -> std::io::Result<()> {
 
 /// Modification time in whole unix seconds (0 if the filesystem cannot say),
 /// matching the manifest builder so a seeded hash keys on the same value.
-fn mtime_secs(meta: &std::fs::Metadata) -> i64 {
+pub(crate) fn mtime_secs(meta: &std::fs::Metadata) -> i64 {
     meta.modified()
         .ok()
         .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
@@ -815,7 +1394,7 @@ fn hex_lower(bytes: &[u8]) -> String {
     s
 }
 
-fn now_unix() -> u64 {
+pub(crate) fn now_unix() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|d| d.as_secs())
@@ -849,13 +1428,20 @@ mod tests {
             identity: Some(SigningKey::from_bytes(&[77u8; 32])),
             max_file_mb,
             import: ImportManager::new(None, cache),
+            versions: ShareVersions::new(),
+            history: ManifestHistory::new(),
+            chunk_cache: ChunkCache::new(),
             expose: RwLock::new(Arc::new(Vec::new())),
+            trash: Some(crate::config::TrashConfig::default()),
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
         })
     }
 
     /// A directory share; `writable` opts into the write path (LLD-28).
     fn dir_share(path: PathBuf, writable: bool) -> ShareRoot {
-        ShareRoot { path, is_file: false, writable, import: false }
+        ShareRoot { path, is_file: false, writable, import: false, policy: Default::default() }
     }
 
     fn get_with_token(uri: &str, tok: Option<&ShareToken>) -> HttpRequest<Body> {
@@ -943,7 +1529,7 @@ mod tests {
         let file = dir.path().join("report.pdf");
         std::fs::write(&file, b"hello").unwrap();
         let mut shares = SharesMap::new();
-        shares.insert("F".into(), ShareRoot { path: file.canonicalize().unwrap(), is_file: true, writable: false, import: false });
+        shares.insert("F".into(), ShareRoot { path: file.canonicalize().unwrap(), is_file: true, writable: false, import: false, policy: Default::default() });
         let app = router(state_with(shares, &key));
         let tok = sign_share_token(&key, "F", SCOPE_READ, now_unix() + 1000);
 
@@ -1017,6 +1603,13 @@ mod tests {
             identity: None,
             max_file_mb: None,
             import: ImportManager::new(None, cache),
+            versions: ShareVersions::new(),
+            history: ManifestHistory::new(),
+            chunk_cache: ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(Vec::new())),
         });
         let app = router(state);
@@ -1090,6 +1683,150 @@ mod tests {
         assert_eq!(std::fs::read(dir.path().join("docs/a.txt")).unwrap(), b"world!!");
     }
 
+    /// The manifest version a response carries.
+    fn version_of(r: &Response) -> u64 {
+        r.headers()
+            .get(MANIFEST_VERSION_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .expect("ответ манифеста несёт версию")
+    }
+
+    #[tokio::test]
+    async fn manifest_wait_holds_until_a_write_and_times_out_with_304() {
+        let key = SigningKey::from_bytes(&[41u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let (app, wtok) = writable_app(&key, dir.path(), None);
+
+        // Без since ответ сразу: это и бутстрап, и версия для следующего ожидания.
+        let r = app
+            .clone()
+            .oneshot(get_with_token("/W/manifest/wait", Some(&wtok)))
+            .await
+            .unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        let v0 = version_of(&r);
+        assert!(r.headers().contains_key(MANIFEST_SIG_HEADER), "листинг подписан как обычно");
+
+        // С текущей версией запрос висит до конца удержания и отвечает 304.
+        let r = app
+            .clone()
+            .oneshot(get_with_token(&format!("/W/manifest/wait?since={v0}&timeout_secs=1"), Some(&wtok)))
+            .await
+            .unwrap();
+        assert_eq!(r.status(), StatusCode::NOT_MODIFIED);
+
+        // Запись будит висящий запрос новым листингом и новой версией.
+        let waiting = tokio::spawn(app.clone().oneshot(get_with_token(
+            &format!("/W/manifest/wait?since={v0}&timeout_secs=30"),
+            Some(&wtok),
+        )));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let put = app
+            .clone()
+            .oneshot(write_req("PUT", "/W/file/new.txt", Some(&wtok), &[], b"fresh"))
+            .await
+            .unwrap();
+        assert_eq!(put.status(), StatusCode::CREATED);
+        let r = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .expect("запись обязана разбудить ожидание")
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        assert!(version_of(&r) > v0);
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let m: ShareManifest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(m.entries[0].path, "new.txt");
+
+        // Ожидание под токен читателя, как и сам манифест.
+        let r = app
+            .clone()
+            .oneshot(get_with_token("/W/manifest/wait", None))
+            .await
+            .unwrap();
+        assert_eq!(r.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn manifest_with_a_known_base_is_a_signed_delta() {
+        let key = SigningKey::from_bytes(&[42u8; 32]);
+        let agent_vk = SigningKey::from_bytes(&[77u8; 32]).verifying_key();
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..4 {
+            std::fs::write(dir.path().join(format!("f{i}.txt")), b"x").unwrap();
+        }
+        let (app, wtok) = writable_app(&key, dir.path(), None);
+
+        let r = app.clone().oneshot(get_with_token("/W/manifest", Some(&wtok))).await.unwrap();
+        let v0 = version_of(&r);
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let full: ShareManifest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(full.version, v0, "версия подписана в теле листинга");
+
+        let put = app
+            .clone()
+            .oneshot(write_req("PUT", "/W/file/new.txt", Some(&wtok), &[], b"fresh"))
+            .await
+            .unwrap();
+        assert_eq!(put.status(), StatusCode::CREATED);
+        std::fs::remove_file(dir.path().join("f0.txt")).unwrap();
+
+        let r = app
+            .clone()
+            .oneshot(get_with_token(&format!("/W/manifest?base={v0}"), Some(&wtok)))
+            .await
+            .unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        assert_eq!(r.headers()[MANIFEST_DELTA_HEADER], v0.to_string().as_str());
+        let v1 = version_of(&r);
+        let sig = r.headers()[MANIFEST_SIG_HEADER].to_str().unwrap().to_string();
+        let signed_at: u64 = r.headers()[MANIFEST_SIGNED_AT_HEADER].to_str().unwrap().parse().unwrap();
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        xr_proto::share::verify_manifest_delta(&sig, &agent_vk, "W", signed_at, &body).unwrap();
+        assert!(xr_proto::share::verify_share_manifest(&sig, &agent_vk, "W", signed_at, &body).is_err());
+        let delta: ShareManifestDelta = serde_json::from_slice(&body).unwrap();
+        assert_eq!((delta.base, delta.version), (v0, v1));
+        assert_eq!(delta.changed.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), vec!["new.txt"]);
+        assert_eq!(delta.removed, vec!["f0.txt".to_string()]);
+
+        // Дельта поверх старого листинга даёт то же, что свежий листинг.
+        let mut applied = full;
+        delta.apply(&mut applied);
+        let r = app.clone().oneshot(get_with_token("/W/manifest", Some(&wtok))).await.unwrap();
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let fresh: ShareManifest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(applied, fresh);
+
+        // Незнакомая база отвечается полным листингом без метки дельты.
+        let r = app.oneshot(get_with_token("/W/manifest?base=12345", Some(&wtok))).await.unwrap();
+        assert!(!r.headers().contains_key(MANIFEST_DELTA_HEADER));
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        assert!(serde_json::from_slice::<ShareManifest>(&body).is_ok());
+    }
+
+    #[tokio::test]
+    async fn chunks_route_lists_a_file_under_the_read_gates() {
+        let key = SigningKey::from_bytes(&[43u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("big.bin"), vec![1u8; 100_000]).unwrap();
+        let (app, wtok) = writable_app(&key, dir.path(), None);
+
+        let r = app.clone().oneshot(get_with_token("/W/chunks/big.bin", Some(&wtok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let chunks: xr_proto::share::FileChunks = serde_json::from_slice(&body).unwrap();
+        assert_eq!(chunks.size, 100_000);
+        assert_eq!(chunks.sha256, hex_lower(&Sha256::digest(vec![1u8; 100_000])));
+
+        let r = app.clone().oneshot(get_with_token("/W/chunks/big.bin", None)).await.unwrap();
+        assert_eq!(r.status(), StatusCode::UNAUTHORIZED);
+        let r = app.clone().oneshot(get_with_token("/W/chunks/missing.bin", Some(&wtok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::NOT_FOUND);
+        let r = app.oneshot(get_with_token("/W/chunks/.xr-part-1", Some(&wtok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::FORBIDDEN);
+    }
+
     #[tokio::test]
     async fn test_put_requires_write_scope() {
         let key = SigningKey::from_bytes(&[21u8; 32]);
@@ -1266,6 +2003,166 @@ mod tests {
         assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none(), "no temp junk");
     }
 
+    #[tokio::test]
+    async fn test_put_share_policy_and_usage() {
+        let key = SigningKey::from_bytes(&[32u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut share = dir_share(dir.path().canonicalize().unwrap(), true);
+        share.policy = crate::config::SharePolicy {
+            quota_mb: Some(1),
+            max_files: Some(2),
+            extensions: vec!["bin".into()],
+            mime: vec!["image/*".into()],
+            reserve_free_mb: None,
+        };
+        let mut shares = SharesMap::new();
+        shares.insert("W".into(), share);
+        let app = router(state_with(shares, &key));
+        let wtok = sign_share_token(&key, "W", &rw_scope(), now_unix() + 1000);
+        let put = |uri: &'static str, hdr: Vec<(&'static str, String)>, body: Vec<u8>| {
+            app.clone().oneshot(write_req("PUT", uri, Some(&wtok), &hdr, &body))
+        };
+
+        assert_eq!(put("/W/file/run.exe", vec![], b"MZ".to_vec()).await.unwrap().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(put("/W/file/a.bin", vec![], vec![1; 600_000]).await.unwrap().status(), StatusCode::CREATED);
+
+        // Past the quota: refused up front by the declared length, and while
+        // streaming without one, leaving nothing behind.
+        let hdr = vec![("content-length", "600000".to_string())];
+        assert_eq!(put("/W/file/b.bin", hdr, vec![2; 600_000]).await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(put("/W/file/b.bin", vec![], vec![2; 600_000]).await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+        let junk = std::fs::read_dir(dir.path())
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(UPLOAD_TEMP_PREFIX))
+            .count();
+        assert_eq!((dir.path().join("b.bin").exists(), junk), (false, 0));
+
+        // Replacing a file frees its bytes; the file limit counts new files only.
+        assert_eq!(put("/W/file/a.bin", vec![], vec![3; 900_000]).await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(put("/W/file/p.png", vec![], vec![4; 10]).await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(put("/W/file/q.png", vec![], vec![5]).await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+
+        let r = app.clone().oneshot(get_with_token("/W/usage", Some(&wtok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let u: xr_proto::share::ShareUsage = serde_json::from_slice(&body).unwrap();
+        assert_eq!((u.used_bytes, u.files, u.quota_bytes, u.max_files), (900_010, 2, Some(1 << 20), Some(2)));
+        assert!(u.free_bytes.is_some_and(|f| f <= (1 << 20) - 900_010), "{u:?}");
+        assert!(u.writable && u.mime == ["image/*"]);
+        let r = app.oneshot(get_with_token("/W/usage", None)).await.unwrap();
+        assert_eq!(r.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// Две загрузки сразу не занимают одно место: идущая держит бронь, пока
+    /// не кончится, удачно или обрывом.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_puts_reserve_their_room() {
+        use tokio::io::AsyncReadExt;
+
+        let key = SigningKey::from_bytes(&[33u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut share = dir_share(dir.path().canonicalize().unwrap(), true);
+        share.policy = crate::config::SharePolicy { quota_mb: Some(1), ..Default::default() };
+        let mut shares = SharesMap::new();
+        shares.insert("W".into(), share);
+        let app = router(state_with(shares, &key));
+        let wtok = sign_share_token(&key, "W", &rw_scope(), now_unix() + 1000);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let served = app.clone();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, served).await;
+        });
+
+        // Загрузка по сети, тело пока не целиком.
+        let started = |path: &'static str, len: usize| {
+            let auth = blob(&wtok);
+            async move {
+                let mut sock = tokio::net::TcpStream::connect(addr).await.unwrap();
+                let head = format!(
+                    "PUT /W/file/{path} HTTP/1.1\r\nhost: x\r\nauthorization: Bearer {auth}\r\ncontent-length: {len}\r\n\r\n"
+                );
+                sock.write_all(head.as_bytes()).await.unwrap();
+                sock.write_all(&[1; 1000]).await.unwrap();
+                // Пусть обработчик успеет дойти до брони.
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                sock
+            }
+        };
+        let put = |uri: &'static str, len: usize| {
+            let hdr = [("content-length", len.to_string())];
+            app.clone().oneshot(write_req("PUT", uri, Some(&wtok), &hdr, &vec![2; len]))
+        };
+
+        let mut a = started("a.bin", 600_000).await;
+        assert_eq!(put("/W/file/b.bin", 600_000).await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+        a.write_all(&vec![1; 599_000]).await.unwrap();
+        let mut resp = vec![0u8; 12];
+        a.read_exact(&mut resp).await.unwrap();
+        assert_eq!(&resp, b"HTTP/1.1 201");
+
+        // Оборванная загрузка отдаёт бронь.
+        let c = started("c.bin", 400_000).await;
+        assert_eq!(put("/W/file/d.bin", 300_000).await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+        drop(c);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(put("/W/file/d.bin", 300_000).await.unwrap().status(), StatusCode::CREATED);
+        assert!(!dir.path().join("b.bin").exists() && !dir.path().join("c.bin").exists());
+    }
+
+    /// Загрузка без `Content-Length` бронирует то, что уже записала, а не весь
+    /// остаток: две такие сразу обе проходят, пока влезают вместе.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_chunked_puts_both_fit() {
+        use tokio::io::AsyncReadExt;
+
+        let key = SigningKey::from_bytes(&[34u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut share = dir_share(dir.path().canonicalize().unwrap(), true);
+        share.policy = crate::config::SharePolicy { quota_mb: Some(1), ..Default::default() };
+        let mut shares = SharesMap::new();
+        shares.insert("W".into(), share);
+        let app = router(state_with(shares, &key));
+        let wtok = sign_share_token(&key, "W", &rw_scope(), now_unix() + 1000);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let chunk = |n: usize| {
+            let mut c = format!("{n:x}\r\n").into_bytes();
+            c.extend(vec![1; n]);
+            c.extend(b"\r\n");
+            c
+        };
+        let mut socks = Vec::new();
+        for path in ["a.bin", "b.bin"] {
+            let mut sock = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let head = format!(
+                "PUT /W/file/{path} HTTP/1.1\r\nhost: x\r\nauthorization: Bearer {}\r\ntransfer-encoding: chunked\r\n\r\n",
+                blob(&wtok)
+            );
+            sock.write_all(head.as_bytes()).await.unwrap();
+            sock.write_all(&chunk(1000)).await.unwrap();
+            socks.push(sock);
+        }
+        // Обе уже пишут и держат бронь.
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        for sock in &mut socks {
+            sock.write_all(&chunk(400_000)).await.unwrap();
+            sock.write_all(b"0\r\n\r\n").await.unwrap();
+        }
+        for mut sock in socks {
+            let mut resp = vec![0u8; 12];
+            sock.read_exact(&mut resp).await.unwrap();
+            assert_eq!(&resp, b"HTTP/1.1 201");
+        }
+        assert_eq!(std::fs::metadata(dir.path().join("a.bin")).unwrap().len(), 401_000);
+        assert_eq!(std::fs::metadata(dir.path().join("b.bin")).unwrap().len(), 401_000);
+    }
+
     #[tokio::test]
     async fn test_delete_file() {
         let key = SigningKey::from_bytes(&[27u8; 32]);
@@ -1305,6 +2202,180 @@ mod tests {
         assert!(dir.path().join("sub/b.txt").exists());
     }
 
+    /// Удаление и перезапись откладывают прежнюю версию в корзину, откуда её
+    /// возвращает `restore`; список и возврат требуют права записи.
+    #[tokio::test]
+    async fn test_trash_keeps_and_restores_versions() {
+        let key = SigningKey::from_bytes(&[28u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let (app, wtok) = writable_app(&key, dir.path(), None);
+        let send = |method: &str, uri: &str, body: &[u8]| {
+            app.clone().oneshot(write_req(method, uri, Some(&wtok), &[], body))
+        };
+
+        assert_eq!(send("PUT", "/W/file/a.txt", b"v1").await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(send("PUT", "/W/file/a.txt", b"v2").await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(send("DELETE", "/W/file/a.txt", b"").await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert!(!dir.path().join("a.txt").exists());
+        // Корзина не видна в манифесте.
+        assert!(manifest_paths(&app, "W", &wtok).await.is_empty());
+
+        let r = app.clone().oneshot(get_with_token("/W/trash?path=a.txt", Some(&wtok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        let body = r.into_body().collect().await.unwrap().to_bytes();
+        let items: Vec<trash::TrashItem> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(items.len(), 2);
+        let v1 = items.iter().find(|i| i.reason == Reason::Replaced).expect("заменённая версия");
+        assert_eq!(v1.size, 2);
+
+        // Без права записи корзины не видно.
+        let rtok = sign_share_token(&key, "W", SCOPE_READ, now_unix() + 1000);
+        let r = app.clone().oneshot(get_with_token("/W/trash", Some(&rtok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::FORBIDDEN);
+
+        // Первую версию на новое место, затем её же повторно: уже нет.
+        let uri = format!("/W/trash/{}/restore?to=old/a.txt", v1.id);
+        assert_eq!(send("POST", &uri, b"").await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(std::fs::read(dir.path().join("old/a.txt")).unwrap(), b"v1");
+        assert_eq!(send("POST", &uri, b"").await.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(manifest_paths(&app, "W", &wtok).await, vec!["old/a.txt".to_string()]);
+
+        // Вне шары восстановить нельзя.
+        let v2 = items.iter().find(|i| i.reason == Reason::Deleted).unwrap();
+        let uri = format!("/W/trash/{}/restore?to=../x", v2.id);
+        assert_eq!(send("POST", &uri, b"").await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    /// Возврат из корзины проходит политику шары, как загрузка той же версии.
+    #[tokio::test]
+    async fn test_restore_obeys_the_share_policy() {
+        let key = SigningKey::from_bytes(&[34u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut share = dir_share(dir.path().canonicalize().unwrap(), true);
+        share.policy = crate::config::SharePolicy { quota_mb: Some(1), extensions: vec!["bin".into()], ..Default::default() };
+        let mut shares = SharesMap::new();
+        shares.insert("W".into(), share);
+        let app = router(state_with(shares, &key));
+        let wtok = sign_share_token(&key, "W", &rw_scope(), now_unix() + 1000);
+        let send = |method: &str, uri: &str, body: &[u8]| {
+            app.clone().oneshot(write_req(method, uri, Some(&wtok), &[], body))
+        };
+
+        assert_eq!(send("PUT", "/W/file/a.bin", &[1; 600_000]).await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(send("DELETE", "/W/file/a.bin", b"").await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(send("PUT", "/W/file/b.bin", &[2; 600_000]).await.unwrap().status(), StatusCode::CREATED);
+        let items: Vec<trash::TrashItem> = {
+            let r = app.clone().oneshot(get_with_token("/W/trash", Some(&wtok))).await.unwrap();
+            serde_json::from_slice(&r.into_body().collect().await.unwrap().to_bytes()).unwrap()
+        };
+        let id = &items[0].id;
+
+        // Чужой тип и место сверх квоты.
+        let uri = format!("/W/trash/{id}/restore?to=a.exe");
+        assert_eq!(send("POST", &uri, b"").await.unwrap().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let uri = format!("/W/trash/{id}/restore");
+        assert_eq!(send("POST", &uri, b"").await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+        assert!(!dir.path().join("a.bin").exists());
+
+        // Поверх файла того же размера место есть: его байты уходят в корзину.
+        let uri = format!("/W/trash/{id}/restore?to=b.bin");
+        assert_eq!(send("POST", &uri, b"").await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read(dir.path().join("b.bin")).unwrap(), vec![1; 600_000]);
+    }
+
+    #[tokio::test]
+    async fn test_archive_streams_a_folder() {
+        let key = SigningKey::from_bytes(&[29u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("Фото/2024")).unwrap();
+        std::fs::write(dir.path().join("Фото/2024/a.jpg"), b"jpeg").unwrap();
+        std::fs::write(dir.path().join("Фото/.xr-meta.json"), b"{}").unwrap();
+        std::fs::write(dir.path().join("top.txt"), b"t").unwrap();
+        let (app, _) = writable_app(&key, dir.path(), None);
+        let rtok = sign_share_token(&key, "W", SCOPE_READ, now_unix() + 1000);
+        let get = |uri: &str, tok: Option<&ShareToken>| app.clone().oneshot(get_with_token(uri, tok));
+
+        let r = get("/W/archive?path=%D0%A4%D0%BE%D1%82%D0%BE&format=tar", Some(&rtok)).await.unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        assert_eq!(r.headers()[header::CONTENT_TYPE], "application/x-tar");
+        let cd = r.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().to_string();
+        assert!(cd.contains("filename*=UTF-8''%D0%A4%D0%BE%D1%82%D0%BE%2Etar"), "{cd}");
+        let bytes = r.into_body().collect().await.unwrap().to_bytes();
+        let mut names: Vec<String> = tar::Archive::new(&bytes[..])
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["SHA256SUMS", "Фото/", "Фото/2024/", "Фото/2024/a.jpg"]);
+
+        // Вся шара по умолчанию и ZIP по умолчанию.
+        let r = get("/W/archive", Some(&rtok)).await.unwrap();
+        assert_eq!(r.headers()[header::CONTENT_TYPE], "application/zip");
+
+        for (uri, tok, want) in [
+            ("/W/archive", None, StatusCode::UNAUTHORIZED),
+            ("/W/archive?path=..", Some(&rtok), StatusCode::FORBIDDEN),
+            ("/W/archive?path=.xr-trash", Some(&rtok), StatusCode::FORBIDDEN),
+            ("/W/archive?path=top.txt", Some(&rtok), StatusCode::CONFLICT),
+            ("/W/archive?path=nope", Some(&rtok), StatusCode::NOT_FOUND),
+            ("/W/archive?format=rar", Some(&rtok), StatusCode::BAD_REQUEST),
+        ] {
+            assert_eq!(get(uri, tok).await.unwrap().status(), want, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_thumb_served_cached_and_advertised() {
+        let key = SigningKey::from_bytes(&[31u8; 32]);
+        let dir = tempfile::tempdir().unwrap();
+        image::RgbImage::from_pixel(200, 100, image::Rgb([10, 200, 30]))
+            .save(dir.path().join("photo.png"))
+            .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"text").unwrap();
+        let mut shares = SharesMap::new();
+        shares.insert("P".into(), dir_share(dir.path().canonicalize().unwrap(), false));
+        let mut state = state_with(shares, &key);
+        let cfg = crate::config::ThumbsConfig { size: 50, ..Default::default() };
+        Arc::get_mut(&mut state).unwrap().thumbs = Some(crate::thumbs::Thumbs::new(cfg, dir.path()));
+        let app = router(state);
+        let tok = sign_share_token(&key, "P", SCOPE_READ, now_unix() + 1000);
+
+        let r = app.clone().oneshot(get_with_token("/P/manifest", Some(&tok))).await.unwrap();
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let m: ShareManifest = serde_json::from_slice(&body).unwrap();
+        let previews: Vec<_> = m.entries.iter().map(|e| (e.path.as_str(), e.preview)).collect();
+        assert_eq!(
+            previews,
+            [("notes.txt", None), ("photo.png", Some(xr_proto::share::PreviewKind::Image))]
+        );
+
+        let r = app.clone().oneshot(get_with_token("/P/thumb/photo.png", Some(&tok))).await.unwrap();
+        assert_eq!(r.status(), StatusCode::OK);
+        assert_eq!(r.headers()[header::CONTENT_TYPE], "image/jpeg");
+        let etag = r.headers()[header::ETAG].clone();
+        let body = axum::body::to_bytes(r.into_body(), 1 << 20).await.unwrap();
+        let img = image::load_from_memory(&body).unwrap();
+        assert_eq!((img.width(), img.height()), (50, 25));
+        // Cached under the reserved prefix, so the listing does not grow.
+        assert!(dir.path().join(crate::thumbs::THUMBS_DIR).is_dir());
+        assert_eq!(manifest_paths(&app, "P", &tok).await, ["notes.txt", "photo.png"]);
+
+        let mut req = get_with_token("/P/thumb/photo.png", Some(&tok));
+        req.headers_mut().insert(header::IF_NONE_MATCH, etag);
+        assert_eq!(app.clone().oneshot(req).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+
+        for (uri, tok, want) in [
+            ("/P/thumb/photo.png", None, StatusCode::UNAUTHORIZED),
+            ("/P/thumb/notes.txt", Some(&tok), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            ("/P/thumb/gone.png", Some(&tok), StatusCode::NOT_FOUND),
+            ("/P/thumb/.xr-thumbs/x.jpg", Some(&tok), StatusCode::FORBIDDEN),
+            ("/Q/thumb/photo.png", Some(&tok), StatusCode::NOT_FOUND),
+        ] {
+            assert_eq!(app.clone().oneshot(get_with_token(uri, tok)).await.unwrap().status(), want, "{uri}");
+        }
+    }
+
     #[tokio::test]
     async fn test_file_share_not_writable() {
         // A file share is never writable, even if the config asked (build_shares
@@ -1320,6 +2391,7 @@ mod tests {
             writable: true,
             import: false,
             attached: false,
+            policy: None,
         }];
         let shares = build_shares(&entries);
         assert!(!shares.get("F").unwrap().writable, "a file share must not be writable");
@@ -1369,11 +2441,21 @@ mod tests {
         dir: &Path,
         cfg: Option<ImportConfig>,
         max_file_mb: Option<u64>,
+    ) -> (Router, ShareToken) {
+        import_app_with_policy(key, dir, cfg, max_file_mb, Default::default())
+    }
+
+    fn import_app_with_policy(
+        key: &SigningKey,
+        dir: &Path,
+        cfg: Option<ImportConfig>,
+        max_file_mb: Option<u64>,
+        policy: crate::config::SharePolicy,
     ) -> (Router, ShareToken) {
         let mut shares = SharesMap::new();
         shares.insert(
             "I".into(),
-            ShareRoot { path: dir.canonicalize().unwrap(), is_file: false, writable: true, import: true },
+            ShareRoot { path: dir.canonicalize().unwrap(), is_file: false, writable: true, import: true, policy },
         );
         let cache = Arc::new(HashCache::new());
         let state = Arc::new(AgentState {
@@ -1383,6 +2465,13 @@ mod tests {
             identity: Some(SigningKey::from_bytes(&[77u8; 32])),
             max_file_mb,
             import: ImportManager::new(cfg, cache),
+            versions: ShareVersions::new(),
+            history: ManifestHistory::new(),
+            chunk_cache: ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(Vec::new())),
         });
         state.import.spawn_runner();
@@ -1682,7 +2771,7 @@ mod tests {
         let mut shares = SharesMap::new();
         shares.insert(
             "I".into(),
-            ShareRoot { path: share.path().canonicalize().unwrap(), is_file: false, writable: true, import: false },
+            ShareRoot { path: share.path().canonicalize().unwrap(), is_file: false, writable: true, import: false, policy: Default::default() },
         );
         let cache = Arc::new(HashCache::new());
         let no_flag = router(Arc::new(AgentState {
@@ -1692,6 +2781,13 @@ mod tests {
             identity: None,
             max_file_mb: None,
             import: ImportManager::new(Some(cfg), cache),
+            versions: ShareVersions::new(),
+            history: ManifestHistory::new(),
+            chunk_cache: ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(Vec::new())),
         }));
         let (status, _) = post_import(&no_flag, Some(&tok), "/I/import", body.clone()).await;
@@ -1784,6 +2880,27 @@ mod tests {
         ).await;
         let v = wait_finished(&app, &tok, v["job_id"].as_str().unwrap()).await;
         assert_eq!(v["state"], "failed", "{v}");
+
+        // The share's policy: a type it does not take fails the job, a file
+        // past the quota too, and a full share refuses the job up front.
+        let plugin = |script: &str| Some(one_plugin(script, &["{url}"], &["*"], 1080));
+        let photos = crate::config::SharePolicy { quota_mb: Some(1), mime: vec!["video/*".into()], ..Default::default() };
+        for (body, want) in [
+            ("echo x > setup.exe", "тип файла"),
+            ("head -c 2097160 /dev/zero > big.mp4", "quota_mb"),
+        ] {
+            let script = write_script(bin.path(), body);
+            let (app, tok) = import_app_with_policy(&key, share.path(), plugin(&script), None, photos.clone());
+            let (_, v) = post_import(&app, Some(&tok), "/I/import", serde_json::json!({ "url": PUB_URL, "dest": "" })).await;
+            let v = wait_finished(&app, &tok, v["job_id"].as_str().unwrap()).await;
+            assert_eq!(v["state"], "failed", "{v}");
+            assert!(v["error"].as_str().unwrap().contains(want), "{v}");
+        }
+        assert!(no_job_dirs(share.path()));
+        std::fs::write(share.path().join("full.mp4"), vec![0u8; 1 << 20]).unwrap();
+        let (app, tok) = import_app_with_policy(&key, share.path(), plugin("true"), None, photos);
+        let (status, _) = post_import(&app, Some(&tok), "/I/import", serde_json::json!({ "url": PUB_URL, "dest": "" })).await;
+        assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
     }
 
     #[cfg(unix)]
@@ -1815,6 +2932,7 @@ mod tests {
             max_total_bytes: cap,
             max_file_bytes: None,
             sandbox: "none".into(),
+            policy: Default::default(),
         };
         let wait = |mgr: Arc<ImportManager>, id: String| async move {
             for _ in 0..600 {
@@ -1949,7 +3067,7 @@ mod tests {
         for (id, dir) in [("I", dir_i.path()), ("J", dir_j.path())] {
             shares.insert(
                 id.into(),
-                ShareRoot { path: dir.canonicalize().unwrap(), is_file: false, writable: true, import: true },
+                ShareRoot { path: dir.canonicalize().unwrap(), is_file: false, writable: true, import: true, policy: Default::default() },
             );
         }
         let cache = Arc::new(HashCache::new());
@@ -1960,6 +3078,13 @@ mod tests {
             identity: None,
             max_file_mb: None,
             import: ImportManager::new(Some(one_plugin(&script, &["{url}"], &["*"], 1080)), cache),
+            versions: ShareVersions::new(),
+            history: ManifestHistory::new(),
+            chunk_cache: ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
             expose: RwLock::new(Arc::new(Vec::new())),
         });
         state.import.spawn_runner();
@@ -0,0 +1,646 @@
+//! Two-way sync of a local folder with a writable share:
+//! `xr-share sync <dir> --invite <token>` (LLD-28).
+//!
+//! `pull` mirrors one way and `push` writes single files; this keeps a laptop
+//! folder and a writable share in step both ways. What tells a local edit from
+//! a remote one is the **base**: the content hash of every path as of the last
+//! sync, kept in the folder itself (`.xr-sync/state.json`, under the reserved
+//! `.xr-` prefix, so neither the walk here nor an agent serving the folder ever
+//! lists it). Each run compares base, local and the agent's manifest path by
+//! path ([`plan`]): a side that moved off the base wins over one that did not,
+//! and when both moved to different content the local edit is kept under a
+//! conflict name and uploaded next to the remote version instead of
+//! overwriting either.
+//!
+//! Every write carries the agent's precondition (LLD-28 п. 2.3): an upload over
+//! a known version goes with `If-Match` on it, a new file with
+//! `If-None-Match: *`, a delete with `If-Match`. A change that lands on the
+//! agent between the manifest read and the write fails that one path with
+//! `412`; its base stays as it was, so the next run sees the conflict and
+//! resolves it like any other. The same goes for any per-path failure: the run
+//! continues, and the next one picks the path up again. Same `ureq` transport
+//! as `pull`/`push`, so the agent binary still cross-compiles to Windows.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use xr_proto::share::{ShareManifest, ShareManifestEntry};
+
+use crate::manifest::{mtime_secs, walk_share};
+use crate::pull::{download_verify, encode_path, get_json, safe_join, InviteShareDto, HUB_DEFAULT};
+use crate::push::{delete_file, ensure_writable_grant, put_file, select_share, sha256_file, Guard};
+
+#[derive(Args)]
+pub struct SyncArgs {
+    /// Local folder to keep in sync with the share (created if missing).
+    pub dir: String,
+    /// Invite token granting write access (the access anchor, LLD-19 п. 9.5).
+    #[arg(long)]
+    pub invite: String,
+    /// Hub base URL (default https://xr-hub.zoobr.top).
+    #[arg(long)]
+    pub hub: Option<String>,
+    /// Which share to sync with, by its share_id or name. May be omitted when
+    /// the invite carries a single share.
+    #[arg(long)]
+    pub share: Option<String>,
+    /// Reach the agent over https (default http).
+    #[arg(long)]
+    pub https: bool,
+    /// Print what would be done without touching either side.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Where the sync state lives inside the folder.
+const STATE_DIR: &str = ".xr-sync";
+const STATE_FILE: &str = "state.json";
+const STATE_VERSION: u32 = 1;
+
+/// What the last sync left behind: the share the folder belongs to and, per
+/// path, the content both sides agreed on.
+#[derive(Default, Serialize, Deserialize)]
+struct SyncState {
+    version: u32,
+    share_id: String,
+    files: BTreeMap<String, Synced>,
+}
+
+/// One path as of the last sync. The local stat rides along so an untouched
+/// file is not re-hashed on every run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Synced {
+    sha256: String,
+    size: u64,
+    mtime: i64,
+}
+
+/// One step of a two-way sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    /// Both sides hold the same content: only the base moves.
+    Agree(String),
+    /// Gone on both sides: drop it from the base.
+    Forget(String),
+    /// Local content goes up. `guard` is the version it replaces on the agent
+    /// (`If-Match`), `None` for a path the agent must not have yet.
+    Upload { path: String, guard: Option<String> },
+    /// Remote content comes down over whatever is here.
+    Download(String),
+    /// Removed here: remove it on the agent, if it still holds `sha256`.
+    DeleteRemote { path: String, sha256: String },
+    /// Removed on the agent: remove it here.
+    DeleteLocal(String),
+    /// Both sides changed it differently: the local edit moves to `copy` and
+    /// goes up as a new file, the remote version takes `path`.
+    Conflict { path: String, copy: String },
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    actions: Vec<Action>,
+    /// Paths the agent lists but has not hashed yet (cold cache): nothing to
+    /// compare against, they wait for the next run.
+    deferred: Vec<String>,
+}
+
+/// Three-way plan over path → content hash maps. `remote` holds `""` for an
+/// entry the agent has not hashed. `tag` names conflict copies.
+fn plan(
+    base: &BTreeMap<String, String>,
+    local: &BTreeMap<String, String>,
+    remote: &BTreeMap<String, String>,
+    tag: &str,
+) -> Plan {
+    let mut out = Plan::default();
+    let mut taken: HashSet<String> = base.keys().chain(local.keys()).chain(remote.keys()).cloned().collect();
+    let paths: BTreeSet<&String> = base.keys().chain(local.keys()).chain(remote.keys()).collect();
+    for path in paths {
+        let (b, l, r) = (base.get(path), local.get(path), remote.get(path));
+        if r.is_some_and(|r| r.is_empty()) {
+            out.deferred.push(path.clone());
+            continue;
+        }
+        let action = match (l, r) {
+            (Some(l), Some(r)) if l == r => Action::Agree(path.clone()),
+            (None, None) => Action::Forget(path.clone()),
+            // Only the local side moved off the base.
+            (Some(_), _) if r == b => Action::Upload { path: path.clone(), guard: r.cloned() },
+            (None, Some(r)) if b == Some(r) => Action::DeleteRemote { path: path.clone(), sha256: r.clone() },
+            // Only the remote side moved.
+            (_, Some(_)) if l == b => Action::Download(path.clone()),
+            (Some(_), None) if l == b => Action::DeleteLocal(path.clone()),
+            // Both moved. An edit beats a removal on either side; two edits
+            // keep both.
+            (Some(_), None) => Action::Upload { path: path.clone(), guard: None },
+            (None, Some(_)) => Action::Download(path.clone()),
+            (Some(_), Some(_)) => {
+                let copy = conflict_name(path, tag, &taken);
+                taken.insert(copy.clone());
+                Action::Conflict { path: path.clone(), copy }
+            }
+        };
+        out.actions.push(action);
+    }
+    out
+}
+
+/// `dir/report.docx` → `dir/report (tag).docx`, numbered past names already
+/// in use on either side.
+fn conflict_name(path: &str, tag: &str, taken: &HashSet<String>) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((d, n)) => (format!("{d}/"), n),
+        None => (String::new(), path),
+    };
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((s, e)) if !s.is_empty() => (s, format!(".{e}")),
+        _ => (name, String::new()),
+    };
+    let mut n = 1;
+    loop {
+        let label = if n == 1 { tag.to_string() } else { format!("{tag} {n}") };
+        let candidate = format!("{dir}{stem} ({label}){ext}");
+        if !taken.contains(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Two-way sync of `args.dir` with a writable share (the `sync` subcommand).
+pub fn sync(args: SyncArgs) -> Result<()> {
+    let hub = args.hub.clone().unwrap_or_else(|| HUB_DEFAULT.to_string());
+    let share = match &args.share {
+        Some(want) => select_share(&hub, &args.invite, want)?,
+        None => only_share(&hub, &args.invite)?,
+    };
+    ensure_writable_grant(&share)?;
+
+    let root = PathBuf::from(&args.dir);
+    std::fs::create_dir_all(&root).with_context(|| format!("создание {}", root.display()))?;
+    let state_path = root.join(STATE_DIR).join(STATE_FILE);
+    let mut state = load_state(&state_path);
+    if state.share_id.is_empty() {
+        state.share_id = share.share_id.clone();
+    } else if state.share_id != share.share_id {
+        bail!(
+            "папка {} уже синхронизируется с другой шарой ({}); для «{}» возьми другую папку",
+            root.display(),
+            state.share_id,
+            share.name
+        );
+    }
+
+    let scheme = if args.https { "https" } else { "http" };
+    let (base_url, manifest) = crate::pull::resolve_base(scheme, &share)
+        .with_context(|| format!("шара «{}» недоступна", share.name))?;
+    reconcile(&root, state, &share, &base_url, &manifest, args.dry_run)
+}
+
+/// One run against an agent already located: scan, plan, apply, save the base.
+fn reconcile(
+    root: &Path,
+    mut state: SyncState,
+    share: &InviteShareDto,
+    base_url: &str,
+    manifest: &ShareManifest,
+    dry_run: bool,
+) -> Result<()> {
+    let state_path = root.join(STATE_DIR).join(STATE_FILE);
+    let local = scan_local(root, &state.files)?;
+
+    let base: BTreeMap<String, String> =
+        state.files.iter().map(|(p, s)| (p.clone(), s.sha256.clone())).collect();
+    let local_sha: BTreeMap<String, String> =
+        local.iter().map(|(p, s)| (p.clone(), s.sha256.clone())).collect();
+    let remote_sha: BTreeMap<String, String> =
+        manifest.entries.iter().map(|e| (e.path.clone(), e.sha256.to_ascii_lowercase())).collect();
+    let tag = format!("конфликт {}", crate::setup::hostname());
+    let plan = plan(&base, &local_sha, &remote_sha, &tag);
+
+    let remote: HashMap<&str, &ShareManifestEntry> =
+        manifest.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+    let run = Run { root, base_url, share, local: &local, remote: &remote };
+    let (mut done, mut failed) = (0usize, 0usize);
+    for action in &plan.actions {
+        if let Some(line) = describe(action) {
+            println!("  {line}");
+        }
+        if dry_run {
+            continue;
+        }
+        match run.apply(action, &mut state.files) {
+            Ok(()) => done += usize::from(describe(action).is_some()),
+            Err(e) => {
+                failed += 1;
+                eprintln!("    ошибка: {e:#}");
+            }
+        }
+    }
+    for path in &plan.deferred {
+        println!("  отложен до следующего синка (агент ещё не посчитал хеш): {path}");
+    }
+    if dry_run {
+        println!("Пробный прогон: ничего не изменено");
+        return Ok(());
+    }
+    state.version = STATE_VERSION;
+    save_state(&state_path, &state)?;
+    println!("Готово: {done} изменени(й), ошибок {failed}");
+    if failed > 0 {
+        bail!("{failed} путь(ей) не синхронизировано, повтори синк");
+    }
+    Ok(())
+}
+
+/// The invite's only share, when `--share` is omitted.
+pub(crate) fn only_share(hub: &str, invite: &str) -> Result<InviteShareDto> {
+    let url = format!("{}/api/v1/invite/{}/shares", hub.trim_end_matches('/'), invite);
+    let mut shares: Vec<InviteShareDto> = get_json(&url, None).context("список шар по инвайту")?;
+    match shares.len() {
+        1 => Ok(shares.remove(0)),
+        0 => bail!("на инвайте нет шар"),
+        _ => {
+            let names: Vec<&str> = shares.iter().map(|s| s.name.as_str()).collect();
+            bail!("на инвайте несколько шар, выбери одну через --share: {}", names.join(", "))
+        }
+    }
+}
+
+/// A line for the operator, `None` for the silent bookkeeping steps.
+fn describe(action: &Action) -> Option<String> {
+    Some(match action {
+        Action::Agree(_) | Action::Forget(_) => return None,
+        Action::Upload { path, .. } => format!("↑ {path}"),
+        Action::Download(path) => format!("↓ {path}"),
+        Action::DeleteRemote { path, .. } => format!("удалён на агенте: {path}"),
+        Action::DeleteLocal(path) => format!("удалён здесь: {path}"),
+        Action::Conflict { path, copy } => format!("конфликт: {path}, локальная правка -> {copy}"),
+    })
+}
+
+/// One sync run's fixed context.
+struct Run<'a> {
+    root: &'a Path,
+    base_url: &'a str,
+    share: &'a InviteShareDto,
+    local: &'a BTreeMap<String, Synced>,
+    remote: &'a HashMap<&'a str, &'a ShareManifestEntry>,
+}
+
+impl Run<'_> {
+    /// Carry out one action and move the base for its path(s) on success.
+    fn apply(&self, action: &Action, files: &mut BTreeMap<String, Synced>) -> Result<()> {
+        match action {
+            Action::Agree(path) => {
+                if let Some(l) = self.local.get(path) {
+                    files.insert(path.clone(), l.clone());
+                }
+            }
+            Action::Forget(path) => {
+                files.remove(path);
+            }
+            Action::Upload { path, guard } => {
+                let (abs, l) = self.unchanged_local(path)?;
+                let guard = guard.as_deref().map_or(Guard::Absent, Guard::Match);
+                put_file(self.base_url, self.share, path, &abs, guard, &l.sha256)?;
+                files.insert(path.clone(), l.clone());
+            }
+            Action::Download(path) => {
+                if self.local.contains_key(path) {
+                    self.unchanged_local(path)?;
+                }
+                files.insert(path.clone(), self.download(path)?);
+            }
+            Action::DeleteRemote { path, sha256 } => {
+                delete_file(self.base_url, self.share, path, Some(sha256))?;
+                files.remove(path);
+            }
+            Action::DeleteLocal(path) => {
+                let (abs, _) = self.unchanged_local(path)?;
+                std::fs::remove_file(&abs).with_context(|| format!("удаление {}", abs.display()))?;
+                prune_empty_dirs(self.root, &abs);
+                files.remove(path);
+            }
+            Action::Conflict { path, copy } => {
+                let (abs, l) = self.unchanged_local(path)?;
+                let copy_abs = safe_join(self.root, copy)?;
+                // The rename keeps the mtime, so the copy's stat is the edit's.
+                std::fs::rename(&abs, &copy_abs)
+                    .with_context(|| format!("переименование в {}", copy_abs.display()))?;
+                put_file(self.base_url, self.share, copy, &copy_abs, Guard::Absent, &l.sha256)?;
+                files.insert(copy.clone(), l.clone());
+                files.insert(path.clone(), self.download(path)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// The local file as the scan saw it. One touched since then is left for
+    /// the next run rather than overwritten, removed or uploaded half-written.
+    fn unchanged_local(&self, path: &str) -> Result<(PathBuf, &Synced)> {
+        let abs = safe_join(self.root, path)?;
+        let l = self.local.get(path).with_context(|| format!("нет локального файла {path}"))?;
+        let meta = std::fs::metadata(&abs).with_context(|| format!("stat {}", abs.display()))?;
+        if meta.len() != l.size || mtime_secs(&meta) != l.mtime {
+            bail!("{path} изменился во время синка, он уйдёт следующим");
+        }
+        Ok((abs, l))
+    }
+
+    fn download(&self, path: &str) -> Result<Synced> {
+        let entry = self.remote.get(path).with_context(|| format!("{path} нет в манифесте"))?;
+        let abs = safe_join(self.root, path)?;
+        download_verify(
+            &format!("{}/file/{}", self.base_url, encode_path(path)),
+            &self.share.token,
+            &abs,
+            &entry.sha256,
+        )
+        .with_context(|| format!("скачивание {path}"))?;
+        let meta = std::fs::metadata(&abs).with_context(|| format!("stat {}", abs.display()))?;
+        Ok(Synced { sha256: entry.sha256.to_ascii_lowercase(), size: meta.len(), mtime: mtime_secs(&meta) })
+    }
+}
+
+/// Hash every regular file under `root`, reusing the base's hash for a file
+/// whose size and mtime have not moved. The state directory and `pull`'s
+/// partials are not content.
+fn scan_local(root: &Path, known: &BTreeMap<String, Synced>) -> Result<BTreeMap<String, Synced>> {
+    let mut out = BTreeMap::new();
+    for entry in walk_share(root) {
+        let entry = entry.context("обход папки")?;
+        if !entry.file_type().is_file() || entry.file_name().to_string_lossy().ends_with(".xrpull-part") {
+            continue;
+        }
+        let rel: Vec<String> = entry
+            .path()
+            .strip_prefix(root)
+            .unwrap_or(entry.path())
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let rel = rel.join("/");
+        let meta = entry.metadata().context("stat")?;
+        let (size, mtime) = (meta.len(), mtime_secs(&meta));
+        let sha256 = match known.get(&rel) {
+            Some(k) if k.size == size && k.mtime == mtime => k.sha256.clone(),
+            _ => sha256_file(entry.path()).with_context(|| format!("хеш {rel}"))?,
+        };
+        out.insert(rel, Synced { sha256, size, mtime });
+    }
+    Ok(out)
+}
+
+/// A missing, corrupt or foreign-version state is a first sync.
+fn load_state(path: &Path) -> SyncState {
+    std::fs::read(path)
+        .ok()
+        .and_then(|b| serde_json::from_slice::<SyncState>(&b).ok())
+        .filter(|s| s.version == STATE_VERSION)
+        .unwrap_or_default()
+}
+
+/// Write the state atomically: a torn file would read as a first sync and turn
+/// every local edit into a conflict.
+fn save_state(path: &Path, state: &SyncState) -> Result<()> {
+    let dir = path.parent().context("путь состояния без каталога")?;
+    std::fs::create_dir_all(dir).with_context(|| format!("создание {}", dir.display()))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec(&state)?).with_context(|| format!("запись {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("запись {}", path.display()))
+}
+
+/// Remove now-empty parent directories up to (not including) `root`.
+fn prune_empty_dirs(root: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(d) = dir {
+        if d == root || !d.starts_with(root) || std::fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(rows: &[(&str, &str)]) -> BTreeMap<String, String> {
+        rows.iter().map(|(p, s)| (p.to_string(), s.to_string())).collect()
+    }
+
+    #[test]
+    fn the_side_that_moved_off_the_base_wins() {
+        let base = map(&[
+            ("same", "1"),
+            ("edited_here", "1"),
+            ("edited_there", "1"),
+            ("rm_here", "1"),
+            ("rm_there", "1"),
+            ("rm_both", "1"),
+        ]);
+        let local = map(&[
+            ("same", "1"),
+            ("edited_here", "2"),
+            ("edited_there", "1"),
+            ("rm_there", "1"),
+            ("new_here", "n"),
+        ]);
+        let remote = map(&[
+            ("same", "1"),
+            ("edited_here", "1"),
+            ("edited_there", "3"),
+            ("rm_here", "1"),
+            ("new_there", "m"),
+            ("cold", ""),
+        ]);
+        let p = plan(&base, &local, &remote, "конфликт");
+        assert_eq!(
+            p.actions,
+            vec![
+                Action::Upload { path: "edited_here".into(), guard: Some("1".into()) },
+                Action::Download("edited_there".into()),
+                Action::Upload { path: "new_here".into(), guard: None },
+                Action::Download("new_there".into()),
+                Action::Forget("rm_both".into()),
+                Action::DeleteRemote { path: "rm_here".into(), sha256: "1".into() },
+                Action::DeleteLocal("rm_there".into()),
+                Action::Agree("same".into()),
+            ]
+        );
+        assert_eq!(p.deferred, vec!["cold".to_string()]);
+    }
+
+    #[test]
+    fn two_edits_keep_both_and_an_edit_beats_a_removal() {
+        let base = map(&[("doc.txt", "1"), ("gone_there.txt", "1"), ("gone_here.txt", "1")]);
+        let local = map(&[("doc.txt", "2"), ("gone_there.txt", "2"), ("doc (конфликт).txt", "x")]);
+        let remote = map(&[("doc.txt", "3"), ("gone_here.txt", "3"), ("fresh.txt", "a")]);
+        let p = plan(&base, &local, &remote, "конфликт");
+        assert!(p
+            .actions
+            .contains(&Action::Conflict {
+                path: "doc.txt".into(), copy: "doc (конфликт 2).txt".into()
+            }));
+        assert!(p.actions.contains(&Action::Upload { path: "gone_there.txt".into(), guard: None }));
+        assert!(p.actions.contains(&Action::Download("gone_here.txt".into())));
+
+        // First sync (no base): different content on both sides is a conflict,
+        // equal content just becomes the base.
+        let p = plan(
+            &BTreeMap::new(),
+            &map(&[("a/b.txt", "1"), ("c", "2")]),
+            &map(&[("a/b.txt", "9"), ("c", "2")]),
+            "конфликт",
+        );
+        assert_eq!(
+            p.actions,
+            vec![
+                Action::Conflict { path: "a/b.txt".into(), copy: "a/b (конфликт).txt".into() },
+                Action::Agree("c".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflict_names_keep_the_extension_and_dotfiles() {
+        let taken = HashSet::new();
+        assert_eq!(conflict_name("x/.env", "t", &taken), "x/.env (t)");
+        assert_eq!(conflict_name("a.tar.gz", "t", &taken), "a.tar (t).gz");
+        assert_eq!(conflict_name("README", "t", &taken), "README (t)");
+    }
+
+    /// Агент в процессе на той же машине: записываемая шара `S` из `dir`.
+    async fn agent(dir: &Path, key: &ed25519_dalek::SigningKey) -> String {
+        use std::sync::{Arc, RwLock};
+
+        use crate::manifest::HashCache;
+        use crate::server::{router, AgentState, ShareRoot, SharesMap};
+
+        let mut shares = SharesMap::new();
+        let root = ShareRoot { path: dir.canonicalize().unwrap(), is_file: false, writable: true, import: false, policy: Default::default() };
+        shares.insert("S".into(), root);
+        let cache = Arc::new(HashCache::new());
+        let app = router(Arc::new(AgentState {
+            shares: RwLock::new(Arc::new(shares)),
+            hub_key: key.verifying_key(),
+            hash_cache: cache.clone(),
+            identity: None,
+            max_file_mb: None,
+            import: crate::import::ImportManager::new(None, cache),
+            expose: RwLock::new(Arc::new(Vec::new())),
+            versions: crate::watch::ShareVersions::new(),
+            history: crate::delta::ManifestHistory::new(),
+            chunk_cache: crate::chunks::ChunkCache::new(),
+            trash: None,
+            webdav: false,
+            thumbs: None,
+            usage: Default::default(),
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/S", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        base
+    }
+
+    /// Манифест агента с хешами, как его видит синк после прогрева.
+    fn manifest_of(dir: &Path) -> ShareManifest {
+        let mut entries: Vec<ShareManifestEntry> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .map(|p| ShareManifestEntry {
+                path: p.file_name().unwrap().to_string_lossy().into_owned(),
+                size: std::fs::metadata(&p).unwrap().len(),
+                mtime: 0,
+                sha256: sha256_file(&p).unwrap(),
+                meta: None,
+                preview: None,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        ShareManifest { entries, version: 0 }
+    }
+
+    fn sha_of(data: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        Sha256::digest(data).iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Два прогона против настоящего роутера агента: первый разводит конфликт
+    /// и заливает новое, второй идёт по устаревшему манифесту и получает `412`
+    /// на `If-Match` и на `If-None-Match: *`, не сдвигая базу.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn runs_against_the_agent_and_keeps_the_base_on_412() {
+        use xr_proto::share::{sign_share_token, SCOPE_READ, SCOPE_WRITE};
+
+        let key = ed25519_dalek::SigningKey::from_bytes(&[41u8; 32]);
+        let (remote, local) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        std::fs::write(remote.path().join("both.txt"), b"remote").unwrap();
+        std::fs::write(local.path().join("both.txt"), b"local").unwrap();
+        std::fs::write(local.path().join("new.txt"), b"new").unwrap();
+        let base_url = agent(remote.path(), &key).await;
+        let tok = sign_share_token(&key, "S", &format!("{SCOPE_READ} {SCOPE_WRITE}"), crate::server::now_unix() + 1000);
+        let share = InviteShareDto {
+            share_id: "S".into(),
+            name: "docs".into(),
+            addr: "127.0.0.1".into(),
+            addrs: Vec::new(),
+            port: 0,
+            agent_pubkey: String::new(),
+            token: {
+                use base64::Engine;
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&tok).unwrap())
+            },
+            relay: None,
+        };
+        let (r, l) = (remote.path().to_path_buf(), local.path().to_path_buf());
+        let state_path = l.join(STATE_DIR).join(STATE_FILE);
+
+        let (first, second) = tokio::task::spawn_blocking(move || {
+            let first = reconcile(&l, SyncState::default(), &share, &base_url, &manifest_of(&r), false);
+
+            // Манифест прочитан, потом агенту правят `both.txt` и кладут
+            // `late.txt` в обход синка.
+            let stale = manifest_of(&r);
+            std::fs::write(r.join("both.txt"), b"agent edit").unwrap();
+            std::fs::write(r.join("late.txt"), b"agent late").unwrap();
+            std::fs::write(l.join("both.txt"), b"local edit").unwrap();
+            std::fs::write(l.join("late.txt"), b"local late").unwrap();
+            let second = reconcile(&l, load_state(&state_path), &share, &base_url, &stale, false);
+            (first, second)
+        })
+        .await
+        .unwrap();
+        first.expect("первый прогон");
+        // Оба пути отказаны агентом, прогон кончается ошибкой.
+        let err = second.expect_err("412 это ошибка прогона").to_string();
+        assert!(err.starts_with("2 путь"), "{err}");
+
+        let copy = conflict_name("both.txt", &format!("конфликт {}", crate::setup::hostname()), &HashSet::new());
+        let read = |dir: &Path, p: &str| std::fs::read(dir.join(p)).unwrap();
+        // Конфликт: удалённая версия встала на место, локальная уехала копией
+        // на обе стороны; новый файл залит.
+        assert_eq!(read(remote.path(), &copy), b"local");
+        assert_eq!(read(local.path(), &copy), b"local");
+        assert_eq!(read(remote.path(), "new.txt"), b"new");
+        // Правки агента за спиной синка не перезаписаны.
+        assert_eq!(read(remote.path(), "both.txt"), b"agent edit");
+        assert_eq!(read(remote.path(), "late.txt"), b"agent late");
+
+        // База на диске: сошедшееся в первом прогоне, без отказанных путей.
+        let state = load_state(&local.path().join(STATE_DIR).join(STATE_FILE));
+        assert_eq!(state.version, STATE_VERSION);
+        let base: BTreeMap<&str, &str> = state.files.iter().map(|(p, s)| (p.as_str(), s.sha256.as_str())).collect();
+        let want = sha_of(b"remote");
+        assert_eq!(base.get("both.txt"), Some(&want.as_str()));
+        assert_eq!(base.get(copy.as_str()).copied(), Some(sha_of(b"local").as_str()));
+        assert_eq!(base.get("new.txt").copied(), Some(sha_of(b"new").as_str()));
+        assert!(!base.contains_key("late.txt"));
+    }
+}
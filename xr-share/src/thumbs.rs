@@ -0,0 +1,428 @@
+//! Превью файлов шары: миниатюры картинок и кадры видео, чтобы листать
+//! фотошару, не скачивая каждый снимок целиком.
+//!
+//! Миниатюра делается по первому запросу (`GET /{share_id}/thumb/{*path}`) и
+//! ложится в `.xr-thumbs/` в корне шары (под зарезанным `.xr-`, так что ни
+//! манифест, ни маршруты её не видят); у файловой шары своего корня нет, и
+//! кеш лежит в `thumbs/<share_id>/` рядом с конфигом агента. Каталог кеша
+//! создаётся с правами 0700, а ссылка на месте каталога не годится: превью
+//! тогда отдаётся без кеша. Ключ кеша это путь, размер, mtime и размер миниатюры:
+//! изменённый файл просто получает новую, а старая уходит при очистке по
+//! потолку `cache_mb`, первой из давно не запрошенных. Неудача (битая
+//! картинка, программа кадров упала) тоже кешируется, пустым файлом, чтобы не
+//! повторяться на каждом запросе.
+//!
+//! Картинки разбирает сам агент (JPEG, PNG, WebP, GIF, с поворотом по EXIF).
+//! Кадр видео вынимает внешняя программа из `[thumbs.poster]`, по образцу
+//! плагинов импорта: команда и шаблон argv без шелла, убивается по таймауту.
+//! Одновременно делается не больше [`PARALLEL`] превью: галерея на сотню
+//! снимков не должна занять все ядра машины.
+//!
+//! В манифесте у файла, для которого превью будет, стоит `preview`: `image`
+//! или `video`.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use sha2::{Digest, Sha256};
+use xr_proto::share::{PreviewKind, ShareManifest};
+
+use crate::config::ThumbsConfig;
+
+/// Кеш превью в корне шары.
+pub(crate) const THUMBS_DIR: &str = ".xr-thumbs";
+
+/// Кеши файловых шар в каталоге агента.
+const FILE_SHARES_DIR: &str = "thumbs";
+
+/// Сколько превью делается одновременно.
+const PARALLEL: usize = 2;
+
+/// Через сколько новых превью кеш шары сверяется с потолком.
+const PRUNE_EVERY: u64 = 64;
+
+const JPEG_QUALITY: u8 = 80;
+
+/// Что агент разбирает сам.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif"];
+
+/// Превью агента: настройки и очередь на изготовление.
+pub struct Thumbs {
+    cfg: ThumbsConfig,
+    /// Где лежат кеши файловых шар.
+    files_dir: PathBuf,
+    gate: tokio::sync::Semaphore,
+    made: AtomicU64,
+}
+
+/// Превью не будет: тип не тот, файл велик или не разобрался.
+#[derive(Debug)]
+pub(crate) struct NoPreview;
+
+impl Thumbs {
+    /// `agent_dir` это каталог конфига агента: там кеши файловых шар.
+    pub fn new(cfg: ThumbsConfig, agent_dir: &Path) -> Self {
+        Self {
+            cfg,
+            files_dir: agent_dir.join(FILE_SHARES_DIR),
+            gate: tokio::sync::Semaphore::new(PARALLEL),
+            made: AtomicU64::new(0),
+        }
+    }
+
+    /// Кеш файловой шары: у неё нет корня, куда его положить.
+    pub(crate) fn file_share_dir(&self, share_id: &str) -> PathBuf {
+        self.files_dir.join(share_id)
+    }
+
+    /// Какое превью у файла с таким именем, по расширению.
+    pub(crate) fn kind_of(&self, name: &str) -> Option<PreviewKind> {
+        let ext = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+            return Some(PreviewKind::Image);
+        }
+        let poster = self.cfg.poster.as_ref()?;
+        poster
+            .extensions
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(&ext))
+            .then_some(PreviewKind::Video)
+    }
+
+    /// Отметить в листинге файлы, у которых будет превью. Слишком большие
+    /// картинки не отмечаются: за ними всё равно пришёл бы отказ.
+    pub(crate) fn advertise(&self, manifest: &mut ShareManifest) {
+        let max = self.cfg.max_source_mb.saturating_mul(1024 * 1024);
+        for e in &mut manifest.entries {
+            e.preview = self
+                .kind_of(&e.path)
+                .filter(|k| *k == PreviewKind::Video || e.size <= max);
+        }
+    }
+
+    /// Ключ превью: он же имя в кеше и `ETag`.
+    pub(crate) fn key(&self, rel: &str, md: &std::fs::Metadata) -> String {
+        let mut h = Sha256::new();
+        h.update(rel.as_bytes());
+        h.update([0]);
+        h.update(md.len().to_le_bytes());
+        h.update(crate::manifest::mtime_secs(md).to_le_bytes());
+        h.update(self.cfg.size.to_le_bytes());
+        h.finalize()[..16].iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// JPEG превью файла `source` (путь `rel` в шаре) из кеша `cache_dir` или
+    /// сделанный заново.
+    pub(crate) async fn get(&self, cache_dir: &Path, source: &Path, rel: &str) -> Result<Vec<u8>, NoPreview> {
+        let kind = self.kind_of(rel).ok_or(NoPreview)?;
+        let md = tokio::fs::metadata(source).await.map_err(|_| NoPreview)?;
+        let cached = cache_dir.join(format!("{}.jpg", self.key(rel, &md)));
+        let cache_ok = private_dir(cache_dir).await;
+        if !cache_ok {
+            tracing::debug!("кеш превью {} не годится, превью без кеша", cache_dir.display());
+        } else if let Some(hit) = read_cached(&cached).await {
+            return hit;
+        }
+        let _permit = self.gate.acquire().await.map_err(|_| NoPreview)?;
+        // Пока ждали очереди, тот же превью мог сделать соседний запрос.
+        if cache_ok {
+            if let Some(hit) = read_cached(&cached).await {
+                return hit;
+            }
+        }
+        let made = match kind {
+            PreviewKind::Image => {
+                let (src, edge, max) = (source.to_path_buf(), self.cfg.size, self.cfg.max_source_mb);
+                tokio::task::spawn_blocking(move || render(&src, edge, max))
+                    .await
+                    .unwrap_or_else(|_| Err("задача превью упала".into()))
+            }
+            PreviewKind::Video if cache_ok => self.poster(cache_dir, source).await,
+            PreviewKind::Video => Err("кадру видео некуда лечь".into()),
+        };
+        if let Err(e) = &made {
+            tracing::debug!("превью {rel}: {e}");
+        }
+        if !cache_ok {
+            return made.map_err(|_| NoPreview);
+        }
+        store(&cached, made.as_deref().unwrap_or_default()).await;
+        if self.made.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            let (dir, cap) = (cache_dir.to_path_buf(), self.cfg.cache_mb.saturating_mul(1024 * 1024));
+            tokio::task::spawn_blocking(move || prune(&dir, cap));
+        }
+        made.map_err(|_| NoPreview)
+    }
+
+    /// Кадр видео внешней программой, затем тот же путь, что у картинки. Кадр
+    /// пишется в уже проверенный каталог кеша.
+    async fn poster(&self, cache_dir: &Path, source: &Path) -> Result<Vec<u8>, String> {
+        let tool = self.cfg.poster.as_ref().ok_or("программа кадров не задана")?;
+        let frame = cache_dir.join(format!("tmp-{:016x}.jpg", rand::random::<u64>()));
+        let args: Vec<String> = tool
+            .args
+            .iter()
+            .map(|a| match a.as_str() {
+                "{input}" => source.to_string_lossy().into_owned(),
+                "{output}" => frame.to_string_lossy().into_owned(),
+                other => other.replace("{size}", &self.cfg.size.to_string()),
+            })
+            .collect();
+        let run = tokio::process::Command::new(&tool.cmd)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .output();
+        let outcome = match tokio::time::timeout(Duration::from_secs(tool.timeout_secs), run).await {
+            Ok(Ok(out)) if out.status.success() => {
+                let (frame, edge) = (frame.clone(), self.cfg.size);
+                tokio::task::spawn_blocking(move || render(&frame, edge, u64::MAX))
+                    .await
+                    .unwrap_or_else(|_| Err("задача превью упала".into()))
+            }
+            Ok(Ok(out)) => {
+                let err = String::from_utf8_lossy(&out.stderr);
+                Err(format!("{} завершился с {}: {}", tool.cmd, out.status, err.trim().lines().last().unwrap_or("")))
+            }
+            Ok(Err(e)) => Err(format!("не удалось запустить {}: {e}", tool.cmd)),
+            Err(_) => Err(format!("{} не уложился в {} с", tool.cmd, tool.timeout_secs)),
+        };
+        let _ = tokio::fs::remove_file(&frame).await;
+        outcome
+    }
+}
+
+/// Каталог кеша готов: настоящий каталог, при создании с правами 0700. Ссылка
+/// на его месте не годится: через неё превью и кадры легли бы куда угодно.
+async fn private_dir(dir: &Path) -> bool {
+    if let Ok(md) = tokio::fs::symlink_metadata(dir).await {
+        return md.file_type().is_dir();
+    }
+    let mut builder = tokio::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    if builder.create(dir).await.is_err() {
+        return false;
+    }
+    tokio::fs::symlink_metadata(dir).await.is_ok_and(|md| md.file_type().is_dir())
+}
+
+/// Кеш: превью, известная неудача (пустой файл) или ничего. Попадание
+/// обновляет mtime: по нему очистка узнаёт давно не нужные.
+async fn read_cached(path: &Path) -> Option<Result<Vec<u8>, NoPreview>> {
+    let bytes = tokio::fs::read(path).await.ok()?;
+    if bytes.is_empty() {
+        return Some(Err(NoPreview));
+    }
+    let touch = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        if let Ok(f) = std::fs::File::options().write(true).open(&touch) {
+            let _ = f.set_modified(SystemTime::now());
+        }
+    });
+    Some(Ok(bytes))
+}
+
+/// Положить превью в кеш через временный файл. Не вышло (шара на носителе
+/// только для чтения) значит без кеша: превью всё равно отдаётся.
+async fn store(path: &Path, bytes: &[u8]) {
+    let Some(dir) = path.parent() else { return };
+    let tmp = dir.join(format!("tmp-{:016x}", rand::random::<u64>()));
+    if tokio::fs::write(&tmp, bytes).await.is_err() || tokio::fs::rename(&tmp, path).await.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+}
+
+/// Миниатюра картинки: поворот по EXIF, вписать в `edge` по длинной стороне
+/// (меньшие не растягиваются), прозрачное на белом, JPEG.
+fn render(path: &Path, edge: u32, max_source_mb: u64) -> Result<Vec<u8>, String> {
+    let len = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if len > max_source_mb.saturating_mul(1024 * 1024) {
+        return Err(format!("больше {max_source_mb} МиБ"));
+    }
+    let mut decoder = ImageReader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .into_decoder()
+        .map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut img = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    img.apply_orientation(orientation);
+    if img.width() > edge || img.height() > edge {
+        img = img.thumbnail(edge, edge);
+    }
+    let mut rgba = img.to_rgba8();
+    for p in rgba.pixels_mut() {
+        let a = u32::from(p[3]);
+        for c in 0..3 {
+            p[c] = ((u32::from(p[c]) * a + 255 * (255 - a)) / 255) as u8;
+        }
+    }
+    let rgb = DynamicImage::ImageRgba8(rgba).to_rgb8();
+    let mut out = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+        .encode_image(&rgb)
+        .map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// Ужать кеш до `cap` байт, убирая давно не запрошенные превью, и подмести
+/// брошенные временные файлы.
+pub(crate) fn prune(dir: &Path, cap: u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let stale = SystemTime::now() - Duration::from_secs(3600);
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = Vec::new();
+    for e in entries.flatten() {
+        let Ok(md) = e.metadata() else { continue };
+        let mtime = md.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if e.file_name().to_string_lossy().starts_with("tmp-") {
+            if mtime < stale {
+                let _ = std::fs::remove_file(e.path());
+            }
+            continue;
+        }
+        files.push((mtime, md.len(), e.path()));
+    }
+    let mut total: u64 = files.iter().map(|f| f.1).sum();
+    files.sort();
+    for (_, len, path) in files {
+        if total <= cap {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xr_proto::share::ShareManifestEntry;
+
+    fn thumbs(poster: Option<crate::config::PosterTool>) -> Thumbs {
+        Thumbs::new(ThumbsConfig { size: 64, poster, ..ThumbsConfig::default() }, Path::new("/nonexistent"))
+    }
+
+    fn photo(path: &Path, w: u32, h: u32) {
+        image::RgbImage::from_fn(w, h, |x, _| image::Rgb([(x % 256) as u8, 90, 200])).save(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renders_caches_and_remembers_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join(THUMBS_DIR);
+        photo(&dir.path().join("wide.png"), 400, 100);
+        std::fs::write(dir.path().join("broken.jpg"), b"not a jpeg").unwrap();
+        let t = thumbs(None);
+
+        let jpeg = t.get(&cache, &dir.path().join("wide.png"), "wide.png").await.unwrap();
+        let img = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((img.width(), img.height()), (64, 16), "вписана по длинной стороне");
+        let md = std::fs::metadata(dir.path().join("wide.png")).unwrap();
+        assert!(cache.join(format!("{}.jpg", t.key("wide.png", &md))).is_file());
+
+        assert!(t.get(&cache, &dir.path().join("broken.jpg"), "broken.jpg").await.is_err());
+        let markers = std::fs::read_dir(&cache).unwrap().flatten().filter(|e| e.metadata().unwrap().len() == 0).count();
+        assert_eq!(markers, 1, "неудача запомнена");
+        assert!(t.get(&cache, &dir.path().join("a.txt"), "a.txt").await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cache_dir_is_private_and_never_a_symlink() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        photo(&dir.path().join("p.png"), 80, 40);
+        let t = Thumbs::new(ThumbsConfig { size: 64, ..ThumbsConfig::default() }, &dir.path().join("agent"));
+        let cache = t.file_share_dir("F");
+        assert_eq!(cache, dir.path().join("agent/thumbs/F"));
+        t.get(&cache, &dir.path().join("p.png"), "p.png").await.unwrap();
+        for d in [&cache, &dir.path().join("agent/thumbs")] {
+            assert_eq!(std::fs::metadata(d).unwrap().permissions().mode() & 0o777, 0o700, "{}", d.display());
+        }
+
+        // Ссылка на месте каталога: превью отдаётся, но мимо кеша.
+        let elsewhere = dir.path().join("elsewhere");
+        std::fs::create_dir(&elsewhere).unwrap();
+        let linked = dir.path().join(THUMBS_DIR);
+        std::os::unix::fs::symlink(&elsewhere, &linked).unwrap();
+        t.get(&linked, &dir.path().join("p.png"), "p.png").await.unwrap();
+        assert_eq!(std::fs::read_dir(&elsewhere).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn advertises_only_what_it_can_render() {
+        let mut m = ShareManifest {
+            entries: ["a.JPG", "b.txt", "c.mp4", "huge.png"]
+                .iter()
+                .map(|p| ShareManifestEntry {
+                    path: p.to_string(),
+                    size: if *p == "huge.png" { 1 << 40 } else { 1 },
+                    mtime: 0,
+                    sha256: String::new(),
+                    meta: None,
+                    preview: None,
+                })
+                .collect(),
+            version: 0,
+        };
+        thumbs(None).advertise(&mut m);
+        let kinds: Vec<_> = m.entries.iter().map(|e| e.preview).collect();
+        assert_eq!(kinds, [Some(PreviewKind::Image), None, None, None]);
+
+        let poster = crate::config::PosterTool {
+            cmd: "ffmpeg".into(),
+            args: vec!["{input}".into(), "{output}".into()],
+            extensions: vec!["mp4".into()],
+            timeout_secs: 5,
+        };
+        thumbs(Some(poster)).advertise(&mut m);
+        assert_eq!(m.entries[2].preview, Some(PreviewKind::Video));
+    }
+
+    /// Кадр видео через программу из конфига: здесь `cp` готовой картинки
+    /// вместо ffmpeg.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn poster_runs_the_configured_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = dir.path().join("frame.png");
+        photo(&frame, 100, 200);
+        std::fs::write(dir.path().join("clip.mp4"), b"video").unwrap();
+        let poster = crate::config::PosterTool {
+            cmd: "cp".into(),
+            args: vec![frame.to_string_lossy().into_owned(), "{output}".into()],
+            extensions: vec!["mp4".into()],
+            timeout_secs: 5,
+        };
+        let t = thumbs(Some(poster));
+        let cache = dir.path().join(THUMBS_DIR);
+        let jpeg = t.get(&cache, &dir.path().join("clip.mp4"), "clip.mp4").await.unwrap();
+        let img = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((img.width(), img.height()), (32, 64));
+        let leftovers = std::fs::read_dir(&cache).unwrap().flatten().filter(|e| e.file_name().to_string_lossy().starts_with("tmp-")).count();
+        assert_eq!(leftovers, 0, "кадр программы убран");
+    }
+
+    #[test]
+    fn prune_drops_the_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, age) in [("old.jpg", 300), ("mid.jpg", 200), ("new.jpg", 100)] {
+            let p = dir.path().join(name);
+            std::fs::write(&p, [0u8; 10]).unwrap();
+            let f = std::fs::File::options().write(true).open(&p).unwrap();
+            f.set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+        }
+        prune(dir.path(), 20);
+        assert!(!dir.path().join("old.jpg").exists());
+        assert!(dir.path().join("mid.jpg").exists() && dir.path().join("new.jpg").exists());
+    }
+}
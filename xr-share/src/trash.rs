@@ -0,0 +1,467 @@
+//! Корзина записываемых шар. `DELETE` и перезаписывающий `PUT` агента больше не
+//! окончательны: прежняя версия файла уезжает в [`TRASH_DIR`] в корне шары и
+//! живёт там, пока её не вытеснит срок или потолок объёма (`[trash]` в конфиге).
+//! Вернуть её можно ручкой `POST /{share_id}/trash/{id}/restore` или
+//! `xr-share trash restore`.
+//!
+//! **Где лежит.** Каталог внутри зарезервированного `.xr-` пространства:
+//! листинг, слежение и `safepath` его не видят, маршрутом до файлов в нём не
+//! дотянуться. Версии лежат плоско под случайными id, рядом индекс
+//! [`INDEX_NAME`] с исходным путём, размером, временем и причиной. Плоско, а не
+//! деревом исходных путей: у одного файла сколько угодно версий, и ни одна не
+//! должна затирать другую.
+//!
+//! **Почему в той же шаре.** Удаление это `rename` в пределах одной ФС:
+//! мгновенно для файла любого размера и без лишнего места на диске. Перед
+//! перезаписью старая версия остаётся в корзине жёсткой ссылкой, а подмена цели
+//! как была атомарной, так и осталась: читатель ни в какой момент не видит дыры
+//! на месте файла. Где ни ссылка, ни перенос не проходят (подкаталог шары
+//! смонтирован с другой ФС), версия копируется.
+//!
+//! **Индекс важнее файлов.** Битый индекс не читается как пустой: иначе чистка
+//! приняла бы все версии за сирот и стёрла их. Запись в такую шару отказывает,
+//! пока владелец не разберётся, а чистка её пропускает.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use xr_proto::share::FileMeta;
+
+use crate::config::TrashConfig;
+use crate::manifest::mtime_secs;
+
+/// Каталог корзины в корне шары.
+pub const TRASH_DIR: &str = ".xr-trash";
+
+/// Индекс версий внутри [`TRASH_DIR`]. Id версий это hex, так что с ними имя
+/// не пересекается.
+pub const INDEX_NAME: &str = "index.json";
+
+/// Через него индекс пишется перед атомарной подменой.
+const INDEX_TEMP: &str = "index.json.new";
+
+/// Один замок на все шары, как у индекса метаданных: записи редки, а
+/// перенос файла и строка индекса о нём должны случаться вместе.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Почему версия попала в корзину.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    /// Файл удалили.
+    Deleted,
+    /// Поверх файла записали другой: загрузкой или восстановлением.
+    Replaced,
+}
+
+/// Версия в корзине, как её отдаёт `GET /{share_id}/trash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    /// Имя версии в корзине, по нему её восстанавливают.
+    pub id: String,
+    /// Где файл лежал в шаре, путь как в манифесте.
+    pub path: String,
+    pub size: u64,
+    /// Время изменения самой версии, unix-секунды.
+    pub mtime: i64,
+    /// Когда версия попала в корзину, unix-секунды. От него считается срок.
+    pub trashed_at: u64,
+    pub reason: Reason,
+    /// SHA-256, если агент его уже знал. Ради корзины файл не хешируется.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Происхождение файла ([`crate::meta`]): вернётся вместе с ним.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<FileMeta>,
+}
+
+/// Чем кончилось восстановление.
+pub struct Restored {
+    pub item: TrashItem,
+    /// На месте назначения был файл, и теперь он сам в корзине.
+    pub replaced: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    items: Vec<TrashItem>,
+}
+
+/// Индекс корзины шары. Нет файла значит пустая корзина; нечитаемый или битый
+/// индекс это ошибка, а не пустота (см. заголовок модуля).
+fn load(root: &Path) -> io::Result<Vec<TrashItem>> {
+    let path = root.join(TRASH_DIR).join(INDEX_NAME);
+    let raw = match std::fs::read(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_slice::<Index>(&raw)
+        .map(|idx| idx.items)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display())))
+}
+
+/// Записать индекс через временный файл и атомарный `rename`.
+fn save(root: &Path, items: &[TrashItem]) -> io::Result<()> {
+    let dir = root.join(TRASH_DIR);
+    let temp = dir.join(INDEX_TEMP);
+    let body = serde_json::to_vec_pretty(&Index { items: items.to_vec() })
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    std::fs::write(&temp, body)?;
+    std::fs::rename(&temp, dir.join(INDEX_NAME)).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })
+}
+
+/// Убрать текущую версию `target` (путь `rel` в шаре) в корзину. `Deleted`
+/// переносит файл, `Replaced` оставляет его на месте: вызывающий тут же
+/// подменит цель новой версией. Пока строка индекса не записана, версия не
+/// считается отложенной, и при ошибке файл возвращается как был.
+pub fn keep(
+    root: &Path,
+    target: &Path,
+    rel: &str,
+    reason: Reason,
+    sha256: Option<String>,
+) -> io::Result<TrashItem> {
+    let _guard = WRITE_LOCK.lock().expect("trash lock poisoned");
+    let mut items = load(root)?;
+    let item = stash(root, &mut items, target, rel, reason, sha256)?;
+    if let Err(e) = save(root, &items) {
+        unstash(root, &item, target);
+        return Err(e);
+    }
+    Ok(item)
+}
+
+/// Версии в корзине шары, новые сверху.
+pub fn list(root: &Path) -> io::Result<Vec<TrashItem>> {
+    let mut items = load(root)?;
+    items.sort_by(|a, b| b.trashed_at.cmp(&a.trashed_at).then_with(|| a.id.cmp(&b.id)));
+    Ok(items)
+}
+
+/// Версия `id`, если она ещё в корзине.
+pub fn find(root: &Path, id: &str) -> io::Result<Option<TrashItem>> {
+    Ok(load(root)?.into_iter().find(|i| i.id == id))
+}
+
+/// Вернуть версию `id` на место `dest` (путь `dest_rel` в шаре). Файл, который
+/// сейчас лежит по этому пути, сам уходит в корзину: восстановление тоже можно
+/// откатить. `Ok(None)`, если такой версии в корзине уже нет.
+pub fn restore(
+    root: &Path,
+    id: &str,
+    dest: &Path,
+    dest_rel: &str,
+    current_sha: Option<String>,
+) -> io::Result<Option<Restored>> {
+    let _guard = WRITE_LOCK.lock().expect("trash lock poisoned");
+    let mut items = load(root)?;
+    let Some(pos) = items.iter().position(|i| i.id == id) else {
+        return Ok(None);
+    };
+    let blob = root.join(TRASH_DIR).join(id);
+    if !blob.is_file() {
+        // Строка без файла: версию уже не вернуть, строку незачем держать.
+        items.remove(pos);
+        save(root, &items)?;
+        return Ok(None);
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Вытесненная версия сперва записывается в индекс: файл без строки чистка
+    // приняла бы за сироту.
+    let displaced = match dest.is_file() {
+        true => {
+            let d = stash(root, &mut items, dest, dest_rel, Reason::Replaced, current_sha)?;
+            if let Err(e) = save(root, &items) {
+                unstash(root, &d, dest);
+                return Err(e);
+            }
+            Some(d)
+        }
+        false => None,
+    };
+    if let Err(e) = replace_file(&blob, dest) {
+        if let Some(d) = &displaced {
+            unstash(root, d, dest);
+            items.pop();
+            let _ = save(root, &items);
+        }
+        return Err(e);
+    }
+    let item = items.remove(pos);
+    // Файл уже на месте. Если индекс не записался, в нём осталась строка без
+    // файла, её уберёт чистка.
+    if let Err(e) = save(root, &items) {
+        tracing::warn!("индекс корзины не записан ({e}): {}", root.display());
+    }
+    Ok(Some(Restored { item, replaced: displaced.is_some() }))
+}
+
+/// Применить срок и потолок объёма к корзине шары. Заодно уходят файлы без
+/// строки в индексе (агент упал между переносом и записью индекса) и строки без
+/// файла. Возвращает, сколько версий удалено.
+pub fn prune(root: &Path, cfg: &TrashConfig, now: u64) -> io::Result<usize> {
+    let dir = root.join(TRASH_DIR);
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let _guard = WRITE_LOCK.lock().expect("trash lock poisoned");
+    let mut items = load(root)?;
+    let before = items.len();
+    items.retain(|i| dir.join(&i.id).is_file());
+    let mut dropped = Vec::new();
+
+    let keep_secs = cfg.keep_days.saturating_mul(24 * 3600);
+    let (expired, live): (Vec<_>, Vec<_>) =
+        items.into_iter().partition(|i| now.saturating_sub(i.trashed_at) > keep_secs);
+    dropped.extend(expired);
+    items = live;
+
+    if cfg.max_mb > 0 {
+        let cap = cfg.max_mb.saturating_mul(1024 * 1024);
+        // Старые первыми: из-за потолка уходит то, что и так ближе всех к сроку.
+        items.sort_by_key(|i| i.trashed_at);
+        let mut total: u64 = items.iter().map(|i| i.size).sum();
+        while total > cap && !items.is_empty() {
+            let old = items.remove(0);
+            total -= old.size;
+            dropped.push(old);
+        }
+    }
+
+    for old in &dropped {
+        let _ = std::fs::remove_file(dir.join(&old.id));
+    }
+    let known: HashSet<&str> = items.iter().map(|i| i.id.as_str()).collect();
+    let mut orphans = 0;
+    for entry in std::fs::read_dir(&dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == INDEX_NAME || name == INDEX_TEMP || known.contains(name.as_str()) {
+            continue;
+        }
+        if entry.file_type().is_ok_and(|t| t.is_file()) && std::fs::remove_file(entry.path()).is_ok() {
+            orphans += 1;
+        }
+    }
+
+    if items.is_empty() {
+        // Пустая корзина не оставляет следов в шаре.
+        let _ = std::fs::remove_file(dir.join(INDEX_NAME));
+        let _ = std::fs::remove_dir(&dir);
+    } else if items.len() != before {
+        save(root, &items)?;
+    }
+    Ok(dropped.len() + orphans)
+}
+
+/// Отложить `target` в корзину и дописать строку в `items` (без записи индекса:
+/// это дело вызывающего, держащего замок).
+fn stash(
+    root: &Path,
+    items: &mut Vec<TrashItem>,
+    target: &Path,
+    rel: &str,
+    reason: Reason,
+    sha256: Option<String>,
+) -> io::Result<TrashItem> {
+    let md = std::fs::metadata(target)?;
+    let dir = root.join(TRASH_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let id = loop {
+        let id = format!("{:016x}", rand::random::<u64>());
+        if !dir.join(&id).exists() {
+            break id;
+        }
+    };
+    let blob = dir.join(&id);
+    match reason {
+        Reason::Deleted => move_file(target, &blob)?,
+        Reason::Replaced => link_or_copy(target, &blob)?,
+    }
+    let item = TrashItem {
+        id,
+        path: rel.to_string(),
+        size: md.len(),
+        mtime: mtime_secs(&md),
+        trashed_at: now_unix(),
+        reason,
+        sha256,
+        meta: crate::meta::load(root).remove(rel),
+    };
+    items.push(item.clone());
+    Ok(item)
+}
+
+/// Откатить [`stash`], чей индекс не записался: удалённый файл вернуть на
+/// место, копию заменённого просто убрать.
+fn unstash(root: &Path, item: &TrashItem, target: &Path) {
+    let blob = root.join(TRASH_DIR).join(&item.id);
+    match item.reason {
+        Reason::Deleted => {
+            let _ = move_file(&blob, target);
+        }
+        Reason::Replaced => {
+            let _ = std::fs::remove_file(&blob);
+        }
+    }
+}
+
+/// Перенести файл: `rename`, а между разными ФС копия с удалением оригинала.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_file(from, to)?;
+    std::fs::remove_file(from).inspect_err(|_| {
+        let _ = std::fs::remove_file(to);
+    })
+}
+
+/// Вторая ссылка на тот же файл, а где её не дать, копия.
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if std::fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_file(from, to)
+}
+
+/// Копия с исходным временем изменения: по нему версии различают в списке, а
+/// `xr-share sync` сверяет файлы.
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::copy(from, to)?;
+    if let Ok(mtime) = std::fs::metadata(from).and_then(|m| m.modified()) {
+        let _ = std::fs::File::options().write(true).open(to).and_then(|f| f.set_modified(mtime));
+    }
+    Ok(())
+}
+
+/// `rename` поверх существующего файла. Windows так не умеет, там цель сперва
+/// удаляется (то же окно, что у записи агента, LLD-28 риск 2).
+fn replace_file(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            #[cfg(windows)]
+            {
+                let _ = std::fs::remove_file(to);
+                return std::fs::rename(from, to);
+            }
+            #[cfg(not(windows))]
+            Err(e)
+        }
+    }
+}
+
+fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn blob_path(root: &Path, id: &str) -> PathBuf {
+        root.join(TRASH_DIR).join(id)
+    }
+
+    fn cfg(keep_days: u64, max_mb: u64) -> TrashConfig {
+        TrashConfig { keep_days, max_mb }
+    }
+
+    #[test]
+    fn deleted_and_replaced_versions_come_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let file = root.join("a.txt");
+        std::fs::write(&file, b"first").unwrap();
+
+        // Перезапись: старая версия в корзине, файл на месте.
+        let old = keep(root, &file, "a.txt", Reason::Replaced, Some("aa".into())).unwrap();
+        assert!(file.is_file());
+        // Агент подменяет цель переименованием, не пишет поверх.
+        std::fs::write(root.join("a.new"), b"second").unwrap();
+        std::fs::rename(root.join("a.new"), &file).unwrap();
+        assert_eq!(std::fs::read(blob_path(root, &old.id)).unwrap(), b"first");
+
+        // Удаление: файла больше нет, в корзине обе версии, новая сверху.
+        let gone = keep(root, &file, "a.txt", Reason::Deleted, None).unwrap();
+        assert!(!file.exists());
+        let ids: Vec<_> = list(root).unwrap().into_iter().map(|i| (i.id, i.reason)).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&(old.id.clone(), Reason::Replaced)));
+        assert!(ids.contains(&(gone.id.clone(), Reason::Deleted)));
+
+        // Возврат удалённой версии на пустое место.
+        let r = restore(root, &gone.id, &file, "a.txt", None).unwrap().expect("версия есть");
+        assert!(!r.replaced);
+        assert_eq!(std::fs::read(&file).unwrap(), b"second");
+
+        // Возврат первой поверх текущей: текущая сама уезжает в корзину.
+        let r = restore(root, &old.id, &file, "a.txt", None).unwrap().expect("версия есть");
+        assert!(r.replaced);
+        assert_eq!(std::fs::read(&file).unwrap(), b"first");
+        let left = list(root).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(std::fs::read(blob_path(root, &left[0].id)).unwrap(), b"second");
+
+        // Повторно ту же версию не вернуть.
+        assert!(restore(root, &old.id, &file, "a.txt", None).unwrap().is_none());
+    }
+
+    #[test]
+    fn prune_applies_age_and_size_and_drops_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for name in ["a", "b", "c"] {
+            let f = root.join(name);
+            std::fs::write(&f, vec![0u8; 600 * 1024]).unwrap();
+            keep(root, &f, name, Reason::Deleted, None).unwrap();
+        }
+        // Состарить версии: a на 10 дней, b на 2, c на 1.
+        let now = now_unix();
+        let mut items = load(root).unwrap();
+        for (i, age) in items.iter_mut().zip([10u64, 2, 1]) {
+            i.trashed_at = now - age * 24 * 3600;
+        }
+        save(root, &items).unwrap();
+        std::fs::write(root.join(TRASH_DIR).join("0000dead0000beef"), b"orphan").unwrap();
+
+        // Срок 7 дней уносит a, потолок в 1 МиБ следом самую старую из остальных.
+        assert_eq!(prune(root, &cfg(7, 1), now).unwrap(), 3);
+        let left: Vec<_> = list(root).unwrap().into_iter().map(|i| i.path).collect();
+        assert_eq!(left, vec!["c".to_string()]);
+        assert!(!root.join(TRASH_DIR).join("0000dead0000beef").exists());
+
+        // Истёк и последний: от корзины не остаётся и каталога.
+        assert_eq!(prune(root, &cfg(7, 0), now + 30 * 24 * 3600).unwrap(), 1);
+        assert!(!root.join(TRASH_DIR).exists());
+    }
+
+    #[test]
+    fn a_broken_index_is_never_treated_as_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let file = root.join("a.txt");
+        std::fs::write(&file, b"x").unwrap();
+        let item = keep(root, &file, "a.txt", Reason::Deleted, None).unwrap();
+        std::fs::write(root.join(TRASH_DIR).join(INDEX_NAME), b"{not json").unwrap();
+
+        // Ни чистка, ни новая запись не трогают версии при битом индексе.
+        assert!(prune(root, &cfg(1, 1), now_unix() + 1_000_000).is_err());
+        assert!(blob_path(root, &item.id).is_file());
+        std::fs::write(&file, b"y").unwrap();
+        assert!(keep(root, &file, "a.txt", Reason::Deleted, None).is_err());
+        assert!(file.is_file(), "при отказе файл остаётся на месте");
+    }
+}
@@ -0,0 +1,426 @@
+//! Живые изменения шар: версия манифеста и слежение за корнями.
+//!
+//! У каждой шары есть счётчик [`ShareVersions`]: агент поднимает его на каждое
+//! замеченное изменение, а ручка `GET /{share_id}/manifest/wait?since=` держит
+//! запрос, пока версия совпадает с клиентской (зеркало ожидания пресета на
+//! хабе). Так телефон и `xr-share pull --watch` узнают о новом файле за
+//! секунды, а не на следующем плановом походе за манифестом.
+//!
+//! Источник изменений это уведомления ОС ([`notify`]: inotify на Linux), а
+//! где они не заводятся (кончились inotify-вотчи, экзотическая ФС) или
+//! выключены в `[watch]`, шара уходит на опрос: дешёвый отпечаток обхода
+//! раз в `poll_secs`. Запись через ручки агента (`PUT`/`DELETE`) поднимает
+//! версию сама, не дожидаясь ни того, ни другого.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, watch};
+
+use crate::config::WatchConfig;
+use crate::manifest::{walk_share, RESERVED_PREFIX};
+use crate::server::{AgentState, SharesMap};
+
+/// Сколько копить пачку событий перед подъёмом версии: копирование папки это
+/// сотни событий, а ждущим нужно одно пробуждение.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Как часто сверять набор шар с тем, за чем следим. Тот же период, что у
+/// перечитывания конфига в `main`: чаще набор всё равно не меняется.
+const RECONCILE_EVERY: Duration = Duration::from_secs(5);
+
+/// Версии манифестов по `share_id`. Версия шары начинается с unix-миллисекунд
+/// момента, когда агент её впервые увидел, и дальше растёт на единицу за
+/// изменение. Поэтому после перезапуска агента версия не повторяет старую, и
+/// клиент с версией прошлой жизни получает ответ сразу, а не виснет на
+/// совпадении.
+#[derive(Default)]
+pub struct ShareVersions {
+    inner: Mutex<HashMap<String, watch::Sender<u64>>>,
+}
+
+impl ShareVersions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, watch::Sender<u64>>> {
+        self.inner.lock().expect("versions lock poisoned")
+    }
+
+    /// Текущая версия шары.
+    pub fn current(&self, share_id: &str) -> u64 {
+        *self.lock().entry(share_id.to_string()).or_insert_with(fresh).borrow()
+    }
+
+    /// Подписка на версию шары: `changed()` будит на каждый подъём.
+    pub fn subscribe(&self, share_id: &str) -> watch::Receiver<u64> {
+        self.lock().entry(share_id.to_string()).or_insert_with(fresh).subscribe()
+    }
+
+    /// Шара изменилась: поднять версию и разбудить ждущих. Возвращает новую
+    /// версию.
+    pub fn bump(&self, share_id: &str) -> u64 {
+        let mut map = self.lock();
+        let tx = map.entry(share_id.to_string()).or_insert_with(fresh);
+        let mut next = 0;
+        tx.send_modify(|v| {
+            *v += 1;
+            next = *v;
+        });
+        next
+    }
+
+    /// Забыть снятые шары. Ждущие снятой шары просыпаются закрытым каналом.
+    pub fn retain(&self, live: &SharesMap) {
+        self.lock().retain(|id, _| live.contains_key(id));
+    }
+}
+
+fn fresh() -> watch::Sender<u64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(1);
+    watch::channel(now).0
+}
+
+/// Поднять фоновое слежение за всеми шарами агента. Набор шар сверяется с
+/// живой таблицей, так что `share`/`unshare` подхватываются без перезапуска.
+pub fn spawn(state: Arc<AgentState>, cfg: WatchConfig) {
+    tokio::spawn(run(state, cfg));
+}
+
+/// Что прислал обработчик уведомлений ОС.
+enum Change {
+    Paths(Vec<PathBuf>),
+    /// Очередь ядра переполнилась или вотчер сломался: что именно менялось,
+    /// неизвестно, и честно поднять версию всем шарам на уведомлениях.
+    Rescan,
+}
+
+/// Как отслеживается одна шара.
+struct Watched {
+    root: PathBuf,
+    is_file: bool,
+    mode: Mode,
+}
+
+enum Mode {
+    Native,
+    /// Опрос: последний отпечаток, `None` до первого обхода.
+    Poll(Option<u64>),
+}
+
+async fn run(state: Arc<AgentState>, cfg: WatchConfig) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Change>();
+    let mut native = if cfg.poll { None } else { Native::new(tx) };
+    let mut watched: HashMap<String, Watched> = HashMap::new();
+    let mut seen: Option<Arc<SharesMap>> = None;
+    let poll_every = Duration::from_secs(cfg.poll_secs.max(1));
+
+    let mut reconcile = tokio::time::interval(RECONCILE_EVERY);
+    let mut poll = tokio::time::interval(poll_every);
+    loop {
+        tokio::select! {
+            _ = reconcile.tick() => {
+                let shares = state.snapshot();
+                if seen.as_ref().is_some_and(|s| Arc::ptr_eq(s, &shares)) {
+                    continue;
+                }
+                reconcile_shares(&shares, &mut native, &mut watched, poll_every);
+                state.versions.retain(&shares);
+                state.history.retain(&shares);
+                seen = Some(shares);
+            }
+            _ = poll.tick() => poll_shares(&state, &mut watched).await,
+            Some(first) = rx.recv() => {
+                let mut changed = HashSet::new();
+                collect(&watched, first, &mut changed);
+                let until = tokio::time::Instant::now() + DEBOUNCE;
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(until) => break,
+                        Some(more) = rx.recv() => collect(&watched, more, &mut changed),
+                    }
+                }
+                for id in changed {
+                    state.versions.bump(&id);
+                }
+            }
+        }
+    }
+}
+
+/// Привести набор отслеживаемых шар к живой таблице: снятые и переехавшие
+/// отпустить, новые завести. Шара, для которой уведомления ОС не заводятся,
+/// уходит на опрос с предупреждением в лог.
+fn reconcile_shares(
+    shares: &SharesMap,
+    native: &mut Option<Native>,
+    watched: &mut HashMap<String, Watched>,
+    poll_every: Duration,
+) {
+    watched.retain(|id, w| {
+        let keep = shares
+            .get(id)
+            .is_some_and(|s| s.path == w.root && s.is_file == w.is_file);
+        if !keep && matches!(w.mode, Mode::Native) {
+            if let Some(n) = native.as_mut() {
+                n.remove(&w.root, w.is_file);
+            }
+        }
+        keep
+    });
+    for (id, share) in shares {
+        if watched.contains_key(id) {
+            continue;
+        }
+        let mode = match native.as_mut().map(|n| n.add(&share.path, share.is_file)) {
+            Some(Ok(())) => Mode::Native,
+            Some(Err(e)) => {
+                tracing::warn!(
+                    "share {id}: уведомления ОС не заводятся ({e}), опрос каждые {}s",
+                    poll_every.as_secs()
+                );
+                Mode::Poll(None)
+            }
+            None => Mode::Poll(None),
+        };
+        watched.insert(
+            id.clone(),
+            Watched { root: share.path.clone(), is_file: share.is_file, mode },
+        );
+    }
+}
+
+/// Обойти шары на опросе и поднять версию тем, чей отпечаток сдвинулся.
+/// Обход блокирующий, поэтому уезжает с async-воркеров.
+async fn poll_shares(state: &AgentState, watched: &mut HashMap<String, Watched>) {
+    let jobs: Vec<(String, PathBuf, bool)> = watched
+        .iter()
+        .filter(|(_, w)| matches!(w.mode, Mode::Poll(_)))
+        .map(|(id, w)| (id.clone(), w.root.clone(), w.is_file))
+        .collect();
+    if jobs.is_empty() {
+        return;
+    }
+    let Ok(prints) = tokio::task::spawn_blocking(move || {
+        jobs.into_iter()
+            .map(|(id, root, is_file)| (id, fingerprint(&root, is_file)))
+            .collect::<Vec<_>>()
+    })
+    .await
+    else {
+        return;
+    };
+    for (id, print) in prints {
+        let Some(Watched { mode: Mode::Poll(last), .. }) = watched.get_mut(&id) else {
+            continue;
+        };
+        if last.is_some_and(|l| l != print) {
+            state.versions.bump(&id);
+        }
+        *last = Some(print);
+    }
+}
+
+/// Разложить событие по шарам, которых оно касается.
+fn collect(watched: &HashMap<String, Watched>, change: Change, out: &mut HashSet<String>) {
+    let native = watched.iter().filter(|(_, w)| matches!(w.mode, Mode::Native));
+    match change {
+        Change::Rescan => out.extend(native.map(|(id, _)| id.clone())),
+        Change::Paths(paths) => {
+            for (id, w) in native {
+                if paths.iter().any(|p| touches(&w.root, w.is_file, p)) {
+                    out.insert(id.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Касается ли путь из события шары с корнем `root`. Служебные `.xr-` имена
+/// (временные файлы загрузки, каталоги импорта) в манифест не попадают, и
+/// возня в них ждущих не будит: готовый файл всё равно придёт переименованием
+/// под своим именем. Для шары-файла следим за родительским каталогом, и
+/// соседние файлы её не касаются.
+fn touches(root: &Path, is_file: bool, path: &Path) -> bool {
+    if is_file {
+        return path == root;
+    }
+    let Ok(rel) = path.strip_prefix(root) else {
+        return false;
+    };
+    !rel
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with(RESERVED_PREFIX))
+}
+
+/// Отпечаток содержимого шары для опроса: пути, размеры и mtime всех файлов
+/// манифеста. Без хеширования содержимого, то есть цена как у листинга.
+/// Сумма по файлам не зависит от порядка обхода, а порядок `readdir` не
+/// обещан.
+fn fingerprint(root: &Path, is_file: bool) -> u64 {
+    fn one(path: &Path, meta: &std::fs::Metadata) -> u64 {
+        let mut h = DefaultHasher::new();
+        path.hash(&mut h);
+        meta.len().hash(&mut h);
+        meta.modified().ok().hash(&mut h);
+        h.finish()
+    }
+    if is_file {
+        return std::fs::metadata(root).map(|m| one(root, &m)).unwrap_or(0);
+    }
+    walk_share(root)
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok().map(|m| one(e.path(), &m)))
+        .fold(0u64, u64::wrapping_add)
+}
+
+/// Вотчер ОС и счётчик ссылок на его цели: две шары-файла из одного каталога
+/// делят один вотч, и снятие одной не должно глушить другую.
+struct Native {
+    watcher: RecommendedWatcher,
+    targets: HashMap<PathBuf, usize>,
+}
+
+impl Native {
+    fn new(tx: mpsc::UnboundedSender<Change>) -> Option<Self> {
+        let handler = move |res: notify::Result<notify::Event>| {
+            let change = match res {
+                Ok(ev) if !ev.need_rescan() => Change::Paths(ev.paths),
+                _ => Change::Rescan,
+            };
+            let _ = tx.send(change);
+        };
+        match notify::recommended_watcher(handler) {
+            Ok(watcher) => Some(Self { watcher, targets: HashMap::new() }),
+            Err(e) => {
+                tracing::warn!("уведомления ОС недоступны ({e}), все шары на опросе");
+                None
+            }
+        }
+    }
+
+    /// Цель вотча: каталог шары целиком, у шары-файла её каталог без
+    /// рекурсии (атомарная замена файла меняет inode, и вотч на сам файл
+    /// после первой же замены ослеп бы).
+    fn target(root: &Path, is_file: bool) -> (PathBuf, RecursiveMode) {
+        match (is_file, root.parent()) {
+            (true, Some(parent)) => (parent.to_path_buf(), RecursiveMode::NonRecursive),
+            _ => (root.to_path_buf(), RecursiveMode::Recursive),
+        }
+    }
+
+    fn add(&mut self, root: &Path, is_file: bool) -> notify::Result<()> {
+        let (target, mode) = Self::target(root, is_file);
+        if let Some(n) = self.targets.get_mut(&target) {
+            *n += 1;
+            return Ok(());
+        }
+        self.watcher.watch(&target, mode)?;
+        self.targets.insert(target, 1);
+        Ok(())
+    }
+
+    fn remove(&mut self, root: &Path, is_file: bool) {
+        let (target, _) = Self::target(root, is_file);
+        let Some(n) = self.targets.get_mut(&target) else {
+            return;
+        };
+        *n -= 1;
+        if *n == 0 {
+            self.targets.remove(&target);
+            let _ = self.watcher.unwatch(&target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_start_fresh_and_bump_wakes_subscribers() {
+        let v = ShareVersions::new();
+        let start = v.current("a");
+        // Начало это момент первого знакомства, а не ноль: после перезапуска
+        // агента клиент с версией прошлой жизни не должен совпасть.
+        assert!(start > 1_600_000_000_000, "{start}");
+        let mut rx = v.subscribe("a");
+        v.bump("a");
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(*rx.borrow_and_update(), start + 1);
+        assert_eq!(v.current("a"), start + 1);
+
+        // Снятая шара закрывает канал своим ждущим.
+        v.retain(&SharesMap::new());
+        assert!(rx.has_changed().is_err());
+    }
+
+    #[test]
+    fn events_map_to_shares_and_skip_service_names() {
+        let root = Path::new("/srv/share");
+        assert!(touches(root, false, Path::new("/srv/share/a/b.txt")));
+        assert!(touches(root, false, root), "удаление корня тоже изменение");
+        assert!(!touches(root, false, Path::new("/srv/share/.xr-part-0001")));
+        assert!(!touches(root, false, Path::new("/srv/share/.xr-import-x/out.mp4")));
+        assert!(!touches(root, false, Path::new("/srv/other/a.txt")));
+
+        let file = Path::new("/srv/one.iso");
+        assert!(touches(file, true, file));
+        assert!(!touches(file, true, Path::new("/srv/two.iso")), "сосед по каталогу не в счёт");
+    }
+
+    #[test]
+    fn fingerprint_moves_on_change_but_not_on_service_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"one").unwrap();
+        let first = fingerprint(dir.path(), false);
+        assert_eq!(first, fingerprint(dir.path(), false), "без изменений отпечаток стоит");
+
+        std::fs::write(dir.path().join(".xr-part-1"), b"half").unwrap();
+        assert_eq!(first, fingerprint(dir.path(), false), "временный файл загрузки не в счёт");
+
+        std::fs::write(dir.path().join("a.txt"), b"one and more").unwrap();
+        assert_ne!(first, fingerprint(dir.path(), false));
+    }
+
+    #[tokio::test]
+    async fn native_watch_reports_writes_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let Some(mut native) = Native::new(tx) else {
+            // Песочница без inotify: проверять нечего, опрос покрыт отдельно.
+            return;
+        };
+        native.add(&root, false).unwrap();
+
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/new.txt"), b"x").unwrap();
+
+        let mut watched = HashMap::new();
+        watched.insert(
+            "s".to_string(),
+            Watched { root: root.clone(), is_file: false, mode: Mode::Native },
+        );
+        let mut hit = HashSet::new();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while hit.is_empty() {
+            let change = tokio::time::timeout_at(deadline, rx.recv())
+                .await
+                .expect("событие ОС не пришло за 5 секунд")
+                .unwrap();
+            collect(&watched, change, &mut hit);
+        }
+        assert!(hit.contains("s"));
+    }
+}
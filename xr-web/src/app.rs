@@ -18,10 +18,11 @@ use axum::response::{IntoResponse, Response};
 use axum::routing::{any, get, post};
 use axum::Router;
 use xr_proto::share::{
-    encode_expose_mandate, valid_publication_name, WebRoute, EXPOSE_HEADER, FORWARDED_AUTH_HEADER,
+    encode_expose_mandate, valid_publication_name, WebAccessKind, WebRoute, EXPOSE_HEADER,
+    FORWARDED_AUTH_HEADER,
 };
 
-use crate::hub::{HubApi, HubError, RouteCache};
+use crate::hub::{Credentials, HubApi, HubError, RouteCache};
 use crate::pool::{AgentPool, LeasedBody};
 use crate::session::{self, Sessions};
 
@@ -29,9 +30,9 @@ use crate::session::{self, Sessions};
 /// путями проксируемого приложения (LLD-38 п. 2.2).
 pub const SERVICE_PREFIX: &str = "/.xr-web/";
 
-/// Метка, зарезервированная под страницы шар (`s.<домен>`, LLD-38 п. 2.6).
-/// Публикацией она не считается даже сейчас, пока фазы 4 нет: иначе имя `s` в
-/// реестре хаба однажды перехватило бы весь путь шар.
+/// Метка страниц шар (`s.<домен>`, LLD-38 п. 2.6). Публикацией она не
+/// считается никогда: иначе имя `s` в реестре хаба перехватило бы весь путь
+/// шар.
 pub const SHARE_LABEL: &str = "s";
 
 /// Заголовки, которые не переезжают через посредника: они про соединение, а не
@@ -39,7 +40,7 @@ pub const SHARE_LABEL: &str = "s";
 /// пула. На запросе апгрейда `Connection` и `Upgrade` ставятся заново своими
 /// значениями (п. 2.4): дальше едет ровно тот протокол, который фронт умеет
 /// сплайсить.
-const HOP_BY_HOP: [&str; 8] = [
+pub(crate) const HOP_BY_HOP: [&str; 8] = [
     "connection",
     "keep-alive",
     "proxy-authenticate",
@@ -74,6 +75,20 @@ pub struct WebState {
     pub pool: Arc<AgentPool>,
     pub sessions: Sessions,
     pub attempts: LoginAttempts,
+    /// Страницы шар на `s.<домен>`: ключ хаба и кеш маршрутов шар.
+    pub shares: crate::share::SharePages,
+    /// Срок своего сертификата и продления ACME для `/.xr-web/metrics`. За
+    /// фронтом сертификата нет, и метрик срока тоже.
+    pub cert: Arc<xr_proto::acme::CertStatus>,
+    /// Кеш ответов публикаций на диске (`[cache]`). `None` значит каждый
+    /// запрос едет до агента.
+    pub cache: Option<Arc<crate::cache::ResponseCache>>,
+    /// Жать ли несжатые текстовые ответы на выходе (`[web] compress`).
+    pub compress: bool,
+    /// Моменты «выйти везде» из прошлого ответа хаба. Сдвиг момента значит и
+    /// смену политики входа, а она едет в маршруте: маршрут такой публикации
+    /// из кеша выбрасывается.
+    cutoffs: Mutex<HashMap<String, u64>>,
 }
 
 impl WebState {
@@ -85,8 +100,72 @@ impl WebState {
             pool,
             sessions: Sessions::new(session_ttl),
             attempts: LoginAttempts::default(),
+            shares: crate::share::SharePages::disabled(),
+            cert: Arc::default(),
+            cache: None,
+            compress: true,
+            cutoffs: Mutex::default(),
         }
     }
+
+    /// Сессии из файла вместо сессий в памяти: так их собирает `main`, чтобы
+    /// деплой никого не разлогинивал.
+    pub fn with_sessions(mut self, sessions: Sessions) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Страницы шар с ключом хаба из конфига. Без него хост шар отвечает, что
+    /// страницы выключены.
+    pub fn with_share_pages(mut self, shares: crate::share::SharePages) -> Self {
+        self.shares = shares;
+        self
+    }
+
+    /// Статус сертификата, который ведёт листенер с `[tls]`.
+    pub fn with_cert_status(mut self, cert: Arc<xr_proto::acme::CertStatus>) -> Self {
+        self.cert = cert;
+        self
+    }
+
+    /// Кеш ответов и сжатие из конфига.
+    pub fn with_cache(mut self, cache: Option<Arc<crate::cache::ResponseCache>>, compress: bool) -> Self {
+        self.cache = cache;
+        self.compress = compress;
+        self
+    }
+}
+
+/// Как часто фронт докладывает хабу сессии и забирает «выйти везде». Это же
+/// верхняя граница того, сколько снятая в админке сессия ещё проживёт.
+pub const SESSION_SYNC_SECS: u64 = 15;
+
+/// Один обмен с хабом по сессиям: доклад живых, снятие разлогиненных, запись
+/// файла. Хаб недоступен значит снимать нечего: сессии живут как жили, а
+/// продления всё равно ложатся на диск.
+pub async fn sync_sessions(state: &WebState) {
+    let report = state.sessions.report(now_unix());
+    match state.hub.report_sessions(report).await {
+        Ok(verdict) => {
+            {
+                let mut seen = state.cutoffs.lock().expect("cutoffs lock");
+                for (publication, cut) in &verdict.logout_before {
+                    if seen.get(publication) != Some(cut) {
+                        state.routes.forget(publication);
+                    }
+                }
+                seen.clone_from(&verdict.logout_before);
+            }
+            let removed = state.sessions.apply(&verdict);
+            if removed > 0 {
+                tracing::info!("сессии: {removed} снято по «выйти везде» и отзыву гостевых ссылок");
+            }
+        }
+        Err(e) => tracing::warn!("сессии: хаб не принял доклад ({e})"),
+    }
+    if let Err(e) = state.sessions.flush().await {
+        tracing::warn!("сессии не записались на диск: {e}");
+    }
 }
 
 /// Роутер фронта. Служебные пути разобраны явно, всё остальное это запрос к
@@ -94,8 +173,10 @@ impl WebState {
 pub fn router(state: Arc<WebState>) -> Router {
     Router::new()
         .route("/.xr-web/healthz", get(healthz))
+        .route("/.xr-web/metrics", get(metrics))
         .route("/.xr-web/login", post(login))
         .route("/.xr-web/logout", post(logout))
+        .route("/.xr-web/guest/{token}", get(guest))
         .fallback(any(entry))
         .with_state(state)
 }
@@ -106,17 +187,30 @@ async fn healthz() -> Response {
     (StatusCode::OK, "ok").into_response()
 }
 
+/// Срок сертификата, продления и счётчики кеша в формате Prometheus. Срок и
+/// так виден любому браузеру, поэтому путь открыт, как и здоровье.
+async fn metrics(State(state): State<Arc<WebState>>) -> Response {
+    let mut body = state.cert.render("xr-web");
+    if let Some(cache) = &state.cache {
+        body.push_str(&cache.render());
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
 #[derive(serde::Deserialize)]
 struct LoginForm {
     #[serde(default)]
     username: String,
     #[serde(default)]
     password: String,
+    #[serde(default)]
+    invite: String,
 }
 
 async fn login(State(state): State<Arc<WebState>>, req: Request) -> Response {
     let host = host_of(req.headers());
     let peer = peer_of(&req);
+    let client = client_of(&req, &peer);
     let Some(publication) = publication_of(&host, &state.domain) else {
         return unknown_host(&host, &state.domain);
     };
@@ -126,7 +220,7 @@ async fn login(State(state): State<Arc<WebState>>, req: Request) -> Response {
         Err(_) => {
             return html(
                 StatusCode::BAD_REQUEST,
-                pages_login(&publication, Some("форма входа не разобралась")),
+                login_page(&state, &publication, Some("форма входа не разобралась")).await,
             )
         }
     };
@@ -137,23 +231,29 @@ async fn login(State(state): State<Arc<WebState>>, req: Request) -> Response {
         tracing::warn!("вход {publication}: адрес {peer} упёрся в задержку, ещё {secs} с");
         return html(
             StatusCode::TOO_MANY_REQUESTS,
-            pages_login(
+            login_page(
+                &state,
                 &publication,
                 Some(&format!("слишком много попыток, повторить через {secs} с")),
-            ),
+            )
+            .await,
         );
     }
 
-    match state
-        .hub
-        .verify_password(form.username.clone(), form.password)
-        .await
-    {
-        Ok(true) => {
+    // Какие поля нужны, решает политика публикации, и проверяет её хаб: фронт
+    // отдаёт ему всё, что пришло с формы.
+    let creds = Credentials {
+        username: form.username.clone(),
+        password: form.password,
+        invite: form.invite,
+    };
+    match state.hub.verify_access(publication.clone(), creds).await {
+        Ok(Some(pass)) => {
             state.attempts.succeeded(&key);
-            let token = state.sessions.open(&publication, &form.username, now_unix());
-            tracing::info!("вход {publication}: {} с адреса {peer}, успех", form.username);
-            let cookie = session::set_cookie(&token, state.sessions.ttl_secs());
+            let now = now_unix();
+            let token = state.sessions.open_granted(&publication, &pass.who, &pass.grant, pass.until, now, &client);
+            tracing::info!("вход {publication}: {} с адреса {peer}, успех", pass.who);
+            let cookie = session::set_cookie(&token, state.sessions.cookie_ttl(pass.until, now));
             (
                 StatusCode::SEE_OTHER,
                 [
@@ -163,15 +263,15 @@ async fn login(State(state): State<Arc<WebState>>, req: Request) -> Response {
             )
                 .into_response()
         }
-        Ok(false) => {
+        Ok(None) => {
             state.attempts.failed(&key, now_ms());
             tracing::warn!(
-                "вход {publication}: {} с адреса {peer}, неверный пароль",
+                "вход {publication}: {} с адреса {peer}, отказ",
                 form.username
             );
             html(
                 StatusCode::UNAUTHORIZED,
-                pages_login(&publication, Some("неверный логин или пароль")),
+                login_page(&state, &publication, Some("неверный логин или пароль")).await,
             )
         }
         Err(e) => {
@@ -180,7 +280,7 @@ async fn login(State(state): State<Arc<WebState>>, req: Request) -> Response {
                 StatusCode::SERVICE_UNAVAILABLE,
                 crate::pages::failure(
                     "Вход недоступен",
-                    &format!("хаб не ответил на проверку пароля: {e}"),
+                    &format!("хаб не ответил на проверку входа: {e}"),
                 ),
             )
         }
@@ -201,6 +301,79 @@ async fn logout(State(state): State<Arc<WebState>>, headers: HeaderMap) -> Respo
         .into_response()
 }
 
+/// Гостевая ссылка: `/.xr-web/guest/<токен>`. Хаб признаёт ссылку, фронт
+/// открывает сессию не дольше её срока и уводит на корень публикации. Промахи
+/// считаются тем же счётчиком, что и промахи пароля.
+async fn guest(
+    State(state): State<Arc<WebState>>,
+    axum::extract::Path(token): axum::extract::Path<String>,
+    req: Request,
+) -> Response {
+    let host = host_of(req.headers());
+    let peer = peer_of(&req);
+    let client = client_of(&req, &peer);
+    let Some(publication) = publication_of(&host, &state.domain) else {
+        return unknown_host(&host, &state.domain);
+    };
+    let key = format!("{peer}|{publication}");
+    if let Some(wait_ms) = state.attempts.blocked_for(&key, now_ms()) {
+        let secs = wait_ms.div_ceil(1000);
+        return html(
+            StatusCode::TOO_MANY_REQUESTS,
+            crate::pages::failure(
+                "Слишком много попыток",
+                &format!("повторить через {secs} с"),
+            ),
+        );
+    }
+
+    match state.hub.redeem_guest(publication.clone(), token).await {
+        Ok(Some(pass)) => {
+            state.attempts.succeeded(&key);
+            let now = now_unix();
+            let token = state.sessions.open_guest(
+                &publication,
+                &pass.label,
+                &pass.id,
+                pass.expires,
+                now,
+                &client,
+            );
+            tracing::info!("вход {publication}: гостевая ссылка {} с адреса {peer}", pass.id);
+            let cookie = session::set_cookie(&token, state.sessions.cookie_ttl(pass.expires, now));
+            (
+                StatusCode::SEE_OTHER,
+                [
+                    (header::SET_COOKIE, cookie),
+                    (header::LOCATION, "/".to_string()),
+                ],
+            )
+                .into_response()
+        }
+        Ok(None) | Err(HubError::NotFound) => {
+            state.attempts.failed(&key, now_ms());
+            tracing::warn!("вход {publication}: негодная гостевая ссылка с адреса {peer}");
+            html(
+                StatusCode::FORBIDDEN,
+                crate::pages::failure(
+                    "Ссылка недействительна",
+                    "гостевая ссылка истекла или отозвана: попросите у владельца новую",
+                ),
+            )
+        }
+        Err(e) => {
+            tracing::warn!("вход {publication}: хаб не дал вердикта по гостевой ссылке ({e})");
+            html(
+                StatusCode::SERVICE_UNAVAILABLE,
+                crate::pages::failure(
+                    "Вход недоступен",
+                    &format!("хаб не ответил на проверку ссылки: {e}"),
+                ),
+            )
+        }
+    }
+}
+
 /// Запрос к публикации: адресация, гейт сессии, маршрут, туннель.
 async fn entry(State(state): State<Arc<WebState>>, req: Request) -> Response {
     let started = std::time::Instant::now();
@@ -209,6 +382,15 @@ async fn entry(State(state): State<Arc<WebState>>, req: Request) -> Response {
     let path = req.uri().path().to_string();
     let host = host_of(req.headers());
 
+    if is_share_host(&host, &state.domain) {
+        let response = crate::share::serve(&state, req).await;
+        tracing::info!(
+            "{SHARE_LABEL} {method} {path} -> {} за {} мс",
+            response.status().as_u16(),
+            started.elapsed().as_millis()
+        );
+        return response;
+    }
     let Some(publication) = publication_of(&host, &state.domain) else {
         return unknown_host(&host, &state.domain);
     };
@@ -224,7 +406,15 @@ async fn entry(State(state): State<Arc<WebState>>, req: Request) -> Response {
         );
     }
 
-    let response = serve_publication(&state, &publication, &host, req).await;
+    let accept_encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let mut response = serve_publication(&state, &publication, &host, req).await;
+    if state.compress {
+        response = crate::cache::compress(response, &method, accept_encoding.as_deref());
+    }
     tracing::info!(
         "{publication} {method} {path} -> {} за {} мс",
         response.status().as_u16(),
@@ -239,9 +429,18 @@ async fn serve_publication(
     host: &str,
     mut req: Request,
 ) -> Response {
+    let client = client_of(&req, &peer_of(&req));
     let session = session_token(req.headers())
-        .and_then(|token| state.sessions.touch(&token, publication, now_unix()));
-    if session.is_none() {
+        .and_then(|token| state.sessions.touch(&token, publication, now_unix(), &client));
+    let has_session = session.is_some();
+    // Без сессии решает политика публикации: открытую пускаем так, для
+    // остальных рисуем её форму входа. Политику несёт маршрут, поэтому он
+    // берётся и без сессии; туннель без сессии не поднимается.
+    let access = match session {
+        Some(_) => None,
+        None => Some(access_of(state, publication).await),
+    };
+    if let Some(access) = access.filter(|a| *a != WebAccessKind::Public) {
         // XHR дашборда получает код, а не страницу: HTML в разборе JSON
         // выглядит как поломка сервиса, хотя это всего лишь протухшая сессия.
         return if wants_json(req.headers()) {
@@ -252,10 +451,39 @@ async fn serve_publication(
             )
                 .into_response()
         } else {
-            html(StatusCode::OK, pages_login(publication, None))
+            html(StatusCode::OK, crate::pages::login(publication, access, None))
         };
     }
 
+    // Кеш стоит за гейтом: с диска получает ответ только тот, кого пустила
+    // бы сама публикация. Свежий ответ не стоит ни маршрута, ни туннеля.
+    let now = now_unix();
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let cacheable = state.cache.is_some() && crate::cache::cacheable_request(req.method(), req.headers());
+    let private = crate::cache::private_request(req.headers(), has_session);
+    let cached = match &state.cache {
+        Some(cache) if cacheable => cache.lookup(publication, &uri, req.headers(), now),
+        _ => None,
+    };
+    if let (Some(cache), Some(hit)) = (&state.cache, &cached) {
+        if hit.fresh(req.headers(), now) {
+            if let Some(resp) = crate::cache::serve_hit(cache, hit, req.headers(), now).await {
+                return resp;
+            }
+        }
+    }
+    let cached = cached.filter(|hit| hit.revalidatable());
+    // Заголовки браузера до подмены валидаторов: по ним сверяются `Vary` и
+    // его собственный условный запрос.
+    let asked = cacheable.then(|| req.headers().clone());
+    if let Some(hit) = &cached {
+        crate::cache::add_validators(req.headers_mut(), hit);
+    }
+
     let route = match state.routes.get(publication, now_unix()).await {
         Ok(route) => route,
         Err(HubError::NotFound) => {
@@ -319,7 +547,38 @@ async fn serve_publication(
                 tracing::warn!("публикация {publication}: агент отверг мандат");
                 state.routes.forget(publication);
             }
-            Response::from_parts(parts, Body::new(LeasedBody::new(body, lease)))
+            if let (Some(cache), Some(hit), Some(asked)) = (&state.cache, &cached, &asked) {
+                if parts.status == StatusCode::NOT_MODIFIED {
+                    // У `304` тела нет: соединение сразу уходит обратно в пул.
+                    drop((body, lease));
+                    let hit = cache.refresh(hit, &parts.headers, now_unix());
+                    return match cache.respond(&hit, asked, now_unix()).await {
+                        Some(resp) => resp,
+                        None => html(
+                            StatusCode::BAD_GATEWAY,
+                            crate::pages::failure(
+                                "Кеш потерял ответ",
+                                "агент подтвердил ответ из кеша, а его файла уже нет: обновите страницу",
+                            ),
+                        ),
+                    };
+                }
+            }
+            let body = LeasedBody::new(body, lease);
+            let body = match (&state.cache, &asked) {
+                (Some(cache), Some(asked)) => cache.tee(
+                    publication,
+                    &uri,
+                    asked,
+                    parts.status,
+                    &parts.headers,
+                    private,
+                    body,
+                    now,
+                ),
+                _ => Body::new(body),
+            };
+            Response::from_parts(parts, body)
         }
         Err(e) => {
             tracing::warn!("обрыв туннеля {publication}: {e}");
@@ -473,11 +732,18 @@ pub fn publication_of(host: &str, domain: &str) -> Option<String> {
     (valid_publication_name(name) && name != SHARE_LABEL).then(|| name.to_string())
 }
 
+/// Хост страниц шар: `s.<домен>` с любым портом и регистром.
+fn is_share_host(host: &str, domain: &str) -> bool {
+    let host = host.trim().to_ascii_lowercase();
+    let host = host.split(':').next().unwrap_or_default().trim_end_matches('.');
+    host == format!("{SHARE_LABEL}.{domain}")
+}
+
 fn unknown_host(host: &str, domain: &str) -> Response {
-    // Путь шар (`s.<домен>`) это фаза 4 (XR-265): пока его нет, отказ называет
-    // причину, а не притворяется поломкой.
-    let detail = if host.split(':').next().unwrap_or_default() == format!("{SHARE_LABEL}.{domain}") {
-        "страница шары наружу ещё не включена".to_string()
+    // На хост шар сюда попадают только служебные ручки входа: у страниц шар
+    // входа нет, доступ им даёт ссылка.
+    let detail = if is_share_host(host, domain) {
+        "у страниц шар нет входа: их открывает ссылка с токеном от владельца".to_string()
     } else {
         format!("имя {host} не адресует публикацию на домене {domain}")
     };
@@ -495,6 +761,19 @@ fn host_of(headers: &HeaderMap) -> String {
         .to_string()
 }
 
+/// Адрес и браузер запроса для списка сессий в админке.
+fn client_of(req: &Request, peer: &str) -> session::Client {
+    session::Client {
+        ip: peer.to_string(),
+        user_agent: req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
 /// Адрес браузера для лога и лимита попыток: за фронтом он приезжает
 /// заголовком, без фронта берётся у сокета.
 fn peer_of(req: &Request) -> String {
@@ -542,8 +821,20 @@ fn html(status: StatusCode, body: String) -> Response {
         .into_response()
 }
 
-fn pages_login(publication: &str, error: Option<&str>) -> String {
-    crate::pages::login(publication, error)
+/// Вид политики входа публикации. Маршрут берётся из кеша, так что гейт
+/// стоит хабу не больше, чем сам запрос. Хаб недоступен или публикации нет:
+/// рисуем форму владельца, а отказ назовёт уже вердикт хаба.
+async fn access_of(state: &WebState, publication: &str) -> WebAccessKind {
+    state
+        .routes
+        .get(publication, now_unix())
+        .await
+        .map(|route| route.access)
+        .unwrap_or_default()
+}
+
+async fn login_page(state: &WebState, publication: &str, error: Option<&str>) -> String {
+    crate::pages::login(publication, access_of(state, publication).await, error)
 }
 
 pub fn now_unix() -> u64 {
@@ -711,7 +1002,8 @@ mod tests {
         let page = text(resp).await;
         assert!(page.contains("/.xr-web/login"), "{page}");
         assert_eq!(s.dialer.dials.load(Ordering::SeqCst), 0, "туннель не поднимался");
-        assert_eq!(s.hub.route_calls.load(Ordering::SeqCst), 0, "маршрут не спрашивали");
+        // Маршрут спрошен один раз: в нём политика входа, а дальше он в кеше.
+        assert_eq!(s.hub.route_calls.load(Ordering::SeqCst), 1);
 
         // XHR дашборда: код и JSON, а не HTML.
         for req in [
@@ -740,6 +1032,171 @@ mod tests {
         assert!(text(resp).await.contains("agent ok"));
     }
 
+    #[tokio::test]
+    async fn logout_everywhere_from_the_hub_closes_browser_sessions() {
+        let s = stand(HubMode::Route(far_future()), DuplexDialer::serving());
+        let cookie = cookie_value(&sign_in(&s.state, HOST).await);
+        let resp = call(
+            &s.state,
+            get(HOST, "/")
+                .header(header::COOKIE, &cookie)
+                .header(header::USER_AGENT, "Firefox/128")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Доклад несёт владельца и браузер, но не токен.
+        sync_sessions(&s.state).await;
+        let report = s.hub.reported.lock().unwrap().clone().expect("доклад дошёл до хаба");
+        assert_eq!(report.sessions.len(), 1);
+        assert_eq!(report.sessions[0].publication, "dash");
+        assert_eq!(report.sessions[0].user_agent, "Firefox/128");
+        assert!(!cookie.contains(&report.sessions[0].id));
+
+        // В админке нажали «выйти везде»: следующий обмен снимает сессию.
+        s.hub
+            .logout_before
+            .lock()
+            .unwrap()
+            .insert("dash".into(), now_unix() + 1);
+        sync_sessions(&s.state).await;
+        let resp = call(
+            &s.state,
+            get(HOST, "/").header(header::COOKIE, &cookie).body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(text(resp).await.contains("/.xr-web/login"), "снятая сессия ведёт на вход");
+    }
+
+    fn stand_with_access(access: WebAccessKind) -> Stand {
+        stand_with_hub(crate::test_support::FakeHub {
+            access,
+            ..crate::test_support::FakeHub::new(HubMode::Route(far_future()))
+        })
+    }
+
+    fn stand_with_hub(hub: crate::test_support::FakeHub) -> Stand {
+        let hub = Arc::new(hub);
+        let dialer = Arc::new(DuplexDialer::serving());
+        let state = Arc::new(WebState::new(
+            "web.example.com".into(),
+            hub.clone(),
+            AgentPool::new(dialer.clone()),
+            3600,
+        ));
+        Stand { state, hub, dialer }
+    }
+
+    fn login_form(body: &str) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/.xr-web/login")
+            .header(header::HOST, HOST)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn public_publication_needs_no_session() {
+        let s = stand_with_access(WebAccessKind::Public);
+        let resp = call(&s.state, get(HOST, "/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(text(resp).await.contains("agent ok"), "открытая публикация идёт без входа");
+        assert!(s.state.sessions.is_empty(), "сессия на открытой не заводится");
+    }
+
+    #[tokio::test]
+    async fn login_form_and_verdict_follow_the_policy() {
+        // Пароль публикации: формы с логином нет, пускает один пароль.
+        let s = stand_with_access(WebAccessKind::Password);
+        let page = text(call(&s.state, get(HOST, "/").body(Body::empty()).unwrap()).await).await;
+        assert!(!page.contains("name=\"username\""), "{page}");
+        let resp = call(&s.state, login_form("password=открой")).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let report = s.state.sessions.report(now_unix());
+        assert_eq!(report.sessions[0].username, "пароль публикации");
+
+        // Инвайт: поле кода, чужой код не пускает.
+        let s = stand_with_access(WebAccessKind::Invites);
+        let page = text(call(&s.state, get(HOST, "/").body(Body::empty()).unwrap()).await).await;
+        assert!(page.contains("name=\"invite\""), "{page}");
+        let resp = call(&s.state, login_form("invite=чужой")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(text(resp).await.contains("name=\"invite\""), "отказ рисует ту же форму");
+        let resp = call(&s.state, login_form("invite=инвайт-код")).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn invite_session_is_capped_and_ends_with_the_invite() {
+        let s = stand_with_hub(crate::test_support::FakeHub {
+            access: WebAccessKind::Invites,
+            invite_until: now_unix() + 600,
+            ..crate::test_support::FakeHub::new(HubMode::Route(far_future()))
+        });
+        let resp = call(&s.state, login_form("invite=инвайт-код")).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let set_cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+        assert!(set_cookie.contains("Max-Age=600") || set_cookie.contains("Max-Age=599"), "{set_cookie}");
+        let cookie = cookie_value(&set_cookie);
+        let report = s.state.sessions.report(now_unix());
+        assert_eq!(report.sessions[0].grant, crate::test_support::INVITE_GRANT);
+
+        // Инвайт отозван: следующий обмен с хабом снимает сессию.
+        s.hub.revoked_guests.lock().unwrap().push(crate::test_support::INVITE_GRANT.into());
+        sync_sessions(&s.state).await;
+        let resp = call(
+            &s.state,
+            get(HOST, "/").header(header::COOKIE, &cookie).body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(text(resp).await.contains("/.xr-web/login"));
+    }
+
+    #[tokio::test]
+    async fn guest_link_opens_a_capped_session_until_revoked() {
+        let s = stand(HubMode::Route(far_future()), DuplexDialer::serving());
+        let expires = now_unix() + 600;
+        s.hub.guests.lock().unwrap().insert(
+            "guest-token".into(),
+            crate::hub::GuestPass { id: "g1".into(), label: "Вася".into(), expires },
+        );
+
+        let resp = call(
+            &s.state,
+            get(HOST, "/.xr-web/guest/guest-token").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let set_cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+        assert!(set_cookie.contains("Max-Age=600") || set_cookie.contains("Max-Age=599"), "{set_cookie}");
+        let cookie = cookie_value(&set_cookie);
+        let resp = call(
+            &s.state,
+            get(HOST, "/").header(header::COOKIE, &cookie).body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(text(resp).await.contains("agent ok"));
+        assert_eq!(s.state.sessions.report(now_unix()).sessions[0].username, "гость: Вася");
+
+        // Чужая ссылка: отказ с причиной, а не форма входа.
+        let resp = call(&s.state, get(HOST, "/.xr-web/guest/nope").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Владелец отозвал ссылку: следующий обмен с хабом снимает сессию.
+        s.hub.revoked_guests.lock().unwrap().push("g1".into());
+        sync_sessions(&s.state).await;
+        let resp = call(
+            &s.state,
+            get(HOST, "/").header(header::COOKIE, &cookie).body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert!(text(resp).await.contains("/.xr-web/login"));
+    }
+
     #[tokio::test]
     async fn wrong_password_and_hub_silence_are_told_apart() {
         let s = stand(HubMode::Route(far_future()), DuplexDialer::serving());
@@ -808,6 +1265,136 @@ mod tests {
         assert_eq!(s.dialer.dials.load(Ordering::SeqCst), dials_before);
     }
 
+    /// Стенд с кешем ответов в `dir` и заданной политикой входа.
+    fn stand_cached(access: WebAccessKind, dir: &std::path::Path) -> Stand {
+        let s = stand_with_access(access);
+        let cache = crate::cache::ResponseCache::open(&crate::config::CacheConfig {
+            dir: dir.to_path_buf(),
+            max_mb_per_publication: 16,
+            max_object_mb: 1,
+        })
+        .unwrap();
+        let state = Arc::try_unwrap(s.state).ok().expect("стенд ещё ни с кем не делился");
+        Stand { state: Arc::new(state.with_cache(Some(cache), true)), ..s }
+    }
+
+    /// Дождаться, пока копия ответа ляжет на диск: пишется она отдельной
+    /// задачей после последнего кадра.
+    async fn settle(s: &Stand, path: &str) {
+        let cache = s.state.cache.as_ref().unwrap();
+        for _ in 0..100 {
+            if cache.lookup("dash", path, &HeaderMap::new(), now_unix()).is_some() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn public_assets_come_from_disk_and_revalidate() {
+        let dir = tempfile::tempdir().unwrap();
+        let s = stand_cached(WebAccessKind::Public, dir.path());
+        let asset = crate::test_support::ASSET_BODY.repeat(200);
+
+        let resp = call(&s.state, get(HOST, "/static/app.js").body(Body::empty()).unwrap()).await;
+        assert_eq!(text(resp).await, asset);
+        settle(&s, "/static/app.js").await;
+        let routes = s.hub.route_calls.load(Ordering::SeqCst);
+
+        // Свежий ответ едет с диска: ни маршрута, ни агента.
+        let resp = call(&s.state, get(HOST, "/static/app.js").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().contains_key(header::AGE));
+        assert_eq!(text(resp).await, asset);
+        assert_eq!(s.dialer.asset_bodies.load(Ordering::SeqCst), 1);
+        assert_eq!(s.hub.route_calls.load(Ordering::SeqCst), routes);
+
+        // Условный запрос браузера сверяется с кешем.
+        let resp = call(
+            &s.state,
+            get(HOST, "/static/app.js")
+                .header(header::IF_NONE_MATCH, "\"v1\"")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        // Обновление страницы переспрашивает агента, и тот отвечает `304`.
+        let resp = call(
+            &s.state,
+            get(HOST, "/static/app.js")
+                .header(header::CACHE_CONTROL, "max-age=0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(text(resp).await, asset);
+        assert_eq!(s.dialer.asset_not_modified.load(Ordering::SeqCst), 1);
+        assert_eq!(s.dialer.asset_bodies.load(Ordering::SeqCst), 1);
+
+        // `no-cache` у приложения: каждый раз `304`, тело только первое.
+        for round in 0..3 {
+            let resp = call(&s.state, get(HOST, "/static/check.js").body(Body::empty()).unwrap()).await;
+            assert_eq!(text(resp).await, asset);
+            if round == 0 {
+                settle(&s, "/static/check.js").await;
+            }
+        }
+        assert_eq!(s.dialer.asset_bodies.load(Ordering::SeqCst), 2);
+        assert_eq!(s.dialer.asset_not_modified.load(Ordering::SeqCst), 3);
+
+        let resp = call(&s.state, get(HOST, "/.xr-web/metrics").body(Body::empty()).unwrap()).await;
+        assert!(text(resp).await.contains("xr_web_cache_hits_total 2"));
+    }
+
+    #[tokio::test]
+    async fn content_behind_the_gate_is_cached_only_when_public() {
+        let dir = tempfile::tempdir().unwrap();
+        let s = stand_cached(WebAccessKind::Owner, dir.path());
+        let cookie = cookie_value(&sign_in(&s.state, HOST).await);
+        let fetch = |path: &'static str| {
+            get(HOST, path).header(header::COOKIE, &cookie).body(Body::empty()).unwrap()
+        };
+
+        // Ответ владельцу без явного `public` на диск не ложится.
+        for _ in 0..2 {
+            let resp = call(&s.state, fetch("/static/app.js")).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            text(resp).await;
+        }
+        assert_eq!(s.dialer.asset_bodies.load(Ordering::SeqCst), 2);
+
+        // Объявленный `public` ложится, но и с диска уходит только за гейтом.
+        text(call(&s.state, fetch("/static/shared.js")).await).await;
+        settle(&s, "/static/shared.js").await;
+        text(call(&s.state, fetch("/static/shared.js")).await).await;
+        assert_eq!(s.dialer.asset_bodies.load(Ordering::SeqCst), 3);
+        let page = text(call(&s.state, get(HOST, "/static/shared.js").body(Body::empty()).unwrap()).await).await;
+        assert!(page.contains("/.xr-web/login"), "без сессии кеш не отвечает");
+    }
+
+    #[tokio::test]
+    async fn text_responses_are_gzipped_for_browsers_that_accept_it() {
+        use std::io::Read as _;
+        let s = stand_with_access(WebAccessKind::Public);
+        let resp = call(
+            &s.state,
+            get(HOST, "/static/app.js")
+                .header(header::ACCEPT_ENCODING, "gzip, deflate, br")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[header::ETAG], "W/\"v1\"");
+        let packed = axum::body::to_bytes(resp.into_body(), 1 << 22).await.unwrap();
+        let mut unpacked = String::new();
+        flate2::read::GzDecoder::new(&packed[..]).read_to_string(&mut unpacked).unwrap();
+        assert_eq!(unpacked, crate::test_support::ASSET_BODY.repeat(200));
+        assert!(packed.len() < unpacked.len() / 10);
+    }
+
     #[tokio::test]
     async fn test_web_route_cache_and_refresh() {
         // Свежий маршрут кешируется: страница с десятком запросов не стоит
@@ -936,6 +1523,9 @@ mod tests {
         let resp = call(&s.state, get(HOST, "/.xr-web/healthz").body(Body::empty()).unwrap()).await;
         assert_eq!(resp.status(), StatusCode::OK);
         assert_eq!(text(resp).await, "ok");
+        let resp = call(&s.state, get(HOST, "/.xr-web/metrics").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(text(resp).await.contains("xr_acme_renewals_total{service=\"xr-web\"}"));
 
         let cookie = cookie_value(&sign_in(&s.state, HOST).await);
         let dials = s.dialer.dials.load(Ordering::SeqCst);
@@ -978,8 +1568,8 @@ mod tests {
         for (host, expect) in [
             ("web.example.com", "не адресует публикацию"),
             ("dash.web.other.com", "не адресует публикацию"),
-            // Путь шар это фаза 4: отказ называет причину, а не молчит.
-            ("s.web.example.com", "шары"),
+            // Хост шар без ключа хаба: отказ называет причину, а не молчит.
+            ("s.web.example.com", "Страницы шар выключены"),
         ] {
             let resp = call(&s.state, get(host, "/").body(Body::empty()).unwrap()).await;
             assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{host}");
@@ -997,7 +1587,7 @@ mod tests {
         assert_eq!(publication_of("dash.web.example.com.evil.net", d), None);
         assert_eq!(publication_of("a.b.web.example.com", d), None, "метка одна, поддомены глубже не наши");
         assert_eq!(publication_of("", d), None);
-        // Метка `s` отдана страницам шар (фаза 4) и публикацией не считается.
+        // Метка `s` отдана страницам шар и публикацией не считается.
         assert_eq!(publication_of("s.web.example.com", d), None);
     }
 
@@ -1063,7 +1653,7 @@ mod tests {
         // LLD-38 п. 2.4: `101` проходит насквозь, байты идут в обе стороны,
         // закрытие с одной стороны доезжает до другой.
         let s = stand(HubMode::Route(far_future()), DuplexDialer::serving());
-        let token = s.state.sessions.open("dash", "owner", now_unix());
+        let token = s.state.sessions.open("dash", "owner", now_unix(), &session::Client::default());
         let mut client = browser_socket(&s.state);
 
         client.write_all(&upgrade_request(&token, "/live")).await.unwrap();
@@ -1102,7 +1692,7 @@ mod tests {
         // это неотличимо от зависания. Фронт закрывает апгрейд сам и раньше,
         // штатным `1001 going away`, чтобы приложение переподключилось.
         let s = stand_capped(HubMode::Route(far_future()), DuplexDialer::serving(), 1);
-        let token = s.state.sessions.open("dash", "owner", now_unix());
+        let token = s.state.sessions.open("dash", "owner", now_unix(), &session::Client::default());
         let mut client = browser_socket(&s.state);
 
         client.write_all(&upgrade_request(&token, "/live")).await.unwrap();
@@ -1134,7 +1724,7 @@ mod tests {
         // заход, а не подвешивают до таймаута. Браузер увидит на этом месте
         // отказ рукопожатия, скрипт проверки 502.
         let s = stand(HubMode::Route(far_future()), DuplexDialer::offline());
-        let token = s.state.sessions.open("dash", "owner", now_unix());
+        let token = s.state.sessions.open("dash", "owner", now_unix(), &session::Client::default());
         let mut client = browser_socket(&s.state);
 
         client.write_all(&upgrade_request(&token, "/live")).await.unwrap();
@@ -0,0 +1,1041 @@
+//! Кеш ответов публикаций на диске и сжатие на лету.
+//!
+//! Каждый запрос браузера к публикации едет через relay и аплинк агента, а
+//! это часто домашний ADSL: одни и те же JS и CSS дашборда тянутся заново на
+//! каждой загрузке страницы. Фронт держит кеш по публикации в `[cache] dir`
+//! с потолком на публикацию и вытеснением давно не спрошенного, а правила
+//! берёт у самого приложения: `Cache-Control`, `Expires`, `ETag` и
+//! `Last-Modified`, как общий кеш по RFC 9111.
+//!
+//! Свежий ответ отдаётся с диска без маршрута и без туннеля. Протухший с
+//! валидатором переспрашивается у агента условным запросом, и `304` стоит
+//! аплинку агента пару сотен байт вместо тела. Гейт сессии стоит раньше кеша:
+//! с диска получает ответ только тот, кого пустила бы и сама публикация.
+//!
+//! Что видно не всем, в кеш не ложится: `private`, `no-store`, `Set-Cookie`,
+//! а у запроса с сессией фронта, своим `Authorization` или cookie приложения
+//! ещё и всё, что приложение не объявило `public` явно. Персональная страница
+//! владельца иначе досталась бы гостю с той же публикации.
+//!
+//! Сжатие ([`compress`]) отдельно от кеша: несжатый текстовый ответ уходит
+//! браузеру gzip-ом, если тот его принимает. В кеше лежит ответ как его отдал
+//! агент, жмётся он на выходе.
+
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::response::Response;
+use hyper::body::{Body as HttpBody, Bytes, Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::CacheConfig;
+
+/// Меньше этого жать нет смысла: заголовок gzip и словарь съедят выигрыш.
+const MIN_COMPRESS_BYTES: u64 = 1024;
+
+/// Заголовки ответа, которые `304` обновляет у лежащего в кеше.
+const REFRESHED_BY_304: [HeaderName; 5] = [
+    header::CACHE_CONTROL,
+    header::EXPIRES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+    header::DATE,
+];
+
+/// Кеш ответов: по подкаталогу на публикацию, в нём пара файлов на ответ,
+/// `<ключ>.json` с заголовками и сроком и `<ключ>.body` с телом.
+pub struct ResponseCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_object: u64,
+    shelves: Mutex<HashMap<String, Shelf>>,
+    hits: AtomicU64,
+    revalidated: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Shelf {
+    entries: HashMap<String, Meta>,
+    bytes: u64,
+}
+
+/// То, что лежит в `<ключ>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Meta {
+    /// Путь с query: для глаз того, кто откроет каталог кеша.
+    pub uri: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Значения заголовков запроса, названных в `Vary` ответа.
+    #[serde(default)]
+    pub vary: Vec<(String, String)>,
+    pub stored_at: u64,
+    pub fresh_until: u64,
+    pub size: u64,
+    #[serde(default)]
+    pub last_used: u64,
+}
+
+impl Meta {
+    fn header(&self, name: &HeaderName) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name.as_str()))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn has_validator(&self) -> bool {
+        self.header(&header::ETAG).is_some() || self.header(&header::LAST_MODIFIED).is_some()
+    }
+}
+
+/// Найденный в кеше ответ.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    publication: String,
+    key: String,
+    pub meta: Meta,
+}
+
+impl Hit {
+    /// Можно ли отдать с диска, не спрашивая агента.
+    pub fn fresh(&self, req: &HeaderMap, now: u64) -> bool {
+        now < self.meta.fresh_until && !wants_revalidation(req)
+    }
+
+    /// Протухший ответ без валидатора переспросить нечем: его заменит
+    /// обычный ответ агента.
+    pub fn revalidatable(&self) -> bool {
+        self.meta.has_validator()
+    }
+}
+
+impl ResponseCache {
+    /// Открыть кеш и поднять индекс с диска: рестарт фронта не обнуляет его.
+    /// Пары без тела или с телом не того размера выбрасываются.
+    pub fn open(cfg: &CacheConfig) -> anyhow::Result<Arc<Self>> {
+        std::fs::create_dir_all(&cfg.dir)
+            .map_err(|e| anyhow::anyhow!("каталог кеша {}: {e}", cfg.dir.display()))?;
+        let cache = Self {
+            dir: cfg.dir.clone(),
+            max_bytes: cfg.max_mb_per_publication.saturating_mul(1 << 20),
+            max_object: cfg.max_object_mb.saturating_mul(1 << 20),
+            shelves: Mutex::default(),
+            hits: AtomicU64::new(0),
+            revalidated: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+        let mut shelves = HashMap::new();
+        for dir in std::fs::read_dir(&cfg.dir)?.flatten() {
+            let Some(publication) = dir.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !xr_proto::share::valid_publication_name(&publication) {
+                continue;
+            }
+            let shelf: &mut Shelf = shelves.entry(publication).or_default();
+            for file in std::fs::read_dir(dir.path())?.flatten() {
+                let path = file.path();
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some("json") => {}
+                    // Недописанный файл от упавшего процесса.
+                    Some("tmp") => {
+                        let _ = std::fs::remove_file(&path);
+                        continue;
+                    }
+                    _ => continue,
+                }
+                let Some(key) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else {
+                    continue;
+                };
+                let meta = std::fs::read(&path)
+                    .ok()
+                    .and_then(|raw| serde_json::from_slice::<Meta>(&raw).ok());
+                let body_len = std::fs::metadata(path.with_extension("body")).map(|m| m.len()).ok();
+                match meta {
+                    Some(meta) if body_len == Some(meta.size) => {
+                        shelf.bytes += meta.size;
+                        shelf.entries.insert(key, meta);
+                    }
+                    _ => {
+                        let _ = std::fs::remove_file(&path);
+                        let _ = std::fs::remove_file(path.with_extension("body"));
+                    }
+                }
+            }
+        }
+        for (publication, shelf) in shelves.iter_mut() {
+            remove_all(cache.evict(publication, shelf));
+        }
+        *cache.shelves.lock().expect("cache lock") = shelves;
+        Ok(Arc::new(cache))
+    }
+
+    fn shelf_dir(&self, publication: &str) -> PathBuf {
+        self.dir.join(publication)
+    }
+
+    /// Ответ под этот запрос, если он есть и `Vary` совпал.
+    pub fn lookup(&self, publication: &str, uri: &str, req: &HeaderMap, now: u64) -> Option<Hit> {
+        let key = key_of(uri);
+        let mut shelves = self.shelves.lock().expect("cache lock");
+        let meta = shelves.get_mut(publication)?.entries.get_mut(&key)?;
+        let vary_matches = meta
+            .vary
+            .iter()
+            .all(|(name, value)| header_joined(req, name) == *value);
+        if !vary_matches {
+            return None;
+        }
+        meta.last_used = now;
+        Some(Hit {
+            publication: publication.to_string(),
+            key,
+            meta: meta.clone(),
+        })
+    }
+
+    /// Отдать найденное с диска. Условный запрос браузера, который совпал с
+    /// валидатором, получает `304` без тела.
+    pub async fn respond(&self, hit: &Hit, req: &HeaderMap, now: u64) -> Option<Response> {
+        if not_modified(req, &hit.meta) {
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = StatusCode::NOT_MODIFIED;
+            for name in REFRESHED_BY_304.iter().chain([&header::VARY]) {
+                if let Some(value) = hit.meta.header(name).and_then(|v| HeaderValue::from_str(v).ok()) {
+                    resp.headers_mut().insert(name.clone(), value);
+                }
+            }
+            return Some(resp);
+        }
+        let path = self.shelf_dir(&hit.publication).join(format!("{}.body", hit.key));
+        let body = match tokio::fs::read(&path).await {
+            Ok(body) if body.len() as u64 == hit.meta.size => body,
+            // Файл пропал или обрезан: запись забывается, запрос уйдёт агенту.
+            _ => {
+                self.forget(&hit.publication, &hit.key);
+                return None;
+            }
+        };
+        let mut resp = Response::new(Body::from(body));
+        *resp.status_mut() = StatusCode::from_u16(hit.meta.status).unwrap_or(StatusCode::OK);
+        for (name, value) in &hit.meta.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::from_str(value)) {
+                resp.headers_mut().append(name, value);
+            }
+        }
+        resp.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(hit.meta.size));
+        resp.headers_mut()
+            .insert(header::AGE, HeaderValue::from(now.saturating_sub(hit.meta.stored_at)));
+        Some(resp)
+    }
+
+    /// Агент ответил `304` на условный запрос: у лежащего ответа обновляются
+    /// заголовки и срок, тело остаётся прежним.
+    pub fn refresh(&self, hit: &Hit, fresh: &HeaderMap, now: u64) -> Hit {
+        self.revalidated.fetch_add(1, Ordering::Relaxed);
+        let mut meta = hit.meta.clone();
+        for name in REFRESHED_BY_304 {
+            if let Some(value) = fresh.get(&name).and_then(|v| v.to_str().ok()) {
+                meta.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name.as_str()));
+                meta.headers.push((name.as_str().to_string(), value.to_string()));
+            }
+        }
+        let merged = headers_of(&meta);
+        meta.fresh_until = now + freshness(&merged, now);
+        meta.stored_at = now;
+        meta.last_used = now;
+        let refreshed = Hit { meta, ..hit.clone() };
+        {
+            let mut shelves = self.shelves.lock().expect("cache lock");
+            if let Some(slot) = shelves
+                .get_mut(&hit.publication)
+                .and_then(|s| s.entries.get_mut(&hit.key))
+            {
+                *slot = refreshed.meta.clone();
+            }
+        }
+        let path = self.shelf_dir(&hit.publication).join(format!("{}.json", hit.key));
+        if let Ok(raw) = serde_json::to_vec(&refreshed.meta) {
+            if let Err(e) = write_atomic(&path, &raw) {
+                tracing::warn!("кеш {}: срок не записался ({e})", hit.publication);
+            }
+        }
+        refreshed
+    }
+
+    /// Обернуть тело ответа агента, если ответ можно положить в кеш: тело
+    /// едет браузеру потоком, а копия ложится на диск, когда оно кончилось
+    /// целиком и не вышло за потолок ответа.
+    #[allow(clippy::too_many_arguments)]
+    pub fn tee<B>(
+        self: &Arc<Self>,
+        publication: &str,
+        uri: &str,
+        req: &HeaderMap,
+        status: StatusCode,
+        headers: &HeaderMap,
+        private: bool,
+        body: B,
+        now: u64,
+    ) -> Body
+    where
+        B: HttpBody<Data = Bytes> + Send + Unpin + 'static,
+        B::Error: Into<axum::BoxError>,
+    {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let Some(fresh_for) = storable(status, req, headers, private, now) else {
+            return Body::new(body);
+        };
+        let too_big = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .is_some_and(|len| len > self.max_object);
+        if too_big {
+            return Body::new(body);
+        }
+        let vary = headers
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|n| n.trim().to_ascii_lowercase())
+            .filter(|n| !n.is_empty())
+            .map(|n| {
+                let value = header_joined(req, &n);
+                (n, value)
+            })
+            .collect();
+        let meta = Meta {
+            uri: uri.to_string(),
+            status: status.as_u16(),
+            headers: headers
+                .iter()
+                .filter(|(k, _)| *k != header::CONTENT_LENGTH && *k != header::AGE)
+                .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+                .collect(),
+            vary,
+            stored_at: now,
+            fresh_until: now + fresh_for,
+            size: 0,
+            last_used: now,
+        };
+        Body::new(Tee {
+            inner: body,
+            buf: Some(Vec::new()),
+            limit: self.max_object,
+            done: Some(Pending {
+                cache: self.clone(),
+                publication: publication.to_string(),
+                meta,
+            }),
+        })
+    }
+
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Положить ответ на диск: тело, потом заголовки. Индекс узнаёт о нём
+    /// только после записи обоих файлов, а сверх потолка уходят самые давно
+    /// не спрошенные ответы этой публикации.
+    async fn store(self: Arc<Self>, publication: String, mut meta: Meta, body: Bytes) {
+        meta.size = body.len() as u64;
+        let key = key_of(&meta.uri);
+        let dir = self.shelf_dir(&publication);
+        let raw = match serde_json::to_vec(&meta) {
+            Ok(raw) => raw,
+            Err(_) => return,
+        };
+        let (json, data) = (dir.join(format!("{key}.json")), dir.join(format!("{key}.body")));
+        let written = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            std::fs::create_dir_all(&dir)?;
+            write_atomic(&data, &body)?;
+            write_atomic(&json, &raw)
+        })
+        .await;
+        match written {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                tracing::warn!("кеш {publication}: ответ не записался ({e})");
+                return;
+            }
+            Err(_) => return,
+        }
+        let evicted = {
+            let mut shelves = self.shelves.lock().expect("cache lock");
+            let shelf = shelves.entry(publication.clone()).or_default();
+            shelf.bytes += meta.size;
+            if let Some(old) = shelf.entries.insert(key, meta) {
+                shelf.bytes -= old.size;
+            }
+            self.evict(&publication, shelf)
+        };
+        // Файлы вытесненных удаляются уже без замка и вне рантайма.
+        if !evicted.is_empty() {
+            let _ = tokio::task::spawn_blocking(move || remove_all(evicted)).await;
+        }
+    }
+
+    /// Вынуть из индекса самые давно не спрошенные ответы сверх потолка.
+    /// Файлы не трогает: их пути возвращаются, чтобы удалить их без замка.
+    fn evict(&self, publication: &str, shelf: &mut Shelf) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        while shelf.bytes > self.max_bytes {
+            let Some(oldest) = shelf
+                .entries
+                .iter()
+                .min_by_key(|(_, m)| m.last_used)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            if let Some(meta) = shelf.entries.remove(&oldest) {
+                shelf.bytes -= meta.size;
+            }
+            let dir = self.shelf_dir(publication);
+            files.push(dir.join(format!("{oldest}.json")));
+            files.push(dir.join(format!("{oldest}.body")));
+        }
+        files
+    }
+
+    fn forget(&self, publication: &str, key: &str) {
+        let mut shelves = self.shelves.lock().expect("cache lock");
+        if let Some(shelf) = shelves.get_mut(publication) {
+            if let Some(meta) = shelf.entries.remove(key) {
+                shelf.bytes -= meta.size;
+            }
+        }
+    }
+
+    /// Сколько байт кеша занимает публикация.
+    pub fn bytes(&self, publication: &str) -> u64 {
+        self.shelves
+            .lock()
+            .expect("cache lock")
+            .get(publication)
+            .map(|s| s.bytes)
+            .unwrap_or(0)
+    }
+
+    /// Счётчики кеша текстом Prometheus, рядом со сроком сертификата.
+    pub fn render(&self) -> String {
+        let bytes: u64 = self
+            .shelves
+            .lock()
+            .expect("cache lock")
+            .values()
+            .map(|s| s.bytes)
+            .sum();
+        format!(
+            "# TYPE xr_web_cache_hits_total counter\n\
+             xr_web_cache_hits_total {}\n\
+             # TYPE xr_web_cache_revalidated_total counter\n\
+             xr_web_cache_revalidated_total {}\n\
+             # TYPE xr_web_cache_misses_total counter\n\
+             xr_web_cache_misses_total {}\n\
+             # TYPE xr_web_cache_bytes gauge\n\
+             xr_web_cache_bytes {bytes}\n",
+            self.hits.load(Ordering::Relaxed),
+            self.revalidated.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Отдать найденное с диска и засчитать попадание.
+pub async fn serve_hit(cache: &ResponseCache, hit: &Hit, req: &HeaderMap, now: u64) -> Option<Response> {
+    let resp = cache.respond(hit, req, now).await?;
+    cache.hit();
+    Some(resp)
+}
+
+/// Запрос, ответ на который вообще ищется в кеше: простой `GET` без
+/// диапазона, апгрейда и `no-store`.
+pub fn cacheable_request(method: &Method, req: &HeaderMap) -> bool {
+    method == Method::GET
+        && !req.contains_key(header::RANGE)
+        && !req.contains_key(header::UPGRADE)
+        && !directives(req).iter().any(|d| d == "no-store")
+}
+
+/// Запрос видит то, что видно не всем: за гейтом сессии фронта, со своим
+/// `Authorization` или с cookie приложения.
+pub fn private_request(req: &HeaderMap, has_session: bool) -> bool {
+    has_session
+        || req.contains_key(header::AUTHORIZATION)
+        || req
+            .get(header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(crate::session::cookie_header_without_session)
+            .is_some()
+}
+
+/// Поставить свои валидаторы на запрос к агенту. Условные заголовки браузера
+/// снимаются: ответ ему фронт сверит с кешем сам.
+pub fn add_validators(req: &mut HeaderMap, hit: &Hit) {
+    req.remove(header::IF_NONE_MATCH);
+    req.remove(header::IF_MODIFIED_SINCE);
+    if let Some(etag) = hit.meta.header(&header::ETAG).and_then(|v| HeaderValue::from_str(v).ok()) {
+        req.insert(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(lm) = hit
+        .meta
+        .header(&header::LAST_MODIFIED)
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        req.insert(header::IF_MODIFIED_SINCE, lm);
+    }
+}
+
+/// Браузер просит не верить кешу: `no-cache`, `max-age=0` (так шлёт
+/// принудительное обновление страницы) или старый `Pragma`.
+fn wants_revalidation(req: &HeaderMap) -> bool {
+    directives(req).iter().any(|d| d == "no-cache" || d == "max-age=0")
+        || req
+            .get(header::PRAGMA)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.to_ascii_lowercase().contains("no-cache"))
+}
+
+/// Сколько секунд ответ свеж, если его можно класть в кеш. `None` значит
+/// класть нельзя.
+pub fn storable(status: StatusCode, req: &HeaderMap, resp: &HeaderMap, private: bool, now: u64) -> Option<u64> {
+    if status != StatusCode::OK {
+        return None;
+    }
+    let cc = directives(resp);
+    let has = |name: &str| cc.iter().any(|d| d == name || d.starts_with(&format!("{name}=")));
+    if has("no-store") || has("private") || directives(req).iter().any(|d| d == "no-store") {
+        return None;
+    }
+    if resp.contains_key(header::SET_COOKIE) || resp.contains_key(header::CONTENT_RANGE) {
+        return None;
+    }
+    let vary_all = resp
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(',').any(|n| n.trim() == "*"));
+    if vary_all {
+        return None;
+    }
+    if private && !has("public") && !has("s-maxage") {
+        return None;
+    }
+    let fresh = freshness(resp, now);
+    let validator = resp.contains_key(header::ETAG) || resp.contains_key(header::LAST_MODIFIED);
+    (fresh > 0 || validator).then_some(fresh)
+}
+
+/// Срок свежести из заголовков ответа: `s-maxage`, `max-age`, потом
+/// `Expires` против `Date`. Эвристик нет: без явного срока ответ каждый раз
+/// переспрашивается по валидатору.
+fn freshness(resp: &HeaderMap, now: u64) -> u64 {
+    let cc = directives(resp);
+    if cc.iter().any(|d| d == "no-cache" || d == "must-understand") {
+        return 0;
+    }
+    let age = resp
+        .get(header::AGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    let seconds = |name: &str| {
+        cc.iter()
+            .find_map(|d| d.strip_prefix(&format!("{name}=")))
+            .and_then(|v| v.trim_matches('"').parse::<u64>().ok())
+    };
+    let lifetime = seconds("s-maxage").or_else(|| seconds("max-age")).or_else(|| {
+        let date = |name| {
+            resp.get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .and_then(|v| httpdate::parse_http_date(v).ok())
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+        };
+        let expires = date(header::EXPIRES)?;
+        Some(expires.saturating_sub(date(header::DATE).unwrap_or(now)))
+    });
+    lifetime.unwrap_or(0).saturating_sub(age)
+}
+
+/// Совпал ли условный запрос браузера с тем, что лежит в кеше. Сравнение
+/// слабое: сжатый ответ уходит браузеру со слабым `ETag`.
+fn not_modified(req: &HeaderMap, meta: &Meta) -> bool {
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if let Some(inm) = req.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        let Some(etag) = meta.header(&header::ETAG) else {
+            return false;
+        };
+        return inm.split(',').any(|t| t.trim() == "*" || weak(t) == weak(etag));
+    }
+    let parse = |v: &str| httpdate::parse_http_date(v).ok();
+    match (
+        req.get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse),
+        meta.header(&header::LAST_MODIFIED).and_then(parse),
+    ) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+fn directives(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|d| d.trim().to_ascii_lowercase().replace(' ', ""))
+        .filter(|d| !d.is_empty())
+        .collect()
+}
+
+fn header_joined(req: &HeaderMap, name: &str) -> String {
+    req.get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn headers_of(meta: &Meta) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in &meta.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::from_str(value)) {
+            map.append(name, value);
+        }
+    }
+    map
+}
+
+/// Имя файла ответа: SHA-256 пути с query. Сам путь в имени файла не
+/// нужен и бывает длиннее, чем позволяет файловая система.
+fn key_of(uri: &str) -> String {
+    Sha256::digest(uri.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Записать через временный файл и переименовать. Имя временного файла
+/// случайное: два ответа на один адрес пишутся параллельно и не портят друг
+/// другу недописанное.
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let tmp = path.with_extension(format!("{ext}.{:016x}.tmp", rand::random::<u64>()));
+    if let Err(e) = std::fs::write(&tmp, data).and_then(|()| std::fs::rename(&tmp, path)) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn remove_all(files: Vec<PathBuf>) {
+    for file in files {
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+struct Pending {
+    cache: Arc<ResponseCache>,
+    publication: String,
+    meta: Meta,
+}
+
+/// Тело, которое копит копию для кеша, пока едет браузеру. Обрыв или выход
+/// за потолок ответа копию выбрасывают, а браузер этого не замечает.
+struct Tee<B> {
+    inner: B,
+    buf: Option<Vec<u8>>,
+    limit: u64,
+    done: Option<Pending>,
+}
+
+impl<B> HttpBody for Tee<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        let polled = Pin::new(&mut this.inner).poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(data), Some(buf)) = (frame.data_ref(), this.buf.as_mut()) {
+                    if (buf.len() + data.len()) as u64 > this.limit {
+                        this.buf = None;
+                    } else {
+                        buf.extend_from_slice(data);
+                    }
+                }
+            }
+            Poll::Ready(Some(Err(_))) => this.buf = None,
+            Poll::Ready(None) => {
+                if let (Some(buf), Some(p)) = (this.buf.take(), this.done.take()) {
+                    tokio::spawn(p.cache.store(p.publication, p.meta, Bytes::from(buf)));
+                }
+            }
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // Конец тела обязан пройти через `poll_frame`, иначе копия не ляжет.
+        self.done.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Жать ли этот тип содержимого: текст, JSON, JS, XML, SVG и wasm. Поток
+/// событий не жмётся: буфер компрессора держал бы события у себя.
+fn compressible(content_type: &str) -> bool {
+    let ct = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if ct == "text/event-stream" {
+        return false;
+    }
+    ct.starts_with("text/")
+        || ct.ends_with("+json")
+        || ct.ends_with("+xml")
+        || matches!(
+            ct.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Браузер принимает gzip: есть в `Accept-Encoding` и не с `q=0`.
+fn accepts_gzip(accept: &str) -> bool {
+    accept.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let refused = parts.any(|p| p.replace(' ', "") == "q=0" || p.replace(' ', "") == "q=0.0");
+        (coding == "gzip" || coding == "*") && !refused
+    })
+}
+
+/// Сжать ответ gzip-ом на выходе, если он несжатый текст, браузер gzip
+/// принимает, а приложение не запретило трогать тело (`no-transform`).
+/// Сильный `ETag` становится слабым: байты тела уже не те, что у агента.
+pub fn compress(resp: Response, method: &Method, accept_encoding: Option<&str>) -> Response {
+    let headers = resp.headers();
+    let eligible = *method != Method::HEAD
+        && resp.status().is_success()
+        && resp.status() != StatusCode::NO_CONTENT
+        && resp.status() != StatusCode::PARTIAL_CONTENT
+        && !headers.contains_key(header::CONTENT_ENCODING)
+        && !headers.contains_key(header::CONTENT_RANGE)
+        && !directives(headers).iter().any(|d| d == "no-transform")
+        && headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(compressible)
+        && accept_encoding.is_some_and(accepts_gzip)
+        && headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .is_none_or(|len| len >= MIN_COMPRESS_BYTES);
+    if !eligible {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if let Some(etag) = parts.headers.get(header::ETAG).and_then(|v| v.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            if let Ok(weak) = HeaderValue::from_str(&format!("W/{etag}")) {
+                parts.headers.insert(header::ETAG, weak);
+            }
+        }
+    }
+    let gzip = Gzip {
+        inner: body,
+        encoder: Some(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())),
+    };
+    Response::from_parts(parts, Body::new(gzip))
+}
+
+/// Тело, сжимаемое gzip-ом по кадрам. Каждый кадр сбрасывается из
+/// компрессора сразу (sync flush): потоковый ответ не копится на VPS.
+struct Gzip {
+    inner: Body,
+    encoder: Option<flate2::write::GzEncoder<Vec<u8>>>,
+}
+
+impl HttpBody for Gzip {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        loop {
+            let this = &mut *self;
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+            match std::task::ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => {
+                    // Трейлеры после сжатого тела браузеру не нужны.
+                    let Ok(data) = frame.into_data() else { continue };
+                    if let Err(e) = encoder.write_all(&data).and_then(|()| encoder.flush()) {
+                        return Poll::Ready(Some(Err(axum::Error::new(e))));
+                    }
+                    let out = std::mem::take(encoder.get_mut());
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::data(Bytes::from(out)))));
+                    }
+                }
+                Some(Err(e)) => {
+                    this.encoder = None;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    let encoder = this.encoder.take().expect("проверено выше");
+                    return match encoder.finish() {
+                        Ok(tail) => Poll::Ready(Some(Ok(Frame::data(Bytes::from(tail))))),
+                        Err(e) => Poll::Ready(Some(Err(axum::Error::new(e)))),
+                    };
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (k, v) in pairs {
+            map.append(HeaderName::try_from(*k).unwrap(), HeaderValue::from_str(v).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn storable_follows_cache_control() {
+        let now = 1_700_000_000;
+        let req = HeaderMap::new();
+        let ok = StatusCode::OK;
+        assert_eq!(storable(ok, &req, &headers(&[("cache-control", "max-age=60")]), false, now), Some(60));
+        assert_eq!(
+            storable(ok, &req, &headers(&[("cache-control", "max-age=60, s-maxage=600")]), false, now),
+            Some(600)
+        );
+        // Без срока, но с валидатором: кладётся, каждый раз переспрашивается.
+        assert_eq!(storable(ok, &req, &headers(&[("etag", "\"v1\"")]), false, now), Some(0));
+        assert_eq!(storable(ok, &req, &HeaderMap::new(), false, now), None, "ни срока, ни валидатора");
+        for bad in [
+            headers(&[("cache-control", "private, max-age=60")]),
+            headers(&[("cache-control", "no-store")]),
+            headers(&[("cache-control", "max-age=60"), ("set-cookie", "a=b")]),
+            headers(&[("cache-control", "max-age=60"), ("vary", "*")]),
+        ] {
+            assert_eq!(storable(ok, &req, &bad, false, now), None, "{bad:?}");
+        }
+        assert_eq!(
+            storable(StatusCode::NOT_FOUND, &req, &headers(&[("cache-control", "max-age=60")]), false, now),
+            None
+        );
+        let expires = headers(&[
+            ("date", "Tue, 14 Nov 2023 22:13:20 GMT"),
+            ("expires", "Tue, 14 Nov 2023 22:15:20 GMT"),
+        ]);
+        assert_eq!(storable(ok, &req, &expires, false, now), Some(120));
+    }
+
+    #[test]
+    fn private_request_needs_explicit_public() {
+        let now = 1_700_000_000;
+        let req = HeaderMap::new();
+        let plain = headers(&[("cache-control", "max-age=60"), ("etag", "\"v1\"")]);
+        assert_eq!(storable(StatusCode::OK, &req, &plain, true, now), None);
+        let public = headers(&[("cache-control", "public, max-age=60")]);
+        assert_eq!(storable(StatusCode::OK, &req, &public, true, now), Some(60));
+
+        assert!(private_request(&HeaderMap::new(), true), "сессия фронта");
+        assert!(private_request(&headers(&[("authorization", "Basic eA==")]), false));
+        assert!(private_request(&headers(&[("cookie", "app=1")]), false));
+        assert!(!private_request(&HeaderMap::new(), false));
+    }
+
+    fn cache_in(dir: &Path, max_mb: u64) -> Arc<ResponseCache> {
+        ResponseCache::open(&CacheConfig {
+            dir: dir.to_path_buf(),
+            max_mb_per_publication: max_mb,
+            max_object_mb: 1,
+        })
+        .unwrap()
+    }
+
+    async fn put(cache: &Arc<ResponseCache>, uri: &str, resp: &HeaderMap, req: &HeaderMap, body: &[u8], now: u64) {
+        use http_body_util::BodyExt;
+        let body = http_body_util::Full::new(Bytes::copy_from_slice(body));
+        let teed = cache.tee("dash", uri, req, StatusCode::OK, resp, false, body, now);
+        teed.collect().await.unwrap();
+        // Запись идёт отдельной задачей: ждём, пока она появится в индексе.
+        for _ in 0..100 {
+            if cache.lookup("dash", uri, req, now).is_some() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("{uri} не лёг в кеш");
+    }
+
+    #[tokio::test]
+    async fn stored_response_survives_restart_and_honours_vary() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = 1_700_000_000;
+        let cache = cache_in(dir.path(), 1);
+        let resp = headers(&[
+            ("cache-control", "max-age=60"),
+            ("etag", "\"v1\""),
+            ("vary", "Accept-Language"),
+            ("content-type", "text/css"),
+        ]);
+        let ru = headers(&[("accept-language", "ru")]);
+        put(&cache, "/app.css", &resp, &ru, b"body{}", now).await;
+
+        let cache = cache_in(dir.path(), 1);
+        let hit = cache.lookup("dash", "/app.css", &ru, now).expect("индекс поднят с диска");
+        assert!(hit.fresh(&HeaderMap::new(), now + 59));
+        assert!(!hit.fresh(&HeaderMap::new(), now + 60));
+        assert!(!hit.fresh(&headers(&[("cache-control", "max-age=0")]), now), "обновление страницы");
+        assert!(cache.lookup("dash", "/app.css", &headers(&[("accept-language", "en")]), now).is_none());
+
+        let full = cache.respond(&hit, &HeaderMap::new(), now + 5).await.unwrap();
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(full.headers()[header::AGE], "5");
+        let cond = cache
+            .respond(&hit, &headers(&[("if-none-match", "W/\"v1\"")]), now)
+            .await
+            .unwrap();
+        assert_eq!(cond.status(), StatusCode::NOT_MODIFIED);
+
+        // 304 агента продлевает срок, тело остаётся.
+        let refreshed = cache.refresh(&hit, &headers(&[("cache-control", "max-age=600")]), now + 100);
+        assert!(refreshed.fresh(&HeaderMap::new(), now + 650));
+        let again = cache.lookup("dash", "/app.css", &ru, now + 100).unwrap();
+        assert_eq!(again.meta.fresh_until, now + 700);
+    }
+
+    #[tokio::test]
+    async fn publication_shelf_stays_under_its_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = 1_700_000_000;
+        let cache = cache_in(dir.path(), 1);
+        let resp = headers(&[("cache-control", "max-age=60")]);
+        let req = HeaderMap::new();
+        let chunk = vec![b'x'; 400 * 1024];
+        put(&cache, "/a", &resp, &req, &chunk, now).await;
+        put(&cache, "/b", &resp, &req, &chunk, now + 1).await;
+        // `/a` спросили последним: вытеснится `/b`.
+        cache.lookup("dash", "/a", &req, now + 2).unwrap();
+        put(&cache, "/c", &resp, &req, &chunk, now + 3).await;
+        assert!(cache.bytes("dash") <= 1 << 20);
+        assert!(cache.lookup("dash", "/a", &req, now + 4).is_some());
+        assert!(cache.lookup("dash", "/b", &req, now + 4).is_none());
+        // Файлы вытесненного удаляются следом за индексом, временных не остаётся.
+        let (a, c) = (key_of("/a"), key_of("/c"));
+        let mut want = vec![format!("{a}.body"), format!("{a}.json"), format!("{c}.body"), format!("{c}.json")];
+        want.sort();
+        let mut files = Vec::new();
+        for _ in 0..100 {
+            files = std::fs::read_dir(dir.path().join("dash"))
+                .unwrap()
+                .map(|f| f.unwrap().file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            files.sort();
+            if files == want {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(files, want);
+
+        // Тело больше потолка ответа едет браузеру целиком, но в кеш не ложится.
+        use http_body_util::BodyExt;
+        let big = http_body_util::Full::new(Bytes::from(vec![b'y'; 2 << 20]));
+        let teed = cache.tee("dash", "/big", &req, StatusCode::OK, &resp, false, big, now);
+        assert_eq!(teed.collect().await.unwrap().to_bytes().len(), 2 << 20);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(cache.lookup("dash", "/big", &req, now).is_none());
+    }
+
+    #[tokio::test]
+    async fn text_is_gzipped_only_when_accepted() {
+        use http_body_util::BodyExt;
+        use std::io::Read as _;
+        let text = "let x = 1;\n".repeat(500);
+        let make = |ct: &str| {
+            let mut resp = Response::new(Body::from(text.clone()));
+            resp.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_str(ct).unwrap());
+            resp.headers_mut().insert(header::ETAG, HeaderValue::from_static("\"v1\""));
+            resp
+        };
+
+        let resp = compress(make("application/javascript"), &Method::GET, Some("gzip, deflate, br"));
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[header::ETAG], "W/\"v1\"");
+        assert!(resp.headers().get(header::CONTENT_LENGTH).is_none());
+        let packed = resp.into_body().collect().await.unwrap().to_bytes();
+        assert!(packed.len() < text.len() / 10, "{} байт", packed.len());
+        let mut unpacked = String::new();
+        flate2::read::GzDecoder::new(&packed[..]).read_to_string(&mut unpacked).unwrap();
+        assert_eq!(unpacked, text);
+
+        for (ct, accept) in [
+            ("application/javascript", None),
+            ("application/javascript", Some("gzip;q=0, br")),
+            ("image/png", Some("gzip")),
+            ("text/event-stream", Some("gzip")),
+        ] {
+            let resp = compress(make(ct), &Method::GET, accept);
+            assert!(resp.headers().get(header::CONTENT_ENCODING).is_none(), "{ct} {accept:?}");
+        }
+    }
+}
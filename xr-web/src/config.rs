@@ -15,9 +15,21 @@ fn default_bind() -> String {
 fn default_session_ttl() -> u64 {
     7 * 24 * 3600
 }
+fn default_session_file() -> PathBuf {
+    PathBuf::from("/var/lib/xr-web/sessions.json")
+}
 fn default_log_level() -> String {
     "info".to_string()
 }
+fn default_compress() -> bool {
+    true
+}
+fn default_cache_max_mb() -> u64 {
+    256
+}
+fn default_cache_max_object_mb() -> u64 {
+    8
+}
 
 #[derive(Debug, Deserialize)]
 pub struct WebConfig {
@@ -35,16 +47,49 @@ pub struct WebConfig {
     /// Сколько живёт сессия браузера без активности, секунды.
     #[serde(default = "default_session_ttl")]
     pub session_ttl_secs: u64,
+    /// Файл сессий браузера: с ним деплой никого не разлогинивает. Токенов в
+    /// нём нет, только их отпечатки.
+    #[serde(default = "default_session_file")]
+    pub session_file: PathBuf,
+    /// Base64 публичного ключа хаба: им страница шары (`s.<domain>`)
+    /// проверяет токен ссылки офлайн, до похода в хаб. Пусто значит страниц
+    /// шар у этого фронта нет.
+    #[serde(default)]
+    pub hub_pubkey: String,
+    /// Сжимать на лету несжатые текстовые ответы агента (gzip), если браузер
+    /// их принимает. Аплинк агента это часто домашний ADSL, и JS дашборда
+    /// без сжатия стоит секунды.
+    #[serde(default = "default_compress")]
+    pub compress: bool,
     #[serde(default = "default_log_level")]
     pub log_level: String,
 }
 
+/// Кеш ответов публикаций на диске (`[cache]`). Блока нет значит кеша нет:
+/// каждый запрос едет до агента, как раньше.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CacheConfig {
+    /// Каталог кеша: по подкаталогу на публикацию.
+    pub dir: PathBuf,
+    /// Потолок кеша одной публикации, мегабайты. Сверх него уходят давно не
+    /// спрошенные ответы.
+    #[serde(default = "default_cache_max_mb")]
+    pub max_mb_per_publication: u64,
+    /// Ответ крупнее этого в кеш не кладётся и едет потоком как обычно.
+    #[serde(default = "default_cache_max_object_mb")]
+    pub max_object_mb: u64,
+}
+
 /// Собственная терминация TLS: вариант установки без фронта. С фронтом блока
 /// нет, и сертификатами `xr-web` не занимается вовсе.
 #[derive(Debug, Clone, Deserialize)]
 pub struct TlsConfig {
     pub cert: PathBuf,
     pub key: PathBuf,
+    /// Встроенный ACME: wildcard публикаций (`*.<домен>`) через dns-01 и
+    /// хук провайдера DNS. Пара ложится в `cert`/`key` и подменяется на ходу.
+    #[serde(default)]
+    pub acme: Option<xr_proto::acme::AcmeConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,6 +97,8 @@ pub struct WebFile {
     pub web: WebConfig,
     #[serde(default)]
     pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
 }
 
 impl WebFile {
@@ -69,6 +116,18 @@ impl WebFile {
         if file.web.shared_secret.trim().is_empty() {
             anyhow::bail!("[web] shared_secret пуст: служебные ручки хаба закрыты секретом");
         }
+        if let Some(acme) = file.tls.as_ref().and_then(|t| t.acme.as_ref()) {
+            acme.validate().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(cache) = &file.cache {
+            if cache.max_object_mb == 0 || cache.max_object_mb > cache.max_mb_per_publication {
+                anyhow::bail!(
+                    "[cache] max_object_mb = {} при потолке публикации {} МБ: в кеш не ляжет ни один ответ",
+                    cache.max_object_mb,
+                    cache.max_mb_per_publication
+                );
+            }
+        }
         Ok(file)
     }
 }
@@ -90,7 +149,30 @@ mod tests {
         .expect("минимального блока хватает");
         assert_eq!(cfg.web.bind, "127.0.0.1:8090");
         assert_eq!(cfg.web.session_ttl_secs, 7 * 24 * 3600);
+        assert_eq!(cfg.web.session_file, PathBuf::from("/var/lib/xr-web/sessions.json"));
         assert!(cfg.tls.is_none(), "без блока [tls] терминирует фронт");
+        assert!(cfg.web.hub_pubkey.is_empty(), "без ключа хаба страниц шар нет");
+        assert!(cfg.web.compress, "сжатие включено по умолчанию");
+        assert!(cfg.cache.is_none(), "без блока [cache] кеша нет");
+    }
+
+    #[test]
+    fn cache_block_defaults_and_bounds() {
+        let base = r#"
+            [web]
+            domain = "web.example.com"
+            hub_url = "https://xr-hub.example.com"
+            shared_secret = "s3cret"
+
+            [cache]
+            dir = "/var/lib/xr-web/cache"
+            "#;
+        let cache = WebFile::parse(base).unwrap().cache.expect("блок [cache] читается");
+        assert_eq!(cache.max_mb_per_publication, 256);
+        assert_eq!(cache.max_object_mb, 8);
+
+        let err = WebFile::parse(&format!("{base}max_object_mb = 512\n")).unwrap_err().to_string();
+        assert!(err.contains("max_object_mb"), "{err}");
     }
 
     #[test]
@@ -111,9 +193,38 @@ mod tests {
         .unwrap();
         let tls = cfg.tls.expect("блок [tls] обязан читаться");
         assert!(tls.cert.ends_with("fullchain.pem"));
+        assert!(tls.acme.is_none());
         assert_eq!(cfg.web.bind, "0.0.0.0:443");
     }
 
+    #[test]
+    fn acme_block_wants_dns01_for_the_wildcard() {
+        let base = r#"
+            [web]
+            domain = "web.example.com"
+            hub_url = "https://xr-hub.example.com"
+            shared_secret = "s3cret"
+
+            [tls]
+            cert = "/etc/xr-web/tls/fullchain.pem"
+            key = "/etc/xr-web/tls/privkey.pem"
+
+            [tls.acme]
+            domains = ["*.web.example.com", "web.example.com"]
+            state_dir = "/var/lib/xr-web/acme"
+            "#;
+        let cfg = WebFile::parse(&format!(
+            "{base}challenge = \"dns-01\"\ndns_hook = \"/etc/xr-web/dns-hook\"\n"
+        ))
+        .expect("wildcard через dns-01 с хуком годится");
+        let acme = cfg.tls.unwrap().acme.unwrap();
+        assert_eq!(acme.domains.len(), 2);
+
+        // http-01 wildcard не выпишет: это ловится на старте, а не отказом CA.
+        let err = WebFile::parse(base).unwrap_err().to_string();
+        assert!(err.contains("dns-01"), "{err}");
+    }
+
     #[test]
     fn empty_domain_or_secret_is_refused() {
         // Пустой домен превратил бы любой Host в публикацию, пустой секрет
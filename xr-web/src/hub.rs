@@ -1,9 +1,11 @@
 //! Дверь фронта в хаб и кеш маршрутов (LLD-38 п. 2.3, п. 3.5).
 //!
-//! Дверь узкая по замыслу: маршрут публикации и вердикт по паролю, оба под
-//! общим секретом. Прав админки у фронта нет, приватного ключа хаба он не
-//! видит, поэтому взломанный фронт не выпишет себе мандат на агента, которого
-//! хаб ему не отдавал.
+//! Дверь узкая по замыслу: маршруты публикаций и шар и вердикты по входу, все
+//! под общим секретом. Политику входа публикации проверяет хаб: фронт приносит
+//! то, что браузер ввёл на форме, и получает «да» с именем для сессии или
+//! «нет». Прав админки у фронта нет, приватного ключа хаба он не видит, поэтому
+//! взломанный фронт не выпишет себе мандат на агента, которого хаб ему не
+//! отдавал.
 //!
 //! Маршрут кешируется до `exp` минус запас: без кеша каждая картинка страницы
 //! стоила бы похода в хаб, а с ним хаб спрашивают не чаще раза в час на
@@ -15,7 +17,7 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use xr_proto::share::WebRoute;
+use xr_proto::share::{WebRoute, WebSessionsReport, WebSessionsVerdict, WebShareRoute};
 
 /// Запас, на который кеш маршрута короче его `exp`: маршрут не должен
 /// протухнуть на полпути запроса.
@@ -46,13 +48,53 @@ impl std::fmt::Display for HubError {
     }
 }
 
+/// Что браузер ввёл на форме входа. Какие поля нужны, решает политика
+/// публикации, а проверяет их хаб: фронт шлёт всё, что пришло.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    pub invite: String,
+}
+
+/// Вход, признанный хабом по форме публикации.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPass {
+    /// Под каким именем открыть сессию.
+    pub who: String,
+    /// Чем пометить сессию, чтобы хаб мог снять её отзывом (инвайт). Пусто
+    /// у входа по паролю.
+    pub grant: String,
+    /// Потолок жизни сессии, unix-секунды. `0` потолка нет.
+    pub until: u64,
+}
+
+/// Гостевая ссылка, признанная хабом.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestPass {
+    pub id: String,
+    pub label: String,
+    /// Потолок жизни гостевой сессии, unix-секунды.
+    pub expires: u64,
+}
+
 /// Служебные ручки хаба глазами фронта. Отдельным типажом, чтобы тесты гоняли
 /// вход и проксирование без единого сетевого вызова.
 pub trait HubApi: Send + Sync + 'static {
     fn route(&self, publication: String) -> Boxed<Result<WebRoute, HubError>>;
-    /// `Ok(true)` пароль подошёл, `Ok(false)` нет, ошибка это отказ самого
-    /// хаба (включая его собственный лимит попыток).
-    fn verify_password(&self, username: String, password: String) -> Boxed<Result<bool, HubError>>;
+    /// Маршрут шары для её страницы. Хаб получает и токен из ссылки: маршрут
+    /// выдаётся её держателю, а не всякому, кто знает общий секрет.
+    fn share_route(&self, share_id: String, token: String) -> Boxed<Result<WebShareRoute, HubError>>;
+    /// Вердикт по форме входа публикации. `Ok(Some(_))` пускать, `Ok(None)`
+    /// нет, ошибка это отказ самого хаба (включая его собственный лимит
+    /// попыток).
+    fn verify_access(&self, publication: String, creds: Credentials) -> Boxed<Result<Option<AccessPass>, HubError>>;
+    /// Вердикт по гостевой ссылке: `Ok(None)` ссылка чужая, отозвана или
+    /// истекла.
+    fn redeem_guest(&self, publication: String, token: String) -> Boxed<Result<Option<GuestPass>, HubError>>;
+    /// Доклад живых сессий браузера. В ответ хаб называет моменты «выйти
+    /// везде» по публикациям: сессии, открытые раньше, фронт снимает сам.
+    fn report_sessions(&self, report: WebSessionsReport) -> Boxed<Result<WebSessionsVerdict, HubError>>;
 }
 
 /// Живой хаб по HTTPS.
@@ -135,16 +177,48 @@ impl HubApi for HttpHub {
         })
     }
 
-    fn verify_password(&self, username: String, password: String) -> Boxed<Result<bool, HubError>> {
+    fn share_route(&self, share_id: String, token: String) -> Boxed<Result<WebShareRoute, HubError>> {
         let req = self
-            .post("/api/v1/web/verify-password")
-            .json(&serde_json::json!({ "username": username, "password": password }));
+            .post("/api/v1/web/share-route")
+            .json(&serde_json::json!({ "share_id": share_id, "token": token }));
         Box::pin(async move {
             let resp = req
                 .send()
                 .await
                 .map_err(|e| HubError::Unavailable(format!("хаб не ответил: {e}")))?;
             let status = resp.status();
+            if status == reqwest::StatusCode::NOT_FOUND {
+                return Err(HubError::NotFound);
+            }
+            if !status.is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                return Err(HubError::Unavailable(format!(
+                    "хаб ответил {status}: {}",
+                    body.trim()
+                )));
+            }
+            resp.json::<WebShareRoute>()
+                .await
+                .map_err(|e| HubError::Unavailable(format!("маршрут шары от хаба не разобрался: {e}")))
+        })
+    }
+
+    fn verify_access(&self, publication: String, creds: Credentials) -> Boxed<Result<Option<AccessPass>, HubError>> {
+        let req = self.post("/api/v1/web/verify-access").json(&serde_json::json!({
+            "publication": publication,
+            "username": creds.username,
+            "password": creds.password,
+            "invite": creds.invite,
+        }));
+        Box::pin(async move {
+            let resp = req
+                .send()
+                .await
+                .map_err(|e| HubError::Unavailable(format!("хаб не ответил: {e}")))?;
+            let status = resp.status();
+            if status == reqwest::StatusCode::NOT_FOUND {
+                return Err(HubError::NotFound);
+            }
             if !status.is_success() {
                 let body = resp.text().await.unwrap_or_default();
                 return Err(HubError::Unavailable(format!(
@@ -155,13 +229,79 @@ impl HubApi for HttpHub {
             #[derive(serde::Deserialize)]
             struct Verdict {
                 ok: bool,
+                #[serde(default)]
+                who: String,
+                #[serde(default)]
+                grant: String,
+                #[serde(default)]
+                until: u64,
             }
             resp.json::<Verdict>()
                 .await
-                .map(|v| v.ok)
+                .map(|v| v.ok.then_some(AccessPass { who: v.who, grant: v.grant, until: v.until }))
                 .map_err(|e| HubError::Unavailable(format!("вердикт хаба не разобрался: {e}")))
         })
     }
+
+    fn redeem_guest(&self, publication: String, token: String) -> Boxed<Result<Option<GuestPass>, HubError>> {
+        let req = self
+            .post("/api/v1/web/guest")
+            .json(&serde_json::json!({ "publication": publication, "token": token }));
+        Box::pin(async move {
+            let resp = req
+                .send()
+                .await
+                .map_err(|e| HubError::Unavailable(format!("хаб не ответил: {e}")))?;
+            let status = resp.status();
+            if status == reqwest::StatusCode::NOT_FOUND {
+                return Err(HubError::NotFound);
+            }
+            if !status.is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                return Err(HubError::Unavailable(format!(
+                    "хаб ответил {status}: {}",
+                    body.trim()
+                )));
+            }
+            #[derive(serde::Deserialize)]
+            struct Verdict {
+                ok: bool,
+                #[serde(default)]
+                id: String,
+                #[serde(default)]
+                label: String,
+                #[serde(default)]
+                expires: u64,
+            }
+            resp.json::<Verdict>()
+                .await
+                .map(|v| {
+                    v.ok.then_some(GuestPass { id: v.id, label: v.label, expires: v.expires })
+                })
+                .map_err(|e| HubError::Unavailable(format!("вердикт хаба не разобрался: {e}")))
+        })
+    }
+
+    fn report_sessions(&self, report: WebSessionsReport) -> Boxed<Result<WebSessionsVerdict, HubError>> {
+        let req = self.post("/api/v1/web/sessions").json(&report);
+        Box::pin(async move {
+            let resp = req
+                .send()
+                .await
+                .map_err(|e| HubError::Unavailable(format!("хаб не ответил: {e}")))?;
+            let status = resp.status();
+            if !status.is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                return Err(HubError::Unavailable(format!(
+                    "хаб ответил {status}: {}",
+                    body.trim()
+                )));
+            }
+            resp.json::<WebSessionsVerdict>()
+                .await
+                .map_err(|e| HubError::Unavailable(format!("ответ на доклад сессий не разобрался: {e}")))
+        })
+    }
 }
 
 /// Кеш маршрутов публикаций.
@@ -228,8 +368,20 @@ mod tests {
             })
         }
 
-        fn verify_password(&self, _u: String, _p: String) -> Boxed<Result<bool, HubError>> {
-            Box::pin(async { Ok(true) })
+        fn share_route(&self, _s: String, _t: String) -> Boxed<Result<WebShareRoute, HubError>> {
+            Box::pin(async { Err(HubError::NotFound) })
+        }
+
+        fn verify_access(&self, _p: String, c: Credentials) -> Boxed<Result<Option<AccessPass>, HubError>> {
+            Box::pin(async move { Ok(Some(AccessPass { who: c.username, grant: String::new(), until: 0 })) })
+        }
+
+        fn redeem_guest(&self, _p: String, _t: String) -> Boxed<Result<Option<GuestPass>, HubError>> {
+            Box::pin(async { Ok(None) })
+        }
+
+        fn report_sessions(&self, _r: WebSessionsReport) -> Boxed<Result<WebSessionsVerdict, HubError>> {
+            Box::pin(async { Ok(WebSessionsVerdict::default()) })
         }
     }
 
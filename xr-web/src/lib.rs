@@ -17,14 +17,22 @@
 //! Апгрейд до WebSocket идёт насквозь ([upgrade]): после `101` фронт перестаёт
 //! быть HTTP-посредником, сплайсит байты в обе стороны и закрывает соединение
 //! сам штатным закрытием до того, как relay обрубит сплайс своим потолком.
+//!
+//! Хост `s.<web-домен>` отдан страницам шар ([share]): каталог и скачивание по
+//! ссылке с токеном шары, без сессии и без приложения.
+//!
+//! Ответы публикаций кешируются на диске по правилам самого приложения и
+//! жмутся на выходе ([cache]): аплинк агента часто медленнее всего пути.
 
 pub mod app;
+pub mod cache;
 pub mod config;
 pub mod hub;
 pub mod listen;
 pub mod pages;
 pub mod pool;
 pub mod session;
+pub mod share;
 pub mod upgrade;
 
 #[cfg(test)]
@@ -4,10 +4,13 @@
 //! Сертификатами `xr-web` по умолчанию не занимается: он стоит за тем же
 //! фронтом, что выводит наружу хаб, и wildcard живёт там. Блок `[tls]` это
 //! установка без фронта, и провайдер там тот же ring, что у пиннинга агента:
-//! второй крипто-бэкенд в одном процессе ссорится с ним за умолчание.
+//! второй крипто-бэкенд в одном процессе ссорится с ним за умолчание. С
+//! `[tls.acme]` wildcard фронт заказывает сам, и пара подменяется на ходу
+//! ([`ReloadableCert`]): открытые соединения доживают на старой, новые
+//! хендшейки идут уже на новой.
 
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use anyhow::Context;
 use axum::Router;
@@ -75,21 +78,73 @@ where
     }
 }
 
-/// Собрать акцептор из пары «сертификат, ключ».
-pub fn tls_acceptor(cfg: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+/// Пара «сертификат, ключ», которую можно подменить без рестарта. Каждый
+/// хендшейк берёт текущую.
+#[derive(Debug)]
+pub struct ReloadableCert {
+    current: RwLock<Arc<rustls::sign::CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    pub fn load(cfg: &TlsConfig) -> anyhow::Result<Arc<Self>> {
+        Ok(Arc::new(Self { current: RwLock::new(certified_key(cfg)?) }))
+    }
+
+    /// Перечитать пару с диска. Негодная пара не ставится: листенер остаётся
+    /// на прежней, а не без сертификата.
+    pub fn reload(&self, cfg: &TlsConfig) -> anyhow::Result<()> {
+        let fresh = certified_key(cfg)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(())
+    }
+}
+
+impl rustls::server::ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _hello: rustls::server::ClientHello<'_>) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.current.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+fn certified_key(cfg: &TlsConfig) -> anyhow::Result<Arc<rustls::sign::CertifiedKey>> {
     let certs = load_certs(&cfg.cert)?;
     let key = load_key(&cfg.key)?;
+    let key = rustls::sign::CertifiedKey::from_der(certs, key, &rustls::crypto::ring::default_provider())
+        .map_err(|e| anyhow::anyhow!("сертификат и ключ не сошлись: {e}"))?;
+    Ok(Arc::new(key))
+}
+
+/// Собрать акцептор поверх подменяемой пары.
+pub fn tls_acceptor(cert: Arc<ReloadableCert>) -> anyhow::Result<TlsAcceptor> {
     let config = rustls::ServerConfig::builder_with_provider(Arc::new(
         rustls::crypto::ring::default_provider(),
     ))
     .with_safe_default_protocol_versions()
     .map_err(|e| anyhow::anyhow!("версии TLS: {e}"))?
     .with_no_client_auth()
-    .with_single_cert(certs, key)
-    .map_err(|e| anyhow::anyhow!("сертификат и ключ не сошлись: {e}"))?;
+    .with_cert_resolver(cert);
     Ok(TlsAcceptor::from(Arc::new(config)))
 }
 
+/// Листенер HTTP-01 для `[tls.acme]`: отдаёт только токены, которые сейчас
+/// ждёт CA, всё прочее на нём `404`.
+pub async fn serve_http01(listener: TcpListener, tokens: xr_proto::acme::Http01Tokens) -> anyhow::Result<()> {
+    use axum::extract::{Path, State};
+    use axum::response::IntoResponse;
+
+    async fn token(
+        State(tokens): State<xr_proto::acme::Http01Tokens>,
+        Path(token): Path<String>,
+    ) -> axum::response::Response {
+        match tokens.get(&token) {
+            Some(key_auth) => key_auth.into_response(),
+            None => axum::http::StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+    let path = format!("{}{{token}}", xr_proto::acme::Http01Tokens::PATH_PREFIX);
+    let app = Router::new().route(&path, axum::routing::get(token)).with_state(tokens);
+    axum::serve(listener, app).await.context("листенер HTTP-01")
+}
+
 fn load_certs(path: &std::path::Path) -> anyhow::Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
     let data = std::fs::read(path).with_context(|| format!("чтение {}", path.display()))?;
     let certs: Result<Vec<_>, _> = rustls_pemfile::certs(&mut data.as_slice()).collect();
@@ -148,4 +203,57 @@ mod tests {
         assert!(answer.starts_with("HTTP/1.1 200 OK"), "{answer}");
         assert!(answer.contains("/.xr-web/login"), "{answer}");
     }
+
+    /// Самоподписанная пара на имя web.example.com в `dir`.
+    fn write_pair(dir: &std::path::Path) -> (TlsConfig, rustls::pki_types::CertificateDer<'static>) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["web.example.com".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let cfg = TlsConfig { cert: dir.join("cert.pem"), key: dir.join("key.pem"), acme: None };
+        std::fs::write(&cfg.cert, cert.pem()).unwrap();
+        std::fs::write(&cfg.key, key.serialize_pem()).unwrap();
+        (cfg, cert.der().clone())
+    }
+
+    /// Хендшейк по duplex с клиентом, доверяющим ровно `anchor`.
+    async fn handshake(acceptor: &TlsAcceptor, anchor: &rustls::pki_types::CertificateDer<'static>) -> bool {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(anchor.clone()).unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let (c, s) = tokio::io::duplex(64 * 1024);
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let _ = acceptor.accept(s).await;
+        });
+        let name = rustls::pki_types::ServerName::try_from("web.example.com").unwrap();
+        tokio_rustls::TlsConnector::from(Arc::new(client)).connect(name, c).await.is_ok()
+    }
+
+    /// Продление подменяет сертификат для новых хендшейков без нового
+    /// акцептора, а негодная пара на диске не снимает действующую.
+    #[tokio::test]
+    async fn reloaded_pair_serves_new_handshakes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cfg, old) = write_pair(dir.path());
+        let cert = ReloadableCert::load(&cfg).unwrap();
+        let acceptor = tls_acceptor(cert.clone()).unwrap();
+        assert!(handshake(&acceptor, &old).await);
+
+        let (_, new) = write_pair(dir.path());
+        cert.reload(&cfg).unwrap();
+        assert!(handshake(&acceptor, &new).await, "новый хендшейк на новой паре");
+        assert!(!handshake(&acceptor, &old).await, "старая пара больше не отдаётся");
+
+        std::fs::write(&cfg.key, b"broken").unwrap();
+        assert!(cert.reload(&cfg).is_err());
+        assert!(handshake(&acceptor, &new).await, "действующая пара осталась на месте");
+    }
 }
@@ -5,11 +5,15 @@ use std::sync::Arc;
 
 use clap::Parser;
 use tokio::net::TcpListener;
-use xr_web::app::{router, WebState};
+use xr_web::app::{router, sync_sessions, WebState, SESSION_SYNC_SECS};
+use xr_web::cache::ResponseCache;
 use xr_web::config::WebFile;
 use xr_web::hub::HttpHub;
-use xr_web::listen::{serve, tls_acceptor};
+use xr_proto::acme::{AcmeManager, CertStatus, Challenge};
+use xr_web::listen::{serve, serve_http01, tls_acceptor, ReloadableCert};
 use xr_web::pool::{AgentPool, RelayDialer};
+use xr_web::session::Sessions;
+use xr_web::share::SharePages;
 
 #[derive(Parser)]
 #[command(name = "xr-web", about = "XR web: браузерный вход к публикациям агентов")]
@@ -47,15 +51,47 @@ async fn main() -> anyhow::Result<()> {
     }
 
     let pool = AgentPool::new(Arc::new(RelayDialer::new()));
-    let state = Arc::new(WebState::new(
-        cfg.web.domain.clone(),
-        hub,
-        pool,
-        cfg.web.session_ttl_secs,
-    ));
+    let sessions = Sessions::load(&cfg.web.session_file, cfg.web.session_ttl_secs)?;
+    tracing::info!(
+        "сессии из {}: {}",
+        cfg.web.session_file.display(),
+        sessions.len()
+    );
+    let cache = match &cfg.cache {
+        Some(c) => {
+            let cache = ResponseCache::open(c)?;
+            tracing::info!(
+                "кеш ответов в {}: до {} МБ на публикацию",
+                c.dir.display(),
+                c.max_mb_per_publication
+            );
+            Some(cache)
+        }
+        None => None,
+    };
+    let cert_status = Arc::new(CertStatus::default());
+    let state = Arc::new(
+        WebState::new(cfg.web.domain.clone(), hub, pool, cfg.web.session_ttl_secs)
+            .with_sessions(sessions)
+            .with_share_pages(SharePages::from_config(&cfg.web.hub_pubkey)?)
+            .with_cert_status(cert_status.clone())
+            .with_cache(cache, cfg.web.compress),
+    );
+
+    // Доклад сессий хабу: список в админке и «выйти везде» оттуда. Первый
+    // обмен сразу, чтобы нажатое, пока фронт лежал, сработало до первого
+    // запроса браузера.
+    let sync = state.clone();
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(std::time::Duration::from_secs(SESSION_SYNC_SECS));
+        loop {
+            tick.tick().await;
+            sync_sessions(&sync).await;
+        }
+    });
 
     let tls = match &cfg.tls {
-        Some(tls) => Some(tls_acceptor(tls)?),
+        Some(tls) => Some(own_tls(tls, cert_status).await?),
         None => None,
     };
     let listener = TcpListener::bind(&cfg.web.bind).await?;
@@ -65,10 +101,13 @@ async fn main() -> anyhow::Result<()> {
         if tls.is_some() { "свой TLS" } else { "HTTP за фронтом" },
         cfg.web.domain
     );
+    if !state.shares.enabled() {
+        tracing::info!("страницы шар выключены: в [web] нет hub_pubkey");
+    }
 
     let mut outcome = Ok(());
     tokio::select! {
-        r = serve(listener, tls, router(state)) => {
+        r = serve(listener, tls, router(state.clone())) => {
             if let Err(e) = &r {
                 tracing::error!("листенер xr-web умер: {e}");
             }
@@ -76,5 +115,59 @@ async fn main() -> anyhow::Result<()> {
         }
         _ = tokio::signal::ctrl_c() => tracing::info!("xr-web останавливается"),
     }
+    // Продления с последнего обмена: без этой записи рестарт укоротил бы
+    // сессии на них.
+    if let Err(e) = state.sessions.flush().await {
+        tracing::warn!("сессии не записались на диск: {e}");
+    }
     outcome
 }
+
+/// Свой TLS: пара с диска, а при `[tls.acme]` заказанная и продлеваемая
+/// фронтом. Первый заказ идёт до листенера, если пары ещё нет.
+async fn own_tls(
+    tls: &xr_web::config::TlsConfig,
+    status: Arc<CertStatus>,
+) -> anyhow::Result<tokio_rustls::TlsAcceptor> {
+    let acme = match tls.acme.clone() {
+        Some(cfg) => Some(
+            AcmeManager::new(cfg, &tls.cert, &tls.key, status.clone()).map_err(anyhow::Error::msg)?,
+        ),
+        None => None,
+    };
+    if let Some(m) = &acme {
+        if m.config().challenge == Challenge::Http01 {
+            let bind = &m.config().http01_bind;
+            let listener = TcpListener::bind(bind).await?;
+            tracing::info!("ACME: HTTP-01 на {bind}");
+            let tokens = m.http01();
+            tokio::spawn(async move {
+                if let Err(e) = serve_http01(listener, tokens).await {
+                    tracing::error!("{e:#}");
+                }
+            });
+        }
+        if !tls.cert.exists() {
+            tracing::info!("ACME: сертификата {} ещё нет, заказ до старта листенера", tls.cert.display());
+            m.issue().await.map_err(|e| anyhow::anyhow!("первый заказ ACME: {e}"))?;
+        }
+    }
+    match status.observe(&tls.cert) {
+        Ok(not_after) => tracing::info!("сертификат {} годен до {not_after}", tls.cert.display()),
+        Err(e) => tracing::warn!("срок сертификата не прочитан: {e}"),
+    }
+    let cert = ReloadableCert::load(tls)?;
+    if let Some(m) = acme {
+        let (cert, tls) = (cert.clone(), tls.clone());
+        tokio::spawn(m.run(move || {
+            let res = cert.reload(&tls);
+            async move {
+                match res {
+                    Ok(()) => tracing::info!("TLS: новый сертификат подхвачен без рестарта"),
+                    Err(e) => tracing::error!("TLS: новый сертификат не подхвачен: {e:#}"),
+                }
+            }
+        }));
+    }
+    tls_acceptor(cert)
+}
@@ -1,4 +1,5 @@
-//! Страницы входа и отказов, вшитые в бинарь (LLD-38 п. 2.2, п. 2.4).
+//! Страницы входа, отказов и шар, вшитые в бинарь (LLD-38 п. 2.2, п. 2.4,
+//! п. 2.6).
 //!
 //! Ни одного внешнего запроса: браузер приходит на публикацию, которой может и
 //! не быть на связи, и страница обязана нарисоваться сама. Стилей ровно
@@ -8,6 +9,25 @@
 //! сервере входа (п. 3.1): браузерный путь это доверенный посредник, и это
 //! обещание не прячется в доке.
 
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use xr_proto::share::{ShareManifestEntry, WebAccessKind};
+
+/// Что кодируется в сегменте пути ссылки: всё, что браузер понял бы как
+/// разделитель или конец адреса. Косые между сегментами ставятся отдельно.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
 const STYLE: &str = "\
 :root{color-scheme:light dark}\
 body{margin:0;min-height:100vh;display:flex;align-items:center;justify-content:center;\
@@ -24,10 +44,17 @@ background:#2f6df6;color:#fff;cursor:pointer}\
 .host{font-weight:600;color:#16181d}\
 .err{margin-top:.75rem;padding:.55rem .7rem;border-radius:9px;background:#fdecec;color:#a32020}\
 .note{margin-top:1.1rem;font-size:.82rem;color:#8a8f9c}\
+main.wide{width:min(96vw,48rem);margin:2rem 0}\
+table{width:100%;border-collapse:collapse;margin-top:.75rem}\
+td{padding:.45rem .3rem;border-top:1px solid #e6e9ef;word-break:break-word}\
+td.n{white-space:nowrap;text-align:right;color:#5a6070;font-size:.9rem}\
+a{color:#2f6df6;text-decoration:none}\
+a.btn{display:inline-block;margin-top:1.1rem;padding:.65rem 1.2rem;border-radius:9px;\
+background:#2f6df6;color:#fff}\
 @media(prefers-color-scheme:dark){body{background:#15171c;color:#e8eaf0}\
 main{background:#1d2027;box-shadow:none}p,label,.note{color:#98a0b0}\
 input{background:#15171c;border-color:#333846;color:#e8eaf0}.host{color:#e8eaf0}\
-.err{background:#3a1d1d;color:#ffb3b3}}\
+.err{background:#3a1d1d;color:#ffb3b3}td{border-color:#333846}td.n{color:#98a0b0}}\
 ";
 
 /// Экранирование того, что попадает в страницу из чужих рук (имя хоста, текст
@@ -40,27 +67,41 @@ fn esc(s: &str) -> String {
         .replace('"', "&quot;")
 }
 
-/// Страница входа для публикации. `error` рисуется, когда пароль не подошёл
-/// или вход упёрся в задержку.
-pub fn login(publication: &str, error: Option<&str>) -> String {
+/// Страница входа для публикации. Поля формы задаёт политика входа: логин и
+/// пароль учётки, один пароль публикации или код инвайта. `error` рисуется,
+/// когда вход не подошёл или упёрся в задержку.
+pub fn login(publication: &str, access: WebAccessKind, error: Option<&str>) -> String {
     let err = match error {
         Some(text) => format!("<div class=\"err\">{}</div>", esc(text)),
         None => String::new(),
     };
+    const USERNAME: &str = "<label for=\"u\">Логин</label><input id=\"u\" name=\"username\" \
+autocomplete=\"username\" autofocus>";
+    const PASSWORD: &str = "<label for=\"p\">Пароль</label><input id=\"p\" name=\"password\" \
+type=\"password\" autocomplete=\"current-password\">";
+    let fields = match access {
+        WebAccessKind::Password => "<label for=\"p\">Пароль публикации</label><input id=\"p\" \
+name=\"password\" type=\"password\" autocomplete=\"current-password\" autofocus>"
+            .to_string(),
+        WebAccessKind::Invites => "<label for=\"i\">Код инвайта</label><input id=\"i\" \
+name=\"invite\" autocomplete=\"off\" autofocus>"
+            .to_string(),
+        WebAccessKind::Owner | WebAccessKind::Users | WebAccessKind::Public => {
+            format!("{USERNAME}{PASSWORD}")
+        }
+    };
     format!(
         "<!doctype html><html lang=\"ru\"><head><meta charset=\"utf-8\">\
 <meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\
 <title>Вход: {name}</title><style>{STYLE}</style></head><body><main>\
 <h1>Вход</h1><p>Публикация <span class=\"host\">{name}</span></p>\
-<form method=\"post\" action=\"/.xr-web/login\">\
-<label for=\"u\">Логин</label><input id=\"u\" name=\"username\" autocomplete=\"username\" autofocus>\
-<label for=\"p\">Пароль</label><input id=\"p\" name=\"password\" type=\"password\" \
-autocomplete=\"current-password\">\
+<form method=\"post\" action=\"/.xr-web/login\">{fields}\
 <button type=\"submit\">Войти</button></form>{err}\
 <p class=\"note\">Трафик расшифровывается на сервере входа: это доверенный посредник, \
 а не сквозное шифрование до вашей машины.</p>\
 </main></body></html>",
         name = esc(publication),
+        fields = fields,
         err = err,
     )
 }
@@ -78,13 +119,168 @@ pub fn failure(title: &str, detail: &str) -> String {
     )
 }
 
+/// Путь шары в ссылку: каждый сегмент кодируется отдельно, косые остаются.
+fn href_path(path: &str) -> String {
+    path.split('/')
+        .map(|seg| utf8_percent_encode(seg, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Размер человеку, в тех же единицах, что у бэкапа хаба.
+fn human_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    const GB: u64 = 1024 * MB;
+    match bytes {
+        b if b >= GB => format!("{:.1} ГБ", b as f64 / GB as f64),
+        b if b >= MB => format!("{:.1} МБ", b as f64 / MB as f64),
+        b if b >= KB => format!("{} КБ", b / KB),
+        b => format!("{b} Б"),
+    }
+}
+
+/// Дата изменения в UTC: часового пояса зрителя фронт не знает.
+fn human_time(mtime: i64) -> String {
+    chrono::DateTime::from_timestamp(mtime, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Шапка страницы шары: имя шары и крошки до текущего каталога.
+fn share_crumbs(name: &str, share_id: &str, dir: &str) -> String {
+    let mut out = format!("<a href=\"/{share_id}/\">{}</a>", esc(name));
+    let mut walked = String::new();
+    for seg in dir.split('/').filter(|s| !s.is_empty()) {
+        if !walked.is_empty() {
+            walked.push('/');
+        }
+        walked.push_str(seg);
+        out.push_str(&format!(
+            " / <a href=\"/{share_id}/ls/{}\">{}</a>",
+            href_path(&walked),
+            esc(seg)
+        ));
+    }
+    out
+}
+
+fn share_page(title: &str, body: &str) -> String {
+    format!(
+        "<!doctype html><html lang=\"ru\"><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\
+<title>{t}</title><style>{STYLE}</style></head><body><main class=\"wide\">{body}\
+<p class=\"note\">Файлы идут через сервер входа: он видит их содержимое по пути \
+от машины владельца до вас.</p></main></body></html>",
+        t = esc(title),
+    )
+}
+
+/// Список каталога шары: подкаталоги, затем файлы с размером и датой. Имена
+/// из манифеста чужие, поэтому экранируются, а в ссылках кодируются.
+pub fn share_listing(
+    name: &str,
+    share_id: &str,
+    dir: &str,
+    dirs: &[String],
+    files: &[&ShareManifestEntry],
+) -> String {
+    let prefix = if dir.is_empty() { String::new() } else { format!("{dir}/") };
+    let mut rows = String::new();
+    for sub in dirs {
+        rows.push_str(&format!(
+            "<tr><td><a href=\"/{share_id}/ls/{}\">{}/</a></td><td class=\"n\"></td>\
+<td class=\"n\"></td></tr>",
+            href_path(&format!("{prefix}{sub}")),
+            esc(sub)
+        ));
+    }
+    for entry in files {
+        rows.push_str(&format!(
+            "<tr><td><a href=\"/{share_id}/view/{}\">{}</a></td><td class=\"n\">{}</td>\
+<td class=\"n\">{}</td></tr>",
+            href_path(&entry.path),
+            esc(last_segment(&entry.path)),
+            human_size(entry.size),
+            human_time(entry.mtime)
+        ));
+    }
+    if rows.is_empty() {
+        rows = "<tr><td>Шара пуста</td></tr>".to_string();
+    }
+    share_page(
+        name,
+        &format!(
+            "<h1>{}</h1><table>{rows}</table>",
+            share_crumbs(name, share_id, dir)
+        ),
+    )
+}
+
+/// Страница файла: размер, дата, происхождение, если агент его знает, и
+/// кнопка скачивания. Внешние ссылки ставятся только на http(s).
+pub fn share_file(name: &str, share_id: &str, entry: &ShareManifestEntry) -> String {
+    let dir = entry.path.rsplit_once('/').map(|(d, _)| d).unwrap_or_default();
+    let file = last_segment(&entry.path);
+    let link = |url: &str, text: &str| {
+        if url.starts_with("https://") || url.starts_with("http://") {
+            format!("<a href=\"{}\" rel=\"noreferrer noopener\">{}</a>", esc(url), esc(text))
+        } else {
+            esc(text)
+        }
+    };
+    let mut facts = format!(
+        "<tr><td>Размер</td><td class=\"n\">{}</td></tr>\
+<tr><td>Изменён</td><td class=\"n\">{}</td></tr>",
+        human_size(entry.size),
+        human_time(entry.mtime)
+    );
+    if let Some(meta) = entry.meta.as_ref().filter(|m| !m.is_empty()) {
+        if !meta.title.is_empty() {
+            facts.push_str(&format!("<tr><td>Название</td><td class=\"n\">{}</td></tr>", esc(&meta.title)));
+        }
+        if !meta.source.is_empty() {
+            facts.push_str(&format!(
+                "<tr><td>Источник</td><td class=\"n\">{}</td></tr>",
+                link(&meta.source_url, &meta.source)
+            ));
+        }
+        if !meta.published.is_empty() {
+            facts.push_str(&format!(
+                "<tr><td>Опубликован</td><td class=\"n\">{}</td></tr>",
+                esc(&meta.published)
+            ));
+        }
+        if !meta.url.is_empty() {
+            facts.push_str(&format!(
+                "<tr><td>Откуда</td><td class=\"n\">{}</td></tr>",
+                link(&meta.url, &meta.url)
+            ));
+        }
+    }
+    share_page(
+        file,
+        &format!(
+            "<h1>{crumbs}</h1><p class=\"host\">{f}</p><table>{facts}</table>\
+<a class=\"btn\" href=\"/{share_id}/file/{href}\" download=\"{f}\">Скачать</a>",
+            crumbs = share_crumbs(name, share_id, dir),
+            f = esc(file),
+            href = href_path(&entry.path),
+        ),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn login_page_is_self_contained_and_names_the_publication() {
-        let page = login("dash", None);
+        let page = login("dash", WebAccessKind::Owner, None);
         assert!(page.contains("dash"));
         assert!(page.contains("action=\"/.xr-web/login\""));
         assert!(
@@ -97,7 +293,19 @@ mod tests {
         assert!(!page.contains("//cdn"), "{page}");
         assert!(!page.contains("<script"), "скриптов на странице входа нет");
         assert!(!page.contains("class=\"err\""), "без отказа плашки ошибки нет");
-        assert!(login("dash", Some("неверный пароль")).contains("неверный пароль"));
+        assert!(login("dash", WebAccessKind::Owner, Some("неверный пароль")).contains("неверный пароль"));
+    }
+
+    #[test]
+    fn login_fields_follow_the_access_policy() {
+        let owner = login("dash", WebAccessKind::Owner, None);
+        assert!(owner.contains("name=\"username\"") && owner.contains("name=\"password\""));
+        let password = login("dash", WebAccessKind::Password, None);
+        assert!(!password.contains("name=\"username\""), "у пароля публикации нет логина");
+        assert!(password.contains("name=\"password\""));
+        let invites = login("dash", WebAccessKind::Invites, None);
+        assert!(invites.contains("name=\"invite\""));
+        assert!(!invites.contains("name=\"password\""));
     }
 
     #[test]
@@ -106,4 +314,48 @@ mod tests {
         assert!(!page.contains("<img"), "чужой текст обязан быть экранирован: {page}");
         assert!(page.contains("&lt;img"));
     }
+
+    #[test]
+    fn share_listing_escapes_names_and_encodes_links() {
+        let file = ShareManifestEntry {
+            path: "docs/a b#1.txt".into(),
+            size: 2048,
+            mtime: 0,
+            sha256: String::new(),
+            meta: None,
+            preview: None,
+        };
+        let page = share_listing("<b>", "sid", "docs", &["x?y".to_string()], &[&file]);
+        assert!(!page.contains("<b>"), "имя шары экранируется: {page}");
+        assert!(page.contains("href=\"/sid/ls/docs/x%3Fy\""), "{page}");
+        assert!(page.contains("href=\"/sid/view/docs/a%20b%231.txt\""), "{page}");
+        assert!(page.contains("2 КБ") && page.contains("1970-01-01 00:00 UTC"));
+        assert!(!page.contains("<script"), "скриптов на странице шары нет");
+
+        let page = share_file("s", "sid", &file);
+        assert!(page.contains("href=\"/sid/file/docs/a%20b%231.txt\""), "{page}");
+        assert!(page.contains("download=\"a b#1.txt\""), "{page}");
+    }
+
+    #[test]
+    fn share_file_links_out_only_over_http() {
+        let mut file = ShareManifestEntry {
+            path: "v.mp4".into(),
+            size: 1,
+            mtime: 0,
+            sha256: String::new(),
+            meta: Some(xr_proto::share::FileMeta {
+                url: "javascript:alert(1)".into(),
+                source: "канал".into(),
+                source_url: "https://example.com/c".into(),
+                ..Default::default()
+            }),
+            preview: None,
+        };
+        let page = share_file("s", "sid", &file);
+        assert!(!page.contains("href=\"javascript:"), "{page}");
+        assert!(page.contains("href=\"https://example.com/c\""), "{page}");
+        file.meta = None;
+        assert!(!share_file("s", "sid", &file).contains("Источник"));
+    }
 }
@@ -25,7 +25,7 @@ use hyper::body::{Body as HttpBody, Bytes, Frame, Incoming, SizeHint};
 use hyper::client::conn::http1::SendRequest;
 use tokio::io::{AsyncRead, AsyncWrite};
 use xr_proto::relay_client::{relay_tls_connect, RelayEndpoint};
-use xr_proto::share::{RelayGrant, WebRoute};
+use xr_proto::share::{RelayGrant, WebRoute, WebShareRoute};
 
 /// Сколько простаивающее соединение считается годным. Relay рубит сплайс по
 /// своему потолку, а агент может уйти в переподключение: соединение, лежавшее
@@ -43,20 +43,72 @@ type Boxed<T> = Pin<Box<dyn Future<Output = T> + Send>>;
 pub trait AgentIo: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
 impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> AgentIo for T {}
 
+/// Куда ведёт соединение: публикация или страница шары. Пулу и набору нужны
+/// только очередь, транзит через relay и срок, до которого годен токен.
+pub trait AgentRoute: Send + Sync + 'static {
+    /// Очередь пула и эндпоинта. Публикации и шары не пересекаются: у шары
+    /// в ключе `/`, которого нет в имени публикации.
+    fn pool_key(&self) -> String;
+    fn relay_grant(&self) -> RelayGrant;
+    fn exp(&self) -> u64;
+}
+
+impl AgentRoute for WebRoute {
+    fn pool_key(&self) -> String {
+        self.publication.clone()
+    }
+
+    fn relay_grant(&self) -> RelayGrant {
+        RelayGrant {
+            addr: self.relay.addr.clone(),
+            port: self.relay.port,
+            obf: self.relay.obf.clone(),
+            relay_token: self.relay_token.clone(),
+            backups: self.backup_relays.clone(),
+            punch: self.relay.punch,
+        }
+    }
+
+    fn exp(&self) -> u64 {
+        self.exp
+    }
+}
+
+impl AgentRoute for WebShareRoute {
+    fn pool_key(&self) -> String {
+        format!("{}/{}", crate::app::SHARE_LABEL, self.share_id)
+    }
+
+    fn relay_grant(&self) -> RelayGrant {
+        RelayGrant {
+            addr: self.relay.addr.clone(),
+            port: self.relay.port,
+            obf: self.relay.obf.clone(),
+            relay_token: self.relay_token.clone(),
+            backups: self.backup_relays.clone(),
+            punch: self.relay.punch,
+        }
+    }
+
+    fn exp(&self) -> u64 {
+        self.exp
+    }
+}
+
 /// Как открывается соединение до агента. Настоящий набирает relay, тестовый
 /// отдаёт половинку duplex, поэтому весь путь запроса проверяется без сети.
 pub trait Dialer: Send + Sync + 'static {
-    fn connect(&self, route: Arc<WebRoute>) -> Boxed<io::Result<Box<dyn AgentIo>>>;
+    fn connect(&self, route: Arc<dyn AgentRoute>) -> Boxed<io::Result<Box<dyn AgentIo>>>;
 }
 
 /// Настоящий набор: обфусцированный mux к relay по токену маршрута, поверх
 /// сплайса pinned-TLS с проверкой `SPKI == agent_pubkey`.
 #[derive(Default)]
 pub struct RelayDialer {
-    /// Эндпоинт на публикацию: он держит живой mux к relay и переоткрывает его
-    /// сам, поэтому на каждое соединение уходит один стрим, а не новый TCP.
-    /// Ключ маршрута (`exp`) в значении: обновился маршрут, обновился и токен,
-    /// и старый эндпоинт больше не годится.
+    /// Эндпоинт на публикацию или шару: он держит живой mux к relay и
+    /// переоткрывает его сам, поэтому на каждое соединение уходит один стрим, а
+    /// не новый TCP. Ключ маршрута (`exp`) в значении: обновился маршрут,
+    /// обновился и токен, и старый эндпоинт больше не годится.
     endpoints: Mutex<HashMap<String, (u64, Arc<RelayEndpoint>)>>,
 }
 
@@ -65,29 +117,24 @@ impl RelayDialer {
         Self::default()
     }
 
-    fn endpoint(&self, route: &WebRoute) -> Result<Arc<RelayEndpoint>, String> {
+    fn endpoint(&self, route: &dyn AgentRoute) -> Result<Arc<RelayEndpoint>, String> {
+        let key = route.pool_key();
         let mut guard = self.endpoints.lock().expect("relay endpoints lock");
-        if let Some((exp, endpoint)) = guard.get(&route.publication) {
-            if *exp == route.exp {
+        if let Some((exp, endpoint)) = guard.get(&key) {
+            if *exp == route.exp() {
                 return Ok(endpoint.clone());
             }
         }
-        let grant = RelayGrant {
-            addr: route.relay.addr.clone(),
-            port: route.relay.port,
-            obf: route.relay.obf.clone(),
-            relay_token: route.relay_token.clone(),
-        };
-        let endpoint = Arc::new(RelayEndpoint::from_grant(&grant)?);
-        guard.insert(route.publication.clone(), (route.exp, endpoint.clone()));
+        let endpoint = Arc::new(RelayEndpoint::from_grant(&route.relay_grant())?);
+        guard.insert(key, (route.exp(), endpoint.clone()));
         Ok(endpoint)
     }
 }
 
 impl Dialer for RelayDialer {
-    fn connect(&self, route: Arc<WebRoute>) -> Boxed<io::Result<Box<dyn AgentIo>>> {
+    fn connect(&self, route: Arc<dyn AgentRoute>) -> Boxed<io::Result<Box<dyn AgentIo>>> {
         let endpoint = self
-            .endpoint(&route)
+            .endpoint(route.as_ref())
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
         Box::pin(async move {
             let endpoint = endpoint?;
@@ -112,7 +159,7 @@ struct Idle {
     opened_at: Instant,
 }
 
-/// Пул соединений: своя очередь на публикацию.
+/// Пул соединений: своя очередь на публикацию и на шару.
 pub struct AgentPool {
     dialer: Arc<dyn Dialer>,
     idle: Mutex<HashMap<String, Vec<Idle>>>,
@@ -127,8 +174,8 @@ impl AgentPool {
     }
 
     /// Готовое к запросу соединение: из пула, если там есть живое, иначе новое.
-    pub async fn checkout(self: &Arc<Self>, route: Arc<WebRoute>) -> io::Result<Lease> {
-        let publication = route.publication.clone();
+    pub async fn checkout(self: &Arc<Self>, route: Arc<dyn AgentRoute>) -> io::Result<Lease> {
+        let publication = route.pool_key();
         if let Some((sender, opened_at)) = self.take_idle(&publication) {
             return Ok(self.lease(publication, sender, opened_at));
         }
@@ -1,8 +1,15 @@
 //! Сессии браузера и их cookie (LLD-38 п. 2.2, п. 3.2).
 //!
 //! Своей учётной базы у фронта нет: пароль проверяет хаб, а здесь живёт только
-//! то, чего хаб не умеет, cookie. Сессии лежат в памяти процесса, рестарт
-//! разлогинивает всех, на диск не пишется ничего.
+//! то, чего хаб не умеет, cookie. Сессии переживают рестарт: они лежат в
+//! маленьком JSON-файле (`[web] session_file`), и деплой никого не
+//! разлогинивает. Токенов в файле нет, только их SHA-256: утёкший файл не
+//! открывает ни одной сессии.
+//!
+//! Владелец видит сессии в админке хаба и снимает их там же («выйти везде» по
+//! публикации). Хаб до фронта не ходит: фронт сам докладывает ему живые сессии
+//! и в ответ узнаёт, какие публикации разлогинить ([`Sessions::report`],
+//! [`Sessions::apply`]).
 //!
 //! Cookie host-only: атрибута `Domain` в ней нет, поэтому браузер не отдаёт
 //! сессию публикации `dash` публикации `notes`. Сервер держит тот же рубеж
@@ -10,10 +17,15 @@
 //! поддомене не считается. Одного браузера тут мало, cookie ходят и без него.
 
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use base64::Engine;
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xr_proto::share::{WebSessionInfo, WebSessionsReport, WebSessionsVerdict};
 
 /// Имя cookie сессии.
 pub const COOKIE_NAME: &str = "xrweb";
@@ -58,7 +70,7 @@ pub fn cookie_header_without_session(raw: &str) -> Option<String> {
     (!kept.is_empty()).then(|| kept.join("; "))
 }
 
-fn cookie_pairs(raw: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+pub(crate) fn cookie_pairs(raw: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
     raw.split(';').filter_map(|pair| {
         let pair = pair.trim();
         if pair.is_empty() {
@@ -71,60 +83,264 @@ fn cookie_pairs(raw: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
     })
 }
 
+/// Откуда пришёл запрос по сессии: для списка сессий в админке.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// Сколько символов `User-Agent` помнит сессия: в админке его читают глазами,
+/// а раздувать файл сессий чужим заголовком без границы незачем.
+const MAX_USER_AGENT: usize = 256;
+
+#[derive(Serialize, Deserialize)]
 struct Session {
     publication: String,
     username: String,
+    /// Когда открыта, unix-секунды: по этому моменту «выйти везде» решает,
+    /// какие сессии снять.
+    created: u64,
     /// Момент, после которого сессия мертва, unix-секунды. Двигается при
     /// каждой активности: владелец, который держит вкладку открытой, не
     /// разлогинивается посреди работы.
     expires: u64,
+    last_seen: u64,
+    #[serde(default)]
+    last_ip: String,
+    #[serde(default)]
+    user_agent: String,
+    /// Гостевая ссылка или инвайт, по которым открыта сессия: их отзыв
+    /// снимает сессию. Имя поля осталось от гостевых ссылок ради старых
+    /// файлов.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    guest: String,
+    /// Потолок жизни, который активность не двигает (срок гостевой ссылки
+    /// или инвайта), unix-секунды. `0` потолка нет.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    until: u64,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+impl Session {
+    fn new(publication: &str, username: &str, now: u64, client: &Client) -> Self {
+        let mut session = Session {
+            publication: publication.to_string(),
+            username: username.to_string(),
+            created: now,
+            expires: now,
+            last_seen: now,
+            last_ip: String::new(),
+            user_agent: String::new(),
+            guest: String::new(),
+            until: 0,
+        };
+        session.saw(client, now);
+        session
+    }
+
+    /// Продлить на `ttl`, но не дальше потолка.
+    fn renew(&mut self, ttl: u64, now: u64) {
+        let mut expires = now.saturating_add(ttl);
+        if self.until > 0 {
+            expires = expires.min(self.until);
+        }
+        self.expires = expires;
+    }
+
+    fn saw(&mut self, client: &Client, now: u64) {
+        self.last_seen = now;
+        self.last_ip.clone_from(&client.ip);
+        self.user_agent = client.user_agent.chars().take(MAX_USER_AGENT).collect();
+    }
+}
+
+/// Файл сессий: отпечаток токена -> сессия.
+#[derive(Default, Serialize, Deserialize)]
+struct Store {
+    #[serde(default)]
+    sessions: HashMap<String, Session>,
+}
+
+/// Отпечаток токена: ключ сессии в памяти и на диске.
+fn key_of(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Короткий отпечаток для админки. По нему сессию видно, но не открыть.
+fn public_id(key: &str) -> String {
+    key[..16].to_string()
+}
+
+/// Файл сессий и очередь записей в него.
+struct Disk {
+    path: PathBuf,
+    /// Что-то поменялось с последней записи. Открытие, выход и снятие пишутся
+    /// сразу; продление только помечает, и его пишет периодический
+    /// [`Sessions::flush`]: запись файла на каждый запрос браузера незачем.
+    dirty: AtomicBool,
+    /// Поколение снимка, последним дошедшего до файла. Записи идут под этим
+    /// замком по одной, а снимок старше записанного пропускается: запоздавшая
+    /// запись иначе вернула бы в файл уже снятую сессию.
+    written: Mutex<u64>,
+}
+
+impl Disk {
+    fn write(&self, generation: u64, json: &[u8]) -> std::io::Result<()> {
+        let mut written = self.written.lock().expect("sessions file lock");
+        if *written >= generation {
+            return Ok(());
+        }
+        let res = write_private(&self.path, json);
+        match &res {
+            Ok(()) => *written = generation,
+            Err(_) => self.dirty.store(true, Ordering::Relaxed),
+        }
+        res
+    }
+}
+
+/// Снимок сессий, ждущий записи.
+struct Snapshot {
+    disk: Arc<Disk>,
+    generation: u64,
+    json: Vec<u8>,
 }
 
-/// Сессии в памяти с TTL и продлением при активности.
+impl Snapshot {
+    fn write(self) -> std::io::Result<()> {
+        self.disk.write(self.generation, &self.json)
+    }
+}
+
+/// Сессии с TTL и продлением при активности, в памяти и, если задан файл, на
+/// диске.
 pub struct Sessions {
     ttl_secs: u64,
-    inner: Mutex<HashMap<String, Session>>,
+    disk: Option<Arc<Disk>>,
+    inner: Mutex<Store>,
+    /// Номер последнего снимка: берётся под замком `inner`, поэтому снимок с
+    /// большим номером содержит все правки меньшего.
+    generation: AtomicU64,
 }
 
 impl Sessions {
+    /// Сессии только в памяти: рестарт разлогинивает всех. Так живут тесты.
     pub fn new(ttl_secs: u64) -> Self {
         Self {
             ttl_secs,
-            inner: Mutex::new(HashMap::new()),
+            disk: None,
+            inner: Mutex::new(Store::default()),
+            generation: AtomicU64::new(0),
         }
     }
 
+    /// Сессии из файла. Нет файла значит первый старт; нечитаемый файл это
+    /// ошибка старта, а не молчаливый выход всех.
+    pub fn load(path: &Path, ttl_secs: u64) -> anyhow::Result<Self> {
+        let store = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| anyhow::anyhow!("parse {}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Store::default(),
+            Err(e) => return Err(anyhow::anyhow!("read {}: {e}", path.display())),
+        };
+        Ok(Self {
+            ttl_secs,
+            disk: Some(Arc::new(Disk {
+                path: path.to_path_buf(),
+                dirty: AtomicBool::new(false),
+                written: Mutex::new(0),
+            })),
+            inner: Mutex::new(store),
+            generation: AtomicU64::new(0),
+        })
+    }
+
     pub fn ttl_secs(&self) -> u64 {
         self.ttl_secs
     }
 
     /// Открыть сессию на публикации и отдать её токен.
-    pub fn open(&self, publication: &str, username: &str, now: u64) -> String {
+    pub fn open(&self, publication: &str, username: &str, now: u64, client: &Client) -> String {
+        let mut session = Session::new(publication, username, now, client);
+        session.renew(self.ttl_secs, now);
+        self.insert(session, now)
+    }
+
+    /// Открыть гостевую сессию: она живёт не дольше `until` и снимается
+    /// отзывом ссылки `guest`.
+    pub fn open_guest(
+        &self,
+        publication: &str,
+        label: &str,
+        guest: &str,
+        until: u64,
+        now: u64,
+        client: &Client,
+    ) -> String {
+        let name = if label.is_empty() { "гость".to_string() } else { format!("гость: {label}") };
+        self.open_granted(publication, &name, guest, until, now, client)
+    }
+
+    /// Открыть сессию по отзываемому допуску `grant` (гостевая ссылка,
+    /// инвайт): она живёт не дольше `until` (`0` без потолка) и снимается,
+    /// когда хаб называет `grant` в вердикте.
+    pub fn open_granted(
+        &self,
+        publication: &str,
+        username: &str,
+        grant: &str,
+        until: u64,
+        now: u64,
+        client: &Client,
+    ) -> String {
+        let mut session = Session::new(publication, username, now, client);
+        session.guest = grant.to_string();
+        session.until = until;
+        session.renew(self.ttl_secs, now);
+        self.insert(session, now)
+    }
+
+    /// Сколько секунд живёт cookie сессии: TTL, а у гостевой не дольше её
+    /// потолка.
+    pub fn cookie_ttl(&self, until: u64, now: u64) -> u64 {
+        if until == 0 {
+            self.ttl_secs
+        } else {
+            self.ttl_secs.min(until.saturating_sub(now))
+        }
+    }
+
+    fn insert(&self, session: Session, now: u64) -> String {
         let mut bytes = [0u8; 32];
         rand::thread_rng().fill_bytes(&mut bytes);
         let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
-        let mut guard = self.inner.lock().expect("sessions lock");
-        // Протухшие выметаются на входе: своего таймера у хранилища нет, а
-        // расти без границы память фронта не должна.
-        guard.retain(|_, s| s.expires > now);
-        guard.insert(
-            token.clone(),
-            Session {
-                publication: publication.to_string(),
-                username: username.to_string(),
-                expires: now.saturating_add(self.ttl_secs),
-            },
-        );
+        {
+            let mut guard = self.inner.lock().expect("sessions lock");
+            // Протухшие выметаются на входе: своего таймера у хранилища нет, а
+            // расти без границы ни память фронта, ни файл не должны.
+            guard.sessions.retain(|_, s| s.expires > now);
+            guard.sessions.insert(key_of(&token), session);
+        }
+        self.changed();
         token
     }
 
-    /// Живая ли это сессия на этой публикации. Живую заодно продлевает и
-    /// возвращает имя владельца.
-    pub fn touch(&self, token: &str, publication: &str, now: u64) -> Option<String> {
+    /// Живая ли это сессия на этой публикации. Живую заодно продлевает,
+    /// запоминает, откуда пришёл запрос, и возвращает имя владельца.
+    pub fn touch(&self, token: &str, publication: &str, now: u64, client: &Client) -> Option<String> {
+        let key = key_of(token);
         let mut guard = self.inner.lock().expect("sessions lock");
-        let session = guard.get_mut(token)?;
+        let session = guard.sessions.get_mut(&key)?;
         if session.expires <= now {
-            guard.remove(token);
+            guard.sessions.remove(&key);
+            self.mark();
             return None;
         }
         // Сессия одной публикации не открывает другую, даже если токен принесли
@@ -132,18 +348,143 @@ impl Sessions {
         if session.publication != publication {
             return None;
         }
-        session.expires = now.saturating_add(self.ttl_secs);
+        session.renew(self.ttl_secs, now);
+        session.saw(client, now);
+        self.mark();
         Some(session.username.clone())
     }
 
     /// Снять сессию (логаут). Возвращает `true`, если было что снимать.
     pub fn close(&self, token: &str) -> bool {
-        self.inner.lock().expect("sessions lock").remove(token).is_some()
+        let removed = self
+            .inner
+            .lock()
+            .expect("sessions lock")
+            .sessions
+            .remove(&key_of(token))
+            .is_some();
+        if removed {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Живые сессии для доклада хабу.
+    pub fn report(&self, now: u64) -> WebSessionsReport {
+        let guard = self.inner.lock().expect("sessions lock");
+        let sessions = guard
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.expires > now)
+            .map(|(key, s)| WebSessionInfo {
+                id: public_id(key),
+                publication: s.publication.clone(),
+                username: s.username.clone(),
+                created: s.created,
+                last_seen: s.last_seen,
+                last_ip: s.last_ip.clone(),
+                user_agent: s.user_agent.clone(),
+                grant: s.guest.clone(),
+            })
+            .collect();
+        WebSessionsReport { sessions }
+    }
+
+    /// Применить вердикт хаба: снять сессии, открытые на публикации раньше её
+    /// «выйти везде», и сессии отозванных гостевых ссылок и инвайтов. Возвращает, сколько
+    /// снято.
+    pub fn apply(&self, verdict: &WebSessionsVerdict) -> usize {
+        let removed = {
+            let mut guard = self.inner.lock().expect("sessions lock");
+            let before = guard.sessions.len();
+            guard.sessions.retain(|_, s| {
+                let revoked = !s.guest.is_empty() && verdict.revoked_guests.contains(&s.guest);
+                !revoked
+                    && verdict
+                        .logout_before
+                        .get(&s.publication)
+                        .is_none_or(|&cut| s.created >= cut)
+            });
+            before - guard.sessions.len()
+        };
+        if removed > 0 {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Записать сессии в файл, если они менялись с прошлой записи. Файл
+    /// пишется вне async-рантайма.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let Some(snapshot) = self.snapshot()? else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(move || snapshot.write())
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    /// [`flush`](Self::flush) прямо в этом потоке: для кода без рантайма.
+    pub fn flush_blocking(&self) -> std::io::Result<()> {
+        match self.snapshot()? {
+            Some(snapshot) => snapshot.write(),
+            None => Ok(()),
+        }
+    }
+
+    /// Что записать, если есть что.
+    fn snapshot(&self) -> std::io::Result<Option<Snapshot>> {
+        let Some(disk) = &self.disk else {
+            return Ok(None);
+        };
+        if !disk.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let guard = self.inner.lock().expect("sessions lock");
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        match serde_json::to_vec(&*guard) {
+            Ok(json) => Ok(Some(Snapshot { disk: disk.clone(), generation, json })),
+            Err(e) => {
+                disk.dirty.store(true, Ordering::Relaxed);
+                Err(std::io::Error::other(e))
+            }
+        }
+    }
+
+    /// Пометить сессии для периодической записи.
+    fn mark(&self) {
+        if let Some(disk) = &self.disk {
+            disk.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Сессии поменялись так, что ждать периодической записи нельзя: вход,
+    /// выход и снятие должны пережить падение сразу. Запрос браузера записи
+    /// не ждёт: она уходит в блокирующий пул, а без рантайма идёт на месте.
+    fn changed(&self) {
+        self.mark();
+        let Ok(rt) = tokio::runtime::Handle::try_current() else {
+            if let Err(e) = self.flush_blocking() {
+                tracing::warn!("сессии не записались на диск: {e}");
+            }
+            return;
+        };
+        match self.snapshot() {
+            Ok(Some(snapshot)) => {
+                rt.spawn_blocking(move || {
+                    if let Err(e) = snapshot.write() {
+                        tracing::warn!("сессии не записались на диск: {e}");
+                    }
+                });
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("сессии не записались на диск: {e}"),
+        }
     }
 
     /// Сколько сессий помнит фронт: только для тестов и диагностики.
     pub fn len(&self) -> usize {
-        self.inner.lock().expect("sessions lock").len()
+        self.inner.lock().expect("sessions lock").sessions.len()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -151,6 +492,21 @@ impl Sessions {
     }
 }
 
+/// Запись только для владельца процесса: в файле отпечатки токенов, адреса и
+/// браузеры посетителей.
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    std::io::Write::write_all(&mut opts.open(&tmp)?, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,37 +544,131 @@ mod tests {
     #[test]
     fn session_expires_and_activity_renews_it() {
         let s = Sessions::new(100);
-        let token = s.open("dash", "owner", 1_000);
-        assert_eq!(s.touch(&token, "dash", 1_099).as_deref(), Some("owner"));
+        let token = s.open("dash", "owner", 1_000, &Client::default());
+        assert_eq!(s.touch(&token, "dash", 1_099, &Client::default()).as_deref(), Some("owner"));
         // Продление сдвинуло срок: без него на 1_150 сессия была бы мертва.
-        assert_eq!(s.touch(&token, "dash", 1_150).as_deref(), Some("owner"));
-        assert_eq!(s.touch(&token, "dash", 1_400), None, "протухшая не пускает");
+        assert_eq!(s.touch(&token, "dash", 1_150, &Client::default()).as_deref(), Some("owner"));
+        assert_eq!(s.touch(&token, "dash", 1_400, &Client::default()), None, "протухшая не пускает");
         assert!(s.is_empty(), "протухшая сессия не остаётся в памяти");
     }
 
     #[test]
     fn session_of_one_publication_does_not_open_another() {
         let s = Sessions::new(TTL);
-        let token = s.open("dash", "owner", 0);
-        assert_eq!(s.touch(&token, "notes", 1), None);
-        assert_eq!(s.touch(&token, "dash", 1).as_deref(), Some("owner"));
+        let token = s.open("dash", "owner", 0, &Client::default());
+        assert_eq!(s.touch(&token, "notes", 1, &Client::default()), None);
+        assert_eq!(s.touch(&token, "dash", 1, &Client::default()).as_deref(), Some("owner"));
     }
 
     #[test]
     fn logout_kills_the_session() {
         let s = Sessions::new(TTL);
-        let token = s.open("dash", "owner", 0);
+        let token = s.open("dash", "owner", 0, &Client::default());
         assert!(s.close(&token));
-        assert_eq!(s.touch(&token, "dash", 1), None);
+        assert_eq!(s.touch(&token, "dash", 1, &Client::default()), None);
         assert!(!s.close(&token), "повторный логаут снимать уже нечего");
     }
 
     #[test]
     fn tokens_do_not_repeat() {
         let s = Sessions::new(TTL);
-        let a = s.open("dash", "owner", 0);
-        let b = s.open("dash", "owner", 0);
+        let a = s.open("dash", "owner", 0, &Client::default());
+        let b = s.open("dash", "owner", 0, &Client::default());
         assert_ne!(a, b);
         assert_eq!(s.len(), 2);
     }
+
+    #[test]
+    fn sessions_survive_a_restart_without_tokens_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.json");
+        let client = Client {
+            ip: "198.51.100.4".into(),
+            user_agent: "Firefox".into(),
+        };
+        let token = {
+            let s = Sessions::load(&path, TTL).unwrap();
+            let token = s.open("dash", "owner", 1_000, &client);
+            // Продление только помечает: его дописывает периодический flush.
+            s.touch(&token, "dash", 1_500, &client).unwrap();
+            s.flush_blocking().unwrap();
+            token
+        };
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(!raw.contains(&token), "токена в файле быть не должно: {raw}");
+
+        let s = Sessions::load(&path, TTL).unwrap();
+        assert_eq!(s.touch(&token, "dash", 2_000, &client).as_deref(), Some("owner"));
+        let report = s.report(2_000);
+        assert_eq!(report.sessions.len(), 1);
+        let info = &report.sessions[0];
+        assert_eq!((info.created, info.last_seen), (1_000, 2_000));
+        assert_eq!((info.last_ip.as_str(), info.user_agent.as_str()), ("198.51.100.4", "Firefox"));
+        assert_eq!(info.id.len(), 16);
+        assert!(!token.starts_with(&info.id));
+    }
+
+    /// Записи из блокирующего пула идут в любом порядке, но файл остаётся за
+    /// последним снимком: запоздавший старый не возвращает снятую сессию.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writes_keep_the_latest_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.json");
+        let s = Arc::new(Sessions::load(&path, TTL).unwrap());
+        let tasks: Vec<_> = (0..32)
+            .map(|i| {
+                let s = s.clone();
+                tokio::spawn(async move { s.open("dash", &format!("u{i}"), 1_000, &Client::default()) })
+            })
+            .collect();
+        let mut tokens = Vec::new();
+        for t in tasks {
+            tokens.push(t.await.unwrap());
+        }
+        assert!(s.close(&tokens[0]));
+        s.mark();
+        s.flush().await.unwrap();
+        assert_eq!(Sessions::load(&path, TTL).unwrap().len(), 31);
+
+        let disk = s.disk.clone().unwrap();
+        disk.write(1, br#"{"sessions":{}}"#).unwrap();
+        assert_eq!(Sessions::load(&path, TTL).unwrap().len(), 31, "старый снимок не пишется");
+    }
+
+    #[test]
+    fn broken_session_file_is_a_startup_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.json");
+        std::fs::write(&path, "{ не json").unwrap();
+        assert!(Sessions::load(&path, TTL).is_err(), "молча разлогинить всех нельзя");
+    }
+
+    #[test]
+    fn verdict_closes_only_older_sessions_of_its_publication() {
+        let s = Sessions::new(TTL);
+        let old = s.open("dash", "owner", 100, &Client::default());
+        let fresh = s.open("dash", "owner", 300, &Client::default());
+        let other = s.open("notes", "owner", 100, &Client::default());
+        let verdict = WebSessionsVerdict {
+            logout_before: HashMap::from([("dash".to_string(), 200)]),
+            ..Default::default()
+        };
+        assert_eq!(s.apply(&verdict), 1);
+        assert_eq!(s.touch(&old, "dash", 400, &Client::default()), None);
+        assert!(s.touch(&fresh, "dash", 400, &Client::default()).is_some(), "вход после «выйти везде» живёт");
+        assert!(s.touch(&other, "notes", 400, &Client::default()).is_some());
+        assert_eq!(s.apply(&verdict), 0, "повторный вердикт снимать уже нечего");
+    }
+
+    #[test]
+    fn guest_session_does_not_outlive_its_link() {
+        let s = Sessions::new(100);
+        let token = s.open_guest("dash", "Вася", "g1", 1_150, 1_000, &Client::default());
+        assert_eq!(s.cookie_ttl(1_150, 1_000), 100);
+        assert_eq!(s.cookie_ttl(1_150, 1_100), 50, "cookie не дольше ссылки");
+        // Активность продлевает, но не дальше срока ссылки.
+        assert!(s.touch(&token, "dash", 1_090, &Client::default()).is_some());
+        assert!(s.touch(&token, "dash", 1_149, &Client::default()).is_some());
+        assert_eq!(s.touch(&token, "dash", 1_150, &Client::default()), None);
+    }
 }
@@ -0,0 +1,902 @@
+//! Страницы шар на `s.<web-домен>` (LLD-38 п. 2.6): каталог и скачивание для
+//! тех, у кого нет ни приложения, ни `xr-share pull`.
+//!
+//! Гейтом служит сам токен шары из ссылки, логина нет. Фронт проверяет его
+//! офлайн ключом хаба (подпись, срок, совпадение `share_id` с путём) и лишь
+//! потом берёт маршрут; конечную авторизацию по-прежнему делает агент, которому
+//! токен едет как есть. После первого захода токен переезжает из query в
+//! cookie на путь шары, и адрес в строке браузера его больше не носит.
+//!
+//! Каталог рисуется по манифесту агента, и подпись манифеста фронт проверяет
+//! ключом агента из маршрута, как это делает приложение: список, который не
+//! сошёлся с подписью, браузеру не показывается. Проверенный список живёт
+//! рядом с маршрутом по своей версии: следующий заход спрашивает агента
+//! ожиданием с нулевым удержанием, и пока шара не менялась, ответ это `304`
+//! без обхода на агенте и без новой проверки здесь. Байты файла едут через тот же
+//! туннель потоком, `Range` доезжает до агента, поэтому докачка и перемотка
+//! видео работают.
+//!
+//! Адреса страниц:
+//! - `/{share_id}/` и `/{share_id}/ls/{каталог}`: список;
+//! - `/{share_id}/view/{путь}`: страница файла;
+//! - `/{share_id}/file/{путь}`: сами байты, тот же путь, что у агента.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use ed25519_dalek::VerifyingKey;
+use percent_encoding::percent_decode_str;
+use xr_proto::share::{
+    decode_share_token, parse_agent_pubkey, verify_share_manifest, verify_share_token,
+    ShareManifest, ShareManifestEntry, ShareTokenError, WebShareRoute, MANIFEST_SIGNED_AT_HEADER,
+    MANIFEST_SIG_HEADER, SCOPE_READ,
+};
+
+use crate::app::{now_unix, WebState};
+use crate::hub::HubError;
+use crate::pool::LeasedBody;
+
+/// Cookie с токеном шары. Путь у неё свой на каждую шару, поэтому ссылка на
+/// одну шару не несёт браузеру токены остальных.
+pub const COOKIE_NAME: &str = "xrshare";
+
+/// Запас, на который кеш маршрута шары короче его `exp`, как у публикаций.
+const CACHE_MARGIN_SECS: u64 = 60;
+
+/// Потолок манифеста, который фронт готов собрать в память: список на сотни
+/// тысяч файлов ещё влезает, а бесконечное тело от сломанного агента нет.
+const MAX_MANIFEST_BYTES: usize = 32 << 20;
+
+/// Сколько шар фронт помнит разом. Ссылок на разные шары бывает много, а
+/// маршрут без захода никому не нужен: сверх потолка уходит тот, к кому
+/// дольше всех не обращались.
+const MAX_CACHED_SHARES: usize = 1024;
+
+/// Сколько байт проверенных списков фронт держит в памяти на все шары: один
+/// список до [`MAX_MANIFEST_BYTES`], а держать их по потолку на каждую шару
+/// фронт не станет.
+const MAX_CACHED_MANIFEST_BYTES: usize = 64 << 20;
+
+/// Заголовки условного и частичного запроса: ради них скачивание через
+/// браузер и докачивается, и перематывается.
+const RANGE_HEADERS: [header::HeaderName; 4] = [
+    header::RANGE,
+    header::IF_RANGE,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+];
+
+/// Ключ хаба для офлайн-проверки ссылок и кеш маршрутов шар.
+pub struct SharePages {
+    hub_key: Option<VerifyingKey>,
+    routes: Mutex<RouteCache>,
+}
+
+/// Маршруты шар и их проверенные списки.
+#[derive(Default)]
+struct RouteCache {
+    shares: HashMap<String, Cached>,
+    /// Счётчик обращений: по нему видно, к какой шаре не заходили дольше.
+    tick: u64,
+    /// Сумма `size` закешированных списков.
+    manifest_bytes: usize,
+}
+
+struct Cached {
+    route: Arc<WebShareRoute>,
+    listing: Option<Listing>,
+    used: u64,
+}
+
+/// Список, уже сошедшийся с подписью ключа агента из маршрута.
+#[derive(Clone)]
+struct Listing {
+    version: u64,
+    size: usize,
+    manifest: Arc<ShareManifest>,
+}
+
+impl RouteCache {
+    fn drop_listing(&mut self, share_id: &str) {
+        if let Some(listing) = self.shares.get_mut(share_id).and_then(|c| c.listing.take()) {
+            self.manifest_bytes -= listing.size;
+        }
+    }
+
+    fn remove(&mut self, share_id: &str) {
+        self.drop_listing(share_id);
+        self.shares.remove(share_id);
+    }
+
+    /// Та шара из `candidates`, к которой дольше всех не заходили.
+    fn stalest<'a>(&self, candidates: impl Iterator<Item = &'a String>) -> Option<String> {
+        candidates
+            .min_by_key(|id| self.shares[id.as_str()].used)
+            .cloned()
+    }
+}
+
+impl SharePages {
+    /// Без ключа хаба страниц шар нет: проверить ссылку фронту нечем, а
+    /// пускать по непроверенной он не станет.
+    pub fn disabled() -> Self {
+        Self {
+            hub_key: None,
+            routes: Mutex::default(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RouteCache> {
+        self.routes.lock().expect("share routes lock")
+    }
+
+    pub fn new(hub_key: VerifyingKey) -> Self {
+        Self {
+            hub_key: Some(hub_key),
+            ..Self::disabled()
+        }
+    }
+
+    /// Из `[web] hub_pubkey`: пустая строка выключает страницы, кривой ключ
+    /// это ошибка конфига, а не тихое выключение.
+    pub fn from_config(hub_pubkey: &str) -> anyhow::Result<Self> {
+        if hub_pubkey.trim().is_empty() {
+            return Ok(Self::disabled());
+        }
+        let key = parse_agent_pubkey(hub_pubkey)
+            .map_err(|e| anyhow::anyhow!("[web] hub_pubkey: {e}"))?;
+        Ok(Self::new(key))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.hub_key.is_some()
+    }
+
+    fn fresh(&self, share_id: &str, now: u64) -> Option<Arc<WebShareRoute>> {
+        let mut guard = self.lock();
+        let route = guard.shares.get(share_id)?.route.clone();
+        if route.exp.saturating_sub(CACHE_MARGIN_SECS) <= now {
+            guard.remove(share_id);
+            return None;
+        }
+        guard.tick += 1;
+        let tick = guard.tick;
+        if let Some(cached) = guard.shares.get_mut(share_id) {
+            cached.used = tick;
+        }
+        Some(route)
+    }
+
+    /// Запомнить свежий маршрут. Проверенный список шары переживает смену
+    /// маршрута, если ключ агента тот же: подпись списка держится на нём.
+    /// Протухшие маршруты выметаются здесь же, своего таймера у кеша нет.
+    fn remember(&self, route: Arc<WebShareRoute>, now: u64) {
+        let mut guard = self.lock();
+        let stale: Vec<String> = guard
+            .shares
+            .iter()
+            .filter(|(_, c)| c.route.exp.saturating_sub(CACHE_MARGIN_SECS) <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in stale {
+            guard.remove(&id);
+        }
+        let same_agent = guard
+            .shares
+            .get(&route.share_id)
+            .is_some_and(|c| c.route.agent_pubkey == route.agent_pubkey);
+        if !same_agent {
+            guard.remove(&route.share_id);
+        }
+        while guard.shares.len() >= MAX_CACHED_SHARES && !guard.shares.contains_key(&route.share_id) {
+            let Some(victim) = guard.stalest(guard.shares.keys()) else {
+                break;
+            };
+            guard.remove(&victim);
+        }
+        guard.tick += 1;
+        let used = guard.tick;
+        let listing = guard.shares.remove(&route.share_id).and_then(|c| c.listing);
+        guard.shares.insert(route.share_id.clone(), Cached { route, listing, used });
+    }
+
+    fn forget(&self, share_id: &str) {
+        self.lock().remove(share_id);
+    }
+
+    /// Проверенный список шары, если он получен для этого же ключа агента.
+    fn listing(&self, route: &WebShareRoute) -> Option<Listing> {
+        let guard = self.lock();
+        let cached = guard.shares.get(&route.share_id)?;
+        if cached.route.agent_pubkey != route.agent_pubkey {
+            return None;
+        }
+        cached.listing.clone()
+    }
+
+    /// Положить проверенный список к маршруту шары. Списки шар, к которым
+    /// дольше не заходили, уступают ему место в общем бюджете; список больше
+    /// всего бюджета не кешируется вовсе.
+    fn keep_listing(&self, route: &WebShareRoute, listing: Listing) {
+        if listing.size > MAX_CACHED_MANIFEST_BYTES {
+            return;
+        }
+        let mut guard = self.lock();
+        if guard
+            .shares
+            .get(&route.share_id)
+            .is_none_or(|c| c.route.agent_pubkey != route.agent_pubkey)
+        {
+            return;
+        }
+        guard.drop_listing(&route.share_id);
+        while guard.manifest_bytes + listing.size > MAX_CACHED_MANIFEST_BYTES {
+            let holders: Vec<String> = guard
+                .shares
+                .iter()
+                .filter(|(_, c)| c.listing.is_some())
+                .map(|(id, _)| id.clone())
+                .collect();
+            let Some(victim) = guard.stalest(holders.iter()) else {
+                break;
+            };
+            guard.drop_listing(&victim);
+        }
+        guard.manifest_bytes += listing.size;
+        if let Some(cached) = guard.shares.get_mut(&route.share_id) {
+            cached.listing = Some(listing);
+        }
+    }
+}
+
+/// Запрос на хост шар. Отвечает всегда сам фронт: страница шары это его
+/// страница, до агента доезжают только манифест и байты файлов.
+pub async fn serve(state: &Arc<WebState>, req: Request) -> Response {
+    let Some(hub_key) = state.shares.hub_key.as_ref() else {
+        return page(
+            StatusCode::NOT_FOUND,
+            "Страницы шар выключены",
+            "у этого сервера входа не задан ключ хаба, проверить ссылку на шару нечем",
+        );
+    };
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return page(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Только чтение",
+            "страница шары отдаёт файлы, но не принимает их",
+        );
+    }
+
+    let path = req.uri().path().to_string();
+    let Some((share_id, rest)) = path.strip_prefix('/').and_then(|p| p.split_once('/')) else {
+        let share_id = path.trim_start_matches('/');
+        if valid_share_id(share_id) {
+            // `/<id>` без косой: относительные ссылки каталога считаются от
+            // `/<id>/`, и cookie шары живёт на том же пути.
+            let query = req.uri().query().map(|q| format!("?{q}")).unwrap_or_default();
+            return redirect(&format!("/{share_id}/{query}"), None);
+        }
+        return page(
+            StatusCode::NOT_FOUND,
+            "Нужна ссылка на шару",
+            "откройте ссылку, которую прислал владелец шары: в ней есть и шара, и доступ",
+        );
+    };
+    let (share_id, rest) = (share_id.to_string(), rest.to_string());
+    if !valid_share_id(&share_id) {
+        return page(StatusCode::NOT_FOUND, "Нет такой шары", "адрес не похож на ссылку на шару");
+    }
+
+    // Токен из ссылки проверяется и тут же переезжает в cookie: адрес без
+    // токена можно пересылать, сохранять в закладки и видеть в истории.
+    if let Some(blob) = query_token(req.uri().query()) {
+        return match check(hub_key, &share_id, &blob) {
+            Ok(exp) => {
+                tracing::info!("{}/{share_id}: вход по ссылке", crate::app::SHARE_LABEL);
+                let cookie = set_cookie(&share_id, &blob, exp.saturating_sub(now_unix()));
+                redirect(&path, Some(cookie))
+            }
+            Err((title, detail)) => page(StatusCode::FORBIDDEN, title, detail),
+        };
+    }
+    let Some(blob) = cookie_token(req.headers(), &share_id) else {
+        return page(
+            StatusCode::UNAUTHORIZED,
+            "Нужна ссылка на шару",
+            "доступ к шаре даёт ссылка с токеном от её владельца: откройте её ещё раз",
+        );
+    };
+    if let Err((title, detail)) = check(hub_key, &share_id, &blob) {
+        let mut response = page(StatusCode::FORBIDDEN, title, detail);
+        if let Ok(value) = HeaderValue::from_str(&clear_cookie(&share_id)) {
+            response.headers_mut().insert(header::SET_COOKIE, value);
+        }
+        return response;
+    }
+
+    let route = match route_of(state, &share_id, &blob).await {
+        Ok(route) => route,
+        Err(response) => return response,
+    };
+
+    if rest.is_empty() {
+        return listing(state, &route, &blob, "").await;
+    }
+    if let Some(dir) = rest.strip_prefix("ls/") {
+        return listing(state, &route, &blob, decode(dir).trim_end_matches('/')).await;
+    }
+    if let Some(file) = rest.strip_prefix("view/") {
+        return file_page(state, &route, &blob, &decode(file)).await;
+    }
+    if let Some(raw) = rest.strip_prefix("file/") {
+        return download(state, &route, &blob, raw, req).await;
+    }
+    page(StatusCode::NOT_FOUND, "Нет такой страницы", "у страницы шары нет такого адреса")
+}
+
+/// Годится ли строка в `share_id`: хаб выдаёт base64url от 16 случайных байт,
+/// но проверка шире, чтобы шары старых регистраций тоже открывались.
+fn valid_share_id(id: &str) -> bool {
+    (1..=64).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Проверить токен ссылки офлайн. `Ok` несёт срок токена: столько же живёт и
+/// cookie с ним; `Err` это заголовок и причина страницы отказа `403`.
+fn check(hub_key: &VerifyingKey, share_id: &str, blob: &str) -> Result<u64, (&'static str, &'static str)> {
+    const REFUSED: &str = "Ссылка недействительна";
+    let Some(token) = decode_share_token(blob) else {
+        return Err((REFUSED, "токен в ссылке не разобрался: скопируйте ссылку целиком"));
+    };
+    match verify_share_token(&token, hub_key, share_id, SCOPE_READ, now_unix()) {
+        Ok(()) => Ok(token.exp),
+        Err(ShareTokenError::Expired) => Err((
+            "Ссылка истекла",
+            "срок ссылки вышел: попросите у владельца шары новую",
+        )),
+        Err(ShareTokenError::WrongShare) => Err((REFUSED, "ссылка выдана на другую шару")),
+        Err(e) => {
+            tracing::warn!("{}/{share_id}: ссылка отвергнута ({e})", crate::app::SHARE_LABEL);
+            Err((REFUSED, "подпись ссылки не сошлась с ключом хаба"))
+        }
+    }
+}
+
+/// Маршрут шары: из кеша, пока он свеж, иначе у хаба.
+async fn route_of(state: &WebState, share_id: &str, blob: &str) -> Result<Arc<WebShareRoute>, Response> {
+    let now = now_unix();
+    if let Some(route) = state.shares.fresh(share_id, now) {
+        return Ok(route);
+    }
+    match state.hub.share_route(share_id.to_string(), blob.to_string()).await {
+        Ok(route) => {
+            let route = Arc::new(route);
+            state.shares.remember(route.clone(), now);
+            Ok(route)
+        }
+        Err(HubError::NotFound) => Err(page(
+            StatusCode::NOT_FOUND,
+            "Шара не найдена",
+            "хаб не знает такой шары: скорее всего владелец её снял",
+        )),
+        Err(e) => {
+            tracing::warn!("маршрут шары {share_id} не получен: {e}");
+            Err(page(StatusCode::BAD_GATEWAY, "Маршрут не получен", &e.to_string()))
+        }
+    }
+}
+
+/// Запрос к агенту шары. Токен едет как `Bearer`: агент проверяет его сам,
+/// фронт ему только посредник.
+async fn send(
+    state: &WebState,
+    route: &Arc<WebShareRoute>,
+    blob: &str,
+    method: Method,
+    uri: &str,
+    extra: &HeaderMap,
+) -> Result<(hyper::Response<hyper::body::Incoming>, crate::pool::Lease), Response> {
+    let mut lease = match state.pool.checkout(route.clone()).await {
+        Ok(lease) => lease,
+        Err(e) => {
+            let offline = e.to_string() == xr_proto::relay_client::RELAY_ERR_AGENT_OFFLINE;
+            tracing::warn!("туннель шары {}: {e}", route.share_id);
+            return Err(if offline {
+                page(
+                    StatusCode::BAD_GATEWAY,
+                    "Машина не на связи",
+                    "компьютер с этой шарой сейчас не подключён: попробуйте позже",
+                )
+            } else {
+                page(StatusCode::BAD_GATEWAY, "Канал до машины не поднялся", &e.to_string())
+            });
+        }
+    };
+    let mut builder = hyper::Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::HOST, format!("{}.{}", crate::app::SHARE_LABEL, state.domain))
+        .header(header::AUTHORIZATION, format!("Bearer {blob}"));
+    for (name, value) in extra {
+        builder = builder.header(name, value);
+    }
+    let Ok(req) = builder.body(Body::empty()) else {
+        return Err(page(StatusCode::BAD_REQUEST, "Кривой адрес", "путь файла не собрался в запрос"));
+    };
+    match lease.sender().send_request(req).await {
+        Ok(resp) => Ok((resp, lease)),
+        Err(e) => {
+            tracing::warn!("обрыв туннеля шары {}: {e}", route.share_id);
+            Err(page(
+                StatusCode::BAD_GATEWAY,
+                "Соединение с машиной оборвалось",
+                &format!("запрос до агента не доехал: {e}"),
+            ))
+        }
+    }
+}
+
+/// Отказ агента по статусу: токен, который фронт признал, агент может не
+/// признать (другой ключ хаба в его конфиге), и это надо назвать. Маршрут
+/// после отказа забывается, как мандат публикации: следующий заход возьмёт у
+/// хаба свежий.
+fn agent_refusal(state: &WebState, route: &WebShareRoute, status: StatusCode) -> Response {
+    if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND) {
+        state.shares.forget(&route.share_id);
+    }
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => page(
+            StatusCode::FORBIDDEN,
+            "Агент не принял ссылку",
+            "машина с шарой отвергла токен: попросите у владельца новую ссылку",
+        ),
+        StatusCode::NOT_FOUND => page(
+            StatusCode::NOT_FOUND,
+            "Шара не раздаётся",
+            &format!("агент сейчас не раздаёт шару «{}»", route.name),
+        ),
+        other => page(
+            StatusCode::BAD_GATEWAY,
+            "Агент ответил ошибкой",
+            &format!("машина с шарой ответила {other}"),
+        ),
+    }
+}
+
+/// Манифест шары с проверенной подписью. Без подписи список не показывается:
+/// фронт верит ключу агента, а не тому, кто ответил на его месте. Уже
+/// проверенный список переспрашивается ожиданием с нулевым удержанием: `304`
+/// значит версия шары та же и годится он.
+async fn manifest(state: &WebState, route: &Arc<WebShareRoute>, blob: &str) -> Result<Arc<ShareManifest>, Response> {
+    let known = state.shares.listing(route);
+    let uri = match &known {
+        Some(listing) => format!("/{}/manifest/wait?since={}&timeout_secs=0", route.share_id, listing.version),
+        None => format!("/{}/manifest", route.share_id),
+    };
+    let (resp, lease) = send(state, route, blob, Method::GET, &uri, &HeaderMap::new()).await?;
+    if let (Some(listing), StatusCode::NOT_MODIFIED) = (&known, resp.status()) {
+        // У `304` тела нет: соединение сразу годится пулу.
+        drop((resp, lease));
+        return Ok(listing.manifest.clone());
+    }
+    if resp.status() != StatusCode::OK {
+        return Err(agent_refusal(state, route, resp.status()));
+    }
+    let header_str = |name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let sig = header_str(MANIFEST_SIG_HEADER);
+    let signed_at = header_str(MANIFEST_SIGNED_AT_HEADER).and_then(|v| v.trim().parse::<u64>().ok());
+    let body = Body::new(LeasedBody::new(resp.into_body(), lease));
+    let bytes = axum::body::to_bytes(body, MAX_MANIFEST_BYTES).await.map_err(|e| {
+        page(StatusCode::BAD_GATEWAY, "Список шары не дочитан", &e.to_string())
+    })?;
+
+    let forged = |why: String| {
+        tracing::warn!("манифест шары {} отвергнут: {why}", route.share_id);
+        page(
+            StatusCode::BAD_GATEWAY,
+            "Список шары не прошёл проверку",
+            "подпись списка файлов не сошлась с ключом машины, показывать его нельзя",
+        )
+    };
+    let (Some(sig), Some(signed_at)) = (sig, signed_at) else {
+        return Err(forged("агент не подписал манифест".into()));
+    };
+    let agent_key = parse_agent_pubkey(&route.agent_pubkey).map_err(|e| forged(e.to_string()))?;
+    verify_share_manifest(&sig, &agent_key, &route.share_id, signed_at, &bytes)
+        .map_err(|e| forged(e.to_string()))?;
+    let manifest: ShareManifest =
+        serde_json::from_slice(&bytes).map_err(|e| forged(format!("манифест не разобрался: {e}")))?;
+    // Версия берётся из подписанного тела, а не из заголовка рядом.
+    let manifest = Arc::new(manifest);
+    let listing = Listing { version: manifest.version, size: bytes.len(), manifest: manifest.clone() };
+    state.shares.keep_listing(route, listing);
+    Ok(manifest)
+}
+
+async fn listing(state: &WebState, route: &Arc<WebShareRoute>, blob: &str, dir: &str) -> Response {
+    let manifest = match manifest(state, route, blob).await {
+        Ok(m) => m,
+        Err(response) => return response,
+    };
+    let (dirs, files) = children(&manifest, dir);
+    if !dir.is_empty() && dirs.is_empty() && files.is_empty() {
+        return page(StatusCode::NOT_FOUND, "Нет такой папки", &format!("в шаре нет папки {dir}"));
+    }
+    html(crate::pages::share_listing(&route.name, &route.share_id, dir, &dirs, &files))
+}
+
+async fn file_page(state: &WebState, route: &Arc<WebShareRoute>, blob: &str, path: &str) -> Response {
+    let manifest = match manifest(state, route, blob).await {
+        Ok(m) => m,
+        Err(response) => return response,
+    };
+    match manifest.entries.iter().find(|e| e.path == path) {
+        Some(entry) => html(crate::pages::share_file(&route.name, &route.share_id, entry)),
+        None => page(StatusCode::NOT_FOUND, "Нет такого файла", &format!("в шаре нет файла {path}")),
+    }
+}
+
+/// Байты файла потоком. Путь уезжает агенту в том же кодировании, в каком
+/// пришёл, а условные заголовки браузера вместе с ним: ответы `206`, `304` и
+/// `416` делает агент.
+async fn download(
+    state: &WebState,
+    route: &Arc<WebShareRoute>,
+    blob: &str,
+    raw_path: &str,
+    req: Request,
+) -> Response {
+    let mut extra = HeaderMap::new();
+    for name in RANGE_HEADERS {
+        if let Some(value) = req.headers().get(&name) {
+            extra.insert(name, value.clone());
+        }
+    }
+    let uri = format!("/{}/file/{raw_path}", route.share_id);
+    let (resp, lease) = match send(state, route, blob, req.method().clone(), &uri, &extra).await {
+        Ok(sent) => sent,
+        Err(response) => return response,
+    };
+    let status = resp.status();
+    if !(status.is_success() || status == StatusCode::NOT_MODIFIED || status == StatusCode::RANGE_NOT_SATISFIABLE) {
+        if status == StatusCode::NOT_FOUND {
+            return page(StatusCode::NOT_FOUND, "Нет такого файла", "агент не нашёл файл в шаре");
+        }
+        return agent_refusal(state, route, status);
+    }
+    let (mut parts, body) = resp.into_parts();
+    for name in crate::app::HOP_BY_HOP {
+        parts.headers.remove(name);
+    }
+    // Файл шары чужой для этого хоста: HTML из шары, открытый на месте, иначе
+    // выполнился бы в общем origin страниц шар и дотянулся бы до соседних.
+    parts.headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+    parts.headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    Response::from_parts(parts, Body::new(LeasedBody::new(body, lease)))
+}
+
+/// Что лежит прямо в каталоге `dir`: подкаталоги по первому сегменту и файлы.
+/// Каталогов в манифесте нет, они выводятся из путей файлов.
+fn children<'a>(manifest: &'a ShareManifest, dir: &str) -> (Vec<String>, Vec<&'a ShareManifestEntry>) {
+    let prefix = if dir.is_empty() { String::new() } else { format!("{dir}/") };
+    let mut dirs = BTreeSet::new();
+    let mut files = Vec::new();
+    for entry in &manifest.entries {
+        let Some(rest) = entry.path.strip_prefix(&prefix) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            Some((sub, _)) => {
+                dirs.insert(sub.to_string());
+            }
+            None => files.push(entry),
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    (dirs.into_iter().collect(), files)
+}
+
+fn decode(raw: &str) -> String {
+    percent_decode_str(raw).decode_utf8_lossy().into_owned()
+}
+
+fn query_token(query: Option<&str>) -> Option<String> {
+    query?
+        .split('&')
+        .find_map(|kv| kv.strip_prefix("token="))
+        .map(str::to_string)
+        .filter(|t| !t.is_empty())
+}
+
+/// Токен этой шары из cookie. Браузер шлёт только cookie с подходящим путём,
+/// но проверяется всё равно `share_id`: мусор в чужой cookie тут не помеха.
+fn cookie_token(headers: &HeaderMap, share_id: &str) -> Option<String> {
+    let raw = headers.get(header::COOKIE)?.to_str().ok()?;
+    crate::session::cookie_pairs(raw)
+        .filter(|(name, _)| *name == COOKIE_NAME)
+        .filter_map(|(_, value)| value)
+        .find(|value| decode_share_token(value).is_some_and(|t| t.share_id == share_id))
+        .map(str::to_string)
+}
+
+/// `Path` на шару, `HttpOnly` и `SameSite=Lax`, как у сессии публикации.
+fn set_cookie(share_id: &str, blob: &str, max_age: u64) -> String {
+    format!("{COOKIE_NAME}={blob}; HttpOnly; Secure; SameSite=Lax; Path=/{share_id}/; Max-Age={max_age}")
+}
+
+fn clear_cookie(share_id: &str) -> String {
+    set_cookie(share_id, "", 0)
+}
+
+fn redirect(location: &str, cookie: Option<String>) -> Response {
+    let mut response = (StatusCode::SEE_OTHER, [(header::LOCATION, location.to_string())]).into_response();
+    if let Some(value) = cookie.and_then(|c| HeaderValue::from_str(&c).ok()) {
+        response.headers_mut().insert(header::SET_COOKIE, value);
+    }
+    response
+}
+
+fn html(body: String) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+fn page(status: StatusCode, title: &str, detail: &str) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        crate::pages::failure(title, detail),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{router, stand, Stand};
+    use crate::test_support::{
+        hub_key, share_link_token, DuplexDialer, HubMode, SHARE_BYTES, SHARE_ID,
+    };
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    const HOST: &str = "s.web.example.com";
+
+    /// Стенд со страницами шар: ключ хаба тот же, которым стенд-хаб подписывает
+    /// ссылки.
+    fn share_stand(dialer: DuplexDialer) -> Stand {
+        let s = stand(HubMode::Route(now_unix() + 3600), dialer);
+        let state = Arc::try_unwrap(s.state)
+            .unwrap_or_else(|_| panic!("стенд ещё ни с кем не делится состоянием"))
+            .with_share_pages(SharePages::new(hub_key().verifying_key()));
+        Stand {
+            state: Arc::new(state),
+            ..s
+        }
+    }
+
+    async fn call(state: &Arc<WebState>, path: &str, cookie: Option<&str>, range: Option<&str>) -> Response {
+        let mut req = Request::builder().uri(path).header(header::HOST, HOST);
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, cookie);
+        }
+        if let Some(range) = range {
+            req = req.header(header::RANGE, range);
+        }
+        router(state.clone())
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .expect("роутер не падает")
+    }
+
+    async fn text(resp: Response) -> String {
+        let bytes = axum::body::to_bytes(resp.into_body(), 1 << 22).await.expect("тело");
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Войти по ссылке и вернуть cookie шары в виде для заголовка `Cookie`.
+    async fn open_link(state: &Arc<WebState>) -> String {
+        let blob = share_link_token(SHARE_ID, now_unix() + 600);
+        let resp = call(state, &format!("/{SHARE_ID}/?token={blob}"), None, None).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert_eq!(resp.headers()[header::LOCATION], format!("/{SHARE_ID}/"), "токен уходит из адреса");
+        let cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+        assert!(cookie.contains(&format!("Path=/{SHARE_ID}/")), "{cookie}");
+        cookie.split(';').next().unwrap().to_string()
+    }
+
+    fn entry(path: &str) -> ShareManifestEntry {
+        ShareManifestEntry {
+            path: path.into(),
+            size: 1,
+            mtime: 0,
+            sha256: String::new(),
+            meta: None,
+            preview: None,
+        }
+    }
+
+    #[test]
+    fn directories_come_from_file_paths() {
+        let manifest = ShareManifest {
+            entries: vec![
+                entry("b.txt"),
+                entry("docs/a.md"),
+                entry("docs/img/x.png"),
+                entry("a.txt"),
+                entry("music/y.mp3"),
+            ],
+            version: 0,
+        };
+        let (dirs, files) = children(&manifest, "");
+        assert_eq!(dirs, vec!["docs", "music"]);
+        assert_eq!(files.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), vec!["a.txt", "b.txt"]);
+
+        let (dirs, files) = children(&manifest, "docs");
+        assert_eq!(dirs, vec!["img"]);
+        assert_eq!(files.len(), 1);
+        // Префикс сравнивается по сегменту: `doc` не каталог `docs`.
+        let (dirs, files) = children(&manifest, "doc");
+        assert!(dirs.is_empty() && files.is_empty());
+    }
+
+    #[test]
+    fn share_cookie_is_scoped_to_its_share() {
+        let cookie = set_cookie("abc", "blob", 60);
+        assert!(cookie.contains("Path=/abc/"), "{cookie}");
+        assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Lax"));
+        assert!(!cookie.contains("Domain"), "cookie host-only: {cookie}");
+        assert!(clear_cookie("abc").contains("Max-Age=0"));
+    }
+
+    #[tokio::test]
+    async fn link_opens_a_verified_listing_and_streams_ranges() {
+        let s = share_stand(DuplexDialer::serving());
+        let cookie = open_link(&s.state).await;
+
+        let resp = call(&s.state, &format!("/{SHARE_ID}/"), Some(&cookie), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let page = text(resp).await;
+        assert!(page.contains("Отпуск") && page.contains(&format!("/{SHARE_ID}/ls/docs")), "{page}");
+
+        let resp = call(&s.state, &format!("/{SHARE_ID}/ls/docs"), Some(&cookie), None).await;
+        assert!(text(resp).await.contains(&format!("/{SHARE_ID}/view/docs/a%20b.txt")));
+
+        let resp = call(&s.state, &format!("/{SHARE_ID}/view/docs/a%20b.txt"), Some(&cookie), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(text(resp).await.contains("Скачать"));
+
+        let resp = call(&s.state, &format!("/{SHARE_ID}/file/docs/a%20b.txt"), Some(&cookie), Some("bytes=2-5")).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()[header::CONTENT_SECURITY_POLICY], "sandbox");
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], format!("bytes 2-5/{}", SHARE_BYTES.len()));
+        assert_eq!(text(resp).await.as_bytes(), &SHARE_BYTES[2..=5]);
+
+        // Маршрут берётся у хаба один раз и дальше живёт в кеше, а список
+        // агент отдал телом тоже один раз: дальше ему хватало `304`.
+        assert_eq!(s.hub.share_route_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(s.dialer.manifest_bodies.load(Ordering::SeqCst), 1);
+    }
+
+    fn route(share_id: &str, agent: &str, exp: u64) -> Arc<WebShareRoute> {
+        Arc::new(WebShareRoute {
+            share_id: share_id.into(),
+            agent_pubkey: agent.into(),
+            exp,
+            ..crate::test_support::share_route_for(SHARE_ID, exp)
+        })
+    }
+
+    fn listing(size: usize) -> Listing {
+        Listing {
+            version: 1,
+            size,
+            manifest: Arc::new(ShareManifest { entries: Vec::new(), version: 1 }),
+        }
+    }
+
+    #[test]
+    fn route_cache_is_bounded() {
+        let pages = SharePages::new(hub_key().verifying_key());
+        let now = 1_000_000;
+        // Протухший выметается первым же новым маршрутом.
+        pages.remember(route("old", "a", now + 10), now);
+        pages.remember(route("s0", "a", now + 3600), now);
+        assert!(pages.fresh("old", now).is_none());
+        for i in 1..=MAX_CACHED_SHARES {
+            pages.remember(route(&format!("s{i}"), "a", now + 3600), now);
+        }
+        let shares = pages.lock().shares.len();
+        assert_eq!(shares, MAX_CACHED_SHARES, "сверх потолка уходит давний");
+        assert!(pages.fresh("s0", now).is_none());
+
+        // Бюджет списков: новый вытесняет давний, больше бюджета не берётся.
+        let half = MAX_CACHED_MANIFEST_BYTES / 2 + 1;
+        pages.keep_listing(&route("s1", "a", now + 3600), listing(half));
+        pages.keep_listing(&route("s2", "a", now + 3600), listing(half));
+        assert!(pages.listing(&route("s1", "a", now + 3600)).is_none());
+        assert!(pages.listing(&route("s2", "a", now + 3600)).is_some());
+        assert_eq!(pages.lock().manifest_bytes, half);
+        pages.keep_listing(&route("s3", "a", now + 3600), listing(MAX_CACHED_MANIFEST_BYTES + 1));
+        assert!(pages.listing(&route("s3", "a", now + 3600)).is_none());
+
+        // Другой ключ агента: список к нему не подходит и с новым маршрутом уходит.
+        assert!(pages.listing(&route("s2", "b", now + 3600)).is_none());
+        pages.remember(route("s2", "b", now + 3600), now);
+        assert_eq!(pages.lock().manifest_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn without_a_valid_link_nothing_reaches_the_hub() {
+        let s = share_stand(DuplexDialer::serving());
+        let resp = call(&s.state, &format!("/{SHARE_ID}/"), None, None).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let expired = share_link_token(SHARE_ID, now_unix() - 1);
+        let resp = call(&s.state, &format!("/{SHARE_ID}/?token={expired}"), None, None).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(text(resp).await.contains("истекла"));
+
+        // Ссылка на одну шару не открывает другую.
+        let other = share_link_token("ZHJ1Z2F5YS1zaGFyYQ", now_unix() + 600);
+        let resp = call(&s.state, &format!("/{SHARE_ID}/?token={other}"), None, None).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = call(&s.state, &format!("/{SHARE_ID}/?token=garbage"), None, None).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(s.hub.share_route_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn forged_manifest_is_not_shown() {
+        let s = share_stand(DuplexDialer::forging_manifest());
+        let cookie = open_link(&s.state).await;
+        let resp = call(&s.state, &format!("/{SHARE_ID}/"), Some(&cookie), None).await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        let page = text(resp).await;
+        assert!(page.contains("не прошёл проверку") && !page.contains("a b.txt"), "{page}");
+    }
+
+    #[tokio::test]
+    async fn agent_refusal_drops_the_cached_share_route() {
+        let s = share_stand(DuplexDialer::refusing_mandate());
+        let cookie = open_link(&s.state).await;
+        for _ in 0..2 {
+            let resp = call(&s.state, &format!("/{SHARE_ID}/"), Some(&cookie), None).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(s.hub.share_route_calls.load(Ordering::SeqCst), 2, "маршрут забыт после отказа");
+    }
+
+    #[tokio::test]
+    async fn without_hub_key_share_pages_are_off() {
+        let s = stand(HubMode::Route(now_unix() + 3600), DuplexDialer::serving());
+        let blob = share_link_token(SHARE_ID, now_unix() + 600);
+        let resp = call(&s.state, &format!("/{SHARE_ID}/?token={blob}"), None, None).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(text(resp).await.contains("выключены"));
+        assert!(SharePages::from_config("").unwrap().hub_key.is_none());
+        assert!(SharePages::from_config("не ключ").is_err());
+    }
+}
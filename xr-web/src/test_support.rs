@@ -15,11 +15,14 @@ use ed25519_dalek::SigningKey;
 use hyper::body::{Bytes, Incoming};
 use xr_proto::relay_client::RELAY_ERR_AGENT_OFFLINE;
 use xr_proto::share::{
-    sign_expose_token, sign_relay_token, web_share_id, RelayDescriptor, RelayObf, WebRoute,
+    sign_expose_token, sign_relay_token, sign_share_manifest, sign_share_token, web_share_id,
+    RelayDescriptor, RelayObf, ShareManifest, ShareManifestEntry, WebAccessKind, WebRoute,
+    WebSessionsReport, WebSessionsVerdict, WebShareRoute, EXPOSE_HEADER, MANIFEST_SIGNED_AT_HEADER,
+    MANIFEST_SIG_HEADER, SCOPE_READ,
 };
 
-use crate::hub::{HubApi, HubError};
-use crate::pool::{AgentIo, Dialer};
+use crate::hub::{AccessPass, Credentials, GuestPass, HubApi, HubError};
+use crate::pool::{AgentIo, AgentRoute, Dialer};
 
 type Boxed<T> = Pin<Box<dyn Future<Output = T> + Send>>;
 
@@ -36,6 +39,37 @@ pub fn agent_pubkey() -> String {
         .encode(SigningKey::from_bytes(&[7u8; 32]).verifying_key().as_bytes())
 }
 
+/// Шара стенда и то, что в ней лежит: файл `docs/a b.txt` с этим телом.
+pub const SHARE_ID: &str = "c2hhcmUtc3RhbmQ";
+pub const SHARE_FILE: &str = "docs/a b.txt";
+pub const SHARE_BYTES: &[u8] = b"0123456789abcdef";
+/// Версия манифеста шары стенда.
+pub const SHARE_VERSION: u64 = 3;
+
+/// Ссылка на шару стенда в той форме, в какой её минтит хаб: base64url блоб
+/// токена, подписанного ключом хаба.
+pub fn share_link_token(share_id: &str, exp: u64) -> String {
+    use base64::Engine;
+    let token = sign_share_token(&hub_key(), share_id, SCOPE_READ, exp);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&token).expect("токен"))
+}
+
+/// Маршрут шары, как его отдал бы хаб.
+pub fn share_route_for(share_id: &str, exp: u64) -> WebShareRoute {
+    let route = route_for("share", exp);
+    let agent = agent_pubkey();
+    WebShareRoute {
+        share_id: share_id.to_string(),
+        name: "Отпуск".into(),
+        agent_pubkey: agent.clone(),
+        relay: route.relay,
+        backup_relays: Vec::new(),
+        relay_token: sign_relay_token(&hub_key(), share_id, &agent, exp),
+        exp,
+        splice_lifetime_secs: 3600,
+    }
+}
+
 /// Маршрут публикации, как его отдал бы хаб.
 pub fn route_for(publication: &str, exp: u64) -> WebRoute {
     route_with_cap(publication, exp, 3600)
@@ -59,14 +93,20 @@ pub fn route_with_cap(publication: &str, exp: u64, splice_lifetime_secs: u64) ->
                 padding_min: 16,
                 padding_max: 128,
             },
+            punch: false,
         },
+        backup_relays: Vec::new(),
         relay_token: sign_relay_token(&hub_key(), &web_share_id(publication), &agent, exp),
         expose_token: sign_expose_token(&hub_key(), publication, &agent, exp),
         exp,
         splice_lifetime_secs,
+        access: WebAccessKind::Owner,
     }
 }
 
+/// Метка сессий по инвайту стенда.
+pub const INVITE_GRANT: &str = "invite:0123456789abcdef";
+
 /// Что стенд-хаб отвечает на запрос маршрута.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum HubMode {
@@ -78,16 +118,32 @@ pub enum HubMode {
     Down,
 }
 
-/// Хаб стенда: считает вызовы обеих ручек и отвечает назначенным вердиктом.
+/// Хаб стенда: считает вызовы ручек и отвечает назначенным вердиктом. Политику
+/// входа он проверяет так же, как хаб: учётка `owner` с паролем `password`,
+/// пароль публикации тот же `password`, инвайт это `invite`.
 pub struct FakeHub {
     pub mode: HubMode,
     pub password: String,
     pub route_calls: AtomicUsize,
+    pub share_route_calls: AtomicUsize,
     pub verify_calls: AtomicUsize,
     pub hub_down_for_password: bool,
+    /// Политика входа публикаций стенда.
+    pub access: WebAccessKind,
+    pub invite: String,
+    /// Срок инвайта стенда, unix-секунды: потолок сессии по нему.
+    pub invite_until: u64,
+    /// Живые гостевые ссылки: токен -> что хаб о ней скажет.
+    pub guests: std::sync::Mutex<std::collections::HashMap<String, GuestPass>>,
     /// Потолок жизни сплайса в маршруте: тест штатного закрытия ставит свой,
     /// как проверка ставит его конфигом relay.
     pub splice_lifetime_secs: u64,
+    /// Что хаб отвечает на доклад сессий: «выйти везде» по публикациям.
+    pub logout_before: std::sync::Mutex<std::collections::HashMap<String, u64>>,
+    /// Отозванные гостевые ссылки в ответе на доклад.
+    pub revoked_guests: std::sync::Mutex<Vec<String>>,
+    /// Последний доклад сессий, как его увидел хаб.
+    pub reported: std::sync::Mutex<Option<WebSessionsReport>>,
 }
 
 impl FakeHub {
@@ -96,9 +152,17 @@ impl FakeHub {
             mode,
             password: "открой".into(),
             route_calls: AtomicUsize::new(0),
+            share_route_calls: AtomicUsize::new(0),
             verify_calls: AtomicUsize::new(0),
             hub_down_for_password: false,
+            access: WebAccessKind::Owner,
+            invite: "инвайт-код".into(),
+            invite_until: 0,
+            guests: Default::default(),
             splice_lifetime_secs: 3600,
+            logout_before: Default::default(),
+            revoked_guests: Default::default(),
+            reported: Default::default(),
         }
     }
 }
@@ -108,24 +172,72 @@ impl HubApi for FakeHub {
         self.route_calls.fetch_add(1, Ordering::SeqCst);
         let mode = self.mode;
         let cap = self.splice_lifetime_secs;
+        let access = self.access;
         Box::pin(async move {
             match mode {
-                HubMode::Route(exp) => Ok(route_with_cap(&publication, exp, cap)),
+                HubMode::Route(exp) => Ok(WebRoute {
+                    access,
+                    ..route_with_cap(&publication, exp, cap)
+                }),
                 HubMode::Missing => Err(HubError::NotFound),
                 HubMode::Down => Err(HubError::Unavailable("хаб не ответил: connect refused".into())),
             }
         })
     }
 
-    fn verify_password(&self, _username: String, password: String) -> Boxed<Result<bool, HubError>> {
+    fn share_route(&self, share_id: String, _token: String) -> Boxed<Result<WebShareRoute, HubError>> {
+        self.share_route_calls.fetch_add(1, Ordering::SeqCst);
+        let mode = self.mode;
+        Box::pin(async move {
+            match mode {
+                HubMode::Route(exp) if share_id == SHARE_ID => Ok(share_route_for(&share_id, exp)),
+                HubMode::Route(_) | HubMode::Missing => Err(HubError::NotFound),
+                HubMode::Down => Err(HubError::Unavailable("хаб не ответил: connect refused".into())),
+            }
+        })
+    }
+
+    fn verify_access(&self, _publication: String, creds: Credentials) -> Boxed<Result<Option<AccessPass>, HubError>> {
         self.verify_calls.fetch_add(1, Ordering::SeqCst);
-        let expected = self.password.clone();
         let down = self.hub_down_for_password;
+        let pass = |who: &str| AccessPass { who: who.to_string(), grant: String::new(), until: 0 };
+        let verdict = match self.access {
+            WebAccessKind::Public => Some(pass("публичный вход")),
+            WebAccessKind::Owner | WebAccessKind::Users => {
+                (creds.username == "owner" && creds.password == self.password).then(|| pass(&creds.username))
+            }
+            WebAccessKind::Password => (creds.password == self.password).then(|| pass("пароль публикации")),
+            WebAccessKind::Invites => (creds.invite == self.invite).then(|| AccessPass {
+                grant: INVITE_GRANT.into(),
+                until: self.invite_until,
+                ..pass("инвайт")
+            }),
+        };
         Box::pin(async move {
             if down {
                 return Err(HubError::Unavailable("хаб не ответил: connect refused".into()));
             }
-            Ok(password == expected)
+            Ok(verdict)
+        })
+    }
+
+    fn redeem_guest(&self, _publication: String, token: String) -> Boxed<Result<Option<GuestPass>, HubError>> {
+        let pass = self.guests.lock().expect("guests lock").get(&token).cloned();
+        Box::pin(async move { Ok(pass) })
+    }
+
+    fn report_sessions(&self, report: WebSessionsReport) -> Boxed<Result<WebSessionsVerdict, HubError>> {
+        *self.reported.lock().expect("reported lock") = Some(report);
+        let verdict = WebSessionsVerdict {
+            logout_before: self.logout_before.lock().expect("logout lock").clone(),
+            revoked_guests: self.revoked_guests.lock().expect("revoked lock").clone(),
+        };
+        let down = self.mode == HubMode::Down;
+        Box::pin(async move {
+            if down {
+                return Err(HubError::Unavailable("хаб не ответил: connect refused".into()));
+            }
+            Ok(verdict)
         })
     }
 }
@@ -138,9 +250,18 @@ pub struct DuplexDialer {
     /// Сколько апгрейдов агент увидел закрытыми со стороны фронта: так тест
     /// судит, доехало ли до агента закрытие браузера.
     pub ws_closed: Arc<AtomicUsize>,
+    /// Сколько раз агент отдал статику `/static/` телом и сколько `304`: по
+    /// ним судится, что кеш фронта сберёг аплинк агента.
+    pub asset_bodies: Arc<AtomicUsize>,
+    pub asset_not_modified: Arc<AtomicUsize>,
+    /// Сколько раз агент отдал манифест шары телом: по нему судится, что
+    /// фронт не переспрашивает список, пока шара не менялась.
+    pub manifest_bodies: Arc<AtomicUsize>,
     offline: bool,
     /// Отвечать `403`, как агент с отвергнутым мандатом.
     refuse_mandate: bool,
+    /// Подписывать манифест шары чужим ключом, как подменённый агент.
+    forge_manifest: bool,
     /// Таски агентских половинок: тест гасит их, когда ему нужно оборвать
     /// транзит под уже открытым соединением.
     agents: Arc<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>>,
@@ -151,8 +272,12 @@ impl DuplexDialer {
         Self {
             dials: AtomicUsize::new(0),
             ws_closed: Arc::default(),
+            asset_bodies: Arc::default(),
+            asset_not_modified: Arc::default(),
+            manifest_bodies: Arc::default(),
             offline: false,
             refuse_mandate: false,
+            forge_manifest: false,
             agents: Arc::default(),
         }
     }
@@ -172,8 +297,12 @@ impl DuplexDialer {
         Self {
             dials: AtomicUsize::new(0),
             ws_closed: Arc::default(),
+            asset_bodies: Arc::default(),
+            asset_not_modified: Arc::default(),
+            manifest_bodies: Arc::default(),
             offline: true,
             refuse_mandate: false,
+            forge_manifest: false,
             agents: Arc::default(),
         }
     }
@@ -182,15 +311,26 @@ impl DuplexDialer {
         Self {
             dials: AtomicUsize::new(0),
             ws_closed: Arc::default(),
+            asset_bodies: Arc::default(),
+            asset_not_modified: Arc::default(),
+            manifest_bodies: Arc::default(),
             offline: false,
             refuse_mandate: true,
+            forge_manifest: false,
             agents: Arc::default(),
         }
     }
+
+    pub fn forging_manifest() -> Self {
+        Self {
+            forge_manifest: true,
+            ..Self::serving()
+        }
+    }
 }
 
 impl Dialer for DuplexDialer {
-    fn connect(&self, _route: Arc<WebRoute>) -> Boxed<io::Result<Box<dyn AgentIo>>> {
+    fn connect(&self, _route: Arc<dyn AgentRoute>) -> Boxed<io::Result<Box<dyn AgentIo>>> {
         self.dials.fetch_add(1, Ordering::SeqCst);
         if self.offline {
             return Box::pin(async {
@@ -201,15 +341,21 @@ impl Dialer for DuplexDialer {
             });
         }
         let refuse = self.refuse_mandate;
+        let forge = self.forge_manifest;
+        let manifests = self.manifest_bodies.clone();
         let agents = self.agents.clone();
         let ws_closed = self.ws_closed.clone();
+        let assets = (self.asset_bodies.clone(), self.asset_not_modified.clone());
         Box::pin(async move {
             let (ours, theirs) = tokio::io::duplex(64 * 1024);
             let task = tokio::spawn(async move {
                 let service = hyper::service::service_fn(move |req: hyper::Request<Incoming>| {
                     let ws_closed = ws_closed.clone();
+                    let assets = assets.clone();
+                    let manifests = manifests.clone();
                     async move {
-                        Ok::<_, std::convert::Infallible>(handle(req, refuse, ws_closed).await)
+                        let reply = handle(req, refuse, forge, manifests, ws_closed, assets).await;
+                        Ok::<_, std::convert::Infallible>(reply)
                     }
                 });
                 let _ = hyper::server::conn::http1::Builder::new()
@@ -224,18 +370,144 @@ impl Dialer for DuplexDialer {
 }
 
 /// Что агент стенда делает с запросом: апгрейд он принимает и эхом гоняет
-/// байты, обычный запрос отдаёт эхом заголовков.
+/// байты, запрос к шаре стенда без заголовка публикации обслуживает как
+/// роутер шары, `/static/` отдаёт статикой с валидатором, остальное эхом
+/// заголовков.
 async fn handle(
     req: hyper::Request<Incoming>,
     refuse_mandate: bool,
+    forge_manifest: bool,
+    manifests: Arc<AtomicUsize>,
     ws_closed: Arc<AtomicUsize>,
+    assets: (Arc<AtomicUsize>, Arc<AtomicUsize>),
 ) -> hyper::Response<http_body_util::Full<Bytes>> {
+    let for_share = req.uri().path().starts_with(&format!("/{SHARE_ID}/"));
+    if for_share && !req.headers().contains_key(EXPOSE_HEADER) {
+        return share(req, refuse_mandate, forge_manifest, &manifests);
+    }
+    if !refuse_mandate && req.uri().path().starts_with("/static/") {
+        return asset(req, assets);
+    }
     if !refuse_mandate && crate::upgrade::requested(req.headers()).is_some() {
         return ws_echo(req, ws_closed).await;
     }
     echo(req, refuse_mandate).await
 }
 
+/// Роутер шары агента стенда: подписанный манифест версии
+/// [`SHARE_VERSION`], его ожидание (с той же версией сразу `304`, шара
+/// стенда не меняется) и байты файла с `Range` (одним диапазоном
+/// `bytes=a-b`, больше стенду не нужно). Токен он не проверяет, это забота
+/// настоящего агента; отказ даёт `refuse_mandate`.
+fn share(
+    req: hyper::Request<Incoming>,
+    refuse: bool,
+    forge_manifest: bool,
+    manifests: &AtomicUsize,
+) -> hyper::Response<http_body_util::Full<Bytes>> {
+    let reply = |status: u16, body: Vec<u8>| {
+        hyper::Response::builder()
+            .status(status)
+            .body(http_body_util::Full::new(Bytes::from(body)))
+            .expect("ответ шары")
+    };
+    let has_bearer = req
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("Bearer "));
+    if refuse || !has_bearer {
+        return reply(403, b"token refused".to_vec());
+    }
+    let path = req.uri().path().to_string();
+    if path == format!("/{SHARE_ID}/manifest/wait")
+        && req.uri().query() == Some(&format!("since={SHARE_VERSION}&timeout_secs=0"))
+    {
+        return reply(304, Vec::new());
+    }
+    if path == format!("/{SHARE_ID}/manifest") || path == format!("/{SHARE_ID}/manifest/wait") {
+        manifests.fetch_add(1, Ordering::SeqCst);
+        let manifest = ShareManifest {
+            entries: vec![ShareManifestEntry {
+                path: SHARE_FILE.into(),
+                size: SHARE_BYTES.len() as u64,
+                mtime: 1_700_000_000,
+                sha256: String::new(),
+                meta: None,
+                preview: None,
+            }],
+            version: SHARE_VERSION,
+        };
+        let body = serde_json::to_vec(&manifest).expect("манифест");
+        let key = if forge_manifest {
+            SigningKey::from_bytes(&[9u8; 32])
+        } else {
+            SigningKey::from_bytes(&[7u8; 32])
+        };
+        let signed_at = 1_700_000_000u64;
+        let sig = sign_share_manifest(&key, SHARE_ID, signed_at, &body);
+        let mut resp = reply(200, body);
+        resp.headers_mut().insert(MANIFEST_SIG_HEADER, sig.parse().expect("подпись"));
+        resp.headers_mut()
+            .insert(MANIFEST_SIGNED_AT_HEADER, signed_at.to_string().parse().expect("время"));
+        return resp;
+    }
+    if path != format!("/{SHARE_ID}/file/docs/a%20b.txt") {
+        return reply(404, b"no such file".to_vec());
+    }
+    let range = req
+        .headers()
+        .get("range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes="))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(a, b)| Some((a.parse::<usize>().ok()?, b.parse::<usize>().ok()?)));
+    let Some((from, to)) = range else {
+        return reply(200, SHARE_BYTES.to_vec());
+    };
+    let mut resp = reply(206, SHARE_BYTES[from..=to.min(SHARE_BYTES.len() - 1)].to_vec());
+    let range = format!("bytes {from}-{to}/{}", SHARE_BYTES.len());
+    resp.headers_mut().insert("content-range", range.parse().expect("диапазон"));
+    resp
+}
+
+/// Тело статики стенда: JS, который хорошо жмётся.
+pub const ASSET_BODY: &str = "console.log('xr-web');\n";
+
+/// Статика агента стенда с `ETag: "v1"` и правилом кеша по имени файла:
+/// `app.js` свежа час, `check.js` переспрашивается каждый раз (`no-cache`),
+/// `shared.js` объявлена `public`. Совпавший `If-None-Match` получает `304`.
+fn asset(
+    req: hyper::Request<Incoming>,
+    (bodies, not_modified): (Arc<AtomicUsize>, Arc<AtomicUsize>),
+) -> hyper::Response<http_body_util::Full<Bytes>> {
+    let cache_control = match req.uri().path() {
+        "/static/check.js" => "no-cache",
+        "/static/shared.js" => "public, max-age=3600",
+        _ => "max-age=3600",
+    };
+    let builder = hyper::Response::builder()
+        .header("etag", "\"v1\"")
+        .header("cache-control", cache_control);
+    let matched = req
+        .headers()
+        .get("if-none-match")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == "\"v1\"");
+    if matched {
+        not_modified.fetch_add(1, Ordering::SeqCst);
+        return builder
+            .status(304)
+            .body(http_body_util::Full::default())
+            .expect("ответ 304");
+    }
+    bodies.fetch_add(1, Ordering::SeqCst);
+    builder
+        .header("content-type", "application/javascript")
+        .body(http_body_util::Full::new(Bytes::from(ASSET_BODY.repeat(200))))
+        .expect("ответ статики")
+}
+
 /// Агент, принявший апгрейд: отвечает `101` и дальше гоняет байты обратно как
 /// есть. Кадры он не разбирает, потому что эхо-сервису это и не нужно: тест
 /// сверяет байты, а закрытие со стороны фронта считает `ws_closed`.